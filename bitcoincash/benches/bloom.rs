@@ -0,0 +1,59 @@
+//! Bloom filter benchmarks.
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+use bitcoincash::hashes::{hash160, Hash};
+use bitcoincash::util::bloom::{Bloom, BloomFilter};
+
+/// Number of elements inserted in the filters.
+const ELEMENTS: usize = 1000;
+/// False-positive rate the filters are sized for.
+const FP_RATE: f64 = 0.0001;
+
+/// Elements that look like public key hashes.
+fn elements() -> Vec<Vec<u8>> {
+    (0..ELEMENTS as u32).map(|i| hash160::Hash::hash(&i.to_le_bytes()).to_vec()).collect()
+}
+
+fn bloom(c: &mut Criterion) {
+    let elements = elements();
+    let mut group = c.benchmark_group("bloom");
+
+    group.throughput(Throughput::Elements(ELEMENTS as u64));
+    group.bench_function("Bloom::insert", |b| {
+        b.iter(|| {
+            let mut bloom = Bloom::<u8>::new_for_fp_rate(ELEMENTS, FP_RATE);
+            for element in elements.iter() {
+                bloom.insert(element);
+            }
+            bloom
+        })
+    });
+
+    let mut bloom = Bloom::<u8>::new_for_fp_rate(ELEMENTS, FP_RATE);
+    for element in elements.iter() {
+        bloom.insert(element);
+    }
+    group.bench_function("Bloom::contains", |b| {
+        b.iter(|| {
+            for element in elements.iter() {
+                black_box(bloom.contains(element));
+            }
+        })
+    });
+
+    let mut filter = BloomFilter::new(ELEMENTS, FP_RATE, 0, 0);
+    for element in elements.iter() {
+        filter.insert(element);
+    }
+    group.bench_function("BloomFilter::contains", |b| {
+        b.iter(|| {
+            for element in elements.iter() {
+                black_box(filter.contains(element));
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bloom);
+criterion_main!(benches);
@@ -0,0 +1,17 @@
+//! Cashaddr encoding and decoding benchmarks.
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use bitcoincash::cash_addr::Address;
+
+fn cash_addr(c: &mut Criterion) {
+    let s = "bitcoincash:qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekg2";
+    let addr = s.parse::<Address>().unwrap();
+    let mut group = c.benchmark_group("cash_addr");
+
+    group.bench_function("encode", |b| b.iter(|| black_box(&addr).to_string()));
+    group.bench_function("decode", |b| b.iter(|| black_box(s).parse::<Address>().unwrap()));
+    group.finish();
+}
+
+criterion_group!(benches, cash_addr);
+criterion_main!(benches);
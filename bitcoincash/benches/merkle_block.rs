@@ -0,0 +1,54 @@
+//! Merkle block parsing benchmarks.
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use bitcoincash::blockdata::constants::genesis_block;
+use bitcoincash::consensus::{deserialize, serialize};
+use bitcoincash::{Block, MerkleBlock, Network, PackedLockTime};
+
+/// Number of transactions in the block.
+const TRANSACTIONS: u32 = 2000;
+
+/// A block with distinct transactions. The blocks in the test data use segwit, which doesn't
+/// decode on Bitcoin Cash.
+fn block() -> Block {
+    let genesis = genesis_block(Network::Bitcoin);
+    let txdata = (0..TRANSACTIONS)
+        .map(|i| {
+            let mut tx = genesis.txdata[0].clone();
+            tx.lock_time = PackedLockTime(i);
+            tx
+        })
+        .collect();
+    let mut block = Block { header: genesis.header, txdata };
+    block.header.merkle_root = block.compute_merkle_root().unwrap();
+
+    block
+}
+
+fn merkle_block(c: &mut Criterion) {
+    let block = block();
+    // Match every hundredth transaction, as a wallet with a bloom filter loaded would.
+    let txids = block.txdata.iter().map(|tx| tx.txid()).step_by(100).collect::<Vec<_>>();
+    let raw =
+        serialize(&MerkleBlock::from_block_with_predicate(&block, |txid| txids.contains(txid)));
+    let mut group = c.benchmark_group("merkle_block");
+
+    group.bench_function("deserialize", |b| {
+        b.iter(|| deserialize::<MerkleBlock>(black_box(&raw)).unwrap())
+    });
+
+    let merkle_block: MerkleBlock = deserialize(&raw).unwrap();
+    group.bench_function("extract_matches", |b| {
+        b.iter(|| {
+            let mut matches = Vec::new();
+            let mut indexes = Vec::new();
+
+            merkle_block.extract_matches(&mut matches, &mut indexes).unwrap();
+            matches
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, merkle_block);
+criterion_main!(benches);
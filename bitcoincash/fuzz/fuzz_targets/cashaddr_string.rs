@@ -0,0 +1,64 @@
+#![cfg_attr(feature = "libfuzzer-sys", no_main)]
+extern crate bitcoincash;
+
+use bitcoincash::cash_addr::Address;
+use bitcoincash::util::address;
+
+fn do_test(data: &[u8]) {
+    let data_str = String::from_utf8_lossy(data);
+    let addr = match data_str.parse::<Address>() {
+        Ok(addr) => addr,
+        Err(_) => return,
+    };
+    // Prefixes are optional and addresses case-insensitive, so it's only the payload that
+    // roundtrips as is.
+    let s = addr.to_string();
+    let payload = data_str.rsplit(':').next().unwrap();
+    assert!(s.ends_with(&payload.to_ascii_lowercase()));
+    assert_eq!(s.parse::<Address>().unwrap(), addr);
+
+    let script = addr.script_pubkey();
+    assert_eq!(Address::from_script(&script, addr.network).unwrap().payload, addr.payload);
+    assert_eq!(s.parse::<address::Address>().unwrap(), address::Address::from(addr));
+}
+
+#[cfg(feature = "afl")]
+#[macro_use] extern crate afl;
+#[cfg(feature = "afl")]
+fn main() {
+    fuzz!(|data| {
+        do_test(&data);
+    });
+}
+
+#[cfg(feature = "honggfuzz")]
+#[macro_use] extern crate honggfuzz;
+#[cfg(feature = "honggfuzz")]
+fn main() {
+    loop {
+        fuzz!(|data| {
+            do_test(data);
+        });
+    }
+}
+
+#[cfg(feature = "libfuzzer-sys")]
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    do_test(data);
+});
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    #[test]
+    fn corpus() {
+        let dir = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/hfuzz_input/cashaddr_string/input"
+        );
+        for entry in fs::read_dir(dir).unwrap() {
+            super::do_test(&fs::read(entry.unwrap().path()).unwrap());
+        }
+    }
+}
@@ -1,7 +1,7 @@
 extern crate bitcoincash;
 
 fn do_test(data: &[u8]) {
-    let _: Result<bitcoincash::network::message::RawNetworkMessage, _> = bitcoin::consensus::encode::deserialize(data);
+    let _: Result<bitcoincash::network::message::RawNetworkMessage, _> = bitcoincash::consensus::encode::deserialize(data);
 }
 
 #[cfg(feature = "afl")]
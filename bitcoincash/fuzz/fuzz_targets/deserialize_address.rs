@@ -1,12 +1,13 @@
-extern crate bitcoin;
+extern crate bitcoincash;
 use std::str::FromStr;
 fn do_test(data: &[u8]) {
     let data_str = String::from_utf8_lossy(data);
-    let addr = match bitcoin::util::address::Address::from_str(&data_str) {
+    let addr = match bitcoincash::util::address::Address::from_str(&data_str) {
         Ok(addr) => addr,
         Err(_) => return,
     };
-    assert_eq!(addr.to_string(), data_str);
+    // Cashaddrs are displayed in base58, unless they pay to a 32-byte script hash.
+    assert_eq!(bitcoincash::util::address::Address::from_str(&addr.to_string()).unwrap(), addr);
 }
 
 #[cfg(feature = "afl")]
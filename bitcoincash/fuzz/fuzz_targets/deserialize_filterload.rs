@@ -0,0 +1,69 @@
+#![cfg_attr(feature = "libfuzzer-sys", no_main)]
+extern crate bitcoincash;
+
+use bitcoincash::consensus::encode;
+use bitcoincash::network::message_bloom::FilterLoad;
+use bitcoincash::util::bloom::{BloomFilter, MAX_BLOOM_FILTER_SIZE, MAX_HASH_FUNCS};
+
+fn do_test(data: &[u8]) {
+    let msg: FilterLoad = match encode::deserialize(data) {
+        Ok(msg) => msg,
+        Err(_) => return,
+    };
+    assert_eq!(&encode::serialize(&msg)[..], data);
+
+    // Filters beyond the BIP37 limits are rejected by peers, and never built.
+    if msg.filter.len() > MAX_BLOOM_FILTER_SIZE || msg.hash_funcs > MAX_HASH_FUNCS {
+        return;
+    }
+    let mut filter = BloomFilter {
+        content: msg.filter,
+        hashes: msg.hash_funcs,
+        tweak: msg.tweak,
+        flags: 0,
+    };
+    filter.insert(data);
+    assert!(filter.content.is_empty() || filter.contains(data));
+    assert!(filter.fp_rate() >= 0.0 && filter.fp_rate() <= 1.0);
+}
+
+#[cfg(feature = "afl")]
+#[macro_use] extern crate afl;
+#[cfg(feature = "afl")]
+fn main() {
+    fuzz!(|data| {
+        do_test(&data);
+    });
+}
+
+#[cfg(feature = "honggfuzz")]
+#[macro_use] extern crate honggfuzz;
+#[cfg(feature = "honggfuzz")]
+fn main() {
+    loop {
+        fuzz!(|data| {
+            do_test(data);
+        });
+    }
+}
+
+#[cfg(feature = "libfuzzer-sys")]
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    do_test(data);
+});
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    #[test]
+    fn corpus() {
+        let dir = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/hfuzz_input/deserialize_filterload/input"
+        );
+        for entry in fs::read_dir(dir).unwrap() {
+            super::do_test(&fs::read(entry.unwrap().path()).unwrap());
+        }
+    }
+}
@@ -0,0 +1,61 @@
+#![cfg_attr(feature = "libfuzzer-sys", no_main)]
+extern crate bitcoincash;
+
+use bitcoincash::consensus::encode;
+use bitcoincash::util::merkleblock::MerkleBlock;
+
+fn do_test(data: &[u8]) {
+    let block: MerkleBlock = match encode::deserialize(data) {
+        Ok(block) => block,
+        Err(_) => return,
+    };
+    assert_eq!(&encode::serialize(&block)[..], data);
+
+    let mut matches = Vec::new();
+    let mut indexes = Vec::new();
+    if block.extract_matches(&mut matches, &mut indexes).is_ok() {
+        assert_eq!(matches.len(), indexes.len());
+        assert!(indexes.iter().all(|i| *i < block.txn.num_transactions()));
+    }
+}
+
+#[cfg(feature = "afl")]
+#[macro_use] extern crate afl;
+#[cfg(feature = "afl")]
+fn main() {
+    fuzz!(|data| {
+        do_test(&data);
+    });
+}
+
+#[cfg(feature = "honggfuzz")]
+#[macro_use] extern crate honggfuzz;
+#[cfg(feature = "honggfuzz")]
+fn main() {
+    loop {
+        fuzz!(|data| {
+            do_test(data);
+        });
+    }
+}
+
+#[cfg(feature = "libfuzzer-sys")]
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    do_test(data);
+});
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    #[test]
+    fn corpus() {
+        let dir = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/hfuzz_input/deserialize_merkle_block/input"
+        );
+        for entry in fs::read_dir(dir).unwrap() {
+            super::do_test(&fs::read(entry.unwrap().path()).unwrap());
+        }
+    }
+}
@@ -0,0 +1,63 @@
+#![cfg_attr(feature = "libfuzzer-sys", no_main)]
+extern crate bitcoincash;
+
+use bitcoincash::blockdata::transaction::{Transaction, TxOut};
+use bitcoincash::consensus::encode;
+
+fn do_test(data: &[u8]) {
+    let tx: Transaction = match encode::deserialize(data) {
+        Ok(tx) => tx,
+        Err(_) => return,
+    };
+    assert_eq!(&encode::serialize(&tx)[..], data);
+
+    // Token data is split off the locking script when decoding, and must be put back in front
+    // of it when encoding.
+    for output in &tx.output {
+        let ser = encode::serialize(output);
+        let out: TxOut = encode::deserialize(&ser).unwrap();
+
+        assert_eq!(&out, output);
+    }
+}
+
+#[cfg(feature = "afl")]
+#[macro_use] extern crate afl;
+#[cfg(feature = "afl")]
+fn main() {
+    fuzz!(|data| {
+        do_test(&data);
+    });
+}
+
+#[cfg(feature = "honggfuzz")]
+#[macro_use] extern crate honggfuzz;
+#[cfg(feature = "honggfuzz")]
+fn main() {
+    loop {
+        fuzz!(|data| {
+            do_test(data);
+        });
+    }
+}
+
+#[cfg(feature = "libfuzzer-sys")]
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    do_test(data);
+});
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    #[test]
+    fn corpus() {
+        let dir = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/hfuzz_input/deserialize_token_transaction/input"
+        );
+        for entry in fs::read_dir(dir).unwrap() {
+            super::do_test(&fs::read(entry.unwrap().path()).unwrap());
+        }
+    }
+}
@@ -1,18 +1,18 @@
 extern crate bitcoincash;
 
 fn do_test(data: &[u8]) {
-    let tx_result: Result<bitcoin::blockdata::transaction::Transaction, _> = bitcoin::consensus::encode::deserialize(data);
+    let tx_result: Result<bitcoincash::blockdata::transaction::Transaction, _> = bitcoincash::consensus::encode::deserialize(data);
     match tx_result {
         Err(_) => {},
         Ok(mut tx) => {
-            let ser = bitcoin::consensus::encode::serialize(&tx);
+            let ser = bitcoincash::consensus::encode::serialize(&tx);
             assert_eq!(&ser[..], data);
             let len = ser.len();
             let calculated_weight = tx.get_weight();
             for input in &mut tx.input {
-                input.witness = bitcoin::blockdata::witness::Witness::default();
+                input.witness = bitcoincash::blockdata::witness::Witness::default();
             }
-            let no_witness_len = bitcoin::consensus::encode::serialize(&tx).len();
+            let no_witness_len = bitcoincash::consensus::encode::serialize(&tx).len();
             // For 0-input transactions, `no_witness_len` will be incorrect because
             // we serialize as segwit even after "stripping the witnesses". We need
             // to drop two bytes (i.e. eight weight)
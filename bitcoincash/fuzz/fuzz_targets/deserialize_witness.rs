@@ -1,7 +1,7 @@
-extern crate bitcoin;
+extern crate bitcoincash;
 
-use bitcoin::consensus::{serialize, deserialize};
-use bitcoin::blockdata::witness::Witness;
+use bitcoincash::consensus::{serialize, deserialize};
+use bitcoincash::blockdata::witness::Witness;
 
 fn do_test(data: &[u8]) {
     let w: Result<Witness, _> = deserialize(data);
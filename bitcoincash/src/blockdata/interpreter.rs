@@ -0,0 +1,1435 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! Bitcoin Cash script interpreter.
+//!
+//! Evaluates the scripts spending a transaction input, with the opcodes enabled as of the May
+//! 2025 upgrade: 64-bit arithmetic, `OP_CHECKDATASIG`, Schnorr signatures and multisig, and the
+//! native and CashTokens introspection opcodes. Both 20-byte and 32-byte P2SH outputs are
+//! supported.
+//!
+//! Scripts are evaluated under the standard relay policy, which is stricter than consensus:
+//! pushes and numbers must be minimally encoded, failed signature checks must be given empty
+//! signatures, and the stack must be left with a single item. This makes the interpreter suited
+//! to checking transactions before they are broadcast. It isn't a consensus implementation
+//! however: script numbers are limited to 64 bits, and the operation cost limits of the May
+//! 2025 upgrade aren't accounted for.
+//!
+
+use crate::prelude::*;
+
+use core::convert::TryFrom;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error;
+
+use secp256k1::{ecdsa, Message, PublicKey, Secp256k1, VerifyOnly};
+
+use crate::blockdata::opcodes;
+use crate::blockdata::script::{self, read_scriptbool, Instruction, Script};
+use crate::blockdata::token::{Capability, OutputData};
+use crate::blockdata::transaction::{Transaction, TxOut};
+use crate::hashes::{hash160, ripemd160, sha1, sha256, sha256d, Hash};
+use crate::util::cash_schnorr;
+use crate::util::sighash::{self, ForkIdSighashType, Prevouts, SighashCache};
+
+/// Maximum size of a script, in bytes.
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+/// Maximum size of a stack item, in bytes.
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 10_000;
+/// Maximum number of items on the stack and alt-stack combined.
+pub const MAX_STACK_SIZE: usize = 1_000;
+/// Maximum number of public keys in a multisig.
+pub const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+/// Maximum size of a script number, in bytes.
+pub const MAX_NUM_SIZE: usize = 8;
+
+/// Maximum size of the numbers read by `OP_CHECKLOCKTIMEVERIFY` and `OP_CHECKSEQUENCEVERIFY`.
+const MAX_LOCK_TIME_SIZE: usize = 5;
+/// Lock-times below this are block heights, and timestamps otherwise.
+const LOCK_TIME_THRESHOLD: i64 = 500_000_000;
+/// Sequence flag disabling relative lock-times.
+const SEQUENCE_DISABLE_FLAG: i64 = 1 << 31;
+/// Sequence flag for relative lock-times in units of 512 seconds, rather than blocks.
+const SEQUENCE_TYPE_FLAG: i64 = 1 << 22;
+/// Sequence bits holding the relative lock-time.
+const SEQUENCE_MASK: i64 = 0x0000_ffff;
+
+/// A script evaluation error.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The script is malformed.
+    Script(script::Error),
+    /// The script is larger than [`MAX_SCRIPT_SIZE`].
+    ScriptSize,
+    /// A stack item is larger than [`MAX_SCRIPT_ELEMENT_SIZE`].
+    PushSize,
+    /// The stacks hold more than [`MAX_STACK_SIZE`] items.
+    StackSize,
+    /// The script left a false value, or nothing, on the stack.
+    EvalFalse,
+    /// An `OP_RETURN` was executed.
+    OpReturn,
+    /// An invalid opcode was executed.
+    BadOpcode(opcodes::All),
+    /// A disabled opcode was encountered.
+    DisabledOpcode(opcodes::All),
+    /// A NOP reserved for upgrades was executed.
+    UpgradableNop(opcodes::All),
+    /// An `OP_IF` or `OP_NOTIF` doesn't have a matching `OP_ENDIF`, or vice versa.
+    UnbalancedConditional,
+    /// An operation needed more stack items than there are.
+    InvalidStackOperation,
+    /// An `OP_FROMALTSTACK` was executed with an empty alt-stack.
+    InvalidAltstackOperation,
+    /// A verifying opcode failed.
+    Verify(opcodes::All),
+    /// A push or number isn't minimally encoded.
+    MinimalData,
+    /// The condition of an `OP_IF` or `OP_NOTIF` isn't empty or `1`.
+    MinimalIf,
+    /// A number is larger than [`MAX_NUM_SIZE`] bytes.
+    NumberOverflow,
+    /// An arithmetic operation overflowed.
+    ArithmeticOverflow,
+    /// A division or modulo by zero.
+    DivisionByZero,
+    /// The operands of a bitwise operation differ in size.
+    InvalidOperandSize,
+    /// An `OP_SPLIT` position is out of range.
+    InvalidSplitRange,
+    /// A number doesn't fit in the size requested by `OP_NUM2BIN`.
+    ImpossibleEncoding,
+    /// A multisig has an invalid number of public keys.
+    PubkeyCount,
+    /// A multisig has an invalid number of signatures.
+    SigCount,
+    /// The bitfield of a Schnorr multisig is invalid.
+    InvalidBitfield,
+    /// A signature has an invalid sighash type.
+    SigHashType,
+    /// An ECDSA signature isn't strictly DER-encoded.
+    SigDer,
+    /// An ECDSA signature has a high `S` value.
+    SigHighS,
+    /// A Schnorr signature was given where only ECDSA is allowed, or vice versa.
+    SigBadLength,
+    /// A public key isn't a compressed or uncompressed key.
+    PubkeyType,
+    /// A signature check failed, with a non-empty signature.
+    SigNullFail,
+    /// A lock-time is negative.
+    NegativeLockTime,
+    /// A lock-time isn't satisfied by the transaction.
+    UnsatisfiedLockTime,
+    /// An unlocking script contains operations other than pushes.
+    SigPushOnly,
+    /// More than one item was left on the stack.
+    CleanStack,
+    /// An input index is out of range.
+    InvalidInputIndex,
+    /// An output index is out of range.
+    InvalidOutputIndex,
+    /// The number of spent outputs doesn't match the number of inputs.
+    SpentOutputs,
+    /// The signature hash couldn't be computed.
+    Sighash(sighash::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Script(ref e) => write!(f, "malformed script: {}", e),
+            Error::ScriptSize => write!(f, "script is larger than {} bytes", MAX_SCRIPT_SIZE),
+            Error::PushSize => {
+                write!(f, "stack item is larger than {} bytes", MAX_SCRIPT_ELEMENT_SIZE)
+            }
+            Error::StackSize => write!(f, "stack holds more than {} items", MAX_STACK_SIZE),
+            Error::EvalFalse => write!(f, "script evaluated to false"),
+            Error::OpReturn => write!(f, "OP_RETURN was executed"),
+            Error::BadOpcode(op) => write!(f, "invalid opcode {}", op),
+            Error::DisabledOpcode(op) => write!(f, "disabled opcode {}", op),
+            Error::UpgradableNop(op) => write!(f, "upgradable {} was executed", op),
+            Error::UnbalancedConditional => write!(f, "unbalanced conditional"),
+            Error::InvalidStackOperation => write!(f, "not enough items on the stack"),
+            Error::InvalidAltstackOperation => write!(f, "not enough items on the alt-stack"),
+            Error::Verify(op) => write!(f, "{} failed", op),
+            Error::MinimalData => write!(f, "non-minimally encoded push or number"),
+            Error::MinimalIf => write!(f, "conditional argument must be empty or 1"),
+            Error::NumberOverflow => write!(f, "number is larger than {} bytes", MAX_NUM_SIZE),
+            Error::ArithmeticOverflow => write!(f, "arithmetic overflow"),
+            Error::DivisionByZero => write!(f, "division by zero"),
+            Error::InvalidOperandSize => write!(f, "operands differ in size"),
+            Error::InvalidSplitRange => write!(f, "split position is out of range"),
+            Error::ImpossibleEncoding => write!(f, "number doesn't fit in the requested size"),
+            Error::PubkeyCount => write!(f, "invalid number of public keys"),
+            Error::SigCount => write!(f, "invalid number of signatures"),
+            Error::InvalidBitfield => write!(f, "invalid multisig bitfield"),
+            Error::SigHashType => write!(f, "invalid signature hash type"),
+            Error::SigDer => write!(f, "signature isn't strictly DER-encoded"),
+            Error::SigHighS => write!(f, "signature has a high S value"),
+            Error::SigBadLength => write!(f, "signature has an invalid length"),
+            Error::PubkeyType => write!(f, "invalid public key encoding"),
+            Error::SigNullFail => write!(f, "failed signature check with a non-empty signature"),
+            Error::NegativeLockTime => write!(f, "negative lock-time"),
+            Error::UnsatisfiedLockTime => write!(f, "lock-time requirement not satisfied"),
+            Error::SigPushOnly => write!(f, "unlocking script isn't push-only"),
+            Error::CleanStack => write!(f, "stack isn't clean after evaluation"),
+            Error::InvalidInputIndex => write!(f, "input index is out of range"),
+            Error::InvalidOutputIndex => write!(f, "output index is out of range"),
+            Error::SpentOutputs => write!(f, "spent outputs don't match the transaction inputs"),
+            Error::Sighash(ref e) => write!(f, "signature hash error: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+impl error::Error for Error {}
+
+#[doc(hidden)]
+impl From<script::Error> for Error {
+    fn from(e: script::Error) -> Error {
+        Error::Script(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<sighash::Error> for Error {
+    fn from(e: sighash::Error) -> Error {
+        Error::Sighash(e)
+    }
+}
+
+/// Verifies that the input at the given index validly spends its output, given the outputs
+/// spent by all of the transaction's inputs, in order.
+pub fn verify_input(tx: &Transaction, index: usize, spent: &[TxOut]) -> Result<(), Error> {
+    Interpreter::new(tx, index, spent)?.verify()
+}
+
+/// A parsed signature.
+enum Signature {
+    Ecdsa(ecdsa::Signature),
+    Schnorr(cash_schnorr::Signature),
+}
+
+/// Evaluates scripts in the context of a transaction input.
+pub struct Interpreter<'a> {
+    tx: &'a Transaction,
+    index: usize,
+    spent: &'a [TxOut],
+    cache: SighashCache<&'a Transaction>,
+    secp: Secp256k1<VerifyOnly>,
+    stack: Vec<Vec<u8>>,
+    altstack: Vec<Vec<u8>>,
+    sigchecks: usize,
+}
+
+impl<'a> Interpreter<'a> {
+    /// Creates an interpreter for the input at the given index, given the outputs spent by all
+    /// of the transaction's inputs, in order.
+    pub fn new(tx: &'a Transaction, index: usize, spent: &'a [TxOut]) -> Result<Self, Error> {
+        if index >= tx.input.len() {
+            return Err(Error::InvalidInputIndex);
+        }
+        if spent.len() != tx.input.len() {
+            return Err(Error::SpentOutputs);
+        }
+        Ok(Interpreter {
+            tx,
+            index,
+            spent,
+            cache: SighashCache::new(tx),
+            secp: Secp256k1::verification_only(),
+            stack: Vec::new(),
+            altstack: Vec::new(),
+            sigchecks: 0,
+        })
+    }
+
+    /// The stack, its top item last.
+    pub fn stack(&self) -> &[Vec<u8>] {
+        &self.stack
+    }
+
+    /// The number of signature checks performed so far, as counted by the May 2020 upgrade:
+    /// one per non-empty signature checked, and, for legacy multisigs whose signatures aren't
+    /// all empty, one per public key.
+    pub fn sigchecks(&self) -> usize {
+        self.sigchecks
+    }
+
+    /// Verifies the input: its unlocking script is evaluated, followed by the locking script of
+    /// the output it spends and, if that is a P2SH output, by the redeem script.
+    pub fn verify(&mut self) -> Result<(), Error> {
+        let script_sig = &self.tx.input[self.index].script_sig;
+        let script_pubkey = &self.spent[self.index].script_pubkey;
+
+        if !is_push_only(script_sig)? {
+            return Err(Error::SigPushOnly);
+        }
+        self.eval(script_sig)?;
+        let unlocked = self.stack.clone();
+
+        self.eval(script_pubkey)?;
+        self.check_true()?;
+
+        if script_pubkey.is_p2sh() || script_pubkey.is_p2sh32() {
+            self.stack = unlocked;
+            let redeem_script = Script::from(self.pop()?);
+
+            self.eval(&redeem_script)?;
+            self.check_true()?;
+        }
+        if self.stack.len() != 1 {
+            return Err(Error::CleanStack);
+        }
+        Ok(())
+    }
+
+    /// Evaluates a script on the current stack.
+    pub fn eval(&mut self, script: &Script) -> Result<(), Error> {
+        use crate::blockdata::opcodes::all::*;
+
+        if script.len() > MAX_SCRIPT_SIZE {
+            return Err(Error::ScriptSize);
+        }
+        self.altstack.clear();
+
+        // Whether each enclosing branch is taken.
+        let mut branches: Vec<bool> = Vec::new();
+        // Start of the code signed by signature checks, following the last executed
+        // `OP_CODESEPARATOR`.
+        let mut code_start = 0;
+        let mut instructions = script.instructions();
+
+        loop {
+            let start = instructions.remaining();
+            let instruction = match instructions.next() {
+                Some(instruction) => instruction?,
+                None => break,
+            };
+            let executing = branches.iter().all(|b| *b);
+
+            match instruction {
+                Instruction::PushBytes(data) => {
+                    if data.len() > MAX_SCRIPT_ELEMENT_SIZE {
+                        return Err(Error::PushSize);
+                    }
+                    if executing {
+                        let header = start - instructions.remaining() - data.len();
+
+                        if !is_minimal_push(data, header) {
+                            return Err(Error::MinimalData);
+                        }
+                        self.stack.push(data.to_vec());
+                    }
+                }
+                Instruction::Op(op) => match op {
+                    OP_INVERT | OP_2MUL | OP_2DIV | OP_LSHIFT | OP_RSHIFT => {
+                        return Err(Error::DisabledOpcode(op));
+                    }
+                    OP_VERIF | OP_VERNOTIF => return Err(Error::BadOpcode(op)),
+                    OP_IF | OP_NOTIF => {
+                        let mut taken = false;
+
+                        if executing {
+                            let condition = self.pop()?;
+
+                            if condition.len() > 1 || condition.first().map_or(false, |b| *b != 1) {
+                                return Err(Error::MinimalIf);
+                            }
+                            taken = read_scriptbool(&condition) == (op == OP_IF);
+                        }
+                        branches.push(taken);
+                    }
+                    OP_ELSE => {
+                        let taken = branches.last_mut().ok_or(Error::UnbalancedConditional)?;
+                        *taken = !*taken;
+                    }
+                    OP_ENDIF => {
+                        branches.pop().ok_or(Error::UnbalancedConditional)?;
+                    }
+                    _ if !executing => {}
+                    OP_CODESEPARATOR => code_start = script.len() - instructions.remaining(),
+                    _ => self.execute(op, &script[code_start..])?,
+                },
+            }
+            if self.stack.len() + self.altstack.len() > MAX_STACK_SIZE {
+                return Err(Error::StackSize);
+            }
+        }
+        if !branches.is_empty() {
+            return Err(Error::UnbalancedConditional);
+        }
+        Ok(())
+    }
+
+    /// Executes an opcode, other than a push or a flow control opcode. The given code is what
+    /// signature checks sign.
+    fn execute(&mut self, op: opcodes::All, code: &[u8]) -> Result<(), Error> {
+        use crate::blockdata::opcodes::all::*;
+
+        let (tx, spent) = (self.tx, self.spent);
+
+        match op {
+            OP_PUSHNUM_NEG1 => self.push_num(-1)?,
+            op if op.to_u8() >= OP_PUSHNUM_1.to_u8() && op.to_u8() <= OP_PUSHNUM_16.to_u8() => {
+                self.push_num(i64::from(op.to_u8() - OP_PUSHNUM_1.to_u8() + 1))?
+            }
+
+            // Control.
+            OP_NOP => {}
+            OP_CLTV => self.check_lock_time()?,
+            OP_CSV => self.check_sequence()?,
+            op if op.to_u8() >= OP_NOP1.to_u8() && op.to_u8() <= OP_NOP10.to_u8() => {
+                return Err(Error::UpgradableNop(op));
+            }
+            OP_VERIFY => {
+                if !self.pop_bool()? {
+                    return Err(Error::Verify(op));
+                }
+            }
+            OP_RETURN => return Err(Error::OpReturn),
+
+            // Stack.
+            OP_TOALTSTACK => {
+                let item = self.pop()?;
+                self.altstack.push(item);
+            }
+            OP_FROMALTSTACK => {
+                let item = self.altstack.pop().ok_or(Error::InvalidAltstackOperation)?;
+                self.stack.push(item);
+            }
+            OP_2DROP => {
+                self.require(2)?;
+                self.stack.truncate(self.stack.len() - 2);
+            }
+            OP_2DUP | OP_3DUP | OP_2OVER => {
+                let (depth, count) = match op {
+                    OP_2DUP => (2, 2),
+                    OP_3DUP => (3, 3),
+                    _ => (4, 2),
+                };
+                self.require(depth)?;
+                let start = self.stack.len() - depth;
+                let items = self.stack[start..start + count].to_vec();
+                self.stack.extend(items);
+            }
+            OP_2ROT => {
+                self.require(6)?;
+                let len = self.stack.len();
+                self.stack[len - 6..].rotate_left(2);
+            }
+            OP_2SWAP => {
+                self.require(4)?;
+                let len = self.stack.len();
+                self.stack[len - 4..].rotate_left(2);
+            }
+            OP_IFDUP => {
+                let item = self.top(1)?.clone();
+                if read_scriptbool(&item) {
+                    self.stack.push(item);
+                }
+            }
+            OP_DEPTH => self.push_num(self.stack.len() as i64)?,
+            OP_DROP => {
+                self.pop()?;
+            }
+            OP_DUP => {
+                let item = self.top(1)?.clone();
+                self.stack.push(item);
+            }
+            OP_NIP => {
+                self.require(2)?;
+                let len = self.stack.len();
+                self.stack.remove(len - 2);
+            }
+            OP_OVER => {
+                let item = self.top(2)?.clone();
+                self.stack.push(item);
+            }
+            OP_PICK | OP_ROLL => {
+                let depth = self.pop_num()?;
+                let depth = usize::try_from(depth)
+                    .ok()
+                    .filter(|d| *d < self.stack.len())
+                    .ok_or(Error::InvalidStackOperation)?;
+                let ix = self.stack.len() - depth - 1;
+                let item =
+                    if op == OP_ROLL { self.stack.remove(ix) } else { self.stack[ix].clone() };
+                self.stack.push(item);
+            }
+            OP_ROT => {
+                self.require(3)?;
+                let len = self.stack.len();
+                self.stack[len - 3..].rotate_left(1);
+            }
+            OP_SWAP => {
+                self.require(2)?;
+                let len = self.stack.len();
+                self.stack.swap(len - 2, len - 1);
+            }
+            OP_TUCK => {
+                let item = self.top(1)?.clone();
+                let len = self.stack.len();
+                self.require(2)?;
+                self.stack.insert(len - 2, item);
+            }
+
+            // Splice.
+            OP_CAT => {
+                let b = self.pop()?;
+                let mut a = self.pop()?;
+
+                if a.len() + b.len() > MAX_SCRIPT_ELEMENT_SIZE {
+                    return Err(Error::PushSize);
+                }
+                a.extend(b);
+                self.push(a)?;
+            }
+            OP_SPLIT => {
+                let position = self.pop_num()?;
+                let mut data = self.pop()?;
+                let position = usize::try_from(position)
+                    .ok()
+                    .filter(|p| *p <= data.len())
+                    .ok_or(Error::InvalidSplitRange)?;
+                let right = data.split_off(position);
+
+                self.push(data)?;
+                self.push(right)?;
+            }
+            OP_NUM2BIN => {
+                let size = self.pop_num()?;
+                let size = usize::try_from(size)
+                    .ok()
+                    .filter(|s| *s <= MAX_SCRIPT_ELEMENT_SIZE)
+                    .ok_or(Error::PushSize)?;
+                let mut data = minimally_encode(self.pop()?);
+
+                if data.len() > size {
+                    return Err(Error::ImpossibleEncoding);
+                }
+                // Move the sign bit to the new last byte.
+                let sign = data.last().map_or(0, |b| b & 0x80);
+                if let Some(last) = data.last_mut() {
+                    *last &= 0x7f;
+                }
+                data.resize(size, 0);
+                if let Some(last) = data.last_mut() {
+                    *last |= sign;
+                }
+                self.push(data)?;
+            }
+            OP_BIN2NUM => {
+                let data = minimally_encode(self.pop()?);
+
+                if data.len() > MAX_NUM_SIZE {
+                    return Err(Error::NumberOverflow);
+                }
+                self.push(data)?;
+            }
+            OP_SIZE => {
+                let size = self.top(1)?.len();
+                self.push_num(size as i64)?;
+            }
+
+            // Bitwise logic.
+            OP_AND | OP_OR | OP_XOR => {
+                let b = self.pop()?;
+                let mut a = self.pop()?;
+
+                if a.len() != b.len() {
+                    return Err(Error::InvalidOperandSize);
+                }
+                for (x, y) in a.iter_mut().zip(&b) {
+                    match op {
+                        OP_AND => *x &= y,
+                        OP_OR => *x |= y,
+                        _ => *x ^= y,
+                    }
+                }
+                self.push(a)?;
+            }
+            OP_EQUAL | OP_EQUALVERIFY => {
+                let equal = self.pop()? == self.pop()?;
+                self.push_or_verify(op, equal)?;
+            }
+            OP_REVERSEBYTES => {
+                let mut data = self.pop()?;
+                data.reverse();
+                self.push(data)?;
+            }
+
+            // Arithmetic.
+            OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL => {
+                let a = self.pop_num()?;
+                let n = match op {
+                    OP_1ADD => a.checked_add(1),
+                    OP_1SUB => a.checked_sub(1),
+                    OP_NEGATE => Some(-a),
+                    OP_ABS => Some(a.abs()),
+                    OP_NOT => Some((a == 0) as i64),
+                    _ => Some((a != 0) as i64),
+                };
+                self.push_num(checked(n)?)?;
+            }
+            OP_ADD
+            | OP_SUB
+            | OP_MUL
+            | OP_DIV
+            | OP_MOD
+            | OP_BOOLAND
+            | OP_BOOLOR
+            | OP_NUMEQUAL
+            | OP_NUMEQUALVERIFY
+            | OP_NUMNOTEQUAL
+            | OP_LESSTHAN
+            | OP_GREATERTHAN
+            | OP_LESSTHANOREQUAL
+            | OP_GREATERTHANOREQUAL
+            | OP_MIN
+            | OP_MAX => {
+                let b = self.pop_num()?;
+                let a = self.pop_num()?;
+                let n = match op {
+                    OP_ADD => a.checked_add(b),
+                    OP_SUB => a.checked_sub(b),
+                    OP_MUL => a.checked_mul(b),
+                    OP_DIV | OP_MOD if b == 0 => return Err(Error::DivisionByZero),
+                    OP_DIV => a.checked_div(b),
+                    OP_MOD => a.checked_rem(b),
+                    OP_BOOLAND => Some((a != 0 && b != 0) as i64),
+                    OP_BOOLOR => Some((a != 0 || b != 0) as i64),
+                    OP_NUMEQUAL | OP_NUMEQUALVERIFY => Some((a == b) as i64),
+                    OP_NUMNOTEQUAL => Some((a != b) as i64),
+                    OP_LESSTHAN => Some((a < b) as i64),
+                    OP_GREATERTHAN => Some((a > b) as i64),
+                    OP_LESSTHANOREQUAL => Some((a <= b) as i64),
+                    OP_GREATERTHANOREQUAL => Some((a >= b) as i64),
+                    OP_MIN => Some(a.min(b)),
+                    _ => Some(a.max(b)),
+                };
+                let n = checked(n)?;
+
+                if op == OP_NUMEQUALVERIFY {
+                    self.push_or_verify(op, n != 0)?;
+                } else {
+                    self.push_num(n)?;
+                }
+            }
+            OP_WITHIN => {
+                let max = self.pop_num()?;
+                let min = self.pop_num()?;
+                let n = self.pop_num()?;
+                self.push_bool(min <= n && n < max)?;
+            }
+
+            // Crypto.
+            OP_RIPEMD160 => {
+                let data = self.pop()?;
+                self.push(ripemd160::Hash::hash(&data)[..].to_vec())?;
+            }
+            OP_SHA1 => {
+                let data = self.pop()?;
+                self.push(sha1::Hash::hash(&data)[..].to_vec())?;
+            }
+            OP_SHA256 => {
+                let data = self.pop()?;
+                self.push(sha256::Hash::hash(&data)[..].to_vec())?;
+            }
+            OP_HASH160 => {
+                let data = self.pop()?;
+                self.push(hash160::Hash::hash(&data)[..].to_vec())?;
+            }
+            OP_HASH256 => {
+                let data = self.pop()?;
+                self.push(sha256d::Hash::hash(&data)[..].to_vec())?;
+            }
+            OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+                let pubkey = self.pop()?;
+                let sig = self.pop()?;
+                let valid = self.check_sig(&sig, &pubkey, code)?;
+
+                if !sig.is_empty() {
+                    self.sigchecks += 1;
+                }
+                if !valid && !sig.is_empty() {
+                    return Err(Error::SigNullFail);
+                }
+                self.push_or_verify(op, valid)?;
+            }
+            OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                let valid = self.check_multisig(code)?;
+                self.push_or_verify(op, valid)?;
+            }
+            OP_CHECKDATASIG | OP_CHECKDATASIGVERIFY => {
+                let pubkey = self.pop()?;
+                let msg = self.pop()?;
+                let sig = self.pop()?;
+                let valid = self.check_data_sig(&sig, &msg, &pubkey)?;
+
+                if !sig.is_empty() {
+                    self.sigchecks += 1;
+                }
+                if !valid && !sig.is_empty() {
+                    return Err(Error::SigNullFail);
+                }
+                self.push_or_verify(op, valid)?;
+            }
+
+            // Introspection.
+            OP_INPUTINDEX => self.push_num(self.index as i64)?,
+            OP_ACTIVEBYTECODE => self.push(code.to_vec())?,
+            OP_TXVERSION => self.push_num(i64::from(tx.version))?,
+            OP_TXINPUTCOUNT => self.push_num(tx.input.len() as i64)?,
+            OP_TXOUTPUTCOUNT => self.push_num(tx.output.len() as i64)?,
+            OP_TXLOCKTIME => self.push_num(i64::from(tx.lock_time.to_u32()))?,
+            OP_UTXOVALUE
+            | OP_UTXOBYTECODE
+            | OP_OUTPOINTTXHASH
+            | OP_OUTPOINTINDEX
+            | OP_INPUTBYTECODE
+            | OP_INPUTSEQUENCENUMBER
+            | OP_UTXOTOKENCATEGORY
+            | OP_UTXOTOKENCOMMITMENT
+            | OP_UTXOTOKENAMOUNT => {
+                let ix = self.pop_num()?;
+                let ix = usize::try_from(ix)
+                    .ok()
+                    .filter(|ix| *ix < tx.input.len())
+                    .ok_or(Error::InvalidInputIndex)?;
+                let (input, utxo) = (&tx.input[ix], &spent[ix]);
+
+                match op {
+                    OP_UTXOVALUE => self.push_num(utxo.value as i64)?,
+                    OP_UTXOBYTECODE => self.push(utxo.script_pubkey.to_bytes())?,
+                    OP_OUTPOINTTXHASH => self.push(input.previous_output.txid[..].to_vec())?,
+                    OP_OUTPOINTINDEX => self.push_num(i64::from(input.previous_output.vout))?,
+                    OP_INPUTBYTECODE => self.push(input.script_sig.to_bytes())?,
+                    OP_INPUTSEQUENCENUMBER => self.push_num(i64::from(input.sequence.0))?,
+                    OP_UTXOTOKENCATEGORY => self.push(token_category(&utxo.token))?,
+                    OP_UTXOTOKENCOMMITMENT => self.push(token_commitment(&utxo.token))?,
+                    _ => self.push_num(token_amount(&utxo.token))?,
+                }
+            }
+            OP_OUTPUTVALUE
+            | OP_OUTPUTBYTECODE
+            | OP_OUTPUTTOKENCATEGORY
+            | OP_OUTPUTTOKENCOMMITMENT
+            | OP_OUTPUTTOKENAMOUNT => {
+                let ix = self.pop_num()?;
+                let output = usize::try_from(ix)
+                    .ok()
+                    .and_then(|ix| tx.output.get(ix))
+                    .ok_or(Error::InvalidOutputIndex)?;
+
+                match op {
+                    OP_OUTPUTVALUE => self.push_num(output.value as i64)?,
+                    OP_OUTPUTBYTECODE => self.push(output.script_pubkey.to_bytes())?,
+                    OP_OUTPUTTOKENCATEGORY => self.push(token_category(&output.token))?,
+                    OP_OUTPUTTOKENCOMMITMENT => self.push(token_commitment(&output.token))?,
+                    _ => self.push_num(token_amount(&output.token))?,
+                }
+            }
+
+            _ => return Err(Error::BadOpcode(op)),
+        }
+        Ok(())
+    }
+
+    /// Checks a transaction signature, followed by its sighash type, against a public key.
+    /// Returns `false` if the signature is empty.
+    fn check_sig(&mut self, sig: &[u8], pubkey: &[u8], code: &[u8]) -> Result<bool, Error> {
+        let sig = match sig.split_last() {
+            Some((hash_ty, sig)) => {
+                let sig = parse_signature(sig)?;
+                let hash_ty = ForkIdSighashType::from_consensus(u32::from(*hash_ty))
+                    .map_err(|_| Error::SigHashType)?;
+
+                Some((sig, hash_ty))
+            }
+            None => None,
+        };
+        check_pubkey_encoding(pubkey)?;
+
+        let (sig, hash_ty) = match sig {
+            Some(sig) => sig,
+            None => return Ok(false),
+        };
+        let script_code = Script::from(code.to_vec());
+        let sighash = self.cache.forkid_signature_hash(
+            self.index,
+            &script_code,
+            &Prevouts::All(self.spent),
+            hash_ty,
+        )?;
+
+        Ok(self.verify_signature(&sig, &sighash.into_inner(), pubkey))
+    }
+
+    /// Checks a signature of the `SHA256` hash of a message against a public key. Returns
+    /// `false` if the signature is empty.
+    fn check_data_sig(&self, sig: &[u8], msg: &[u8], pubkey: &[u8]) -> Result<bool, Error> {
+        let sig = if sig.is_empty() { None } else { Some(parse_signature(sig)?) };
+        check_pubkey_encoding(pubkey)?;
+
+        let sig = match sig {
+            Some(sig) => sig,
+            None => return Ok(false),
+        };
+        Ok(self.verify_signature(&sig, &sha256::Hash::hash(msg).into_inner(), pubkey))
+    }
+
+    /// Checks a multisig, popping its items off the stack.
+    ///
+    /// If the dummy item is empty, signatures must be ECDSA signatures, given in the same order
+    /// as their public keys. Otherwise it is a bitfield of the public keys the signatures are
+    /// for, in which case signatures must be Schnorr signatures.
+    fn check_multisig(&mut self, code: &[u8]) -> Result<bool, Error> {
+        let keys = decode_num(self.top(1)?, MAX_NUM_SIZE)?;
+        let keys = usize::try_from(keys)
+            .ok()
+            .filter(|k| *k <= MAX_PUBKEYS_PER_MULTISIG)
+            .ok_or(Error::PubkeyCount)?;
+        let sigs = decode_num(self.top(keys + 2)?, MAX_NUM_SIZE)?;
+        let sigs = usize::try_from(sigs).ok().filter(|s| *s <= keys).ok_or(Error::SigCount)?;
+
+        // The dummy item, the signatures and their count, and the public keys and their count.
+        let count = sigs + keys + 3;
+        self.require(count)?;
+        let items = self.stack.split_off(self.stack.len() - count);
+        let dummy = &items[0];
+        let signatures = &items[1..=sigs];
+        let pubkeys = &items[sigs + 2..sigs + 2 + keys];
+
+        if !dummy.is_empty() {
+            let bitfield = decode_bitfield(dummy, keys)?;
+
+            if bitfield.count_ones() as usize != sigs {
+                return Err(Error::InvalidBitfield);
+            }
+            let signed = (0..keys).filter(|k| bitfield & (1 << k) != 0);
+
+            for (sig, k) in signatures.iter().zip(signed) {
+                if sig.len() != cash_schnorr::SIGNATURE_SIZE + 1 {
+                    return Err(Error::SigBadLength);
+                }
+                if !self.check_sig(sig, &pubkeys[k], code)? {
+                    return Err(Error::SigNullFail);
+                }
+            }
+            self.sigchecks += sigs;
+
+            return Ok(true);
+        }
+
+        // Signatures are matched against public keys starting from the top of the stack, and
+        // fail as soon as there are fewer public keys left than signatures.
+        let (mut sigs_left, mut keys_left) = (sigs, keys);
+        let mut valid = true;
+
+        while valid && sigs_left > 0 {
+            let sig = &signatures[sigs_left - 1];
+
+            if sig.len() == cash_schnorr::SIGNATURE_SIZE + 1 {
+                return Err(Error::SigBadLength);
+            }
+            if self.check_sig(sig, &pubkeys[keys_left - 1], code)? {
+                sigs_left -= 1;
+            }
+            keys_left -= 1;
+
+            if sigs_left > keys_left {
+                valid = false;
+            }
+        }
+        if signatures.iter().any(|s| !s.is_empty()) {
+            if !valid {
+                return Err(Error::SigNullFail);
+            }
+            self.sigchecks += keys;
+        }
+        Ok(valid)
+    }
+
+    /// Verifies a signature of a 32-byte message. Returns `false` if the public key isn't a
+    /// valid point.
+    fn verify_signature(&self, sig: &Signature, msg: &[u8; 32], pubkey: &[u8]) -> bool {
+        let pubkey = match PublicKey::from_slice(pubkey) {
+            Ok(pubkey) => pubkey,
+            Err(_) => return false,
+        };
+        match sig {
+            Signature::Schnorr(sig) => cash_schnorr::verify(&self.secp, msg, sig, &pubkey),
+            Signature::Ecdsa(sig) => {
+                let msg = Message::from_slice(msg).expect("messages are 32 bytes");
+                self.secp.verify_ecdsa(&msg, sig, &pubkey).is_ok()
+            }
+        }
+    }
+
+    /// Checks a BIP65 `OP_CHECKLOCKTIMEVERIFY`.
+    fn check_lock_time(&self) -> Result<(), Error> {
+        let lock_time = decode_num(self.top(1)?, MAX_LOCK_TIME_SIZE)?;
+        let tx_lock_time = i64::from(self.tx.lock_time.to_u32());
+
+        if lock_time < 0 {
+            return Err(Error::NegativeLockTime);
+        }
+        // Heights and timestamps can't be compared.
+        if (lock_time < LOCK_TIME_THRESHOLD) != (tx_lock_time < LOCK_TIME_THRESHOLD)
+            || lock_time > tx_lock_time
+        {
+            return Err(Error::UnsatisfiedLockTime);
+        }
+        // The transaction's lock-time is ignored if the input is final.
+        if !self.tx.input[self.index].sequence.enables_absolute_lock_time() {
+            return Err(Error::UnsatisfiedLockTime);
+        }
+        Ok(())
+    }
+
+    /// Checks a BIP112 `OP_CHECKSEQUENCEVERIFY`.
+    fn check_sequence(&self) -> Result<(), Error> {
+        let sequence = decode_num(self.top(1)?, MAX_LOCK_TIME_SIZE)?;
+        let tx_sequence = i64::from(self.tx.input[self.index].sequence.0);
+
+        if sequence < 0 {
+            return Err(Error::NegativeLockTime);
+        }
+        // Reserved for future upgrades.
+        if sequence & SEQUENCE_DISABLE_FLAG != 0 {
+            return Ok(());
+        }
+        if self.tx.version < 2 || tx_sequence & SEQUENCE_DISABLE_FLAG != 0 {
+            return Err(Error::UnsatisfiedLockTime);
+        }
+        let sequence = sequence & (SEQUENCE_TYPE_FLAG | SEQUENCE_MASK);
+        let tx_sequence = tx_sequence & (SEQUENCE_TYPE_FLAG | SEQUENCE_MASK);
+
+        // Heights and times can't be compared.
+        if (sequence < SEQUENCE_TYPE_FLAG) != (tx_sequence < SEQUENCE_TYPE_FLAG)
+            || sequence > tx_sequence
+        {
+            return Err(Error::UnsatisfiedLockTime);
+        }
+        Ok(())
+    }
+
+    /// Checks that the top of the stack is true.
+    fn check_true(&self) -> Result<(), Error> {
+        match self.stack.last() {
+            Some(top) if read_scriptbool(top) => Ok(()),
+            _ => Err(Error::EvalFalse),
+        }
+    }
+
+    /// Checks that the stack holds at least the given number of items.
+    fn require(&self, count: usize) -> Result<(), Error> {
+        if self.stack.len() < count {
+            return Err(Error::InvalidStackOperation);
+        }
+        Ok(())
+    }
+
+    /// The item at the given depth, starting at `1` for the top of the stack.
+    fn top(&self, depth: usize) -> Result<&Vec<u8>, Error> {
+        self.require(depth)?;
+        Ok(&self.stack[self.stack.len() - depth])
+    }
+
+    fn pop(&mut self) -> Result<Vec<u8>, Error> {
+        self.stack.pop().ok_or(Error::InvalidStackOperation)
+    }
+
+    fn pop_num(&mut self) -> Result<i64, Error> {
+        decode_num(&self.pop()?, MAX_NUM_SIZE)
+    }
+
+    fn pop_bool(&mut self) -> Result<bool, Error> {
+        Ok(read_scriptbool(&self.pop()?))
+    }
+
+    fn push(&mut self, item: Vec<u8>) -> Result<(), Error> {
+        if item.len() > MAX_SCRIPT_ELEMENT_SIZE {
+            return Err(Error::PushSize);
+        }
+        self.stack.push(item);
+        Ok(())
+    }
+
+    fn push_num(&mut self, n: i64) -> Result<(), Error> {
+        self.push(encode_num(n))
+    }
+
+    fn push_bool(&mut self, b: bool) -> Result<(), Error> {
+        self.push(if b { vec![1] } else { vec![] })
+    }
+
+    /// Pushes the result of a check, or fails if it's the verifying variant of the opcode and
+    /// the check failed.
+    fn push_or_verify(&mut self, op: opcodes::All, result: bool) -> Result<(), Error> {
+        use crate::blockdata::opcodes::all::*;
+
+        match op {
+            OP_EQUALVERIFY
+            | OP_NUMEQUALVERIFY
+            | OP_CHECKSIGVERIFY
+            | OP_CHECKMULTISIGVERIFY
+            | OP_CHECKDATASIGVERIFY => {
+                if !result {
+                    return Err(Error::Verify(op));
+                }
+                Ok(())
+            }
+            _ => self.push_bool(result),
+        }
+    }
+}
+
+/// Decodes a script number, which must be minimally encoded.
+fn decode_num(data: &[u8], max_size: usize) -> Result<i64, Error> {
+    if data.len() > max_size {
+        return Err(Error::NumberOverflow);
+    }
+    let (last, rest) = match data.split_last() {
+        Some(split) => split,
+        None => return Ok(0),
+    };
+    // The last byte may only be zero, save for its sign bit, if the sign bit can't be stored in
+    // the previous byte.
+    if last & 0x7f == 0 && rest.last().map_or(true, |b| b & 0x80 == 0) {
+        return Err(Error::MinimalData);
+    }
+    let magnitude = rest
+        .iter()
+        .chain(core::iter::once(&(last & 0x7f)))
+        .enumerate()
+        .fold(0u64, |n, (i, b)| n | u64::from(*b) << (8 * i));
+    // With the sign bit cleared, eight bytes hold at most 63 bits.
+    let n = magnitude as i64;
+
+    Ok(if last & 0x80 != 0 { -n } else { n })
+}
+
+/// Encodes a script number, minimally.
+fn encode_num(n: i64) -> Vec<u8> {
+    let mut data = Vec::with_capacity(MAX_NUM_SIZE);
+    let mut magnitude = if n < 0 { (n as u64).wrapping_neg() } else { n as u64 };
+
+    while magnitude > 0 {
+        data.push(magnitude as u8);
+        magnitude >>= 8;
+    }
+    if data.last().map_or(false, |b| b & 0x80 != 0) {
+        data.push(if n < 0 { 0x80 } else { 0x00 });
+    } else if n < 0 {
+        let last = data.len() - 1;
+        data[last] |= 0x80;
+    }
+    data
+}
+
+/// Strips the padding off a number.
+fn minimally_encode(mut data: Vec<u8>) -> Vec<u8> {
+    let last = match data.last() {
+        Some(last) => *last,
+        None => return data,
+    };
+    if last & 0x7f != 0 || (data.len() > 1 && data[data.len() - 2] & 0x80 != 0) {
+        return data;
+    }
+    // Find the last non-zero byte, and move the sign bit to it, or past it.
+    for i in (1..data.len()).rev() {
+        if data[i - 1] != 0 {
+            if data[i - 1] & 0x80 != 0 {
+                data[i] = last;
+                data.truncate(i + 1);
+            } else {
+                data[i - 1] |= last;
+                data.truncate(i);
+            }
+            return data;
+        }
+    }
+    Vec::new()
+}
+
+/// Fails if a number isn't the result of a checked arithmetic operation within range.
+fn checked(n: Option<i64>) -> Result<i64, Error> {
+    // The minimum value can't be encoded, since numbers use a sign bit.
+    n.filter(|n| *n != i64::MIN).ok_or(Error::ArithmeticOverflow)
+}
+
+/// Whether the data is pushed with the smallest possible opcode, given the size of the pushing
+/// opcode and its length prefix.
+fn is_minimal_push(data: &[u8], header: usize) -> bool {
+    match data {
+        // Pushed with `OP_1` to `OP_16`, or `OP_1NEGATE`.
+        [n] if (1..=16).contains(n) || *n == 0x81 => false,
+        _ => {
+            let minimal = match data.len() {
+                0..=75 => 1,
+                76..=0xff => 2,
+                0x100..=0xffff => 3,
+                _ => 5,
+            };
+            header == minimal
+        }
+    }
+}
+
+/// Whether the script only pushes data.
+pub(crate) fn is_push_only(script: &Script) -> Result<bool, Error> {
+    for instruction in script.instructions() {
+        if let Instruction::Op(op) = instruction? {
+            if op.to_u8() > opcodes::all::OP_PUSHNUM_16.to_u8() {
+                return Ok(false);
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Checks that a public key is a compressed or uncompressed key.
+fn check_pubkey_encoding(pubkey: &[u8]) -> Result<(), Error> {
+    match (pubkey.first().copied(), pubkey.len()) {
+        (Some(0x02), 33) | (Some(0x03), 33) | (Some(0x04), 65) => Ok(()),
+        _ => Err(Error::PubkeyType),
+    }
+}
+
+/// Parses a signature without its sighash type. 64-byte signatures are Schnorr signatures, and
+/// others are DER-encoded ECDSA signatures, which must have a low `S` value.
+fn parse_signature(sig: &[u8]) -> Result<Signature, Error> {
+    if sig.len() == cash_schnorr::SIGNATURE_SIZE {
+        return cash_schnorr::Signature::from_slice(sig)
+            .map(Signature::Schnorr)
+            .map_err(|_| Error::SigBadLength);
+    }
+    let sig = ecdsa::Signature::from_der(sig).map_err(|_| Error::SigDer)?;
+    let mut normalized = sig;
+    normalized.normalize_s();
+
+    if normalized != sig {
+        return Err(Error::SigHighS);
+    }
+    Ok(Signature::Ecdsa(sig))
+}
+
+/// Decodes the bitfield of a Schnorr multisig, which has a bit for each public key.
+fn decode_bitfield(data: &[u8], keys: usize) -> Result<u32, Error> {
+    if data.len() != (keys + 7) / 8 {
+        return Err(Error::InvalidBitfield);
+    }
+    let bitfield = data.iter().rev().fold(0u32, |bits, b| bits << 8 | u32::from(*b));
+
+    if bitfield >> keys != 0 {
+        return Err(Error::InvalidBitfield);
+    }
+    Ok(bitfield)
+}
+
+/// The category of a token, followed by the capability of its non-fungible token if that's
+/// mutable or minting. Empty if there is no token.
+fn token_category(token: &Option<OutputData>) -> Vec<u8> {
+    match token {
+        Some(token) => {
+            let mut category = token.id[..].to_vec();
+
+            if token.has_nft() && token.capability() != Capability::None as u8 {
+                category.push(token.capability());
+            }
+            category
+        }
+        None => Vec::new(),
+    }
+}
+
+/// The commitment of a non-fungible token. Empty if there is none.
+fn token_commitment(token: &Option<OutputData>) -> Vec<u8> {
+    token.as_ref().map_or_else(Vec::new, |t| t.commitment.clone())
+}
+
+/// The fungible token amount. Zero if there is none.
+fn token_amount(token: &Option<OutputData>) -> i64 {
+    token.as_ref().map_or(0, |t| t.amount)
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::SecretKey;
+
+    use super::*;
+    use crate::blockdata::opcodes::all::*;
+    use crate::blockdata::script::Builder;
+    use crate::blockdata::transaction::TxIn;
+    use crate::util::key;
+    use crate::{PackedLockTime, TokenID};
+
+    fn spend(script_pubkey: Script, token: Option<OutputData>) -> (Transaction, Vec<TxOut>) {
+        let spent = vec![TxOut { value: 10_000, script_pubkey, token }];
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: vec![TxOut { value: 9_000, script_pubkey: Script::new(), token: None }],
+        };
+        (tx, spent)
+    }
+
+    fn eval(script: Script) -> Result<Vec<Vec<u8>>, Error> {
+        let (tx, spent) = spend(Script::new(), None);
+        let mut interpreter = Interpreter::new(&tx, 0, &spent)?;
+
+        interpreter.eval(&script)?;
+        Ok(interpreter.stack().to_vec())
+    }
+
+    #[test]
+    fn test_num() {
+        for n in [0, 1, -1, 127, 128, -128, 255, 1 << 31, -(1 << 40), i64::MAX, -i64::MAX] {
+            assert_eq!(decode_num(&encode_num(n), MAX_NUM_SIZE), Ok(n));
+        }
+        assert_eq!(encode_num(128), vec![0x80, 0x00]);
+        assert_eq!(encode_num(-128), vec![0x80, 0x80]);
+        assert_eq!(decode_num(&[0x01, 0x00], MAX_NUM_SIZE), Err(Error::MinimalData));
+        assert_eq!(decode_num(&[0x80], MAX_NUM_SIZE), Err(Error::MinimalData));
+        assert_eq!(decode_num(&[0x01; 9], MAX_NUM_SIZE), Err(Error::NumberOverflow));
+        assert_eq!(minimally_encode(vec![0x01, 0x00, 0x00, 0x80]), vec![0x81]);
+        assert_eq!(minimally_encode(vec![0x80, 0x00, 0x00]), vec![0x80, 0x00]);
+        assert_eq!(minimally_encode(vec![0x00, 0x80]), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let script = Builder::new()
+            .push_int(1 << 40)
+            .push_int(1 << 20)
+            .push_opcode(OP_MUL)
+            .push_int(1 << 60)
+            .push_opcode(OP_NUMEQUAL)
+            .push_int(-7)
+            .push_int(2)
+            .push_opcode(OP_DIV)
+            .push_int(-7)
+            .push_int(2)
+            .push_opcode(OP_MOD)
+            .into_script();
+        assert_eq!(eval(script), Ok(vec![vec![0x01], encode_num(-3), encode_num(-1)]));
+
+        let script =
+            Builder::new().push_int(i64::MAX).push_int(1).push_opcode(OP_ADD).into_script();
+        assert_eq!(eval(script), Err(Error::ArithmeticOverflow));
+
+        let script = Builder::new().push_int(1).push_int(0).push_opcode(OP_MOD).into_script();
+        assert_eq!(eval(script), Err(Error::DivisionByZero));
+    }
+
+    #[test]
+    fn test_splice() {
+        let script = Builder::new()
+            .push_slice(b"hello")
+            .push_slice(b" world")
+            .push_opcode(OP_CAT)
+            .push_int(5)
+            .push_opcode(OP_SPLIT)
+            .push_opcode(OP_REVERSEBYTES)
+            .push_int(-1)
+            .push_int(4)
+            .push_opcode(OP_NUM2BIN)
+            .push_opcode(OP_DUP)
+            .push_opcode(OP_BIN2NUM)
+            .into_script();
+        assert_eq!(
+            eval(script),
+            Ok(vec![
+                b"hello".to_vec(),
+                b"dlrow ".to_vec(),
+                vec![0x01, 0x00, 0x00, 0x80],
+                vec![0x81]
+            ])
+        );
+
+        let script = Builder::new().push_slice(b"abc").push_int(4).push_opcode(OP_SPLIT);
+        assert_eq!(eval(script.into_script()), Err(Error::InvalidSplitRange));
+    }
+
+    #[test]
+    fn test_flow_control() {
+        let script = Builder::new()
+            .push_int(0)
+            .push_opcode(OP_IF)
+            .push_opcode(OP_RETURN)
+            .push_opcode(OP_ELSE)
+            .push_int(2)
+            .push_opcode(OP_ENDIF)
+            .into_script();
+        assert_eq!(eval(script), Ok(vec![vec![0x02]]));
+
+        let script = Builder::new().push_int(1).push_opcode(OP_IF).into_script();
+        assert_eq!(eval(script), Err(Error::UnbalancedConditional));
+
+        let script = Builder::new().push_slice(&[0x02, 0x00]).push_opcode(OP_IF).into_script();
+        assert_eq!(eval(script), Err(Error::MinimalIf));
+
+        // Disabled opcodes fail even if not executed.
+        let script = Builder::new()
+            .push_int(0)
+            .push_opcode(OP_IF)
+            .push_opcode(OP_2MUL)
+            .push_opcode(OP_ENDIF)
+            .into_script();
+        assert_eq!(eval(script), Err(Error::DisabledOpcode(OP_2MUL)));
+
+        // Pushes must be minimal.
+        assert_eq!(eval(Script::from(vec![0x01, 0x05])), Err(Error::MinimalData));
+    }
+
+    #[test]
+    fn test_checksig() {
+        let secp = Secp256k1::new();
+        let secret = SecretKey::from_slice(&[7; 32]).unwrap();
+        let pubkey = key::PublicKey::new(PublicKey::from_secret_key(&secp, &secret));
+        let (mut tx, spent) = spend(Script::new_p2pkh(&pubkey.pubkey_hash()), None);
+
+        let sighash = SighashCache::new(&tx)
+            .forkid_signature_hash(
+                0,
+                &spent[0].script_pubkey,
+                &Prevouts::All(&spent),
+                ForkIdSighashType::ALL,
+            )
+            .unwrap();
+        let schnorr = cash_schnorr::sign(&secp, &sighash.into_inner(), &secret).unwrap();
+        let ecdsa = secp.sign_ecdsa(&Message::from_slice(&sighash[..]).unwrap(), &secret);
+
+        for sig in [schnorr.serialize().to_vec(), ecdsa.serialize_der().to_vec()] {
+            let mut sig = sig;
+            sig.push(ForkIdSighashType::ALL.to_u32() as u8);
+
+            tx.input[0].script_sig =
+                Builder::new().push_slice(&sig).push_key(&pubkey).into_script();
+            let mut interpreter = Interpreter::new(&tx, 0, &spent).unwrap();
+            assert_eq!(interpreter.verify(), Ok(()));
+            assert_eq!(interpreter.sigchecks(), 1);
+
+            // Signatures commit to the outputs.
+            tx.output[0].value += 1;
+            assert_eq!(verify_input(&tx, 0, &spent), Err(Error::SigNullFail));
+            tx.output[0].value -= 1;
+        }
+        tx.input[0].script_sig = Builder::new().push_slice(&[]).push_key(&pubkey).into_script();
+        assert_eq!(verify_input(&tx, 0, &spent), Err(Error::EvalFalse));
+
+        tx.input[0].script_sig = Builder::new().push_key(&pubkey).push_opcode(OP_DUP).into_script();
+        assert_eq!(verify_input(&tx, 0, &spent), Err(Error::SigPushOnly));
+    }
+
+    #[test]
+    fn test_checkdatasig() {
+        let secp = Secp256k1::new();
+        let secret = SecretKey::from_slice(&[7; 32]).unwrap();
+        let pubkey = PublicKey::from_secret_key(&secp, &secret).serialize();
+        let msg = b"message";
+        let hash = sha256::Hash::hash(msg);
+
+        let schnorr = cash_schnorr::sign(&secp, &hash.into_inner(), &secret).unwrap();
+        let ecdsa = secp.sign_ecdsa(&Message::from_slice(&hash[..]).unwrap(), &secret);
+
+        for sig in [schnorr.serialize().to_vec(), ecdsa.serialize_der().to_vec()] {
+            let script = Builder::new()
+                .push_slice(&sig)
+                .push_slice(msg)
+                .push_slice(&pubkey)
+                .push_opcode(OP_CHECKDATASIG)
+                .into_script();
+            assert_eq!(eval(script), Ok(vec![vec![0x01]]));
+
+            let script = Builder::new()
+                .push_slice(&sig)
+                .push_slice(b"other")
+                .push_slice(&pubkey)
+                .push_opcode(OP_CHECKDATASIG)
+                .into_script();
+            assert_eq!(eval(script), Err(Error::SigNullFail));
+        }
+        let script = Builder::new()
+            .push_slice(&[])
+            .push_slice(msg)
+            .push_slice(&pubkey)
+            .push_opcode(OP_CHECKDATASIG)
+            .into_script();
+        assert_eq!(eval(script), Ok(vec![vec![]]));
+    }
+
+    #[test]
+    fn test_introspection() {
+        let token = OutputData {
+            id: TokenID::from_inner([7; 32]),
+            bitfield: 0x32,
+            amount: 100,
+            commitment: vec![],
+        };
+        let (tx, spent) = spend(Script::new(), Some(token));
+        let mut category = vec![7; 32];
+        category.push(Capability::Minting as u8);
+
+        let script = Builder::new()
+            .push_opcode(OP_INPUTINDEX)
+            .push_opcode(OP_UTXOTOKENCATEGORY)
+            .push_int(0)
+            .push_opcode(OP_UTXOTOKENAMOUNT)
+            .push_int(0)
+            .push_opcode(OP_UTXOVALUE)
+            .push_int(0)
+            .push_opcode(OP_OUTPUTTOKENCATEGORY)
+            .push_opcode(OP_TXOUTPUTCOUNT)
+            .into_script();
+        let mut interpreter = Interpreter::new(&tx, 0, &spent).unwrap();
+        interpreter.eval(&script).unwrap();
+
+        assert_eq!(
+            interpreter.stack(),
+            &[category, encode_num(100), encode_num(10_000), vec![], vec![0x01]]
+        );
+
+        let script = Builder::new().push_int(1).push_opcode(OP_OUTPUTVALUE).into_script();
+        assert_eq!(eval(script), Err(Error::InvalidOutputIndex));
+    }
+
+    #[test]
+    fn test_p2sh32() {
+        let redeem_script = Builder::new()
+            .push_int(2)
+            .push_opcode(OP_ADD)
+            .push_int(5)
+            .push_opcode(OP_NUMEQUAL)
+            .into_script();
+        let script_pubkey = Builder::new()
+            .push_opcode(OP_HASH256)
+            .push_slice(&sha256d::Hash::hash(redeem_script.as_bytes())[..])
+            .push_opcode(OP_EQUAL)
+            .into_script();
+        assert!(script_pubkey.is_p2sh32());
+
+        let (mut tx, spent) = spend(script_pubkey, None);
+
+        tx.input[0].script_sig =
+            Builder::new().push_int(3).push_slice(redeem_script.as_bytes()).into_script();
+        assert_eq!(verify_input(&tx, 0, &spent), Ok(()));
+
+        tx.input[0].script_sig =
+            Builder::new().push_int(4).push_slice(redeem_script.as_bytes()).into_script();
+        assert_eq!(verify_input(&tx, 0, &spent), Err(Error::EvalFalse));
+
+        tx.input[0].script_sig = Builder::new()
+            .push_int(1)
+            .push_int(3)
+            .push_slice(redeem_script.as_bytes())
+            .into_script();
+        assert_eq!(verify_input(&tx, 0, &spent), Err(Error::CleanStack));
+    }
+}
@@ -9,6 +9,7 @@
 
 pub mod block;
 pub mod constants;
+pub mod interpreter;
 pub mod locktime;
 pub mod opcodes;
 pub mod script;
@@ -449,18 +449,18 @@ pub mod all {
     pub const OP_OUTPUTVALUE: All = All {code: 0xcc};
     /// Pop the top item from the stack as an output index (Script Number). Push the locking bytecode of the output at that index to the stack.
     pub const OP_OUTPUTBYTECODE: All = All {code: 0xcd};
-    /// Synonym for OP_RETURN.
-    pub const OP_RETURN_206: All = All {code: 0xce};
-    /// Synonym for OP_RETURN.
-    pub const OP_RETURN_207: All = All {code: 0xcf};
-    /// Synonym for OP_RETURN.
-    pub const OP_RETURN_208: All = All {code: 0xd0};
-    /// Synonym for OP_RETURN.
-    pub const OP_RETURN_209: All = All {code: 0xd1};
-    /// Synonym for OP_RETURN.
-    pub const OP_RETURN_210: All = All {code: 0xd2};
-    /// Synonym for OP_RETURN.
-    pub const OP_RETURN_211: All = All {code: 0xd3};
+    /// Pop the top item from the stack as an input index (Script Number). Push the token category of the Unspent Transaction Output (UTXO) spent by that input, followed by its non-fungible token capability if it's mutable or minting, or an empty item if it has no tokens.
+    pub const OP_UTXOTOKENCATEGORY: All = All {code: 0xce};
+    /// Pop the top item from the stack as an input index (Script Number). Push the non-fungible token commitment of the Unspent Transaction Output (UTXO) spent by that input, or an empty item if it has none.
+    pub const OP_UTXOTOKENCOMMITMENT: All = All {code: 0xcf};
+    /// Pop the top item from the stack as an input index (Script Number). Push the fungible token amount of the Unspent Transaction Output (UTXO) spent by that input to the stack as a Script Number.
+    pub const OP_UTXOTOKENAMOUNT: All = All {code: 0xd0};
+    /// Pop the top item from the stack as an output index (Script Number). Push the token category of the output at that index, followed by its non-fungible token capability if it's mutable or minting, or an empty item if it has no tokens.
+    pub const OP_OUTPUTTOKENCATEGORY: All = All {code: 0xd1};
+    /// Pop the top item from the stack as an output index (Script Number). Push the non-fungible token commitment of the output at that index, or an empty item if it has none.
+    pub const OP_OUTPUTTOKENCOMMITMENT: All = All {code: 0xd2};
+    /// Pop the top item from the stack as an output index (Script Number). Push the fungible token amount of the output at that index to the stack as a Script Number.
+    pub const OP_OUTPUTTOKENAMOUNT: All = All {code: 0xd3};
     /// Synonym for OP_RETURN.
     pub const OP_RETURN_212: All = All {code: 0xd4};
     /// Synonym for OP_RETURN.
@@ -662,6 +662,12 @@ impl fmt::Debug for All {
             all::OP_INPUTSEQUENCENUMBER => write!(f, "INPUTSEQUENCENUMBER"),
             all::OP_OUTPUTVALUE => write!(f, "OUTPUTVALUE"),
             all::OP_OUTPUTBYTECODE => write!(f, "OUTPUTBYTECODE"),
+            all::OP_UTXOTOKENCATEGORY => write!(f, "UTXOTOKENCATEGORY"),
+            all::OP_UTXOTOKENCOMMITMENT => write!(f, "UTXOTOKENCOMMITMENT"),
+            all::OP_UTXOTOKENAMOUNT => write!(f, "UTXOTOKENAMOUNT"),
+            all::OP_OUTPUTTOKENCATEGORY => write!(f, "OUTPUTTOKENCATEGORY"),
+            all::OP_OUTPUTTOKENCOMMITMENT => write!(f, "OUTPUTTOKENCOMMITMENT"),
+            all::OP_OUTPUTTOKENAMOUNT => write!(f, "OUTPUTTOKENAMOUNT"),
             all::OP_SPECIAL_TOKEN_PREFIX => write!(f, "SPECIAL_TOKEN_PREFIX"),
             All {code: x} => write!(f, "RETURN_{}", x),
         }
@@ -734,7 +740,7 @@ impl All {
             // 76 opcodes of PushBytes class
             (op, _) if op.code <= OP_PUSHBYTES_75.code => Class::PushBytes(self.code as u32),
 
-            // opcodes of Ordinary class: 61 for Legacy and 60 for TapScript context
+            // opcodes of Ordinary class: 70 for Legacy and 59 for TapScript context
             (_, _) => Class::Ordinary(Ordinary::with(self)),
         }
     }
@@ -842,7 +848,7 @@ macro_rules! ordinary_opcode {
     );
 }
 
-// "Ordinary" opcodes -- should be 61 of these
+// "Ordinary" opcodes -- should be 71 of these
 ordinary_opcode! {
     // pushdata
     OP_PUSHDATA1, OP_PUSHDATA2, OP_PUSHDATA4,
@@ -853,11 +859,15 @@ ordinary_opcode! {
     OP_2DROP, OP_2DUP, OP_3DUP, OP_2OVER, OP_2ROT, OP_2SWAP,
     OP_DROP, OP_DUP, OP_NIP, OP_OVER, OP_PICK, OP_ROLL, OP_ROT, OP_SWAP, OP_TUCK,
     OP_IFDUP, OP_DEPTH, OP_SIZE,
+    // splice, re-enabled on Bitcoin Cash
+    OP_CAT, OP_SPLIT, OP_NUM2BIN, OP_BIN2NUM,
+    // bitwise, re-enabled on Bitcoin Cash
+    OP_AND, OP_OR, OP_XOR,
     // equality
     OP_EQUAL, OP_EQUALVERIFY,
     // arithmetic
     OP_1ADD, OP_1SUB, OP_NEGATE, OP_ABS, OP_NOT, OP_0NOTEQUAL,
-    OP_ADD, OP_SUB, OP_BOOLAND, OP_BOOLOR,
+    OP_ADD, OP_SUB, OP_MUL, OP_DIV, OP_MOD, OP_BOOLAND, OP_BOOLOR,
     OP_NUMEQUAL, OP_NUMEQUALVERIFY, OP_NUMNOTEQUAL, OP_LESSTHAN,
     OP_GREATERTHAN, OP_LESSTHANOREQUAL, OP_GREATERTHANOREQUAL,
     OP_MIN, OP_MAX, OP_WITHIN,
@@ -919,6 +929,14 @@ mod tests {
         let op187 = all::OP_CHECKDATASIGVERIFY;
         assert_eq!(op187.classify(ClassifyContext::Legacy), Class::ReturnOp);
         assert_eq!(op187.classify(ClassifyContext::TapScript), Class::SuccessOp);
+
+        let op126 = all::OP_CAT;
+        assert_eq!(op126.classify(ClassifyContext::Legacy), Class::Ordinary(Ordinary::OP_CAT));
+        assert_eq!(op126.classify(ClassifyContext::TapScript), Class::SuccessOp);
+
+        let op149 = all::OP_MUL;
+        assert_eq!(op149.classify(ClassifyContext::Legacy), Class::Ordinary(Ordinary::OP_MUL));
+        assert_eq!(op149.classify(ClassifyContext::TapScript), Class::SuccessOp);
     }
 
     #[test]
@@ -1130,12 +1148,12 @@ mod tests {
         roundtrip!(unique, OP_INPUTSEQUENCENUMBER);
         roundtrip!(unique, OP_OUTPUTVALUE);
         roundtrip!(unique, OP_OUTPUTBYTECODE);
-        roundtrip!(unique, OP_RETURN_206);
-        roundtrip!(unique, OP_RETURN_207);
-        roundtrip!(unique, OP_RETURN_208);
-        roundtrip!(unique, OP_RETURN_209);
-        roundtrip!(unique, OP_RETURN_210);
-        roundtrip!(unique, OP_RETURN_211);
+        roundtrip!(unique, OP_UTXOTOKENCATEGORY);
+        roundtrip!(unique, OP_UTXOTOKENCOMMITMENT);
+        roundtrip!(unique, OP_UTXOTOKENAMOUNT);
+        roundtrip!(unique, OP_OUTPUTTOKENCATEGORY);
+        roundtrip!(unique, OP_OUTPUTTOKENCOMMITMENT);
+        roundtrip!(unique, OP_OUTPUTTOKENAMOUNT);
         roundtrip!(unique, OP_RETURN_212);
         roundtrip!(unique, OP_RETURN_213);
         roundtrip!(unique, OP_RETURN_214);
@@ -25,7 +25,7 @@ use serde;
 
 use crate::blockdata::opcodes;
 use crate::consensus::{encode, Decodable, Encodable};
-use crate::hash_types::{PubkeyHash, ScriptHash, WPubkeyHash, WScriptHash};
+use crate::hash_types::{PubkeyHash, ScriptHash, ScriptHash32, WPubkeyHash, WScriptHash};
 use crate::hashes::{hex, Hash};
 use crate::policy::DUST_RELAY_TX_FEE;
 use crate::OutPoint;
@@ -369,6 +369,15 @@ impl Script {
             .into_script()
     }
 
+    /// Generates P2SH32-type of scriptPubkey with a given 32-byte hash of the redeem script.
+    pub fn new_p2sh32(script_hash: &ScriptHash32) -> Script {
+        Builder::new()
+            .push_opcode(opcodes::all::OP_HASH256)
+            .push_slice(&script_hash[..])
+            .push_opcode(opcodes::all::OP_EQUAL)
+            .into_script()
+    }
+
     /// Generates P2WPKH-type of scriptPubkey.
     #[deprecated(since = "0.28.0", note = "use Script::new_v0_p2wpkh method instead")]
     pub fn new_v0_wpkh(pubkey_hash: &WPubkeyHash) -> Script {
@@ -422,6 +431,11 @@ impl Script {
         ScriptHash::hash(self.as_bytes())
     }
 
+    /// Returns 256-bit hash of the script for P2SH32 outputs.
+    pub fn script_hash32(&self) -> ScriptHash32 {
+        ScriptHash32::hash(self.as_bytes())
+    }
+
     /// Returns 256-bit hash of the script for P2WSH outputs.
     pub fn wscript_hash(&self) -> WScriptHash {
         WScriptHash::hash(self.as_bytes())
@@ -457,6 +471,11 @@ impl Script {
         Script::new_p2sh(&self.script_hash())
     }
 
+    /// Computes the P2SH32 output corresponding to this redeem script.
+    pub fn to_p2sh32(&self) -> Script {
+        Script::new_p2sh32(&self.script_hash32())
+    }
+
     /// Returns the script code used for spending a P2WPKH output if this script is a script pubkey
     /// for a P2WPKH output. The `scriptCode` is described in [BIP143].
     ///
@@ -510,6 +529,15 @@ impl Script {
             && self.0[22] == opcodes::all::OP_EQUAL.to_u8()
     }
 
+    /// Checks whether a script pubkey is a 32-byte P2SH output, as introduced in May 2023.
+    #[inline]
+    pub fn is_p2sh32(&self) -> bool {
+        self.0.len() == 35
+            && self.0[0] == opcodes::all::OP_HASH256.to_u8()
+            && self.0[1] == opcodes::all::OP_PUSHBYTES_32.to_u8()
+            && self.0[34] == opcodes::all::OP_EQUAL.to_u8()
+    }
+
     /// Checks whether a script pubkey is a P2PKH output.
     #[inline]
     pub fn is_p2pkh(&self) -> bool {
@@ -802,6 +830,11 @@ pub struct Instructions<'a> {
 }
 
 impl<'a> Instructions<'a> {
+    /// Returns the number of script bytes not yet iterated over.
+    pub(crate) fn remaining(&self) -> usize {
+        self.data.len()
+    }
+
     /// Set the iterator to end so that it won't iterate any longer
     fn kill(&mut self) {
         let len = self.data.len();
@@ -1142,6 +1175,7 @@ mod test {
     use crate::blockdata::opcodes;
     use crate::consensus::encode::{deserialize, serialize};
     use crate::hashes::hex::{FromHex, ToHex};
+    use crate::hashes::sha256d;
     use crate::internal_macros::hex_script;
     use crate::util::key::PublicKey;
     use crate::util::psbt::serialize::Serialize;
@@ -1370,6 +1404,12 @@ mod test {
             script.wscript_hash().to_hex(),
             "3e1525eb183ad4f9b3c5fa3175bdca2a52e947b135bbb90383bf9f6408e2c324"
         );
+        assert_eq!(script.script_hash32()[..], sha256d::Hash::hash(script.as_bytes())[..]);
+
+        let p2sh32 = script.to_p2sh32();
+        assert!(p2sh32.is_p2sh32());
+        assert!(!p2sh32.is_p2sh());
+        assert_eq!(&p2sh32[2..34], &script.script_hash32()[..]);
     }
 
     #[test]
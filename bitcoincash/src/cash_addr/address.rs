@@ -0,0 +1,271 @@
+//! Cashaddr addresses.
+//!
+//! Unlike [`crate::Address`], which follows Bitcoin's base58 and bech32 encodings, a cashaddr
+//! [`Address`] tells whether its recipient accepts CashTokens, and can pay to 32-byte script
+//! hashes. Both convert to and from output scripts, and into each other.
+
+use core::convert::TryFrom;
+use core::fmt;
+use core::str::FromStr;
+
+use super::version_byte_flags::*;
+use super::{decode, encode, prefix, DecodingError};
+use crate::blockdata::script::Script;
+use crate::hashes::Hash;
+use crate::util::address::{self, Error};
+use crate::util::key::PublicKey;
+use crate::{Network, PubkeyHash, ScriptHash, ScriptHash32};
+
+/// What a cashaddr pays to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Payload {
+    /// P2PKH.
+    PubkeyHash(PubkeyHash),
+    /// P2SH, with a 20-byte script hash.
+    ScriptHash(ScriptHash),
+    /// P2SH32, with a 32-byte script hash.
+    ScriptHash32(ScriptHash32),
+}
+
+impl Payload {
+    /// Constructs a payload from an output script, if it has a cashaddr.
+    pub fn from_script(script: &Script) -> Result<Payload, Error> {
+        match address::Payload::from_script(script)? {
+            address::Payload::PubkeyHash(hash) => Ok(Payload::PubkeyHash(hash)),
+            address::Payload::ScriptHash(hash) => Ok(Payload::ScriptHash(hash)),
+            address::Payload::ScriptHash32(hash) => Ok(Payload::ScriptHash32(hash)),
+            address::Payload::WitnessProgram { .. } => Err(Error::UnrecognizedScript),
+        }
+    }
+
+    /// Generates the output script paying to this payload.
+    pub fn script_pubkey(&self) -> Script {
+        match self {
+            Payload::PubkeyHash(hash) => Script::new_p2pkh(hash),
+            Payload::ScriptHash(hash) => Script::new_p2sh(hash),
+            Payload::ScriptHash32(hash) => Script::new_p2sh32(hash),
+        }
+    }
+
+    /// Returns the hash paid to.
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Payload::PubkeyHash(hash) => hash,
+            Payload::ScriptHash(hash) => hash,
+            Payload::ScriptHash32(hash) => hash,
+        }
+    }
+}
+
+/// A cashaddr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Address {
+    /// What the address pays to.
+    pub payload: Payload,
+    /// The network on which this address is usable. All test networks share the `bchtest`
+    /// prefix, and are parsed as [`Network::Testnet`].
+    pub network: Network,
+    /// Whether the recipient signals that it accepts CashTokens.
+    pub token_aware: bool,
+}
+
+impl Address {
+    /// Creates an address paying to the given payload, which doesn't signal token support.
+    pub fn new(payload: Payload, network: Network) -> Address {
+        Address { payload, network, token_aware: false }
+    }
+
+    /// Creates a pay to (compressed) public key hash address from a public key.
+    pub fn p2pkh(pk: &PublicKey, network: Network) -> Address {
+        Address::new(Payload::PubkeyHash(pk.pubkey_hash()), network)
+    }
+
+    /// Creates a pay to script hash P2SH address from a redeem script.
+    pub fn p2sh(script: &Script, network: Network) -> Result<Address, Error> {
+        Address::try_from(address::Address::p2sh(script, network)?)
+    }
+
+    /// Creates a pay to 32-byte script hash P2SH32 address from a redeem script.
+    pub fn p2sh32(script: &Script, network: Network) -> Result<Address, Error> {
+        Address::try_from(address::Address::p2sh32(script, network)?)
+    }
+
+    /// Constructs an address from an output script.
+    pub fn from_script(script: &Script, network: Network) -> Result<Address, Error> {
+        Ok(Address::new(Payload::from_script(script)?, network))
+    }
+
+    /// Generates the output script paying to this address.
+    pub fn script_pubkey(&self) -> Script {
+        self.payload.script_pubkey()
+    }
+
+    /// Returns the same address, signalling that its recipient accepts tokens.
+    pub fn into_token_aware(self) -> Address {
+        Address { token_aware: true, ..self }
+    }
+
+    /// The type bits of the address version byte, see [`super::version_byte_flags`].
+    pub fn hash_type(&self) -> u8 {
+        match (self.payload, self.token_aware) {
+            (Payload::PubkeyHash(_), false) => TYPE_P2PKH,
+            (Payload::PubkeyHash(_), true) => TYPE_P2PKH_TOKEN,
+            (Payload::ScriptHash(_), false) | (Payload::ScriptHash32(_), false) => TYPE_P2SH,
+            (Payload::ScriptHash(_), true) | (Payload::ScriptHash32(_), true) => TYPE_P2SH_TOKEN,
+        }
+    }
+
+    /// Whether the address can be used on the given network, ie. whether they share the same
+    /// prefix.
+    pub fn is_valid_for_network(&self, network: Network) -> bool {
+        prefix(self.network) == prefix(network)
+    }
+}
+
+/// Alternate formatting `{:#}` gives the uppercase address, as used in QR codes.
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let addr = encode(self.payload.as_bytes(), self.hash_type(), self.network)
+            .expect("hashes have a valid cashaddr length");
+
+        if f.alternate() {
+            f.write_str(&addr.to_ascii_uppercase())
+        } else {
+            f.write_str(&addr)
+        }
+    }
+}
+
+/// Parses a cashaddr, see [`decode`] for the prefixes and spellings accepted.
+impl FromStr for Address {
+    type Err = DecodingError;
+
+    fn from_str(s: &str) -> Result<Address, DecodingError> {
+        let (hash, hash_type, network) = decode(s)?;
+        let payload = match (hash_type, hash.len()) {
+            (TYPE_P2PKH, 20) | (TYPE_P2PKH_TOKEN, 20) => {
+                Payload::PubkeyHash(PubkeyHash::from_slice(&hash).expect("hash is 20 bytes"))
+            }
+            (TYPE_P2SH, 20) | (TYPE_P2SH_TOKEN, 20) => {
+                Payload::ScriptHash(ScriptHash::from_slice(&hash).expect("hash is 20 bytes"))
+            }
+            (TYPE_P2SH, 32) | (TYPE_P2SH_TOKEN, 32) => {
+                Payload::ScriptHash32(ScriptHash32::from_slice(&hash).expect("hash is 32 bytes"))
+            }
+            (TYPE_P2PKH, len)
+            | (TYPE_P2PKH_TOKEN, len)
+            | (TYPE_P2SH, len)
+            | (TYPE_P2SH_TOKEN, len) => return Err(DecodingError::InvalidLength(len)),
+            (other, _) => return Err(DecodingError::InvalidVersion(other)),
+        };
+        let token_aware = hash_type == TYPE_P2PKH_TOKEN || hash_type == TYPE_P2SH_TOKEN;
+
+        Ok(Address { payload, network, token_aware })
+    }
+}
+
+impl From<Address> for address::Address {
+    fn from(addr: Address) -> address::Address {
+        let payload = match addr.payload {
+            Payload::PubkeyHash(hash) => address::Payload::PubkeyHash(hash),
+            Payload::ScriptHash(hash) => address::Payload::ScriptHash(hash),
+            Payload::ScriptHash32(hash) => address::Payload::ScriptHash32(hash),
+        };
+        address::Address { payload, network: addr.network }
+    }
+}
+
+/// Segwit addresses have no cashaddr.
+impl TryFrom<address::Address> for Address {
+    type Error = Error;
+
+    fn try_from(addr: address::Address) -> Result<Address, Error> {
+        let payload = match addr.payload {
+            address::Payload::PubkeyHash(hash) => Payload::PubkeyHash(hash),
+            address::Payload::ScriptHash(hash) => Payload::ScriptHash(hash),
+            address::Payload::ScriptHash32(hash) => Payload::ScriptHash32(hash),
+            address::Payload::WitnessProgram { .. } => return Err(Error::UnrecognizedScript),
+        };
+        Ok(Address::new(payload, addr.network))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let cases = [
+            ("bitcoincash:qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekg2", TYPE_P2PKH),
+            ("bitcoincash:zr7fzmep8g7h7ymfxy74lgc0v950j3r295z4y4gq0v", TYPE_P2PKH_TOKEN),
+            ("bitcoincash:pqv53dwyatxse2xh7nnlqhyr6ryjgfdtagkd4vc388", TYPE_P2SH),
+            ("bitcoincash:rqv53dwyatxse2xh7nnlqhyr6ryjgfdtag38xjkhc5", TYPE_P2SH_TOKEN),
+            (
+                "bitcoincash:p0llllllllllllllllllllllllllllllllllllllllllllllllll7x3vthu35",
+                TYPE_P2SH,
+            ),
+            (
+                "bitcoincash:r0llllllllllllllllllllllllllllllllllllllllllllllllll75zs2wagl",
+                TYPE_P2SH_TOKEN,
+            ),
+        ];
+        for (s, hash_type) in cases.iter() {
+            let addr = s.parse::<Address>().unwrap();
+
+            assert_eq!(addr.to_string(), *s);
+            assert_eq!(addr.hash_type(), *hash_type);
+            assert_eq!(
+                Address::from_script(&addr.script_pubkey(), addr.network).unwrap(),
+                Address { token_aware: false, ..addr }
+            );
+            assert_eq!(
+                Address::try_from(address::Address::from(addr)).unwrap(),
+                Address { token_aware: false, ..addr }
+            );
+        }
+    }
+
+    #[test]
+    fn test_script() {
+        let script = Script::from(vec![0x51]);
+        let p2sh = Address::p2sh(&script, Network::Testnet).unwrap();
+        let p2sh32 = Address::p2sh32(&script, Network::Testnet).unwrap();
+
+        assert!(p2sh.script_pubkey().is_p2sh());
+        assert!(p2sh32.script_pubkey().is_p2sh32());
+        assert_eq!(p2sh32.script_pubkey(), script.to_p2sh32());
+        assert!(p2sh32.to_string().starts_with("bchtest:p"));
+        assert!(p2sh32.into_token_aware().to_string().starts_with("bchtest:r"));
+        assert_eq!(format!("{:#}", p2sh32), p2sh32.to_string().to_ascii_uppercase());
+
+        assert!(p2sh32.is_valid_for_network(Network::Chipnet));
+        assert!(!p2sh32.is_valid_for_network(Network::Regtest));
+        assert!(!p2sh32.is_valid_for_network(Network::Bitcoin));
+
+        assert_eq!(
+            Address::from_script(&Script::new_op_return(b"memo"), Network::Bitcoin),
+            Err(Error::UnrecognizedScript)
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        // Unprefixed addresses are mainnet addresses.
+        assert_eq!(
+            "qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekg2".parse::<Address>().unwrap().network,
+            Network::Bitcoin
+        );
+        assert_eq!(
+            "bitcoincash:qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekg3".parse::<Address>(),
+            Err(DecodingError::Misspelled(String::from(
+                "bitcoincash:qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekg2"
+            )))
+        );
+        // A 24-byte public key hash.
+        assert_eq!(
+            "bitcoincash:q9adhakpwzztepkpwp5z0dq62m6u5v5xtyj7j3h2ws4mr9g0".parse::<Address>(),
+            Err(DecodingError::InvalidLength(24))
+        );
+    }
+}
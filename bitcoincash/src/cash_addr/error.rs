@@ -30,6 +30,8 @@ pub enum DecodingError {
     InvalidPrefix(String),
     /// Checksum failed (checksum).
     ChecksumFailed(u64),
+    /// Checksum failed, because of a single mistyped character (corrected address).
+    Misspelled(String),
     /// Unexpected character (char).
     InvalidChar(char),
     /// Version byte was not recognized.
@@ -46,6 +48,9 @@ impl fmt::Display for DecodingError {
             DecodingError::ChecksumFailed(actual) => {
                 write!(f, "invalid checksum (actual {} != 0)", actual)
             }
+            DecodingError::Misspelled(corrected) => {
+                write!(f, "invalid checksum, did you mean {}?", corrected)
+            }
             DecodingError::InvalidChar(index) => write!(f, "invalid char ({})", index),
             DecodingError::NoPrefix => write!(f, "zero or multiple prefixes"),
             DecodingError::MixedCase => write!(f, "mixed case string"),
@@ -64,6 +69,7 @@ impl Error for DecodingError {
     fn description(&self) -> &str {
         match *self {
             DecodingError::ChecksumFailed { .. } => "invalid checksum",
+            DecodingError::Misspelled(_) => "invalid checksum",
             DecodingError::InvalidChar(_) => "invalid char",
             DecodingError::NoPrefix => "zero or multiple prefixes",
             DecodingError::MixedCase => "mixed case string",
@@ -1,7 +1,9 @@
 //! Bitcoin cash address.
+pub mod address;
 /// cash addr errors
 pub mod error;
 // use anyhow::Result;
+pub use address::{Address, Payload};
 pub use error::{DecodingError, EncodingError};
 
 use crate::Network;
@@ -94,6 +96,37 @@ fn polymod(v: &[u8]) -> u64 {
     c ^ 1
 }
 
+// The network of an address prefix.
+fn network(prefix: &str) -> Option<Network> {
+    match prefix {
+        MAINNET_PREFIX => Some(Network::Bitcoin),
+        TESTNET_PREFIX => Some(Network::Testnet),
+        REGNET_PREFIX => Some(Network::Regtest),
+        _ => None,
+    }
+}
+
+// Find the valid payload differing from the given one by a single character. Since the
+// checksum detects any error affecting up to two characters, there is at most one.
+fn correct(payload: &[u8], is_valid: impl Fn(&[u8]) -> bool) -> Option<Vec<u8>> {
+    let mut candidate = payload.to_vec();
+
+    for i in 0..payload.len() {
+        for c in 0..CHARSET.len() as u8 {
+            if c == payload[i] {
+                continue;
+            }
+            candidate[i] = c;
+
+            if is_valid(&candidate) {
+                return Some(candidate);
+            }
+        }
+        candidate[i] = payload[i];
+    }
+    None
+}
+
 // Expand the address prefix for the checksum operation.
 fn expand_prefix(prefix: &str) -> Vec<u8> {
     let mut ret: Vec<u8> = prefix.chars().map(|c| (c as u8) & 0x1f).collect();
@@ -133,6 +166,18 @@ fn convert_bits(data: &[u8], inbits: u8, outbits: u8, pad: bool) -> Vec<u8> {
     }
     ret
 }
+/// The address prefix used on the given network. Test networks all share the same prefix.
+pub fn prefix(network: Network) -> &'static str {
+    match network {
+        Network::Bitcoin => MAINNET_PREFIX,
+        Network::Testnet => TESTNET_PREFIX,
+        Network::Testnet4 => TESTNET_PREFIX,
+        Network::Scalenet => TESTNET_PREFIX,
+        Network::Regtest => REGNET_PREFIX,
+        Network::Chipnet => TESTNET_PREFIX,
+    }
+}
+
 /// todo
 pub fn encode(
     raw: &[u8],
@@ -154,14 +199,7 @@ pub fn encode(
     } | hash_flag;
 
     // Get prefix
-    let prefix = match network {
-        Network::Bitcoin => MAINNET_PREFIX,
-        Network::Testnet => TESTNET_PREFIX,
-        Network::Testnet4 => TESTNET_PREFIX,
-        Network::Scalenet => TESTNET_PREFIX,
-        Network::Regtest => REGNET_PREFIX,
-        Network::Chipnet => TESTNET_PREFIX,
-    };
+    let prefix = prefix(network);
 
     // Convert payload to 5 bit array
     let mut payload = Vec::with_capacity(1 + raw.len());
@@ -185,22 +223,30 @@ pub fn encode(
     let cashaddr = [prefix, ":", &payload_str, &checksum_str].concat();
     Ok(cashaddr)
 }
-/// todo
+/// Decode a cashaddr, returning its hash, the type bits of its version byte, and its network.
+///
+/// The prefix is case-insensitive, and may be omitted, in which case the network is that of
+/// the prefix the checksum is valid for. All test networks share the same prefix, and are
+/// returned as [`Network::Testnet`]. When the checksum fails because of a single mistyped
+/// character, the corrected address is suggested with [`DecodingError::Misspelled`].
 pub fn decode(addr_str: &str) -> Result<(Vec<u8>, u8, Network), DecodingError> {
     // Delimit and extract prefix
     let parts: Vec<&str> = addr_str.split(':').collect();
     let (prefix, payload_str) = match parts.len() {
-        1 => (MAINNET_PREFIX, parts[0]),
-        2 => (parts[0], parts[1]),
+        1 => (None, parts[0]),
+        2 => (Some(parts[0].to_ascii_lowercase()), parts[1]),
         _ => return Err(DecodingError::Other(format!("Invalid address: '{}'", addr_str))),
     };
 
-    // Match network
-    let network = match prefix {
-        MAINNET_PREFIX => Network::Bitcoin,
-        TESTNET_PREFIX => Network::Testnet,
-        REGNET_PREFIX => Network::Regtest,
-        _ => return Err(DecodingError::InvalidPrefix(prefix.to_string())),
+    // Match network, or try all of them when there is no prefix
+    let prefixes = match prefix {
+        Some(ref prefix) => {
+            if self::network(prefix).is_none() {
+                return Err(DecodingError::InvalidPrefix(prefix.to_string()));
+            }
+            vec![prefix.as_str()]
+        }
+        None => vec![MAINNET_PREFIX, TESTNET_PREFIX, REGNET_PREFIX],
     };
 
     // Do some sanity checks on the string
@@ -231,12 +277,34 @@ pub fn decode(addr_str: &str) -> Result<(Vec<u8>, u8, Network), DecodingError> {
         .collect();
     let payload_5_bits = payload_5_bits?;
 
-    // Verify the checksum
-    let checksum = polymod(&[&expand_prefix(prefix), &payload_5_bits[..]].concat());
-    if checksum != 0 {
-        return Err(DecodingError::ChecksumFailed(checksum));
+    // The checksum alone takes 8 characters, and the version byte 2 more
+    if payload_5_bits.len() < 10 {
+        return Err(DecodingError::InvalidLength(0));
     }
 
+    // Verify the checksum
+    let checksum = |prefix: &str, payload: &[u8]| -> u64 {
+        polymod(&[&expand_prefix(prefix), payload].concat())
+    };
+    let prefix = match prefixes.iter().find(|p| checksum(p, &payload_5_bits) == 0) {
+        Some(prefix) => *prefix,
+        None => {
+            let correction = prefixes.iter().find_map(|p| {
+                correct(&payload_5_bits, |payload| checksum(p, payload) == 0)
+                    .map(|payload| (*p, payload))
+            });
+            return Err(match correction {
+                Some((prefix, payload)) => {
+                    let payload: String =
+                        payload.iter().map(|b| CHARSET[*b as usize] as char).collect();
+                    DecodingError::Misspelled([prefix, ":", &payload].concat())
+                }
+                None => DecodingError::ChecksumFailed(checksum(prefixes[0], &payload_5_bits)),
+            });
+        }
+    };
+    let network = self::network(prefix).expect("prefix is known");
+
     // Convert from 5 bit array to byte array
     let len_5_bit = payload_5_bits.len();
     let payload = convert_bits(&payload_5_bits[..(len_5_bit - 8)], 5, 8, false);
@@ -266,159 +334,130 @@ pub fn decode(addr_str: &str) -> Result<(Vec<u8>, u8, Network), DecodingError> {
     Ok((body.to_vec(), version_type, network))
 }
 
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-//     use hex;
-
-//     // token aware test vectors from: https://github.com/bitjson/cashtokens
-
-//     #[test]
-//     fn mainnet_20byte() {
-//         // 20-byte public key hash on mainnet
-//         verify(
-//             Network::Bitcoin,
-//             &hex::decode("F5BF48B397DAE70BE82B3CCA4793F8EB2B6CDAC9").unwrap(),
-//             "bitcoincash:qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekg2",
-//             false,
-//         );
-
-//         verify(
-//             Network::Bitcoin,
-//             &hex::decode("fc916f213a3d7f1369313d5fa30f6168f9446a2d").unwrap(),
-//             "bitcoincash:zr7fzmep8g7h7ymfxy74lgc0v950j3r295z4y4gq0v",
-//             true,
-//         );
-
-//         verify_p2sh(
-//             Network::Bitcoin,
-//             &hex::decode("1948b5c4eacd0ca8d7f4e7f05c83d0c92425abea").unwrap(),
-//             "bitcoincash:pqv53dwyatxse2xh7nnlqhyr6ryjgfdtagkd4vc388",
-//             false,
-//         );
-
-//         verify_p2sh(
-//             Network::Bitcoin,
-//             &hex::decode("1948b5c4eacd0ca8d7f4e7f05c83d0c92425abea").unwrap(),
-//             "bitcoincash:rqv53dwyatxse2xh7nnlqhyr6ryjgfdtag38xjkhc5",
-//             true,
-//         );
-//     }
-
-//     #[test]
-//     fn mainnet_24byte() {
-//         // 24-byte public key hash on mainnet
-//         verify(
-//             Network::Bitcoin,
-//             &hex::decode("7ADBF6C17084BC86C1706827B41A56F5CA32865925E946EA").unwrap(),
-//             "bitcoincash:q9adhakpwzztepkpwp5z0dq62m6u5v5xtyj7j3h2ws4mr9g0",
-//             false,
-//         );
-//     }
-
-//     #[test]
-//     fn mainnet_28byte() {
-//         // 28-byte public key hash on mainnet
-//         verify(
-//             Network::Bitcoin,
-//             &hex::decode("3A84F9CF51AAE98A3BB3A78BF16A6183790B18719126325BFC0C075B").unwrap(),
-//             "bitcoincash:qgagf7w02x4wnz3mkwnchut2vxphjzccwxgjvvjmlsxqwkcw59jxxuz",
-//             false,
-//         );
-//     }
-
-//     #[test]
-//     fn mainnet_32byte() {
-//         // 32-byte public key hash on mainnet
-//         verify(
-//             Network::Bitcoin,
-//             &hex::decode("3173EF6623C6B48FFD1A3DCC0CC6489B0A07BB47A37F47CFEF4FE69DE825C060")
-//                 .unwrap(),
-//             "bitcoincash:qvch8mmxy0rtfrlarg7ucrxxfzds5pamg73h7370aa87d80gyhqxq5nlegake",
-//             false,
-//         );
-
-//         verify_p2sh(
-//             Network::Bitcoin,
-//             &hex::decode("ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff")
-//                 .unwrap(),
-//             "bitcoincash:p0llllllllllllllllllllllllllllllllllllllllllllllllll7x3vthu35",
-//             false,
-//         );
-
-//         verify_p2sh(
-//             Network::Bitcoin,
-//             &hex::decode("ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff")
-//                 .unwrap(),
-//             "bitcoincash:r0llllllllllllllllllllllllllllllllllllllllllllllllll75zs2wagl",
-//             true,
-//         );
-//     }
-
-//     #[test]
-//     fn mainnet_40byte() {
-//         // 40-byte public key hash on mainnet
-//         verify(
-//             Network::Bitcoin,
-//             &hex::decode("C07138323E00FA4FC122D3B85B9628EA810B3F381706385E289B0B25631197D194B5C238BEB136FB").unwrap(),
-//             "bitcoincash:qnq8zwpj8cq05n7pytfmskuk9r4gzzel8qtsvwz79zdskftrzxtar994cgutavfklv39gr3uvz",
-//             false,
-//         );
-//     }
-
-//     #[test]
-//     fn mainnet_48byte() {
-//         // 48-byte public key hash on mainnet
-//         verify(
-//             Network::Bitcoin,
-//             &hex::decode("E361CA9A7F99107C17A622E047E3745D3E19CF804ED63C5C40C6BA763696B98241223D8CE62AD48D863F4CB18C930E4C").unwrap(),
-//             "bitcoincash:qh3krj5607v3qlqh5c3wq3lrw3wnuxw0sp8dv0zugrrt5a3kj6ucysfz8kxwv2k53krr7n933jfsunqex2w82sl",
-//             false,
-//         );
-//     }
-
-//     #[test]
-//     fn mainnet_56byte() {
-//         // 56-byte public key hash on mainnet
-//         verify(
-//             Network::Bitcoin,
-//             &hex::decode("D9FA7C4C6EF56DC4FF423BAAE6D495DBFF663D034A72D1DC7D52CBFE7D1E6858F9D523AC0A7A5C34077638E4DD1A701BD017842789982041").unwrap(),
-//             "bitcoincash:qmvl5lzvdm6km38lgga64ek5jhdl7e3aqd9895wu04fvhlnare5937w4ywkq57juxsrhvw8ym5d8qx7sz7zz0zvcypqscw8jd03f",
-//             false
-//         );
-//     }
-//     #[test]
-//     fn mainnet_64byte() {
-//         // 64-byte public key hash on mainnet
-//         verify(
-//             Network::Bitcoin,
-//             &hex::decode("D0F346310D5513D9E01E299978624BA883E6BDA8F4C60883C10F28C2967E67EC77ECC7EEEAEAFC6DA89FAD72D11AC961E164678B868AEEEC5F2C1DA08884175B").unwrap(),
-//             "bitcoincash:qlg0x333p4238k0qrc5ej7rzfw5g8e4a4r6vvzyrcy8j3s5k0en7calvclhw46hudk5flttj6ydvjc0pv3nchp52amk97tqa5zygg96mtky5sv5w",
-//             false
-//         );
-//     }
-
-//     fn verify(network: Network, data: &Vec<u8>, cashaddr: &str, token_aware: bool) {
-//         let hash_type = if token_aware {
-//             version_byte_flags::TYPE_P2PKH_TOKEN
-//         } else {
-//             version_byte_flags::TYPE_P2PKH
-//         };
-//         let output = encode(data, hash_type, network).unwrap();
-//         assert_eq!(output, cashaddr.to_ascii_lowercase());
-//         let decoded = decode(cashaddr).unwrap();
-//         assert_eq!(decoded.0, *data);
-//     }
-
-//     fn verify_p2sh(network: Network, data: &Vec<u8>, cashaddr: &str, token_aware: bool) {
-//         let hash_type = if token_aware {
-//             version_byte_flags::TYPE_P2SH_TOKEN
-//         } else {
-//             version_byte_flags::TYPE_P2SH
-//         };
-//         let output = encode(data, hash_type, network).unwrap();
-//         assert_eq!(output, cashaddr.to_ascii_lowercase());
-//         let decoded = decode(cashaddr).unwrap();
-//         assert_eq!(decoded.0, *data);
-//     }
-// }
+#[cfg(test)]
+mod tests {
+    use super::version_byte_flags::*;
+    use super::*;
+    use crate::hashes::hex::FromHex;
+
+    // Token-aware test vectors from: https://github.com/bitjson/cashtokens
+
+    fn roundtrip(hex: &str, hash_flag: u8, cashaddr: &str) {
+        let data = Vec::<u8>::from_hex(hex).unwrap();
+
+        assert_eq!(encode(&data, hash_flag, Network::Bitcoin).unwrap(), cashaddr);
+        assert_eq!(decode(cashaddr).unwrap(), (data, hash_flag, Network::Bitcoin));
+    }
+
+    #[test]
+    fn test_p2pkh() {
+        roundtrip(
+            "F5BF48B397DAE70BE82B3CCA4793F8EB2B6CDAC9",
+            TYPE_P2PKH,
+            "bitcoincash:qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekg2",
+        );
+        roundtrip(
+            "fc916f213a3d7f1369313d5fa30f6168f9446a2d",
+            TYPE_P2PKH_TOKEN,
+            "bitcoincash:zr7fzmep8g7h7ymfxy74lgc0v950j3r295z4y4gq0v",
+        );
+        roundtrip(
+            "7ADBF6C17084BC86C1706827B41A56F5CA32865925E946EA",
+            TYPE_P2PKH,
+            "bitcoincash:q9adhakpwzztepkpwp5z0dq62m6u5v5xtyj7j3h2ws4mr9g0",
+        );
+        roundtrip(
+            "3A84F9CF51AAE98A3BB3A78BF16A6183790B18719126325BFC0C075B",
+            TYPE_P2PKH,
+            "bitcoincash:qgagf7w02x4wnz3mkwnchut2vxphjzccwxgjvvjmlsxqwkcw59jxxuz",
+        );
+        roundtrip(
+            "3173EF6623C6B48FFD1A3DCC0CC6489B0A07BB47A37F47CFEF4FE69DE825C060",
+            TYPE_P2PKH,
+            "bitcoincash:qvch8mmxy0rtfrlarg7ucrxxfzds5pamg73h7370aa87d80gyhqxq5nlegake",
+        );
+    }
+
+    #[test]
+    fn test_p2sh() {
+        roundtrip(
+            "1948b5c4eacd0ca8d7f4e7f05c83d0c92425abea",
+            TYPE_P2SH,
+            "bitcoincash:pqv53dwyatxse2xh7nnlqhyr6ryjgfdtagkd4vc388",
+        );
+        roundtrip(
+            "1948b5c4eacd0ca8d7f4e7f05c83d0c92425abea",
+            TYPE_P2SH_TOKEN,
+            "bitcoincash:rqv53dwyatxse2xh7nnlqhyr6ryjgfdtag38xjkhc5",
+        );
+    }
+
+    #[test]
+    fn test_p2sh32() {
+        let hash = "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff";
+
+        roundtrip(
+            hash,
+            TYPE_P2SH,
+            "bitcoincash:p0llllllllllllllllllllllllllllllllllllllllllllllllll7x3vthu35",
+        );
+        roundtrip(
+            hash,
+            TYPE_P2SH_TOKEN,
+            "bitcoincash:r0llllllllllllllllllllllllllllllllllllllllllllllllll75zs2wagl",
+        );
+
+        // The size bits are masked out of the decoded type.
+        let data = [0x42; 32];
+        let addr = encode(&data, TYPE_P2SH, Network::Testnet).unwrap();
+
+        assert!(addr.starts_with("bchtest:p"));
+        assert_eq!(decode(&addr).unwrap(), (data.to_vec(), TYPE_P2SH, Network::Testnet));
+        assert_eq!(encode(&data[..31], TYPE_P2SH, Network::Bitcoin), Err(EncodingError(31)));
+    }
+
+    #[test]
+    fn test_decode_prefix() {
+        let data = Vec::<u8>::from_hex("f5bf48b397dae70be82b3cca4793f8eb2b6cdac9").unwrap();
+
+        for network in [Network::Bitcoin, Network::Testnet, Network::Regtest].iter() {
+            let addr = encode(&data, TYPE_P2PKH, *network).unwrap();
+            let payload = &addr[addr.find(':').unwrap() + 1..];
+            let expected = Ok((data.clone(), TYPE_P2PKH, *network));
+
+            assert_eq!(decode(payload), expected);
+            assert_eq!(decode(&addr.to_ascii_uppercase()), expected);
+            assert_eq!(decode(&payload.to_ascii_uppercase()), expected);
+        }
+        assert_eq!(
+            decode(&encode(&data, TYPE_P2PKH, Network::Chipnet).unwrap()).unwrap().2,
+            Network::Testnet
+        );
+        // A mainnet address with a testnet prefix.
+        assert!(matches!(
+            decode("bchtest:qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekg2"),
+            Err(DecodingError::ChecksumFailed(_))
+        ));
+        assert_eq!(decode("bchreg:qr6m"), Err(DecodingError::InvalidLength(0)));
+        assert_eq!(
+            decode("bitcoin:qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekg2"),
+            Err(DecodingError::InvalidPrefix(String::from("bitcoin")))
+        );
+    }
+
+    #[test]
+    fn test_decode_misspelled() {
+        let addr = "bitcoincash:qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekg2";
+        let misspelled = Err(DecodingError::Misspelled(addr.to_owned()));
+
+        // In the hash, in the checksum, and without a prefix.
+        assert_eq!(decode("bitcoincash:qr6m7j9njldwwzlg9v7v53unlr4jkmx7eylep8ekg2"), misspelled);
+        assert_eq!(decode("bitcoincash:qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekgq"), misspelled);
+        assert_eq!(decode("QR6M7J9NJLDWWZLG9V7V53UNLR4JKMX6EYLEP8EKG3"), misspelled);
+        // Two mistyped characters can't be corrected.
+        assert!(matches!(
+            decode("bitcoincash:qr6m7j9njldwwzlg9v7v53unlr4jkmx7eylep8ekg3"),
+            Err(DecodingError::ChecksumFailed(_))
+        ));
+    }
+}
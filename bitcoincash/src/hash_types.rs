@@ -49,6 +49,7 @@ See [`hashes::Hash::DISPLAY_BACKWARD`] for more details.
 
     hash_newtype!(PubkeyHash, hash160::Hash, 20, doc="A hash of a public key.");
     hash_newtype!(ScriptHash, hash160::Hash, 20, doc="A hash of Bitcoin Script bytecode.");
+    hash_newtype!(ScriptHash32, sha256d::Hash, 32, doc="A 32-byte hash of Bitcoin Script bytecode, as used by P2SH32 outputs.");
     hash_newtype!(WPubkeyHash, hash160::Hash, 20, doc="SegWit version of a public key hash.");
     hash_newtype!(WScriptHash, sha256::Hash, 32, doc="SegWit version of a Bitcoin Script bytecode hash.");
 
@@ -61,6 +62,7 @@ See [`hashes::Hash::DISPLAY_BACKWARD`] for more details.
     hash_newtype!(FilterHeader, sha256d::Hash, 32, doc="Filter header, as defined in BIP-157");
 
     hash_newtype!(TokenID, sha256d::Hash, 32, doc="Cashtoken ID");
+    hash_newtype!(DSProofId, sha256d::Hash, 32, doc="Double-spend proof hash");
 
     impl_hashencode!(Txid);
     impl_hashencode!(Wtxid);
@@ -74,4 +76,5 @@ See [`hashes::Hash::DISPLAY_BACKWARD`] for more details.
     impl_hashencode!(FilterHeader);
 
     impl_hashencode!(TokenID);
+    impl_hashencode!(DSProofId);
 }
@@ -21,6 +21,7 @@ use crate::network::{message_network, message_bloom};
 use crate::network::message_blockdata;
 use crate::network::message_filter;
 use crate::network::message_compact_blocks;
+use crate::network::message_dsproof;
 use crate::consensus::encode::{CheckedData, Decodable, Encodable, VarInt};
 use crate::consensus::{encode, serialize};
 use crate::util::merkleblock::MerkleBlock;
@@ -34,6 +35,9 @@ pub const MAX_INV_SIZE: usize = 50_000;
 /// This by neccessity should be larger tham `MAX_VEC_SIZE`
 pub const MAX_MSG_SIZE: usize = 5_000_000;
 
+/// Size, in bytes, of a message header: the magic, command, payload length and checksum.
+const HEADER_SIZE: usize = 24;
+
 /// Serializer for command string
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub struct CommandString(Cow<'static, str>);
@@ -250,6 +254,8 @@ pub enum NetworkMessage {
     AddrV2(Vec<AddrV2Message>),
     /// `sendaddrv2`
     SendAddrV2,
+    /// `dsproof-beta`
+    DSProof(message_dsproof::DSProof),
 
     /// Any other message.
     Unknown {
@@ -303,6 +309,7 @@ impl NetworkMessage {
             NetworkMessage::FeeFilter(_) => "feefilter",
             NetworkMessage::AddrV2(_) => "addrv2",
             NetworkMessage::SendAddrV2 => "sendaddrv2",
+            NetworkMessage::DSProof(_) => "dsproof-beta",
             NetworkMessage::Unknown { .. } => "unknown",
         }
     }
@@ -382,6 +389,7 @@ impl Encodable for RawNetworkMessage {
             NetworkMessage::Reject(ref dat) => serialize(dat),
             NetworkMessage::FeeFilter(ref data) => serialize(data),
             NetworkMessage::AddrV2(ref dat) => serialize(dat),
+            NetworkMessage::DSProof(ref dat) => serialize(dat),
             NetworkMessage::Verack
             | NetworkMessage::SendHeaders
             | NetworkMessage::MemPool
@@ -418,52 +426,110 @@ impl Decodable for HeaderDeserializationWrapper {
     }
 }
 
-impl Decodable for RawNetworkMessage {
-    fn consensus_decode_from_finite_reader<R: io::Read + ?Sized>(r: &mut R) -> Result<Self, encode::Error> {
-        let magic = Decodable::consensus_decode_from_finite_reader(r)?;
-        let cmd = CommandString::consensus_decode_from_finite_reader(r)?;
-        let raw_payload = CheckedData::consensus_decode_from_finite_reader(r)?.0;
-
-        let mut mem_d = io::Cursor::new(raw_payload);
-        let payload = match &cmd.0[..] {
-            "version" => NetworkMessage::Version(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
+impl RawNetworkMessage {
+    /// Decodes the payload of a message with the given command. Returns `None` if the command
+    /// is unknown.
+    fn decode_payload<R: io::Read + ?Sized>(cmd: &str, r: &mut R) -> Result<Option<NetworkMessage>, encode::Error> {
+        let payload = match cmd {
+            "version" => NetworkMessage::Version(Decodable::consensus_decode_from_finite_reader(r)?),
             "verack"  => NetworkMessage::Verack,
-            "addr"    => NetworkMessage::Addr(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
-            "inv"     => NetworkMessage::Inv(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
-            "getdata" => NetworkMessage::GetData(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
-            "notfound" => NetworkMessage::NotFound(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
-            "getblocks" => NetworkMessage::GetBlocks(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
-            "getheaders" => NetworkMessage::GetHeaders(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
+            "addr"    => NetworkMessage::Addr(Decodable::consensus_decode_from_finite_reader(r)?),
+            "inv"     => NetworkMessage::Inv(Decodable::consensus_decode_from_finite_reader(r)?),
+            "getdata" => NetworkMessage::GetData(Decodable::consensus_decode_from_finite_reader(r)?),
+            "notfound" => NetworkMessage::NotFound(Decodable::consensus_decode_from_finite_reader(r)?),
+            "getblocks" => NetworkMessage::GetBlocks(Decodable::consensus_decode_from_finite_reader(r)?),
+            "getheaders" => NetworkMessage::GetHeaders(Decodable::consensus_decode_from_finite_reader(r)?),
             "mempool" => NetworkMessage::MemPool,
-            "block"   => NetworkMessage::Block(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
+            "block"   => NetworkMessage::Block(Decodable::consensus_decode_from_finite_reader(r)?),
             "headers" => NetworkMessage::Headers(
-                HeaderDeserializationWrapper::consensus_decode_from_finite_reader(&mut mem_d)?.0
+                HeaderDeserializationWrapper::consensus_decode_from_finite_reader(r)?.0
             ),
             "sendheaders" => NetworkMessage::SendHeaders,
             "getaddr" => NetworkMessage::GetAddr,
-            "ping"    => NetworkMessage::Ping(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
-            "pong"    => NetworkMessage::Pong(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
-            "merkleblock" => NetworkMessage::MerkleBlock(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
-            "filterload" => NetworkMessage::FilterLoad(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
-            "filteradd" => NetworkMessage::FilterAdd(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
+            "ping"    => NetworkMessage::Ping(Decodable::consensus_decode_from_finite_reader(r)?),
+            "pong"    => NetworkMessage::Pong(Decodable::consensus_decode_from_finite_reader(r)?),
+            "merkleblock" => NetworkMessage::MerkleBlock(Decodable::consensus_decode_from_finite_reader(r)?),
+            "filterload" => NetworkMessage::FilterLoad(Decodable::consensus_decode_from_finite_reader(r)?),
+            "filteradd" => NetworkMessage::FilterAdd(Decodable::consensus_decode_from_finite_reader(r)?),
             "filterclear" => NetworkMessage::FilterClear,
-            "tx"      => NetworkMessage::Tx(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
-            "getcfilters" => NetworkMessage::GetCFilters(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
-            "cfilter" => NetworkMessage::CFilter(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
-            "getcfheaders" => NetworkMessage::GetCFHeaders(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
-            "cfheaders" => NetworkMessage::CFHeaders(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
-            "getcfcheckpt" => NetworkMessage::GetCFCheckpt(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
-            "cfcheckpt" => NetworkMessage::CFCheckpt(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
-            "reject" => NetworkMessage::Reject(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
-            "alert"   => NetworkMessage::Alert(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
-            "feefilter" => NetworkMessage::FeeFilter(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
-            "sendcmpct" => NetworkMessage::SendCmpct(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
-            "cmpctblock" => NetworkMessage::CmpctBlock(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
-            "getblocktxn" => NetworkMessage::GetBlockTxn(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
-            "blocktxn" => NetworkMessage::BlockTxn(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
-            "addrv2" => NetworkMessage::AddrV2(Decodable::consensus_decode_from_finite_reader(&mut mem_d)?),
+            "tx"      => NetworkMessage::Tx(Decodable::consensus_decode_from_finite_reader(r)?),
+            "getcfilters" => NetworkMessage::GetCFilters(Decodable::consensus_decode_from_finite_reader(r)?),
+            "cfilter" => NetworkMessage::CFilter(Decodable::consensus_decode_from_finite_reader(r)?),
+            "getcfheaders" => NetworkMessage::GetCFHeaders(Decodable::consensus_decode_from_finite_reader(r)?),
+            "cfheaders" => NetworkMessage::CFHeaders(Decodable::consensus_decode_from_finite_reader(r)?),
+            "getcfcheckpt" => NetworkMessage::GetCFCheckpt(Decodable::consensus_decode_from_finite_reader(r)?),
+            "cfcheckpt" => NetworkMessage::CFCheckpt(Decodable::consensus_decode_from_finite_reader(r)?),
+            "reject" => NetworkMessage::Reject(Decodable::consensus_decode_from_finite_reader(r)?),
+            "alert"   => NetworkMessage::Alert(Decodable::consensus_decode_from_finite_reader(r)?),
+            "feefilter" => NetworkMessage::FeeFilter(Decodable::consensus_decode_from_finite_reader(r)?),
+            "sendcmpct" => NetworkMessage::SendCmpct(Decodable::consensus_decode_from_finite_reader(r)?),
+            "cmpctblock" => NetworkMessage::CmpctBlock(Decodable::consensus_decode_from_finite_reader(r)?),
+            "getblocktxn" => NetworkMessage::GetBlockTxn(Decodable::consensus_decode_from_finite_reader(r)?),
+            "blocktxn" => NetworkMessage::BlockTxn(Decodable::consensus_decode_from_finite_reader(r)?),
+            "addrv2" => NetworkMessage::AddrV2(Decodable::consensus_decode_from_finite_reader(r)?),
             "sendaddrv2" => NetworkMessage::SendAddrV2,
-            _ => NetworkMessage::Unknown {
+            "dsproof-beta" => NetworkMessage::DSProof(Decodable::consensus_decode_from_finite_reader(r)?),
+            _ => return Ok(None),
+        };
+        Ok(Some(payload))
+    }
+
+    /// Decodes a message from the start of a buffer, and returns it along with the number of
+    /// bytes it spans.
+    ///
+    /// Unlike [`Decodable::consensus_decode`], the payload is decoded in place, rather than
+    /// copied out of the buffer first, and the command of known messages isn't allocated. If
+    /// the buffer only holds part of the message, this fails with an unexpected end of file
+    /// error as soon as the header is read.
+    pub fn decode_from_slice(data: &[u8]) -> Result<(RawNetworkMessage, usize), encode::Error> {
+        let eof = || encode::Error::Io(io::Error::from(io::ErrorKind::UnexpectedEof));
+        let header = data.get(..HEADER_SIZE).ok_or_else(eof)?;
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let command = &header[4..16];
+        let len = u32::from_le_bytes([header[16], header[17], header[18], header[19]]) as usize;
+        let checksum = [header[20], header[21], header[22], header[23]];
+
+        if len > MAX_MSG_SIZE - HEADER_SIZE {
+            return Err(encode::Error::OversizedVectorAllocation {
+                requested: len,
+                max: MAX_MSG_SIZE - HEADER_SIZE,
+            });
+        }
+        let raw_payload = data.get(HEADER_SIZE..HEADER_SIZE + len).ok_or_else(eof)?;
+        let expected = encode::sha2_checksum(raw_payload);
+        if expected != checksum {
+            return Err(encode::Error::InvalidChecksum { expected, actual: checksum });
+        }
+
+        // Known commands are padded with zeroes.
+        let end = command.iter().position(|b| *b == 0).unwrap_or(command.len());
+        let cmd = if command[end..].iter().all(|b| *b == 0) {
+            core::str::from_utf8(&command[..end]).unwrap_or_default()
+        } else {
+            ""
+        };
+        let mut mem_d = io::Cursor::new(raw_payload);
+        let payload = match Self::decode_payload(cmd, &mut mem_d)? {
+            Some(payload) => payload,
+            None => NetworkMessage::Unknown {
+                command: CommandString::consensus_decode(&mut io::Cursor::new(command))?,
+                payload: raw_payload.to_vec(),
+            }
+        };
+        Ok((RawNetworkMessage { magic, payload }, HEADER_SIZE + len))
+    }
+}
+
+impl Decodable for RawNetworkMessage {
+    fn consensus_decode_from_finite_reader<R: io::Read + ?Sized>(r: &mut R) -> Result<Self, encode::Error> {
+        let magic = Decodable::consensus_decode_from_finite_reader(r)?;
+        let cmd = CommandString::consensus_decode_from_finite_reader(r)?;
+        let raw_payload = CheckedData::consensus_decode_from_finite_reader(r)?.0;
+
+        let mut mem_d = io::Cursor::new(raw_payload);
+        let payload = match Self::decode_payload(&cmd.0, &mut mem_d)? {
+            Some(payload) => payload,
+            None => NetworkMessage::Unknown {
                 command: cmd,
                 payload: mem_d.into_inner(),
             }
@@ -483,9 +549,10 @@ impl Decodable for RawNetworkMessage {
 #[cfg(test)]
 mod test {
     use std::net::Ipv4Addr;
+    use crate::io;
     use super::{RawNetworkMessage, NetworkMessage, CommandString};
     use crate::network::constants::ServiceFlags;
-    use crate::consensus::encode::{deserialize, deserialize_partial, serialize};
+    use crate::consensus::encode::{deserialize, deserialize_partial, serialize, Error};
     use crate::hashes::hex::FromHex;
     use crate::hashes::sha256d::Hash;
     use crate::hashes::Hash as HashTrait;
@@ -499,7 +566,9 @@ mod test {
     use crate::network::message_bloom::{FilterAdd, FilterLoad, BloomFlags};
     use crate::MerkleBlock;
     use crate::network::message_compact_blocks::{GetBlockTxn, SendCmpct};
+    use crate::network::message_dsproof::{DSProof, Spender};
     use crate::util::bip152::BlockTransactionsRequest;
+    use crate::OutPoint;
 
     fn hash(slice: [u8;32]) -> Hash {
         Hash::from_slice(&slice).unwrap()
@@ -515,6 +584,16 @@ mod test {
         let script: Script = deserialize(&Vec::from_hex("1976a91431a420903c05a0a7de2de40c9f02ebedbacdc17288ac").unwrap()).unwrap();
         let merkle_block: MerkleBlock = deserialize(&Vec::from_hex("0100000079cda856b143d9db2c1caff01d1aecc8630d30625d10e8b4b8b0000000000000b50cc069d6a3e33e3ff84a5c41d9d3febe7c770fdcc96b2c3ff60abe184f196367291b4d4c86041b8fa45d630100000001b50cc069d6a3e33e3ff84a5c41d9d3febe7c770fdcc96b2c3ff60abe184f19630101").unwrap()).unwrap();
         let cmptblock = deserialize(&Vec::from_hex("00000030d923ad36ff2d955abab07f8a0a6e813bc6e066b973e780c5e36674cad5d1cd1f6e265f2a17a0d35cbe701fe9d06e2c6324cfe135f6233e8b767bfa3fb4479b71115dc562ffff7f2006000000000000000000000000010002000000010000000000000000000000000000000000000000000000000000000000000000ffffffff0302ee00ffffffff0100f9029500000000015100000000").unwrap()).unwrap();
+        let spender = |n: u8| Spender {
+            version: 2, sequence: 0xffffffff, lock_time: 0,
+            hash_prevouts: hash([n; 32]), hash_sequence: hash([n + 1; 32]), hash_outputs: hash([n + 2; 32]),
+            push_data: vec![vec![n; 65]],
+        };
+        let dsproof = DSProof {
+            outpoint: OutPoint::new(hash([61u8; 32]).into(), 1),
+            first_spender: spender(1),
+            double_spender: spender(2),
+        };
         let blocktxn = deserialize(&Vec::from_hex("2e93c0cff39ff605020072d96bc3a8d20b8447e294d08092351c8583e08d9b5a01020000000001010000000000000000000000000000000000000000000000000000000000000000ffffffff0402dc0000ffffffff0200f90295000000001976a9142b4569203694fc997e13f2c0a1383b9e16c77a0d88ac0000000000000000266a24aa21a9ede2f61c3f71d1defd3fa999dfa36953755c690689799962b48bebd836974e8cf90120000000000000000000000000000000000000000000000000000000000000000000000000").unwrap()).unwrap();
 
 
@@ -555,11 +634,15 @@ mod test {
             NetworkMessage::GetBlockTxn(GetBlockTxn { txs_request: BlockTransactionsRequest { block_hash: hash([11u8; 32]).into(), indexes: vec![0, 1, 2, 3, 10, 3002] } }),
             NetworkMessage::BlockTxn(blocktxn),
             NetworkMessage::SendCmpct(SendCmpct{send_compact: true, version: 8333}),
+            NetworkMessage::Inv(vec![Inventory::DoubleSpendProof(dsproof.id())]),
+            NetworkMessage::DSProof(dsproof),
         ];
 
         for msg in msgs {
             let raw_msg = RawNetworkMessage {magic: 57, payload: msg};
-            assert_eq!(deserialize::<RawNetworkMessage>(&serialize(&raw_msg)).unwrap(), raw_msg);
+            let data = serialize(&raw_msg);
+            assert_eq!(deserialize::<RawNetworkMessage>(&data).unwrap(), raw_msg);
+            assert_eq!(RawNetworkMessage::decode_from_slice(&data).unwrap(), (raw_msg, data.len()));
         }
 
     }
@@ -703,4 +786,38 @@ mod test {
             panic!("Wrong message type");
         }
     }
+
+    #[test]
+    fn decode_from_slice_test() {
+        let ping = serialize(&RawNetworkMessage { magic: 0xd9b4bef9, payload: NetworkMessage::Ping(100) });
+        let unknown = RawNetworkMessage {
+            magic: 0xd9b4bef9,
+            payload: NetworkMessage::Unknown { command: CommandString::try_from_static("dunno").unwrap(), payload: vec![1, 2, 3] },
+        };
+        let mut data = ping.clone();
+        data.extend(serialize(&unknown));
+
+        let (msg, consumed) = RawNetworkMessage::decode_from_slice(&data).unwrap();
+        assert_eq!(msg.payload, NetworkMessage::Ping(100));
+        assert_eq!(consumed, ping.len());
+        // Unknown messages decode as they do from a reader.
+        let (msg, _) = RawNetworkMessage::decode_from_slice(&data[consumed..]).unwrap();
+        assert_eq!(msg, deserialize::<RawNetworkMessage>(&data[consumed..]).unwrap());
+        assert_eq!(msg.command(), unknown.command());
+
+        // Partial messages.
+        for len in [0, 12, 24, ping.len() - 1].iter() {
+            match RawNetworkMessage::decode_from_slice(&ping[..*len]) {
+                Err(Error::Io(ref err)) if err.kind() == io::ErrorKind::UnexpectedEof => {}
+                other => panic!("Unexpected result {:?}", other),
+            }
+        }
+        // Corrupted payload.
+        let mut corrupted = ping.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            RawNetworkMessage::decode_from_slice(&corrupted),
+            Err(Error::InvalidChecksum { .. })
+        ));
+    }
 }
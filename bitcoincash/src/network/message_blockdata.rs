@@ -14,11 +14,14 @@ use crate::io;
 use crate::hashes::{sha256d, Hash as _};
 
 use crate::consensus::encode::{self, Decodable, Encodable};
-use crate::hash_types::{BlockHash, Txid};
+use crate::hash_types::{BlockHash, DSProofId, Txid};
 use crate::internal_macros::impl_consensus_encoding;
 use crate::network::constants;
 // use crate::MerkleBlock;
 
+/// Inventory type of double-spend proofs.
+pub const MSG_DOUBLESPENDPROOF: u32 = 0x94a0;
+
 /// An inventory item.
 #[derive(PartialEq, Eq, Clone, Debug, Hash, PartialOrd, Ord)]
 pub enum Inventory {
@@ -32,6 +35,8 @@ pub enum Inventory {
     FilteredBlock(BlockHash),
     /// Compact Block
     CompactBlock(BlockHash),
+    /// Double-spend proof
+    DoubleSpendProof(DSProofId),
     /// Unknown inventory type
     Unknown {
         /// The inventory item type.
@@ -55,6 +60,7 @@ impl Encodable for Inventory {
             Inventory::Block(ref b) => encode_inv!(2, b),
             Inventory::FilteredBlock(ref b) => encode_inv!(3, b),
             Inventory::CompactBlock(ref b) => encode_inv!(4, b),
+            Inventory::DoubleSpendProof(ref p) => encode_inv!(MSG_DOUBLESPENDPROOF, p),
             Inventory::Unknown { inv_type: t, hash: ref d } => encode_inv!(t, d),
         })
     }
@@ -70,6 +76,7 @@ impl Decodable for Inventory {
             2 => Inventory::Block(Decodable::consensus_decode(r)?),
             3 => Inventory::FilteredBlock(Decodable::consensus_decode(r)?),
             4 => Inventory::CompactBlock(Decodable::consensus_decode(r)?),
+            MSG_DOUBLESPENDPROOF => Inventory::DoubleSpendProof(Decodable::consensus_decode(r)?),
             tp => Inventory::Unknown { inv_type: tp, hash: Decodable::consensus_decode(r)? },
         })
     }
@@ -0,0 +1,248 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! Bitcoin Cash double-spend proof network messages.
+//!
+//! This module describes the `dsproof-beta` message, relayed by nodes when they see two
+//! transactions spending the same output. A proof holds enough of each transaction's signed
+//! data to show that both spends were signed by the owner of the output, without including
+//! the transactions themselves.
+//!
+
+use core::fmt;
+
+use crate::prelude::*;
+
+use crate::blockdata::transaction::{OutPoint, Transaction};
+use crate::consensus::encode::{self, Encodable};
+use crate::hash_types::DSProofId;
+use crate::hashes::{sha256d, Hash};
+use crate::internal_macros::impl_consensus_encoding;
+
+/// The signed data of one of the two transactions spending an output.
+///
+/// The hashes are the ones committed to by a `SIGHASH_ALL|SIGHASH_FORKID` signature.
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+pub struct Spender {
+    /// Version of the spending transaction.
+    pub version: u32,
+    /// Sequence number of the spending input.
+    pub sequence: u32,
+    /// Lock time of the spending transaction.
+    pub lock_time: u32,
+    /// Hash of all the outpoints spent by the transaction.
+    pub hash_prevouts: sha256d::Hash,
+    /// Hash of all the input sequence numbers of the transaction.
+    pub hash_sequence: sha256d::Hash,
+    /// Hash of all the outputs of the transaction.
+    pub hash_outputs: sha256d::Hash,
+    /// Data pushed by the spending input, ie. the signature.
+    pub push_data: Vec<Vec<u8>>,
+}
+impl_consensus_encoding!(
+    Spender, version, sequence, lock_time, hash_prevouts, hash_sequence, hash_outputs, push_data
+);
+
+impl Spender {
+    /// Builds the spender of a transaction input, with the data it pushes.
+    ///
+    /// # Panics
+    ///
+    /// If the input index is out of bounds.
+    pub fn from_tx(tx: &Transaction, input_index: usize, push_data: Vec<Vec<u8>>) -> Self {
+        let mut prevouts = sha256d::Hash::engine();
+        let mut sequences = sha256d::Hash::engine();
+        let mut outputs = sha256d::Hash::engine();
+
+        for input in &tx.input {
+            input.previous_output.consensus_encode(&mut prevouts).expect("engines don't error");
+            input.sequence.consensus_encode(&mut sequences).expect("engines don't error");
+        }
+        for output in &tx.output {
+            output.consensus_encode(&mut outputs).expect("engines don't error");
+        }
+        Spender {
+            version: tx.version as u32,
+            sequence: tx.input[input_index].sequence.0,
+            lock_time: tx.lock_time.to_u32(),
+            hash_prevouts: sha256d::Hash::from_engine(prevouts),
+            hash_sequence: sha256d::Hash::from_engine(sequences),
+            hash_outputs: sha256d::Hash::from_engine(outputs),
+            push_data,
+        }
+    }
+
+    /// Whether both spenders commit to the same transaction data, regardless of what they push.
+    fn commits_to(&self, other: &Spender) -> bool {
+        self.version == other.version
+            && self.sequence == other.sequence
+            && self.lock_time == other.lock_time
+            && self.hash_prevouts == other.hash_prevouts
+            && self.hash_sequence == other.hash_sequence
+            && self.hash_outputs == other.hash_outputs
+    }
+}
+
+/// `dsproof-beta` message, proving that an output was spent twice.
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+pub struct DSProof {
+    /// The output spent twice.
+    pub outpoint: OutPoint,
+    /// The first spender. Spenders are ordered by their outputs hash, then by their
+    /// prevouts hash.
+    pub first_spender: Spender,
+    /// The second spender.
+    pub double_spender: Spender,
+}
+impl_consensus_encoding!(DSProof, outpoint, first_spender, double_spender);
+
+/// A double-spend proof validation error.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The transaction doesn't spend the output of the proof.
+    OutPointNotSpent,
+    /// Both spenders are the same.
+    IdenticalSpenders,
+    /// The spenders are not in canonical order.
+    NonCanonicalOrder,
+    /// A spender doesn't push any data.
+    MissingPushData,
+    /// Neither of the spenders is the given transaction.
+    SpenderMismatch,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::OutPointNotSpent => f.write_str("transaction does not spend the proof outpoint"),
+            Error::IdenticalSpenders => f.write_str("proof spenders are identical"),
+            Error::NonCanonicalOrder => f.write_str("proof spenders are not in canonical order"),
+            Error::MissingPushData => f.write_str("proof spender is missing push data"),
+            Error::SpenderMismatch => f.write_str("transaction is not one of the proof spenders"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+impl std::error::Error for Error {}
+
+impl DSProof {
+    /// Returns the proof identifier, used in inventory messages.
+    pub fn id(&self) -> DSProofId {
+        DSProofId::from_hash(sha256d::Hash::hash(&encode::serialize(self)))
+    }
+
+    /// Checks the proof against a known transaction spending the proof's output, ie. that the
+    /// transaction is one of the two spenders, and that the proof is well-formed.
+    ///
+    /// Nb. Signatures are not verified, since this requires the output being spent.
+    pub fn validate(&self, tx: &Transaction) -> Result<(), Error> {
+        let (first, double) = (&self.first_spender, &self.double_spender);
+
+        if first == double {
+            return Err(Error::IdenticalSpenders);
+        }
+        let order = first.hash_outputs[..]
+            .cmp(&double.hash_outputs[..])
+            .then_with(|| first.hash_prevouts[..].cmp(&double.hash_prevouts[..]));
+        if order == core::cmp::Ordering::Greater {
+            return Err(Error::NonCanonicalOrder);
+        }
+        if first.push_data.is_empty() || double.push_data.is_empty() {
+            return Err(Error::MissingPushData);
+        }
+        let index = tx
+            .input
+            .iter()
+            .position(|i| i.previous_output == self.outpoint)
+            .ok_or(Error::OutPointNotSpent)?;
+        let spender = Spender::from_tx(tx, index, vec![]);
+
+        if spender.commits_to(first) || spender.commits_to(double) {
+            Ok(())
+        } else {
+            Err(Error::SpenderMismatch)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockdata::script::Script;
+    use crate::blockdata::transaction::{Sequence, TxIn, TxOut};
+    use crate::consensus::encode::{deserialize, serialize};
+    use crate::PackedLockTime;
+
+    fn spend(outpoint: OutPoint, value: u64) -> Transaction {
+        Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: outpoint,
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+            }],
+            output: vec![TxOut { value, script_pubkey: Script::new(), token: None }],
+        }
+    }
+
+    fn proof(outpoint: OutPoint, a: &Transaction, b: &Transaction) -> DSProof {
+        let mut spenders = [
+            Spender::from_tx(a, 0, vec![vec![1; 65]]),
+            Spender::from_tx(b, 0, vec![vec![2; 65]]),
+        ];
+        spenders.sort_by(|x, y| {
+            x.hash_outputs[..]
+                .cmp(&y.hash_outputs[..])
+                .then_with(|| x.hash_prevouts[..].cmp(&y.hash_prevouts[..]))
+        });
+        let [first_spender, double_spender] = spenders;
+
+        DSProof { outpoint, first_spender, double_spender }
+    }
+
+    #[test]
+    fn dsproof_serialize() {
+        let outpoint = OutPoint::new(Hash::hash(&[1]), 0);
+        let proof = proof(outpoint, &spend(outpoint, 1000), &spend(outpoint, 900));
+        let bytes = serialize(&proof);
+
+        // Outpoint, then two spenders of 108 bytes plus their single 65-byte push.
+        assert_eq!(bytes.len(), 36 + 2 * (108 + 1 + 1 + 65));
+        assert_eq!(deserialize::<DSProof>(&bytes).unwrap(), proof);
+        assert_eq!(proof.id(), DSProofId::from_hash(sha256d::Hash::hash(&bytes)));
+    }
+
+    #[test]
+    fn dsproof_validate() {
+        let outpoint = OutPoint::new(Hash::hash(&[1]), 0);
+        let original = spend(outpoint, 1000);
+        let double_spend = spend(outpoint, 900);
+        let proof = proof(outpoint, &original, &double_spend);
+
+        assert_eq!(proof.validate(&original), Ok(()));
+        assert_eq!(proof.validate(&double_spend), Ok(()));
+        assert_eq!(
+            proof.validate(&spend(outpoint, 800)),
+            Err(Error::SpenderMismatch)
+        );
+        assert_eq!(
+            proof.validate(&spend(OutPoint::new(Hash::hash(&[2]), 0), 1000)),
+            Err(Error::OutPointNotSpent)
+        );
+
+        let mut reordered = proof.clone();
+        core::mem::swap(&mut reordered.first_spender, &mut reordered.double_spender);
+        assert_eq!(reordered.validate(&original), Err(Error::NonCanonicalOrder));
+
+        let mut identical = proof.clone();
+        identical.double_spender = identical.first_spender.clone();
+        assert_eq!(identical.validate(&original), Err(Error::IdenticalSpenders));
+
+        let mut unsigned = proof;
+        unsigned.first_spender.push_data.clear();
+        assert_eq!(unsigned.validate(&original), Err(Error::MissingPushData));
+    }
+}
@@ -12,10 +12,23 @@
 //!
 //! These values were taken from bitcoind v0.21.1 (194b9b8792d9b0798fdb570b79fa51f1d1f5ebaf).
 //!
+//! # Bitcoin Cash
+//! The relay policy of the BCH network is checked by [`check_transaction`], so that
+//! transactions which would be dropped by nodes are caught before they are broadcast. Its
+//! limits are those of Bitcoin Cash Node.
+//!
 
 use core::cmp;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error;
 
 use super::blockdata::constants::{MAX_BLOCK_SIGOPS_COST, WITNESS_SCALE_FACTOR};
+use crate::blockdata::interpreter::{self, Interpreter};
+use crate::blockdata::opcodes;
+use crate::blockdata::script::{Instruction, Script};
+use crate::blockdata::transaction::{Transaction, TxOut};
+use crate::consensus::encode::serialize;
 
 /// Maximum weight of a transaction for it to be relayed by most nodes on the network
 pub const MAX_STANDARD_TX_WEIGHT: u32 = 400_000;
@@ -50,3 +63,395 @@ pub fn get_virtual_tx_size(weight: i64, n_sigops: i64) -> i64 {
     (cmp::max(weight, n_sigops * DEFAULT_BYTES_PER_SIGOP as i64) + WITNESS_SCALE_FACTOR as i64 - 1)
         / WITNESS_SCALE_FACTOR as i64
 }
+
+/// Maximum size of a transaction for it to be relayed by most nodes on the BCH network.
+pub const MAX_STANDARD_TX_SIZE: usize = 100_000;
+
+/// Minimum size of a transaction, as of the May 2023 upgrade.
+pub const MIN_TX_SIZE: usize = 65;
+
+/// Maximum size of the input script of a standard transaction.
+pub const MAX_STANDARD_SCRIPT_SIG_SIZE: usize = 1_650;
+
+/// Maximum combined size of the `OP_RETURN` output scripts of a standard transaction.
+pub const MAX_OP_RETURN_RELAY: usize = 223;
+
+/// Maximum number of signature checks performed by a standard transaction.
+pub const MAX_STANDARD_TX_SIGCHECKS: usize = 3_000;
+
+/// Maximum number of public keys in a standard bare multisig output.
+pub const MAX_STANDARD_MULTISIG_PUBKEYS: usize = 3;
+
+/// Maximum size of a non-fungible token commitment.
+pub const MAX_TOKEN_COMMITMENT_SIZE: usize = 40;
+
+/// The feerate, in sats per kilobyte, defining dust on the BCH network. An output is dust if
+/// its value is less than three times the fee of creating and spending it at this feerate.
+pub const DUST_RELAY_FEE: u64 = 1_000;
+
+/// Size of the P2PKH input that outputs are assumed to be spent with, when computing dust.
+const DUST_SPEND_SIZE: u64 = 148;
+
+/// A violation of the BCH relay policy.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The transaction version isn't 1 or 2.
+    Version(i32),
+    /// The transaction is larger than [`MAX_STANDARD_TX_SIZE`].
+    Oversize(usize),
+    /// The transaction is smaller than [`MIN_TX_SIZE`].
+    Undersize(usize),
+    /// An input script is larger than [`MAX_STANDARD_SCRIPT_SIG_SIZE`].
+    ScriptSigSize {
+        /// Index of the input.
+        input: usize,
+        /// Size of its script.
+        size: usize,
+    },
+    /// An input script contains operations other than pushes.
+    ScriptSigPushOnly(usize),
+    /// An input doesn't validly spend its output.
+    Script {
+        /// Index of the input.
+        input: usize,
+        /// Why its scripts failed.
+        error: interpreter::Error,
+    },
+    /// An input performs more signature checks than its script size allows.
+    InputSigChecks(usize),
+    /// The transaction performs more than [`MAX_STANDARD_TX_SIGCHECKS`] signature checks.
+    SigChecks(usize),
+    /// An output script isn't one of the standard templates.
+    NonStandardScript(usize),
+    /// An output's value is below its dust threshold.
+    Dust {
+        /// Index of the output.
+        output: usize,
+        /// Its value.
+        value: u64,
+        /// Its dust threshold.
+        threshold: u64,
+    },
+    /// The `OP_RETURN` output scripts are larger than [`MAX_OP_RETURN_RELAY`] combined.
+    DataSize(usize),
+    /// An output's token commitment is larger than [`MAX_TOKEN_COMMITMENT_SIZE`].
+    TokenCommitment {
+        /// Index of the output.
+        output: usize,
+        /// Size of its commitment.
+        size: usize,
+    },
+    /// An output's fungible token amount isn't positive, or is set without the amount flag.
+    TokenAmount {
+        /// Index of the output.
+        output: usize,
+        /// Its amount.
+        amount: i64,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Version(v) => write!(f, "non-standard transaction version {}", v),
+            Error::Oversize(size) => {
+                write!(f, "transaction of {} bytes exceeds {} bytes", size, MAX_STANDARD_TX_SIZE)
+            }
+            Error::Undersize(size) => {
+                write!(f, "transaction of {} bytes is below {} bytes", size, MIN_TX_SIZE)
+            }
+            Error::ScriptSigSize { input, size } => write!(
+                f,
+                "input {} script of {} bytes exceeds {} bytes",
+                input, size, MAX_STANDARD_SCRIPT_SIG_SIZE
+            ),
+            Error::ScriptSigPushOnly(input) => write!(f, "input {} script isn't push-only", input),
+            Error::Script { input, ref error } => {
+                write!(f, "input {} is invalid: {}", input, error)
+            }
+            Error::InputSigChecks(input) => {
+                write!(f, "input {} performs too many signature checks for its size", input)
+            }
+            Error::SigChecks(n) => write!(
+                f,
+                "transaction performs {} signature checks, more than {}",
+                n, MAX_STANDARD_TX_SIGCHECKS
+            ),
+            Error::NonStandardScript(output) => {
+                write!(f, "output {} script is non-standard", output)
+            }
+            Error::Dust { output, value, threshold } => write!(
+                f,
+                "output {} value of {} sats is below the dust threshold of {} sats",
+                output, value, threshold
+            ),
+            Error::DataSize(size) => write!(
+                f,
+                "OP_RETURN outputs of {} bytes exceed {} bytes",
+                size, MAX_OP_RETURN_RELAY
+            ),
+            Error::TokenCommitment { output, size } => write!(
+                f,
+                "output {} token commitment of {} bytes exceeds {} bytes",
+                output, size, MAX_TOKEN_COMMITMENT_SIZE
+            ),
+            Error::TokenAmount { output, amount } => {
+                write!(f, "output {} has invalid token amount {}", output, amount)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Script { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Checks that a transaction would be relayed by most nodes on the BCH network, given the
+/// outputs spent by its inputs, in order. Inputs are evaluated, so the transaction must be
+/// signed. Returns the first violation found.
+pub fn check_transaction(tx: &Transaction, spent: &[TxOut]) -> Result<(), Error> {
+    if tx.version != 1 && tx.version != 2 {
+        return Err(Error::Version(tx.version));
+    }
+    let size = tx.size();
+
+    if size > MAX_STANDARD_TX_SIZE {
+        return Err(Error::Oversize(size));
+    }
+    if size < MIN_TX_SIZE {
+        return Err(Error::Undersize(size));
+    }
+
+    let mut sigchecks = 0;
+
+    for (input, txin) in tx.input.iter().enumerate() {
+        let size = txin.script_sig.len();
+
+        if size > MAX_STANDARD_SCRIPT_SIG_SIZE {
+            return Err(Error::ScriptSigSize { input, size });
+        }
+        if !interpreter::is_push_only(&txin.script_sig).unwrap_or(false) {
+            return Err(Error::ScriptSigPushOnly(input));
+        }
+        let script_error = |error| Error::Script { input, error };
+        let mut interpreter = Interpreter::new(tx, input, spent).map_err(script_error)?;
+        interpreter.verify().map_err(script_error)?;
+
+        // As of the May 2020 upgrade, inputs are allowed one signature check for every 43
+        // bytes of input script, plus some leeway.
+        if interpreter.sigchecks() > (size + 60) / 43 {
+            return Err(Error::InputSigChecks(input));
+        }
+        sigchecks += interpreter.sigchecks();
+    }
+    if sigchecks > MAX_STANDARD_TX_SIGCHECKS {
+        return Err(Error::SigChecks(sigchecks));
+    }
+
+    let mut data_size = 0;
+
+    for (output, txout) in tx.output.iter().enumerate() {
+        if !is_standard_script(&txout.script_pubkey) {
+            return Err(Error::NonStandardScript(output));
+        }
+        if txout.script_pubkey.is_op_return() {
+            data_size += txout.script_pubkey.len();
+        } else {
+            let threshold = dust_threshold(txout);
+
+            if txout.value < threshold {
+                return Err(Error::Dust { output, value: txout.value, threshold });
+            }
+        }
+        if let Some(token) = &txout.token {
+            let size = token.commitment.len();
+
+            if size > MAX_TOKEN_COMMITMENT_SIZE {
+                return Err(Error::TokenCommitment { output, size });
+            }
+            if token.amount < 0 || token.has_amount() != (token.amount != 0) {
+                return Err(Error::TokenAmount { output, amount: token.amount });
+            }
+        }
+    }
+    if data_size > MAX_OP_RETURN_RELAY {
+        return Err(Error::DataSize(data_size));
+    }
+    Ok(())
+}
+
+/// The minimum value of an output for it not to be dust, assuming it is spent with a P2PKH
+/// input. This is 546 sats for P2PKH outputs, and more for outputs carrying tokens.
+pub fn dust_threshold(output: &TxOut) -> u64 {
+    let size = serialize(output).len() as u64 + DUST_SPEND_SIZE;
+
+    3 * size * DUST_RELAY_FEE / 1000
+}
+
+/// Checks whether an output script is one of the standard templates: P2PKH, P2SH, P2SH32,
+/// P2PK, bare multisig of up to [`MAX_STANDARD_MULTISIG_PUBKEYS`] keys, or `OP_RETURN`
+/// followed by pushes.
+pub fn is_standard_script(script: &Script) -> bool {
+    script.is_p2pkh()
+        || script.is_p2sh()
+        || script.is_p2sh32()
+        || script.is_p2pk()
+        || is_standard_multisig(script)
+        || is_null_data(script)
+}
+
+/// Checks whether a script is `OP_RETURN` followed by pushes.
+fn is_null_data(script: &Script) -> bool {
+    script.is_op_return()
+        && script.instructions().skip(1).all(|i| match i {
+            Ok(Instruction::PushBytes(_)) => true,
+            Ok(Instruction::Op(op)) => op.to_u8() <= opcodes::all::OP_PUSHNUM_16.to_u8(),
+            Err(_) => false,
+        })
+}
+
+/// Checks whether a script is an `m`-of-`n` bare multisig, ie.
+/// `OP_m <pubkey>.. OP_n OP_CHECKMULTISIG`, of up to [`MAX_STANDARD_MULTISIG_PUBKEYS`] keys.
+fn is_standard_multisig(script: &Script) -> bool {
+    let instructions = match script.instructions().collect::<Result<Vec<_>, _>>() {
+        Ok(instructions) => instructions,
+        Err(_) => return false,
+    };
+    let (m, pubkeys, n) = match instructions.as_slice() {
+        [Instruction::Op(m), pubkeys @ .., Instruction::Op(n), Instruction::Op(checkmultisig)]
+            if *checkmultisig == opcodes::all::OP_CHECKMULTISIG =>
+        {
+            (pushnum(*m), pubkeys, pushnum(*n))
+        }
+        _ => return false,
+    };
+    let (m, n) = match (m, n) {
+        (Some(m), Some(n)) => (m, n),
+        _ => return false,
+    };
+    1 <= m
+        && m <= n
+        && n <= MAX_STANDARD_MULTISIG_PUBKEYS
+        && pubkeys.len() == n
+        && pubkeys.iter().all(|pk| match pk {
+            Instruction::PushBytes(pk) => pk.len() == 33 || pk.len() == 65,
+            _ => false,
+        })
+}
+
+/// The number pushed by `OP_1` to `OP_16`.
+fn pushnum(op: opcodes::All) -> Option<usize> {
+    let n = op.to_u8().wrapping_sub(opcodes::all::OP_PUSHNUM_1.to_u8()) as usize + 1;
+
+    if (1..=16).contains(&n) {
+        Some(n)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockdata::opcodes::all::*;
+    use crate::blockdata::script::Builder;
+    use crate::blockdata::token::OutputData;
+    use crate::blockdata::transaction::TxIn;
+    use crate::hashes::{sha256d, Hash};
+    use crate::{PackedLockTime, PubkeyHash, TokenID};
+
+    /// A transaction spending a P2SH32 output whose redeem script is `OP_1`.
+    fn spend() -> (Transaction, Vec<TxOut>) {
+        let redeem_script = Builder::new().push_opcode(OP_PUSHNUM_1).into_script();
+        let script_pubkey = Builder::new()
+            .push_opcode(OP_HASH256)
+            .push_slice(&sha256d::Hash::hash(redeem_script.as_bytes())[..])
+            .push_opcode(OP_EQUAL)
+            .into_script();
+        let spent = vec![TxOut { value: 10_000, script_pubkey, token: None }];
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn {
+                script_sig: Builder::new().push_slice(redeem_script.as_bytes()).into_script(),
+                ..TxIn::default()
+            }],
+            output: vec![TxOut {
+                value: 9_000,
+                script_pubkey: Script::new_p2pkh(&PubkeyHash::hash(&[])),
+                token: None,
+            }],
+        };
+        (tx, spent)
+    }
+
+    #[test]
+    fn test_check_transaction() {
+        let (tx, spent) = spend();
+        assert_eq!(check_transaction(&tx, &spent), Ok(()));
+
+        let mut dust = tx.clone();
+        dust.output[0].value = 545;
+        assert_eq!(
+            check_transaction(&dust, &spent),
+            Err(Error::Dust { output: 0, value: 545, threshold: 546 })
+        );
+
+        let mut version = tx.clone();
+        version.version = 3;
+        assert_eq!(check_transaction(&version, &spent), Err(Error::Version(3)));
+
+        let mut data = tx.clone();
+        data.output.push(TxOut {
+            value: 0,
+            script_pubkey: Script::new_op_return(&[0; 221]),
+            token: None,
+        });
+        assert_eq!(check_transaction(&data, &spent), Err(Error::DataSize(224)));
+
+        let mut token = tx.clone();
+        token.output[0].value = 1_000;
+        token.output[0].token = Some(OutputData {
+            id: TokenID::from_inner([7; 32]),
+            bitfield: 0x60,
+            amount: 0,
+            commitment: vec![0; 41],
+        });
+        assert_eq!(
+            check_transaction(&token, &spent),
+            Err(Error::TokenCommitment { output: 0, size: 41 })
+        );
+
+        let mut invalid = tx;
+        invalid.input[0].script_sig = Script::new();
+        assert!(matches!(check_transaction(&invalid, &spent), Err(Error::Script { input: 0, .. })));
+    }
+
+    #[test]
+    fn test_is_standard_script() {
+        let pubkey = [2; 33];
+        let multisig = |m, n| {
+            (0..n)
+                .fold(Builder::new().push_int(m), |b, _| b.push_slice(&pubkey))
+                .push_int(n)
+                .push_opcode(OP_CHECKMULTISIG)
+                .into_script()
+        };
+        assert!(is_standard_script(&multisig(1, 3)));
+        assert!(!is_standard_script(&multisig(1, 4)));
+        assert!(!is_standard_script(&multisig(3, 2)));
+        assert!(is_standard_script(&Script::new_op_return(b"memo")));
+        assert!(!is_standard_script(&Builder::new().push_opcode(OP_PUSHNUM_1).into_script()));
+        assert!(!is_standard_script(
+            &Builder::new().push_opcode(OP_RETURN).push_opcode(OP_DUP).into_script()
+        ));
+    }
+}
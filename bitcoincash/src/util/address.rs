@@ -32,10 +32,11 @@ use core::str::FromStr;
 use secp256k1::{Secp256k1, Verification, XOnlyPublicKey};
 use bech32;
 use crate::hashes::{sha256, Hash, HashEngine};
-use crate::hash_types::{PubkeyHash, ScriptHash};
+use crate::hash_types::{PubkeyHash, ScriptHash, ScriptHash32};
 use crate::blockdata::{script, opcodes};
 use crate::blockdata::constants::{PUBKEY_ADDRESS_PREFIX_MAIN, SCRIPT_ADDRESS_PREFIX_MAIN, PUBKEY_ADDRESS_PREFIX_TEST, SCRIPT_ADDRESS_PREFIX_TEST, MAX_SCRIPT_ELEMENT_SIZE};
 use crate::network::constants::Network;
+use crate::cash_addr;
 use crate::util::base58;
 use crate::util::taproot::TapBranchHash;
 use crate::util::key::PublicKey;
@@ -78,6 +79,8 @@ pub enum Error {
     UnrecognizedScript,
     /// Address type is either invalid or not supported in rust-bitcoin.
     UnknownAddressType(String),
+    /// Cashaddr encoding error.
+    CashAddr(cash_addr::DecodingError),
 }
 
 impl fmt::Display for Error {
@@ -96,6 +99,7 @@ impl fmt::Display for Error {
             Error::ExcessiveScriptSize => write!(f, "script size exceed 520 bytes"),
             Error::UnrecognizedScript => write!(f, "script is not a p2pkh, p2sh or witness program"),
             Error::UnknownAddressType(ref s) => write!(f, "unknown address type: '{}' is either invalid or not supported in rust-bitcoin", s),
+            Error::CashAddr(ref e) => write_err!(f, "cashaddr address encoding error"; e),
         }
     }
 }
@@ -110,6 +114,7 @@ impl std::error::Error for Error {
             Base58(e) => Some(e),
             Bech32(e) => Some(e),
             UnparsableWitnessVersion(e) => Some(e),
+            CashAddr(e) => Some(e),
             EmptyBech32Payload
             | InvalidBech32Variant { .. }
             | InvalidWitnessVersion(_)
@@ -138,6 +143,13 @@ impl From<bech32::Error> for Error {
     }
 }
 
+#[doc(hidden)]
+impl From<cash_addr::DecodingError> for Error {
+    fn from(e: cash_addr::DecodingError) -> Error {
+        Error::CashAddr(e)
+    }
+}
+
 /// The different types of addresses.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[non_exhaustive]
@@ -146,6 +158,8 @@ pub enum AddressType {
     P2pkh,
     /// Pay to script hash.
     P2sh,
+    /// Pay to 32-byte script hash.
+    P2sh32,
     /// Pay to witness pubkey hash.
     P2wpkh,
     /// Pay to witness script hash.
@@ -159,6 +173,7 @@ impl fmt::Display for AddressType {
         f.write_str(match *self {
             AddressType::P2pkh => "p2pkh",
             AddressType::P2sh => "p2sh",
+            AddressType::P2sh32 => "p2sh32",
             AddressType::P2wpkh => "p2wpkh",
             AddressType::P2wsh => "p2wsh",
             AddressType::P2tr => "p2tr",
@@ -172,6 +187,7 @@ impl FromStr for AddressType {
         match s {
             "p2pkh" => Ok(AddressType::P2pkh),
             "p2sh" => Ok(AddressType::P2sh),
+            "p2sh32" => Ok(AddressType::P2sh32),
             "p2wpkh" => Ok(AddressType::P2wpkh),
             "p2wsh" => Ok(AddressType::P2wsh),
             "p2tr" => Ok(AddressType::P2tr),
@@ -447,6 +463,8 @@ pub enum Payload {
     PubkeyHash(PubkeyHash),
     /// P2SH address.
     ScriptHash(ScriptHash),
+    /// P2SH32 address, only encodable as a cashaddr.
+    ScriptHash32(ScriptHash32),
     /// Segwit address.
     WitnessProgram {
         /// The witness program version.
@@ -467,6 +485,10 @@ impl Payload {
             let mut hash_inner = [0u8; 20];
             hash_inner.copy_from_slice(&script.as_bytes()[2..22]);
             Payload::ScriptHash(ScriptHash::from_inner(hash_inner))
+        } else if script.is_p2sh32() {
+            let mut hash_inner = [0u8; 32];
+            hash_inner.copy_from_slice(&script.as_bytes()[2..34]);
+            Payload::ScriptHash32(ScriptHash32::from_inner(hash_inner))
         } else if script.is_witness_program() {
             if script.witness_version() == Some(WitnessVersion::V0) && !(script.is_v0_p2wpkh() || script.is_v0_p2wsh()) {
                 return Err(Error::InvalidSegwitV0ProgramLength(script.len() - 2));
@@ -486,6 +508,7 @@ impl Payload {
         match *self {
             Payload::PubkeyHash(ref hash) => script::Script::new_p2pkh(hash),
             Payload::ScriptHash(ref hash) => script::Script::new_p2sh(hash),
+            Payload::ScriptHash32(ref hash) => script::Script::new_p2sh32(hash),
             Payload::WitnessProgram { version, program: ref prog } => {
                 script::Script::new_witness_program(version, prog)
             }
@@ -507,6 +530,15 @@ impl Payload {
         Ok(Payload::ScriptHash(script.script_hash()))
     }
 
+    /// Creates a pay to 32-byte script hash P2SH32 payload from a script
+    #[inline]
+    pub fn p2sh32(script: &script::Script) -> Result<Payload, Error> {
+        if script.len() > MAX_SCRIPT_ELEMENT_SIZE {
+            return Err(Error::ExcessiveScriptSize);
+        }
+        Ok(Payload::ScriptHash32(script.script_hash32()))
+    }
+
     /// Create a witness pay to public key payload from a public key
     pub fn p2wpkh(pk: &PublicKey) -> Result<Payload, Error> {
         Ok(Payload::WitnessProgram {
@@ -569,6 +601,7 @@ impl Payload {
     pub fn as_bytes(&self) -> &[u8] {
         match self {
             Payload::ScriptHash(hash) => hash,
+            Payload::ScriptHash32(hash) => hash,
             Payload::PubkeyHash(hash) => hash,
             Payload::WitnessProgram { program, .. } => program,
         }
@@ -603,6 +636,8 @@ impl<'a> fmt::Display for AddressEncoding<'a> {
                 prefixed[1..].copy_from_slice(&hash[..]);
                 base58::check_encode_slice_to_fmt(fmt, &prefixed[..])
             }
+            // There is no base58 encoding of 32-byte hashes, see [`Address`] for their cashaddr.
+            Payload::ScriptHash32(_) => Err(fmt::Error),
             Payload::WitnessProgram {
                 version,
                 program: prog,
@@ -666,6 +701,19 @@ impl Address {
         })
     }
 
+    /// Creates a pay to 32-byte script hash P2SH32 address from a script.
+    ///
+    /// This address type was introduced with the May 2023 upgrade, for contracts whose
+    /// security relies on collision resistance, eg. covenants with multiple parties. It is only
+    /// encodable as a cashaddr.
+    #[inline]
+    pub fn p2sh32(script: &script::Script, network: Network) -> Result<Address, Error> {
+        Ok(Address {
+            network,
+            payload: Payload::p2sh32(script)?,
+        })
+    }
+
     /// Creates a witness pay to public key address from a public key.
     ///
     /// This is the native segwit address type for an output redeemable with a single signature.
@@ -741,6 +789,7 @@ impl Address {
         match self.payload {
             Payload::PubkeyHash(_) => Some(AddressType::P2pkh),
             Payload::ScriptHash(_) => Some(AddressType::P2sh),
+            Payload::ScriptHash32(_) => Some(AddressType::P2sh32),
             Payload::WitnessProgram {
                 version,
                 program: ref prog,
@@ -787,8 +836,11 @@ impl Address {
     ///
     /// Quoting BIP 173 "inside QR codes uppercase SHOULD be used, as those permit the use of
     /// alphanumeric mode, which is 45% more compact than the normal byte mode."
+    ///
+    /// Cashaddrs already carry their schema, and are made uppercase.
     pub fn to_qr_uri(&self) -> String {
         let schema = match self.payload {
+            Payload::ScriptHash32(_) => return format!("{:#}", self),
             Payload::WitnessProgram { .. } => "BITCOIN",
             _ => "bitcoin",
         };
@@ -856,6 +908,11 @@ impl Address {
 // be used in QR codes, see [`Address::to_qr_uri`].
 impl fmt::Display for Address {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if let Payload::ScriptHash32(hash) = self.payload {
+            // There is no base58 encoding of 32-byte hashes.
+            let payload = cash_addr::Payload::ScriptHash32(hash);
+            return fmt::Display::fmt(&cash_addr::Address::new(payload, self.network), fmt);
+        }
         let p2pkh_prefix = match self.network {
             Network::Bitcoin => PUBKEY_ADDRESS_PREFIX_MAIN,
             Network::Testnet | Network::Testnet4 | Network::Scalenet | Network::Chipnet | Network::Regtest => PUBKEY_ADDRESS_PREFIX_TEST,
@@ -906,6 +963,12 @@ impl FromStr for Address {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Address, Error> {
+        // try cashaddr, which is always prefixed here, as unprefixed ones can't be told apart
+        // from base58
+        if s.contains(':') {
+            return Ok(s.parse::<cash_addr::Address>()?.into());
+        }
+
         // try bech32
         let bech32_network = match find_bech32_prefix(s) {
             // note that upper or lowercase is allowed but NOT mixed case
@@ -1096,6 +1159,41 @@ mod tests {
         assert_eq!(Address::p2sh(&script, Testnet), Err(Error::ExcessiveScriptSize));
     }
 
+    #[test]
+    fn test_p2sh32() {
+        let addr = Address {
+            network: Bitcoin,
+            payload: Payload::ScriptHash32(ScriptHash32::from_inner([0xff; 32])),
+        };
+        assert_eq!(
+            addr.script_pubkey(),
+            hex_script!("aa20ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff87")
+        );
+        assert_eq!(&addr.to_string(), "bitcoincash:p0llllllllllllllllllllllllllllllllllllllllllllllllll7x3vthu35");
+        assert_eq!(addr.to_qr_uri(), "BITCOINCASH:P0LLLLLLLLLLLLLLLLLLLLLLLLLLLLLLLLLLLLLLLLLLLLLLLLLL7X3VTHU35");
+        assert_eq!(addr.address_type(), Some(AddressType::P2sh32));
+        roundtrips(&addr);
+
+        // Token-aware cashaddrs pay to the same script.
+        let token = Address::from_str("bitcoincash:r0llllllllllllllllllllllllllllllllllllllllllllllllll75zs2wagl").unwrap();
+        assert_eq!(token, addr);
+
+        let script = hex_script!("51");
+        let addr = Address::p2sh32(&script, Testnet).unwrap();
+        assert!(addr.script_pubkey().is_p2sh32());
+        assert_eq!(addr.script_pubkey(), script.to_p2sh32());
+        assert!(addr.to_string().starts_with("bchtest:p"));
+        roundtrips(&addr);
+
+        let p2sh = Address::from_str("bitcoincash:pqv53dwyatxse2xh7nnlqhyr6ryjgfdtagkd4vc388").unwrap();
+        assert_eq!(p2sh.address_type(), Some(AddressType::P2sh));
+        assert_eq!(p2sh.payload.as_bytes().len(), 20);
+        assert!(matches!(
+            Address::from_str("bitcoincash:p0llllllllllllllllllllllllllllllllllllllllllllllllll7x3vthu36"),
+            Err(Error::CashAddr(_))
+        ));
+    }
+
     #[test]
     fn test_p2wpkh() {
         // stolen from Bitcoin transaction: b3c8c2b6cfc335abbcb2c7823a8453f55d64b2b5125a9a61e8737230cdb8ce20
@@ -1390,6 +1488,8 @@ mod tests {
 
         test_addr_type(legacy_payload, LEGACY_EQUIVALENCE_CLASSES);
         test_addr_type(&segwit_payload, SEGWIT_EQUIVALENCE_CLASSES);
+        // Unlike base58, cashaddrs tell regtest apart.
+        test_addr_type(&[Payload::ScriptHash32(ScriptHash32::all_zeros())], SEGWIT_EQUIVALENCE_CLASSES);
     }
 
     #[test]
@@ -0,0 +1,379 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! BIP21 Payment URIs
+//!
+//! Parsing and generation of BIP21-style payment URIs for Bitcoin Cash, eg.
+//! `bitcoincash:qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekg2?amount=0.5&label=Alice`.
+//!
+//! The URI scheme is the cashaddr prefix of the recipient address. Besides the BIP21
+//! `amount`, `label` and `message` parameters, CashTokens can be requested with the
+//! `c` (token category), `ft` (fungible amount) and `nft` (hex-encoded commitment)
+//! parameters.
+//!
+
+use crate::prelude::*;
+
+use core::fmt;
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::error;
+
+use crate::cash_addr::{self, DecodingError};
+use crate::hashes::hex::{FromHex, ToHex};
+use crate::util::amount::{Amount, Denomination, ParseAmountError};
+use crate::{Network, TokenID};
+
+/// A payment URI error.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// The recipient address is invalid.
+    Address(DecodingError),
+    /// The amount is invalid.
+    Amount(ParseAmountError),
+    /// A parameter is malformed or has an invalid value.
+    InvalidParameter(String),
+    /// A parameter appears more than once.
+    DuplicateParameter(String),
+    /// A parameter prefixed with `req-` isn't understood, so the URI can't be used.
+    UnknownRequired(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Address(ref e) => write!(f, "invalid address: {}", e),
+            Error::Amount(ref e) => write!(f, "invalid amount: {}", e),
+            Error::InvalidParameter(ref p) => write!(f, "invalid parameter `{}`", p),
+            Error::DuplicateParameter(ref p) => write!(f, "duplicate parameter `{}`", p),
+            Error::UnknownRequired(ref p) => write!(f, "unknown required parameter `{}`", p),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+impl error::Error for Error {}
+
+#[doc(hidden)]
+impl From<DecodingError> for Error {
+    fn from(e: DecodingError) -> Error {
+        Error::Address(e)
+    }
+}
+
+#[doc(hidden)]
+impl From<ParseAmountError> for Error {
+    fn from(e: ParseAmountError) -> Error {
+        Error::Amount(e)
+    }
+}
+
+/// CashTokens requested by a payment URI.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TokenRequest {
+    /// Token category.
+    pub category: TokenID,
+    /// Amount of fungible tokens, if any.
+    pub amount: Option<u64>,
+    /// Commitment of the requested non-fungible token, if any.
+    pub commitment: Option<Vec<u8>>,
+}
+
+/// A payment URI.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Uri {
+    /// Recipient cashaddr, including its prefix.
+    pub address: String,
+    /// Network of the recipient address.
+    pub network: Network,
+    /// Requested amount.
+    pub amount: Option<Amount>,
+    /// Label for the recipient.
+    pub label: Option<String>,
+    /// Message describing the payment.
+    pub message: Option<String>,
+    /// Requested tokens.
+    pub token: Option<TokenRequest>,
+}
+
+impl Uri {
+    /// Create a URI paying to the given cashaddr, with no parameters. The address may omit
+    /// its prefix, in which case its network is the one its checksum is valid for.
+    pub fn new(address: &str) -> Result<Uri, Error> {
+        let address = normalize(address);
+        let (_, _, network) = cash_addr::decode(&address)?;
+        let address = if address.contains(':') {
+            address
+        } else {
+            format!("{}:{}", cash_addr::prefix(network), address)
+        };
+
+        Ok(Uri { address, network, amount: None, label: None, message: None, token: None })
+    }
+}
+
+/// Lowercase the prefix of an address, and its payload if given in uppercase, as is done in
+/// QR codes. Mixed-case payloads are left alone, and rejected when decoded.
+fn normalize(address: &str) -> String {
+    if let Some(ix) = address.find(':') {
+        return format!("{}:{}", address[..ix].to_ascii_lowercase(), normalize(&address[ix + 1..]));
+    }
+    if address.chars().any(|c| c.is_ascii_lowercase()) {
+        address.to_owned()
+    } else {
+        address.to_ascii_lowercase()
+    }
+}
+
+/// Format an amount in BCH, without trailing zeros.
+fn format_amount(amount: Amount) -> String {
+    let s = amount.to_string_in(Denomination::Bitcoin);
+
+    if s.contains('.') {
+        s.trim_end_matches('0').trim_end_matches('.').to_owned()
+    } else {
+        s
+    }
+}
+
+/// Percent-encode a parameter value.
+fn encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+/// Decode a percent-encoded parameter value. Returns `None` if it's malformed.
+fn decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3).filter(|h| h.bytes().all(|b| b.is_ascii_hexdigit()))?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+impl fmt::Display for Uri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut params = Vec::new();
+
+        if let Some(amount) = self.amount {
+            params.push(format!("amount={}", format_amount(amount)));
+        }
+        if let Some(ref label) = self.label {
+            params.push(format!("label={}", encode(label)));
+        }
+        if let Some(ref message) = self.message {
+            params.push(format!("message={}", encode(message)));
+        }
+        if let Some(ref token) = self.token {
+            params.push(format!("c={}", token.category));
+
+            if let Some(amount) = token.amount {
+                params.push(format!("ft={}", amount));
+            }
+            if let Some(ref commitment) = token.commitment {
+                params.push(format!("nft={}", commitment.to_hex()));
+            }
+        }
+        write!(f, "{}", self.address)?;
+
+        if !params.is_empty() {
+            write!(f, "?{}", params.join("&"))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Uri {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Uri, Error> {
+        let s = s.trim();
+        let (address, query) = match s.find('?') {
+            Some(ix) => (&s[..ix], Some(&s[ix + 1..])),
+            None => (s, None),
+        };
+        // The scheme is the address prefix, so it's checked when decoding the address.
+        let mut uri = Uri::new(address)?;
+        let mut category = None;
+        let mut token_amount = None;
+        let mut commitment = None;
+
+        for param in query.into_iter().flat_map(|q| q.split('&')).filter(|p| !p.is_empty()) {
+            let (key, value) = match param.find('=') {
+                Some(ix) => (&param[..ix], &param[ix + 1..]),
+                None => (param, ""),
+            };
+            let invalid = || Error::InvalidParameter(key.to_owned());
+            let value = decode(value).ok_or_else(invalid)?;
+
+            macro_rules! set {
+                ($field:expr, $value:expr) => {
+                    if $field.is_some() {
+                        return Err(Error::DuplicateParameter(key.to_owned()));
+                    }
+                    $field = Some($value);
+                };
+            }
+
+            match key {
+                "amount" => {
+                    set!(uri.amount, Amount::from_str_in(&value, Denomination::Bitcoin)?);
+                }
+                "label" => {
+                    set!(uri.label, value);
+                }
+                "message" => {
+                    set!(uri.message, value);
+                }
+                "c" => {
+                    set!(category, TokenID::from_str(&value).map_err(|_| invalid())?);
+                }
+                "ft" => {
+                    set!(token_amount, value.parse::<u64>().map_err(|_| invalid())?);
+                }
+                "nft" => {
+                    set!(commitment, Vec::<u8>::from_hex(&value).map_err(|_| invalid())?);
+                }
+                key if key.starts_with("req-") => {
+                    return Err(Error::UnknownRequired(key.to_owned()));
+                }
+                // Unknown optional parameters are ignored.
+                _ => {}
+            }
+        }
+
+        uri.token = match category {
+            Some(category) => Some(TokenRequest { category, amount: token_amount, commitment }),
+            None if token_amount.is_some() => return Err(Error::InvalidParameter("ft".to_owned())),
+            None if commitment.is_some() => return Err(Error::InvalidParameter("nft".to_owned())),
+            None => None,
+        };
+        Ok(uri)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "bitcoincash:qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekg2";
+    const CATEGORY: &str = "8473d94f604de351cdee3030f6c354d36b257861ad8e95bbc0a06fbab2a2f9cf";
+
+    #[test]
+    fn parse() {
+        let uri: Uri = ADDRESS.parse().unwrap();
+        assert_eq!(uri.address, ADDRESS);
+        assert_eq!(uri.network, Network::Bitcoin);
+        assert_eq!(uri.amount, None);
+
+        let uri: Uri =
+            format!("{}?amount=0.5&label=Alice%27s%20shop&message=Order%20%2342&foo=bar", ADDRESS)
+                .parse()
+                .unwrap();
+        assert_eq!(uri.amount, Some(Amount::from_sat(50_000_000)));
+        assert_eq!(uri.label.as_deref(), Some("Alice's shop"));
+        assert_eq!(uri.message.as_deref(), Some("Order #42"));
+
+        // Uppercase, as found in QR codes.
+        let uri: Uri = ADDRESS.to_uppercase().parse().unwrap();
+        assert_eq!(uri.address, ADDRESS);
+
+        // Without a scheme.
+        let uri: Uri = ADDRESS[12..].parse().unwrap();
+        assert_eq!(uri.address, ADDRESS);
+
+        let uri: Uri = "bchtest:qr95sy3j9xwd2ap32xkykttr4cvcu7as4ytjg7p7mc".parse().unwrap();
+        assert_eq!(uri.network, Network::Testnet);
+    }
+
+    #[test]
+    fn parse_token() {
+        let uri: Uri = format!("{}?c={}&ft=1000&nft=cafe", ADDRESS, CATEGORY).parse().unwrap();
+        let token = uri.token.unwrap();
+
+        assert_eq!(token.category.to_string(), CATEGORY);
+        assert_eq!(token.amount, Some(1000));
+        assert_eq!(token.commitment, Some(vec![0xca, 0xfe]));
+
+        assert_eq!(
+            format!("{}?ft=1000", ADDRESS).parse::<Uri>(),
+            Err(Error::InvalidParameter("ft".to_owned()))
+        );
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(matches!("bitcoincash:qr6m7j".parse::<Uri>(), Err(Error::Address(_))));
+        assert!(matches!(
+            format!("{}?amount=-1", ADDRESS).parse::<Uri>(),
+            Err(Error::Amount(_))
+        ));
+        assert!(matches!(
+            format!("{}?amount=1.123456789", ADDRESS).parse::<Uri>(),
+            Err(Error::Amount(_))
+        ));
+        assert_eq!(
+            format!("{}?amount=1&amount=2", ADDRESS).parse::<Uri>(),
+            Err(Error::DuplicateParameter("amount".to_owned()))
+        );
+        assert_eq!(
+            format!("{}?req-foo=bar", ADDRESS).parse::<Uri>(),
+            Err(Error::UnknownRequired("req-foo".to_owned()))
+        );
+        assert_eq!(
+            format!("{}?label=%zz", ADDRESS).parse::<Uri>(),
+            Err(Error::InvalidParameter("label".to_owned()))
+        );
+    }
+
+    #[test]
+    fn generate() {
+        let mut uri = Uri::new(ADDRESS).unwrap();
+        assert_eq!(uri.to_string(), ADDRESS);
+
+        uri.amount = Some(Amount::from_sat(150_000_000));
+        uri.label = Some("Alice & Bob".to_owned());
+        assert_eq!(uri.to_string(), format!("{}?amount=1.5&label=Alice%20%26%20Bob", ADDRESS));
+
+        uri.amount = Some(Amount::from_sat(100_000_000));
+        uri.label = None;
+        uri.token = Some(TokenRequest {
+            category: TokenID::from_str(CATEGORY).unwrap(),
+            amount: Some(10),
+            commitment: None,
+        });
+        assert_eq!(uri.to_string(), format!("{}?amount=1&c={}&ft=10", ADDRESS, CATEGORY));
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut uri = Uri::new(&ADDRESS[12..]).unwrap();
+        uri.amount = Some(Amount::from_sat(1));
+        uri.message = Some("½ pizza?".to_owned());
+        uri.token = Some(TokenRequest {
+            category: TokenID::from_str(CATEGORY).unwrap(),
+            amount: None,
+            commitment: Some(vec![1, 2, 3]),
+        });
+        assert_eq!(uri.to_string().parse::<Uri>().unwrap(), uri);
+    }
+}
@@ -10,8 +10,64 @@ use bit_vec::BitVec;
 use murmur3::murmur3_32;
 use rand::{self};
 
+use crate::blockdata::opcodes::all::{OP_CHECKMULTISIG, OP_PUSHNUM_1, OP_PUSHNUM_16};
+use crate::blockdata::script::{Instruction, Script};
+use crate::blockdata::transaction::{OutPoint, Transaction};
+use crate::consensus::encode::serialize;
+use crate::hashes::{hash160, Hash as _, HashEngine};
+use crate::util::key::PublicKey;
+
+/// Maximum size of a BIP37 filter in bytes.
+pub const MAX_BLOOM_FILTER_SIZE: usize = 36_000;
+/// Maximum number of hash functions allowed by BIP37.
+pub const MAX_HASH_FUNCS: u32 = 50;
+
+/// Filter flag: never add the outpoints of matched outputs to the filter.
+pub const BLOOM_UPDATE_NONE: u8 = 0;
+/// Filter flag: add the outpoints of all matched outputs to the filter, so that transactions
+/// spending them match too.
+pub const BLOOM_UPDATE_ALL: u8 = 1;
+/// Filter flag: only add the outpoints of matched pay-to-pubkey and bare multisig outputs to
+/// the filter, since spends of other outputs reveal their public key, which can be inserted
+/// up-front.
+pub const BLOOM_UPDATE_P2PUBKEY_ONLY: u8 = 2;
+/// Mask of the update flags, in a filter's flags.
+pub const BLOOM_UPDATE_MASK: u8 = 3;
+
+/// Strategy for inserting decoy elements into a filter.
+///
+/// Decoys grow the set of elements a peer sees in a filter, hiding the ones actually
+/// watched, at the cost of a larger filter for the same false-positive rate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decoys {
+    /// Don't insert decoys.
+    None,
+    /// Insert a fixed number of decoys.
+    Fixed(usize),
+    /// Insert a number of decoys proportional to the number of elements, eg. `2.0` for
+    /// twice as many decoys as elements.
+    Ratio(f64),
+}
+
+impl Default for Decoys {
+    fn default() -> Self {
+        Decoys::None
+    }
+}
+
+impl Decoys {
+    /// Number of decoys to insert alongside the given number of elements.
+    pub fn count(&self, elements: usize) -> usize {
+        match *self {
+            Decoys::None => 0,
+            Decoys::Fixed(n) => n,
+            Decoys::Ratio(ratio) => (elements as f64 * ratio.max(0.0)).ceil() as usize,
+        }
+    }
+}
+
 /// BIP37 BloomFilter
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct BloomFilter {
     /// the filter
     pub content: Vec<u8>,
@@ -23,6 +79,222 @@ pub struct BloomFilter {
     pub flags: u8,
 }
 
+impl BloomFilter {
+    /// Create a new empty filter sized for `elements` insertions at the given
+    /// false-positive rate, as specified by BIP37.
+    pub fn new(elements: usize, fp_rate: f64, tweak: u32, flags: u8) -> Self {
+        let ln2 = f64::consts::LN_2;
+        let elements = cmp::max(elements, 1) as f64;
+        let size = (-1.0 / (ln2 * ln2) * elements * fp_rate.ln() / 8.0) as usize;
+        let size = cmp::min(cmp::max(size, 1), MAX_BLOOM_FILTER_SIZE);
+        let hashes = (size as f64 * 8.0 / elements * ln2) as u32;
+        let hashes = cmp::min(cmp::max(hashes, 1), MAX_HASH_FUNCS);
+
+        Self { content: vec![0; size], hashes, tweak, flags }
+    }
+
+    /// Insert a data element into the filter.
+    pub fn insert(&mut self, data: &[u8]) {
+        if self.content.is_empty() {
+            return;
+        }
+        for n in 0..self.hashes {
+            let index = self.hash(n, data);
+            self.content[index >> 3] |= 1 << (7 & index);
+        }
+    }
+
+    /// Insert `count` decoy elements into the filter.
+    ///
+    /// Decoys are derived from the given seed, which should be kept secret, and look like
+    /// script hashes, so that peers can't tell them apart from watched elements.
+    pub fn insert_decoys(&mut self, count: usize, seed: u64) {
+        for i in 0..count as u64 {
+            let mut engine = hash160::Hash::engine();
+            engine.input(&seed.to_le_bytes());
+            engine.input(&i.to_le_bytes());
+
+            self.insert(&hash160::Hash::from_engine(engine)[..]);
+        }
+    }
+
+    /// Insert an output script, ie. its data pushes, which is what peers match outputs on. For
+    /// a P2PKH script, this is the public key hash.
+    pub fn insert_script(&mut self, script: &Script) {
+        for data in pushes(script) {
+            self.insert(data);
+        }
+    }
+
+    /// Insert an outpoint, so that transactions spending it match.
+    pub fn insert_outpoint(&mut self, outpoint: &OutPoint) {
+        self.insert(&serialize(outpoint));
+    }
+
+    /// Insert a public key and its hash, so that both outputs paying to it and inputs spending
+    /// them, which push the key, match.
+    pub fn insert_pubkey(&mut self, pubkey: &PublicKey) {
+        self.insert(&pubkey.to_bytes());
+        self.insert(&pubkey.pubkey_hash()[..]);
+    }
+
+    /// Check whether an outpoint matches the filter.
+    pub fn contains_outpoint(&self, outpoint: &OutPoint) -> bool {
+        self.contains(&serialize(outpoint))
+    }
+
+    /// Check whether a transaction matches the filter, the way peers do when serving merkle
+    /// blocks and transactions to us, and update the filter like they do.
+    ///
+    /// Keeping a local copy of the filter updated this way keeps it in sync with the one
+    /// loaded on peers. See [`match_tx`].
+    pub fn match_tx(&mut self, tx: &Transaction) -> bool {
+        let flags = self.flags;
+
+        match_tx(self, flags, tx)
+    }
+
+    /// Check whether a data element matches the filter.
+    /// There can be false positives, but no false negatives.
+    pub fn contains(&self, data: &[u8]) -> bool {
+        if self.content.is_empty() {
+            return false;
+        }
+        (0..self.hashes).all(|n| {
+            let index = self.hash(n, data);
+            self.content[index >> 3] & (1 << (7 & index)) != 0
+        })
+    }
+
+    /// Estimate the filter's current false-positive rate from the proportion of bits set.
+    ///
+    /// This grows as elements are inserted past what the filter was sized for.
+    pub fn fp_rate(&self) -> f64 {
+        if self.content.is_empty() {
+            return 0.0;
+        }
+        let set: u32 = self.content.iter().map(|b| b.count_ones()).sum();
+        let ratio = set as f64 / (self.content.len() * 8) as f64;
+
+        ratio.powi(self.hashes as i32)
+    }
+
+    /// Bit index of `data` for the `n`th hash function.
+    fn hash(&self, n: u32, data: &[u8]) -> usize {
+        bit_index(n, self.tweak, data, self.content.len() * 8)
+    }
+}
+
+impl Elements for BloomFilter {
+    fn contains(&self, data: &[u8]) -> bool {
+        BloomFilter::contains(self, data)
+    }
+
+    fn insert(&mut self, data: &[u8]) {
+        BloomFilter::insert(self, data)
+    }
+}
+
+/// A set of data elements that transactions are matched against, as specified by BIP37.
+///
+/// Besides [`BloomFilter`], exact sets of elements can implement this, to match transactions
+/// the way a filter without false positives would.
+pub trait Elements {
+    /// Check whether a data element is in the set.
+    fn contains(&self, data: &[u8]) -> bool;
+    /// Insert a data element into the set.
+    fn insert(&mut self, data: &[u8]);
+}
+
+/// Check whether a transaction matches the given elements, the way peers match it against a
+/// filter with the given flags, and update the elements like they do.
+///
+/// A transaction matches if its txid does, if any data push of its outputs does, or if
+/// it spends a matching outpoint or any data push of its inputs does. The outpoints of
+/// matching outputs are inserted according to the update flags, see [`BLOOM_UPDATE_ALL`]
+/// and [`BLOOM_UPDATE_P2PUBKEY_ONLY`].
+pub fn match_tx<E: Elements + ?Sized>(elements: &mut E, flags: u8, tx: &Transaction) -> bool {
+    let txid = tx.txid();
+    let mut matched = elements.contains(&txid[..]);
+
+    for (vout, output) in tx.output.iter().enumerate() {
+        if !pushes(&output.script_pubkey).any(|data| elements.contains(data)) {
+            continue;
+        }
+        matched = true;
+
+        let update = match flags & BLOOM_UPDATE_MASK {
+            BLOOM_UPDATE_ALL => true,
+            BLOOM_UPDATE_P2PUBKEY_ONLY => {
+                output.script_pubkey.is_p2pk() || is_multisig(&output.script_pubkey)
+            }
+            _ => false,
+        };
+        if update {
+            elements.insert(&serialize(&OutPoint::new(txid, vout as u32)));
+        }
+    }
+    if matched {
+        return true;
+    }
+    tx.input.iter().any(|input| {
+        elements.contains(&serialize(&input.previous_output))
+            || pushes(&input.script_sig).any(|data| elements.contains(data))
+    })
+}
+
+/// Non-empty data pushes of a script, up to the first invalid instruction, if any.
+fn pushes(script: &Script) -> impl Iterator<Item = &[u8]> {
+    script.instructions().take_while(|i| i.is_ok()).filter_map(|i| match i {
+        Ok(Instruction::PushBytes(data)) if !data.is_empty() => Some(data),
+        _ => None,
+    })
+}
+
+/// Check whether a script is a bare multisig output script, ie.
+/// `<m> <pubkey>... <n> OP_CHECKMULTISIG`.
+fn is_multisig(script: &Script) -> bool {
+    let pushnum = |i: &Instruction| match *i {
+        Instruction::Op(op) => {
+            let code = op.to_u8();
+
+            if (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8()).contains(&code) {
+                Some((code - OP_PUSHNUM_1.to_u8() + 1) as usize)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    };
+    let instructions = match script.instructions().collect::<Result<Vec<_>, _>>() {
+        Ok(instructions) => instructions,
+        Err(_) => return false,
+    };
+    match instructions.as_slice() {
+        [m, keys @ .., n, Instruction::Op(OP_CHECKMULTISIG)] => match (pushnum(m), pushnum(n)) {
+            (Some(m), Some(n)) => {
+                m <= n
+                    && keys.len() == n
+                    && keys.iter().all(|k| match k {
+                        Instruction::PushBytes(key) => key.len() == 33 || key.len() == 65,
+                        _ => false,
+                    })
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Index of the bit set for `data` by the `n`th hash function, in a filter of `bits` bits, as
+/// specified by BIP37. Bit `i` is the `i % 8`th least significant bit of byte `i / 8`.
+fn bit_index(n: u32, tweak: u32, data: &[u8], bits: usize) -> usize {
+    let seed = n.wrapping_mul(0xFBA4C795).wrapping_add(tweak);
+    let h = murmur3_32(&mut Cursor::new(data), seed).expect("reading from memory can't fail");
+
+    h as usize % bits
+}
+
 impl From<Bloom<u8>> for BloomFilter {
     fn from(b: Bloom<u8>) -> Self {
         Self { content: b.bit_vec.to_bytes(), hashes: b.k_num, tweak: b.tweak, flags: 0 }
@@ -57,6 +329,11 @@ impl<T: ?Sized> Bloom<T> {
         Self { bit_vec: bitmap, bitmap_bits, k_num, tweak, _phantom: PhantomData }
     }
 
+    /// Use the given tweak instead of a random one. Must be called before inserting items.
+    pub fn with_tweak(self, tweak: u32) -> Self {
+        Self { tweak, ..self }
+    }
+
     /// Create a new bloom filter structure.
     /// items_count is an estimation of the maximum number of items to store.
     /// fp_p is the wanted rate of false positives, in ]0.0, 1.0[
@@ -77,44 +354,60 @@ impl<T: ?Sized> Bloom<T> {
     }
 
     /// Record the presence of an item.
-    pub fn set(&mut self, data: &mut Vec<u8>)
+    pub fn insert(&mut self, data: &[u8])
     where
         T: Hash,
     {
-        let mut v = self.bit_vec.to_bytes();
         for k in 0..self.k_num {
-            let index = self.hash(k, data);
-            let bit_offset = 1 << (7 & index);
-            v[index as usize >> 3] |= bit_offset;
+            let index = self.position(k, data);
+            self.bit_vec.set(index, true);
         }
-        self.bit_vec = BitVec::from_bytes(&v);
     }
 
     /// Check if an item is present in the set.
     /// There can be false positives, but no false negatives.
-    pub fn check(&self, data: &mut Vec<u8>) -> bool
+    pub fn contains(&self, data: &[u8]) -> bool
     where
         T: Hash,
     {
-        if self.k_num == 0 {
+        if self.k_num == 0 || self.bit_vec.is_empty() {
             return false;
         }
-        for i in 0..self.k_num {
-            let index = self.hash(i, data) as usize;
-            if self.bit_vec.to_bytes()[index >> 3] & (1 << (7 & index)) == 0 {
-                return false;
-            }
-        }
-        true
+        (0..self.k_num).all(|k| self.bit_vec[self.position(k, data)])
     }
 
-    /// murmur3 hash
-    pub fn hash(&self, hashes: u32, data: &mut Vec<u8>) -> u32 {
-        let mut cursor = Cursor::new(data);
-        let h = murmur3_32(&mut cursor, (hashes as u64 * 0xFBA4C795 + self.tweak as u64) as u32)
-            .unwrap();
-        let modulus: u32 = (self.bit_vec.to_bytes().len() * 8) as u32;
-        h % modulus
+    /// Record the presence of an item.
+    #[deprecated(since = "0.29.2", note = "Please use `Bloom::insert` instead.")]
+    #[allow(clippy::ptr_arg)] // Kept for compatibility.
+    pub fn set(&mut self, data: &mut Vec<u8>)
+    where
+        T: Hash,
+    {
+        self.insert(data)
+    }
+
+    /// Check if an item is present in the set.
+    #[deprecated(since = "0.29.2", note = "Please use `Bloom::contains` instead.")]
+    #[allow(clippy::ptr_arg)] // Kept for compatibility.
+    pub fn check(&self, data: &mut Vec<u8>) -> bool
+    where
+        T: Hash,
+    {
+        self.contains(data)
+    }
+
+    /// BIP37 bit index of `data` for the given hash function.
+    pub fn hash(&self, hashes: u32, data: &[u8]) -> u32 {
+        bit_index(hashes, self.tweak, data, self.bit_vec.len()) as u32
+    }
+
+    /// Position in the bit vector of the BIP37 bit index of `data`. Bits are stored most
+    /// significant first in each byte of a [`BitVec`], and least significant first in BIP37
+    /// filters, so that [`BitVec::to_bytes`] gives the filter's content.
+    fn position(&self, hashes: u32, data: &[u8]) -> usize {
+        let index = self.hash(hashes, data) as usize;
+
+        (index & !7) | (7 - (index & 7))
     }
 
     fn optimal_k_num(bitmap_bits: u64, items_count: usize) -> u32 {
@@ -126,6 +419,186 @@ impl<T: ?Sized> Bloom<T> {
 }
 
 mod test {
+    #[test]
+    fn test_bloom_filter_insert() {
+        use super::BloomFilter;
+        use crate::hashes::hex::FromHex;
+
+        let mut filter = BloomFilter::new(3, 0.01, 0, 1);
+        let a = Vec::from_hex("99108ad8ed9bb6274d3980bab5a85c048f0950c8").unwrap();
+        let b = Vec::from_hex("19108ad8ed9bb6274d3980bab5a85c048f0950c8").unwrap();
+
+        filter.insert(&a);
+        assert!(filter.contains(&a));
+        assert!(!filter.contains(&b));
+        assert_eq!(filter.content.len(), 3);
+        assert_eq!(filter.hashes, 5);
+    }
+
+    #[test]
+    fn test_bloom_filter_bip37_vectors() {
+        use super::{Bloom, BloomFilter};
+        use crate::consensus::serialize;
+        use crate::hashes::hex::{FromHex, ToHex};
+        use crate::network::message_bloom::{BloomFlags, FilterLoad};
+
+        let elements = [
+            "99108ad8ed9bb6274d3980bab5a85c048f0950c8",
+            "b5a2c786d9ef4658287ced5914b37a1b4aa32eee",
+            "b9300670b4c5366e95b2699e8b18bc75e5f729c5",
+        ]
+        .iter()
+        .map(|e| Vec::from_hex(e).unwrap())
+        .collect::<Vec<_>>();
+        let absent = Vec::from_hex("19108ad8ed9bb6274d3980bab5a85c048f0950c8").unwrap();
+
+        for (tweak, expected) in
+            [(0, "03614e9b050000000000000001"), (2147483649, "03ce4299050000000100008001")].iter()
+        {
+            let mut filter = BloomFilter::new(3, 0.01, *tweak, 1);
+            for element in elements.iter() {
+                filter.insert(element);
+            }
+            assert!(elements.iter().all(|e| filter.contains(e)));
+            assert!(!filter.contains(&absent));
+
+            let load = FilterLoad {
+                filter: filter.content.clone(),
+                hash_funcs: filter.hashes,
+                tweak: filter.tweak,
+                flags: BloomFlags::All,
+            };
+            assert_eq!(serialize(&load).to_hex(), *expected);
+
+            // Both filter types set the same bits.
+            let mut bloom = Bloom::<u8>::new(3, 3).with_tweak(*tweak);
+            let mut filter = BloomFilter { hashes: bloom.k_num, ..filter };
+            filter.content = vec![0; 3];
+
+            for element in elements.iter() {
+                bloom.insert(element);
+                filter.insert(element);
+            }
+            assert!(elements.iter().all(|e| bloom.contains(e)));
+            assert!(!bloom.contains(&absent));
+            assert_eq!(BloomFilter::from(bloom).content, filter.content);
+        }
+    }
+
+    #[test]
+    fn test_bloom_filter_update_flags() {
+        use super::{
+            BloomFilter, BLOOM_UPDATE_ALL, BLOOM_UPDATE_NONE, BLOOM_UPDATE_P2PUBKEY_ONLY,
+        };
+        use crate::blockdata::script::{Builder, Script};
+        use crate::blockdata::locktime::PackedLockTime;
+        use crate::blockdata::opcodes::all::{OP_CHECKMULTISIG, OP_PUSHNUM_1};
+        use crate::blockdata::transaction::{OutPoint, Transaction, TxIn, TxOut};
+        use crate::util::key::PublicKey;
+        use std::str::FromStr;
+
+        let pubkey = PublicKey::from_str(
+            "0250863ad64a87ae8a2fe83c1af1a8403cb53f53e486d8511dad8a04887e5b2352",
+        )
+        .unwrap();
+        let tx = |input: Vec<TxIn>, script_pubkey: Script| Transaction {
+            version: 1,
+            lock_time: PackedLockTime(0),
+            input,
+            output: vec![TxOut { value: 1000, script_pubkey, ..TxOut::default() }],
+        };
+        let spend = |outpoint| TxIn { previous_output: outpoint, ..TxIn::default() };
+        let p2pkh = Script::new_p2pkh(&pubkey.pubkey_hash());
+        let p2pk = Script::new_p2pk(&pubkey);
+        let multisig = Builder::new()
+            .push_opcode(OP_PUSHNUM_1)
+            .push_key(&pubkey)
+            .push_opcode(OP_PUSHNUM_1)
+            .push_opcode(OP_CHECKMULTISIG)
+            .into_script();
+
+        // Whether spends of outputs paying to each script match, after matching the outputs.
+        let cases = [
+            (BLOOM_UPDATE_NONE, &p2pkh, false),
+            (BLOOM_UPDATE_NONE, &p2pk, false),
+            (BLOOM_UPDATE_ALL, &p2pkh, true),
+            (BLOOM_UPDATE_ALL, &p2pk, true),
+            (BLOOM_UPDATE_P2PUBKEY_ONLY, &p2pkh, false),
+            (BLOOM_UPDATE_P2PUBKEY_ONLY, &p2pk, true),
+            (BLOOM_UPDATE_P2PUBKEY_ONLY, &multisig, true),
+        ];
+        for (flags, script, spent) in cases.iter() {
+            let mut filter = BloomFilter::new(10, 0.0001, 0, *flags);
+            filter.insert_pubkey(&pubkey);
+
+            let funding = tx(vec![spend(OutPoint::default())], (*script).clone());
+            let outpoint = OutPoint::new(funding.txid(), 0);
+            assert!(filter.match_tx(&funding));
+            assert_eq!(filter.contains_outpoint(&outpoint), *spent);
+
+            let spending = tx(vec![spend(outpoint)], Script::new_op_return(b"memo"));
+            assert_eq!(filter.match_tx(&spending), *spent);
+        }
+
+        // Inserting a script inserts its data pushes, ie. the public key hash.
+        let mut filter = BloomFilter::new(10, 0.0001, 0, BLOOM_UPDATE_NONE);
+        filter.insert_script(&p2pkh);
+        assert!(filter.contains(&pubkey.pubkey_hash()[..]));
+        assert!(!filter.contains(&pubkey.to_bytes()));
+
+        // Inputs pushing a watched key match, even if the outpoint wasn't inserted.
+        let mut filter = BloomFilter::new(10, 0.0001, 0, BLOOM_UPDATE_NONE);
+        filter.insert_pubkey(&pubkey);
+        let mut input = spend(OutPoint::default());
+        input.script_sig = Builder::new().push_slice(&[0; 71]).push_key(&pubkey).into_script();
+        assert!(filter.match_tx(&tx(vec![input], Script::new_op_return(b"memo"))));
+    }
+
+    #[test]
+    fn test_bloom_filter_decoys() {
+        use super::{BloomFilter, Decoys};
+
+        assert_eq!(Decoys::None.count(10), 0);
+        assert_eq!(Decoys::Fixed(4).count(10), 4);
+        assert_eq!(Decoys::Ratio(1.5).count(3), 5);
+        assert_eq!(Decoys::Ratio(-1.0).count(3), 0);
+
+        let mut filter = BloomFilter::new(8, 0.001, 0, 0);
+        let mut decoys = filter.clone();
+
+        filter.insert(&[1; 20]);
+        decoys.insert(&[1; 20]);
+        decoys.insert_decoys(7, 42);
+
+        assert!(decoys.contains(&[1; 20]));
+        assert!(decoys.fp_rate() > filter.fp_rate());
+
+        // The same seed yields the same decoys.
+        let mut other = filter.clone();
+        other.insert_decoys(7, 42);
+        assert_eq!(other, decoys);
+    }
+
+    #[test]
+    fn test_bloom_filter_fp_rate() {
+        use super::BloomFilter;
+
+        let mut filter = BloomFilter::new(10, 0.001, 0, 0);
+        assert_eq!(filter.fp_rate(), 0.0);
+
+        for i in 0..10u8 {
+            filter.insert(&[i; 20]);
+        }
+        let sized = filter.fp_rate();
+        assert!(sized > 0.0 && sized < 0.01, "{}", sized);
+
+        for i in 10..100u8 {
+            filter.insert(&[i; 20]);
+        }
+        assert!(filter.fp_rate() > sized * 10.0);
+        assert_eq!(BloomFilter::default().fp_rate(), 0.0);
+    }
+
     #[test]
     fn test_bloom2() {
         use super::Bloom;
@@ -158,20 +631,20 @@ mod test {
             vec_h.push(h);
         }
 
-        bloom.set(&mut vec_a);
-        bloom.set(&mut vec_b);
-        bloom.set(&mut vec_c);
-        bloom.set(&mut vec_d);
+        bloom.insert(&vec_a);
+        bloom.insert(&vec_b);
+        bloom.insert(&vec_c);
+        bloom.insert(&vec_d);
 
-        assert!(bloom.check(&mut vec_a));
-        assert!(bloom.check(&mut vec_b));
-        assert!(bloom.check(&mut vec_c));
-        assert!(bloom.check(&mut vec_d));
+        assert!(bloom.contains(&vec_a));
+        assert!(bloom.contains(&vec_b));
+        assert!(bloom.contains(&vec_c));
+        assert!(bloom.contains(&vec_d));
 
         //probalistic, so can fail 0.01
-        assert!(!bloom.check(&mut vec_e));
-        assert!(!bloom.check(&mut vec_f));
-        assert!(!bloom.check(&mut vec_g));
-        assert!(!bloom.check(&mut vec_h));
+        assert!(!bloom.contains(&vec_e));
+        assert!(!bloom.contains(&vec_f));
+        assert!(!bloom.contains(&vec_g));
+        assert!(!bloom.contains(&vec_h));
     }
 }
@@ -0,0 +1,647 @@
+// SPDX-License-Identifier: CC0-1.0
+
+//! Bitcoin Cash Schnorr signatures.
+//!
+//! Implementation of the Schnorr signature scheme accepted by `OP_CHECKSIG` and
+//! `OP_CHECKDATASIG` on Bitcoin Cash since the May 2019 upgrade. These are *not* the BIP340
+//! signatures used by taproot: public keys are regular 33-byte compressed keys, the nonce point
+//! must have a quadratic residue `y` coordinate, and the challenge is computed with a plain
+//! `SHA256` rather than a tagged hash.
+//!
+//! In transactions, a Schnorr signature is told apart from an ECDSA one by its length: 64 bytes,
+//! plus the sighash byte.
+
+use crate::prelude::*;
+use core::{fmt, iter};
+
+use secp256k1::scalar::Scalar;
+use secp256k1::{PublicKey, Secp256k1, SecretKey, Signing, Verification};
+
+use crate::hashes::hex::{self, FromHex};
+use crate::hashes::{sha256, Hash, HashEngine, Hmac, HmacEngine};
+use crate::internal_macros::write_err;
+
+/// Size of a serialized Schnorr signature, without the sighash byte.
+pub const SIGNATURE_SIZE: usize = 64;
+
+/// Additional data used to derive nonces with RFC6979, which sets them apart from ECDSA nonces
+/// for the same key and message.
+const NONCE_ALGORITHM: &[u8; 16] = b"Schnorr+SHA256  ";
+
+/// The secp256k1 generator point, compressed.
+const GENERATOR: [u8; 33] = [
+    0x02, 0x79, 0xbe, 0x66, 0x7e, 0xf9, 0xdc, 0xbb, 0xac, 0x55, 0xa0, 0x62, 0x95, 0xce, 0x87, 0x0b,
+    0x07, 0x02, 0x9b, 0xfc, 0xdb, 0x2d, 0xce, 0x28, 0xd9, 0x59, 0xf2, 0x81, 0x5b, 0x16, 0xf8, 0x17,
+    0x98,
+];
+
+/// The secp256k1 field prime, as little-endian 64-bit limbs.
+const FIELD_PRIME: [u64; 4] =
+    [0xffff_fffe_ffff_fc2f, 0xffff_ffff_ffff_ffff, 0xffff_ffff_ffff_ffff, 0xffff_ffff_ffff_ffff];
+
+/// `2^256` minus the field prime.
+const FIELD_PRIME_COMPLEMENT: u64 = 0x1_0000_03d1;
+
+/// The secp256k1 group order, as little-endian 64-bit limbs.
+const GROUP_ORDER: [u64; 4] =
+    [0xbfd2_5e8c_d036_4141, 0xbaae_dce6_af48_a03b, 0xffff_ffff_ffff_fffe, 0xffff_ffff_ffff_ffff];
+
+/// A Bitcoin Cash Schnorr signature, ie. the `r` and `s` values, 32 bytes each.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Signature([u8; SIGNATURE_SIZE]);
+
+impl Signature {
+    /// Deserializes a 64-byte signature.
+    pub fn from_slice(sl: &[u8]) -> Result<Self, Error> {
+        if sl.len() != SIGNATURE_SIZE {
+            return Err(Error::InvalidSignatureSize(sl.len()));
+        }
+        let mut sig = [0; SIGNATURE_SIZE];
+        sig.copy_from_slice(sl);
+
+        Ok(Signature(sig))
+    }
+
+    /// Serializes the signature.
+    pub fn serialize(&self) -> [u8; SIGNATURE_SIZE] {
+        self.0
+    }
+}
+
+impl fmt::Debug for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        hex::format_hex(&self.0, f)
+    }
+}
+
+impl core::str::FromStr for Signature {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Signature::from_slice(&Vec::from_hex(s)?)
+    }
+}
+
+/// A Schnorr signature with the corresponding sighash type, as found in a `scriptSig`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct CashSchnorrSig {
+    /// The underlying Schnorr signature.
+    pub sig: Signature,
+    /// The sighash type, including the fork id bit.
+    pub hash_ty: u8,
+}
+
+impl CashSchnorrSig {
+    /// Deserializes a 65-byte signature, the last byte being the sighash type.
+    pub fn from_slice(sl: &[u8]) -> Result<Self, Error> {
+        let (hash_ty, sig) = sl.split_last().ok_or(Error::InvalidSignatureSize(0))?;
+
+        Ok(CashSchnorrSig { sig: Signature::from_slice(sig)?, hash_ty: *hash_ty })
+    }
+
+    /// Serializes the signature followed by its sighash type.
+    pub fn to_vec(self) -> Vec<u8> {
+        self.sig.0.iter().copied().chain(iter::once(self.hash_ty)).collect()
+    }
+}
+
+impl fmt::Display for CashSchnorrSig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        hex::format_hex(&self.sig.0, f)?;
+        hex::format_hex(&[self.hash_ty], f)
+    }
+}
+
+/// A Schnorr signing error.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Hex decoding error.
+    HexEncoding(hex::Error),
+    /// Signature is not 64 bytes long.
+    InvalidSignatureSize(usize),
+    /// secp256k1-related error.
+    Secp256k1(secp256k1::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::HexEncoding(ref e) => write_err!(f, "Schnorr signature hex encoding error"; e),
+            Error::InvalidSignatureSize(sz) => {
+                write!(f, "Invalid Schnorr signature size: {}", sz)
+            }
+            Error::Secp256k1(ref e) => write_err!(f, "Schnorr signing error"; e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::HexEncoding(e) => Some(e),
+            Error::Secp256k1(e) => Some(e),
+            Error::InvalidSignatureSize(_) => None,
+        }
+    }
+}
+
+impl From<hex::Error> for Error {
+    fn from(e: hex::Error) -> Self {
+        Error::HexEncoding(e)
+    }
+}
+
+impl From<secp256k1::Error> for Error {
+    fn from(e: secp256k1::Error) -> Self {
+        Error::Secp256k1(e)
+    }
+}
+
+/// Signs a 32-byte message, usually a sighash.
+///
+/// The nonce is derived deterministically with RFC6979, using `Schnorr+SHA256  ` as additional
+/// data, like Bitcoin Cash nodes do. Signing the same message with the same key thus always
+/// yields the same signature, and the one other implementations yield.
+pub fn sign<C: Signing>(
+    secp: &Secp256k1<C>,
+    msg: &[u8; 32],
+    key: &SecretKey,
+) -> Result<Signature, Error> {
+    sign_with_nonce(secp, msg, key, rfc6979_nonce(key, msg, Some(NONCE_ALGORITHM)))
+}
+
+/// Signs a 32-byte message with the given nonce.
+fn sign_with_nonce<C: Signing>(
+    secp: &Secp256k1<C>,
+    msg: &[u8; 32],
+    key: &SecretKey,
+    nonce: SecretKey,
+) -> Result<Signature, Error> {
+    let pubkey = PublicKey::from_secret_key(secp, key);
+    let point = PublicKey::from_secret_key(secp, &nonce).serialize_uncompressed();
+    // The nonce point must have a quadratic residue `y` coordinate, which we get by negating the
+    // nonce if needed.
+    let nonce = if is_quadratic_residue(&point[33..]) { nonce } else { nonce.negate() };
+
+    let mut r = [0; 32];
+    r.copy_from_slice(&point[1..33]);
+
+    let e = challenge(&r, &pubkey, msg)?;
+    let s = nonce.add_tweak(&Scalar::from(key.mul_tweak(&e)?))?;
+
+    let mut sig = [0; SIGNATURE_SIZE];
+    sig[..32].copy_from_slice(&r);
+    sig[32..].copy_from_slice(&s.secret_bytes());
+
+    Ok(Signature(sig))
+}
+
+/// Verifies a signature of a 32-byte message against a public key.
+pub fn verify<C: Verification>(
+    secp: &Secp256k1<C>,
+    msg: &[u8; 32],
+    sig: &Signature,
+    pubkey: &PublicKey,
+) -> bool {
+    let mut r = [0; 32];
+    let mut s = [0; 32];
+    r.copy_from_slice(&sig.0[..32]);
+    s.copy_from_slice(&sig.0[32..]);
+
+    // `s` must be a valid non-zero scalar.
+    let s = match Scalar::from_be_bytes(s) {
+        Ok(s) if s != Scalar::ZERO => s,
+        _ => return false,
+    };
+    let e = match challenge(&r, pubkey, msg) {
+        Ok(e) => e,
+        Err(_) => return false,
+    };
+    // R = s*G - e*P.
+    let generator = PublicKey::from_slice(&GENERATOR).expect("generator is a valid point");
+    let point = match (generator.mul_tweak(secp, &s), pubkey.mul_tweak(secp, &e)) {
+        (Ok(sg), Ok(ep)) => match sg.combine(&ep.negate(secp)) {
+            Ok(point) => point.serialize_uncompressed(),
+            // The point at infinity.
+            Err(_) => return false,
+        },
+        _ => return false,
+    };
+    point[1..33] == r && is_quadratic_residue(&point[33..])
+}
+
+/// Derives a nonce from a key and message, as specified by RFC6979 with HMAC-SHA256, and the
+/// way libsecp256k1 does, ie. with the message used as-is, and the algorithm name, if any,
+/// appended to the key and message.
+fn rfc6979_nonce(key: &SecretKey, msg: &[u8; 32], algorithm: Option<&[u8; 16]>) -> SecretKey {
+    let hmac = |k: &[u8; 32], data: &[&[u8]]| {
+        let mut engine = HmacEngine::<sha256::Hash>::new(k);
+        for d in data {
+            engine.input(d);
+        }
+        Hmac::from_engine(engine).into_inner()
+    };
+    let mut seed = key.secret_bytes().to_vec();
+    seed.extend_from_slice(msg);
+    seed.extend_from_slice(algorithm.map_or(&[][..], |a| &a[..]));
+
+    let mut v = [0x01; 32];
+    let mut k = [0x00; 32];
+    k = hmac(&k, &[&v, &[0x00], &seed]);
+    v = hmac(&k, &[&v]);
+    k = hmac(&k, &[&v, &[0x01], &seed]);
+    v = hmac(&k, &[&v]);
+
+    loop {
+        v = hmac(&k, &[&v]);
+        // Candidates that aren't valid keys, ie. zero or not below the group order, are skipped.
+        if let Ok(nonce) = SecretKey::from_slice(&v) {
+            return nonce;
+        }
+        k = hmac(&k, &[&v, &[0x00]]);
+        v = hmac(&k, &[&v]);
+    }
+}
+
+/// Computes the challenge `SHA256(r || P || msg)`, reduced modulo the group order.
+fn challenge(r: &[u8; 32], pubkey: &PublicKey, msg: &[u8; 32]) -> Result<Scalar, Error> {
+    let mut engine = sha256::Hash::engine();
+    engine.input(r);
+    engine.input(&pubkey.serialize());
+    engine.input(msg);
+
+    let e = reduce_order(sha256::Hash::from_engine(engine).into_inner());
+    if e == [0; 32] {
+        return Err(Error::Secp256k1(secp256k1::Error::InvalidTweak));
+    }
+    Scalar::from_be_bytes(e).map_err(|_| Error::Secp256k1(secp256k1::Error::InvalidTweak))
+}
+
+/// Reduces a 256-bit big-endian integer modulo the group order. Since the order is close to
+/// `2^256`, a single subtraction is enough.
+fn reduce_order(bytes: [u8; 32]) -> [u8; 32] {
+    let n = from_be_bytes(&bytes);
+    if less_than(&n, &GROUP_ORDER) {
+        bytes
+    } else {
+        to_be_bytes(&sub(&n, &GROUP_ORDER))
+    }
+}
+
+/// Checks whether a field element, encoded as 32 big-endian bytes, is a quadratic residue
+/// modulo the field prime, ie. whether its Jacobi symbol is `1`.
+///
+/// By Euler's criterion, this is the case when `y^((p - 1) / 2) = 1 (mod p)`.
+fn is_quadratic_residue(y: &[u8]) -> bool {
+    let mut bytes = [0; 32];
+    bytes.copy_from_slice(y);
+
+    let y = from_be_bytes(&bytes);
+    let mut exp = FIELD_PRIME;
+    // (p - 1) / 2. The prime is odd, so shifting discards the `- 1`.
+    for i in 0..4 {
+        exp[i] = (exp[i] >> 1) | if i < 3 { exp[i + 1] << 63 } else { 0 };
+    }
+    let mut acc = [1, 0, 0, 0];
+    for bit in (0..256).rev() {
+        acc = mul_mod_p(&acc, &acc);
+        if (exp[bit / 64] >> (bit % 64)) & 1 == 1 {
+            acc = mul_mod_p(&acc, &y);
+        }
+    }
+    acc == [1, 0, 0, 0]
+}
+
+/// Multiplies two field elements modulo the field prime.
+fn mul_mod_p(a: &[u64; 4], b: &[u64; 4]) -> [u64; 4] {
+    let mut wide = [0u64; 8];
+    for i in 0..4 {
+        let mut carry = 0u128;
+        for j in 0..4 {
+            let x = wide[i + j] as u128 + a[i] as u128 * b[j] as u128 + carry;
+            wide[i + j] = x as u64;
+            carry = x >> 64;
+        }
+        wide[i + 4] = carry as u64;
+    }
+    // Since `2^256 = c (mod p)`, `hi * 2^256 + lo = hi * c + lo (mod p)`.
+    let c = FIELD_PRIME_COMPLEMENT as u128;
+    let mut narrow = [0u64; 4];
+    let mut carry = 0u128;
+    for i in 0..4 {
+        let x = wide[i] as u128 + wide[i + 4] as u128 * c + carry;
+        narrow[i] = x as u64;
+        carry = x >> 64;
+    }
+    // Fold the remaining high bits back in, twice at most.
+    while carry > 0 {
+        let mut x = carry * c;
+        for limb in narrow.iter_mut() {
+            let y = *limb as u128 + x;
+            *limb = y as u64;
+            x = y >> 64;
+            if x == 0 {
+                break;
+            }
+        }
+        carry = x;
+    }
+    if less_than(&narrow, &FIELD_PRIME) {
+        narrow
+    } else {
+        sub(&narrow, &FIELD_PRIME)
+    }
+}
+
+/// Whether `a < b`.
+fn less_than(a: &[u64; 4], b: &[u64; 4]) -> bool {
+    a.iter().rev().cmp(b.iter().rev()) == core::cmp::Ordering::Less
+}
+
+/// Computes `a - b`, assuming `a >= b`.
+fn sub(a: &[u64; 4], b: &[u64; 4]) -> [u64; 4] {
+    let mut out = [0; 4];
+    let mut borrow = false;
+    for i in 0..4 {
+        let (x, b1) = a[i].overflowing_sub(b[i]);
+        let (x, b2) = x.overflowing_sub(borrow as u64);
+        out[i] = x;
+        borrow = b1 || b2;
+    }
+    out
+}
+
+fn from_be_bytes(bytes: &[u8; 32]) -> [u64; 4] {
+    let mut limbs = [0; 4];
+    for (i, limb) in limbs.iter_mut().enumerate() {
+        let mut word = [0; 8];
+        word.copy_from_slice(&bytes[24 - 8 * i..32 - 8 * i]);
+        *limb = u64::from_be_bytes(word);
+    }
+    limbs
+}
+
+fn to_be_bytes(limbs: &[u64; 4]) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    for (i, limb) in limbs.iter().enumerate() {
+        bytes[24 - 8 * i..32 - 8 * i].copy_from_slice(&limb.to_be_bytes());
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashes::hex::ToHex;
+    use core::str::FromStr;
+    use secp256k1::Message;
+
+    /// Test vectors of the Bitcoin Cash Schnorr specification, ie. public key, message,
+    /// signature, whether it is valid, and why not.
+    const VECTORS: &[(&str, &str, &str, bool, &str)] = &[
+        (
+            "0279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "787A848E71043D280C50470E8E1532B2DD5D20EE912A45DBDD2BD1DFBF187EF6\
+             7031A98831859DC34DFFEEDDA86831842CCD0079E1F92AF177F7F22CC1DCED05",
+            true,
+            "",
+        ),
+        (
+            "02DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+            "2A298DACAE57395A15D0795DDBFD1DCB564DA82B0F269BC70A74F8220429BA1D\
+             1E51A22CCEC35599B8F266912281F8365FFC2D035A230434A1A64DC59F7013FD",
+            true,
+            "",
+        ),
+        (
+            "03FAC2114C2FBB091527EB7C64ECB11F8021CB45E8E7809D3C0938E4B8C0E5F84B",
+            "5E2D58D8B3BCDF1ABADEC7829054F90DDA9805AAB56C77333024B9D0A508B75C",
+            "00DA9B08172A9B6F0466A2DEFD817F2D7AB437E0D253CB5395A963866B3574BE\
+             00880371D01766935B92D2AB4CD5C8A2A5837EC57FED7660773A05F0DE142380",
+            true,
+            "",
+        ),
+        (
+            "03DEFDEA4CDB677750A420FEE807EACF21EB9898AE79B9768766E4FAA04A2D4A34",
+            "4DF3C3F68FCC83B27E9D42C90431A72499F17875C81A599B566C9889B9696703",
+            "00000000000000000000003B78CE563F89A0ED9414F5AA28AD0D96D6795F9C63\
+             02A8DC32E64E86A333F20EF56EAC9BA30B7246D6D25E22ADB8C6BE1AEB08D49D",
+            true,
+            "",
+        ),
+        (
+            "031B84C5567B126440995D3ED5AABA0565D71E1834604819FF9C17F5E9D5DD078F",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "52818579ACA59767E3291D91B76B637BEF062083284992F2D95F564CA6CB4E35\
+             30B1DA849C8E8304ADC0CFE870660334B3CFC18E825EF1DB34CFAE3DFC5D8187",
+            true,
+            "",
+        ),
+        (
+            "03EEFDEA4CDB677750A420FEE807EACF21EB9898AE79B9768766E4FAA04A2D4A34",
+            "4DF3C3F68FCC83B27E9D42C90431A72499F17875C81A599B566C9889B9696703",
+            "00000000000000000000003B78CE563F89A0ED9414F5AA28AD0D96D6795F9C63\
+             02A8DC32E64E86A333F20EF56EAC9BA30B7246D6D25E22ADB8C6BE1AEB08D49D",
+            false,
+            "public key not on the curve",
+        ),
+        (
+            "02DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+            "2A298DACAE57395A15D0795DDBFD1DCB564DA82B0F269BC70A74F8220429BA1D\
+             FA16AEE06609280A19B67A24E1977E4697712B5FD2943914ECD5F730901B4AB7",
+            false,
+            "R has a non-square y coordinate",
+        ),
+        (
+            "03FAC2114C2FBB091527EB7C64ECB11F8021CB45E8E7809D3C0938E4B8C0E5F84B",
+            "5E2D58D8B3BCDF1ABADEC7829054F90DDA9805AAB56C77333024B9D0A508B75C",
+            "00DA9B08172A9B6F0466A2DEFD817F2D7AB437E0D253CB5395A963866B3574BE\
+             D092F9D860F1776A1F7412AD8A1EB50DACCC222BC8C0E26B2056DF2F273EFDEC",
+            false,
+            "negated message hash",
+        ),
+        (
+            "0279BE667EF9DCBBAC55A06295CE870B07029BFCDB2DCE28D959F2815B16F81798",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "787A848E71043D280C50470E8E1532B2DD5D20EE912A45DBDD2BD1DFBF187EF6\
+             8FCE5677CE7A623CB20011225797CE7A8DE1DC6CCD4F754A47DA6C600E59543C",
+            false,
+            "negated s value",
+        ),
+        (
+            "03DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+            "2A298DACAE57395A15D0795DDBFD1DCB564DA82B0F269BC70A74F8220429BA1D\
+             1E51A22CCEC35599B8F266912281F8365FFC2D035A230434A1A64DC59F7013FD",
+            false,
+            "negated public key",
+        ),
+        (
+            "02DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+            "0000000000000000000000000000000000000000000000000000000000000000\
+             9E9D01AF988B5CEDCE47221BFA9B222721F3FA408915444A4B489021DB55775F",
+            false,
+            "sG - eP is infinite, with r = 0",
+        ),
+        (
+            "02DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+            "0000000000000000000000000000000000000000000000000000000000000001\
+             D37DDF0254351836D84B1BD6A795FD5D523048F298C4214D187FE4892947F728",
+            false,
+            "sG - eP is infinite, with r = 1",
+        ),
+        (
+            "02DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+            "4A298DACAE57395A15D0795DDBFD1DCB564DA82B0F269BC70A74F8220429BA1D\
+             1E51A22CCEC35599B8F266912281F8365FFC2D035A230434A1A64DC59F7013FD",
+            false,
+            "r is not the x coordinate of a point on the curve",
+        ),
+        (
+            "02DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+            "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEFFFFFC2F\
+             1E51A22CCEC35599B8F266912281F8365FFC2D035A230434A1A64DC59F7013FD",
+            false,
+            "r is equal to the field size",
+        ),
+        (
+            "02DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+            "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+            "2A298DACAE57395A15D0795DDBFD1DCB564DA82B0F269BC70A74F8220429BA1D\
+             FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFEBAAEDCE6AF48A03BBFD25E8CD0364141",
+            false,
+            "s is equal to the curve order",
+        ),
+    ];
+
+    /// Secret keys of the first vectors, which the specification gives signing vectors for.
+    const SECRET_KEYS: &[&str] = &[
+        "0000000000000000000000000000000000000000000000000000000000000001",
+        "B7E151628AED2A6ABF7158809CF4F3C762E7160F38B4DA56A784D9045190CFEF",
+        "C90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B14E5C7",
+    ];
+
+    #[test]
+    fn test_verify_vectors() {
+        let secp = Secp256k1::verification_only();
+
+        for (pubkey, msg, sig, valid, reason) in VECTORS {
+            let msg = <[u8; 32]>::from_hex(msg).unwrap();
+            let sig = Signature::from_str(sig).unwrap();
+            let verified = match PublicKey::from_slice(&Vec::from_hex(pubkey).unwrap()) {
+                Ok(pubkey) => verify(&secp, &msg, &sig, &pubkey),
+                Err(_) => false,
+            };
+            assert_eq!(verified, *valid, "{} {}", sig, reason);
+        }
+    }
+
+    #[test]
+    fn test_sign_vectors() {
+        let secp = Secp256k1::new();
+
+        // The specification's signatures were made with the nonce `SHA256(key || msg)`, rather
+        // than with RFC6979, which is only recommended.
+        for (key, (pubkey_hex, msg, sig, _, _)) in SECRET_KEYS.iter().zip(VECTORS) {
+            let key = SecretKey::from_str(key).unwrap();
+            let msg = <[u8; 32]>::from_hex(msg).unwrap();
+
+            let mut engine = sha256::Hash::engine();
+            engine.input(&key.secret_bytes());
+            engine.input(&msg);
+            let nonce = reduce_order(sha256::Hash::from_engine(engine).into_inner());
+            let nonce = SecretKey::from_slice(&nonce).unwrap();
+
+            let pubkey = PublicKey::from_secret_key(&secp, &key);
+            assert_eq!(pubkey.serialize().to_hex(), pubkey_hex.to_lowercase());
+            assert_eq!(
+                sign_with_nonce(&secp, &msg, &key, nonce).unwrap(),
+                Signature::from_str(sig).unwrap()
+            );
+            // Signatures with RFC6979 nonces differ, but are just as valid.
+            let sig = sign(&secp, &msg, &key).unwrap();
+            assert!(verify(&secp, &msg, &sig, &pubkey));
+        }
+    }
+
+    #[test]
+    fn test_rfc6979_nonce() {
+        let secp = Secp256k1::new();
+
+        for i in 1..16u8 {
+            let key = SecretKey::from_slice(&[i; 32]).unwrap();
+            let msg = sha256::Hash::hash(&[i]).into_inner();
+
+            // Without additional data, nonces are the ones libsecp256k1 signs ECDSA with,
+            // which is told by the signature's `r`, ie. the nonce point's `x` coordinate.
+            let nonce = rfc6979_nonce(&key, &msg, None);
+            let ecdsa = secp.sign_ecdsa(&Message::from_slice(&msg).unwrap(), &key);
+            assert_eq!(
+                ecdsa.serialize_compact()[..32],
+                PublicKey::from_secret_key(&secp, &nonce).serialize()[1..]
+            );
+            assert_ne!(rfc6979_nonce(&key, &msg, Some(NONCE_ALGORITHM)), nonce);
+        }
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let secp = Secp256k1::new();
+
+        for i in 1..16u8 {
+            let key = SecretKey::from_slice(&[i; 32]).unwrap();
+            let pubkey = PublicKey::from_secret_key(&secp, &key);
+            let msg = sha256::Hash::hash(&[i]).into_inner();
+            let sig = sign(&secp, &msg, &key).unwrap();
+
+            assert!(verify(&secp, &msg, &sig, &pubkey));
+            // Wrong message.
+            assert!(!verify(&secp, &[i; 32], &sig, &pubkey));
+            // Tampered signature.
+            let mut bytes = sig.serialize();
+            bytes[63] ^= 1;
+            assert!(!verify(&secp, &msg, &Signature(bytes), &pubkey));
+        }
+    }
+
+    #[test]
+    fn test_quadratic_residue() {
+        // 4 = 2^2 is a residue. Since p = 3 (mod 4), -1 is not, and neither is p - 4.
+        let mut four = [0; 32];
+        four[31] = 4;
+        assert!(is_quadratic_residue(&four));
+
+        let minus_one = to_be_bytes(&sub(&FIELD_PRIME, &[1, 0, 0, 0]));
+        assert!(!is_quadratic_residue(&minus_one));
+
+        let minus_four = to_be_bytes(&sub(&FIELD_PRIME, &[4, 0, 0, 0]));
+        assert!(!is_quadratic_residue(&minus_four));
+    }
+
+    #[test]
+    fn test_serialize_with_sighash() {
+        let sig = CashSchnorrSig { sig: Signature([7; SIGNATURE_SIZE]), hash_ty: 0x41 };
+        let bytes = sig.to_vec();
+
+        assert_eq!(bytes.len(), SIGNATURE_SIZE + 1);
+        assert_eq!(CashSchnorrSig::from_slice(&bytes).unwrap(), sig);
+        assert_eq!(sig.to_string(), format!("{}41", [7u8; SIGNATURE_SIZE].to_hex()));
+        assert!(CashSchnorrSig::from_slice(&bytes[1..]).is_err());
+    }
+}
@@ -13,6 +13,7 @@ use crate::blockdata::transaction::NonStandardSighashType;
 use secp256k1;
 use crate::EcdsaSighashType;
 use crate::internal_macros::write_err;
+use crate::util::sighash::SIGHASH_FORKID;
 
 /// An ECDSA signature with the corresponding hash type.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -23,6 +24,8 @@ pub struct EcdsaSig {
     pub sig: secp256k1::ecdsa::Signature,
     /// The corresponding hash type
     pub hash_ty: EcdsaSighashType,
+    /// Whether the `SIGHASH_FORKID` bit is set in the hash type, as required on Bitcoin Cash
+    pub fork_id: bool,
 }
 
 impl EcdsaSig {
@@ -30,19 +33,38 @@ impl EcdsaSig {
     pub fn sighash_all(sig: secp256k1::ecdsa::Signature) -> EcdsaSig {
         EcdsaSig {
             sig,
-            hash_ty: EcdsaSighashType::All
+            hash_ty: EcdsaSighashType::All,
+            fork_id: false,
         }
     }
 
-    /// Deserializes from slice following the standardness rules for [`EcdsaSighashType`].
+    /// Constructs an ECDSA Bitcoin Cash signature for `SIGHASH_ALL|SIGHASH_FORKID`.
+    pub fn sighash_all_forkid(sig: secp256k1::ecdsa::Signature) -> EcdsaSig {
+        EcdsaSig {
+            sig,
+            hash_ty: EcdsaSighashType::All,
+            fork_id: true,
+        }
+    }
+
+    /// Returns the sighash flag, including the `SIGHASH_FORKID` bit if set.
+    pub fn sighash_u32(&self) -> u32 {
+        if self.fork_id {
+            self.hash_ty.to_u32() | SIGHASH_FORKID
+        } else {
+            self.hash_ty.to_u32()
+        }
+    }
+
+    /// Deserializes from slice following the standardness rules for [`EcdsaSighashType`]. The
+    /// `SIGHASH_FORKID` bit may be set.
     pub fn from_slice(sl: &[u8]) -> Result<Self, EcdsaSigError> {
         let (hash_ty, sig) = sl.split_last()
             .ok_or(EcdsaSigError::EmptySignature)?;
-        let hash_ty = EcdsaSighashType::from_standard(*hash_ty as u32)
-            .map_err(|_| EcdsaSigError::NonStandardSighashType(*hash_ty as u32))?;
+        let (hash_ty, fork_id) = split_forkid(*hash_ty)?;
         let sig = secp256k1::ecdsa::Signature::from_der(sig)
             .map_err(EcdsaSigError::Secp256k1)?;
-        Ok(EcdsaSig { sig, hash_ty })
+        Ok(EcdsaSig { sig, hash_ty, fork_id })
     }
 
     /// Serializes an ECDSA signature (inner secp256k1 signature in DER format).
@@ -50,15 +72,24 @@ impl EcdsaSig {
         // TODO: add support to serialize to a writer to SerializedSig
         self.sig.serialize_der()
             .iter().copied()
-            .chain(iter::once(self.hash_ty as u8))
+            .chain(iter::once(self.sighash_u32() as u8))
             .collect()
     }
 }
 
+/// Splits a sighash byte into its standard hash type and whether the `SIGHASH_FORKID` bit is set.
+fn split_forkid(hash_ty: u8) -> Result<(EcdsaSighashType, bool), EcdsaSigError> {
+    let fork_id = hash_ty as u32 & SIGHASH_FORKID != 0;
+    let standard = EcdsaSighashType::from_standard(hash_ty as u32 & !SIGHASH_FORKID)
+        .map_err(|_| EcdsaSigError::NonStandardSighashType(hash_ty as u32))?;
+
+    Ok((standard, fork_id))
+}
+
 impl fmt::Display for EcdsaSig {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         hex::format_hex(&self.sig.serialize_der(), f)?;
-        hex::format_hex(&[self.hash_ty as u8], f)
+        hex::format_hex(&[self.sighash_u32() as u8], f)
     }
 }
 
@@ -69,9 +100,11 @@ impl FromStr for EcdsaSig {
         let bytes = Vec::from_hex(s)?;
         let (sighash_byte, signature) = bytes.split_last()
             .ok_or(EcdsaSigError::EmptySignature)?;
+        let (hash_ty, fork_id) = split_forkid(*sighash_byte)?;
         Ok(EcdsaSig {
             sig: secp256k1::ecdsa::Signature::from_der(signature)?,
-            hash_ty: EcdsaSighashType::from_standard(*sighash_byte as u32)?
+            hash_ty,
+            fork_id,
         })
     }
 }
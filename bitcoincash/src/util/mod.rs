@@ -12,8 +12,10 @@ pub mod base58;
 pub mod bip143;
 pub mod bip152;
 pub mod bip158;
+pub mod bip21;
 pub mod bip32;
 pub mod bloom;
+pub mod cash_schnorr;
 pub mod ecdsa;
 pub mod hash;
 pub mod key;
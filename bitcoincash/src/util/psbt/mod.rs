@@ -6,6 +6,10 @@
 //! defined at <https://github.com/bitcoin/bips/blob/master/bip-0174.mediawiki>
 //! except we define PSBTs containing non-standard sighash types as invalid.
 //!
+//! Bitcoin Cash has no witness data, but signatures commit to the value and CashToken
+//! data of the outputs being spent. These are carried in each input's `witness_utxo`,
+//! and token data is serialized as part of the output, as on the wire.
+//!
 
 use core::cmp;
 
@@ -119,6 +123,23 @@ impl PartiallySignedTransaction {
         Ok(psbt)
     }
 
+    /// Creates a PSBT from an unsigned transaction and the outputs it spends, in input order.
+    ///
+    /// # Errors
+    ///
+    /// If transactions is not unsigned, or if there isn't exactly one spent output per input.
+    pub fn from_unsigned_tx_and_utxos(tx: Transaction, utxos: Vec<TxOut>) -> Result<Self, Error> {
+        if utxos.len() != tx.input.len() {
+            return Err(Error::MissingUtxo);
+        }
+        let mut psbt = Self::from_unsigned_tx(tx)?;
+
+        for (input, utxo) in psbt.inputs.iter_mut().zip(utxos) {
+            input.witness_utxo = Some(utxo);
+        }
+        Ok(psbt)
+    }
+
     /// Extracts the `Transaction` from a PSBT by filling in the available signature information.
     pub fn extract_tx(self) -> Transaction {
         let mut tx: Transaction = self.unsigned_tx;
@@ -1142,4 +1163,84 @@ mod tests {
 
         assert_eq!(psbt1, psbt2);
     }
+
+    #[test]
+    fn serialize_and_deserialize_tokens() {
+        use crate::blockdata::token::{OutputData, Structure};
+        use crate::hash_types::TokenID;
+
+        let token = OutputData {
+            id: TokenID::from_hex(
+                "94d03dfec9efb0c7238dd12dab01f64159d8c8fb7ca4acb74043bbfe603b6d9d",
+            )
+            .unwrap(),
+            bitfield: Structure::HasAmount as u8
+                | Structure::HasNFT as u8
+                | Structure::HasCommitmentLength as u8,
+            amount: 42,
+            commitment: vec![0xca, 0xfe],
+        };
+        let script_pubkey = hex_script!("76a914299b5c3b5ee98da407db2093596e61cb3cd5a93188ac");
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: Txid::from_hex(
+                        "f61b1742ca13176464adb3cb66050c00787bb3a4eead37e985f2df1e37718126",
+                    )
+                    .unwrap(),
+                    vout: 0,
+                },
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+            }],
+            output: vec![TxOut {
+                value: 800,
+                script_pubkey: script_pubkey.clone(),
+                token: Some(token.clone()),
+            }],
+        };
+        let spent = TxOut { value: 1000, script_pubkey, token: Some(token) };
+
+        assert!(matches!(
+            PartiallySignedTransaction::from_unsigned_tx_and_utxos(tx.clone(), vec![]),
+            Err(Error::MissingUtxo)
+        ));
+        let psbt =
+            PartiallySignedTransaction::from_unsigned_tx_and_utxos(tx, vec![spent.clone()]).unwrap();
+        let decoded: PartiallySignedTransaction = deserialize(&serialize(&psbt)).unwrap();
+
+        assert_eq!(decoded, psbt);
+        assert_eq!(decoded.inputs[0].witness_utxo, Some(spent));
+        assert_eq!(decoded.unsigned_tx.output[0].token.as_ref().unwrap().amount, 42);
+        assert_eq!(decoded.iter_funding_utxos().count(), 1);
+
+        #[cfg(feature = "base64")]
+        {
+            let encoded = psbt.to_string();
+            assert_eq!(encoded.parse::<PartiallySignedTransaction>().unwrap(), psbt);
+        }
+    }
+
+    #[test]
+    fn serialize_and_deserialize_forkid_partial_sig() {
+        use super::serialize::{Deserialize, Serialize};
+        use crate::util::ecdsa::EcdsaSig;
+        use crate::EcdsaSighashType;
+
+        // Signature with `SIGHASH_ALL|SIGHASH_FORKID`, as returned by Bitcoin Cash signers.
+        let hex = "304402204f67e2afb76142d44fae58a2495d33a3419daa26cd0db8d04f3452b63289ac0f022010762a9fb67e94cc5cad9026f6dc99ff7f070f4278d30fbc7d0c869dd38c7fe741";
+        let sig = hex.parse::<EcdsaSig>().unwrap();
+
+        assert!(sig.fork_id);
+        assert_eq!(sig.hash_ty, EcdsaSighashType::All);
+        assert_eq!(sig.sighash_u32(), 0x41);
+        assert_eq!(sig.to_string(), hex);
+
+        let bytes = Vec::from_hex(hex).unwrap();
+        let decoded: EcdsaSig = Deserialize::deserialize(&bytes).unwrap();
+        assert_eq!(decoded, sig);
+        assert_eq!(Serialize::serialize(&decoded), bytes);
+    }
 }
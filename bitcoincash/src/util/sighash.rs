@@ -8,6 +8,7 @@
 //! and legacy (before Bip143).
 //!
 
+use crate::blockdata::token;
 use crate::blockdata::transaction::EncodeSigningDataResult;
 use crate::prelude::*;
 
@@ -25,6 +26,16 @@ use core::{fmt, str};
 
 use super::taproot::LeafVersion;
 
+/// Sighash flag required by Bitcoin Cash, selecting the BIP143-style signing algorithm.
+pub const SIGHASH_FORKID: u32 = 0x40;
+
+/// Sighash flag committing to the signed input only, rather than to all inputs.
+pub const SIGHASH_ANYONECANPAY: u32 = 0x80;
+
+/// Sighash flag committing to all the outputs spent by the transaction, including their token
+/// data. Can't be combined with `SIGHASH_ANYONECANPAY`.
+pub const SIGHASH_UTXOS: u32 = 0x20;
+
 /// Used for signature hash for invalid use of SIGHASH_SINGLE.
 pub(crate) const UINT256_ONE: [u8; 32] = [
     1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
@@ -46,6 +57,9 @@ pub struct SighashCache<T: Deref<Target = Transaction>> {
 
     /// Cache for taproot v1 inputs.
     taproot_cache: Option<TaprootCache>,
+
+    /// Hash of the spent outputs, for Bitcoin Cash `SIGHASH_UTXOS` signatures.
+    utxos_cache: Option<sha256d::Hash>,
 }
 
 /// Common values cached between segwit and taproot inputs.
@@ -199,6 +213,12 @@ pub enum Error {
 
     /// Invalid Sighash type.
     InvalidSighashType(u32),
+
+    /// A P2SH output is being signed for without its redeem script.
+    MissingRedeemScript,
+
+    /// An output script has an invalid token prefix.
+    InvalidTokenPrefix,
 }
 
 impl fmt::Display for Error {
@@ -212,6 +232,8 @@ impl fmt::Display for Error {
             Error::PrevoutKind => write!(f, "A single prevout has been provided but all prevouts are needed without `ANYONECANPAY`"),
             Error::WrongAnnex => write!(f, "Annex must be at least one byte long and the first bytes must be `0x50`"),
             Error::InvalidSighashType(hash_ty) => write!(f, "Invalid schnorr Signature hash type : {} ", hash_ty),
+            Error::MissingRedeemScript => write!(f, "A redeem script is required to sign for a P2SH output"),
+            Error::InvalidTokenPrefix => write!(f, "Output script has an invalid token prefix"),
         }
     }
 }
@@ -230,8 +252,120 @@ impl std::error::Error for Error {
             | PrevoutIndex
             | PrevoutKind
             | WrongAnnex
-            | InvalidSighashType(_) => None,
+            | InvalidSighashType(_)
+            | MissingRedeemScript
+            | InvalidTokenPrefix => None,
+        }
+    }
+}
+
+/// Hashtype of a Bitcoin Cash signature, encoded in the last byte of the signature.
+///
+/// This is a base type (`SIGHASH_ALL`, `SIGHASH_NONE` or `SIGHASH_SINGLE`) with the
+/// `SIGHASH_FORKID` bit set, optionally combined with either `SIGHASH_ANYONECANPAY` or
+/// `SIGHASH_UTXOS`.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct ForkIdSighashType(u8);
+
+impl ForkIdSighashType {
+    /// `SIGHASH_ALL|SIGHASH_FORKID`, committing to all inputs and outputs.
+    pub const ALL: Self = ForkIdSighashType(0x41);
+    /// `SIGHASH_NONE|SIGHASH_FORKID`, committing to all inputs and no outputs.
+    pub const NONE: Self = ForkIdSighashType(0x42);
+    /// `SIGHASH_SINGLE|SIGHASH_FORKID`, committing to all inputs and the output with the same
+    /// index as the signed input.
+    pub const SINGLE: Self = ForkIdSighashType(0x43);
+
+    /// Creates a fork id sighash type from a legacy sighash type, setting the fork id bit.
+    pub fn from_ecdsa(hash_ty: EcdsaSighashType) -> Self {
+        ForkIdSighashType(hash_ty as u8 | SIGHASH_FORKID as u8)
+    }
+
+    /// Creates a fork id sighash type from a raw `u32`.
+    ///
+    /// # Errors
+    ///
+    /// If the fork id bit isn't set, the base type is unknown, or unknown flags are set.
+    pub fn from_consensus(n: u32) -> Result<Self, Error> {
+        let flags = SIGHASH_FORKID | SIGHASH_ANYONECANPAY | SIGHASH_UTXOS;
+
+        if n & SIGHASH_FORKID == 0 || n & !(flags | 0x03) != 0 || n & 0x03 == 0 {
+            return Err(Error::InvalidSighashType(n));
+        }
+        if n & SIGHASH_ANYONECANPAY != 0 && n & SIGHASH_UTXOS != 0 {
+            return Err(Error::InvalidSighashType(n));
+        }
+        Ok(ForkIdSighashType(n as u8))
+    }
+
+    /// Sets the `SIGHASH_ANYONECANPAY` flag.
+    ///
+    /// # Errors
+    ///
+    /// If the `SIGHASH_UTXOS` flag is set.
+    pub fn with_anyone_can_pay(self) -> Result<Self, Error> {
+        Self::from_consensus(self.to_u32() | SIGHASH_ANYONECANPAY)
+    }
+
+    /// Sets the `SIGHASH_UTXOS` flag.
+    ///
+    /// # Errors
+    ///
+    /// If the `SIGHASH_ANYONECANPAY` flag is set.
+    pub fn with_utxos(self) -> Result<Self, Error> {
+        Self::from_consensus(self.to_u32() | SIGHASH_UTXOS)
+    }
+
+    /// Returns the base type, without flags.
+    pub fn base(self) -> EcdsaSighashType {
+        EcdsaSighashType::from_consensus(self.to_u32() & 0x1f)
+    }
+
+    /// Whether the signature only commits to the signed input.
+    pub fn anyone_can_pay(self) -> bool {
+        self.to_u32() & SIGHASH_ANYONECANPAY != 0
+    }
+
+    /// Whether the signature commits to all the spent outputs.
+    pub fn utxos(self) -> bool {
+        self.to_u32() & SIGHASH_UTXOS != 0
+    }
+
+    /// Converts the sighash type to a `u32` sighash flag.
+    pub fn to_u32(self) -> u32 {
+        self.0 as u32
+    }
+}
+
+impl fmt::Display for ForkIdSighashType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}|SIGHASH_FORKID", self.base())?;
+        if self.anyone_can_pay() {
+            write!(f, "|SIGHASH_ANYONECANPAY")?;
         }
+        if self.utxos() {
+            write!(f, "|SIGHASH_UTXOS")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the script code signed for when spending an output: the redeem script for P2SH
+/// outputs, and the output script otherwise.
+///
+/// Since token data is committed to separately, any token prefix is stripped from the output
+/// script.
+pub fn forkid_script_code(
+    script_pubkey: &Script,
+    redeem_script: Option<&Script>,
+) -> Result<Script, Error> {
+    let (script_pubkey, _) = token::unwrap_scriptpubkey(script_pubkey.clone())
+        .map_err(|_| Error::InvalidTokenPrefix)?;
+
+    if script_pubkey.is_p2sh() {
+        redeem_script.cloned().ok_or(Error::MissingRedeemScript)
+    } else {
+        Ok(script_pubkey)
     }
 }
 
@@ -357,7 +491,13 @@ impl<R: Deref<Target = Transaction>> SighashCache<R> {
     /// sighashes to be valid, no fields in the transaction may change except for script_sig and
     /// witness.
     pub fn new(tx: R) -> Self {
-        SighashCache { tx, common_cache: None, taproot_cache: None, segwit_cache: None }
+        SighashCache {
+            tx,
+            common_cache: None,
+            taproot_cache: None,
+            segwit_cache: None,
+            utxos_cache: None,
+        }
     }
 
     /// Encodes the BIP341 signing data for any flag type into a given object implementing a
@@ -618,6 +758,99 @@ impl<R: Deref<Target = Transaction>> SighashCache<R> {
         Ok(Sighash::from_engine(enc))
     }
 
+    /// Encodes the Bitcoin Cash `SIGHASH_FORKID` signing data for any flag type into a given
+    /// object implementing a [`std::io::Write`] trait.
+    ///
+    /// This is the BIP143 algorithm, with the spent output's token data, if any, committed to
+    /// right before the script code, and with the spent outputs committed to if
+    /// `SIGHASH_UTXOS` is set, in which case all prevouts must be provided. The script code
+    /// can be obtained with [`forkid_script_code`].
+    pub fn forkid_encode_signing_data_to<Write: io::Write, T: Borrow<TxOut>>(
+        &mut self,
+        mut writer: Write,
+        input_index: usize,
+        script_code: &Script,
+        prevouts: &Prevouts<T>,
+        sighash_type: ForkIdSighashType,
+    ) -> Result<(), Error> {
+        let zero_hash = sha256d::Hash::all_zeros();
+
+        let anyone_can_pay = sighash_type.anyone_can_pay();
+        let sighash = sighash_type.base();
+        let spent = prevouts.get(input_index)?;
+
+        self.tx.version.consensus_encode(&mut writer)?;
+
+        if !anyone_can_pay {
+            self.segwit_cache().prevouts.consensus_encode(&mut writer)?;
+        } else {
+            zero_hash.consensus_encode(&mut writer)?;
+        }
+
+        if sighash_type.utxos() {
+            prevouts.check_all(&self.tx)?;
+            self.utxos_cache(prevouts.get_all()?).consensus_encode(&mut writer)?;
+        }
+
+        if !anyone_can_pay
+            && sighash != EcdsaSighashType::Single
+            && sighash != EcdsaSighashType::None
+        {
+            self.segwit_cache().sequences.consensus_encode(&mut writer)?;
+        } else {
+            zero_hash.consensus_encode(&mut writer)?;
+        }
+
+        {
+            let txin = &self.tx.input.get(input_index).ok_or(Error::IndexOutOfInputsBounds {
+                index: input_index,
+                inputs_size: self.tx.input.len(),
+            })?;
+
+            txin.previous_output.consensus_encode(&mut writer)?;
+            if let Some(ref token) = spent.token {
+                token::PREFIX_BYTE.consensus_encode(&mut writer)?;
+                token.consensus_encode(&mut writer)?;
+            }
+            script_code.consensus_encode(&mut writer)?;
+            spent.value.consensus_encode(&mut writer)?;
+            txin.sequence.consensus_encode(&mut writer)?;
+        }
+
+        if sighash != EcdsaSighashType::Single && sighash != EcdsaSighashType::None {
+            self.segwit_cache().outputs.consensus_encode(&mut writer)?;
+        } else if sighash == EcdsaSighashType::Single && input_index < self.tx.output.len() {
+            let mut single_enc = Sighash::engine();
+            self.tx.output[input_index].consensus_encode(&mut single_enc)?;
+            Sighash::from_engine(single_enc).consensus_encode(&mut writer)?;
+        } else {
+            zero_hash.consensus_encode(&mut writer)?;
+        }
+
+        self.tx.lock_time.consensus_encode(&mut writer)?;
+        sighash_type.to_u32().consensus_encode(&mut writer)?;
+        Ok(())
+    }
+
+    /// Computes the Bitcoin Cash `SIGHASH_FORKID` sighash for any flag type.
+    pub fn forkid_signature_hash<T: Borrow<TxOut>>(
+        &mut self,
+        input_index: usize,
+        script_code: &Script,
+        prevouts: &Prevouts<T>,
+        sighash_type: ForkIdSighashType,
+    ) -> Result<Sighash, Error> {
+        let mut enc = Sighash::engine();
+        self.forkid_encode_signing_data_to(
+            &mut enc,
+            input_index,
+            script_code,
+            prevouts,
+            sighash_type,
+        )?;
+        Ok(Sighash::from_engine(enc))
+    }
+
     /// Encodes the legacy signing data for any flag type into a given object implementing a
     /// [`std::io::Write`] trait. Internally calls [`Transaction::encode_signing_data_to`].
     pub fn legacy_encode_signing_data_to<Write: io::Write, U: Into<u32>>(
@@ -720,6 +953,16 @@ impl<R: Deref<Target = Transaction>> SighashCache<R> {
             }
         })
     }
+
+    fn utxos_cache<T: Borrow<TxOut>>(&mut self, prevouts: &[T]) -> sha256d::Hash {
+        *self.utxos_cache.get_or_insert_with(|| {
+            let mut enc = sha256d::Hash::engine();
+            for prevout in prevouts {
+                prevout.borrow().consensus_encode(&mut enc).unwrap();
+            }
+            sha256d::Hash::from_engine(enc)
+        })
+    }
 }
 
 impl From<io::Error> for Error {
@@ -1134,6 +1377,169 @@ mod tests {
         serde_json::from_str(json_str).expect("JSON was not well-formatted")
     }
 
+    #[test]
+    fn forkid_sighash() {
+        use crate::blockdata::token::{OutputData, Structure};
+        use crate::TokenID;
+
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn::default(), TxIn::default()],
+            output: vec![TxOut { value: 1000, script_pubkey: Script::new(), token: None }],
+        };
+        let script_code = hex_script!("76a914000000000000000000000000000000000000000088ac");
+        let mut spent = vec![
+            TxOut { value: 4000, script_pubkey: script_code.clone(), token: None },
+            TxOut { value: 5000, script_pubkey: script_code.clone(), token: None },
+        ];
+        let mut cache = SighashCache::new(&tx);
+
+        // Without tokens, the preimage only differs from BIP143 by the sighash type.
+        let mut forkid = Vec::new();
+        let mut segwit = Vec::new();
+        cache
+            .forkid_encode_signing_data_to(
+                &mut forkid,
+                1,
+                &script_code,
+                &Prevouts::All(&spent),
+                ForkIdSighashType::ALL,
+            )
+            .unwrap();
+        cache
+            .segwit_encode_signing_data_to(
+                &mut segwit,
+                1,
+                &script_code,
+                spent[1].value,
+                EcdsaSighashType::All,
+            )
+            .unwrap();
+        assert_eq!(forkid[..forkid.len() - 4], segwit[..segwit.len() - 4]);
+        assert_eq!(forkid[forkid.len() - 4..], [0x41, 0, 0, 0]);
+        assert_eq!(
+            cache
+                .forkid_signature_hash(
+                    1,
+                    &script_code,
+                    &Prevouts::One(1, &spent[1]),
+                    ForkIdSighashType::ALL
+                )
+                .unwrap(),
+            Sighash::hash(&forkid)
+        );
+
+        // The spent output's token data is committed to before the script code.
+        spent[1].token = Some(OutputData {
+            id: TokenID::from_inner([1; 32]),
+            bitfield: Structure::HasAmount as u8,
+            amount: 10,
+            commitment: vec![],
+        });
+        let mut tokens = Vec::new();
+        cache
+            .forkid_encode_signing_data_to(
+                &mut tokens,
+                1,
+                &script_code,
+                &Prevouts::All(&spent),
+                ForkIdSighashType::ALL,
+            )
+            .unwrap();
+        // Version, prevouts, sequences and outpoint.
+        let offset = 4 + 32 + 32 + 36;
+        assert_eq!(tokens[offset], token::PREFIX_BYTE);
+        assert_eq!(&tokens[offset + 1..offset + 33], &[1; 32]);
+
+        assert_eq!(
+            cache.forkid_signature_hash(
+                2,
+                &script_code,
+                &Prevouts::All(&spent),
+                ForkIdSighashType::ALL
+            ),
+            Err(Error::PrevoutIndex)
+        );
+    }
+
+    #[test]
+    fn forkid_sighash_utxos() {
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: vec![TxIn::default(), TxIn::default()],
+            output: vec![],
+        };
+        let script_code = hex_script!("76a914000000000000000000000000000000000000000088ac");
+        let spent = vec![
+            TxOut { value: 4000, script_pubkey: script_code.clone(), token: None },
+            TxOut { value: 5000, script_pubkey: script_code.clone(), token: None },
+        ];
+        let utxos = ForkIdSighashType::ALL.with_utxos().unwrap();
+        let mut cache = SighashCache::new(&tx);
+
+        // All spent outputs are needed.
+        assert_eq!(
+            cache.forkid_signature_hash(0, &script_code, &Prevouts::One(0, &spent[0]), utxos),
+            Err(Error::PrevoutKind)
+        );
+        assert_eq!(
+            cache.forkid_signature_hash(0, &script_code, &Prevouts::All(&spent[..1]), utxos),
+            Err(Error::PrevoutsSize)
+        );
+
+        let mut preimage = Vec::new();
+        cache
+            .forkid_encode_signing_data_to(
+                &mut preimage,
+                0,
+                &script_code,
+                &Prevouts::All(&spent),
+                utxos,
+            )
+            .unwrap();
+        let mut enc = sha256d::Hash::engine();
+        spent[0].consensus_encode(&mut enc).unwrap();
+        spent[1].consensus_encode(&mut enc).unwrap();
+
+        // The spent outputs are committed to right after the prevouts.
+        assert_eq!(preimage[36..68], sha256d::Hash::from_engine(enc)[..]);
+        assert_eq!(preimage[preimage.len() - 4..], [0x61, 0, 0, 0]);
+    }
+
+    #[test]
+    fn forkid_sighash_type() {
+        assert_eq!(ForkIdSighashType::from_consensus(0x41), Ok(ForkIdSighashType::ALL));
+        assert_eq!(
+            ForkIdSighashType::from_ecdsa(EcdsaSighashType::SinglePlusAnyoneCanPay),
+            ForkIdSighashType::from_consensus(0xc3).unwrap()
+        );
+        // No fork id, no base type, unknown flags, or both ANYONECANPAY and UTXOS.
+        for n in [0x01, 0x40, 0x44, 0x51, 0x141, 0xe1] {
+            assert_eq!(ForkIdSighashType::from_consensus(n), Err(Error::InvalidSighashType(n)));
+        }
+        let acp = ForkIdSighashType::NONE.with_anyone_can_pay().unwrap();
+        assert!(acp.anyone_can_pay() && !acp.utxos());
+        assert_eq!(acp.base(), EcdsaSighashType::None);
+        assert!(acp.with_utxos().is_err());
+        assert_eq!(
+            ForkIdSighashType::ALL.with_utxos().unwrap().to_string(),
+            "SIGHASH_ALL|SIGHASH_FORKID|SIGHASH_UTXOS"
+        );
+    }
+
+    #[test]
+    fn forkid_script_code_p2sh() {
+        let p2pkh = hex_script!("76a914000000000000000000000000000000000000000088ac");
+        let p2sh = hex_script!("a914000000000000000000000000000000000000000087");
+        let redeem = hex_script!("51");
+
+        assert_eq!(forkid_script_code(&p2pkh, None), Ok(p2pkh.clone()));
+        assert_eq!(forkid_script_code(&p2sh, Some(&redeem)), Ok(redeem));
+        assert_eq!(forkid_script_code(&p2sh, None), Err(Error::MissingRedeemScript));
+    }
+
     #[test]
     fn sighashtype_fromstr_display() {
         let sighashtypes = vec![
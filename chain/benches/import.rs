@@ -0,0 +1,46 @@
+//! Header import benchmarks.
+use std::net;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+use nakamoto_chain::block::cache::BlockCache;
+use nakamoto_chain::block::store;
+use nakamoto_chain::block::BlockTree;
+use nakamoto_common::bitcoin;
+use nakamoto_common::bitcoin::blockdata::constants;
+use nakamoto_common::bitcoin::consensus::params::Params;
+use nakamoto_common::block::time::{AdjustedTime, LocalTime};
+use nakamoto_common::nonempty::NonEmpty;
+use nakamoto_test::block::gen;
+
+/// Number of headers imported.
+const HEADERS: u64 = 100_000;
+
+fn import_blocks(c: &mut Criterion) {
+    let network = bitcoin::Network::Regtest;
+    let genesis = constants::genesis_block(network).header;
+    let headers = gen::headers(genesis, HEADERS, &mut fastrand::Rng::with_seed(1));
+    let clock = AdjustedTime::<net::SocketAddr>::new(LocalTime::now());
+    let mut group = c.benchmark_group("import");
+
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(HEADERS));
+    group.bench_function("import_blocks", |b| {
+        b.iter_batched(
+            || {
+                let store = store::Memory::new(NonEmpty::new(genesis));
+                BlockCache::from(store, Params::new(network), &[]).unwrap()
+            },
+            |mut cache| {
+                cache
+                    .import_blocks(headers.tail.iter().cloned(), &clock)
+                    .unwrap()
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, import_blocks);
+criterion_main!(benches);
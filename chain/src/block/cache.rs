@@ -7,9 +7,10 @@
 #[cfg(test)]
 pub mod test;
 
-// use std::cmp::Ordering;
+use std::cmp::Ordering;
 use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::ops::ControlFlow;
+use std::thread;
 
 // use common::bitcoin::pow::{CompactTarget, Target};
 // use common::bitcoin_hashes::hex::ToHex;
@@ -23,19 +24,22 @@ use nakamoto_common::bitcoin::network::constants::Network;
 use nakamoto_common::bitcoin::util::BitArray;
 
 use nakamoto_common::bitcoin::util::uint::Uint256;
-use nakamoto_common::block::tree::{self, BlockReader, BlockTree, Branch, Error, ImportResult};
+use nakamoto_common::block::tree::{
+    self, AsertAnchor, BlockReader, BlockTree, Branch, Error, ImportResult,
+};
 use nakamoto_common::block::{
     self,
     iter::Iter,
     store::Store,
     time::{self, Clock},
-    // Bits,
-    BlockTime,
-    Height,
-    Work,
+    Bits, BlockTime, Height, Work,
 };
 use nakamoto_common::nonempty::NonEmpty;
 
+/// Number of headers imported at once, from which their proof-of-work is checked in parallel.
+/// Below this, spawning threads costs more than it saves.
+pub const PARALLEL_POW_THRESHOLD: usize = 256;
+
 /// A block that is being stored by the block cache.
 #[derive(Debug, Clone, Copy)]
 struct CachedBlock {
@@ -74,12 +78,20 @@ struct Candidate {
 
 /// An implementation of [`BlockTree`] using a generic storage backend.
 /// Most of the functionality is accessible via the trait.
+///
+/// If the store is pruned, the active chain starts at the pruning horizon instead of
+/// genesis, and blocks below it are not available.
 #[derive(Debug, Clone)]
 pub struct BlockCache<S: Store> {
     chain: NonEmpty<CachedBlock>,
+    genesis: BlockHeader,
+    /// Height of the first block of the active chain.
+    horizon: Height,
     headers: HashMap<BlockHash, Height>,
     orphans: HashMap<BlockHash, BlockHeader>,
     checkpoints: BTreeMap<Height, BlockHash>,
+    /// Block up to which headers are trusted without validation, if any.
+    assume_valid: Option<(Height, BlockHash)>,
     params: Params,
     /// Total cumulative work on the active chain.
     chainwork: Uint256,
@@ -94,32 +106,50 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
         checkpoints: &[(Height, BlockHash)],
     ) -> Result<Self, Error> {
         let genesis = store.genesis();
-        let length = store.len()?;
+        let horizon = store.horizon();
+        let length = store.len()? - horizon as usize;
         let orphans = HashMap::new();
         let checkpoints = checkpoints.iter().cloned().collect();
-        let chainwork = genesis.work();
+        let root = store.get(horizon)?;
+        // Nb. When the store is pruned, the work below the horizon is not accounted for.
+        let chainwork = root.work();
         let chain = NonEmpty::from((
             CachedBlock {
-                height: 0,
-                header: genesis,
+                height: horizon,
+                header: root,
             },
             Vec::with_capacity(length - 1),
         ));
         let mut headers = HashMap::with_capacity(length);
-        // Insert genesis in the headers map, but skip it during iteration.
-        headers.insert(chain.head.hash(), 0);
+        // Insert the root in the headers map, but skip it during iteration.
+        headers.insert(chain.head.hash(), horizon);
 
         Ok(Self {
             chain,
+            genesis,
+            horizon,
             headers,
             orphans,
             params,
             checkpoints,
+            assume_valid: None,
             chainwork,
             store,
         })
     }
 
+    /// Trust the headers leading up to the given block, instead of fully validating them.
+    /// Their timestamps and difficulty targets aren't checked: they only have to connect to
+    /// the active chain, have valid proof-of-work, match any checkpoints, and the header at
+    /// the given height has to match the given hash.
+    ///
+    /// Until that block is reached, peers could feed us a chain that doesn't lead to it, so
+    /// only a block known to be on the best chain should be given.
+    pub fn with_assume_valid(mut self, height: Height, hash: BlockHash) -> Self {
+        self.assume_valid = Some((height, hash));
+        self
+    }
+
     /// Create a new `BlockCache` from a `Store`, consensus parameters, and checkpoints,
     /// and load all the blocks from the store.
     pub fn from(
@@ -144,6 +174,9 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
         for result in self.store.iter().skip(1) {
             let (height, header) = result?;
 
+            if height <= self.horizon {
+                continue;
+            }
             self.chain.push(CachedBlock { height, header });
             self.chainwork = self.chainwork + header.work();
 
@@ -155,10 +188,10 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
         // Make sure that the store was properly configured. If we loaded a store that doesn't
         // match the provided genesis, we return an error here.
         if let Some(header) = self.chain.tail.first() {
-            let genesis = self.store.genesis().block_hash();
-            if genesis != header.prev_blockhash {
+            if self.chain.head.hash() != header.prev_blockhash {
                 return Err(Error::GenesisMismatch);
             }
+            let genesis = self.store.genesis().block_hash();
             if common::network::Network::from(self.params.network).genesis_hash() != genesis {
                 return Err(Error::GenesisMismatch);
             }
@@ -170,13 +203,21 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
         }
         self.headers.insert(self.chain.last().hash(), self.height());
 
-        let length = self.store.len()?;
+        let length = self.store.len()? - self.horizon as usize;
         assert_eq!(length, self.chain.len());
         assert_eq!(length, self.headers.len());
 
         Ok(self)
     }
 
+    /// Get a block of the active chain by height. Returns `None` if the height is
+    /// below the pruning horizon.
+    fn block(&self, height: Height) -> Option<&CachedBlock> {
+        height
+            .checked_sub(self.horizon)
+            .and_then(|ix| self.chain.get(ix as usize))
+    }
+
     /// Iterate over a range of blocks.
     ///
     /// # Errors
@@ -189,10 +230,12 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
             "BlockCache::range: range start must not be greater than range end"
         );
 
+        let start = range.start.max(self.horizon);
+
         self.chain
             .iter()
-            .skip(range.start as usize)
-            .take((range.end - range.start) as usize)
+            .skip((start - self.horizon) as usize)
+            .take(range.end.saturating_sub(start) as usize)
     }
 
     /// Get the median time past for the blocks leading up to the given height.
@@ -204,20 +247,12 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
     pub fn median_time_past(&self, height: Height) -> BlockTime {
         assert!(height != 0, "height must be > 0");
 
-        let mut times = [0; time::MEDIAN_TIME_SPAN as usize];
-
-        let start = height.saturating_sub(time::MEDIAN_TIME_SPAN);
-        let end = height;
+        let start = height
+            .saturating_sub(time::MEDIAN_TIME_SPAN)
+            .max(self.horizon);
+        let times = self.range(start..height).map(|blk| blk.time);
 
-        for (i, blk) in self.range(start..end).enumerate() {
-            times[i] = blk.time;
-        }
-
-        // Gracefully handle the case where `height` < `MEDIUM_TIME_SPAN`.
-        let available = &mut times[0..(end - start) as usize];
-
-        available.sort_unstable();
-        available[available.len() / 2]
+        median_time(times)
     }
 
     /// Import a block into the tree. Performs header validation. This function may trigger
@@ -226,6 +261,19 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
         &mut self,
         header: BlockHeader,
         clock: &impl Clock,
+    ) -> Result<ImportResult, Error> {
+        let pow = self::check_pow(&header, self.params.pow_limit);
+
+        self.import_checked_block(header, pow, clock)
+    }
+
+    /// Import a block whose proof-of-work was already checked, given the result of the check.
+    /// Otherwise, performs header validation like [`BlockCache::import_block`].
+    fn import_checked_block(
+        &mut self,
+        header: BlockHeader,
+        pow: Result<(), Error>,
+        clock: &impl Clock,
     ) -> Result<ImportResult, Error> {
         let hash = header.block_hash();
         let tip = self.chain.last();
@@ -238,32 +286,20 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
         // Block extends the active chain. We can fully validate it before proceeding.
         // Instead of adding the block to the main chain, we let chain selection do the job.
         if header.prev_blockhash == best {
-            self.validate(tip, &header, clock)?;
+            self.validate(
+                tip,
+                &header,
+                self.median_time_past(tip.height + 1),
+                |h| self.block(h).map(|b| b.header),
+                clock,
+            )?;
         }
 
         // Validate that the block's PoW is valid against its difficulty target, and
         // is greater than the minimum allowed for this network.
         //
         // We do this because it's cheap to verify and prevents flooding attacks.
-        let target = header.target();
-        match header.validate_pow(&target) {
-            Ok(_) => {
-                let limit = self.params.pow_limit;
-                if target > limit {
-                    return Err(Error::InvalidBlockTarget(target, limit));
-                }
-            }
-            Err(bitcoin::util::Error::BlockBadProofOfWork) => {
-                return Err(Error::InvalidBlockPoW);
-            }
-            Err(bitcoin::util::Error::BlockBadTarget) => unreachable! {
-                // The only way to get a 'bad target' error is to pass a different target
-                // than the one specified in the header.
-            },
-            Err(_) => unreachable! {
-                // We've handled all possible errors above.
-            },
-        }
+        pow?;
 
         if let Some(height) = self.headers.get(&header.prev_blockhash) {
             // Don't accept any forks from the main chain, prior to the last checkpoint.
@@ -430,9 +466,42 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
             height: candidate.fork_height,
             header: candidate.fork_header,
         };
+        // The median time past of a branch header depends on the branch blocks before it,
+        // so we can't look it up on the active chain. Instead, keep a window of the last
+        // timestamps, starting with the blocks leading up to and including the fork point.
+        let start = (candidate.fork_height + 1)
+            .saturating_sub(time::MEDIAN_TIME_SPAN)
+            .max(self.horizon);
+        let mut times = self
+            .range(start..candidate.fork_height + 1)
+            .map(|blk| blk.time)
+            .collect::<VecDeque<_>>();
+        // Likewise, the difficulty target of a branch header is computed from the branch
+        // blocks before it, and the active chain blocks up to the fork point.
+        let header_at = |height: Height| {
+            if height <= candidate.fork_height {
+                self.block(height).map(|b| b.header)
+            } else {
+                candidate
+                    .headers
+                    .get((height - candidate.fork_height - 1) as usize)
+                    .copied()
+            }
+        };
 
         for header in candidate.headers.iter() {
-            self.validate(&tip, header, clock)?;
+            self.validate(
+                &tip,
+                header,
+                median_time(times.iter().copied()),
+                header_at,
+                clock,
+            )?;
+
+            if times.len() == time::MEDIAN_TIME_SPAN as usize {
+                times.pop_front();
+            }
+            times.push_back(header.time);
 
             tip = CachedBlock {
                 height: tip.height + 1,
@@ -443,113 +512,91 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
     }
 
     /// Validate a block header as a potential new tip. This performs full header validation.
+    ///
+    /// The median time past is that of the blocks leading up to the header, which may
+    /// not be on the active chain, as are the headers looked up with `header_at`.
     fn validate(
         &self,
         tip: &CachedBlock,
         header: &BlockHeader,
+        median_time_past: BlockTime,
+        header_at: impl Fn(Height) -> Option<BlockHeader>,
         clock: &impl Clock,
     ) -> Result<(), Error> {
         assert_eq!(tip.hash(), header.prev_blockhash);
-        _ = clock;
-        // TODO MOVE THESE CONSTANTS later
-        // CHIP ASERT 16844
-        // MAIN ASERT 661647
-        // DAA MAIN 504031
-        // DAA CHIP 3000
-
-        // const ASSERTHEIGHT: u64 = 16844;
-        // const DAAHEIGHT: u64 = 3000;
-        // let compact_target = if self.params.allow_min_difficulty_blocks
-        //     && (tip.height + 1) % self.params.difficulty_adjustment_interval() != 0
-        // {
-        //     if header.time > tip.time + self.params.pow_target_spacing as BlockTime * 2 {
-        //         block::pow_limit_bits(&self.params.network)
-        //     } else {
-        //         if tip.height >= DAAHEIGHT {
-        //             self.next_cash_work_difficulty(tip.height, tip.time, &self.params)
-        //         } else {
-        //             self.next_min_difficulty_target(&self.params)
-        //         }
-        //     }
-        // } else {
-        //     if tip.height >= DAAHEIGHT {
-        //         self.next_cash_work_difficulty(tip.height, tip.time, &self.params)
-        //     } else if tip.height >= ASSERTHEIGHT {
-        //         self.next_asert_difficulty_target(
-        //             tip.height,
-        //             self.get_block_by_height(tip.height - 1).unwrap().time,
-        //             self.get_block_by_height(tip.height - 1).unwrap().target(),
-        //             &self.params,
-        //         )
-        //     } else {
-        //         self.next_difficulty_target(tip.height, tip.time, tip.target(), &self.params)
-        //     }
-        // };
-
-        // let target = BlockHeader::u256_from_compact_target(compact_target);
-        // match header.validate_pow(&target) {
-        //     Err(bitcoin::util::Error::BlockBadProofOfWork) => {
-        //         log::error!(target: "CACHE", "BlockBadProofOfWork TARGET {}", target);
-        //         return Err(Error::InvalidBlockPoW);
-        //     }
-        //     Err(bitcoin::util::Error::BlockBadTarget) => {
-        //         // log::error!(target: "CACHE", "InvalidBlockTarget: {:?} expeted {:?}", target,header.target());
-        //         return Err(Error::InvalidBlockTarget(header.target(), target));
-        //     }
-        //     Err(_) => unreachable!(),
-        //     Ok(_data) => {} //         let mut current = tip.height + (100 - tip.height % 100) % 100;
-        //                     //         while current <= tip.height {
-        //                     //             current += 100;
-        //                     //             log::debug!(target: "BLOCK", "POW VALID {} < {}\n{}", data,target,current);
-        //                     //         }
-        //                     //     }
-        // }
-
-        // // Validate against block checkpoints.
-        // let height = tip.height + 1;
-
-        // if let Some(checkpoint) = self.checkpoints.get(&height) {
-        //     let hash = header.block_hash();
-
-        //     if &hash != checkpoint {
-        //         return Err(Error::InvalidBlockHash(hash, height));
-        //     }
-        // }
-
-        // // A timestamp is accepted as valid if it is greater than the median timestamp of
-        // // the previous MEDIAN_TIME_SPAN blocks, and less than the network-adjusted
-        // // time + MAX_FUTURE_BLOCK_TIME.
-        // if header.time <= self.median_time_past(height) {
-        //     return Err(Error::InvalidBlockTime(header.time, Ordering::Less));
-        // }
-        // if header.time > clock.block_time() + time::MAX_FUTURE_BLOCK_TIME {
-        //     return Err(Error::InvalidBlockTime(header.time, Ordering::Greater));
-        // }
 
+        // A timestamp is accepted as valid if it is greater than the median timestamp of
+        // the previous MEDIAN_TIME_SPAN blocks, and less than the network-adjusted
+        // time + MAX_FUTURE_BLOCK_TIME.
+        if header.time <= median_time_past {
+            return Err(Error::InvalidBlockTime(header.time, Ordering::Less));
+        }
+        if header.time > clock.block_time() + time::MAX_FUTURE_BLOCK_TIME {
+            return Err(Error::InvalidBlockTime(header.time, Ordering::Greater));
+        }
+        // Validate the difficulty target. If the blocks it is computed from aren't available,
+        // eg. below the pruning horizon, only the proof-of-work is checked.
+        match self.next_target(tip, header.time, header_at) {
+            Ok(Some(bits)) if bits != header.bits.to_consensus() => {
+                return Err(Error::InvalidBlockTarget(
+                    header.target(),
+                    BlockHeader::u256_from_compact_target(bits),
+                ));
+            }
+            Ok(_) | Err(Error::BlockUnavailable(_) | Error::InsufficientHistory(_)) => {}
+            Err(err) => return Err(err),
+        }
+
+        // Validate against block checkpoints.
+        let height = tip.height + 1;
+
+        if let Some(checkpoint) = self.checkpoints.get(&height) {
+            let hash = header.block_hash();
+
+            if &hash != checkpoint {
+                return Err(Error::InvalidBlockHash(hash, height));
+            }
+        }
         Ok(())
     }
 
-    // /// Get the next minimum-difficulty target. Only valid in testnet and regtest networks.
-    // fn next_min_difficulty_target(&self, params: &Params) -> Bits {
-    //     assert!(params.allow_min_difficulty_blocks);
-
-    //     let pow_limit_bits = block::pow_limit_bits(&params.network);
+    /// Get the difficulty target of a block built on the given tip, at the given time.
+    /// The headers leading up to the tip are looked up with the given function, since the
+    /// tip may not be on the active chain.
+    ///
+    /// Blocks built on the ASERT anchor or above use ASERT, and the ones below use the cash
+    /// work algorithm from its activation. Returns `None` before then, since the emergency
+    /// difficulty adjustment that preceded it isn't implemented. These blocks are all below
+    /// the last checkpoint.
+    fn next_target(
+        &self,
+        tip: &CachedBlock,
+        time: BlockTime,
+        header_at: impl Fn(Height) -> Option<BlockHeader>,
+    ) -> Result<Option<Bits>, Error> {
+        let params = &self.params;
 
-    //     for (height, header) in self.iter().rev() {
-    //         if header.bits.to_consensus() != pow_limit_bits
-    //             || height % self.params.difficulty_adjustment_interval() == 0
-    //         {
-    //             return header.bits.to_consensus();
-    //         }
-    //     }
-    //     pow_limit_bits
-    // }
+        if params.no_pow_retargeting {
+            return Ok(Some(tip.bits.to_consensus()));
+        }
+        if let Some(anchor) = AsertAnchor::new(params.network) {
+            if tip.height >= anchor.height {
+                return tree::asert_difficulty(&tip.header, tip.height, time, params).map(Some);
+            }
+        }
+        if tip.height >= tree::cash_work_height(params.network) {
+            return tree::cash_work_difficulty(header_at, tip.height, time, params).map(Some);
+        }
+        Ok(None)
+    }
 
     /// Rollback active chain to the given height. Returns the list of rolled-back headers.
     fn rollback(&mut self, height: Height) -> Result<Vec<(Height, BlockHeader)>, Error> {
         let mut stale = Vec::new();
 
-        for (block, height) in self.chain.tail.drain(height as usize..).zip(height + 1..) {
+        let ix = (height - self.horizon) as usize;
+
+        for (block, height) in self.chain.tail.drain(ix..).zip(height + 1..) {
             stale.push((height, block.header));
 
             self.chainwork = self.chainwork - block.work();
@@ -577,6 +624,45 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
         Ok(stale)
     }
 
+    /// Extend the active chain with the leading headers that connect to it, up to the
+    /// assumed-valid block, skipping contextual validation, ie. timestamp and difficulty
+    /// checks. Returns the headers imported.
+    ///
+    /// Headers are only imported up to the first one whose proof-of-work check failed, or
+    /// that doesn't match a checkpoint, which is left for full validation to reject.
+    fn import_assumed_valid(
+        &mut self,
+        chain: &[BlockHeader],
+        pow: &[Result<(), Error>],
+    ) -> Result<Vec<(Height, BlockHeader)>, Error> {
+        let Some((assumed_height, assumed_hash)) = self.assume_valid else {
+            return Ok(Vec::new());
+        };
+        let mut tip = self.chain.last().hash();
+        let mut trusted = Vec::new();
+
+        for ((height, header), pow) in (self.height() + 1..=assumed_height).zip(chain).zip(pow) {
+            if header.prev_blockhash != tip || pow.is_err() {
+                break;
+            }
+            tip = header.block_hash();
+
+            if matches!(self.checkpoints.get(&height), Some(hash) if hash != &tip) {
+                break;
+            }
+            if height == assumed_height && tip != assumed_hash {
+                return Err(Error::InvalidBlockHash(tip, height));
+            }
+            trusted.push((height, *header));
+        }
+        for (height, header) in &trusted {
+            self.extend_chain(*height, header.block_hash(), *header);
+        }
+        self.store.put(trusted.iter().map(|(_, h)| *h))?;
+
+        Ok(trusted)
+    }
+
     /// Extend the active chain with a block.
     fn extend_chain(&mut self, height: Height, hash: BlockHash, header: BlockHeader) {
         assert_eq!(header.prev_blockhash, self.chain.last().hash());
@@ -589,7 +675,7 @@ impl<S: Store<Header = BlockHeader>> BlockCache<S> {
 
     /// Get the blocks starting from the given height.
     fn chain_suffix(&self, height: Height) -> &[CachedBlock] {
-        &self.chain.tail[height as usize..]
+        &self.chain.tail[(height - self.horizon) as usize..]
     }
 }
 
@@ -607,8 +693,34 @@ impl<S: Store<Header = BlockHeader>> BlockTree for BlockCache<S> {
         let mut best_hash = self.chain.last().hash();
         let mut best_header = self.chain.last().header;
 
-        for (i, header) in chain.enumerate() {
-            match self.import_block(header, context) {
+        let mut chain = chain.collect::<Vec<_>>();
+
+        // Proof-of-work doesn't depend on the other headers, so it can be checked for all
+        // headers upfront, while the contextual checks must be done in order.
+        let mut pow = if chain.len() >= PARALLEL_POW_THRESHOLD {
+            self::check_pow_parallel(&chain, self.params.pow_limit)
+        } else {
+            chain
+                .iter()
+                .map(|h| self::check_pow(h, self.params.pow_limit))
+                .collect()
+        };
+        let trusted = self
+            .import_assumed_valid(&chain, &pow)
+            .map_err(|err| Error::BlockImportAborted(err.into(), 0, self.height()))?;
+
+        if let Some((height, header)) = trusted.last() {
+            best_height = *height;
+            best_hash = header.block_hash();
+            best_header = *header;
+        }
+        let skipped = trusted.len();
+        chain.drain(..skipped);
+        pow.drain(..skipped);
+        connected.extend(trusted);
+
+        for (i, (header, pow)) in chain.into_iter().zip(pow).enumerate() {
+            match self.import_checked_block(header, pow, context) {
                 Ok(ImportResult::TipChanged {
                     header,
                     hash,
@@ -627,7 +739,13 @@ impl<S: Store<Header = BlockHeader>> BlockTree for BlockCache<S> {
                 Ok(ImportResult::TipUnchanged) => {}
                 Err(Error::DuplicateBlock(hash)) => log::trace!("Duplicate block {}", hash),
                 Err(Error::BlockMissing(hash)) => log::trace!("Missing block {}", hash),
-                Err(err) => return Err(Error::BlockImportAborted(err.into(), i, self.height())),
+                Err(err) => {
+                    return Err(Error::BlockImportAborted(
+                        err.into(),
+                        skipped + i,
+                        self.height(),
+                    ))
+                }
             }
         }
 
@@ -668,7 +786,13 @@ impl<S: Store<Header = BlockHeader>> BlockTree for BlockCache<S> {
         if header.prev_blockhash == tip.hash() {
             let height = tip.height + 1;
 
-            self.validate(tip, &header, clock)?;
+            self.validate(
+                tip,
+                &header,
+                self.median_time_past(height),
+                |h| self.block(h).map(|b| b.header),
+                clock,
+            )?;
             self.extend_chain(height, hash, header);
             self.store.put(std::iter::once(header))?;
 
@@ -683,6 +807,11 @@ impl<S: Store<Header = BlockHeader>> BlockTree for BlockCache<S> {
             Ok(ImportResult::TipUnchanged)
         }
     }
+
+    /// Write buffered headers out to the store, and sync it to disk.
+    fn flush(&mut self) -> Result<(), Error> {
+        self.store.sync().map_err(Error::from)
+    }
 }
 
 impl<S: Store<Header = BlockHeader>> BlockReader for BlockCache<S> {
@@ -690,13 +819,13 @@ impl<S: Store<Header = BlockHeader>> BlockReader for BlockCache<S> {
     fn get_block(&self, hash: &BlockHash) -> Option<(Height, &BlockHeader)> {
         self.headers
             .get(hash)
-            .and_then(|height| self.chain.get(*height as usize))
+            .and_then(|height| self.block(*height))
             .map(|blk| (blk.height, &blk.header))
     }
 
     /// Get a block by height.
     fn get_block_by_height(&self, height: Height) -> Option<&BlockHeader> {
-        self.chain.get(height as usize).map(|b| &b.header)
+        self.block(height).map(|b| &b.header)
     }
 
     /// Find a branch.
@@ -733,12 +862,14 @@ impl<S: Store<Header = BlockHeader>> BlockReader for BlockCache<S> {
 
     /// Get the genesis block header.
     fn genesis(&self) -> &BlockHeader {
-        &self.chain.first().header
+        &self.genesis
     }
 
     /// Iterate over the longest chain, starting from genesis.
     fn iter<'a>(&'a self) -> Box<dyn DoubleEndedIterator<Item = (Height, BlockHeader)> + 'a> {
-        Box::new(Iter::new(&self.chain).map(|(i, h)| (i, h.header)))
+        let horizon = self.horizon;
+
+        Box::new(Iter::new(&self.chain).map(move |(i, h)| (i + horizon, h.header)))
     }
 
     /// Iterate over a range of blocks.
@@ -746,13 +877,7 @@ impl<S: Store<Header = BlockHeader>> BlockReader for BlockCache<S> {
         &'a self,
         range: std::ops::Range<Height>,
     ) -> Box<dyn Iterator<Item = (Height, BlockHash)> + 'a> {
-        Box::new(
-            self.chain
-                .iter()
-                .map(|block| (block.height, block.hash()))
-                .skip(range.start as usize)
-                .take((range.end - range.start) as usize),
-        )
+        Box::new(BlockCache::range(self, range).map(|block| (block.height, block.hash())))
     }
 
     /// Return the height of the longest chain.
@@ -760,6 +885,11 @@ impl<S: Store<Header = BlockHeader>> BlockReader for BlockCache<S> {
         self.chain.last().height
     }
 
+    /// Return the height of the first block of the active chain.
+    fn horizon(&self) -> Height {
+        self.horizon
+    }
+
     /// Get the height of the last checkpoint block.
     fn last_checkpoint(&self) -> Height {
         let height = self.height();
@@ -820,6 +950,10 @@ impl<S: Store<Header = BlockHeader>> BlockReader for BlockCache<S> {
         };
 
         let start = start + 1;
+        if start <= self.horizon {
+            // We can't serve headers below the pruning horizon.
+            return vec![];
+        }
         let stop = self
             .get_block(&stop_hash)
             .map(|(h, _)| h)
@@ -850,10 +984,81 @@ impl<S: Store<Header = BlockHeader>> BlockReader for BlockCache<S> {
                 // older than our last checkpoint.
                 break;
             }
-            if let Some(blk) = self.chain.get(height as usize) {
+            if let Some(blk) = self.block(height) {
                 hashes.push(blk.hash());
+            } else if height < self.horizon {
+                // Blocks below the horizon were pruned, so we end with the oldest block we
+                // have.
+                let root = self.chain.first().hash();
+                if hashes.last() != Some(&root) {
+                    hashes.push(root);
+                }
+                break;
             }
         }
         hashes
     }
 }
+
+/// Get the median of the given block timestamps, ie. the median time past when given the
+/// timestamps of the blocks leading up to a height.
+///
+/// # Panics
+///
+/// Panics if no timestamps are given.
+fn median_time(times: impl Iterator<Item = BlockTime>) -> BlockTime {
+    let mut times = times.collect::<Vec<_>>();
+    assert!(!times.is_empty(), "at least one timestamp must be given");
+
+    times.sort_unstable();
+    times[times.len() / 2]
+}
+
+/// Check that a header's proof-of-work is valid against its difficulty target, and that the
+/// target is within the given limit.
+fn check_pow(header: &BlockHeader, limit: Uint256) -> Result<(), Error> {
+    let target = header.target();
+
+    match header.validate_pow(&target) {
+        Ok(_) => {
+            if target > limit {
+                return Err(Error::InvalidBlockTarget(target, limit));
+            }
+            Ok(())
+        }
+        Err(bitcoin::util::Error::BlockBadProofOfWork) => Err(Error::InvalidBlockPoW),
+        Err(bitcoin::util::Error::BlockBadTarget) => unreachable! {
+            // The only way to get a 'bad target' error is to pass a different target
+            // than the one specified in the header.
+        },
+        Err(_) => unreachable! {
+            // We've handled all possible errors above.
+        },
+    }
+}
+
+/// Check the proof-of-work of the given headers in parallel, one chunk of headers per
+/// available core. Results are returned in the order of the headers.
+fn check_pow_parallel(headers: &[BlockHeader], limit: Uint256) -> Vec<Result<(), Error>> {
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk = headers.len().div_ceil(threads).max(1);
+
+    thread::scope(|s| {
+        let workers = headers
+            .chunks(chunk)
+            .map(|chunk| {
+                s.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|h| self::check_pow(h, limit))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        workers
+            .into_iter()
+            .flat_map(|w| w.join().expect("proof-of-work checks don't panic"))
+            .collect()
+    })
+}
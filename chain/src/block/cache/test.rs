@@ -1,8 +1,8 @@
 use super::BlockCache;
 
 use nakamoto_common::bitcoin_hashes::Hash;
-use nakamoto_common::block::time::{AdjustedTime, Clock, LocalTime};
-use nakamoto_common::block::tree::{BlockReader, BlockTree, Error, ImportResult};
+use nakamoto_common::block::time::{self, AdjustedTime, Clock, LocalTime};
+use nakamoto_common::block::tree::{BlockReader, BlockTree, Error, ImportResult, CASH_WORK_WINDOW};
 use nakamoto_common::block::{BlockTime, Height, Target};
 use nakamoto_common::nonempty::NonEmpty;
 
@@ -12,6 +12,7 @@ use nakamoto_test::block::cache::model;
 
 use crate::block::store::{self, Store};
 
+use std::cmp::Ordering;
 use std::collections::{BTreeMap, VecDeque};
 use std::iter;
 use std::net;
@@ -557,6 +558,74 @@ fn test_from_store() {
     }
 }
 
+// Test that a pruned store only serves blocks above the pruning horizon.
+#[test]
+fn test_from_pruned_store() {
+    let network = bitcoin::Network::Bitcoin;
+    let genesis = constants::genesis_block(network).header;
+    let params = Params::new(network);
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("headers.db");
+
+    std::fs::copy(&*nakamoto_test::headers::PATH, &path).unwrap();
+
+    let full = BlockCache::from(
+        store::File::open(&path, genesis).unwrap(),
+        params.clone(),
+        &[],
+    )
+    .unwrap();
+
+    let mut store = store::File::open(&path, genesis).unwrap();
+    store.prune(500, 100).unwrap();
+
+    let cache = BlockCache::from(store, params, &[]).unwrap();
+
+    assert_eq!(cache.horizon(), 500);
+    assert_eq!(cache.genesis(), &genesis);
+    assert_eq!(cache.tip(), full.tip());
+    assert_eq!(cache.height(), full.height());
+    assert_eq!(cache.get_block_by_height(499), None);
+    assert_eq!(
+        cache.get_block_by_height(500),
+        full.get_block_by_height(500)
+    );
+    assert_eq!(
+        cache.iter().collect::<Vec<_>>(),
+        full.iter().skip(500).collect::<Vec<_>>()
+    );
+
+    let hash = full.get_block_by_height(400).unwrap().block_hash();
+    assert_eq!(cache.get_block(&hash), None);
+
+    let (hash, _) = cache.tip();
+    assert_eq!(cache.get_block(&hash).map(|(h, _)| h), Some(cache.height()));
+
+    // Headers below the horizon can't be served.
+    assert!(cache
+        .locate_headers(&[genesis.block_hash()], BlockHash::all_zeros(), 2000)
+        .is_empty());
+    assert_eq!(
+        cache.locate_headers(
+            &[cache.get_block_by_height(600).unwrap().block_hash()],
+            BlockHash::all_zeros(),
+            10
+        ),
+        full.locate_headers(
+            &[full.get_block_by_height(600).unwrap().block_hash()],
+            BlockHash::all_zeros(),
+            10
+        )
+    );
+    assert_eq!(
+        cache.locator_hashes(cache.height()).last(),
+        cache
+            .get_block_by_height(500)
+            .map(|h| h.block_hash())
+            .as_ref()
+    );
+}
+
 #[test]
 fn test_median_time_past() {
     let network = bitcoin::Network::Bitcoin;
@@ -575,6 +644,279 @@ fn test_median_time_past() {
     assert_eq!(cache.median_time_past(13), headers[7].time);
 }
 
+/// Create a header extending the given one, with the given timestamp.
+fn header_at(prev: &BlockHeader, time: BlockTime) -> BlockHeader {
+    let mut header = BlockHeader {
+        version: 1,
+        prev_blockhash: prev.block_hash(),
+        merkle_root: TxMerkleNode::all_zeros(),
+        bits: prev.bits,
+        time,
+        nonce: 0,
+    };
+    block::solve(&mut header);
+
+    header
+}
+
+#[test]
+fn test_cache_import_parallel_pow() {
+    let network = bitcoin::Network::Regtest;
+    let genesis = constants::genesis_block(network).header;
+    let store = store::Memory::new(NonEmpty::new(genesis));
+    let clock = AdjustedTime::<net::SocketAddr>::new(LOCAL_TIME);
+    let params = Params::new(network);
+    let mut cache = BlockCache::from(store, params, &[]).unwrap();
+
+    // Enough headers for their proof-of-work to be checked in parallel.
+    let mut headers = vec![genesis];
+    for _ in 0..super::PARALLEL_POW_THRESHOLD * 2 {
+        let prev = headers.last().unwrap();
+        headers.push(header_at(prev, prev.time + TARGET_SPACING));
+    }
+
+    // Break the proof-of-work of a header half-way through.
+    let invalid = super::PARALLEL_POW_THRESHOLD;
+    while headers[invalid]
+        .validate_pow(&headers[invalid].target())
+        .is_ok()
+    {
+        headers[invalid].nonce += 1;
+    }
+
+    // The headers before the invalid one are imported, as when checked one at a time.
+    assert_matches!(
+        cache.import_blocks(headers[1..].iter().cloned(), &clock),
+        Err(Error::BlockImportAborted(err, i, _))
+        if matches!(*err, Error::InvalidBlockPoW) && i == invalid - 1
+    );
+    assert_eq!(cache.height(), invalid as Height - 1);
+    assert_eq!(cache.tip().0, headers[invalid - 1].block_hash());
+}
+
+#[test]
+fn test_cache_import_cash_work_difficulty() {
+    let network = bitcoin::Network::Regtest;
+    let genesis = constants::genesis_block(network).header;
+    let store = store::Memory::new(NonEmpty::new(genesis));
+    let clock = AdjustedTime::<net::SocketAddr>::new(LOCAL_TIME);
+    let mut params = Params::new(network);
+    // Retarget with the cash work algorithm, which is active from genesis on this network.
+    params.no_pow_retargeting = false;
+
+    let mut cache = BlockCache::from(store, params, &[]).unwrap();
+
+    // Blocks at the minimum difficulty, on schedule. The difficulty doesn't change.
+    let mut active = vec![genesis];
+    for _ in 0..CASH_WORK_WINDOW + 3 {
+        let prev = active.last().unwrap();
+        active.push(header_at(prev, prev.time + TARGET_SPACING));
+    }
+    cache
+        .import_blocks(active.iter().skip(1).cloned(), &clock)
+        .unwrap();
+
+    let with_bits = |prev: &BlockHeader, bits: u32| {
+        let mut header = BlockHeader {
+            bits,
+            ..header_at(prev, prev.time + TARGET_SPACING)
+        };
+        block::solve(&mut header);
+        header
+    };
+    let tip = *active.last().unwrap();
+    let invalid = with_bits(&tip, genesis.bits - 1);
+
+    assert_matches!(
+        cache.import_block(invalid, &clock),
+        Err(Error::InvalidBlockTarget(actual, expected))
+        if actual == invalid.target() && expected == genesis.target()
+    );
+
+    // A branch header's target is computed from the branch headers before it.
+    let fork = active.len() - 2;
+    let mut branch = active[..=fork].to_vec();
+    for _ in 0..2 {
+        let prev = branch.last().unwrap();
+        branch.push(header_at(prev, prev.time + TARGET_SPACING));
+    }
+    let invalid = with_bits(branch.last().unwrap(), genesis.bits - 1);
+    branch.push(invalid);
+
+    cache
+        .import_blocks(branch[fork + 1..].iter().cloned(), &clock)
+        .unwrap();
+
+    assert!(!cache.contains(&invalid.block_hash()));
+    assert_eq!(cache.tip().0, branch[branch.len() - 2].block_hash());
+}
+
+#[test]
+fn test_cache_import_assume_valid() {
+    let network = bitcoin::Network::Regtest;
+    let genesis = constants::genesis_block(network).header;
+    let store = store::Memory::new(NonEmpty::new(genesis));
+    let clock = AdjustedTime::<net::SocketAddr>::new(LOCAL_TIME);
+    let params = Params::new(network);
+
+    // A chain with a header that has an invalid timestamp, below the assumed-valid block.
+    let mut headers = vec![genesis];
+    for height in 1..=10 {
+        let prev = headers.last().unwrap();
+        let time = if height == 3 {
+            genesis.time
+        } else {
+            prev.time + TARGET_SPACING
+        };
+        headers.push(header_at(prev, time));
+    }
+    let assumed = headers[5].block_hash();
+
+    let mut cache = BlockCache::from(store.clone(), params.clone(), &[])
+        .unwrap()
+        .with_assume_valid(5, assumed);
+    assert_matches!(
+        cache.import_blocks(headers[1..].iter().cloned(), &clock),
+        Ok(ImportResult::TipChanged { height: 10, connected, .. }) if connected.len() == 10
+    );
+
+    // Without it, the invalid header is rejected.
+    let mut cache = BlockCache::from(store.clone(), params.clone(), &[]).unwrap();
+    assert_matches!(
+        cache.import_blocks(headers[1..].iter().cloned(), &clock),
+        Err(Error::BlockImportAborted(_, 2, 2))
+    );
+
+    // The proof-of-work of headers below the assumed-valid block is still checked.
+    let mut invalid = headers.clone();
+    while invalid[2].validate_pow(&invalid[2].target()).is_ok() {
+        invalid[2].nonce += 1;
+    }
+    let mut cache = BlockCache::from(store.clone(), params.clone(), &[])
+        .unwrap()
+        .with_assume_valid(5, assumed);
+    assert_matches!(
+        cache.import_blocks(invalid[1..].iter().cloned(), &clock),
+        Err(Error::BlockImportAborted(err, 1, 1)) if matches!(*err, Error::InvalidBlockPoW)
+    );
+
+    // So are checkpoints.
+    let checkpoints = &[(2, headers[1].block_hash())];
+    let mut cache = BlockCache::from(store.clone(), params.clone(), checkpoints)
+        .unwrap()
+        .with_assume_valid(5, assumed);
+    assert_matches!(
+        cache.import_blocks(headers[1..].iter().cloned(), &clock),
+        Err(Error::BlockImportAborted(err, 1, 1))
+        if matches!(*err, Error::InvalidBlockHash(hash, 2) if hash == headers[2].block_hash())
+    );
+
+    // Headers leading to another block than the assumed-valid one are not imported.
+    let mut cache = BlockCache::from(store, params, &[])
+        .unwrap()
+        .with_assume_valid(5, headers[4].block_hash());
+    assert_matches!(
+        cache.import_blocks(headers[1..].iter().cloned(), &clock),
+        Err(Error::BlockImportAborted(err, 0, 0))
+        if matches!(*err, Error::InvalidBlockHash(hash, 5) if hash == assumed)
+    );
+    assert_eq!(cache.height(), 0);
+}
+
+#[test]
+fn test_invalid_block_time() {
+    let network = bitcoin::Network::Regtest;
+    let genesis = constants::genesis_block(network).header;
+    let store = store::Memory::new(NonEmpty::new(genesis));
+    let clock = AdjustedTime::<net::SocketAddr>::new(LOCAL_TIME);
+    let params = Params::new(network);
+
+    let mut cache = BlockCache::from(store, params, &[]).unwrap();
+    let mut tip = genesis;
+
+    for _ in 0..time::MEDIAN_TIME_SPAN {
+        tip = header_at(&tip, tip.time + TARGET_SPACING);
+        cache.import_block(tip, &clock).unwrap();
+    }
+    let mtp = cache.median_time_past(cache.height() + 1);
+    assert_eq!(mtp, genesis.time + TARGET_SPACING * 6);
+
+    // The timestamp must be strictly greater than the median time past.
+    assert_matches!(
+        cache.import_block(header_at(&tip, mtp), &clock),
+        Err(Error::InvalidBlockTime(t, Ordering::Less)) if t == mtp
+    );
+    // ... but may be earlier than the tip's timestamp.
+    let header = header_at(&tip, mtp + 1);
+    cache.clone().import_block(header, &clock).unwrap();
+
+    // The timestamp must not be too far in the future.
+    let limit = clock.block_time() + time::MAX_FUTURE_BLOCK_TIME;
+    assert_matches!(
+        cache.import_block(header_at(&tip, limit + 1), &clock),
+        Err(Error::InvalidBlockTime(t, Ordering::Greater)) if t == limit + 1
+    );
+    cache.import_block(header_at(&tip, limit), &clock).unwrap();
+    assert_eq!(cache.height(), time::MEDIAN_TIME_SPAN + 1);
+}
+
+/// A branch forking off an active chain is validated against its own median time past,
+/// which depends on the branch's timestamps, and not against the active chain's.
+#[quickcheck]
+fn prop_cache_import_branch_median_time_past(height: u8, fork: u8, valid: bool) -> bool {
+    let network = bitcoin::Network::Regtest;
+    let genesis = constants::genesis_block(network).header;
+    let store = store::Memory::new(NonEmpty::new(genesis));
+    let clock = AdjustedTime::<net::SocketAddr>::new(LOCAL_TIME);
+    let params = Params::new(network);
+    let mut cache = BlockCache::from(store, params, &[]).unwrap();
+
+    // Build the active chain.
+    let height = 1 + height as Height % (time::MEDIAN_TIME_SPAN * 2);
+    let mut active = vec![genesis];
+    for _ in 0..height {
+        let prev = active.last().unwrap();
+        active.push(header_at(prev, prev.time + TARGET_SPACING));
+    }
+    cache
+        .import_blocks(active.iter().skip(1).cloned(), &clock)
+        .unwrap();
+
+    // Build a branch that is only longer than the active chain with its last header. Its
+    // blocks are spaced further apart, so its median time past is ahead of the active
+    // chain's.
+    let fork = fork as Height % height;
+    let mut branch = active[..=fork as usize].to_vec();
+    for _ in fork..height {
+        let prev = branch.last().unwrap();
+        branch.push(header_at(prev, prev.time + TARGET_SPACING * 2));
+    }
+    let mut times = branch
+        .iter()
+        .rev()
+        .take(time::MEDIAN_TIME_SPAN as usize)
+        .map(|h| h.time)
+        .collect::<Vec<_>>();
+    times.sort_unstable();
+
+    // The last header's timestamp is right at the rule boundary.
+    let mtp = times[times.len() / 2];
+    let last = header_at(branch.last().unwrap(), mtp + valid as BlockTime);
+    branch.push(last);
+
+    cache
+        .import_blocks(branch[fork as usize + 1..].iter().cloned(), &clock)
+        .unwrap();
+
+    if valid {
+        cache.tip() == (last.block_hash(), last) && cache.height() == height + 1
+    } else {
+        // On this network, ties are broken by hash, so the tip may have switched to the
+        // branch, but not to its last header.
+        !cache.contains(&last.block_hash()) && cache.height() == height
+    }
+}
+
 #[quickcheck]
 fn prop_cache_import_ordered(input: arbitrary::OrderedHeaders) -> bool {
     let arbitrary::OrderedHeaders { headers } = input;
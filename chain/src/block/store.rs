@@ -5,5 +5,5 @@ pub use nakamoto_common::block::store::*;
 pub mod io;
 pub mod memory;
 
-pub use io::File;
+pub use io::{File, Flush};
 pub use memory::Memory;
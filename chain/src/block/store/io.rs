@@ -4,13 +4,139 @@ use std::fs;
 use std::io::{self, Read, Seek, Write};
 use std::iter;
 use std::mem;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time;
 
-use nakamoto_common::bitcoin::consensus::encode::{Decodable, Encodable};
+use nakamoto_common::bitcoin::consensus::encode::{self, Decodable, Encodable};
+use nakamoto_common::bitcoin_hashes::{sha256d, Hash, HashEngine};
 
 use nakamoto_common::block::store::{Error, Store};
 use nakamoto_common::block::Height;
 
+/// Suffix of the file holding the commitments to pruned headers.
+const PRUNED_SUFFIX: &str = ".pruned";
+/// Suffix of files being written during pruning.
+const TMP_SUFFIX: &str = ".tmp";
+
+/// Append a suffix to a file path.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+
+    PathBuf::from(name)
+}
+
+/// Remove a file, if it exists.
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// When headers written to a [`File`] store are flushed to disk.
+///
+/// Headers are buffered in memory until either limit is reached, and then written out and
+/// synced to disk together. Headers that weren't flushed yet are lost if the process
+/// crashes, and are fetched again from peers on the next start.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Flush {
+    /// Maximum number of headers to buffer. When `0`, headers are written out as soon as
+    /// they are stored, without syncing.
+    pub max_headers: usize,
+    /// Maximum time since the last flush, after which buffered headers are flushed when
+    /// more headers are stored.
+    pub interval: time::Duration,
+}
+
+impl Flush {
+    /// Write headers out as soon as they are stored.
+    pub const IMMEDIATE: Self = Self {
+        max_headers: 0,
+        interval: time::Duration::ZERO,
+    };
+}
+
+impl Default for Flush {
+    fn default() -> Self {
+        Self {
+            max_headers: 2000,
+            interval: time::Duration::from_secs(5),
+        }
+    }
+}
+
+/// A commitment to a range of pruned headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Commitment<H> {
+    /// Height of the last header in the range.
+    pub height: Height,
+    /// The last header in the range.
+    pub header: H,
+    /// Hash of all the headers in the range, ie. the headers after the previous commitment
+    /// and up to and including this one.
+    pub digest: sha256d::Hash,
+}
+
+impl<H: Encodable> Encodable for Commitment<H> {
+    fn consensus_encode<W: io::Write + ?Sized>(&self, e: &mut W) -> Result<usize, io::Error> {
+        let mut len = 0;
+        len += self.height.consensus_encode(e)?;
+        len += self.header.consensus_encode(e)?;
+        len += self.digest.consensus_encode(e)?;
+        Ok(len)
+    }
+}
+
+impl<H: Decodable> Decodable for Commitment<H> {
+    fn consensus_decode<D: io::Read + ?Sized>(d: &mut D) -> Result<Self, encode::Error> {
+        let height = Height::consensus_decode(d)?;
+        let header = H::consensus_decode(d)?;
+        let digest = sha256d::Hash::consensus_decode(d)?;
+
+        Ok(Commitment {
+            height,
+            header,
+            digest,
+        })
+    }
+}
+
+/// Read all commitments from the given path. Returns an empty list if the file
+/// doesn't exist.
+fn read_commitments<H: Decodable>(path: &Path) -> Result<Vec<Commitment<H>>, Error> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => return Err(err.into()),
+    };
+    let mut cursor = bytes.as_slice();
+    let mut commitments = Vec::new();
+
+    while !cursor.is_empty() {
+        commitments.push(Commitment::consensus_decode(&mut cursor)?);
+    }
+    Ok(commitments)
+}
+
+/// Finish or discard a pruning operation that was interrupted.
+///
+/// Pruning starts by creating the temporary commitments file, before the compacted headers
+/// are written, and is committed once that file is moved into place. If that didn't happen,
+/// the temporary files are discarded, otherwise the compacted headers are moved into place.
+fn recover(path: &Path) -> io::Result<()> {
+    let headers = with_suffix(path, TMP_SUFFIX);
+    let commitments = with_suffix(&with_suffix(path, PRUNED_SUFFIX), TMP_SUFFIX);
+
+    if commitments.exists() {
+        remove_if_exists(&commitments)?;
+        remove_if_exists(&headers)?;
+    } else if headers.exists() {
+        fs::rename(&headers, path)?;
+    }
+    Ok(())
+}
+
 /// Append a block to the end of the stream.
 fn put<H: Sized + Encodable, S: Seek + Write, I: Iterator<Item = H>>(
     mut stream: S,
@@ -96,10 +222,10 @@ pub struct Iter<H> {
 }
 
 impl<H: Decodable> Iter<H> {
-    fn new(file: fs::File) -> Self {
+    fn new(file: fs::File, height: Height) -> Self {
         Self {
             file: FileReader::new(file),
-            height: 1,
+            height,
         }
     }
 }
@@ -129,32 +255,206 @@ impl<H: Decodable> Iterator for Iter<H> {
 }
 
 /// A `Store` backed by a single file.
+///
+/// The store can be pruned, in which case the headers below the pruning horizon are
+/// removed from the file, and replaced with periodic [`Commitment`]s, kept in a separate
+/// file next to it.
+///
+/// Writes can be batched, see [`Flush`]. Buffered headers are visible to readers of the
+/// store, and are written out by [`Store::sync`], or when the store is dropped.
 #[derive(Debug)]
 pub struct File<H> {
     file: fs::File,
+    path: PathBuf,
     genesis: H,
+    commitments: Vec<Commitment<H>>,
+    flush: Flush,
+    /// Encoded headers that weren't written to the file yet.
+    pending: Vec<u8>,
+    /// Time of the last flush.
+    flushed: time::Instant,
 }
 
-impl<H> File<H> {
+impl<H: Decodable> File<H> {
     /// Open a new file store from the given path and genesis header.
     pub fn open<P: AsRef<Path>>(path: P, genesis: H) -> io::Result<Self> {
+        let path = path.as_ref();
+
+        recover(path)?;
+
+        let commitments = read_commitments(&with_suffix(path, PRUNED_SUFFIX))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
         fs::OpenOptions::new()
             .create(true)
             .read(true)
             .append(true)
             .open(path)
-            .map(|file| Self { file, genesis })
+            .map(|file| Self {
+                file,
+                path: path.to_owned(),
+                genesis,
+                commitments,
+                flush: Flush::IMMEDIATE,
+                pending: Vec::new(),
+                flushed: time::Instant::now(),
+            })
     }
 
     /// Create a new file store at the given path, with the provided genesis header.
     pub fn create<P: AsRef<Path>>(path: P, genesis: H) -> Result<Self, Error> {
+        let path = path.as_ref();
         let file = fs::OpenOptions::new()
             .create_new(true)
             .read(true)
             .append(true)
             .open(path)?;
 
-        Ok(Self { file, genesis })
+        // Any commitments left over from a previous store don't apply to this one.
+        remove_if_exists(&with_suffix(path, PRUNED_SUFFIX))?;
+
+        Ok(Self {
+            file,
+            path: path.to_owned(),
+            genesis,
+            commitments: vec![],
+            flush: Flush::IMMEDIATE,
+            pending: Vec::new(),
+            flushed: time::Instant::now(),
+        })
+    }
+
+    /// Batch header writes, flushing them according to the given policy.
+    pub fn with_flush(mut self, flush: Flush) -> Self {
+        self.flush = flush;
+        self
+    }
+
+    /// Write the buffered headers to the file, in a single write.
+    fn write_pending(&mut self) -> io::Result<()> {
+        if !self.pending.is_empty() {
+            self.file.write_all(&self.pending)?;
+            self.pending.clear();
+        }
+        Ok(())
+    }
+
+    /// Number of headers in the file, not counting buffered headers.
+    fn file_count(&self) -> Result<u64, Error> {
+        let len = self.file.metadata()?.len();
+        let size = mem::size_of::<H>() as u64;
+
+        if len % size != 0 {
+            return Err(Error::Corruption);
+        }
+        Ok(len / size)
+    }
+}
+
+impl<H> Drop for File<H> {
+    fn drop(&mut self) {
+        if !self.pending.is_empty() {
+            self.file.write_all(&self.pending).ok();
+        }
+    }
+}
+
+impl<H: 'static + Copy + Encodable + Decodable> File<H> {
+    /// Commitments to the pruned headers, in ascending height order.
+    pub fn commitments(&self) -> &[Commitment<H>] {
+        &self.commitments
+    }
+
+    /// Headers that weren't written to the file yet, with their heights.
+    fn buffered(&self) -> Result<Vec<(Height, H)>, Error> {
+        if self.pending.is_empty() {
+            return Ok(vec![]);
+        }
+        let start = self.horizon() + self.file_count()? + 1;
+
+        self.pending
+            .chunks_exact(mem::size_of::<H>())
+            .zip(start..)
+            .map(|(mut bytes, height)| Ok((height, H::consensus_decode(&mut bytes)?)))
+            .collect()
+    }
+
+    /// Prune the headers below the given horizon, keeping a commitment every `interval`
+    /// blocks, as well as one at the horizon. The header at the horizon remains available.
+    ///
+    /// Does nothing if the store is already pruned up to the given horizon.
+    ///
+    /// # Errors
+    ///
+    /// Panics if `interval` is `0`.
+    ///
+    pub fn prune(&mut self, horizon: Height, interval: Height) -> Result<(), Error> {
+        assert!(
+            interval > 0,
+            "File::prune: the interval must be greater than zero"
+        );
+
+        let current = self.horizon();
+        if horizon <= current {
+            return Ok(());
+        }
+        if horizon > self.height()? {
+            return Err(Error::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "pruning horizon is above the store height",
+            )));
+        }
+        self.write_pending()?;
+
+        let headers_tmp = with_suffix(&self.path, TMP_SUFFIX);
+        let commitments_path = with_suffix(&self.path, PRUNED_SUFFIX);
+        let commitments_tmp = with_suffix(&commitments_path, TMP_SUFFIX);
+
+        // The temporary commitments file marks the pruning as in progress, so it's created
+        // before any compacted header is written. See [`recover`].
+        let mut file = fs::File::create(&commitments_tmp)?;
+        if let Some(dir) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::File::open(dir)?.sync_all()?;
+        }
+        let mut commitments = self.commitments.clone();
+        let mut engine = sha256d::Hash::engine();
+        let mut tail = fs::File::create(&headers_tmp)?;
+
+        for result in Iter::new(self.file.try_clone()?, current + 1) {
+            let (height, header) = result?;
+
+            if height > horizon {
+                header.consensus_encode(&mut tail)?;
+                continue;
+            }
+            engine.input(&encode::serialize(&header));
+
+            if height % interval == 0 || height == horizon {
+                commitments.push(Commitment {
+                    height,
+                    header,
+                    digest: sha256d::Hash::from_engine(engine),
+                });
+                engine = sha256d::Hash::engine();
+            }
+        }
+        tail.sync_all()?;
+
+        for commitment in commitments.iter() {
+            commitment.consensus_encode(&mut file)?;
+        }
+        file.sync_all()?;
+        // Once the commitments are in place, the pruning is committed. See [`recover`].
+        fs::rename(&commitments_tmp, &commitments_path)?;
+        fs::rename(&headers_tmp, &self.path)?;
+
+        self.file = fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&self.path)?;
+        self.commitments = commitments;
+
+        Ok(())
     }
 }
 
@@ -166,48 +466,120 @@ impl<H: 'static + Copy + Encodable + Decodable> Store for File<H> {
         self.genesis
     }
 
-    /// Append a block to the end of the file.
+    /// Append a block to the end of the file. When writes are batched, the headers are
+    /// buffered until the next flush.
     fn put<I: Iterator<Item = Self::Header>>(&mut self, headers: I) -> Result<Height, Error> {
-        self::put(&mut self.file, headers)
+        if self.flush.max_headers == 0 {
+            return self::put(&mut self.file, headers).map(|height| height + self.horizon());
+        }
+        for header in headers {
+            header.consensus_encode(&mut self.pending)?;
+        }
+        let size = mem::size_of::<H>();
+        let buffered = self.pending.len() / size;
+
+        if buffered >= self.flush.max_headers || self.flushed.elapsed() >= self.flush.interval {
+            self.sync()?;
+        }
+        self.height()
     }
 
     /// Get the block at the given height. Returns `io::ErrorKind::UnexpectedEof` if
-    /// the height is not found.
+    /// the height is not found, and [`Error::Pruned`] if it is below the pruning horizon
+    /// and isn't a commitment.
     fn get(&self, height: Height) -> Result<H, Error> {
-        if let Some(ix) = height.checked_sub(1) {
-            // Clone so this function doesn't have to take a `&mut self`.
-            let mut file = self.file.try_clone()?;
-            get(&mut file, ix)
-        } else {
-            Ok(self.genesis)
+        let horizon = self.horizon();
+
+        if height == 0 {
+            return Ok(self.genesis);
         }
+        if height <= horizon {
+            return self
+                .commitments
+                .binary_search_by_key(&height, |c| c.height)
+                .map(|ix| self.commitments[ix].header)
+                .map_err(|_| Error::Pruned(height));
+        }
+        let ix = height - horizon - 1;
+
+        if !self.pending.is_empty() {
+            let count = self.file_count()?;
+
+            if ix >= count {
+                let size = mem::size_of::<H>();
+                let start = (ix - count) as usize * size;
+
+                return match self.pending.get(start..start + size) {
+                    Some(mut bytes) => H::consensus_decode(&mut bytes).map_err(Error::from),
+                    None => Err(Error::Io(io::ErrorKind::UnexpectedEof.into())),
+                };
+            }
+        }
+        // Clone so this function doesn't have to take a `&mut self`.
+        let mut file = self.file.try_clone()?;
+        get(&mut file, ix)
     }
 
     /// Rollback the chain to the given height. Behavior is undefined if the given
-    /// height is not contained in the store.
+    /// height is not contained in the store. Returns [`Error::Pruned`] if the height
+    /// is below the pruning horizon.
     fn rollback(&mut self, height: Height) -> Result<(), Error> {
         let size = mem::size_of::<H>();
+        let horizon = self.horizon();
 
-        self.file
-            .set_len((height) * size as u64)
-            .map_err(Error::from)
+        if height < horizon {
+            return Err(Error::Pruned(height));
+        }
+        let keep = height - horizon;
+
+        if !self.pending.is_empty() {
+            let count = self.file_count()?;
+
+            if keep >= count {
+                self.pending.truncate((keep - count) as usize * size);
+
+                return Ok(());
+            }
+            self.pending.clear();
+        }
+        self.file.set_len(keep * size as u64).map_err(Error::from)
     }
 
-    /// Flush changes to disk.
+    /// Write out buffered headers, and flush changes to disk.
     fn sync(&mut self) -> Result<(), Error> {
+        self.write_pending()?;
+        self.flushed = time::Instant::now();
         self.file.sync_data().map_err(Error::from)
     }
 
-    /// Iterate over all headers in the store.
+    /// Iterate over all headers in the store. When the store is pruned, the genesis is
+    /// followed by the header at the pruning horizon.
     fn iter(&self) -> Box<dyn Iterator<Item = Result<(Height, H), Error>>> {
+        let genesis = iter::once(Ok((0, self.genesis)));
+        let horizon = self
+            .commitments
+            .last()
+            .map(|c| Ok((c.height, c.header)))
+            .into_iter();
+
+        let pending = match self.buffered() {
+            Ok(headers) => headers.into_iter().map(Ok),
+            Err(err) => return Box::new(iter::once(Err(err))),
+        };
+
         // Clone so this function doesn't have to take a `&mut self`.
         match self.file.try_clone() {
-            Ok(file) => Box::new(iter::once(Ok((0, self.genesis))).chain(Iter::new(file))),
+            Ok(file) => Box::new(
+                genesis
+                    .chain(horizon)
+                    .chain(Iter::new(file, self.horizon() + 1))
+                    .chain(pending),
+            ),
             Err(err) => Box::new(iter::once(Err(Error::Io(err)))),
         }
     }
 
-    /// Return the number of headers in the store.
+    /// Return the number of headers in the store, including pruned headers.
     fn len(&self) -> Result<usize, Error> {
         let meta = self.file.metadata()?;
         let len = meta.len();
@@ -218,7 +590,9 @@ impl<H: 'static + Copy + Encodable + Decodable> Store for File<H> {
         if len as usize % size != 0 {
             return Err(Error::Corruption);
         }
-        Ok(len as usize / size + 1)
+        let pending = self.pending.len() / size;
+
+        Ok(len as usize / size + pending + self.horizon() as usize + 1)
     }
 
     /// Return the block height of the store.
@@ -226,6 +600,11 @@ impl<H: 'static + Copy + Encodable + Decodable> Store for File<H> {
         self.len().map(|n| n as Height - 1)
     }
 
+    /// Return the height of the last commitment, or `0` if the store isn't pruned.
+    fn horizon(&self) -> Height {
+        self.commitments.last().map(|c| c.height).unwrap_or(0)
+    }
+
     /// Check the file store integrity.
     fn check(&self) -> Result<(), Error> {
         self.len().map(|_| ())
@@ -250,29 +629,35 @@ impl<H: 'static + Copy + Encodable + Decodable> Store for File<H> {
 
 #[cfg(test)]
 mod test {
-    use std::{io, iter};
+    use std::{fs, io, iter, time};
 
     use nakamoto_common::bitcoin::TxMerkleNode;
     use nakamoto_common::bitcoin_hashes::Hash;
     use nakamoto_common::block::BlockHash;
 
-    use super::{Error, File, Height, Store};
+    use nakamoto_common::bitcoin::consensus::encode;
+    use nakamoto_common::bitcoin_hashes::sha256d;
+
+    use super::{with_suffix, Error, File, Flush, Height, Store, PRUNED_SUFFIX, TMP_SUFFIX};
     use crate::block::BlockHeader;
 
     const HEADER_SIZE: usize = 80;
 
-    fn store(path: &str) -> File<BlockHeader> {
-        let tmp = tempfile::tempdir().unwrap();
-        let genesis = BlockHeader {
+    fn genesis() -> BlockHeader {
+        BlockHeader {
             version: 1,
             prev_blockhash: BlockHash::all_zeros(),
             merkle_root: TxMerkleNode::all_zeros(),
             bits: 0x2ffffff,
             time: 39123818,
             nonce: 0,
-        };
+        }
+    }
 
-        File::open(tmp.path().join(path), genesis).unwrap()
+    fn store(path: &str) -> File<BlockHeader> {
+        let tmp = tempfile::tempdir().unwrap();
+
+        File::open(tmp.path().join(path), genesis()).unwrap()
     }
 
     #[test]
@@ -371,6 +756,60 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_put_batched() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("headers.db");
+        let flush = Flush {
+            max_headers: 8,
+            interval: time::Duration::from_secs(60),
+        };
+        let mut store = File::create(&path, genesis()).unwrap().with_flush(flush);
+
+        let header = BlockHeader {
+            version: 1,
+            prev_blockhash: store.genesis().block_hash(),
+            merkle_root: TxMerkleNode::all_zeros(),
+            bits: 0x2ffffff,
+            time: 1842918273,
+            nonce: 0,
+        };
+        let headers = (0..12)
+            .map(|i| BlockHeader { nonce: i, ..header })
+            .collect::<Vec<_>>();
+        let size = (HEADER_SIZE * headers.len()) as u64;
+
+        // Buffered headers are readable, but not written out yet.
+        assert_eq!(store.put(headers[..6].iter().cloned()).unwrap(), 6);
+        assert_eq!(store.len().unwrap(), 7);
+        assert_eq!(store.get(6).unwrap(), headers[5]);
+        assert!(store.get(7).is_err());
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+
+        // Reaching the limit flushes all buffered headers.
+        assert_eq!(store.put(headers[6..].iter().cloned()).unwrap(), 12);
+        assert_eq!(fs::metadata(&path).unwrap().len(), size);
+
+        // Rolling back works across the file and the buffer.
+        assert_eq!(store.put(iter::once(header)).unwrap(), 13);
+        store.rollback(12).unwrap();
+        assert!(store.get(13).is_err());
+        store.rollback(10).unwrap();
+        assert_eq!(store.height().unwrap(), 10);
+        store.put(headers[10..].iter().cloned()).unwrap();
+
+        let iter = store.iter().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(iter.len(), 13);
+        assert_eq!(iter.last(), Some(&(12, headers[11])));
+
+        // Buffered headers are written out when the store is dropped.
+        drop(store);
+
+        let store = File::open(&path, genesis()).unwrap();
+        assert_eq!(store.height().unwrap(), 12);
+        assert_eq!(store.get(12).unwrap(), headers[11]);
+    }
+
     #[test]
     fn test_iter() {
         let mut store = store("headers.db");
@@ -464,4 +903,118 @@ mod test {
             "the last (corrupted) header was removed"
         );
     }
+
+    #[test]
+    fn test_prune() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("headers.db");
+        let mut store = File::create(&path, genesis()).unwrap();
+
+        let header = BlockHeader {
+            version: 1,
+            prev_blockhash: store.genesis().block_hash(),
+            merkle_root: TxMerkleNode::all_zeros(),
+            bits: 0x2ffffff,
+            time: 1842918273,
+            nonce: 0,
+        };
+        let headers = (0..32)
+            .map(|i| BlockHeader { nonce: i, ..header })
+            .collect::<Vec<_>>();
+
+        store.put(headers.iter().cloned()).unwrap();
+        store.prune(20, 8).unwrap();
+
+        assert_eq!(store.horizon(), 20);
+        assert_eq!(store.len().unwrap(), headers.len() + 1);
+        assert_eq!(store.height().unwrap(), 32);
+        assert_eq!(
+            store
+                .commitments()
+                .iter()
+                .map(|c| c.height)
+                .collect::<Vec<_>>(),
+            vec![8, 16, 20]
+        );
+
+        // The digest commits to all headers since the previous commitment.
+        let encoded = headers[8..16]
+            .iter()
+            .flat_map(encode::serialize)
+            .collect::<Vec<_>>();
+        assert_eq!(store.commitments()[1].digest, sha256d::Hash::hash(&encoded));
+
+        assert_eq!(store.get(0).unwrap(), store.genesis);
+        assert_eq!(store.get(16).unwrap(), headers[15]);
+        assert_eq!(store.get(20).unwrap(), headers[19]);
+        assert_eq!(store.get(21).unwrap(), headers[20]);
+        assert_eq!(store.get(32).unwrap(), headers[31]);
+        assert!(matches!(store.get(9), Err(Error::Pruned(9))));
+        assert!(store.get(33).is_err());
+
+        let iter = store.iter().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(iter[0], (0, store.genesis));
+        assert_eq!(iter[1], (20, headers[19]));
+        assert_eq!(iter.len(), 2 + 12);
+        assert_eq!(iter.last(), Some(&(32, headers[31])));
+
+        // Rolling back below the horizon isn't possible.
+        assert!(matches!(store.rollback(19), Err(Error::Pruned(19))));
+
+        store.rollback(24).unwrap();
+        assert_eq!(store.height().unwrap(), 24);
+        assert_eq!(store.put(iter::once(header)).unwrap(), 25);
+        assert_eq!(store.get(25).unwrap(), header);
+
+        // Pruning below the current horizon has no effect.
+        store.prune(10, 8).unwrap();
+        assert_eq!(store.horizon(), 20);
+        drop(store);
+
+        // The pruned store can be re-opened.
+        let store = File::open(&path, genesis()).unwrap();
+
+        assert_eq!(store.horizon(), 20);
+        assert_eq!(store.height().unwrap(), 25);
+        assert_eq!(store.get(8).unwrap(), headers[7]);
+        assert_eq!(store.get(24).unwrap(), headers[23]);
+        assert_eq!(store.get(25).unwrap(), header);
+    }
+
+    #[test]
+    fn test_prune_interrupted() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("headers.db");
+        let mut store = File::create(&path, genesis()).unwrap();
+
+        let header = BlockHeader {
+            version: 1,
+            prev_blockhash: store.genesis().block_hash(),
+            merkle_root: TxMerkleNode::all_zeros(),
+            bits: 0x2ffffff,
+            time: 1842918273,
+            nonce: 0,
+        };
+        let headers = (0..32)
+            .map(|i| BlockHeader { nonce: i, ..header })
+            .collect::<Vec<_>>();
+
+        store.put(headers.iter().cloned()).unwrap();
+        drop(store);
+
+        // Interrupted while writing the compacted headers, before the pruning was committed.
+        let headers_tmp = with_suffix(&path, TMP_SUFFIX);
+        let commitments_tmp = with_suffix(&with_suffix(&path, PRUNED_SUFFIX), TMP_SUFFIX);
+
+        fs::write(&commitments_tmp, b"").unwrap();
+        fs::write(&headers_tmp, encode::serialize(&headers[20])).unwrap();
+
+        let store = File::open(&path, genesis()).unwrap();
+
+        assert_eq!(store.horizon(), 0);
+        assert_eq!(store.height().unwrap(), 32);
+        assert_eq!(store.get(9).unwrap(), headers[8]);
+        assert!(!headers_tmp.exists());
+        assert!(!commitments_tmp.exists());
+    }
 }
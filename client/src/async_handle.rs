@@ -0,0 +1,215 @@
+//! Async adapter over client handles.
+//!
+//! [`Handle`] methods block the calling thread until the client replies, which async
+//! applications shouldn't do on their runtime's worker threads. [`AsyncHandle`] wraps a
+//! handle, and turns these methods into futures: the blocking calls are moved to tokio's
+//! blocking thread pool, and their replies are awaited.
+//!
+//! ```no_run
+//! # async fn example<H: nakamoto_client::handle::Handle + 'static>(handle: H) {
+//! use nakamoto_client::async_handle::AsyncHandle;
+//!
+//! let handle = AsyncHandle::new(handle);
+//! let (height, _, _) = handle.get_tip().await.unwrap();
+//! # }
+//! ```
+//!
+//! Requires the `tokio` feature.
+use std::net;
+
+use nakamoto_common::bitcoin::network::constants::ServiceFlags;
+use nakamoto_common::bitcoin::network::message::NetworkMessage;
+use nakamoto_common::bitcoin::network::Address;
+use nakamoto_common::bitcoin::util::uint::Uint256;
+use nakamoto_common::bitcoin::{Script, Txid};
+use nakamoto_common::block::snapshot::Snapshot;
+use nakamoto_common::block::tree::ImportResult;
+use nakamoto_common::block::{self, BlockHash, BlockHeader, Height, Transaction};
+use nakamoto_common::nonempty::NonEmpty;
+use nakamoto_p2p::fsm::fees::FeeRate;
+use nakamoto_p2p::fsm::{Command, ImportSnapshotError, Link, Peer, PeerStats, RescanStatus};
+
+use crate::handle::{Error, Handle};
+
+/// A client handle with async methods.
+#[derive(Debug, Clone)]
+pub struct AsyncHandle<H> {
+    inner: H,
+}
+
+impl<H: Handle + 'static> AsyncHandle<H> {
+    /// Wrap a client handle.
+    pub fn new(inner: H) -> Self {
+        Self { inner }
+    }
+
+    /// The underlying handle, eg. to subscribe to events, which doesn't block.
+    pub fn inner(&self) -> &H {
+        &self.inner
+    }
+
+    /// Unwrap the underlying handle.
+    pub fn into_inner(self) -> H {
+        self.inner
+    }
+
+    /// Send a command to the client. This doesn't wait for the command to be processed.
+    pub fn command(&self, cmd: Command) -> Result<(), Error> {
+        self.inner.command(cmd)
+    }
+
+    /// See [`Handle::get_tip`].
+    pub async fn get_tip(&self) -> Result<(Height, BlockHeader, Uint256), Error> {
+        self.call(|h| h.get_tip()).await
+    }
+
+    /// See [`Handle::get_block_by_height`].
+    pub async fn get_block_by_height(&self, height: Height) -> Result<Option<BlockHeader>, Error> {
+        self.call(move |h| h.get_block_by_height(height)).await
+    }
+
+    /// See [`Handle::find_branch`].
+    pub async fn find_branch(
+        &self,
+        to: BlockHash,
+    ) -> Result<Option<(Height, NonEmpty<BlockHeader>)>, Error> {
+        self.call(move |h| h.find_branch(&to)).await
+    }
+
+    /// See [`Handle::get_rescan_status`].
+    pub async fn get_rescan_status(&self) -> Result<RescanStatus, Error> {
+        self.call(|h| h.get_rescan_status()).await
+    }
+
+    /// See [`Handle::list_watched`].
+    pub async fn list_watched(&self) -> Result<Vec<(Script, usize)>, Error> {
+        self.call(|h| h.list_watched()).await
+    }
+
+    /// See [`Handle::broadcast`].
+    pub async fn broadcast(
+        &self,
+        msg: NetworkMessage,
+        predicate: fn(Peer) -> bool,
+    ) -> Result<Vec<net::SocketAddr>, Error> {
+        self.call(move |h| h.broadcast(msg, predicate)).await
+    }
+
+    /// See [`Handle::connect`].
+    pub async fn connect(&self, addr: net::SocketAddr) -> Result<Link, Error> {
+        self.call(move |h| h.connect(addr)).await
+    }
+
+    /// See [`Handle::disconnect`].
+    pub async fn disconnect(&self, addr: net::SocketAddr) -> Result<(), Error> {
+        self.call(move |h| h.disconnect(addr)).await
+    }
+
+    /// See [`Handle::ban`].
+    pub async fn ban(&self, addr: net::SocketAddr) -> Result<(), Error> {
+        self.call(move |h| h.ban(addr)).await
+    }
+
+    /// See [`Handle::get_peers`].
+    pub async fn get_peers(&self, services: ServiceFlags) -> Result<Vec<Peer>, Error> {
+        self.call(move |h| h.get_peers(services)).await
+    }
+
+    /// See [`Handle::submit_transaction`].
+    pub async fn submit_transaction(
+        &self,
+        tx: Transaction,
+    ) -> Result<NonEmpty<net::SocketAddr>, Error> {
+        self.call(move |h| h.submit_transaction(tx)).await
+    }
+
+    /// See [`Handle::get_submitted_transaction`].
+    pub async fn get_submitted_transaction(
+        &self,
+        txid: Txid,
+    ) -> Result<Option<Transaction>, Error> {
+        self.call(move |h| h.get_submitted_transaction(&txid)).await
+    }
+
+    /// See [`Handle::estimate_fee`].
+    pub async fn estimate_fee(&self, blocks: usize) -> Result<Option<FeeRate>, Error> {
+        self.call(move |h| h.estimate_fee(blocks)).await
+    }
+
+    /// See [`Handle::import_headers`].
+    pub async fn import_headers(
+        &self,
+        headers: Vec<BlockHeader>,
+    ) -> Result<Result<ImportResult, block::tree::Error>, Error> {
+        self.call(move |h| h.import_headers(headers)).await
+    }
+
+    /// See [`Handle::import_snapshot`].
+    pub async fn import_snapshot(
+        &self,
+        snapshot: Snapshot,
+    ) -> Result<Result<Height, ImportSnapshotError>, Error> {
+        self.call(move |h| h.import_snapshot(snapshot)).await
+    }
+
+    /// See [`Handle::import_addresses`].
+    pub async fn import_addresses(&self, addrs: Vec<Address>) -> Result<(), Error> {
+        self.call(move |h| h.import_addresses(addrs)).await
+    }
+
+    /// See [`Handle::peer_stats`].
+    pub async fn peer_stats(&self) -> Result<Vec<PeerStats>, Error> {
+        self.call(|h| h.peer_stats()).await
+    }
+
+    /// See [`Handle::wait_for_peers`].
+    pub async fn wait_for_peers(
+        &self,
+        count: usize,
+        required_services: ServiceFlags,
+    ) -> Result<Vec<(net::SocketAddr, Height, ServiceFlags)>, Error> {
+        self.call(move |h| h.wait_for_peers(count, required_services))
+            .await
+    }
+
+    /// See [`Handle::wait_for_height`].
+    pub async fn wait_for_height(&self, height: Height) -> Result<BlockHash, Error> {
+        self.call(move |h| h.wait_for_height(height)).await
+    }
+
+    /// See [`Handle::shutdown`].
+    pub async fn shutdown(self) -> Result<(), Error> {
+        self.call(|h| h.clone().shutdown()).await
+    }
+
+    /// Call a blocking handle method on the blocking thread pool, and wait for its result.
+    async fn call<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&H) -> Result<T, Error> + Send + 'static,
+    {
+        let handle = self.inner.clone();
+
+        match tokio::task::spawn_blocking(move || f(&handle)).await {
+            Ok(result) => result,
+            Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+            // The runtime is shutting down.
+            Err(_) => Err(Error::Disconnected),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::mock;
+    use nakamoto_common::network::Network;
+
+    #[tokio::test]
+    async fn test_get_tip() {
+        let client = mock::Client::new(Network::Regtest);
+        let handle = AsyncHandle::new(client.handle());
+
+        assert_eq!(handle.get_tip().await.unwrap(), handle.inner().tip);
+    }
+}
@@ -2,18 +2,17 @@
 //! interface.
 use nakamoto_common::collections::HashMap;
 use nakamoto_p2p::PeerId;
-use std::env;
-use std::fs;
 use std::io;
 use std::net;
 use std::ops::ControlFlow;
 use std::ops::RangeInclusive;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::{self, SystemTime};
 
 pub use crossbeam_channel as chan;
 use nakamoto_chain::block::{store, Block};
-use nakamoto_common::bitcoin::util::bloom::BloomFilter;
+use nakamoto_common::bitcoin::util::bloom::{BloomFilter, Decoys};
 // use nakamoto_chain::bloom::store as bloom_store;
 use nakamoto_chain::filter;
 use nakamoto_chain::filter::cache::FilterCache;
@@ -21,33 +20,44 @@ use nakamoto_chain::filter::cache::StoredHeader;
 use nakamoto_chain::{block::cache::BlockCache, filter::BlockFilter};
 // use nakamoto_common::bloom::store:: cache::FilterCache as BloomFilterCache;
 
-use nakamoto_common::bitcoin::network::constants::ServiceFlags;
+pub use nakamoto_common::bitcoin::network::constants::ServiceFlags;
 use nakamoto_common::bitcoin::network::message::NetworkMessage;
+use nakamoto_common::bitcoin::network::message_bloom::BloomFlags;
 use nakamoto_common::bitcoin::network::Address;
 use nakamoto_common::bitcoin::util::uint::Uint256;
 use nakamoto_common::bitcoin::MerkleBlock;
-use nakamoto_common::bitcoin::Txid;
+use nakamoto_common::bitcoin::{Script, Txid};
+use nakamoto_common::block::snapshot::Snapshot;
 use nakamoto_common::block::store::{Genesis as _, Store as _};
 use nakamoto_common::block::time::{AdjustedTime, RefClock};
 use nakamoto_common::block::tree::{self, BlockReader, ImportResult};
 use nakamoto_common::block::{BlockHash, BlockHeader, Height, Transaction};
+use nakamoto_common::bloom::store::cache::{PrivacySegment, DEFAULT_FP_RATE};
 
 use nakamoto_common::nonempty::NonEmpty;
 use nakamoto_common::p2p::peer::{Source, Store as _};
 use nakamoto_p2p::fsm;
+use nakamoto_p2p::fsm::fees::FeeRate;
 
 pub use nakamoto_common::network;
 pub use nakamoto_common::network::Network;
+pub use nakamoto_common::p2p::netgroup::Asmap;
 pub use nakamoto_common::p2p::Domain;
 pub use nakamoto_net::event;
-pub use nakamoto_p2p::fsm::{Command, CommandError, Event, Hooks, Limits, Link, Peer};
+pub use nakamoto_p2p::fsm::event::Category as EventCategory;
+pub use nakamoto_p2p::fsm::{
+    BroadcastPolicy, Command, CommandError, Event, Hooks, Limits, Link, Peer, PeerStats,
+    RescanProgress, RescanStatus,
+};
 
+use crate::datadir::DataDir;
 pub use crate::error::Error;
 pub use crate::event::Loading;
 pub use crate::handle;
 pub use crate::service::Service;
 
 use crate::peer;
+use crate::segment;
 use nakamoto_net::{Reactor, Waker};
 
 /// Client configuration.
@@ -61,20 +71,65 @@ pub struct Config {
     pub connect: Vec<net::SocketAddr>,
     /// Client listen addresses.
     pub listen: Vec<net::SocketAddr>,
-    /// Client home path, where runtime data is stored, eg. block headers and filters.
+    /// SOCKS5 proxy to make all outbound peer connections through, eg. Tor. When set,
+    /// onion service peers are connected to as well.
+    pub proxy: Option<net::SocketAddr>,
+    /// Mapping of IP ranges to autonomous systems. When set, outbound peers are spread across
+    /// autonomous systems, instead of only across address ranges.
+    pub asmap: Option<Arc<Asmap>>,
+    /// Client data directory, where runtime data is stored, eg. block headers and filters.
+    /// Each network's data is stored in its own sub-directory.
     pub root: PathBuf,
     /// Verify on-disk data at load time.
     /// This can be set to `true` for additional checks, if for example data integrity
     /// of the file system is not guaranteed, or the file system is untrusted.
     pub verify: bool,
-    /// User agent string.
-    pub user_agent: &'static str,
+    /// Prune block headers below the last checkpoint at load time, keeping only periodic
+    /// commitments to them. Saves space on constrained devices, but pruned headers can no
+    /// longer be queried or served to peers.
+    pub prune_headers: bool,
+    /// Trust block headers up to the checkpoint at this height, or the last one below it,
+    /// instead of fully validating them: their timestamps and difficulty targets aren't
+    /// checked, only their proof-of-work and that they lead to the checkpoint. Speeds up the
+    /// initial sync on low-power devices.
+    pub assume_valid: Option<Height>,
+    /// When block headers are flushed to disk. Batching writes speeds up the initial
+    /// sync on slow storage.
+    pub header_flush: store::Flush,
+    /// User agent string, sent to peers. Wallets can set their own.
+    pub user_agent: String,
+    /// Whether to report our best block height to peers. If not, height zero is reported,
+    /// which makes the client harder to fingerprint.
+    pub report_height: bool,
+    /// Whether peers should announce transactions to us before a bloom filter is loaded.
+    pub relay: bool,
     /// Client hooks.
     pub hooks: Hooks,
     /// Services offered by this node.
     pub services: ServiceFlags,
+    /// Services required from outbound peers. Peers without them are disconnected, and
+    /// known addresses without them aren't connected to. Wallets relying only on BIP 37 can
+    /// require `BLOOM`.
+    pub required_services: ServiceFlags,
+    /// Services preferred from outbound peers. As many peers as possible are connected to
+    /// with these services, falling back to peers with only the required services until
+    /// enough are found.
+    pub preferred_services: ServiceFlags,
     /// Configured limits.
     pub limits: Limits,
+    /// Bloom filter privacy segments to load onto peers, keyed by segment id.
+    pub bloom_segments: HashMap<u32, PrivacySegment>,
+    /// False-positive rate of bloom filters rebuilt when the watch set changes.
+    pub bloom_fp_rate: f64,
+    /// Decoys inserted into bloom filters rebuilt when the watch set changes. More decoys
+    /// reveal less about the watched elements, but make filters larger.
+    pub bloom_decoys: Decoys,
+    /// How submitted transactions are broadcast. Announcing them to a few peers at first,
+    /// and to more only if they don't propagate, makes it harder to tell where they originate.
+    pub broadcast: BroadcastPolicy,
+    /// Block checkpoints to use instead of the network's built-in ones, if set.
+    #[cfg(feature = "checkpoint-override")]
+    pub checkpoints: Option<Vec<(Height, BlockHash)>>,
 }
 
 /// Configuration for loading event handling.
@@ -116,6 +171,25 @@ impl Config {
             ..Self::default()
         }
     }
+
+    /// Block checkpoints used to validate the header chain.
+    pub fn checkpoints(&self) -> Vec<(Height, BlockHash)> {
+        #[cfg(feature = "checkpoint-override")]
+        if let Some(checkpoints) = &self.checkpoints {
+            return checkpoints.clone();
+        }
+        self.network.checkpoints().collect()
+    }
+
+    /// Checkpoint up to which block headers are trusted, if any. See [`Config::assume_valid`].
+    pub fn assume_valid_checkpoint(&self) -> Option<(Height, BlockHash)> {
+        let height = self.assume_valid?;
+
+        self.checkpoints()
+            .into_iter()
+            .filter(|(h, _)| *h <= height)
+            .max_by_key(|(h, _)| *h)
+    }
 }
 
 impl Default for Config {
@@ -125,12 +199,27 @@ impl Default for Config {
             connect: Vec::new(),
             domains: Domain::all(),
             listen: vec![([0, 0, 0, 0], 0).into()],
-            root: PathBuf::from(env::var("HOME").unwrap_or_default()),
+            proxy: None,
+            asmap: None,
+            root: DataDir::platform().path().to_owned(),
             verify: false,
-            user_agent: fsm::USER_AGENT,
+            prune_headers: false,
+            assume_valid: None,
+            header_flush: store::Flush::default(),
+            user_agent: fsm::USER_AGENT.to_owned(),
+            report_height: true,
+            relay: false,
             hooks: Hooks::default(),
             limits: Limits::default(),
             services: ServiceFlags::NONE,
+            required_services: ServiceFlags::NETWORK,
+            preferred_services: ServiceFlags::NETWORK | ServiceFlags::BLOOM,
+            bloom_segments: HashMap::default(),
+            bloom_fp_rate: DEFAULT_FP_RATE,
+            bloom_decoys: Decoys::default(),
+            broadcast: BroadcastPolicy::default(),
+            #[cfg(feature = "checkpoint-override")]
+            checkpoints: None,
         }
     }
 }
@@ -266,26 +355,36 @@ impl<R: Reactor> Client<R> {
     /// Load the client configuration. Takes a loading handler that can optionally receive
     /// loading events.
     pub fn load(
-        self,
-        config: Config,
+        mut self,
+        mut config: Config,
         loading: impl Into<LoadingHandler>,
     ) -> Result<ClientRunner<R>, Error> {
         let loading = loading.into();
 
-        let home = config.root.join(".nakamoto-cash");
-
         let network = config.network;
-        let dir = home.join(network.as_str());
+        let dir = DataDir::new(&config.root).network(network)?;
         let listen = config.listen.clone();
-        fs::create_dir_all(&dir)?;
         let genesis = network.genesis();
 
         let params = network.params();
 
         log::info!(target: "client", "Initializing client ({:?})..", network);
         log::info!(target: "client", "Genesis block hash is {}", network.genesis_hash());
+        log::info!(target: "client", "Using data directory {:?}", dir.path());
+
+        if let Some(proxy) = config.proxy {
+            self.reactor.set_proxy(proxy);
+
+            // Onion services are reachable via the proxy.
+            if !config.domains.contains(&Domain::Onion) {
+                config.domains.push(Domain::Onion);
+            }
+        }
+        if let Some(asmap) = &config.asmap {
+            log::info!(target: "client", "Using AS map with {} range(s)", asmap.len());
+        }
 
-        let path = dir.join("headers.db");
+        let path = dir.headers();
         let store = match store::File::create(&path, genesis) {
             Ok(store) => {
                 log::info!(target: "client", "Initializing new block store {:?}", path);
@@ -305,81 +404,81 @@ impl<R: Reactor> Client<R> {
             }
             Err(err) => return Err(err.into()),
         };
+        let mut store = store.with_flush(config.header_flush);
 
         let local_time = SystemTime::now().into();
-        let checkpoints = network.checkpoints().collect::<Vec<_>>();
+        let checkpoints = config.checkpoints();
+
+        #[cfg(feature = "checkpoint-override")]
+        if config.checkpoints.is_some() {
+            log::warn!(
+                target: "client",
+                "Using {} overridden checkpoint(s) for {}",
+                checkpoints.len(),
+                network.as_str()
+            );
+        }
+
+        if config.prune_headers {
+            let height = store.height()?;
+            let horizon = checkpoints
+                .iter()
+                .rev()
+                .map(|(h, _)| *h)
+                .find(|h| *h <= height)
+                .unwrap_or(0);
+
+            if horizon > store.horizon() {
+                log::info!(target: "client", "Pruning block headers below height {}..", horizon);
+                store.prune(horizon, params.difficulty_adjustment_interval())?;
+            }
+        }
         let clock = AdjustedTime::<net::SocketAddr>::new(local_time);
         let rng = fastrand::Rng::new();
 
         log::info!(target: "client", "Loading block headers from store..");
 
-        let cache = BlockCache::new(store, params, &checkpoints)?
-            .load_with(|height| loading.send(Loading::BlockHeaderLoaded { height }))?;
-
-        // log::info!(target: "client", "Initializing bloom filters..");
-        /*
-        Temp, wallet client should load filters
-        */
-        // let mut script_hash = Vec::from_hex("347eeb9896b64a484d1019a16075c194a17e6081").unwrap();
-        // Vec::from_hex("64462479fb3bf5b307ab42123dea68d9ec6db353").unwrap();
-        // Vec::from_hex("7dcc5bd98ad7f437957c28d4d0312d91818d1d236531b5ae78e59e10b9610155").unwrap();
-        // Vec::from_hex("84487d5b5448dcb272921965eebb266728b25853").unwrap();
-
-        // let mut privacy_segment = PrivacySegment::default();
-        // let tweak = Rng::new().u32(u32::MIN..u32::MAX);
-        // privacy_segment.filter = BloomFilter::new(10, 0.0001, tweak, 0);
-        // let mut bf = BloomFilter::new(10000, 0.001, 987987, 0);
-        // bf.insert(&mut script_hash);
-        // privacy_segment.filter.insert(&mut script_hash);
-        // config.bloom_segments.insert(0, privacy_segment.clone());
-        // config.bloom_segments.insert(1, privacy_segment.clone());
-        // config.bloom_segments.insert(2, privacy_segment.clone());
-        // config.bloom_segments.insert(3, privacy_segment.clone());
-
-        // let bloomfilters_path = dir.join("bloomfilters.db");
-        // let bloomfilter_store = match nakamoto_common::bloom::store::File::create(
-        //     &bloomfilters_path,
-        //     privacy_segment.clone(),
-        // ) {
-        //     Ok(store) => {
-        //         log::info!(target: "client", "Initializing new bloom filter store {:?}", bloomfilters_path);
-        //         store
-        //     }
-        //     Err(nakamoto_common::bloom::store::Error::Io(e))
-        //         if e.kind() == io::ErrorKind::AlreadyExists =>
-        //     {
-        //         log::info!(target: "client", "Found existing bloomstore {:?}", bloomfilters_path);
-        //         let store = nakamoto_common::bloom::store::File::open(
-        //             bloomfilters_path,
-        //             privacy_segment.clone(),
-        //         )?;
-
-        //         if store.check().is_err() {
-        //             log::warn!(target: "client", "Corruption detected in bloom store, healing..");
-        //             //TODO FIX
-        //             // store.heal()?; // Rollback store to the last valid header.
-        //         }
-        //         // bloom_filters.
-        //         log::info!(target: "client", "Bloom filter segments found" );
-
-        //         // config.bloom_segments.insert(0, store.get(0).unwrap());
-        //         // config.bloom_segments.insert(1, store.get(0).unwrap());
-        //         store
-        //     }
-        //     Err(err) => {
-        //         return Err(error::Error::Io(std::io::Error::new(
-        //             io::ErrorKind::NotFound,
-        //             err,
-        //         )))
-        //     }
-        // };
-        // // let segments = bloomfilter_store.len();
-        // log::info!(target: "client", "Loading Bloom filter cache: segments {:?}",config.bloom_segments.len());
-        // log::info!(target: "client", "Loading Bloom filter Store {:?}",bloomfilter_store);
+        let mut cache = BlockCache::new(store, params, &checkpoints)?;
+
+        if let Some(height) = config.assume_valid {
+            if let Some((height, hash)) = config.assume_valid_checkpoint() {
+                log::info!(
+                    target: "client",
+                    "Assuming block headers up to checkpoint {} at height {} are valid",
+                    hash,
+                    height
+                );
+                cache = cache.with_assume_valid(height, hash);
+            } else {
+                log::warn!(
+                    target: "client",
+                    "No checkpoint at or below height {} to assume valid, validating all headers",
+                    height
+                );
+            }
+        }
+        let cache =
+            cache.load_with(|height| loading.send(Loading::BlockHeaderLoaded { height }))?;
+
+        log::info!(target: "client", "Loading privacy segments..");
+
+        let mut segments = segment::open(dir.segments())?;
+        segment::restore(&mut segments, &mut config.bloom_segments)?;
+
+        for s in config.bloom_segments.values().filter(|s| s.is_enabled) {
+            log::info!(
+                target: "client",
+                "Privacy segment {} synced up to height {}",
+                s.segment,
+                s.synced_height
+            );
+        }
+        loading.send(Loading::BloomFilterLoaded {});
+
         log::info!(target: "client", "Initializing block filters..");
 
         let cfheaders_genesis = filter::cache::StoredHeader::genesis(network);
-        let cfheaders_path = dir.join("filters.db");
+        let cfheaders_path = dir.filters();
         let cfheaders_store = match store::File::create(&cfheaders_path, cfheaders_genesis) {
             Ok(store) => {
                 log::info!(target: "client", "Initializing new filter header store {:?}", cfheaders_path);
@@ -387,12 +486,23 @@ impl<R: Reactor> Client<R> {
             }
             Err(store::Error::Io(e)) if e.kind() == io::ErrorKind::AlreadyExists => {
                 log::info!(target: "client", "Found existing store {:?}", cfheaders_path);
-                let store = store::File::open(cfheaders_path, cfheaders_genesis)?;
+                let mut store = store::File::open(cfheaders_path, cfheaders_genesis)?;
 
                 if store.check().is_err() {
                     log::warn!(target: "client", "Corruption detected in filter store, healing..");
                     store.heal()?; // Rollback store to the last valid header.
                 }
+                // Filter headers past the block header tip, eg. if block headers weren't
+                // flushed before shutdown, are for blocks we no longer know of.
+                let height = cache.height();
+                if store.height()? > height {
+                    log::warn!(
+                        target: "client",
+                        "Rolling back filter headers to block height {}..",
+                        height
+                    );
+                    store.rollback(height)?;
+                }
                 log::info!(target: "client", "Filters height = {}", store.height()?);
 
                 store
@@ -418,30 +528,24 @@ impl<R: Reactor> Client<R> {
 
         log::info!(target: "client", "Loading peer addresses..");
 
-        let peers_path = dir.join("peers.json");
+        let peers_path = dir.peers();
         let mut peers = match peer::Cache::create(&peers_path) {
             Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
                 log::info!(target: "client", "Found existing peer cache {:?}", peers_path);
                 let cache = peer::Cache::open(&peers_path).map_err(Error::PeerStore)?;
-                // let cfpeers = cache
-                //     .iter()
-                //     .filter(|(_, ka)| ka.addr.services.has(ServiceFlags::COMPACT_FILTERS))
-                //     .count();
-
-                // log::info!(
-                //     target: "client",
-                //     "{} peer(s) found.. {} with compact filters support",
-                //     cache.len(),
-                //     cfpeers
-                // );
+                let cfpeers = cache
+                    .iter()
+                    .filter(|(_, ka)| ka.addr.services.has(ServiceFlags::COMPACT_FILTERS))
+                    .count();
                 let bfpeers = cache
                     .iter()
                     .filter(|(_, ka)| ka.addr.services.has(ServiceFlags::BLOOM))
                     .count();
                 log::info!(
                     target: "client",
-                    "{} peer(s) found.. {} with bloom filters support",
+                    "{} peer(s) found.. {} with compact filters and {} with bloom filters support",
                     cache.len(),
+                    cfpeers,
                     bfpeers
                 );
                 cache
@@ -460,6 +564,10 @@ impl<R: Reactor> Client<R> {
         if config.connect.is_empty() && peers.is_empty() {
             log::info!(target: "client", "Address book is empty. Trying DNS seeds..");
 
+            if config.proxy.is_some() {
+                log::warn!(target: "client", "DNS seeds are resolved without the proxy");
+            }
+
             peers.seed(
                 network.seeds().iter().map(|s| (*s, network.port())),
                 Source::Dns,
@@ -473,7 +581,7 @@ impl<R: Reactor> Client<R> {
         Ok(ClientRunner {
             listen,
             commands: self.commands,
-            publisher: self.publisher,
+            publisher: self.publisher.register(segment::Checkpoint::new(segments)),
             reactor: self.reactor,
             service: Service::new(cache, filters, peers, RefClock::from(clock), rng, config),
         })
@@ -547,14 +655,6 @@ impl<W: Waker> Handle<W> {
         self.timeout = timeout;
     }
 
-    /// Get connected peers.
-    pub fn get_peers(&self, services: impl Into<ServiceFlags>) -> Result<Vec<Peer>, handle::Error> {
-        let (sender, recvr) = chan::bounded(1);
-        self._command(Command::GetPeers(services.into(), sender))?;
-
-        Ok(recvr.recv()?)
-    }
-
     /// Send a command to the command channel, and wake up the event loop.
     fn _command(&self, cmd: Command) -> Result<(), handle::Error> {
         self.commands.send(cmd)?;
@@ -580,6 +680,28 @@ impl<W: Waker> handle::Handle for Handle<W> {
         // Ok(receive.recv()?)
         Ok(())
     }
+    fn update_bloom_filter(
+        &self,
+        watch: Vec<Vec<u8>>,
+        flags: BloomFlags,
+        rescan_from: Option<Height>,
+    ) -> Result<(), handle::Error> {
+        self._command(Command::UpdateBloomFilter {
+            watch,
+            flags,
+            rescan_from,
+        })
+    }
+    fn add_to_bloom_filter(
+        &self,
+        elements: Vec<Vec<u8>>,
+        rescan_from: Option<Height>,
+    ) -> Result<(), handle::Error> {
+        self._command(Command::AddToBloomFilter {
+            elements,
+            rescan_from,
+        })
+    }
     fn get_tip(&self) -> Result<(Height, BlockHeader, Uint256), handle::Error> {
         let (transmit, receive) = chan::bounded::<(Height, BlockHeader, Uint256)>(1);
         self._command(Command::GetTip(transmit))?;
@@ -657,6 +779,13 @@ impl<W: Waker> handle::Handle for Handle<W> {
         self.subscriber.subscribe()
     }
 
+    fn subscribe(&self, categories: &[EventCategory]) -> chan::Receiver<Event> {
+        let categories = categories.to_vec();
+
+        self.subscriber
+            .subscribe_filtered(move |e| categories.contains(&e.category()))
+    }
+
     fn command(&self, cmd: Command) -> Result<(), handle::Error> {
         self._command(cmd)
     }
@@ -711,6 +840,19 @@ impl<W: Waker> handle::Handle for Handle<W> {
         Ok(())
     }
 
+    fn ban(&self, addr: net::SocketAddr) -> Result<(), handle::Error> {
+        self.command(Command::Ban(addr))?;
+
+        Ok(())
+    }
+
+    fn get_peers(&self, services: impl Into<ServiceFlags>) -> Result<Vec<Peer>, handle::Error> {
+        let (sender, recvr) = chan::bounded(1);
+        self._command(Command::GetPeers(services.into(), sender))?;
+
+        Ok(recvr.recv()?)
+    }
+
     fn import_headers(
         &self,
         headers: Vec<BlockHeader>,
@@ -721,12 +863,29 @@ impl<W: Waker> handle::Handle for Handle<W> {
         Ok(receive.recv()?)
     }
 
+    fn import_snapshot(
+        &self,
+        snapshot: Snapshot,
+    ) -> Result<Result<Height, fsm::ImportSnapshotError>, handle::Error> {
+        let (transmit, receive) = chan::bounded(1);
+        self.command(Command::ImportSnapshot(snapshot, transmit))?;
+
+        Ok(receive.recv()?)
+    }
+
     fn import_addresses(&self, addrs: Vec<Address>) -> Result<(), handle::Error> {
         self.command(Command::ImportAddresses(addrs))?;
 
         Ok(())
     }
 
+    fn peer_stats(&self) -> Result<Vec<fsm::PeerStats>, handle::Error> {
+        let (transmit, receive) = chan::bounded(1);
+        self.command(Command::GetPeerStats(transmit))?;
+
+        Ok(receive.recv()?)
+    }
+
     fn submit_transaction(
         &self,
         tx: Transaction,
@@ -743,6 +902,27 @@ impl<W: Waker> handle::Handle for Handle<W> {
         Ok(receive.recv()?)
     }
 
+    fn estimate_fee(&self, blocks: usize) -> Result<Option<FeeRate>, handle::Error> {
+        let (transmit, receive) = chan::bounded(1);
+        self.command(Command::EstimateFee(blocks, transmit))?;
+
+        Ok(receive.recv()?)
+    }
+
+    fn get_rescan_status(&self) -> Result<RescanStatus, handle::Error> {
+        let (transmit, receive) = chan::bounded(1);
+        self.command(Command::GetRescanStatus(transmit))?;
+
+        Ok(receive.recv()?)
+    }
+
+    fn list_watched(&self) -> Result<Vec<(Script, usize)>, handle::Error> {
+        let (transmit, receive) = chan::bounded(1);
+        self.command(Command::ListWatched(transmit))?;
+
+        Ok(receive.recv()?)
+    }
+
     fn wait<F, T>(&self, f: F) -> Result<T, handle::Error>
     where
         F: FnMut(fsm::Event) -> Option<T>,
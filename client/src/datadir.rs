@@ -0,0 +1,209 @@
+//! Data directory layout.
+//!
+//! Client files are kept in a platform-specific data directory, eg.
+//! `~/.local/share/nakamoto-cash` on Linux, as specified by the XDG base directory
+//! specification. Each network has its own sub-directory, which records the network it
+//! belongs to, so that stores of different networks can't be mixed up:
+//!
+//! ```text
+//! nakamoto-cash/
+//! ├── mainnet/
+//! │   ├── network
+//! │   ├── headers.db
+//! │   ├── filters.db
+//! │   ├── segments.db
+//! │   └── peers.json
+//! └── chipnet/
+//!     └── ...
+//! ```
+//!
+//! Installations still using the legacy `~/.nakamoto-cash` directory keep using it.
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+
+use thiserror::Error;
+
+use nakamoto_common::network::Network;
+
+/// Name of the application directory, within the platform directories.
+pub const APP_NAME: &str = "nakamoto-cash";
+/// Name of the legacy data directory, within the user's home directory.
+pub const LEGACY_DIR_NAME: &str = ".nakamoto-cash";
+
+/// Name of the file recording the network of a network directory.
+const NETWORK_FILE: &str = "network";
+
+/// A data directory error.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// An I/O error.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The network directory belongs to another network.
+    #[error("directory {path:?} holds {found} data, not {expected} data")]
+    NetworkMismatch {
+        /// Network directory.
+        path: PathBuf,
+        /// Network we're connecting to.
+        expected: String,
+        /// Network recorded in the directory.
+        found: String,
+    },
+}
+
+/// The client data directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataDir {
+    path: PathBuf,
+}
+
+impl DataDir {
+    /// Use the given path as data directory.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// The platform data directory, or the legacy data directory if it exists.
+    pub fn platform() -> Self {
+        let legacy = self::home().join(LEGACY_DIR_NAME);
+
+        if legacy.is_dir() {
+            return Self::new(legacy);
+        }
+        Self::new(self::data_home().join(APP_NAME))
+    }
+
+    /// Data directory path.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Open the directory of the given network, creating it if it doesn't exist.
+    pub fn network(&self, network: Network) -> Result<NetworkDir, Error> {
+        let path = self.path.join(network.as_str());
+        let marker = path.join(NETWORK_FILE);
+
+        fs::create_dir_all(&path)?;
+
+        match fs::read_to_string(&marker) {
+            Ok(found) if found.trim() != network.as_str() => {
+                return Err(Error::NetworkMismatch {
+                    path,
+                    expected: network.as_str().to_owned(),
+                    found: found.trim().to_owned(),
+                });
+            }
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                fs::write(&marker, network.as_str())?;
+            }
+            Err(err) => return Err(err.into()),
+        }
+        Ok(NetworkDir { path })
+    }
+}
+
+impl Default for DataDir {
+    fn default() -> Self {
+        Self::platform()
+    }
+}
+
+/// The data directory of a network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkDir {
+    path: PathBuf,
+}
+
+impl NetworkDir {
+    /// Network directory path.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Block header store.
+    pub fn headers(&self) -> PathBuf {
+        self.path.join("headers.db")
+    }
+
+    /// Filter header store.
+    pub fn filters(&self) -> PathBuf {
+        self.path.join("filters.db")
+    }
+
+    /// Privacy segment store.
+    pub fn segments(&self) -> PathBuf {
+        self.path.join("segments.db")
+    }
+
+    /// Peer address cache.
+    pub fn peers(&self) -> PathBuf {
+        self.path.join("peers.json")
+    }
+}
+
+/// The user's home directory.
+pub fn home() -> PathBuf {
+    let var = if cfg!(windows) { "USERPROFILE" } else { "HOME" };
+
+    PathBuf::from(env::var_os(var).unwrap_or_default())
+}
+
+/// The platform data directory, eg. `$XDG_DATA_HOME`, or `~/.local/share` on Linux.
+pub fn data_home() -> PathBuf {
+    self::platform_dir("XDG_DATA_HOME", &[".local", "share"])
+}
+
+/// The platform configuration directory, eg. `$XDG_CONFIG_HOME`, or `~/.config` on Linux.
+pub fn config_home() -> PathBuf {
+    self::platform_dir("XDG_CONFIG_HOME", &[".config"])
+}
+
+fn platform_dir(xdg_var: &str, xdg_default: &[&str]) -> PathBuf {
+    if cfg!(target_os = "macos") {
+        return self::home().join("Library").join("Application Support");
+    }
+    if cfg!(windows) {
+        return env::var_os("APPDATA")
+            .map(PathBuf::from)
+            .unwrap_or_else(self::home);
+    }
+    // Relative paths are invalid, and should be ignored, as per the XDG specification.
+    match env::var_os(xdg_var).map(PathBuf::from) {
+        Some(path) if path.is_absolute() => path,
+        _ => xdg_default
+            .iter()
+            .fold(self::home(), |path, dir| path.join(dir)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_network_dirs() {
+        let tmp = tempfile::tempdir().unwrap();
+        let datadir = DataDir::new(tmp.path());
+
+        let mainnet = datadir.network(Network::Mainnet).unwrap();
+        let chipnet = datadir.network(Network::Chipnet).unwrap();
+
+        assert_eq!(
+            mainnet.headers(),
+            tmp.path().join("mainnet").join("headers.db")
+        );
+        assert_eq!(
+            chipnet.peers(),
+            tmp.path().join("chipnet").join("peers.json")
+        );
+        assert_eq!(datadir.network(Network::Mainnet).unwrap(), mainnet);
+
+        // A directory holding another network's data is refused.
+        fs::write(chipnet.path().join(NETWORK_FILE), "mainnet").unwrap();
+        assert!(matches!(
+            datadir.network(Network::Chipnet),
+            Err(Error::NetworkMismatch { .. })
+        ));
+    }
+}
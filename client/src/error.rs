@@ -31,6 +31,12 @@ pub enum Error {
     /// An error coming from the filter store.
     #[error(transparent)]
     FilterStore(#[from] chain::filter::store::Error),
+    /// An error coming from the privacy segment store.
+    #[error(transparent)]
+    SegmentStore(#[from] common::bloom::store::Error),
+    /// An error coming from the data directory.
+    #[error(transparent)]
+    DataDir(#[from] crate::datadir::Error),
     /// An error coming from the peer store.
     #[error("error loading peers: {0}")]
     PeerStore(io::Error),
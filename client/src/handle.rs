@@ -14,14 +14,21 @@ use nakamoto_common::bitcoin::util::uint::Uint256;
 use nakamoto_common::bitcoin::{Script, Txid};
 
 use nakamoto_common::bitcoin::network::message::NetworkMessage;
+use nakamoto_common::bitcoin::network::message_bloom::BloomFlags;
 use nakamoto_common::block::filter::BlockFilter;
+use nakamoto_common::block::snapshot::Snapshot;
 use nakamoto_common::block::tree::{BlockReader, ImportResult};
 use nakamoto_common::block::{
     self, Block, BlockHash, BlockHeader, Height, MerkleBlock, Transaction,
 };
 use nakamoto_common::nonempty::NonEmpty;
+use nakamoto_p2p::fsm::event::Category as EventCategory;
+use nakamoto_p2p::fsm::fees::FeeRate;
 use nakamoto_p2p::fsm::Link;
-use nakamoto_p2p::fsm::{self, Command, CommandError, Event, GetFiltersError, Peer};
+use nakamoto_p2p::fsm::{
+    self, Command, CommandError, Event, GetFiltersError, ImportSnapshotError, Peer, PeerStats,
+    RescanStatus,
+};
 
 /// An error resulting from a handle method.
 #[derive(Error, Debug)]
@@ -99,6 +106,9 @@ pub trait Handle: Sized + Send + Sync + Clone {
     fn filters(&self) -> chan::Receiver<(BlockFilter, BlockHash, Height)>;
     /// Subscribe to client events.
     fn events(&self) -> chan::Receiver<Event>;
+    /// Subscribe to client events of the given categories only. Events of other categories
+    /// are never sent on the returned channel.
+    fn subscribe(&self, categories: &[EventCategory]) -> chan::Receiver<Event>;
 
     /// Send a command to the client.
     fn command(&self, cmd: Command) -> Result<(), Error>;
@@ -124,6 +134,50 @@ pub trait Handle: Sized + Send + Sync + Clone {
 
         Ok(())
     }
+    /// Rescan the blockchain for matching scripts with compact block filters, like
+    /// [`Handle::rescan`], but fail with [`GetFiltersError::NotConnected`] instead of waiting
+    /// if no connected peer serves them, eg. to fall back to bloom filters.
+    fn rescan_cf(
+        &self,
+        range: impl RangeBounds<Height>,
+        watch: impl Iterator<Item = Script>,
+    ) -> Result<(), Error> {
+        let (transmit, receive) = chan::bounded(1);
+
+        self.command(Command::RescanFilters {
+            from: range.start_bound().cloned(),
+            to: range.end_bound().cloned(),
+            watch: watch.collect(),
+            reply: transmit,
+        })?;
+
+        receive.recv()?.map_err(Error::GetFilters)
+    }
+    /// Scan the blockchain for matching scripts, with compact block filters when connected to
+    /// peers serving them, and with merkle blocks otherwise, switching between the two as
+    /// peers come and go. Matching blocks are reported with [`Event::ScanMatched`].
+    fn scan(
+        &self,
+        range: impl RangeBounds<Height>,
+        watch: impl Iterator<Item = Script>,
+    ) -> Result<(), Error> {
+        self.command(Command::Scan {
+            from: range.start_bound().cloned(),
+            to: range.end_bound().cloned(),
+            watch: watch.collect(),
+        })?;
+
+        Ok(())
+    }
+    /// Abort the compact filter and merkle block rescans in progress, eg. before starting
+    /// a rescan from another height.
+    fn abort_rescan(&self) -> Result<(), Error> {
+        self.command(Command::AbortRescan)?;
+
+        Ok(())
+    }
+    /// Get the progress of the compact filter and merkle block rescans.
+    fn get_rescan_status(&self) -> Result<RescanStatus, Error>;
     /// Update the watchlist with the provided scripts.
     ///
     /// Note that this won't trigger a rescan of any existing blocks. To avoid
@@ -136,6 +190,21 @@ pub trait Handle: Sized + Send + Sync + Clone {
 
         Ok(())
     }
+    /// Remove the provided scripts from the watchlist.
+    ///
+    /// Watching a script adds a reference to it, and unwatching it removes one: scripts
+    /// watched more than once, eg. by different wallet accounts sharing a client, are only
+    /// removed from the watchlist once they were unwatched as many times.
+    fn unwatch(&self, scripts: impl Iterator<Item = Script>) -> Result<(), Error> {
+        self.command(Command::Unwatch {
+            scripts: scripts.collect(),
+        })?;
+
+        Ok(())
+    }
+    /// Get the watched scripts, along with the number of times they were watched. Scripts
+    /// that were only passed to [`Handle::rescan`] were watched zero times.
+    fn list_watched(&self) -> Result<Vec<(Script, usize)>, Error>;
     /// Broadcast a message to peers matching the predicate.
     /// To only broadcast to outbound peers, use [`Peer::is_outbound`].
     fn broadcast(
@@ -147,20 +216,40 @@ pub trait Handle: Sized + Send + Sync + Clone {
     fn connect(&self, addr: net::SocketAddr) -> Result<Link, Error>;
     /// Disconnect from the designated peer address.
     fn disconnect(&self, addr: net::SocketAddr) -> Result<(), Error>;
+    /// Ban the designated peer address, disconnecting from it if connected.
+    fn ban(&self, addr: net::SocketAddr) -> Result<(), Error>;
+    /// Get connected peers with the given services.
+    fn get_peers(&self, services: impl Into<ServiceFlags>) -> Result<Vec<Peer>, Error>;
     /// Submit a transaction to the network.
     ///
     /// Returns the peer(s) the transaction was announced to, or an error if no peers were found.
+    ///
+    /// Peers rejecting the transaction are reported with [`Event::TxRejected`] events. Once
+    /// the transaction was rejected, and wasn't relayed by any peer, submitting it again fails
+    /// with [`CommandError::Rejected`], which carries the rejection reason.
     fn submit_transaction(&self, tx: Transaction) -> Result<NonEmpty<net::SocketAddr>, Error>;
     /// Return a transaction that was propagated by the client.
     fn get_submitted_transaction(&self, txid: &Txid) -> Result<Option<Transaction>, Error>;
+    /// Estimate the fee rate, in satoshis per byte, needed for a transaction to be included
+    /// within the given number of blocks. Returns `None` if no estimate is available yet.
+    fn estimate_fee(&self, blocks: usize) -> Result<Option<FeeRate>, Error>;
     /// Import block headers into the node.
     /// This may cause the node to broadcast header or inventory messages to its peers.
     fn import_headers(
         &self,
         headers: Vec<BlockHeader>,
     ) -> Result<Result<ImportResult, block::tree::Error>, Error>;
+    /// Import a trusted snapshot of wallet-relevant chain state. The snapshot headers are
+    /// imported, and blocks up to the snapshot height are no longer scanned for matches.
+    /// Returns the snapshot height.
+    fn import_snapshot(
+        &self,
+        snapshot: Snapshot,
+    ) -> Result<Result<Height, ImportSnapshotError>, Error>;
     /// Import peer addresses into the node's address book.
     fn import_addresses(&self, addrs: Vec<Address>) -> Result<(), Error>;
+    /// Get the bandwidth used by each connected peer, in total and by message type.
+    fn peer_stats(&self) -> Result<Vec<PeerStats>, Error>;
     /// Wait for the given predicate to be fulfilled.
     fn wait<F: FnMut(fsm::Event) -> Option<T>, T>(&self, f: F) -> Result<T, Error>;
     /// Wait for a given number of peers to be connected with the given services.
@@ -178,4 +267,20 @@ pub trait Handle: Sized + Send + Sync + Clone {
     fn load_bloom_filter(&self, filter: BloomFilter, peer: Vec<PeerId>) -> Result<(), Error>;
     /// get peers not bloom filter loaded
     fn get_peers_not_filter_loaded(&self) -> Result<Vec<PeerId>, Error>;
+    /// Rebuild the bloom filters from the given watch set, with the given update flags, and
+    /// reload them onto all filtered peers. If a height is given, blocks are re-scanned from
+    /// that height up to the last synced height.
+    fn update_bloom_filter(
+        &self,
+        watch: Vec<Vec<u8>>,
+        flags: BloomFlags,
+        rescan_from: Option<Height>,
+    ) -> Result<(), Error>;
+    /// Add elements to the bloom filters loaded on peers using `filteradd`, without
+    /// reloading them. Filters are reloaded if their false-positive rate degrades too much.
+    fn add_to_bloom_filter(
+        &self,
+        elements: Vec<Vec<u8>>,
+        rescan_from: Option<Height>,
+    ) -> Result<(), Error>;
 }
@@ -3,13 +3,18 @@
 #![allow(clippy::type_complexity)]
 #![deny(missing_docs, unsafe_code)]
 mod client;
+pub mod datadir;
 mod error;
 mod event;
 mod peer;
+mod segment;
 mod service;
 
 pub use client::*;
 pub mod handle;
 
+#[cfg(feature = "tokio")]
+pub mod async_handle;
+
 #[cfg(test)]
 mod tests;
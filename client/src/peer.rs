@@ -5,10 +5,21 @@ use std::{fs, io, net};
 
 pub use nakamoto_common::p2p::peer::*;
 
+/// Version of the on-disk address book format.
+///
+/// Version `0` is the original, unversioned format: a flat object mapping IP addresses to
+/// known addresses. Since version `1`, the address book is an object with a `version` and
+/// a `peers` field, the latter holding the addresses, and an optional `anchors` field,
+/// holding the addresses of our anchor peers.
+pub const VERSION: u64 = 1;
+
 /// A file-backed implementation of [`Store`].
+///
+/// Address books saved in an older format are migrated to the current one when opened.
 #[derive(Debug)]
 pub struct Cache {
     addrs: HashMap<net::IpAddr, KnownAddress>,
+    anchors: Vec<net::SocketAddr>,
     file: fs::File,
 }
 
@@ -32,26 +43,61 @@ impl Cache {
         Ok(Self {
             file,
             addrs: HashMap::new(),
+            anchors: Vec::new(),
         })
     }
 
     /// Create a new cache from a file.
     pub fn from(mut file: fs::File) -> io::Result<Self> {
         use io::Read;
-        use microserde::json::Value;
+        use microserde::json::{Number, Value};
         use std::str::FromStr;
 
         let mut s = String::new();
         let mut addrs = HashMap::new();
+        let mut anchors = Vec::new();
+        let mut version = VERSION;
 
         file.read_to_string(&mut s)?;
 
         if !s.is_empty() {
             let val = microserde::json::from_str(&s)
                 .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+            let peers = match val {
+                Value::Object(mut obj) => match obj.remove("version") {
+                    Some(Value::Number(Number::U64(v))) if v <= VERSION => {
+                        match obj.remove("anchors") {
+                            Some(Value::Array(ary)) => {
+                                for v in ary {
+                                    match v {
+                                        Value::String(s) => {
+                                            anchors.push(s.parse().map_err(|_| {
+                                                io::Error::from(io::ErrorKind::InvalidData)
+                                            })?)
+                                        }
+                                        _ => return Err(io::ErrorKind::InvalidData.into()),
+                                    }
+                                }
+                            }
+                            None => {}
+                            Some(_) => return Err(io::ErrorKind::InvalidData.into()),
+                        }
+                        version = v;
+                        obj.remove("peers")
+                    }
+                    // Written by a newer version of the software, or corrupted.
+                    Some(_) => return Err(io::ErrorKind::InvalidData.into()),
+                    // The original, flat format.
+                    None => {
+                        version = 0;
+                        Some(Value::Object(obj))
+                    }
+                },
+                _ => return Err(io::ErrorKind::InvalidData.into()),
+            };
 
-            match val {
-                Value::Object(ary) => {
+            match peers {
+                Some(Value::Object(ary)) => {
                     for (k, v) in ary.into_iter() {
                         let ka = KnownAddress::from_json(v)
                             .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
@@ -64,8 +110,16 @@ impl Cache {
                 _ => return Err(io::ErrorKind::InvalidData.into()),
             }
         }
+        let mut cache = Self {
+            file,
+            addrs,
+            anchors,
+        };
 
-        Ok(Self { file, addrs })
+        if version < VERSION {
+            cache.flush()?;
+        }
+        Ok(cache)
     }
 }
 
@@ -90,6 +144,14 @@ impl Store for Cache {
         Box::new(self.addrs.iter())
     }
 
+    fn anchors(&self) -> Vec<net::SocketAddr> {
+        self.anchors.clone()
+    }
+
+    fn set_anchors(&mut self, anchors: Vec<net::SocketAddr>) {
+        self.anchors = anchors;
+    }
+
     fn clear(&mut self) {
         self.addrs.clear()
     }
@@ -100,14 +162,28 @@ impl Store for Cache {
 
     fn flush<'a>(&mut self) -> io::Result<()> {
         use io::{Seek, Write};
-        use microserde::json::Value;
+        use microserde::json::{Number, Object, Value};
 
-        let peers: microserde::json::Object = self
+        let peers: Object = self
             .addrs
             .iter()
             .map(|(ip, ka)| (ip.to_string(), ka.to_json()))
             .collect();
-        let s = microserde::json::to_string(&Value::Object(peers));
+        let mut obj = Object::new();
+
+        obj.insert("version".to_owned(), Value::Number(Number::U64(VERSION)));
+        obj.insert("peers".to_owned(), Value::Object(peers));
+        obj.insert(
+            "anchors".to_owned(),
+            Value::Array(
+                self.anchors
+                    .iter()
+                    .map(|a| Value::String(a.to_string()))
+                    .collect(),
+            ),
+        );
+
+        let s = microserde::json::to_string(&Value::Object(obj));
 
         self.file.set_len(0)?;
         self.file.seek(io::SeekFrom::Start(0))?;
@@ -157,9 +233,12 @@ mod test {
                     last_sampled: Some(LocalTime::from_secs((i + 1) as u64)),
                     last_attempt: None,
                     last_active: None,
+                    segment: None,
+                    failures: i as u32 % 3,
                 };
                 cache.insert(ip, ka);
             }
+            cache.set_anchors(vec![([127, 0, 0, 32], 8333).into()]);
             cache.flush().unwrap();
 
             for (ip, ka) in cache.iter() {
@@ -178,6 +257,58 @@ mod test {
             expected.sort_by_key(|(i, _)| *i);
 
             assert_eq!(actual, expected);
+            assert_eq!(cache.anchors(), vec![([127, 0, 0, 32], 8333).into()]);
+        }
+    }
+
+    #[test]
+    fn test_migrate() {
+        use microserde::json::{Object, Value};
+
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("cache");
+        let sockaddr = net::SocketAddr::from(([1, 2, 3, 4], 8333));
+        let ka = KnownAddress::new(
+            Address::new(&sockaddr, ServiceFlags::NETWORK),
+            Source::Dns,
+            Some(LocalTime::from_secs(42)),
+        );
+        // An address book in the original, unversioned format, without failure counts.
+        let mut value = ka.to_json();
+        if let Value::Object(obj) = &mut value {
+            obj.remove("failures");
         }
+        let mut flat = Object::new();
+        flat.insert(sockaddr.ip().to_string(), value);
+
+        fs::write(&path, microserde::json::to_string(&Value::Object(flat))).unwrap();
+
+        let cache = Cache::open(&path).unwrap();
+        assert_eq!(cache.get(&sockaddr.ip()), Some(&ka));
+        drop(cache);
+
+        // The address book was migrated to the current format.
+        let s = fs::read_to_string(&path).unwrap();
+        assert!(s.contains(&format!("\"version\":{}", VERSION)), "{}", s);
+
+        let cache = Cache::open(&path).unwrap();
+        assert_eq!(cache.get(&sockaddr.ip()), Some(&ka));
+    }
+
+    #[test]
+    fn test_unknown_version() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("cache");
+
+        fs::write(
+            &path,
+            format!("{{\"version\":{},\"peers\":{{}}}}", VERSION + 1),
+        )
+        .unwrap();
+
+        assert_eq!(
+            Cache::open(&path).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
     }
 }
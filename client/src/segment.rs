@@ -0,0 +1,113 @@
+//! Privacy segment persistence.
+use std::path::Path;
+
+use nakamoto_common::bloom::store::cache::{FilterCache, PrivacySegment};
+use nakamoto_common::bloom::store::{self, Store as _};
+use nakamoto_common::collections::HashMap;
+use nakamoto_p2p::fsm;
+
+/// A segment cache, backed by a file.
+pub type Cache = FilterCache<store::File>;
+
+/// Open the segment store at the given path, creating it if it doesn't exist, and load
+/// its segments.
+pub fn open<P: AsRef<Path>>(path: P) -> Result<Cache, store::Error> {
+    let path = path.as_ref();
+
+    let store = match store::File::create(path) {
+        Ok(store) => {
+            log::info!(target: "client", "Initializing new segment store {:?}", path);
+            store
+        }
+        Err(store::Error::Io(e)) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            log::info!(target: "client", "Found existing segment store {:?}", path);
+            let store = store::File::open(path)?;
+
+            if store.check().is_err() {
+                log::warn!(target: "client", "Corruption detected in segment store, healing..");
+                store.heal()?; // Drop the partially written segment.
+            }
+            store
+        }
+        Err(err) => return Err(err),
+    };
+    FilterCache::load(store)
+}
+
+/// Restore the sync state of the given segments from the store, and write them back.
+///
+/// A stored segment's sync state only applies if it was born at or before the configured
+/// segment, otherwise the blocks in between were never scanned.
+pub fn restore(
+    store: &mut Cache,
+    segments: &mut HashMap<u32, PrivacySegment>,
+) -> Result<(), store::Error> {
+    for result in store.iter() {
+        let (id, stored) = result?;
+
+        if let Some(segment) = segments.get_mut(&id) {
+            if stored.birth <= segment.birth {
+                segment.synced_height = segment.synced_height.max(stored.synced_height);
+            }
+        }
+    }
+
+    let mut ids = segments.keys().copied().collect::<Vec<_>>();
+    ids.sort_unstable();
+
+    for id in ids {
+        save(store, &segments[&id])?;
+    }
+    store.sync()
+}
+
+/// Save a segment to the store, overwriting any previous version of it.
+///
+/// Segments are stored at the index of their id, so any missing segments in between are
+/// filled with disabled ones.
+fn save(store: &mut Cache, segment: &PrivacySegment) -> Result<(), store::Error> {
+    let len = store.len()? as u32;
+
+    if segment.segment < len {
+        store.update(segment.segment, segment.clone())
+    } else {
+        let padding = (len..segment.segment).map(|id| PrivacySegment {
+            segment: id,
+            is_enabled: false,
+            ..PrivacySegment::default()
+        });
+        store
+            .put(padding.chain(std::iter::once(segment.clone())))
+            .map(|_| ())
+    }
+}
+
+/// Checkpoints privacy segments to the store as they are updated.
+#[derive(Debug)]
+pub struct Checkpoint {
+    store: Cache,
+}
+
+impl Checkpoint {
+    /// Create a new checkpoint publisher.
+    pub fn new(store: Cache) -> Self {
+        Self { store }
+    }
+}
+
+impl nakamoto_net::Publisher<fsm::Event> for Checkpoint {
+    fn publish(&mut self, event: fsm::Event) {
+        if let fsm::Event::PrivacySegmentUpdated { segment } = event {
+            let result = save(&mut self.store, &segment).and_then(|()| self.store.sync());
+
+            if let Err(err) = result {
+                log::error!(
+                    target: "client",
+                    "Failed to checkpoint privacy segment {}: {}",
+                    segment.segment,
+                    err
+                );
+            }
+        }
+    }
+}
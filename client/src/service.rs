@@ -15,9 +15,15 @@ use crate::peer;
 use nakamoto_common::block::filter;
 use nakamoto_common::block::filter::Filters;
 
+/// Number of message decoders kept for reuse once their peers disconnect.
+const DECODER_POOL_SIZE: usize = 16;
+/// Initial buffer capacity of message decoders.
+const DECODER_CAPACITY: usize = 1024;
+
 /// Client service. Wraps a state machine and handles decoding and encoding of network messages.
 pub struct Service<T, F, P, C> {
     inboxes: HashMap<net::SocketAddr, p2p::stream::Decoder>,
+    decoders: p2p::stream::Pool,
     machine: p2p::StateMachine<T, F, P, C>,
 }
 
@@ -35,6 +41,7 @@ impl<T: BlockTree, F: filter::Filters, P: peer::Store, C: AdjustedClock<net::Soc
     ) -> Self {
         Self {
             inboxes: HashMap::new(),
+            decoders: p2p::stream::Pool::new(DECODER_POOL_SIZE, DECODER_CAPACITY),
             machine: p2p::StateMachine::new(
                 tree,
                 filters,
@@ -44,11 +51,20 @@ impl<T: BlockTree, F: filter::Filters, P: peer::Store, C: AdjustedClock<net::Soc
                 p2p::Config {
                     network: config.network,
                     domains: config.domains,
+                    asmap: config.asmap,
                     connect: config.connect,
                     user_agent: config.user_agent,
+                    report_height: config.report_height,
+                    relay: config.relay,
                     hooks: config.hooks,
                     limits: config.limits,
                     services: config.services,
+                    required_services: config.required_services,
+                    preferred_services: config.preferred_services,
+                    bloom_segments: config.bloom_segments,
+                    bloom_fp_rate: config.bloom_fp_rate,
+                    bloom_decoys: config.bloom_decoys,
+                    broadcast: config.broadcast,
                     ..p2p::Config::default()
                 },
             ),
@@ -94,12 +110,18 @@ where
         self.machine.timer_expired();
     }
 
+    fn shutdown(&mut self) {
+        self.machine.shutdown();
+    }
+
     fn message_received(&mut self, addr: &net::SocketAddr, bytes: Cow<[u8]>) {
         if let Some(inbox) = self.inboxes.get_mut(addr) {
             inbox.input(bytes.borrow());
 
             loop {
-                match inbox.decode_next() {
+                match inbox.decode_message() {
+                    // Decoded messages own their fields, eg. a block's transactions, and are
+                    // moved to the state machine rather than copied.
                     Ok(Some(msg)) => self.machine.message_received(addr, Cow::Owned(msg)),
                     Ok(None) => break,
 
@@ -123,12 +145,14 @@ where
     }
 
     fn connected(&mut self, addr: net::SocketAddr, local_addr: &net::SocketAddr, link: Link) {
-        self.inboxes.insert(addr, p2p::stream::Decoder::new(1024));
+        self.inboxes.insert(addr, self.decoders.take());
         self.machine.connected(addr, local_addr, link)
     }
 
     fn disconnected(&mut self, addr: &net::SocketAddr, reason: Disconnect<Self::DisconnectReason>) {
-        self.inboxes.remove(addr);
+        if let Some(inbox) = self.inboxes.remove(addr) {
+            self.decoders.put(inbox);
+        }
         self.machine.disconnected(addr, reason)
     }
 }
@@ -8,10 +8,12 @@ use nakamoto_chain::filter::BlockFilter;
 
 use nakamoto_common::bitcoin::network::constants::ServiceFlags;
 use nakamoto_common::bitcoin::network::message::{NetworkMessage, RawNetworkMessage};
+use nakamoto_common::bitcoin::network::message_bloom::BloomFlags;
 use nakamoto_common::bitcoin::network::Address;
 use nakamoto_common::bitcoin::util::uint::Uint256;
-use nakamoto_common::bitcoin::Txid;
+use nakamoto_common::bitcoin::{Script, Txid};
 use nakamoto_common::block::filter::FilterHeader;
+use nakamoto_common::block::snapshot::Snapshot;
 use nakamoto_common::block::store::Genesis as _;
 use nakamoto_common::block::time::{AdjustedTime, LocalTime};
 use nakamoto_common::block::tree::{self, ImportResult};
@@ -24,12 +26,14 @@ use nakamoto_test::block::cache::model;
 use nakamoto_net::event;
 use nakamoto_net::StateMachine as _;
 use nakamoto_p2p::fsm;
+use nakamoto_p2p::fsm::fees::FeeRate;
 use nakamoto_p2p::fsm::Command;
 use nakamoto_p2p::fsm::Link;
 use nakamoto_p2p::fsm::Peer;
+use nakamoto_p2p::fsm::RescanStatus;
 use nakamoto_p2p::fsm::StateMachine;
 
-use crate::client::{chan, Event, Loading};
+use crate::client::{chan, Event, EventCategory, Loading};
 use crate::handle::{self, Handle};
 
 pub struct Client {
@@ -175,6 +179,35 @@ impl Handle for TestHandle {
         unimplemented!()
     }
 
+    fn estimate_fee(&self, _blocks: usize) -> Result<Option<FeeRate>, handle::Error> {
+        unimplemented!()
+    }
+
+    fn get_rescan_status(&self) -> Result<RescanStatus, handle::Error> {
+        unimplemented!()
+    }
+
+    fn list_watched(&self) -> Result<Vec<(Script, usize)>, handle::Error> {
+        unimplemented!()
+    }
+
+    fn update_bloom_filter(
+        &self,
+        _watch: Vec<Vec<u8>>,
+        _flags: BloomFlags,
+        _rescan_from: Option<Height>,
+    ) -> Result<(), handle::Error> {
+        unimplemented!()
+    }
+
+    fn add_to_bloom_filter(
+        &self,
+        _elements: Vec<Vec<u8>>,
+        _rescan_from: Option<Height>,
+    ) -> Result<(), handle::Error> {
+        unimplemented!()
+    }
+
     fn request_block(&self, hash: &BlockHash) -> Result<(), handle::Error> {
         self.command(Command::RequestBlock(*hash))?;
 
@@ -207,6 +240,13 @@ impl Handle for TestHandle {
         self.subscriber.subscribe()
     }
 
+    fn subscribe(&self, categories: &[EventCategory]) -> chan::Receiver<Event> {
+        let categories = categories.to_vec();
+
+        self.subscriber
+            .subscribe_filtered(move |e| categories.contains(&e.category()))
+    }
+
     fn command(&self, cmd: Command) -> Result<(), handle::Error> {
         log::debug!("Sending {:?}", cmd);
         self.commands.send(cmd).map_err(handle::Error::from)
@@ -228,6 +268,14 @@ impl Handle for TestHandle {
         unimplemented!()
     }
 
+    fn ban(&self, _addr: net::SocketAddr) -> Result<(), handle::Error> {
+        unimplemented!()
+    }
+
+    fn get_peers(&self, _services: impl Into<ServiceFlags>) -> Result<Vec<Peer>, handle::Error> {
+        unimplemented!()
+    }
+
     fn query_tree(
         &self,
         _query: impl Fn(&dyn nakamoto_chain::BlockReader) + Send + Sync + 'static,
@@ -242,10 +290,21 @@ impl Handle for TestHandle {
         unimplemented!()
     }
 
+    fn import_snapshot(
+        &self,
+        _snapshot: Snapshot,
+    ) -> Result<Result<Height, fsm::ImportSnapshotError>, handle::Error> {
+        unimplemented!()
+    }
+
     fn import_addresses(&self, _addrs: Vec<Address>) -> Result<(), handle::Error> {
         unimplemented!()
     }
 
+    fn peer_stats(&self) -> Result<Vec<fsm::PeerStats>, handle::Error> {
+        unimplemented!()
+    }
+
     fn submit_transaction(
         &self,
         _tx: Transaction,
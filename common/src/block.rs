@@ -3,6 +3,7 @@ pub mod checkpoints;
 pub mod filter;
 pub mod genesis;
 pub mod iter;
+pub mod snapshot;
 pub mod store;
 pub mod time;
 pub mod tree;
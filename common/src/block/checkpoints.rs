@@ -1,4 +1,6 @@
 //! Checkpoints used to validate blocks at certain heights.
+use crate::bitcoin::hashes::hex::FromHex;
+use crate::block::{BlockHash, Height};
 
 #[rustfmt::skip]
 /// Mainnet checkpoints.
@@ -23,36 +25,99 @@ pub const MAINNET: &[(u64, &str)] = &[
 /// Testnet checkpoints.
 #[rustfmt::skip]
 pub const TESTNET: &[(u64, &str)] = &[
-    (546, "000000002a936ca763904c3c35fce2f3556c559c0214345d31b1bcebf76acb70"),
+    (546,     "000000002a936ca763904c3c35fce2f3556c559c0214345d31b1bcebf76acb70"),
+    // UAHF fork block.
+    (1155875, "00000000f17c850672894b9a75b63a1e72830bbd5f4c8889b5c1a80e7faef138"),
+    // DAA activation.
+    (1188697, "0000000000170ed0918077bde7b4d36cc4c91be69fa09211f748240dabe047fb"),
+    // Great wall activation.
+    (1303885, "00000000000000479138892ef0e4fa478ccc938fb94df862ef5bde7e8dee23d3"),
+    // Graviton activation.
+    (1341712, "00000000fffc44ea2e202bd905a9fbbb9491ef9e9d5a9eed4039079229afa35b"),
+    // Phonon activation.
+    (1378461, "0000000099f5509b5f36b1926bcf82b21d936ebeadee811030dfbbb7fae915d7"),
+    // Axion activation.
+    (1421482, "0000000023e0680a8a062b3cc289a4a341124ce7fcb6340ede207e194d73b60a"),
 ];
 
-/// Chipnet checkpoints.
+/// Regtest checkpoints.
 pub const REGTEST: &[(u64, &str)] = &[];
 
-/// chipnet checkpoints.
+/// Testnet4 checkpoints.
+///
+/// Chipnet forked off testnet4, so these are shared with chipnet.
+#[rustfmt::skip]
+pub const TESTNET4: &[(u64, &str)] = &[
+    (2,     "00000000b0c65b1e03baace7d5c093db0d6aac224df01484985ffd5e86a1a20c"),
+    (6,     "00000000d71b9b1f7e13b0c9b218a12df6526c1bcd1b667764b8693ae9a413cb"),
+    (3000,  "00000000253cdbca769f44f8c54f49be05c1926bd856670c4cf387080d86ff3e"),
+    (5000,  "000000009f092d074574a216faec682040a853c4f079c33dfd2c3ef1fd8108c4"),
+    // Axion activation.
+    (16845, "00000000fb325b8f34fe80c96a5f708a08699a68bbab82dba4474d86bd743077"),
+];
+
+/// Scalenet checkpoints.
+///
+/// Scalenet may be reset at any time, so it has none.
+pub const SCALENET: &[(u64, &str)] = &[];
+
+/// Chipnet checkpoints.
+#[rustfmt::skip]
 pub const CHIPNET: &[(u64, &str)] = &[
-    (
-        2,
-        "00000000b0c65b1e03baace7d5c093db0d6aac224df01484985ffd5e86a1a20c",
-    ),
-    (
-        6,
-        "00000000d71b9b1f7e13b0c9b218a12df6526c1bcd1b667764b8693ae9a413cb",
-    ),
-    (
-        3000,
-        "00000000253cdbca769f44f8c54f49be05c1926bd856670c4cf387080d86ff3e",
-    ),
-    (
-        5000,
-        "000000009f092d074574a216faec682040a853c4f079c33dfd2c3ef1fd8108c4",
-    ),
-    (
-        16845,
-        "00000000fb325b8f34fe80c96a5f708a08699a68bbab82dba4474d86bd743077",
-    ),
-    (
-        188843,
-        "0000000009210bc5d55ff530b107942edda0bc684419d22b245ca2302b631c5b",
-    ),
+    (2,      "00000000b0c65b1e03baace7d5c093db0d6aac224df01484985ffd5e86a1a20c"),
+    (6,      "00000000d71b9b1f7e13b0c9b218a12df6526c1bcd1b667764b8693ae9a413cb"),
+    (3000,   "00000000253cdbca769f44f8c54f49be05c1926bd856670c4cf387080d86ff3e"),
+    (5000,   "000000009f092d074574a216faec682040a853c4f079c33dfd2c3ef1fd8108c4"),
+    // Axion activation, shared with testnet4.
+    (16845,  "00000000fb325b8f34fe80c96a5f708a08699a68bbab82dba4474d86bd743077"),
+    (188843, "0000000009210bc5d55ff530b107942edda0bc684419d22b245ca2302b631c5b"),
 ];
+
+/// Parse a checkpoint of the form `<height>:<block-hash>`, eg. to override the checkpoints
+/// of a network at runtime.
+pub fn parse(s: &str) -> Result<(Height, BlockHash), String> {
+    let (height, hash) = s
+        .split_once(':')
+        .ok_or_else(|| format!("invalid checkpoint {:?}: expected `<height>:<hash>`", s))?;
+    let height = height
+        .parse::<Height>()
+        .map_err(|e| format!("invalid checkpoint height {:?}: {}", height, e))?;
+    let hash = BlockHash::from_hex(hash)
+        .map_err(|e| format!("invalid checkpoint hash {:?}: {}", hash, e))?;
+
+    Ok((height, hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoints() {
+        for checkpoints in [MAINNET, TESTNET, TESTNET4, SCALENET, CHIPNET, REGTEST] {
+            let mut prev = None;
+
+            for (height, hash) in checkpoints {
+                let parsed = parse(&format!("{}:{}", height, hash)).unwrap();
+                assert_eq!(parsed.0, *height);
+                // Block hashes at checkpoint heights have proof-of-work.
+                assert!(hash.starts_with("00000000"), "{}", hash);
+                // Checkpoints are sorted by height.
+                assert!(prev < Some(*height));
+                prev = Some(*height);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(
+            parse("5000:000000009f092d074574a216faec682040a853c4f079c33dfd2c3ef1fd8108c4").is_ok()
+        );
+        assert!(parse("5000").is_err());
+        assert!(
+            parse("x:000000009f092d074574a216faec682040a853c4f079c33dfd2c3ef1fd8108c4").is_err()
+        );
+        assert!(parse("5000:0000").is_err());
+    }
+}
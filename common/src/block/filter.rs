@@ -14,6 +14,11 @@ use super::Height;
 use crate::block::store::{self, Genesis};
 use crate::network::Network;
 
+/// Filter type of BIP 158 basic filters, the only type served by peers signaling
+/// `NODE_COMPACT_FILTERS`. Basic filters commit to the output scripts created and spent by a
+/// block, ie. the locking bytecode of outputs, without any token data.
+pub const BASIC_FILTER_TYPE: u8 = 0x00;
+
 impl Genesis for FilterHeader {
     /// Filter header for the genesis block.
     ///
@@ -35,6 +35,25 @@ pub const SIGNET: &[u8; 32] = &[
     0x7c, 0x9f, 0xf9, 0xf0, 0x1f, 0x2c, 0x42, 0x25,
     0xe9, 0x73, 0x98, 0x81, 0x08, 0x00, 0x00, 0x00
 ];
+
+#[rustfmt::skip]
+/// Bitcoin Cash testnet4 genesis hash.
+pub const TESTNET4: &[u8; 32] = &[
+    0x7b, 0x9f, 0xfd, 0x44, 0xdd, 0x73, 0xc0, 0x5f,
+    0x2a, 0x15, 0xd3, 0x74, 0x74, 0x79, 0xcc, 0x18,
+    0x17, 0x75, 0x26, 0xce, 0x68, 0x86, 0x78, 0x9a,
+    0xc4, 0x10, 0xd4, 0x1d, 0x00, 0x00, 0x00, 0x00,
+];
+
+#[rustfmt::skip]
+/// Bitcoin Cash scalenet genesis hash.
+pub const SCALENET: &[u8; 32] = &[
+    0x52, 0xbe, 0x30, 0x04, 0x9f, 0x59, 0xa4, 0x91,
+    0x02, 0x7d, 0xe8, 0xb8, 0xdb, 0x11, 0xeb, 0x02,
+    0x60, 0xf8, 0x23, 0x90, 0xa1, 0xff, 0xe1, 0xdf,
+    0xc2, 0x3d, 0x45, 0xe6, 0x00, 0x00, 0x00, 0x00,
+];
+
 /// Bitcoin chipnet genesis hash.
 pub const CHIPNET: &[u8; 32] = &[
     123, 159, 253, 68, 221, 115, 192, 95, 42, 21, 211, 116, 116, 121, 204, 24, 23, 117, 38, 206,
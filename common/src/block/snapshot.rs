@@ -0,0 +1,149 @@
+//! Trusted snapshots of wallet-relevant chain state.
+//!
+//! A snapshot holds the block headers leading up to a given block, along with the unspent
+//! outputs of watched scripts as of that block. Importing one lets a new wallet with an old
+//! birth height skip scanning the blocks it covers.
+use std::io;
+
+use bitcoin::consensus::encode::{self, Decodable, Encodable, VarInt};
+use bitcoin::{OutPoint, TxOut};
+use bitcoincash as bitcoin;
+
+use crate::block::{BlockHash, BlockHeader, Height};
+
+/// Maximum number of items of each kind decoded from a snapshot.
+pub const MAX_SNAPSHOT_ITEMS: u64 = 10_000_000;
+
+/// An unspent output included in a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotUtxo {
+    /// The output's outpoint.
+    pub outpoint: OutPoint,
+    /// The output.
+    pub txout: TxOut,
+    /// Height of the block including the output.
+    pub height: Height,
+}
+
+impl Encodable for SnapshotUtxo {
+    fn consensus_encode<W: io::Write + ?Sized>(&self, e: &mut W) -> Result<usize, io::Error> {
+        let mut len = 0;
+        len += self.outpoint.consensus_encode(e)?;
+        len += self.txout.consensus_encode(e)?;
+        len += self.height.consensus_encode(e)?;
+        Ok(len)
+    }
+}
+
+impl Decodable for SnapshotUtxo {
+    fn consensus_decode<D: io::Read + ?Sized>(d: &mut D) -> Result<Self, encode::Error> {
+        let outpoint = OutPoint::consensus_decode(d)?;
+        let txout = TxOut::consensus_decode(d)?;
+        let height = Height::consensus_decode(d)?;
+
+        Ok(SnapshotUtxo {
+            outpoint,
+            txout,
+            height,
+        })
+    }
+}
+
+/// A trusted snapshot of wallet-relevant chain state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    /// Height of the snapshot block, ie. the last of the headers.
+    pub height: Height,
+    /// Consecutive block headers, up to and including the snapshot block. They must connect
+    /// to the importing node's chain.
+    pub headers: Vec<BlockHeader>,
+    /// Unspent outputs of watched scripts, as of the snapshot block.
+    pub utxos: Vec<SnapshotUtxo>,
+}
+
+impl Snapshot {
+    /// Hash of the snapshot block. Returns `None` if the snapshot has no headers.
+    pub fn hash(&self) -> Option<BlockHash> {
+        self.headers.last().map(|h| h.block_hash())
+    }
+}
+
+impl Encodable for Snapshot {
+    fn consensus_encode<W: io::Write + ?Sized>(&self, e: &mut W) -> Result<usize, io::Error> {
+        let mut len = 0;
+        len += self.height.consensus_encode(e)?;
+        len += VarInt(self.headers.len() as u64).consensus_encode(e)?;
+        for header in &self.headers {
+            len += header.consensus_encode(e)?;
+        }
+        len += VarInt(self.utxos.len() as u64).consensus_encode(e)?;
+        for utxo in &self.utxos {
+            len += utxo.consensus_encode(e)?;
+        }
+        Ok(len)
+    }
+}
+
+impl Decodable for Snapshot {
+    fn consensus_decode<D: io::Read + ?Sized>(d: &mut D) -> Result<Self, encode::Error> {
+        let height = Height::consensus_decode(d)?;
+        let headers = decode_items(d)?;
+        let utxos = decode_items(d)?;
+
+        Ok(Snapshot {
+            height,
+            headers,
+            utxos,
+        })
+    }
+}
+
+/// Decode a length-prefixed list of items.
+fn decode_items<T: Decodable, D: io::Read + ?Sized>(d: &mut D) -> Result<Vec<T>, encode::Error> {
+    let VarInt(count) = VarInt::consensus_decode(d)?;
+
+    if count > MAX_SNAPSHOT_ITEMS {
+        return Err(encode::Error::ParseFailed("too many items in snapshot"));
+    }
+    let mut items = Vec::new();
+    for _ in 0..count {
+        items.push(T::consensus_decode(d)?);
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::consensus::encode::{deserialize, serialize};
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Script, Txid};
+
+    use crate::network::Network;
+
+    #[test]
+    fn test_encode_decode() {
+        let genesis = Network::Regtest.genesis();
+        let header = BlockHeader {
+            prev_blockhash: genesis.block_hash(),
+            ..genesis
+        };
+        let snapshot = Snapshot {
+            height: 1,
+            headers: vec![header],
+            utxos: vec![SnapshotUtxo {
+                outpoint: OutPoint::new(Txid::all_zeros(), 1),
+                txout: TxOut {
+                    value: 1000,
+                    script_pubkey: Script::new(),
+                    token: None,
+                },
+                height: 1,
+            }],
+        };
+        let decoded: Snapshot = deserialize(&serialize(&snapshot)).unwrap();
+
+        assert_eq!(decoded, snapshot);
+        assert_eq!(decoded.hash(), Some(header.block_hash()));
+    }
+}
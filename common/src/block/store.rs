@@ -27,6 +27,9 @@ pub enum Error {
     /// Operation was interrupted.
     #[error("the operation was interrupted")]
     Interrupted,
+    /// The requested header is below the pruning horizon.
+    #[error("header at height {0} was pruned")]
+    Pruned(Height),
 }
 
 /// Represents an object (such as a header), that has a genesis.
@@ -90,6 +93,11 @@ pub trait Store {
     fn len(&self) -> Result<usize, Error>;
     /// Return the store block height.
     fn height(&self) -> Result<Height, Error>;
+    /// Return the pruning horizon. Headers between genesis and this height may not be
+    /// available. Stores that don't prune return `0`.
+    fn horizon(&self) -> Height {
+        0
+    }
     /// Check the store integrity.
     fn check(&self) -> Result<(), Error>;
     /// Heal data corruption.
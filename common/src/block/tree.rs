@@ -5,8 +5,6 @@ use std::collections::BTreeMap;
 use bitcoin::blockdata::block::BlockHeader;
 use bitcoin::consensus::params::Params;
 use bitcoin::hash_types::BlockHash;
-use bitcoin::pow::Target as PowTarget;
-use bitcoin::pow::U256;
 use bitcoin::util::uint::Uint256;
 // use bitcoin::{Block, Network};
 use bitcoincash as bitcoin;
@@ -18,6 +16,9 @@ use crate::block::time::Clock;
 use crate::block::{Bits, BlockTime, Height, Target, Work};
 use crate::nonempty::NonEmpty;
 
+/// Number of blocks over which work is measured by the cash work difficulty algorithm.
+pub const CASH_WORK_WINDOW: Height = 144;
+
 /// An error related to the block tree.
 #[derive(Debug, Error)]
 pub enum Error {
@@ -64,6 +65,14 @@ pub enum Error {
     /// The operation was interrupted.
     #[error("the operation was interrupted")]
     Interrupted,
+
+    /// A block needed to compute a difficulty target isn't available, eg. it was pruned.
+    #[error("block at height {0} is not available")]
+    BlockUnavailable(Height),
+
+    /// There aren't enough blocks before the given height to compute a difficulty target.
+    #[error("not enough blocks before height {0} to compute the difficulty")]
+    InsufficientHistory(Height),
 }
 
 /// A generic block header.
@@ -134,6 +143,10 @@ pub trait BlockTree: BlockReader {
         header: BlockHeader,
         context: &C,
     ) -> Result<ImportResult, Error>;
+    /// Write any buffered block headers out to storage. Called before shutting down.
+    fn flush(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
 }
 
 /// Read block header state.
@@ -171,6 +184,11 @@ pub trait BlockReader {
     }
     /// Return the height of the longest chain.
     fn height(&self) -> Height;
+    /// Return the pruning horizon. Blocks below this height, except genesis, are not
+    /// available.
+    fn horizon(&self) -> Height {
+        0
+    }
     /// Get the tip of the longest chain.
     fn tip(&self) -> (BlockHash, BlockHeader);
     /// Get the last block of the longest chain.
@@ -191,6 +209,31 @@ pub trait BlockReader {
         self.get_block_by_height(0)
             .expect("the genesis block is always present")
     }
+    /// Find the first block of the longest chain with a timestamp at or after the given
+    /// time, eg. to start scanning from a wallet's birth date. Returns `None` if all blocks
+    /// are older.
+    ///
+    /// Block timestamps are binary-searched, though they only roughly increase with height:
+    /// blocks up to [`crate::block::time::MAX_FUTURE_BLOCK_TIME`] newer than the given time
+    /// may come before the block found. Blocks below the pruning horizon are skipped.
+    fn find_by_time(&self, time: BlockTime) -> Option<Height> {
+        if self.genesis().time >= time {
+            return Some(0);
+        }
+        let (mut low, mut high) = (self.horizon().max(1), self.height() + 1);
+
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let header = self.get_block_by_height(mid)?;
+
+            if header.time < time {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        (low <= self.height()).then_some(low)
+    }
     /// Check whether a block hash is known.
     fn is_known(&self, hash: &BlockHash) -> bool;
     /// Check whether a block hash is part of the active chain.
@@ -250,160 +293,694 @@ pub trait BlockReader {
 
         BlockHeader::compact_target_from_u256(&target)
     }
-    /// ASERT DAA
+    /// Get the next difficulty target using the aserti3-2d algorithm, in use since the
+    /// November 15, 2020 upgrade.
+    ///
+    /// See [`asert_difficulty`].
     fn next_asert_difficulty_target(
         &self,
-        last_height: Height,
-        last_time: BlockTime,
-        last_target: Target,
+        height: Height,
+        time: BlockTime,
         params: &Params,
-    ) -> Bits {
-        let anchor = ASERTAnchor {
-            height: last_height as i64,
-            nbits: BlockHeader::compact_target_from_u256(&last_target),
-            prev_timestamp: last_time as i64,
-        };
+    ) -> Result<Bits, Error> {
+        let last = self
+            .get_block_by_height(height)
+            .ok_or(Error::BlockUnavailable(height))?;
 
-        const ASERT_HALFLIFE: i64 = 2 * 24 * 60 * 60;
-        let pow_limit = params.pow_limit;
-        let ref_block_target = Target::from_u64(anchor.nbits as u64).unwrap();
-
-        let time_diff = last_height as i64 - anchor.prev_timestamp;
-        let height_diff = last_height as i64 - anchor.height;
-
-        let exponent: i64 = ((time_diff - params.pow_target_spacing as i64 * (height_diff + 1))
-            * 65536)
-            / ASERT_HALFLIFE;
-        let mut shifts = exponent >> 16;
-        let frac = u16::try_from(shifts).unwrap() as u64;
-        let factor: u32 = 65536
-            + ((195766423245049u64 * frac
-                + 971821376u64 * frac * frac
-                + 5127u64 * frac * frac * frac
-                + (1u64 << 47))
-                >> 48) as u32;
-        let mut next_target = BlockHeader::compact_target_from_u256(&ref_block_target) * factor;
-        shifts -= 16;
-        if shifts <= 0 {
-            next_target >>= -shifts;
-        } else {
-            let next_target_shifted = next_target << shifts;
-            if (next_target_shifted >> shifts) != next_target {
-                next_target = BlockHeader::compact_target_from_u256(&pow_limit);
-            } else {
-                next_target = next_target_shifted;
-            }
-        }
-        next_target
+        asert_difficulty(last, height, time, params)
     }
-    /// November 13, 2017 hard fork
+    /// Get the next difficulty target using the cash work algorithm (cw-144), in use on
+    /// mainnet from the November 13, 2017 hard fork until the activation of ASERT.
+    ///
+    /// See [`cash_work_difficulty`].
     fn next_cash_work_difficulty(
         &self,
         height: Height,
-        last_time: BlockTime,
+        time: BlockTime,
         params: &Params,
-    ) -> Bits {
-        if params.allow_min_difficulty_blocks
-            && last_time as u64
-                > self.get_block_by_height(height).unwrap().time as u64
-                    + params.pow_target_spacing * 2
-        {
-            return BlockHeader::compact_target_from_u256(&params.pow_limit);
-        }
-        // _ = last_time;
-        // let last_height = height;
-        // let first_height = last_height - 144;
-        let indexlast = self.get_suitable_blocks(self.tip().1);
-        let indexfirst =
-            self.get_suitable_blocks(*self.get_block_by_height(self.height() - 144).unwrap());
-
-        self.compute_target(indexfirst, indexlast, &params)
-        // next_target
-    }
-    /// Given a vector of block headers, returns the median block based on their timestamps.
-    fn get_suitable_blocks(&self, block: BlockHeader) -> BlockHeader {
-        // let mut blocks = self.locate_headers(
-        //     &vec![self.get_block_by_height(height - 3).unwrap().block_hash()],
-        //     self.get_block_by_height(height).unwrap().block_hash(),
-        //     3,
-        // );
-
-        let blk2 = *self.get_block(&block.block_hash()).unwrap().1;
-        let blk1 = *self.get_block(&block.prev_blockhash).unwrap().1;
-        let blk0 = *self.get_block(&blk1.prev_blockhash).unwrap().1;
-        let mut blocks: Vec<BlockHeader> = vec![blk0, blk1, blk2];
-        assert!(blocks.len() >= 3, "Need at least 3 blocks to find a median");
-
-        if blocks[0].time > blocks[2].time {
-            std::mem::swap(&mut blocks[0].clone(), &mut blocks[2]);
-        };
-        if blocks[0].time > blocks[1].time {
-            std::mem::swap(&mut blocks[0].clone(), &mut blocks[1]);
+    ) -> Result<Bits, Error> {
+        cash_work_difficulty(
+            |height| self.get_block_by_height(height).copied(),
+            height,
+            time,
+            params,
+        )
+    }
+}
+
+/// Compute the next difficulty target using the aserti3-2d algorithm, given the last block.
+///
+/// The target is derived from the network's [`AsertAnchor`], based on how far ahead or
+/// behind schedule the last block is. `height` is the height of the last block, and `time`
+/// the timestamp of the block built on top of it, which may be mined at the minimum
+/// difficulty on test networks.
+pub fn asert_difficulty(
+    last: &BlockHeader,
+    height: Height,
+    time: BlockTime,
+    params: &Params,
+) -> Result<Bits, Error> {
+    if params.allow_min_difficulty_blocks
+        && time as u64 > last.time as u64 + params.pow_target_spacing * 2
+    {
+        return Ok(BlockHeader::compact_target_from_u256(&params.pow_limit));
+    }
+    // Networks without an anchor, ie. regtest, don't retarget.
+    let anchor = match AsertAnchor::new(params.network) {
+        Some(anchor) => anchor,
+        None => return Ok(last.bits.to_consensus()),
+    };
+    if height < anchor.height {
+        return Err(Error::InsufficientHistory(height));
+    }
+    let target = asert_target(
+        BlockHeader::u256_from_compact_target(anchor.bits),
+        last.time as i64 - anchor.prev_time as i64,
+        (height - anchor.height) as i64,
+        anchor.half_life,
+        params,
+    );
+    Ok(BlockHeader::compact_target_from_u256(&target))
+}
+
+/// Height from which blocks are built on top of with the cash work difficulty algorithm,
+/// ie. blocks whose parent is at or above this height use it, until ASERT activates.
+pub fn cash_work_height(network: bitcoin::Network) -> Height {
+    use bitcoin::Network;
+
+    match network {
+        Network::Bitcoin => 504031,
+        Network::Testnet => 1188697,
+        Network::Testnet4 | Network::Chipnet | Network::Scalenet => 3000,
+        Network::Regtest => 0,
+    }
+}
+
+/// Compute the next difficulty target using the cash work algorithm (cw-144), given a
+/// function returning the header at a given height.
+///
+/// The target is derived from the work done over the last [`CASH_WORK_WINDOW`] blocks,
+/// and the time it took. `height` is the height of the last block, and `time` the
+/// timestamp of the block built on top of it, which may be mined at the minimum
+/// difficulty on test networks.
+pub fn cash_work_difficulty(
+    header_at: impl Fn(Height) -> Option<BlockHeader>,
+    height: Height,
+    time: BlockTime,
+    params: &Params,
+) -> Result<Bits, Error> {
+    let header_at = |height: Height| header_at(height).ok_or(Error::BlockUnavailable(height));
+    let last = header_at(height)?;
+
+    if params.allow_min_difficulty_blocks
+        && time as u64 > last.time as u64 + params.pow_target_spacing * 2
+    {
+        return Ok(BlockHeader::compact_target_from_u256(&params.pow_limit));
+    }
+    let first = height
+        .checked_sub(CASH_WORK_WINDOW)
+        .ok_or(Error::InsufficientHistory(height))?;
+
+    let first = suitable_block(&header_at, first)?;
+    let last = suitable_block(&header_at, height)?;
+    let target = cash_work_target(&header_at, first, last, params)?;
+
+    if target > params.pow_limit {
+        return Ok(BlockHeader::compact_target_from_u256(&params.pow_limit));
+    }
+    Ok(BlockHeader::compact_target_from_u256(&target))
+}
+
+/// Get the block with the median timestamp among the block at the given height and its
+/// two predecessors, along with its height. This limits the effect of skewed timestamps.
+fn suitable_block(
+    header_at: &impl Fn(Height) -> Result<BlockHeader, Error>,
+    height: Height,
+) -> Result<(Height, BlockHeader), Error> {
+    if height < 2 {
+        return Err(Error::InsufficientHistory(height));
+    }
+    let block = |height: Height| header_at(height).map(|header| (height, header));
+    let mut blocks = [block(height - 2)?, block(height - 1)?, block(height)?];
+
+    // Sort with a sorting network, so that the same block as in other implementations is
+    // picked when timestamps are equal.
+    if blocks[0].1.time > blocks[2].1.time {
+        blocks.swap(0, 2);
+    }
+    if blocks[0].1.time > blocks[1].1.time {
+        blocks.swap(0, 1);
+    }
+    if blocks[1].1.time > blocks[2].1.time {
+        blocks.swap(1, 2);
+    }
+    Ok(blocks[1])
+}
+
+/// Compute a target based on the work done after the first block, up to and including
+/// the last, and the time it took. The time span is bounded to between half and twice the
+/// expected time span of the cash work window.
+fn cash_work_target(
+    header_at: &impl Fn(Height) -> Result<BlockHeader, Error>,
+    first: (Height, BlockHeader),
+    last: (Height, BlockHeader),
+    params: &Params,
+) -> Result<Target, Error> {
+    debug_assert!(last.0 > first.0, "Last block must be higher than first");
+
+    let mut work = Work::default();
+    for height in first.0 + 1..=last.0 {
+        work = work + header_at(height)?.work();
+    }
+    let spacing = params.pow_target_spacing;
+    let window = CASH_WORK_WINDOW * spacing;
+    let timespan = (last.1.time as u64)
+        .saturating_sub(first.1.time as u64)
+        .clamp(window / 2, window * 2);
+
+    let work = work.mul_u32(spacing as u32) / Work::from_u64(timespan).unwrap();
+    // The target is `2^256 / work - 1`, computed as `(2^256 - work) / work`.
+    let mut target = !work;
+    target.increment();
+
+    Ok(target / work)
+}
+
+/// The block ASERT difficulty targets are computed from: the last block before the
+/// November 15, 2020 upgrade.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AsertAnchor {
+    /// Height of the anchor block.
+    pub height: Height,
+    /// Difficulty target of the anchor block.
+    pub bits: Bits,
+    /// Timestamp of the anchor block's parent.
+    pub prev_time: BlockTime,
+    /// Time it takes for the difficulty to halve or double, when blocks are one half-life
+    /// behind or ahead of schedule, in seconds.
+    pub half_life: i64,
+}
+
+impl AsertAnchor {
+    /// The anchor of the given network, if it has one.
+    pub fn new(network: bitcoin::Network) -> Option<Self> {
+        use bitcoin::Network;
+
+        const TWO_DAYS: i64 = 2 * 24 * 60 * 60;
+        const ONE_HOUR: i64 = 60 * 60;
+
+        let (height, bits, prev_time, half_life) = match network {
+            Network::Bitcoin => (661647, 0x1804dafe, 1605447844, TWO_DAYS),
+            Network::Testnet => (1421481, 0x1d00ffff, 1605445400, ONE_HOUR),
+            // Chipnet forked off testnet4 after the upgrade, so they share the anchor.
+            Network::Testnet4 | Network::Chipnet => (16844, 0x1d00ffff, 1605451779, ONE_HOUR),
+            Network::Scalenet => (16868, 0x1d00ffff, 1605452820, TWO_DAYS),
+            Network::Regtest => return None,
         };
-        if blocks[1].time > blocks[2].time {
-            std::mem::swap(&mut blocks[1].clone(), &mut blocks[2]);
+        Some(Self {
+            height,
+            bits,
+            prev_time,
+            half_life,
+        })
+    }
+}
+
+/// Compute an ASERT target, given the anchor block's target, and the time and height
+/// differences between the last block and the anchor. The time difference is measured from
+/// the anchor's parent.
+///
+/// This is the integer approximation of
+/// `anchor_target * 2^((time_diff - spacing * (height_diff + 1)) / half_life)` given by the
+/// aserti3-2d specification, which must be followed exactly for consensus.
+pub fn asert_target(
+    anchor_target: Target,
+    time_diff: i64,
+    height_diff: i64,
+    half_life: i64,
+    params: &Params,
+) -> Target {
+    let spacing = params.pow_target_spacing as i64;
+    // Fixed-point exponent, with 16 fractional bits. Division truncates towards zero.
+    let exponent = ((time_diff - spacing * (height_diff + 1)) * 65536) / half_life;
+    // Split the exponent into its integer and fractional parts. The right shift rounds
+    // towards negative infinity, keeping the fractional part positive.
+    let mut shifts = exponent >> 16;
+    let frac = (exponent & 0xffff) as u64;
+    // `65536 * 2^frac`, approximated with a cubic polynomial.
+    let factor = 65536
+        + ((195766423245049 * frac
+            + 971821376 * frac * frac
+            + 5127 * frac * frac * frac
+            + (1 << 47))
+            >> 48) as u32;
+    let mut target = anchor_target.mul_u32(factor);
+
+    // Multiply by `2^shifts / 65536`.
+    shifts -= 16;
+    if shifts <= 0 {
+        target = target >> (-shifts) as usize;
+    } else {
+        let shifted = target << shifts as usize;
+
+        // Bits were shifted out, so the target is beyond the limit.
+        if shifted >> shifts as usize != target {
+            return params.pow_limit;
+        }
+        target = shifted;
+    }
+
+    if target == Target::default() {
+        // Zero isn't a valid target.
+        Target::from_u64(1).unwrap()
+    } else if target > params.pow_limit {
+        params.pow_limit
+    } else {
+        target
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::pow::CompactTarget;
+    use bitcoin::{Network, TxMerkleNode};
+
+    /// A chain of headers. Headers below the horizon, except genesis, are pruned.
+    struct Chain {
+        headers: Vec<BlockHeader>,
+        horizon: Height,
+    }
+
+    impl Chain {
+        fn new(time: BlockTime, bits: Bits) -> Self {
+            Self {
+                headers: vec![header(BlockHash::all_zeros(), time, bits)],
+                horizon: 0,
+            }
+        }
+
+        fn extend(&mut self, interval: i64, bits: Bits) {
+            let (hash, tip) = self.tip();
+            let time = (tip.time as i64 + interval) as BlockTime;
+
+            self.headers.push(header(hash, time, bits));
+        }
+    }
+
+    impl BlockReader for Chain {
+        fn get_block(&self, _hash: &BlockHash) -> Option<(Height, &BlockHeader)> {
+            unimplemented!()
+        }
+
+        fn get_block_by_height(&self, height: Height) -> Option<&BlockHeader> {
+            if height > 0 && height < self.horizon {
+                return None;
+            }
+            self.headers.get(height as usize)
+        }
+
+        fn find_branch(&self, _to: &BlockHash) -> Option<(Height, NonEmpty<BlockHeader>)> {
+            unimplemented!()
+        }
+
+        fn chain_work(&self) -> Uint256 {
+            unimplemented!()
+        }
+
+        fn iter<'a>(&'a self) -> Box<dyn DoubleEndedIterator<Item = (Height, BlockHeader)> + 'a> {
+            Box::new(
+                self.headers
+                    .iter()
+                    .enumerate()
+                    .map(|(h, header)| (h as Height, *header)),
+            )
+        }
+
+        fn height(&self) -> Height {
+            self.headers.len() as Height - 1
+        }
+
+        fn tip(&self) -> (BlockHash, BlockHeader) {
+            let tip = self.headers[self.headers.len() - 1];
+            (tip.block_hash(), tip)
+        }
+
+        fn last_checkpoint(&self) -> Height {
+            0
+        }
+
+        fn checkpoints(&self) -> BTreeMap<Height, BlockHash> {
+            BTreeMap::new()
+        }
+
+        fn is_known(&self, _hash: &BlockHash) -> bool {
+            unimplemented!()
+        }
+
+        fn contains(&self, _hash: &BlockHash) -> bool {
+            unimplemented!()
+        }
+
+        fn locate_headers(
+            &self,
+            _locators: &[BlockHash],
+            _stop_hash: BlockHash,
+            _max_headers: usize,
+        ) -> Vec<BlockHeader> {
+            unimplemented!()
+        }
+
+        fn locator_hashes(&self, _from: Height) -> Vec<BlockHash> {
+            unimplemented!()
+        }
+    }
+
+    fn header(prev_blockhash: BlockHash, time: BlockTime, bits: Bits) -> BlockHeader {
+        BlockHeader {
+            version: 4,
+            prev_blockhash,
+            merkle_root: TxMerkleNode::all_zeros(),
+            time,
+            bits: CompactTarget::from_consensus(bits),
+            nonce: 0,
+        }
+    }
+
+    /// Mainnet parameters, with the proof-of-work limit used by Bitcoin ABC and derived nodes.
+    fn params() -> Params {
+        Params {
+            pow_limit: Uint256([u64::MAX, u64::MAX, u64::MAX, 0xffff_ffff]),
+            ..Params::new(Network::Bitcoin)
+        }
+    }
+
+    // Vectors from the cash work difficulty test of Bitcoin ABC.
+    #[test]
+    fn test_cash_work_difficulty() {
+        let params = params();
+        let pow_limit_bits = BlockHeader::compact_target_from_u256(&params.pow_limit);
+        let initial = BlockHeader::compact_target_from_u256(&(params.pow_limit >> 4));
+        let mut chain = Chain::new(1269211443, initial);
+        let next = |chain: &Chain| {
+            chain
+                .next_cash_work_difficulty(chain.height(), 0, &params)
+                .unwrap()
         };
-        return blocks[1];
+        let target = BlockHeader::u256_from_compact_target;
+
+        // Pile up some blocks every 10 minutes to establish some history.
+        for _ in 1..2050 {
+            chain.extend(600, initial);
+        }
+        let mut bits = next(&chain);
+        assert_eq!(bits, initial);
+
+        // Difficulty stays the same as long as a block is produced every 10 minutes.
+        for _ in 0..10 {
+            chain.extend(600, bits);
+            assert_eq!(next(&chain), bits);
+        }
+
+        // Blocks with skewed timestamps are skipped over.
+        chain.extend(6000, bits);
+        assert_eq!(next(&chain), bits);
+        chain.extend(2 * 600 - 6000, bits);
+        assert_eq!(next(&chain), bits);
+
+        for _ in 0..20 {
+            chain.extend(600, bits);
+            assert_eq!(next(&chain), bits);
+        }
+
+        // Blocks are produced slightly faster. The first block has no impact.
+        chain.extend(550, bits);
+        assert_eq!(next(&chain), bits);
+
+        // Difficulty then increases slowly.
+        for _ in 0..10 {
+            chain.extend(550, bits);
+
+            let (current, next) = (target(bits), target(next(&chain)));
+            assert!(next < current);
+            assert!(current - next < current >> 10);
+
+            bits = BlockHeader::compact_target_from_u256(&next);
+        }
+        assert_eq!(bits, 0x1c0fe7b1);
+
+        // When block production is dramatically shortened, difficulty increases faster.
+        for _ in 0..20 {
+            chain.extend(10, bits);
+
+            let (current, next) = (target(bits), target(next(&chain)));
+            assert!(next < current);
+            assert!(current - next < current >> 4);
+
+            bits = BlockHeader::compact_target_from_u256(&next);
+        }
+        assert_eq!(bits, 0x1c0db19f);
+
+        // Blocks are produced significantly slower.
+        chain.extend(6000, bits);
+        bits = next(&chain);
+        assert_eq!(bits, 0x1c0d9222);
+
+        // Difficulty decreases.
+        for _ in 0..93 {
+            chain.extend(6000, bits);
+
+            let (current, next) = (target(bits), target(next(&chain)));
+            assert!(next <= params.pow_limit);
+            assert!(next > current);
+            assert!(next - current < current >> 3);
+
+            bits = BlockHeader::compact_target_from_u256(&next);
+        }
+        assert_eq!(bits, 0x1c2f13b9);
+
+        // Since the time span is bounded, the next block's difficulty is actually higher.
+        chain.extend(6000, bits);
+        bits = next(&chain);
+        assert_eq!(bits, 0x1c2ee9bf);
+
+        // And decreases again, until it reaches the minimum.
+        for _ in 0..192 {
+            chain.extend(6000, bits);
+
+            let (current, next) = (target(bits), target(next(&chain)));
+            assert!(next <= params.pow_limit);
+            assert!(next > current);
+            assert!(next - current < current >> 3);
+
+            bits = BlockHeader::compact_target_from_u256(&next);
+        }
+        assert_eq!(bits, 0x1d00ffff);
+
+        // Once at the minimum, it doesn't get any easier.
+        for _ in 0..5 {
+            chain.extend(6000, bits);
+            bits = next(&chain);
+            assert_eq!(bits, pow_limit_bits);
+        }
     }
 
-    /// Compute a target based on the work done between 2 blocks and the time
-    /// required to produce that work.
-    fn compute_target(
-        &self,
-        first_height: BlockHeader,
-        last_height: BlockHeader,
-        params: &Params,
-    ) -> Bits {
-        assert!(
-            self.get_block(&last_height.block_hash()).unwrap().0
-                >= self.get_block(&first_height.block_hash()).unwrap().0,
-            "Last block must have a higher height than first"
+    #[test]
+    fn test_cash_work_difficulty_boundaries() {
+        let params = params();
+        let bits = BlockHeader::compact_target_from_u256(&(params.pow_limit >> 4));
+        let mut chain = Chain::new(1269211443, bits);
+
+        for _ in 0..CASH_WORK_WINDOW {
+            chain.extend(600, bits);
+        }
+        // Near genesis, there isn't enough history.
+        assert!(matches!(
+            chain.next_cash_work_difficulty(CASH_WORK_WINDOW, 0, &params),
+            Err(Error::InsufficientHistory(_))
+        ));
+        assert!(matches!(
+            chain.next_cash_work_difficulty(1, 0, &params),
+            Err(Error::InsufficientHistory(1))
+        ));
+        // Unknown blocks.
+        assert!(matches!(
+            chain.next_cash_work_difficulty(CASH_WORK_WINDOW + 1, 0, &params),
+            Err(Error::BlockUnavailable(h)) if h == CASH_WORK_WINDOW + 1
+        ));
+
+        for _ in 0..CASH_WORK_WINDOW {
+            chain.extend(600, bits);
+        }
+        assert_eq!(
+            chain
+                .next_cash_work_difficulty(chain.height(), 0, &params)
+                .unwrap(),
+            bits
         );
-        let target_spacing = params.pow_target_spacing as u128;
 
-        let daa_block_work = self.locate_headers(
-            &vec![first_height.block_hash()],
-            last_height.block_hash(),
-            150,
+        // Pruned blocks.
+        chain.horizon = chain.height() - CASH_WORK_WINDOW;
+        assert!(matches!(
+            chain.next_cash_work_difficulty(chain.height(), 0, &params),
+            Err(Error::BlockUnavailable(_))
+        ));
+
+        // Test networks allow minimum difficulty blocks, regardless of history.
+        let testnet = Params::new(Network::Testnet);
+        let (_, tip) = chain.tip();
+        assert_eq!(
+            chain
+                .next_cash_work_difficulty(chain.height(), tip.time + 1201, &testnet)
+                .unwrap(),
+            BlockHeader::compact_target_from_u256(&testnet.pow_limit)
         );
-        // let daa_len = daa_block_work.len().clone();
-        let mut daa_work = U256::default();
-        for header in daa_block_work {
-            daa_work = daa_work + header.get_work().0;
+    }
+
+    // Expected values follow exactly from the aserti3-2d specification: when the last block
+    // is a whole number of half-lives behind or ahead of schedule, the exponent has no
+    // fractional part, and the anchor target is multiplied or divided by a power of two,
+    // before the result is bounded by the limit and by one.
+    #[test]
+    fn test_asert_target() {
+        let params = Params::new(Network::Bitcoin);
+        let mainnet = AsertAnchor::new(Network::Bitcoin).unwrap();
+        let testnet4 = AsertAnchor::new(Network::Testnet4).unwrap();
+        let chipnet = AsertAnchor::new(Network::Chipnet).unwrap();
+        let (day2, hour) = (mainnet.half_life, testnet4.half_life);
+        let vectors = [
+            // Steady blocks, on schedule: the target doesn't change.
+            (mainnet, 0x1d00ffff, 600, 0, 0x1d00ffff),
+            (mainnet, 0x1804dafe, 600 * 1001, 1000, 0x1804dafe),
+            (mainnet, 0x1a2b3c4d, 600 * 11, 10, 0x1a2b3c4d),
+            (mainnet, 0x01010000, 600 * 11, 10, 0x01010000),
+            (testnet4, 0x1d00ffff, 600 * 11, 10, 0x1d00ffff),
+            // Whole half-lives ahead of and behind schedule.
+            (mainnet, 0x1804dafe, 600 * 289 - day2, 288, 0x18026d7f),
+            (mainnet, 0x1804dafe, 600 * 289 + day2, 288, 0x1809b5fc),
+            (mainnet, 0x1a2b3c4d, 600 * 1001 - day2 * 3, 1000, 0x1a056789),
+            (mainnet, 0x1a2b3c4d, 600 * 1001 + day2 * 5, 1000, 0x1b056789),
+            (testnet4, 0x1d00ffff, 600 * 11 - hour, 10, 0x1c7fff80),
+            (chipnet, 0x1d00ffff, 600 * 11 - hour * 4, 10, 0x1c0ffff0),
+            // Targets are bounded by the limit and by one.
+            (testnet4, 0x1d00ffff, 600 * 11 + hour, 10, 0x1d00ffff),
+            (mainnet, 0x1804dafe, 600 * 101 + day2 * 200, 100, 0x1d00ffff),
+            (mainnet, 0x1804dafe, 600 * 101 - day2 * 200, 100, 0x01010000),
+        ];
+        assert_eq!(testnet4, chipnet);
+
+        for (anchor, bits, time_diff, height_diff, expected) in vectors {
+            let target = asert_target(
+                BlockHeader::u256_from_compact_target(bits),
+                time_diff,
+                height_diff,
+                anchor.half_life,
+                &params,
+            );
+            assert_eq!(
+                BlockHeader::compact_target_from_u256(&target),
+                expected,
+                "{:?} {} {}",
+                anchor,
+                time_diff,
+                height_diff
+            );
         }
-        daa_work = daa_work * U256::from(target_spacing);
+    }
 
-        let mut time_span = (last_height.time - first_height.time) as u128;
+    #[test]
+    fn test_asert_difficulty() {
+        let params = Params::new(Network::Testnet4);
+        let anchor = AsertAnchor::new(Network::Testnet4).unwrap();
+        let genesis = anchor.prev_time - (anchor.height as BlockTime - 1) * 600;
+        let mut chain = Chain::new(genesis, anchor.bits);
 
-        if time_span > 288 * target_spacing {
-            time_span = 288 * target_spacing;
-        } else if time_span < 72 * target_spacing {
-            time_span = 72 * target_spacing;
+        for _ in 1..anchor.height {
+            chain.extend(600, anchor.bits);
         }
+        assert!(matches!(
+            chain.next_asert_difficulty_target(chain.height(), 0, &params),
+            Err(Error::InsufficientHistory(_))
+        ));
+        // The anchor block.
+        chain.extend(600, anchor.bits);
+        let (_, tip) = chain.tip();
+        assert_eq!(
+            chain
+                .next_asert_difficulty_target(chain.height(), tip.time + 600, &params)
+                .unwrap(),
+            anchor.bits
+        );
 
-        // let projected_work = daa_work * (U256::from(params.pow_target_spacing));
-        let projected_work = daa_work / U256::from(time_span);
-
-        let mut new_target = U256::inverse(&projected_work);
-        if new_target > PowTarget::MAX.0 {
-            new_target = PowTarget::MAX.0
+        // Two hours ahead of schedule.
+        for _ in 0..10 {
+            chain.extend(-120, anchor.bits);
         }
-        PowTarget(new_target).to_compact_lossy().to_consensus()
+        let (_, tip) = chain.tip();
+        assert_eq!(
+            chain
+                .next_asert_difficulty_target(chain.height(), tip.time + 600, &params)
+                .unwrap(),
+            0x1c3fffc0
+        );
+        // Blocks found after more than twice the spacing can be mined at minimum difficulty.
+        assert_eq!(
+            chain
+                .next_asert_difficulty_target(chain.height(), tip.time + 1201, &params)
+                .unwrap(),
+            BlockHeader::compact_target_from_u256(&params.pow_limit)
+        );
+
+        // Regtest doesn't retarget.
+        let regtest = Params::new(Network::Regtest);
+        assert_eq!(
+            chain
+                .next_asert_difficulty_target(chain.height(), tip.time, &regtest)
+                .unwrap(),
+            anchor.bits
+        );
     }
-}
 
-#[derive(Debug, Clone, Copy)]
-struct ASERTAnchor {
-    pub height: i64,         // 661647,
-    pub nbits: u32,          // 0x1804dafe,
-    pub prev_timestamp: i64, // 1605447844,
-}
-impl Default for ASERTAnchor {
-    fn default() -> Self {
-        ASERTAnchor {
-            height: 661647,
-            nbits: 0x1804dafe,
-            prev_timestamp: 1605447844,
+    #[test]
+    fn test_suitable_block() {
+        let mut chain = Chain::new(0, 0x1d00ffff);
+        let header_at = |chain: &Chain, height: Height| {
+            chain
+                .get_block_by_height(height)
+                .copied()
+                .ok_or(Error::BlockUnavailable(height))
+        };
+
+        chain.extend(3, 0x1d00ffff);
+        chain.extend(-2, 0x1d00ffff);
+        // Timestamps are 0, 3 and 1.
+        assert_eq!(suitable_block(&|h| header_at(&chain, h), 2).unwrap().0, 2);
+
+        chain.extend(2, 0x1d00ffff);
+        // Timestamps are 3, 1 and 3. The first of the blocks with equal timestamps is picked.
+        assert_eq!(suitable_block(&|h| header_at(&chain, h), 3).unwrap().0, 1);
+    }
+
+    #[test]
+    fn test_find_by_time() {
+        let mut chain = Chain::new(1000, 0x1d00ffff);
+
+        for _ in 0..10 {
+            chain.extend(600, 0x1d00ffff);
         }
+        // Timestamps are 1000, 1600, .., 7000.
+        assert_eq!(chain.find_by_time(0), Some(0));
+        assert_eq!(chain.find_by_time(1000), Some(0));
+        assert_eq!(chain.find_by_time(1001), Some(1));
+        assert_eq!(chain.find_by_time(4600), Some(6));
+        assert_eq!(chain.find_by_time(4601), Some(7));
+        assert_eq!(chain.find_by_time(7000), Some(10));
+        assert_eq!(chain.find_by_time(7001), None);
+
+        // Pruned blocks are skipped.
+        chain.horizon = 5;
+        assert_eq!(chain.find_by_time(1001), Some(5));
+        assert_eq!(chain.find_by_time(4601), Some(7));
     }
 }
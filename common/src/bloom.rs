@@ -1,4 +1,2 @@
-/// bloom filter type function
-// pub mod cache;
+//! Bloom filter (BIP 37) types shared between the client and wallet.
 pub mod store;
-// pub use nakamoto_common::bitcoin::util::bloom::BloomFilter;
@@ -1,49 +1,54 @@
-// use bitcoincash::consensus::encode;
-// pub mod cache;
-// /// bloom store io
-// pub mod io;
+//! Storage of bloom filter privacy segments.
+#![allow(clippy::len_without_is_empty)]
+use bitcoincash::consensus::encode;
+use thiserror::Error;
+
+pub mod cache;
+pub mod io;
 // pub mod memory;
 
-// pub use io::File;
+pub use io::File;
 // pub use memory::Memory;
 
-// /// Represents objects that can store bloom filter segments.
-// use thiserror::Error;
+/// A privacy segment storage error.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// An I/O error.
+    #[error("i/o error: {0}")]
+    Io(#[from] std::io::Error),
+    /// An error decoding segment data.
+    #[error("error decoding segment: {0}")]
+    Decoding(#[from] encode::Error),
+    /// A data-corruption error.
+    #[error("error: the store data is corrupt")]
+    Corruption,
+    /// Operation was interrupted.
+    #[error("the operation was interrupted")]
+    Interrupted,
+}
+
+/// Represents objects that can store bloom filter privacy segments, keyed by segment id.
+pub trait Store {
+    /// The type used in the store.
+    type PrivacySegment: Sized;
 
-// /// A block storage error.
-// #[derive(Debug, Error)]
-// pub enum Error {
-//     /// An I/O error.
-//     #[error("i/o error: {0}")]
-//     Io(#[from] std::io::Error),
-//     /// An error decoding block data.
-//     #[error("error decoding header: {0}")]
-//     Decoding(#[from] encode::Error),
-//     /// A data-corruption error.
-//     #[error("error: the store data is corrupt")]
-//     Corruption,
-//     /// Operation was interrupted.
-//     #[error("the operation was interrupted")]
-//     Interrupted,
-// }
-// /// Bloomfilter cache trait
-// pub trait Store {
-//     /// The type used in the store.
-//     type PrivacySegment: Sized;
-//     /// default bloom
-//     fn default(&self) -> Self::PrivacySegment;
-//     /// Append a batch of consecutive bloom filters to the end of the .
-//     fn put<I: Iterator<Item = Self::PrivacySegment>>(&mut self, headers: I) -> Result<u32, Error>;
-//     /// Get the filter for a script.
-//     fn get(&self, segment_id: u32) -> Result<Self::PrivacySegment, Error>;
-//     /// Synchronize the changes to disk.
-//     fn sync(&mut self) -> Result<(), Error>;
-//     /// Iterate over all headers in the store.
-//     fn iter(&self) -> Box<dyn Iterator<Item = Result<(u32, Self::PrivacySegment), Error>>>;
-//     /// Return the number of headers in the store.
-//     fn len(&self) -> Result<usize, Error>;
-//     /// Check the store integrity.
-//     fn check(&self) -> Result<(), Error>;
-//     /// Heal data corruption.
-//     fn heal(&self) -> Result<(), Error>;
-// }
+    /// Append a batch of consecutive segments to the end of the store. Returns the number
+    /// of segments in the store.
+    fn put<I: Iterator<Item = Self::PrivacySegment>>(&mut self, segments: I) -> Result<u32, Error>;
+    /// Overwrite the segment with the given id, which must be in the store.
+    fn update(&mut self, segment_id: u32, segment: Self::PrivacySegment) -> Result<(), Error>;
+    /// Get the segment with the given id.
+    fn get(&self, segment_id: u32) -> Result<Self::PrivacySegment, Error>;
+    /// Rollback the store to the given number of segments.
+    fn rollback(&mut self, len: u32) -> Result<(), Error>;
+    /// Synchronize the changes to disk.
+    fn sync(&mut self) -> Result<(), Error>;
+    /// Iterate over all segments in the store.
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<(u32, Self::PrivacySegment), Error>>>;
+    /// Return the number of segments in the store.
+    fn len(&self) -> Result<usize, Error>;
+    /// Check the store integrity.
+    fn check(&self) -> Result<(), Error>;
+    /// Heal data corruption.
+    fn heal(&self) -> Result<(), Error>;
+}
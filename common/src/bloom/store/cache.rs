@@ -1,122 +1,386 @@
-// //! Bloom filter cache.
-
-// #![allow(dead_code)]
-
-// use std::io;
-// use std::ops::ControlFlow;
-// // use std::ops::RangeInclusive;
-
-// use bitcoincash::consensus::{encode, Decodable, Encodable};
-
-// use crate::bitcoin::util::bloom::BloomFilter;
-// use crate::block::Height;
-// use crate::bloom::store::{Error, Store};
-// use crate::nonempty::NonEmpty;
-
-// ///
-// #[derive(Debug, Clone /* Copy */)]
-// pub struct PrivacySegment {
-//     /// segment id
-//     pub segment: u32,
-//     /// this segments bloom filter
-//     pub filter: BloomFilter,
-//     /// first [Height] in which this segment was used in chain.
-//     pub birth: Height,
-//     /// Last [Height] in which this segment synced.
-//     pub synced_height: Height,
-//     /// is the segment currently set
-//     pub is_enabled: bool,
-// }
-
-// impl Default for PrivacySegment {
-//     fn default() -> Self {
-//         Self {
-//             filter: BloomFilter::default(),
-//             segment: 0,
-//             birth: 0,
-//             synced_height: 0,
-//             is_enabled: false,
-//         }
-//     }
-// }
-
-// impl Encodable for PrivacySegment {
-//     fn consensus_encode<W: io::Write + ?Sized>(&self, e: &mut W) -> Result<usize, io::Error> {
-//         let mut len = 0;
-//         len += self.segment.consensus_encode(e)?;
-//         len += self.filter.consensus_encode(e)?;
-//         len += self.birth.consensus_encode(e)?;
-//         len += self.synced_height.consensus_encode(e)?;
-//         len += self.is_enabled.consensus_encode(e)?;
-//         Ok(len)
-//     }
-// }
-
-// impl Decodable for PrivacySegment {
-//     fn consensus_decode<D: io::Read + ?Sized>(d: &mut D) -> Result<Self, encode::Error> {
-//         let segment = u32::consensus_decode(d)?;
-//         let filter = BloomFilter::consensus_decode(d)?;
-//         let birth = Height::consensus_decode(d)?;
-//         let synced_height = Height::consensus_decode(d)?;
-//         let is_enabled = bool::consensus_decode(d)?;
-
-//         Ok(PrivacySegment {
-//             segment,
-//             filter,
-//             birth,
-//             synced_height,
-//             is_enabled,
-//         })
-//     }
-// }
-// /// A privacy segment filter cache
-// pub struct FilterCache<S> {
-//     filters: NonEmpty<PrivacySegment>,
-//     filter_store: S,
-// }
-// impl<S: Store<PrivacySegment = PrivacySegment>> FilterCache<S> {
-//     /// loads the [PrivacySegment]
-//     pub fn load(filter_store: S) -> Result<Self, Error> {
-//         Self::load_with(filter_store, |_| ControlFlow::Continue(()))
-//     }
-//     /// called from [FilterCache::load]
-//     pub fn load_with(
-//         filter_store: S,
-//         progress: impl Fn(Height) -> ControlFlow<()>,
-//     ) -> Result<Self, Error> {
-//         let mut filters = NonEmpty::new(filter_store.default());
-
-//         for (segment, result) in filter_store.iter().enumerate() {
-//             let (_, bloom_filter) = result?;
-//             filters.push(bloom_filter);
-
-//             if progress(segment as Height).is_break() {
-//                 return Err(Error::Interrupted);
-//             }
-//         }
-
-//         Ok(Self {
-//             filter_store,
-//             filters,
-//         })
-//     }
-//     /// update segment state to disk
-//     pub fn update_segments(mut filter_store: S) -> Result<(), Error> {
-//         if filter_store.sync().is_ok() {
-//             Ok(())
-//         } else {
-//             Err(Error::Corruption)
-//         }
-//     }
-// }
-
-// // impl<S: Store<PrivacySegment = PrivacySegment>> Store for FilterCache<S> {
-// //     fn check(&self) -> Result<(), Error> {}
-// //     fn default(&self) -> Self::PrivacySegment {}
-// //     fn get(&self, segment_id: u32) -> Result<Self::PrivacySegment, Error> {}
-// //     fn heal(&self) -> Result<(), Error> {}
-// //     fn iter(&self) -> Box<dyn Iterator<Item = Result<(u32, Self::PrivacySegment), Error>>> {}
-// //     fn len(&self) -> Result<usize, Error> {}
-// //     fn put<I: Iterator<Item = Self::PrivacySegment>>(&mut self, headers: I) -> Result<u32, Error> {}
-// //     fn sync(&mut self) -> Result<(), Error> {}
-// // }
+//! Bloom filter cache.
+
+use std::io;
+use std::ops::ControlFlow;
+
+use bitcoincash::consensus::{encode, Decodable, Encodable};
+
+use crate::bitcoin::util::bloom::{BloomFilter, Decoys};
+use crate::block::Height;
+use crate::bloom::store::{Error, Store};
+use crate::collections::HashMap;
+
+/// Default bloom filter false-positive rate.
+pub const DEFAULT_FP_RATE: f64 = 0.0001;
+
+/// A set of watched elements that is loaded onto peers as a single bloom filter.
+///
+/// Splitting the wallet's elements across several segments limits what any one
+/// peer learns about the wallet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrivacySegment {
+    /// Segment id.
+    pub segment: u32,
+    /// This segment's bloom filter.
+    pub filter: BloomFilter,
+    /// First [`Height`] at which this segment was used in the chain.
+    pub birth: Height,
+    /// Last [`Height`] up to which this segment was synced.
+    pub synced_height: Height,
+    /// Whether the segment is currently in use.
+    pub is_enabled: bool,
+}
+
+impl Default for PrivacySegment {
+    fn default() -> Self {
+        Self {
+            filter: BloomFilter::default(),
+            segment: 0,
+            birth: 0,
+            synced_height: 0,
+            is_enabled: false,
+        }
+    }
+}
+
+impl PrivacySegment {
+    /// Split the given elements into `count` privacy segments, each with its own
+    /// bloom filter sized for the given false-positive rate, including decoys.
+    ///
+    /// Segments that end up without any element are disabled.
+    pub fn split(
+        elements: &[&[u8]],
+        count: usize,
+        fp_rate: f64,
+        decoys: Decoys,
+        birth: Height,
+        rng: fastrand::Rng,
+    ) -> HashMap<u32, PrivacySegment> {
+        let count = count.max(1);
+        let mut segments = HashMap::with_hasher(rng.clone().into());
+
+        for id in 0..count {
+            let elements = elements.iter().skip(id).step_by(count).collect::<Vec<_>>();
+            let decoys = decoys.count(elements.len());
+            let mut filter = BloomFilter::new(elements.len() + decoys, fp_rate, rng.u32(..), 0);
+
+            for element in &elements {
+                filter.insert(element);
+            }
+            filter.insert_decoys(decoys, rng.u64(..));
+            segments.insert(
+                id as u32,
+                PrivacySegment {
+                    segment: id as u32,
+                    filter,
+                    birth,
+                    synced_height: birth,
+                    is_enabled: !elements.is_empty(),
+                },
+            );
+        }
+        segments
+    }
+}
+
+impl Encodable for PrivacySegment {
+    fn consensus_encode<W: io::Write + ?Sized>(&self, e: &mut W) -> Result<usize, io::Error> {
+        let mut len = 0;
+        len += self.segment.consensus_encode(e)?;
+        len += self.filter.content.consensus_encode(e)?;
+        len += self.filter.hashes.consensus_encode(e)?;
+        len += self.filter.tweak.consensus_encode(e)?;
+        len += self.filter.flags.consensus_encode(e)?;
+        len += self.birth.consensus_encode(e)?;
+        len += self.synced_height.consensus_encode(e)?;
+        len += self.is_enabled.consensus_encode(e)?;
+        Ok(len)
+    }
+}
+
+impl Decodable for PrivacySegment {
+    fn consensus_decode<D: io::Read + ?Sized>(d: &mut D) -> Result<Self, encode::Error> {
+        let segment = u32::consensus_decode(d)?;
+        let filter = BloomFilter {
+            content: Vec::<u8>::consensus_decode(d)?,
+            hashes: u32::consensus_decode(d)?,
+            tweak: u32::consensus_decode(d)?,
+            flags: u8::consensus_decode(d)?,
+        };
+        let birth = Height::consensus_decode(d)?;
+        let synced_height = Height::consensus_decode(d)?;
+        let is_enabled = bool::consensus_decode(d)?;
+
+        Ok(PrivacySegment {
+            segment,
+            filter,
+            birth,
+            synced_height,
+            is_enabled,
+        })
+    }
+}
+
+/// A privacy segment cache, backed by a persistent store.
+///
+/// Reads are served from memory, while writes go through to the underlying store.
+#[derive(Debug)]
+pub struct FilterCache<S> {
+    segments: Vec<PrivacySegment>,
+    store: S,
+}
+
+impl<S: Store<PrivacySegment = PrivacySegment>> FilterCache<S> {
+    /// Load the segment cache from a store.
+    pub fn load(store: S) -> Result<Self, Error> {
+        Self::load_with(store, |_| ControlFlow::Continue(()))
+    }
+
+    /// Load the segment cache from a store, calling `progress` with the id of every
+    /// segment loaded. Returns [`Error::Interrupted`] if `progress` breaks.
+    pub fn load_with(store: S, progress: impl Fn(u32) -> ControlFlow<()>) -> Result<Self, Error> {
+        let mut segments = Vec::with_capacity(store.len()?);
+
+        for result in store.iter() {
+            let (segment_id, segment) = result?;
+            segments.push(segment);
+
+            if progress(segment_id).is_break() {
+                return Err(Error::Interrupted);
+            }
+        }
+
+        Ok(Self { segments, store })
+    }
+
+    /// Iterate over the cached segments.
+    pub fn segments(&self) -> impl Iterator<Item = &PrivacySegment> {
+        self.segments.iter()
+    }
+}
+
+impl<S: Store<PrivacySegment = PrivacySegment>> Store for FilterCache<S> {
+    type PrivacySegment = PrivacySegment;
+
+    fn put<I: Iterator<Item = PrivacySegment>>(&mut self, segments: I) -> Result<u32, Error> {
+        let segments = segments.collect::<Vec<_>>();
+        let len = self.store.put(segments.iter().cloned())?;
+
+        self.segments.extend(segments);
+
+        Ok(len)
+    }
+
+    fn update(&mut self, segment_id: u32, segment: PrivacySegment) -> Result<(), Error> {
+        self.store.update(segment_id, segment.clone())?;
+        self.segments[segment_id as usize] = segment;
+
+        Ok(())
+    }
+
+    fn get(&self, segment_id: u32) -> Result<PrivacySegment, Error> {
+        self.segments
+            .get(segment_id as usize)
+            .cloned()
+            .ok_or_else(|| Error::Io(io::ErrorKind::UnexpectedEof.into()))
+    }
+
+    fn rollback(&mut self, len: u32) -> Result<(), Error> {
+        self.store.rollback(len)?;
+        self.segments.truncate(len as usize);
+
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), Error> {
+        self.store.sync()
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<(u32, PrivacySegment), Error>>> {
+        Box::new(
+            self.segments
+                .clone()
+                .into_iter()
+                .enumerate()
+                .map(|(i, s)| Ok((i as u32, s))),
+        )
+    }
+
+    fn len(&self) -> Result<usize, Error> {
+        Ok(self.segments.len())
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        self.store.check()
+    }
+
+    fn heal(&self) -> Result<(), Error> {
+        self.store.heal()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bloom::store::File;
+
+    fn segment(id: u32) -> PrivacySegment {
+        PrivacySegment {
+            segment: id,
+            filter: BloomFilter::new(10, DEFAULT_FP_RATE, id, 0),
+            birth: 100,
+            synced_height: 100,
+            is_enabled: true,
+        }
+    }
+
+    fn cache(dir: &tempfile::TempDir) -> FilterCache<File> {
+        let store = File::open(dir.path().join("segments.db")).unwrap();
+        FilterCache::load(store).unwrap()
+    }
+
+    #[test]
+    fn test_put_get() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cache = cache(&tmp);
+
+        assert!(
+            cache.get(0).is_err(),
+            "when the store is empty, we can't get segment `0`"
+        );
+
+        let len = cache.put(std::iter::once(segment(0))).unwrap();
+        cache.sync().unwrap();
+
+        assert_eq!(len, 1);
+        assert_eq!(cache.get(0).unwrap(), segment(0));
+    }
+
+    #[test]
+    fn test_put_get_batch() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cache = cache(&tmp);
+
+        let count = 32;
+        let segments = (0..count).map(segment).collect::<Vec<_>>();
+
+        // Put all segments into the store and check that we can retrieve them.
+        {
+            let len = cache.put(segments.iter().cloned()).unwrap();
+
+            assert_eq!(len, count);
+            assert_eq!(cache.len().unwrap(), count as usize);
+
+            for (i, s) in segments.iter().enumerate() {
+                assert_eq!(&cache.get(i as u32).unwrap(), s);
+            }
+            assert!(cache.get(count).is_err());
+        }
+
+        let n = count / 2;
+
+        // Rollback and overwrite the segments.
+        {
+            cache.rollback(n).unwrap();
+
+            assert!(
+                cache.get(n).is_err(),
+                "after the rollback, we can't access segments passed `n`"
+            );
+            assert_eq!(cache.len().unwrap(), n as usize);
+
+            let overwritten = PrivacySegment {
+                synced_height: 144,
+                ..segment(n)
+            };
+            let len = cache.put(std::iter::once(overwritten.clone())).unwrap();
+
+            assert_eq!(len, n + 1);
+            assert_eq!(cache.get(n).unwrap(), overwritten);
+
+            // Segments before `n` are unaffected by the rollback.
+            assert_eq!(cache.get(0).unwrap(), segments[0]);
+            assert_eq!(cache.get(n - 1).unwrap(), segments[n as usize - 1]);
+        }
+
+        // The cache is restored from disk.
+        {
+            let cache = self::cache(&tmp);
+
+            assert_eq!(cache.len().unwrap(), n as usize + 1);
+            assert_eq!(cache.get(n).unwrap().synced_height, 144);
+        }
+    }
+
+    #[test]
+    fn test_iter() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cache = cache(&tmp);
+        let segments = (0..32).map(segment).collect::<Vec<_>>();
+
+        cache.put(segments.iter().cloned()).unwrap();
+
+        for (i, result) in cache.iter().enumerate() {
+            let (id, segment) = result.unwrap();
+
+            assert_eq!(i as u32, id);
+            assert_eq!(segment, segments[i]);
+        }
+        assert_eq!(cache.iter().count(), segments.len());
+    }
+
+    #[test]
+    fn test_load_interrupted() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut cache = cache(&tmp);
+
+        cache.put((0..4).map(segment)).unwrap();
+
+        let store = File::open(tmp.path().join("segments.db")).unwrap();
+        let result = FilterCache::load_with(store, |id| {
+            if id == 2 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        assert!(matches!(result, Err(Error::Interrupted)));
+    }
+
+    #[test]
+    fn test_corrupt_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("segments.db");
+        let mut cache = cache(&tmp);
+
+        cache.check().expect("checking always works");
+        cache.heal().expect("healing when there is no corruption");
+
+        cache.put((0..2).map(segment)).unwrap();
+        cache.check().unwrap();
+
+        // Intentionally corrupt the file, by truncating it by 32 bytes.
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(file.metadata().unwrap().len() - 32).unwrap();
+
+        let store = File::open(&path).unwrap();
+
+        assert!(matches! {
+            store
+                .get(1)
+                .expect_err("the second segment has been corrupted"),
+            Error::Io(err) if err.kind() == io::ErrorKind::UnexpectedEof
+        });
+        store.check().expect_err("data is corrupted");
+        FilterCache::load(store).expect_err("data is corrupted");
+
+        let store = File::open(&path).unwrap();
+        store.heal().unwrap();
+
+        let cache = FilterCache::load(store).unwrap();
+        cache.check().unwrap();
+
+        assert_eq!(cache.len().unwrap(), 1, "the corrupted segment was removed");
+        assert_eq!(cache.get(0).unwrap(), segment(0));
+    }
+}
@@ -1,454 +1,290 @@
-// // //! Persistent storage backend for blocks.
-// use std::collections::VecDeque;
-// use std::fs;
-// use std::io::{self, Read, Seek, Write};
-// use std::iter;
-// use std::mem;
-// use std::path::Path;
-
-// use crate::bitcoin::consensus::{Decodable, Encodable};
-// use crate::bloom::store::{Error, Store};
-// // use bitcoincash::ScriptHash;
-
-// /// Append a filter to the end of the stream.
-// fn put<F: Sized + Encodable, S: Seek + Write, I: Iterator<Item = F>>(
-//     mut stream: S,
-//     filters: I,
-// ) -> Result<u32, Error> {
-//     let mut pos = stream.seek(io::SeekFrom::End(0))?;
-//     let size = std::mem::size_of::<F>();
-
-//     for filter in filters {
-//         pos += filter.consensus_encode(&mut stream)? as u64;
-//     }
-//     Ok(pos as u32 / size as u32)
-// }
-
-// /// Get a filter from the stream.
-// fn get<F: Decodable, S: Seek + Read>(mut stream: S, ix: u32) -> Result<F, Error> {
-//     let size = std::mem::size_of::<F>();
-//     let mut buf = vec![0; size]; // TODO: Use an array when rust has const-generics.
-
-//     stream.seek(io::SeekFrom::Start(ix as u64 * size as u64))?;
-//     stream.read_exact(&mut buf)?;
-
-//     F::consensus_decode(&mut buf.as_slice()).map_err(Error::from)
-// }
-
-// /// Reads from a file in an I/O optmized way.
-// #[derive(Debug)]
-// struct FileReader<F> {
-//     file: fs::File,
-//     queue: VecDeque<F>,
-//     index: u64,
-// }
-
-// impl<F: Decodable> FileReader<F> {
-//     const BATCH_SIZE: usize = 16;
-
-//     fn new(file: fs::File) -> Self {
-//         Self {
-//             file,
-//             queue: VecDeque::new(),
-//             index: 0,
-//         }
-//     }
-
-//     fn next(&mut self) -> Result<Option<F>, Error> {
-//         let size = std::mem::size_of::<F>();
-
-//         if self.queue.is_empty() {
-//             let mut buf = vec![0; size * Self::BATCH_SIZE];
-//             let from = self.file.seek(io::SeekFrom::Start(self.index))?;
-
-//             match self.file.read_exact(&mut buf) {
-//                 Ok(()) => {}
-//                 Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
-//                     self.file.seek(io::SeekFrom::Start(from))?;
-//                     let n = self.file.read_to_end(&mut buf)?;
-//                     buf.truncate(n);
-//                 }
-//                 Err(err) => return Err(err.into()),
-//             }
-//             self.index += buf.len() as u64;
-
-//             let items = buf.len() / size;
-//             let mut cursor = io::Cursor::new(buf);
-//             let mut item = vec![0; size];
-
-//             for _ in 0..items {
-//                 cursor.read_exact(&mut item)?;
-
-//                 let item = F::consensus_decode(&mut item.as_slice())?;
-//                 self.queue.push_back(item);
-//             }
-//         }
-//         Ok(self.queue.pop_front())
-//     }
-// }
-
-// /// An iterator over bloom filters file.
-// #[derive(Debug)]
-// pub struct Iter<F> {
-//     segment_id: u32,
-//     file: FileReader<F>,
-// }
-
-// impl<F: Decodable> Iter<F> {
-//     fn new(file: fs::File, segment_id: u32) -> Self {
-//         Self {
-//             file: FileReader::new(file),
-//             segment_id,
-//         }
-//     }
-// }
-
-// impl<F: Decodable> Iterator for Iter<F> {
-//     type Item = Result<(u32, F), Error>;
-
-//     fn next(&mut self) -> Option<Self::Item> {
-//         let segment = self.segment_id;
-
-//         assert!(segment > 0);
-
-//         match self.file.next() {
-//             // If we hit this branch, it's because we're trying to read passed the end
-//             // of the file, which means there are no further headers remaining.
-//             Err(Error::Io(err)) if err.kind() == io::ErrorKind::UnexpectedEof => None,
-//             // If another kind of error occurs, we want to yield it to the caller, so
-//             // that it can be propagated.
-//             Err(err) => Some(Err(err)),
-//             Ok(Some(h)) => {
-//                 self.segment_id += 1;
-//                 Some(Ok((self.segment_id, h)))
-//             }
-//             Ok(None) => None,
-//         }
-//     }
-// }
-
-// /// A `Store` backed by a single file.
-// #[derive(Debug)]
-// pub struct File<PrivacySegment> {
-//     file: fs::File,
-//     segment: PrivacySegment,
-// }
-
-// impl<F> File<F> {
-//     /// Open a new file store from the given path and bloom segment.
-//     pub fn open<P: AsRef<Path>>(path: P, segment: F) -> io::Result<Self> {
-//         fs::OpenOptions::new()
-//             .create(true)
-//             .read(true)
-//             .append(true)
-//             .open(path)
-//             .map(|file| Self { file, segment })
-//     }
-
-//     /// Create a new file store at the given path, with the provided segment.
-//     pub fn create<P: AsRef<Path>>(path: P, segment: F) -> Result<Self, Error> {
-//         let file = fs::OpenOptions::new()
-//             .create_new(true)
-//             .read(true)
-//             .append(true)
-//             .open(path)?;
-
-//         Ok(Self { file, segment })
-//     }
-// }
-
-// impl<F: 'static + Clone + Encodable + Decodable> Store for File<F> {
-//     type PrivacySegment = F;
-
-//     fn default(&self) -> F {
-//         self.segment.clone()
-//     }
-
-//     /// Append a block to the end of the file.
-//     fn put<I: Iterator<Item = Self::PrivacySegment>>(&mut self, segment: I) -> Result<u32, Error> {
-//         self::put(&mut self.file, segment)
-//     }
-
-//     /// Get the block at the given height. Returns `io::ErrorKind::UnexpectedEof` if
-//     /// the height is not found.
-//     fn get(&self, segment_id: u32) -> Result<F, Error> {
-//         if let Some(ix) = segment_id.checked_sub(1) {
-//             // Clone so this function doesn't have to take a `&mut self`.
-//             let mut file = self.file.try_clone()?;
-//             get(&mut file, ix)
-//         } else {
-//             Ok(self.segment.clone())
-//         }
-//     }
-
-//     /// Flush changes to disk.
-//     fn sync(&mut self) -> Result<(), Error> {
-//         self.file.sync_data().map_err(Error::from)
-//     }
-
-//     /// Iterate over all headers in the store.
-//     // fn iter(&self) -> Box<dyn Iterator<Item = Result<(F, F), Error>>> {
-//     fn iter(&self) -> Box<dyn Iterator<Item = Result<(u32, F), Error>>> {
-//         // Clone so this function doesn't have to take a `&mut self`.
-//         match self.file.try_clone() {
-//             Ok(file) => {
-//                 Box::new(iter::once(Ok((0, self.segment.clone()))).chain(Iter::new(file, 0)))
-//             }
-//             Err(err) => Box::new(iter::once(Err(Error::Io(err)))),
-//         }
-//     }
-
-//     /// Return the number of headers in the store.
-//     fn len(&self) -> Result<usize, Error> {
-//         let meta = self.file.metadata()?;
-//         let len = meta.len();
-//         let size = mem::size_of::<F>();
-
-//         assert!(len <= usize::MAX as u64);
-
-//         if len as usize % size != 0 {
-//             return Err(Error::Corruption);
-//         }
-//         Ok(len as usize / size + 1)
-//     }
-
-//     //     /// Return the block height of the store.
-//     //     fn height(&self) -> Result<Height, Error> {
-//     //         self.len().map(|n| n as Height - 1)
-//     //     }
-
-//     /// Check the file store integrity.
-//     fn check(&self) -> Result<(), Error> {
-//         self.len().map(|_| ())
-//     }
-
-//     /// Attempt to heal data corruption.
-//     fn heal(&self) -> Result<(), Error> {
-//         let meta = self.file.metadata()?;
-//         let len = meta.len();
-//         let size = mem::size_of::<F>();
-
-//         assert!(len <= usize::MAX as u64);
-
-//         let extraneous = len as usize % size;
-//         if extraneous != 0 {
-//             self.file.set_len(len - extraneous as u64)?;
-//         }
-
-//         Ok(())
-//     }
-// }
-
-// // #[cfg(test)]
-// mod test {
-//     // use std::{io, iter};
-
-//     // use nakamoto_common::bitcoin::TxMerkleNode;
-//     // use nakamoto_common::bitcoin_hashes::Hash;
-//     // use nakamoto_common::block::BlockHash;
-//     // // use tempfile::*
-
-//     // use super::{Error, File, Height, Store};
-//     // use crate::block::BlockHeader;
-
-//     // const HEADER_SIZE: usize = 80;
-
-//     // use nakamoto_common::bitcoin::util::bloom::BloomFilter;
-
-//     // fn store(path: &str) -> File<BlockHeader> {
-//     //     let tmp = tempfile::tempdir().unwrap();
-//     //     let mut bloom_filter = BloomFilter::new(1000, 0.0001, 987987, 0);
-
-//     //     File::open(tmp.path().join(path), genesis).unwrap()
-//     // }
-
-//     //     #[test]
-//     //     fn test_put_get() {
-//     //         let mut store = store("bloomfilters.db");
-
-//     //         let header = BlockHeader {
-//     //             version: 1,
-//     //             prev_blockhash: store.genesis.block_hash(),
-//     //             merkle_root: TxMerkleNode::all_zeros(),
-//     //             bits: 0x2ffffff,
-//     //             time: 1842918273,
-//     //             nonce: 312143,
-//     //         };
-
-//     //         assert_eq!(
-//     //             store.get(0).unwrap(),
-//     //             store.genesis,
-//     //             "when the store is empty, we can `get` the genesis"
-//     //         );
-//     //         assert!(
-//     //             store.get(1).is_err(),
-//     //             "when the store is empty, we can't get height `1`"
-//     //         );
-
-//     //         let height = store.put(iter::once(header)).unwrap();
-//     //         store.sync().unwrap();
-
-//     //         assert_eq!(height, 1);
-//     //         assert_eq!(store.get(height).unwrap(), header);
-//     //     }
-
-//     //     #[test]
-//     //     fn test_put_get_batch() {
-//     //         let mut store = store("headers.db");
-
-//     //         assert_eq!(store.len().unwrap(), 1);
-
-//     //         let count = 32;
-//     //         let header = BlockHeader {
-//     //             version: 1,
-//     //             prev_blockhash: store.genesis().block_hash(),
-//     //             merkle_root: TxMerkleNode::all_zeros(),
-//     //             bits: 0x2ffffff,
-//     //             time: 1842918273,
-//     //             nonce: 0,
-//     //         };
-//     //         let iter = (0..count).map(|i| BlockHeader { nonce: i, ..header });
-//     //         let headers = iter.clone().collect::<Vec<_>>();
-
-//     //         // Put all headers into the store and check that we can retrieve them.
-//     //         {
-//     //             let height = store.put(iter).unwrap();
-
-//     //             assert_eq!(height, headers.len() as Height);
-//     //             assert_eq!(store.len().unwrap(), headers.len() + 1); // Account for genesis.
-
-//     //             for (i, h) in headers.iter().enumerate() {
-//     //                 assert_eq!(&store.get(i as Height + 1).unwrap(), h);
-//     //             }
-
-//     //             assert!(&store.get(32 + 1).is_err());
-//     //         }
-
-//     //         // Rollback and overwrite the history.
-//     //         {
-//     //             let h = headers.len() as Height / 2; // Some point `h` in the past.
-
-//     //             assert!(&store.get(h + 1).is_ok());
-//     //             assert_eq!(store.get(h + 1).unwrap(), headers[h as usize]);
-
-//     //             store.rollback(h).unwrap();
-
-//     //             assert!(
-//     //                 &store.get(h + 1).is_err(),
-//     //                 "after the rollback, we can't access blocks passed `h`"
-//     //             );
-//     //             assert_eq!(store.len().unwrap(), h as usize + 1);
-
-//     //             // We can now overwrite the block at position `h + 1`.
-//     //             let header = BlockHeader {
-//     //                 nonce: 49219374,
-//     //                 ..header
-//     //             };
-//     //             let height = store.put(iter::once(header)).unwrap();
-
-//     //             assert!(header != headers[height as usize]);
-
-//     //             assert_eq!(height, h + 1);
-//     //             assert_eq!(store.get(height).unwrap(), header);
-
-//     //             // Blocks up to and including `h` are unaffected by the rollback.
-//     //             assert_eq!(store.get(0).unwrap(), store.genesis);
-//     //             assert_eq!(store.get(1).unwrap(), headers[0]);
-//     //             assert_eq!(store.get(h).unwrap(), headers[h as usize - 1]);
-//     //         }
-//     //     }
-
-//     //     #[test]
-//     //     fn test_iter() {
-//     //         let mut store = store("bloomfilters.db");
-
-//     //         let count = 32;
-//     //         let header = BlockHeader {
-//     //             version: 1,
-//     //             prev_blockhash: store.genesis().block_hash(),
-//     //             merkle_root: TxMerkleNode::all_zeros(),
-//     //             bits: 0x2ffffff,
-//     //             time: 1842918273,
-//     //             nonce: 0,
-//     //         };
-//     //         let iter = (0..count).map(|i| BlockHeader { nonce: i, ..header });
-//     //         let headers = iter.clone().collect::<Vec<_>>();
-
-//     //         store.put(iter).unwrap();
-
-//     //         let mut iter = store.iter();
-//     //         assert_eq!(iter.next().unwrap().unwrap(), (0, store.genesis));
-
-//     //         for (i, result) in iter.enumerate() {
-//     //             let (height, header) = result.unwrap();
-
-//     //             assert_eq!(i as u64 + 1, height);
-//     //             assert_eq!(header, headers[height as usize - 1]);
-//     //         }
-//     //     }
-
-//     //     #[test]
-//     //     fn test_corrupt_file() {
-//     //         let mut store = store("bloomfilters.db");
-
-//     //         store.check().expect("checking always works");
-//     //         store.heal().expect("healing when there is no corruption");
-
-//     //         let headers = &[
-//     //             BlockHeader {
-//     //                 version: 1,
-//     //                 prev_blockhash: store.genesis().block_hash(),
-//     //                 merkle_root: TxMerkleNode::all_zeros(),
-//     //                 bits: 0x2ffffff,
-//     //                 time: 1842918273,
-//     //                 nonce: 312143,
-//     //             },
-//     //             BlockHeader {
-//     //                 version: 1,
-//     //                 prev_blockhash: BlockHash::all_zeros(),
-//     //                 merkle_root: TxMerkleNode::all_zeros(),
-//     //                 bits: 0x1ffffff,
-//     //                 time: 1842918920,
-//     //                 nonce: 913716378,
-//     //             },
-//     //         ];
-//     //         store.put(headers.iter().cloned()).unwrap();
-//     //         store.check().unwrap();
-
-//     //         assert_eq!(store.len().unwrap(), 3);
-
-//     //         let size = std::mem::size_of::<BlockHeader>();
-//     //         assert_eq!(size, HEADER_SIZE);
-
-//     //         // Intentionally corrupt the file, by truncating it by 32 bytes.
-//     //         store
-//     //             .file
-//     //             .set_len(headers.len() as u64 * size as u64 - 32)
-//     //             .unwrap();
-
-//     //         assert_eq!(
-//     //             store.get(1).unwrap(),
-//     //             headers[0],
-//     //             "the first header is intact"
-//     //         );
-
-//     //         matches! {
-//     //             store
-//     //                 .get(2)
-//     //                 .expect_err("the second header has been corrupted"),
-//     //             Error::Io(err) if err.kind() == io::ErrorKind::UnexpectedEof
-//     //         };
-
-//     //         store.len().expect_err("data is corrupted");
-//     //         store.check().expect_err("data is corrupted");
-
-//     //         store.heal().unwrap();
-//     //         store.check().unwrap();
-
-//     //         assert_eq!(
-//     //             store.len().unwrap(),
-//     //             2,
-//     //             "the last (corrupted) header was removed"
-//     //         );
-//     //     }
-// }
+//! Persistent storage backend for privacy segments.
+use std::fs;
+use std::io::{self, Read, Seek, Write};
+use std::path::Path;
+
+use crate::bitcoin::consensus::encode::{Decodable, Encodable};
+use crate::bitcoin::util::bloom::MAX_BLOOM_FILTER_SIZE;
+use crate::bloom::store::cache::PrivacySegment;
+use crate::bloom::store::{Error, Store};
+
+/// Size of a segment record on disk. Records are padded to this size, which fits the
+/// largest filter, so that segments can be updated in place.
+pub const RECORD_SIZE: usize = MAX_BLOOM_FILTER_SIZE + 64;
+
+/// Write a segment at the given index of the stream.
+fn put<S: Seek + Write>(mut stream: S, ix: u64, segment: &PrivacySegment) -> Result<(), Error> {
+    let mut buf = Vec::with_capacity(RECORD_SIZE);
+
+    segment.consensus_encode(&mut buf)?;
+
+    if buf.len() > RECORD_SIZE {
+        return Err(Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "segment is too large",
+        )));
+    }
+    buf.resize(RECORD_SIZE, 0);
+
+    stream.seek(io::SeekFrom::Start(ix * RECORD_SIZE as u64))?;
+    stream.write_all(&buf)?;
+
+    Ok(())
+}
+
+/// Get a segment from the stream.
+fn get<S: Seek + Read>(mut stream: S, ix: u64) -> Result<PrivacySegment, Error> {
+    let mut buf = vec![0; RECORD_SIZE];
+
+    stream.seek(io::SeekFrom::Start(ix * RECORD_SIZE as u64))?;
+    stream.read_exact(&mut buf)?;
+
+    PrivacySegment::consensus_decode(&mut buf.as_slice()).map_err(Error::from)
+}
+
+/// An iterator over the segments in a file.
+#[derive(Debug)]
+pub struct Iter {
+    segment_id: u32,
+    file: fs::File,
+}
+
+impl Iterator for Iter {
+    type Item = Result<(u32, PrivacySegment), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let segment_id = self.segment_id;
+
+        match get(&mut self.file, segment_id as u64) {
+            // If we hit this branch, it's because we're trying to read passed the end
+            // of the file, which means there are no further segments remaining.
+            Err(Error::Io(err)) if err.kind() == io::ErrorKind::UnexpectedEof => None,
+            Err(err) => Some(Err(err)),
+            Ok(segment) => {
+                self.segment_id = segment_id + 1;
+                Some(Ok((segment_id, segment)))
+            }
+        }
+    }
+}
+
+/// A `Store` backed by a single file.
+#[derive(Debug)]
+pub struct File {
+    file: fs::File,
+}
+
+impl File {
+    /// Open a new file store from the given path.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        fs::OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .open(path)
+            .map(|file| Self { file })
+    }
+
+    /// Create a new file store at the given path.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = fs::OpenOptions::new()
+            .create_new(true)
+            .read(true)
+            .write(true)
+            .open(path)?;
+
+        Ok(Self { file })
+    }
+}
+
+impl Store for File {
+    type PrivacySegment = PrivacySegment;
+
+    /// Append segments to the end of the file.
+    fn put<I: Iterator<Item = PrivacySegment>>(&mut self, segments: I) -> Result<u32, Error> {
+        let mut len = self.len()? as u64;
+
+        for segment in segments {
+            self::put(&mut self.file, len, &segment)?;
+            len += 1;
+        }
+        Ok(len as u32)
+    }
+
+    /// Overwrite a segment in place. Returns `io::ErrorKind::UnexpectedEof` if the
+    /// segment is not found.
+    fn update(&mut self, segment_id: u32, segment: PrivacySegment) -> Result<(), Error> {
+        if segment_id as usize >= self.len()? {
+            return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+        self::put(&mut self.file, segment_id as u64, &segment)
+    }
+
+    /// Get the segment with the given id. Returns `io::ErrorKind::UnexpectedEof` if
+    /// the segment is not found.
+    fn get(&self, segment_id: u32) -> Result<PrivacySegment, Error> {
+        // Clone so this function doesn't have to take a `&mut self`.
+        let mut file = self.file.try_clone()?;
+        get(&mut file, segment_id as u64)
+    }
+
+    /// Rollback the file to the given number of segments.
+    fn rollback(&mut self, len: u32) -> Result<(), Error> {
+        self.file
+            .set_len(len as u64 * RECORD_SIZE as u64)
+            .map_err(Error::from)
+    }
+
+    /// Flush changes to disk.
+    fn sync(&mut self) -> Result<(), Error> {
+        self.file.sync_data().map_err(Error::from)
+    }
+
+    /// Iterate over all segments in the store.
+    fn iter(&self) -> Box<dyn Iterator<Item = Result<(u32, PrivacySegment), Error>>> {
+        // Clone so this function doesn't have to take a `&mut self`.
+        match self.file.try_clone() {
+            Ok(file) => Box::new(Iter {
+                segment_id: 0,
+                file,
+            }),
+            Err(err) => Box::new(std::iter::once(Err(Error::Io(err)))),
+        }
+    }
+
+    /// Return the number of segments in the store.
+    fn len(&self) -> Result<usize, Error> {
+        let meta = self.file.metadata()?;
+        let len = meta.len();
+
+        assert!(len <= usize::MAX as u64);
+
+        if len as usize % RECORD_SIZE != 0 {
+            return Err(Error::Corruption);
+        }
+        Ok(len as usize / RECORD_SIZE)
+    }
+
+    /// Check the file store integrity.
+    fn check(&self) -> Result<(), Error> {
+        self.len().map(|_| ())
+    }
+
+    /// Attempt to heal data corruption, by dropping any partially written segment.
+    fn heal(&self) -> Result<(), Error> {
+        let meta = self.file.metadata()?;
+        let len = meta.len();
+
+        assert!(len <= usize::MAX as u64);
+
+        let extraneous = len as usize % RECORD_SIZE;
+        if extraneous != 0 {
+            self.file.set_len(len - extraneous as u64)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use super::{Error, File, Store, RECORD_SIZE};
+    use crate::bitcoin::util::bloom::{BloomFilter, MAX_BLOOM_FILTER_SIZE};
+    use crate::bloom::store::cache::PrivacySegment;
+
+    fn segment(id: u32, elements: usize) -> PrivacySegment {
+        PrivacySegment {
+            segment: id,
+            filter: BloomFilter::new(elements, 0.0001, id, 0),
+            birth: 100,
+            synced_height: 100 + id as u64,
+            is_enabled: true,
+        }
+    }
+
+    #[test]
+    fn test_put_get_update() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("segments.db");
+        let segments = (0..4).map(|i| segment(i, 10)).collect::<Vec<_>>();
+
+        {
+            let mut store = File::create(&path).unwrap();
+
+            assert_eq!(store.len().unwrap(), 0);
+            assert_eq!(store.put(segments.iter().cloned()).unwrap(), 4);
+            assert_eq!(store.get(2).unwrap(), segments[2]);
+            assert!(store.get(4).is_err());
+
+            let mut synced = segments[1].clone();
+            synced.synced_height = 144;
+
+            store.update(1, synced.clone()).unwrap();
+            store.sync().unwrap();
+
+            assert_eq!(store.len().unwrap(), 4);
+            assert_eq!(store.get(1).unwrap(), synced);
+            assert_eq!(store.get(2).unwrap(), segments[2]);
+            assert!(store.update(4, synced).is_err());
+        }
+
+        // Segments are restored from disk.
+        let store = File::open(&path).unwrap();
+        let restored = store.iter().collect::<Result<Vec<_>, _>>().unwrap();
+
+        assert_eq!(restored.len(), 4);
+        assert_eq!(restored[1].1.synced_height, 144);
+        assert_eq!(restored[3], (3, segments[3].clone()));
+    }
+
+    #[test]
+    fn test_largest_filter() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut store = File::create(tmp.path().join("segments.db")).unwrap();
+        let mut segment = segment(0, 1_000_000);
+
+        assert_eq!(segment.filter.content.len(), MAX_BLOOM_FILTER_SIZE);
+        store.put(std::iter::once(segment.clone())).unwrap();
+        assert_eq!(store.get(0).unwrap(), segment);
+
+        segment.filter.content.extend([0; 64]);
+        assert!(store.put(std::iter::once(segment)).is_err());
+    }
+
+    #[test]
+    fn test_corrupt_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut store = File::create(tmp.path().join("segments.db")).unwrap();
+
+        store.check().expect("checking always works");
+        store.heal().expect("healing when there is no corruption");
+
+        store.put((0..2).map(|i| segment(i, 10))).unwrap();
+        store.check().unwrap();
+
+        // Intentionally corrupt the file, by truncating it by 32 bytes.
+        store.file.set_len(2 * RECORD_SIZE as u64 - 32).unwrap();
+
+        assert_eq!(
+            store.get(0).unwrap(),
+            segment(0, 10),
+            "the first segment is intact"
+        );
+        assert!(matches! {
+            store
+                .get(1)
+                .expect_err("the second segment has been corrupted"),
+            Error::Io(err) if err.kind() == io::ErrorKind::UnexpectedEof
+        });
+
+        store.len().expect_err("data is corrupted");
+        store.check().expect_err("data is corrupted");
+
+        store.heal().unwrap();
+        store.check().unwrap();
+
+        assert_eq!(store.len().unwrap(), 1, "the corrupted segment was removed");
+    }
+}
@@ -44,7 +44,11 @@ pub enum Network {
     Testnet,
     /// Bitcoin regression test net.
     Regtest,
-    /// Bitcoin signet.
+    /// Bitcoin Cash testnet4.
+    Testnet4,
+    /// Bitcoin Cash scaling test net.
+    Scalenet,
+    /// Bitcoin Cash chipnet, which activates upgrades ahead of the other networks.
     Chipnet,
 }
 
@@ -62,6 +66,8 @@ impl FromStr for Network {
             "mainnet" | "bitcoin" => Ok(Self::Mainnet),
             "testnet" => Ok(Self::Testnet),
             "regtest" => Ok(Self::Regtest),
+            "testnet4" => Ok(Self::Testnet4),
+            "scalenet" => Ok(Self::Scalenet),
             "chipnet" => Ok(Self::Chipnet),
             _ => Err(format!("invalid network specified {:?}", s)),
         }
@@ -74,6 +80,8 @@ impl From<Network> for bitcoin::Network {
             Network::Mainnet => Self::Bitcoin,
             Network::Testnet => Self::Testnet,
             Network::Regtest => Self::Regtest,
+            Network::Testnet4 => Self::Testnet4,
+            Network::Scalenet => Self::Scalenet,
             Network::Chipnet => Self::Chipnet,
         }
     }
@@ -84,9 +92,10 @@ impl From<bitcoin::Network> for Network {
         match value {
             bitcoin::Network::Bitcoin => Self::Mainnet,
             bitcoin::Network::Testnet => Self::Testnet,
+            bitcoin::Network::Testnet4 => Self::Testnet4,
+            bitcoin::Network::Scalenet => Self::Scalenet,
             bitcoin::Network::Chipnet => Self::Chipnet,
             bitcoin::Network::Regtest => Self::Regtest,
-            _ => Self::Mainnet,
         }
     }
 }
@@ -98,6 +107,8 @@ impl Network {
             Network::Mainnet => 8333,
             Network::Testnet => 18333,
             Network::Regtest => 18334,
+            Network::Testnet4 => 28333,
+            Network::Scalenet => 38333,
             Network::Chipnet => 48333,
         }
     }
@@ -110,6 +121,8 @@ impl Network {
             Network::Mainnet => checkpoints::MAINNET,
             Network::Testnet => checkpoints::TESTNET,
             Network::Regtest => checkpoints::REGTEST,
+            Network::Testnet4 => checkpoints::TESTNET4,
+            Network::Scalenet => checkpoints::SCALENET,
             Network::Chipnet => checkpoints::CHIPNET,
         }
         .iter()
@@ -128,6 +141,8 @@ impl Network {
             Network::Mainnet => "mainnet",
             Network::Testnet => "testnet",
             Network::Regtest => "regtest",
+            Network::Testnet4 => "testnet4",
+            Network::Scalenet => "scalenet",
             Network::Chipnet => "chipnet",
         }
     }
@@ -146,10 +161,24 @@ impl Network {
                 "bch.bitjson.com",
             ],
             Network::Testnet => &[
-                //TODO
+                "testnet-seed-bch.bitcoinforks.org",
+                "testnet-seed-bch.toom.im",
+                "seed.tbch.loping.net",
+                "seed.testnet.bitcoinunlimited.info",
             ],
             Network::Regtest => &[], // No seeds
-            Network::Chipnet => &["chipnet.bitjson.com"],
+            Network::Testnet4 => &[
+                "testnet4-seed-bch.bitcoinforks.org",
+                "testnet4-seed-bch.toom.im",
+                "seed.tbch4.loping.net",
+                "testnet4-seed.flowee.cash",
+            ],
+            Network::Scalenet => &[
+                "scalenet-seed-bch.bitcoinforks.org",
+                "scalenet-seed-bch.toom.im",
+                "seed.sbch.loping.net",
+            ],
+            Network::Chipnet => &["chipnet.imaginary.cash", "chipnet.bitjson.com"],
         }
     }
 }
@@ -185,6 +214,8 @@ impl Network {
             Self::Mainnet => genesis::MAINNET,
             Self::Testnet => genesis::TESTNET,
             Self::Regtest => genesis::REGTEST,
+            Self::Testnet4 => genesis::TESTNET4,
+            Self::Scalenet => genesis::SCALENET,
             Self::Chipnet => genesis::CHIPNET,
         };
         BlockHash::from_hash(
@@ -203,3 +234,47 @@ impl Network {
         bitcoin::Network::from(*self).net_magic()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NETWORKS: &[Network] = &[
+        Network::Mainnet,
+        Network::Testnet,
+        Network::Regtest,
+        Network::Testnet4,
+        Network::Scalenet,
+        Network::Chipnet,
+    ];
+
+    #[test]
+    fn test_genesis() {
+        for network in NETWORKS {
+            assert_eq!(
+                network.genesis_hash(),
+                network.genesis().block_hash(),
+                "{}",
+                network.as_str()
+            );
+        }
+        // Chipnet forked off testnet4, so they share a genesis block.
+        assert_eq!(
+            Network::Chipnet.genesis_hash(),
+            Network::Testnet4.genesis_hash()
+        );
+    }
+
+    #[test]
+    fn test_network_roundtrip() {
+        for network in NETWORKS {
+            let parsed = network.as_str().parse::<Network>().unwrap();
+            let converted = Network::from(bitcoin::Network::from(*network));
+
+            assert_eq!(parsed.as_str(), network.as_str());
+            assert_eq!(converted.as_str(), network.as_str());
+            assert_eq!(parsed.magic(), network.magic());
+        }
+        assert_ne!(Network::Testnet4.port(), Network::Scalenet.port());
+    }
+}
@@ -1,5 +1,6 @@
 //! P2P-related types
 use std::net;
+pub mod netgroup;
 pub mod peer;
 
 /// Communication domain of a network socket.
@@ -9,10 +10,12 @@ pub enum Domain {
     IPV4,
     /// IPv6.
     IPV6,
+    /// Tor onion services. These can only be reached via a proxy.
+    Onion,
 }
 
 impl Domain {
-    /// All domains.
+    /// All domains reachable without a proxy.
     pub fn all() -> Vec<Self> {
         vec![Self::IPV4, Self::IPV6]
     }
@@ -21,6 +24,7 @@ impl Domain {
     pub const fn for_address(address: &net::SocketAddr) -> Domain {
         match address {
             net::SocketAddr::V4(_) => Domain::IPV4,
+            net::SocketAddr::V6(addr) if nakamoto_net::onion::is_onion(addr.ip()) => Domain::Onion,
             net::SocketAddr::V6(_) => Domain::IPV6,
         }
     }
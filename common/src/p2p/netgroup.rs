@@ -0,0 +1,214 @@
+//! Network groups.
+//!
+//! Addresses in the same network group are likely to be operated by the same entity, eg. a
+//! hosting provider. Spreading outbound connections across groups makes it harder for a single
+//! entity to control, or observe, all of our peers.
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::str::FromStr;
+use std::{fs, io, net};
+
+use thiserror::Error;
+
+/// An error loading an AS map.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// An I/O error.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// A line of the AS map couldn't be parsed.
+    #[error("line {line}: {reason}")]
+    Parse { line: usize, reason: &'static str },
+}
+
+/// Network group of an address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum NetGroup {
+    /// Autonomous system, when known from an [`Asmap`].
+    Asn(u32),
+    /// IPv4 `/16` range, eg. `1.2.0.0/16`.
+    Ipv4([u8; 2]),
+    /// IPv6 `/32` range.
+    Ipv6([u16; 2]),
+    /// Onion services, split into 16 groups by the first bits of their public key.
+    Onion(u8),
+}
+
+impl NetGroup {
+    /// Get the network group of an address. If an AS map is given and covers the address,
+    /// the address is grouped by autonomous system, rather than by range.
+    pub fn of(ip: &net::IpAddr, asmap: Option<&Asmap>) -> Self {
+        if let Some(asn) = asmap.and_then(|m| m.lookup(ip)) {
+            return Self::Asn(asn);
+        }
+        match ip {
+            net::IpAddr::V4(ip) => Self::ipv4(ip),
+            net::IpAddr::V6(ip) => {
+                if let Some(ip) = ip.to_ipv4_mapped() {
+                    return Self::ipv4(&ip);
+                }
+                if nakamoto_net::onion::is_onion(ip) {
+                    // The placeholder address holds the start of the onion service's key.
+                    return Self::Onion(ip.octets()[6] >> 4);
+                }
+                let segments = ip.segments();
+
+                Self::Ipv6([segments[0], segments[1]])
+            }
+        }
+    }
+
+    fn ipv4(ip: &net::Ipv4Addr) -> Self {
+        let octets = ip.octets();
+
+        Self::Ipv4([octets[0], octets[1]])
+    }
+}
+
+/// A mapping from IP ranges to autonomous system numbers (ASNs).
+///
+/// An AS map is loaded from a text file with one range per line, followed by its ASN, eg.
+/// `1.1.1.0/24 13335`. Empty lines and lines starting with `#` are ignored. When ranges
+/// overlap, the most specific one is used.
+#[derive(Debug, Default, Clone)]
+pub struct Asmap {
+    /// ASNs keyed by prefix length and range, in the IPv6 address space.
+    ranges: BTreeMap<u8, HashMap<u128, u32>>,
+}
+
+impl Asmap {
+    /// Load an AS map from a file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// Get the ASN of an address, if it's covered by the map.
+    pub fn lookup(&self, ip: &net::IpAddr) -> Option<u32> {
+        let bits = u128::from(self::to_ipv6(ip));
+
+        self.ranges
+            .iter()
+            .rev()
+            .find_map(|(len, ranges)| ranges.get(&self::mask(bits, *len)).copied())
+    }
+
+    /// Number of ranges in the map.
+    pub fn len(&self) -> usize {
+        self.ranges.values().map(|r| r.len()).sum()
+    }
+
+    /// Whether the map is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Add a range to the map.
+    fn insert(&mut self, ip: net::IpAddr, len: u8, asn: u32) {
+        // IPv4 ranges are stored as IPv4-mapped IPv6 ranges.
+        let len = if ip.is_ipv4() { len + 96 } else { len };
+        let bits = self::mask(u128::from(self::to_ipv6(&ip)), len);
+
+        self.ranges.entry(len).or_default().insert(bits, asn);
+    }
+}
+
+impl FromStr for Asmap {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut asmap = Self::default();
+
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            let err = |reason| Error::Parse {
+                line: i + 1,
+                reason,
+            };
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            let (range, asn) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(range), Some(asn), None) => (range, asn),
+                _ => return Err(err("expected a range and an ASN")),
+            };
+            let (ip, len) = range
+                .split_once('/')
+                .ok_or_else(|| err("expected a range, eg. `1.1.1.0/24`"))?;
+            let ip = net::IpAddr::from_str(ip).map_err(|_| err("invalid IP address"))?;
+            let len = u8::from_str(len).map_err(|_| err("invalid prefix length"))?;
+
+            if len > if ip.is_ipv4() { 32 } else { 128 } {
+                return Err(err("prefix length out of range"));
+            }
+            let asn = asn.strip_prefix("AS").unwrap_or(asn);
+            let asn = u32::from_str(asn).map_err(|_| err("invalid ASN"))?;
+
+            asmap.insert(ip, len, asn);
+        }
+        Ok(asmap)
+    }
+}
+
+/// Convert an address to IPv6, mapping IPv4 addresses.
+fn to_ipv6(ip: &net::IpAddr) -> net::Ipv6Addr {
+    match ip {
+        net::IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        net::IpAddr::V6(ip) => *ip,
+    }
+}
+
+/// Keep the first `len` bits of an address.
+fn mask(bits: u128, len: u8) -> u128 {
+    match len {
+        0 => 0,
+        len => bits & (u128::MAX << (128 - len as u32)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_netgroup() {
+        let group = |s: &str| NetGroup::of(&s.parse().unwrap(), None);
+
+        assert_eq!(group("1.2.3.4"), group("1.2.200.1"));
+        assert_ne!(group("1.2.3.4"), group("1.3.3.4"));
+        assert_eq!(group("::ffff:1.2.3.4"), group("1.2.99.99"));
+        assert_eq!(group("2001:db8::1"), group("2001:db8:ffff::1"));
+        assert_ne!(group("2001:db8::1"), group("2001:db9::1"));
+    }
+
+    #[test]
+    fn test_asmap() {
+        let asmap: Asmap = "
+            # Comments and empty lines are ignored.
+
+            1.0.0.0/8 1
+            1.2.0.0/16 AS2
+            2001:db8::/32 3
+        "
+        .parse()
+        .unwrap();
+        let group = |s: &str| NetGroup::of(&s.parse().unwrap(), Some(&asmap));
+
+        assert_eq!(asmap.len(), 3);
+        assert_eq!(group("1.1.1.1"), NetGroup::Asn(1));
+        assert_eq!(group("1.2.1.1"), NetGroup::Asn(2));
+        assert_eq!(group("::ffff:1.2.1.1"), NetGroup::Asn(2));
+        assert_eq!(group("2001:db8::1"), NetGroup::Asn(3));
+        assert_eq!(group("2.2.2.2"), NetGroup::Ipv4([2, 2]));
+
+        assert!(matches!(
+            "1.0.0.0/33 1".parse::<Asmap>(),
+            Err(Error::Parse { line: 1, .. })
+        ));
+        assert!(matches!(
+            "1.0.0.0/8 1\n1.0.0.0 1".parse::<Asmap>(),
+            Err(Error::Parse { line: 2, .. })
+        ));
+    }
+}
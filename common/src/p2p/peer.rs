@@ -10,7 +10,12 @@ use bitcoin::network::constants::ServiceFlags;
 use bitcoincash as bitcoin;
 
 use crate::block::time::Clock;
-use crate::net::time::LocalTime;
+use crate::net::time::{LocalDuration, LocalTime};
+
+/// How long after a connection attempt an address is considered recently tried.
+pub const RECENT_ATTEMPT: LocalDuration = LocalDuration::from_mins(10);
+/// Number of failures after which an address isn't penalized any further.
+pub const MAX_FAILURES_PENALTY: u32 = 8;
 
 /// Peer store.
 ///
@@ -81,6 +86,15 @@ pub trait Store {
         Ok(())
     }
 
+    /// Get the anchor peers: outbound peers we were last connected to, and should reconnect
+    /// to first on startup. Stores that don't persist anchors have none.
+    fn anchors(&self) -> Vec<net::SocketAddr> {
+        Vec::new()
+    }
+
+    /// Set the anchor peers, to be persisted on the next flush.
+    fn set_anchors(&mut self, _anchors: Vec<net::SocketAddr>) {}
+
     /// Clears the store of all addresses.
     fn clear(&mut self);
 
@@ -211,6 +225,10 @@ pub struct KnownAddress {
     pub last_attempt: Option<LocalTime>,
     /// Last time this peer was seen alive.
     pub last_active: Option<LocalTime>,
+    /// Bloom filter privacy segment revealed to this peer, if any.
+    pub segment: Option<u32>,
+    /// Number of connection attempts that failed since the last successful one.
+    pub failures: u32,
 }
 
 impl KnownAddress {
@@ -223,9 +241,26 @@ impl KnownAddress {
             last_attempt: None,
             last_sampled: None,
             last_active,
+            segment: None,
+            failures: 0,
         }
     }
 
+    /// Score this address between `0` and `1`, based on its connection history. Addresses
+    /// with a better score are more likely to be connected to successfully.
+    pub fn score(&self, now: LocalTime) -> f64 {
+        let mut score = 1.;
+
+        // Don't keep retrying the same addresses in quick succession.
+        if let Some(last_attempt) = self.last_attempt {
+            if now - last_attempt < RECENT_ATTEMPT {
+                score *= 0.01;
+            }
+        }
+        // Each failure makes it less likely that the next attempt will succeed.
+        score * 0.66f64.powi(self.failures.min(MAX_FAILURES_PENALTY) as i32)
+    }
+
     /// Convert to a JSON value.
     pub fn to_json(&self) -> serde::json::Value {
         use serde::json::{Number, Object, Value};
@@ -267,6 +302,22 @@ impl KnownAddress {
                 None => Value::Null,
             },
         );
+        obj.insert(
+            "segment".to_owned(),
+            match self.segment {
+                Some(s) => Value::Number(Number::U64(s as u64)),
+                None => Value::Null,
+            },
+        );
+        obj.insert(
+            "failures".to_owned(),
+            Value::Number(Number::U64(self.failures as u64)),
+        );
+        // Onion services are only known by their placeholder address for the lifetime of the
+        // process, so we save their hostname.
+        if let Some(host) = nakamoto_net::onion::hostname(&net::Ipv6Addr::from(*ip)) {
+            obj.insert("onion".to_owned(), Value::String(host));
+        }
         obj.insert(
             "source".to_owned(),
             match self.source {
@@ -320,6 +371,29 @@ impl KnownAddress {
             None => None,
             _ => return Err(serde::Error),
         };
+        let segment = match obj.get("segment") {
+            Some(Value::Null) => None,
+            Some(Value::Number(Number::U64(n))) => {
+                Some(u32::try_from(*n).map_err(|_| serde::Error)?)
+            }
+            None => None,
+            _ => return Err(serde::Error),
+        };
+        if let Some(host) = obj.get("onion") {
+            match host {
+                Value::String(host) => {
+                    let pubkey = nakamoto_net::onion::parse(host).ok_or(serde::Error)?;
+                    nakamoto_net::onion::register(pubkey);
+                }
+                _ => return Err(serde::Error),
+            }
+        }
+        // Not present in address books saved before failures were counted.
+        let failures = match obj.get("failures") {
+            Some(Value::Number(Number::U64(n))) => u32::try_from(*n).map_err(|_| serde::Error)?,
+            None => 0,
+            _ => return Err(serde::Error),
+        };
         let source = match obj.get("source") {
             Some(Value::String(s)) => {
                 if s == "dns" {
@@ -343,6 +417,8 @@ impl KnownAddress {
             last_sampled,
             last_attempt,
             last_active,
+            segment,
+            failures,
         })
     }
 }
@@ -388,6 +464,8 @@ mod tests {
             last_sampled: Some(LocalTime::from_secs(144)),
             last_attempt: None,
             last_active: None,
+            segment: Some(3),
+            failures: 2,
         };
 
         let value = ka.to_json();
@@ -395,4 +473,58 @@ mod tests {
 
         assert_eq!(ka, deserialized);
     }
+
+    #[test]
+    fn test_known_address_onion() {
+        use nakamoto_net::onion;
+
+        let pubkey = [7; 32];
+        let ip = onion::register(pubkey);
+        let sockaddr = net::SocketAddr::from((ip, 8333));
+        let ka = KnownAddress::new(
+            Address::new(&sockaddr, ServiceFlags::NETWORK),
+            Source::Dns,
+            None,
+        );
+
+        let value = ka.to_json();
+        match &value {
+            serde::json::Value::Object(obj) => match obj.get("onion") {
+                Some(serde::json::Value::String(host)) => assert_eq!(*host, onion::encode(&pubkey)),
+                _ => panic!("expected an onion hostname"),
+            },
+            _ => panic!("expected an object"),
+        }
+        assert_eq!(KnownAddress::from_json(value).unwrap(), ka);
+        assert_eq!(onion::pubkey(&ip), Some(pubkey));
+    }
+
+    #[test]
+    fn test_known_address_score() {
+        let sockaddr = net::SocketAddr::from(([1, 2, 3, 4], 8333));
+        let now = LocalTime::from_secs(3600);
+        let mut ka = KnownAddress::new(
+            Address::new(&sockaddr, ServiceFlags::NETWORK),
+            Source::Dns,
+            None,
+        );
+
+        assert_eq!(ka.score(now), 1.);
+
+        ka.last_attempt = Some(now - RECENT_ATTEMPT);
+        ka.failures = 1;
+        let failed = ka.score(now);
+        assert!(failed < 1.);
+
+        ka.failures = MAX_FAILURES_PENALTY;
+        let capped = ka.score(now);
+        assert!(capped < failed);
+
+        ka.failures = MAX_FAILURES_PENALTY + 1;
+        assert_eq!(ka.score(now), capped);
+
+        // Recent attempts are penalized.
+        ka.last_attempt = Some(now);
+        assert!(ka.score(now) < 0.01);
+    }
 }
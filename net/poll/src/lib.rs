@@ -30,6 +30,7 @@
 #[cfg(unix)]
 pub mod reactor;
 pub mod socket;
+pub mod socks5;
 pub mod time;
 
 pub use reactor::{Reactor, Waker};
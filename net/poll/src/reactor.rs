@@ -3,6 +3,7 @@ use crossbeam_channel as chan;
 
 use nakamoto_net::error::Error;
 use nakamoto_net::event::Publisher;
+use nakamoto_net::onion;
 use nakamoto_net::time::{LocalDuration, LocalTime};
 use nakamoto_net::{Disconnect, Io, PeerId};
 use nakamoto_net::{Link, Service};
@@ -22,6 +23,7 @@ use std::time::SystemTime;
 
 use crate::fallible;
 use crate::socket::Socket;
+use crate::socks5;
 use crate::time::TimeoutManager;
 
 /// Maximum time to wait when reading from a socket.
@@ -61,11 +63,18 @@ impl nakamoto_net::Waker for Waker {
 pub struct Reactor<R: Write + Read, Id: PeerId = net::SocketAddr> {
     peers: HashMap<Id, Socket<R>>,
     connecting: HashSet<Id>,
+    /// Proxy handshakes in progress, for outbound connections made through a proxy.
+    handshakes: HashMap<Id, socks5::Handshake>,
+    /// SOCKS5 proxy to make outbound connections through, if any.
+    proxy: Option<net::SocketAddr>,
     sources: popol::Sources<Source<Id>>,
     waker: Waker,
     timeouts: TimeoutManager<()>,
     shutdown: chan::Receiver<()>,
     listening: chan::Sender<net::SocketAddr>,
+    /// Socket read buffer, shared by all peers, since data read is handed over to the service
+    /// right away.
+    buffer: Box<[u8]>,
 }
 
 /// The `R` parameter represents the underlying stream type, eg. `net::TcpStream`.
@@ -89,6 +98,7 @@ impl<R: Write + Read + AsRawFd, Id: PeerId> Reactor<R, Id> {
         S: Service<Id>,
     {
         self.connecting.remove(&addr);
+        self.handshakes.remove(&addr);
         self.peers.remove(&addr);
         self.sources.unregister(&Source::Peer(addr.clone()));
 
@@ -114,11 +124,14 @@ impl<Id: PeerId> nakamoto_net::Reactor<Id> for Reactor<net::TcpStream, Id> {
         Ok(Self {
             peers,
             connecting,
+            handshakes: HashMap::new(),
+            proxy: None,
             sources,
             waker,
             timeouts,
             shutdown,
             listening,
+            buffer: vec![0; READ_BUFFER_SIZE].into_boxed_slice(),
         })
     }
 
@@ -240,6 +253,8 @@ impl<Id: PeerId> nakamoto_net::Reactor<Id> for Reactor<net::TcpStream, Id> {
 
                                 // Exit reactor loop if a shutdown was received.
                                 if let Ok(()) = self.shutdown.try_recv() {
+                                    self.close(&mut service, &mut publisher);
+
                                     return Ok(());
                                 }
                                 popol::Waker::reset(ev.source).ok();
@@ -280,6 +295,12 @@ impl<Id: PeerId> nakamoto_net::Reactor<Id> for Reactor<net::TcpStream, Id> {
     fn waker(&self) -> Self::Waker {
         self.waker.clone()
     }
+
+    fn set_proxy(&mut self, proxy: net::SocketAddr) {
+        info!(target: "net", "Connecting to peers via proxy {}", proxy);
+
+        self.proxy = Some(proxy);
+    }
 }
 
 impl<Id: PeerId> Reactor<net::TcpStream, Id> {
@@ -306,13 +327,22 @@ impl<Id: PeerId> Reactor<net::TcpStream, Id> {
                     let socket_addr = addr.to_socket_addr();
                     trace!("Connecting to {}...", socket_addr);
 
-                    match self::dial(&socket_addr) {
-                        Ok(stream) => {
+                    match self::connect(&socket_addr, self.proxy) {
+                        Ok((stream, request)) => {
                             trace!("{:#?}", stream);
 
                             self.register_peer(addr.clone(), stream, Link::Outbound);
                             self.connecting.insert(addr.clone());
 
+                            // The proxy request is sent as soon as we're connected to the
+                            // proxy, and the connection is established once it replies.
+                            if let Some(request) = request {
+                                if let Some(socket) = self.peers.get_mut(&addr) {
+                                    socket.push(&request);
+                                }
+                                self.handshakes
+                                    .insert(addr.clone(), socks5::Handshake::default());
+                            }
                             service.attempted(&addr);
                         }
                         Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
@@ -351,6 +381,36 @@ impl<Id: PeerId> Reactor<net::TcpStream, Id> {
         }
     }
 
+    /// Close all peer connections and shut the service down. Pending writes are flushed on
+    /// a best-effort basis. Service outputs other than events are dropped from then on, since
+    /// no more connections or messages are expected.
+    fn close<S, E>(&mut self, service: &mut S, publisher: &mut E)
+    where
+        S: Service<Id>,
+        E: Publisher<S::Event>,
+    {
+        let peers = self.peers.keys().cloned().collect::<Vec<_>>();
+
+        info!(target: "net", "Shutting down, closing {} connection(s)..", peers.len());
+
+        for addr in peers {
+            if let Some(socket) = self.peers.get_mut(&addr) {
+                socket.flush().ok();
+                socket.disconnect().ok();
+            }
+            let err = io::Error::new(io::ErrorKind::ConnectionAborted, "shutting down");
+
+            self.unregister_peer(addr, Disconnect::ConnectionError(Arc::new(err)), service);
+        }
+        service.shutdown();
+
+        while let Some(out) = service.next() {
+            if let Io::Event(event) = out {
+                publisher.publish(event);
+            }
+        }
+    }
+
     fn handle_readable<S>(&mut self, addr: Id, service: &mut S)
     where
         S: Service<Id>,
@@ -359,8 +419,6 @@ impl<Id: PeerId> Reactor<net::TcpStream, Id> {
         // during an attempt to write, it will no longer be registered and hence available
         // for reads.
         if let Some(socket) = self.peers.get_mut(&addr) {
-            let mut buffer = [0; READ_BUFFER_SIZE];
-
             let socket_addr = addr.to_socket_addr();
             trace!("{}: Socket is readable", socket_addr);
 
@@ -368,12 +426,57 @@ impl<Id: PeerId> Reactor<net::TcpStream, Id> {
             // we will be notified again if there is still data to be read on the socket.
             // Hence, there is no use in putting this socket read in a loop, as the second
             // invocation would likely block.
-            match socket.read(&mut buffer) {
+            match socket.read(&mut self.buffer) {
                 Ok(count) => {
                     if count > 0 {
                         trace!("{}: Read {} bytes", socket_addr, count);
 
-                        service.message_received(&addr, Cow::Borrowed(&buffer[..count]));
+                        if let Some(handshake) = self.handshakes.get_mut(&addr) {
+                            // We're still connecting to the peer via the proxy.
+                            match handshake.received(&self.buffer[..count]) {
+                                Ok(None) => {}
+                                Ok(Some(rest)) => {
+                                    trace!("{}: Connected via proxy", socket_addr);
+
+                                    self.handshakes.remove(&addr);
+                                    self.connecting.remove(&addr);
+
+                                    match socket.local_address() {
+                                        Ok(local_addr) => {
+                                            service.connected(
+                                                addr.clone(),
+                                                &local_addr,
+                                                socket.link,
+                                            );
+
+                                            if !rest.is_empty() {
+                                                service.message_received(&addr, Cow::Owned(rest));
+                                            }
+                                        }
+                                        Err(err) => {
+                                            socket.disconnect().ok();
+                                            self.unregister_peer(
+                                                addr,
+                                                Disconnect::ConnectionError(Arc::new(err)),
+                                                service,
+                                            );
+                                        }
+                                    }
+                                }
+                                Err(err) => {
+                                    error!(target: "net", "{}: Proxy error: {}", socket_addr, err);
+
+                                    socket.disconnect().ok();
+                                    self.unregister_peer(
+                                        addr,
+                                        Disconnect::DialError(Arc::new(err)),
+                                        service,
+                                    );
+                                }
+                            }
+                        } else {
+                            service.message_received(&addr, Cow::Borrowed(&self.buffer[..count]));
+                        }
                     } else {
                         trace!("{}: Read 0 bytes", socket_addr);
                         // If we get zero bytes read as a return value, it means the peer has
@@ -419,8 +522,8 @@ impl<Id: PeerId> Reactor<net::TcpStream, Id> {
         // that it is ready for writing, once a connection has been established."
         //
         // Since we perform a non-blocking connect, we're only really connected once the socket
-        // is writable.
-        if self.connecting.remove(&addr) {
+        // is writable. When connecting via a proxy, we have to wait for its reply as well.
+        if !self.handshakes.contains_key(&addr) && self.connecting.remove(&addr) {
             let local_addr = socket.local_address()?;
 
             service.connected(addr.clone(), &local_addr, socket.link);
@@ -452,6 +555,39 @@ impl<Id: PeerId> Reactor<net::TcpStream, Id> {
     }
 }
 
+/// Connect to a peer, via the proxy if one is given. Returns the stream, along with the
+/// request to send to the proxy when connecting via a proxy.
+fn connect(
+    addr: &net::SocketAddr,
+    proxy: Option<net::SocketAddr>,
+) -> Result<(net::TcpStream, Option<Vec<u8>>), io::Error> {
+    // Onion services are only reachable by their hostname, via a proxy.
+    let onion = match addr {
+        net::SocketAddr::V6(a) if onion::is_onion(a.ip()) => Some(
+            onion::hostname(a.ip())
+                .ok_or_else(|| io::Error::from(io::ErrorKind::AddrNotAvailable))?,
+        ),
+        _ => None,
+    };
+
+    match (proxy, onion) {
+        (Some(proxy), onion) => {
+            let target = match onion {
+                Some(host) => socks5::Target::Domain(host, addr.port()),
+                None => socks5::Target::Addr(*addr),
+            };
+            let request = socks5::request(&target)?;
+
+            Ok((self::dial(&proxy)?, Some(request)))
+        }
+        (None, Some(_)) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "onion peers can only be connected to via a proxy",
+        )),
+        (None, None) => Ok((self::dial(addr)?, None)),
+    }
+}
+
 /// Connect to a peer given a remote address.
 fn dial(addr: &net::SocketAddr) -> Result<net::TcpStream, io::Error> {
     use socket2::{Domain, Socket, Type};
@@ -0,0 +1,193 @@
+//! Minimal SOCKS5 client, as specified in RFC 1928.
+//!
+//! Only the `CONNECT` command without authentication is supported, which is what Tor
+//! expects. The greeting and the connection request are sent together, saving a round-trip.
+use std::io;
+use std::net;
+
+/// Protocol version.
+const VERSION: u8 = 5;
+/// "No authentication required" method.
+const NO_AUTH: u8 = 0;
+/// `CONNECT` command.
+const CONNECT: u8 = 1;
+
+/// Address type: IPv4.
+const ATYP_IPV4: u8 = 1;
+/// Address type: domain name.
+const ATYP_DOMAIN: u8 = 3;
+/// Address type: IPv6.
+const ATYP_IPV6: u8 = 4;
+
+/// Connection target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// An IP address, resolved locally.
+    Addr(net::SocketAddr),
+    /// A host name, resolved by the proxy, eg. an onion service.
+    Domain(String, u16),
+}
+
+/// The bytes to send to the proxy to connect to the given target.
+pub fn request(target: &Target) -> io::Result<Vec<u8>> {
+    let mut buf = vec![VERSION, 1, NO_AUTH, VERSION, CONNECT, 0];
+
+    let port = match target {
+        Target::Addr(net::SocketAddr::V4(addr)) => {
+            buf.push(ATYP_IPV4);
+            buf.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        }
+        Target::Addr(net::SocketAddr::V6(addr)) => {
+            buf.push(ATYP_IPV6);
+            buf.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        }
+        Target::Domain(host, port) => {
+            let len = u8::try_from(host.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "host name too long"))?;
+
+            buf.push(ATYP_DOMAIN);
+            buf.push(len);
+            buf.extend_from_slice(host.as_bytes());
+            *port
+        }
+    };
+    buf.extend_from_slice(&port.to_be_bytes());
+
+    Ok(buf)
+}
+
+/// State of a handshake with the proxy. Accumulates the proxy's replies.
+#[derive(Debug, Default)]
+pub struct Handshake {
+    buffer: Vec<u8>,
+}
+
+impl Handshake {
+    /// Process bytes received from the proxy. Once the handshake is complete, returns any
+    /// bytes received past the end of it, which belong to the proxied connection.
+    pub fn received(&mut self, bytes: &[u8]) -> io::Result<Option<Vec<u8>>> {
+        self.buffer.extend_from_slice(bytes);
+
+        // Method selection reply. The proxy may close the connection right after it, if
+        // it rejects our method, so it is checked on its own.
+        if self.buffer.len() < 2 {
+            return Ok(None);
+        }
+        if self.buffer[0] != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid proxy protocol version",
+            ));
+        }
+        if self.buffer[1] != NO_AUTH {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "proxy requires authentication",
+            ));
+        }
+
+        // Connection reply header.
+        if self.buffer.len() < 2 + 5 {
+            return Ok(None);
+        }
+        if self.buffer[2] != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid proxy protocol version",
+            ));
+        }
+        if let Some(err) = self::reply_error(self.buffer[3]) {
+            return Err(err);
+        }
+        // Length of the address the proxy bound to, which we don't use.
+        let addr_len = match self.buffer[5] {
+            ATYP_IPV4 => 4,
+            ATYP_IPV6 => 16,
+            ATYP_DOMAIN => 1 + self.buffer[6] as usize,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid proxy address type",
+                ))
+            }
+        };
+        let len = 2 + 4 + addr_len + 2;
+
+        if self.buffer.len() < len {
+            return Ok(None);
+        }
+        Ok(Some(self.buffer.split_off(len)))
+    }
+}
+
+/// Convert a connection reply code into an error, if it isn't a success.
+fn reply_error(code: u8) -> Option<io::Error> {
+    let (kind, msg) = match code {
+        0 => return None,
+        2 => (
+            io::ErrorKind::PermissionDenied,
+            "connection not allowed by proxy",
+        ),
+        3 => (io::ErrorKind::Other, "network unreachable"),
+        4 => (io::ErrorKind::Other, "host unreachable"),
+        5 => (io::ErrorKind::ConnectionRefused, "connection refused"),
+        6 => (io::ErrorKind::TimedOut, "TTL expired"),
+        7 => (io::ErrorKind::Unsupported, "command not supported by proxy"),
+        8 => (
+            io::ErrorKind::Unsupported,
+            "address type not supported by proxy",
+        ),
+        _ => (io::ErrorKind::Other, "proxy failure"),
+    };
+    Some(io::Error::new(kind, msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request() {
+        let target = Target::Addr(([1, 2, 3, 4], 8333).into());
+        assert_eq!(
+            request(&target).unwrap(),
+            vec![5, 1, 0, 5, 1, 0, 1, 1, 2, 3, 4, 0x20, 0x8d]
+        );
+
+        let target = Target::Domain(String::from("a.onion"), 8333);
+        assert_eq!(
+            request(&target).unwrap(),
+            [
+                &[5, 1, 0, 5, 1, 0, 3, 7][..],
+                &b"a.onion"[..],
+                &[0x20, 0x8d][..]
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn test_handshake() {
+        let mut hs = Handshake::default();
+
+        assert!(hs.received(&[5, 0, 5, 0, 0]).unwrap().is_none());
+        assert!(hs.received(&[1, 0, 0, 0, 0, 0]).unwrap().is_none());
+        assert_eq!(hs.received(&[0, 42]).unwrap(), Some(vec![42]));
+
+        let mut hs = Handshake::default();
+        let err = hs.received(&[5, 0, 5, 4, 0, 1, 0]).unwrap_err();
+        assert_eq!(err.to_string(), "host unreachable");
+
+        // No acceptable method: the proxy only sends the method selection reply.
+        let mut hs = Handshake::default();
+        assert!(hs.received(&[5]).unwrap().is_none());
+        let err = hs.received(&[0xff]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+
+        let mut hs = Handshake::default();
+        let err = hs.received(&[4, 0]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
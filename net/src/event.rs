@@ -24,10 +24,23 @@ impl<E, T: Clone> Broadcast<E, T> {
     }
 }
 
+/// A subscription to events, optionally only to events matching a filter.
+struct Subscription<T> {
+    sender: chan::Sender<T>,
+    filter: Option<Box<dyn Fn(&T) -> bool + Send + Sync>>,
+}
+
+impl<T> Subscription<T> {
+    /// Check whether the subscription is interested in the given event.
+    fn matches(&self, event: &T) -> bool {
+        self.filter.as_ref().map_or(true, |f| f(event))
+    }
+}
+
 /// Publishes events to subscribers.
 #[derive(Clone)]
 pub struct Emitter<T> {
-    subscribers: Arc<Mutex<Vec<chan::Sender<T>>>>,
+    subscribers: Arc<Mutex<Vec<Subscription<T>>>>,
 }
 
 impl<T> Default for Emitter<T> {
@@ -39,12 +52,12 @@ impl<T> Default for Emitter<T> {
 }
 
 impl<T: Clone> Emitter<T> {
-    /// Emit an event to all subscribers and drop subscribers who can't receive it.
+    /// Emit an event to all interested subscribers and drop subscribers who can't receive it.
     pub fn emit(&self, event: T) {
         self.subscribers
             .lock()
             .unwrap()
-            .retain(|s| s.try_send(event.clone()).is_ok());
+            .retain(|s| !s.matches(&event) || s.sender.try_send(event.clone()).is_ok());
     }
 
     /// Drop all subscribers.
@@ -63,15 +76,28 @@ impl<T: Clone> Emitter<T> {
 /// Subscribes to events.
 #[derive(Clone)]
 pub struct Subscriber<T> {
-    subscribers: Arc<Mutex<Vec<chan::Sender<T>>>>,
+    subscribers: Arc<Mutex<Vec<Subscription<T>>>>,
 }
 
 impl<T: Clone> Subscriber<T> {
     /// Add a subscription to receive broadcast events.
     pub fn subscribe(&self) -> chan::Receiver<T> {
+        self.add(None)
+    }
+
+    /// Add a subscription to receive the broadcast events matching the given filter only.
+    /// Other events are never sent on the returned channel.
+    pub fn subscribe_filtered(
+        &self,
+        filter: impl Fn(&T) -> bool + Send + Sync + 'static,
+    ) -> chan::Receiver<T> {
+        self.add(Some(Box::new(filter)))
+    }
+
+    fn add(&self, filter: Option<Box<dyn Fn(&T) -> bool + Send + Sync>>) -> chan::Receiver<T> {
         let (sender, receiver) = chan::unbounded();
         let mut subs = self.subscribers.lock().unwrap();
-        subs.push(sender);
+        subs.push(Subscription { sender, filter });
 
         receiver
     }
@@ -9,6 +9,7 @@ use crossbeam_channel as chan;
 
 pub mod error;
 pub mod event;
+pub mod onion;
 pub mod simulator;
 pub mod time;
 
@@ -163,6 +164,10 @@ pub trait StateMachine<Id: PeerId = net::SocketAddr>:
     fn tick(&mut self, local_time: LocalTime);
     /// A timer set with [`Io::SetTimer`] has expired.
     fn timer_expired(&mut self);
+    /// Called by the reactor when it's shutting down, once peer connections are closed, and
+    /// before the state machine is dropped. Used to persist state that is still buffered.
+    /// Events output from then on are still published.
+    fn shutdown(&mut self) {}
 }
 
 /// Used by certain types of reactors to wake the event loop, for example when a
@@ -212,4 +217,10 @@ pub trait Reactor<Id: PeerId = net::SocketAddr> {
     /// The reactor can provide multiple wakers such that multiple user threads may wake
     /// the event loop.
     fn waker(&self) -> Self::Waker;
+
+    /// Route all outbound connections through the given SOCKS5 proxy, eg. Tor.
+    ///
+    /// Connections to [`onion`] service placeholder addresses are only possible through a
+    /// proxy.
+    fn set_proxy(&mut self, proxy: net::SocketAddr);
 }
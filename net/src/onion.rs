@@ -0,0 +1,187 @@
+//! Tor onion service addresses.
+//!
+//! Peers are identified by socket address, which can't represent an onion service. Instead,
+//! each onion service we learn about is given a placeholder IPv6 address in the OnionCat range,
+//! `fd87:d87e:eb43::/48`, which is mapped back to the service's hostname when connecting to it
+//! through a proxy.
+use std::collections::BTreeMap;
+use std::net;
+use std::sync::Mutex;
+
+use sha3::{Digest, Sha3_256};
+
+/// Prefix of onion service placeholder addresses.
+const PREFIX: [u8; 6] = [0xfd, 0x87, 0xd8, 0x7e, 0xeb, 0x43];
+/// Onion service version supported.
+const VERSION: u8 = 3;
+/// Onion hostname suffix.
+const SUFFIX: &str = ".onion";
+/// Base32 alphabet used in onion hostnames.
+const ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// Public keys of the onion services known to this process, keyed by placeholder address.
+static SERVICES: Mutex<BTreeMap<net::Ipv6Addr, [u8; 32]>> = Mutex::new(BTreeMap::new());
+
+/// Check whether an address is an onion service placeholder.
+pub const fn is_onion(ip: &net::Ipv6Addr) -> bool {
+    let octets = ip.octets();
+    let mut i = 0;
+
+    while i < PREFIX.len() {
+        if octets[i] != PREFIX[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Register an onion service given its public key, and return its placeholder address.
+///
+/// The placeholder is derived from the key, so registering a service twice yields the same
+/// address.
+pub fn register(pubkey: [u8; 32]) -> net::Ipv6Addr {
+    let mut octets = [0; 16];
+
+    octets[..PREFIX.len()].copy_from_slice(&PREFIX);
+    octets[PREFIX.len()..].copy_from_slice(&pubkey[..16 - PREFIX.len()]);
+
+    let ip = net::Ipv6Addr::from(octets);
+
+    SERVICES
+        .lock()
+        .expect("the lock is never poisoned")
+        .insert(ip, pubkey);
+
+    ip
+}
+
+/// Get the public key of a registered onion service, given its placeholder address.
+pub fn pubkey(ip: &net::Ipv6Addr) -> Option<[u8; 32]> {
+    SERVICES
+        .lock()
+        .expect("the lock is never poisoned")
+        .get(ip)
+        .copied()
+}
+
+/// Get the hostname of a registered onion service, eg. `<base32>.onion`, given its placeholder
+/// address.
+pub fn hostname(ip: &net::Ipv6Addr) -> Option<String> {
+    self::pubkey(ip).map(|k| self::encode(&k))
+}
+
+/// Parse an onion service hostname, returning the service's public key. Only version 3
+/// services are supported.
+pub fn parse(host: &str) -> Option<[u8; 32]> {
+    let host = host.strip_suffix(SUFFIX)?;
+    let bytes = self::base32_decode(host)?;
+
+    if bytes.len() != 35 || bytes[34] != VERSION {
+        return None;
+    }
+    let mut pubkey = [0; 32];
+    pubkey.copy_from_slice(&bytes[..32]);
+
+    if bytes[32..34] != self::checksum(&pubkey) {
+        return None;
+    }
+    Some(pubkey)
+}
+
+/// Encode an onion service public key as a hostname.
+pub fn encode(pubkey: &[u8; 32]) -> String {
+    let mut bytes = Vec::with_capacity(35);
+
+    bytes.extend_from_slice(pubkey);
+    bytes.extend_from_slice(&self::checksum(pubkey));
+    bytes.push(VERSION);
+
+    let mut host = self::base32_encode(&bytes);
+    host.push_str(SUFFIX);
+    host
+}
+
+/// Onion address checksum, as specified in Tor's `rend-spec-v3`.
+fn checksum(pubkey: &[u8; 32]) -> [u8; 2] {
+    let mut hasher = Sha3_256::new();
+
+    hasher.update(b".onion checksum");
+    hasher.update(pubkey);
+    hasher.update([VERSION]);
+
+    let digest = hasher.finalize();
+
+    [digest[0], digest[1]]
+}
+
+/// Lowercase, unpadded base32 encoding, as used in onion hostnames.
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity((bytes.len() * 8 + 4) / 5);
+    let mut buffer: u16 = 0;
+    let mut bits = 0;
+
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u16;
+        bits += 8;
+
+        while bits >= 5 {
+            bits -= 5;
+            out.push(ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+/// Decode lowercase, unpadded base32. Trailing bits that don't make a full byte are dropped.
+fn base32_decode(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() * 5 / 8);
+    let mut buffer: u16 = 0;
+    let mut bits = 0;
+
+    for c in s.bytes() {
+        let value = ALPHABET.iter().position(|a| *a == c)? as u16;
+
+        buffer = (buffer << 5) | value;
+        bits += 5;
+
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOST: &str = "duckduckgogg42xjoc72x3sjasowoarfbgcmvfimaftt6twagswzczad.onion";
+
+    #[test]
+    fn test_parse_encode() {
+        let pubkey = parse(HOST).unwrap();
+
+        assert_eq!(encode(&pubkey), HOST);
+        // Bad checksum.
+        assert_eq!(parse(&HOST.replacen('d', "e", 1)), None);
+        // Not an onion.
+        assert_eq!(parse("duckduckgo.com"), None);
+    }
+
+    #[test]
+    fn test_register() {
+        let pubkey = parse(HOST).unwrap();
+        let ip = register(pubkey);
+
+        assert!(is_onion(&ip));
+        assert!(!is_onion(&net::Ipv6Addr::LOCALHOST));
+        assert_eq!(register(pubkey), ip);
+        assert_eq!(hostname(&ip).as_deref(), Some(HOST));
+        assert_eq!(hostname(&net::Ipv6Addr::LOCALHOST), None);
+    }
+}
@@ -0,0 +1,68 @@
+//! Async streams of client events.
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::thread;
+
+use crossbeam_channel as chan;
+use futures_core::Stream;
+use tokio::sync::mpsc;
+
+/// A stream of events, fed from an event subscription.
+#[derive(Debug)]
+pub struct Events<T> {
+    receiver: mpsc::UnboundedReceiver<T>,
+}
+
+impl<T> Events<T> {
+    /// Receive the next event. Returns `None` once the subscription is closed, eg. because
+    /// the client shut down.
+    pub async fn recv(&mut self) -> Option<T> {
+        self.receiver.recv().await
+    }
+}
+
+impl<T> Stream for Events<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// Turn an event subscription, eg. the client's event channel, into an async stream.
+///
+/// Events are forwarded from the subscription by a background thread, which exits once
+/// either the subscription or the stream is closed.
+pub fn events<T: Send + 'static>(subscription: chan::Receiver<T>) -> Events<T> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    thread::spawn(move || {
+        for event in subscription {
+            if sender.send(event).is_err() {
+                break;
+            }
+        }
+    });
+
+    Events { receiver }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_events() {
+        let (sender, subscription) = chan::unbounded();
+        let mut events = events(subscription);
+
+        sender.send(1).unwrap();
+        sender.send(2).unwrap();
+        assert_eq!(events.recv().await, Some(1));
+        assert_eq!(events.recv().await, Some(2));
+
+        // The stream ends with the subscription.
+        drop(sender);
+        assert_eq!(events.recv().await, None);
+    }
+}
@@ -0,0 +1,23 @@
+//! Tokio-based reactor that drives the protocol state machine.
+//!
+//! This is an alternative to the poll-based reactor, for applications that already run a
+//! [tokio](https://tokio.rs) runtime. Peer connections are handled by tasks spawned on the
+//! application's runtime, instead of a dedicated reactor thread, and client events can be
+//! consumed as async streams with [`events`].
+//!
+//! The reactor can be driven in two ways:
+//!
+//! 1. Through [`nakamoto_net::Reactor::run`], as with any other reactor, eg. via the client's
+//!    `run` function. This blocks the calling thread, which must not be a runtime thread, so
+//!    it should be called with [`tokio::task::spawn_blocking`]. The reactor's tasks are then
+//!    spawned on the runtime that was current when the reactor was created, which should be a
+//!    multi-threaded runtime. If there was no runtime, the reactor creates its own.
+//! 2. Through [`Reactor::run_async`], which returns a future to be awaited on the runtime.
+//!
+//! To await the replies of client handle methods, see the client's `async_handle` module,
+//! enabled with its `tokio` feature.
+pub mod events;
+pub mod reactor;
+
+pub use events::{events, Events};
+pub use reactor::{Reactor, Waker};
@@ -0,0 +1,696 @@
+//! Tokio-based reactor. The event loop runs in a single task, while each peer connection is
+//! handled by a task of its own, which forwards what it reads to the event loop, and writes
+//! what the event loop sends it.
+use crossbeam_channel as chan;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinHandle;
+
+use nakamoto_net::error::Error;
+use nakamoto_net::event::Publisher;
+use nakamoto_net::onion;
+use nakamoto_net::time::{LocalDuration, LocalTime};
+use nakamoto_net::{Disconnect, Io, PeerId};
+use nakamoto_net::{Link, Service};
+use nakamoto_net_poll::socks5;
+use nakamoto_net_poll::time::TimeoutManager;
+
+use log::*;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io;
+use std::net;
+use std::sync::Arc;
+use std::time;
+use std::time::SystemTime;
+
+/// Maximum time to wait for a connection to be established.
+const CONNECT_TIMEOUT: time::Duration = time::Duration::from_secs(6);
+/// Maximum time to wait for pending writes to be flushed when shutting down.
+const SHUTDOWN_TIMEOUT: time::Duration = time::Duration::from_secs(3);
+/// Maximum amount of time to wait for i/o.
+const WAIT_TIMEOUT: LocalDuration = LocalDuration::from_mins(60);
+/// Socket read buffer size.
+const READ_BUFFER_SIZE: usize = 1024 * 192;
+
+/// Identifies a connection, so that inputs from closed connections can be told apart from
+/// inputs from new connections to the same peer.
+type ConnId = u64;
+
+/// Inputs sent to the event loop by the connection tasks.
+#[derive(Debug)]
+enum Input {
+    /// An inbound connection was accepted.
+    Accepted(TcpStream, net::SocketAddr),
+    /// An outbound connection was established. Includes bytes received past the end of the
+    /// proxy handshake, if any.
+    Connected {
+        conn: ConnId,
+        local_addr: net::SocketAddr,
+        received: Vec<u8>,
+    },
+    /// Bytes were received on a connection.
+    Received(ConnId, Vec<u8>),
+    /// A connection failed or was closed by the remote.
+    Failed {
+        conn: ConnId,
+        /// Whether the connection failed before it was established.
+        dial: bool,
+        err: io::Error,
+    },
+}
+
+/// A peer connection, handled by a task.
+#[derive(Debug)]
+struct Peer {
+    conn: ConnId,
+    /// Bytes to write to the peer. Dropping it closes the connection once pending writes
+    /// are flushed.
+    outbox: mpsc::UnboundedSender<Vec<u8>>,
+    task: JoinHandle<()>,
+}
+
+/// The runtime the reactor's tasks are spawned on.
+#[derive(Debug)]
+enum Runtime {
+    /// The runtime that was current when the reactor was created.
+    Shared(tokio::runtime::Handle),
+    /// A runtime created by the reactor, since there was none.
+    Owned(tokio::runtime::Runtime),
+}
+
+impl Runtime {
+    fn block_on<F: std::future::Future>(&self, future: F) -> F::Output {
+        match self {
+            Self::Shared(handle) => handle.block_on(future),
+            Self::Owned(runtime) => runtime.block_on(future),
+        }
+    }
+}
+
+/// Wakes the event loop up, eg. when a command is ready to be processed.
+#[derive(Clone, Debug)]
+pub struct Waker(Arc<Notify>);
+
+impl nakamoto_net::Waker for Waker {
+    fn wake(&self) -> io::Result<()> {
+        self.0.notify_one();
+
+        Ok(())
+    }
+}
+
+/// A tokio-based reactor.
+pub struct Reactor<Id: PeerId = net::SocketAddr> {
+    peers: HashMap<Id, Peer>,
+    /// Peers of each connection.
+    conns: HashMap<ConnId, Id>,
+    /// Next connection id.
+    next_conn: ConnId,
+    /// Outbound connections being established.
+    connecting: HashMap<ConnId, Id>,
+    /// SOCKS5 proxy to make outbound connections through, if any.
+    proxy: Option<net::SocketAddr>,
+    runtime: Option<Runtime>,
+    waker: Waker,
+    timeouts: TimeoutManager<()>,
+    shutdown: chan::Receiver<()>,
+    listening: chan::Sender<net::SocketAddr>,
+}
+
+impl<Id: PeerId> Reactor<Id> {
+    /// Run the given service with the reactor, until it's shut down. The returned future must
+    /// be awaited on a tokio runtime.
+    ///
+    /// See [`nakamoto_net::Reactor::run`].
+    pub async fn run_async<S, E>(
+        &mut self,
+        listen_addrs: &[net::SocketAddr],
+        mut service: S,
+        mut publisher: E,
+        commands: chan::Receiver<S::Command>,
+    ) -> Result<(), Error>
+    where
+        S: Service<Id>,
+        S::DisconnectReason: Into<Disconnect<S::DisconnectReason>>,
+        E: Publisher<S::Event>,
+    {
+        let (inputs_tx, mut inputs) = mpsc::unbounded_channel();
+        let notify = self.waker.0.clone();
+
+        let listener = if listen_addrs.is_empty() {
+            None
+        } else {
+            let listener = TcpListener::bind(listen_addrs).await?;
+            let local_addr = listener.local_addr()?;
+
+            self.listening.send(local_addr).ok();
+
+            info!(target: "net", "Listening on {}", local_addr);
+
+            Some(tokio::spawn(self::accept(listener, inputs_tx.clone())))
+        };
+
+        info!(target: "net", "Initializing service..");
+
+        let local_time = SystemTime::now().into();
+        service.initialize(local_time);
+
+        self.process(&mut service, &mut publisher, local_time, &inputs_tx);
+
+        // Timeouts populated by `TimeoutManager::wake`.
+        let mut timeouts = Vec::with_capacity(32);
+
+        loop {
+            let timeout: time::Duration = self
+                .timeouts
+                .next(SystemTime::now())
+                .unwrap_or(WAIT_TIMEOUT)
+                .into();
+
+            trace!(
+                "Waiting on {} peer(s) and {} timeout(s), waking up in {:?}..",
+                self.peers.len(),
+                self.timeouts.len(),
+                timeout
+            );
+
+            tokio::select! {
+                input = inputs.recv() => {
+                    let local_time = SystemTime::now().into();
+                    service.tick(local_time);
+
+                    // The event loop holds on to a sender, so the channel is never closed.
+                    if let Some(input) = input {
+                        self.handle_input(input, &mut service, &inputs_tx);
+                    }
+                    self.process(&mut service, &mut publisher, local_time, &inputs_tx);
+                }
+                () = notify.notified() => {
+                    let local_time = SystemTime::now().into();
+                    service.tick(local_time);
+
+                    trace!("Woken up by waker ({} command(s))", commands.len());
+
+                    // Exit reactor loop if a shutdown was received.
+                    if let Ok(()) = self.shutdown.try_recv() {
+                        if let Some(listener) = &listener {
+                            listener.abort();
+                        }
+                        self.close(&mut service, &mut publisher).await;
+
+                        return Ok(());
+                    }
+                    for cmd in commands.try_iter() {
+                        service.command_received(cmd);
+                    }
+                    self.process(&mut service, &mut publisher, local_time, &inputs_tx);
+                }
+                () = tokio::time::sleep(timeout) => {
+                    let local_time = SystemTime::now().into();
+                    service.tick(local_time);
+
+                    // Nb. The way this is currently used basically ignores which keys have
+                    // timed out. So as long as *something* timed out, we wake the service.
+                    self.timeouts.wake(local_time, &mut timeouts);
+
+                    if !timeouts.is_empty() {
+                        timeouts.clear();
+                        service.timer_expired();
+                    }
+                    self.process(&mut service, &mut publisher, local_time, &inputs_tx);
+                }
+            }
+        }
+    }
+
+    /// Register a peer connection, handled by the given task.
+    fn register_peer(
+        &mut self,
+        addr: Id,
+        conn: ConnId,
+        outbox: mpsc::UnboundedSender<Vec<u8>>,
+        task: JoinHandle<()>,
+    ) {
+        self.conns.insert(conn, addr.clone());
+        self.peers.insert(addr, Peer { conn, outbox, task });
+    }
+
+    /// Unregister a peer from the reactor, aborting its connection task.
+    fn unregister_peer<S>(
+        &mut self,
+        addr: Id,
+        reason: Disconnect<S::DisconnectReason>,
+        service: &mut S,
+    ) where
+        S: Service<Id>,
+    {
+        if let Some(peer) = self.peers.remove(&addr) {
+            self.conns.remove(&peer.conn);
+            self.connecting.remove(&peer.conn);
+            peer.task.abort();
+        }
+        service.disconnected(&addr, reason);
+    }
+
+    fn conn_id(&mut self) -> ConnId {
+        self.next_conn += 1;
+        self.next_conn
+    }
+
+    /// Handle an input from a connection task.
+    fn handle_input<S>(
+        &mut self,
+        input: Input,
+        service: &mut S,
+        inputs: &mpsc::UnboundedSender<Input>,
+    ) where
+        S: Service<Id>,
+    {
+        match input {
+            Input::Accepted(stream, socket_addr) => {
+                trace!("{}: Accepting peer connection", socket_addr);
+
+                let local_addr = match stream.local_addr() {
+                    Ok(local_addr) => local_addr,
+                    Err(err) => {
+                        error!(target: "net", "{}: Accept error: {}", socket_addr, err);
+                        return;
+                    }
+                };
+                let addr = Id::from(socket_addr);
+                let conn = self.conn_id();
+                let (outbox, receiver) = mpsc::unbounded_channel();
+                let task = tokio::spawn(self::peer(conn, stream, receiver, inputs.clone()));
+
+                self.register_peer(addr.clone(), conn, outbox, task);
+
+                service.connected(addr, &local_addr, Link::Inbound);
+            }
+            Input::Connected {
+                conn,
+                local_addr,
+                received,
+            } => {
+                if let Some(addr) = self.connecting.remove(&conn) {
+                    trace!("{}: Connected", addr.to_socket_addr());
+
+                    service.connected(addr.clone(), &local_addr, Link::Outbound);
+
+                    if !received.is_empty() {
+                        service.message_received(&addr, Cow::Owned(received));
+                    }
+                }
+            }
+            Input::Received(conn, bytes) => {
+                if let Some(addr) = self.conns.get(&conn) {
+                    trace!("{}: Read {} bytes", addr.to_socket_addr(), bytes.len());
+
+                    service.message_received(addr, Cow::Owned(bytes));
+                }
+            }
+            Input::Failed { conn, dial, err } => {
+                if let Some(addr) = self.conns.get(&conn).cloned() {
+                    let reason = if dial {
+                        error!(target: "net", "{}: Dial error: {}", addr.to_socket_addr(), err);
+
+                        Disconnect::DialError(Arc::new(err))
+                    } else {
+                        trace!("{}: Connection error: {}", addr.to_socket_addr(), err);
+
+                        Disconnect::ConnectionError(Arc::new(err))
+                    };
+                    self.unregister_peer(addr, reason, service);
+                }
+            }
+        }
+    }
+
+    /// Process service state machine outputs.
+    fn process<S, E>(
+        &mut self,
+        service: &mut S,
+        publisher: &mut E,
+        local_time: LocalTime,
+        inputs: &mpsc::UnboundedSender<Input>,
+    ) where
+        S: Service<Id>,
+        E: Publisher<S::Event>,
+        S::DisconnectReason: Into<Disconnect<S::DisconnectReason>>,
+    {
+        // Note that there may be messages destined for a peer that has since been
+        // disconnected.
+        while let Some(out) = service.next() {
+            match out {
+                Io::Write(addr, bytes) => {
+                    if let Some(peer) = self.peers.get(&addr) {
+                        peer.outbox.send(bytes).ok();
+                    }
+                }
+                Io::Connect(addr) => {
+                    if self.peers.contains_key(&addr) {
+                        // Ignore. We are already connected or establishing a connection
+                        // to this peer.
+                        continue;
+                    }
+                    let socket_addr = addr.to_socket_addr();
+                    trace!("Connecting to {}...", socket_addr);
+
+                    let conn = self.conn_id();
+                    let (outbox, receiver) = mpsc::unbounded_channel();
+                    let task = tokio::spawn(self::connect(
+                        conn,
+                        socket_addr,
+                        self.proxy,
+                        receiver,
+                        inputs.clone(),
+                    ));
+
+                    self.register_peer(addr.clone(), conn, outbox, task);
+                    self.connecting.insert(conn, addr.clone());
+
+                    service.attempted(&addr);
+                }
+                Io::Disconnect(addr, reason) => {
+                    if self.peers.contains_key(&addr) {
+                        trace!("{}: Disconnecting: {}", addr.to_socket_addr(), reason);
+
+                        self.unregister_peer(addr, reason.into(), service);
+                    }
+                }
+                Io::SetTimer(timeout) => {
+                    self.timeouts.register((), local_time + timeout);
+                }
+                Io::Event(event) => {
+                    trace!("Event: {:?}", event);
+
+                    publisher.publish(event);
+                }
+            }
+        }
+    }
+
+    /// Close all peer connections and shut the service down. Pending writes are flushed on
+    /// a best-effort basis. Service outputs other than events are dropped from then on, since
+    /// no more connections or messages are expected.
+    async fn close<S, E>(&mut self, service: &mut S, publisher: &mut E)
+    where
+        S: Service<Id>,
+        E: Publisher<S::Event>,
+    {
+        info!(target: "net", "Shutting down, closing {} connection(s)..", self.peers.len());
+
+        let mut tasks = Vec::with_capacity(self.peers.len());
+
+        for (addr, peer) in self.peers.drain() {
+            // Dropping the outbox lets the task flush pending writes, and close the connection.
+            drop(peer.outbox);
+            tasks.push(peer.task);
+
+            let err = io::Error::new(io::ErrorKind::ConnectionAborted, "shutting down");
+
+            service.disconnected(&addr, Disconnect::ConnectionError(Arc::new(err)));
+        }
+        self.conns.clear();
+        self.connecting.clear();
+
+        let flushed = tokio::time::timeout(SHUTDOWN_TIMEOUT, async {
+            for task in tasks.iter_mut() {
+                task.await.ok();
+            }
+        })
+        .await;
+
+        if flushed.is_err() {
+            warn!(target: "net", "Timed out flushing connections, aborting them..");
+
+            for task in tasks {
+                task.abort();
+            }
+        }
+        service.shutdown();
+
+        while let Some(out) = service.next() {
+            if let Io::Event(event) = out {
+                publisher.publish(event);
+            }
+        }
+    }
+}
+
+impl<Id: PeerId> nakamoto_net::Reactor<Id> for Reactor<Id> {
+    type Waker = Waker;
+
+    /// Construct a new reactor, given a channel to send events on.
+    fn new(
+        shutdown: chan::Receiver<()>,
+        listening: chan::Sender<net::SocketAddr>,
+    ) -> Result<Self, io::Error> {
+        let runtime = match tokio::runtime::Handle::try_current() {
+            Ok(handle) => Runtime::Shared(handle),
+            Err(_) => Runtime::Owned(
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()?,
+            ),
+        };
+
+        Ok(Self {
+            peers: HashMap::new(),
+            conns: HashMap::new(),
+            next_conn: 0,
+            connecting: HashMap::new(),
+            proxy: None,
+            runtime: Some(runtime),
+            waker: Waker(Arc::new(Notify::new())),
+            timeouts: TimeoutManager::new(LocalDuration::from_secs(1)),
+            shutdown,
+            listening,
+        })
+    }
+
+    /// Run the given service with the reactor, blocking until it's shut down.
+    ///
+    /// Must not be called from a runtime thread. See [`Reactor::run_async`].
+    fn run<S, E>(
+        &mut self,
+        listen_addrs: &[net::SocketAddr],
+        service: S,
+        publisher: E,
+        commands: chan::Receiver<S::Command>,
+    ) -> Result<(), Error>
+    where
+        S: Service<Id>,
+        S::DisconnectReason: Into<Disconnect<S::DisconnectReason>>,
+        E: Publisher<S::Event>,
+    {
+        // The runtime is moved out for the duration of the run, so that it isn't borrowed
+        // along with the reactor.
+        let runtime = self
+            .runtime
+            .take()
+            .expect("Reactor::run: reactor is already running");
+        let result = runtime.block_on(self.run_async(listen_addrs, service, publisher, commands));
+
+        self.runtime = Some(runtime);
+
+        result
+    }
+
+    /// Return a new waker.
+    ///
+    /// Used to wake up the main event loop.
+    fn waker(&self) -> Self::Waker {
+        self.waker.clone()
+    }
+
+    fn set_proxy(&mut self, proxy: net::SocketAddr) {
+        info!(target: "net", "Connecting to peers via proxy {}", proxy);
+
+        self.proxy = Some(proxy);
+    }
+}
+
+/// Accept inbound connections, and forward them to the event loop.
+async fn accept(listener: TcpListener, inputs: mpsc::UnboundedSender<Input>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                if inputs.send(Input::Accepted(stream, addr)).is_err() {
+                    break;
+                }
+            }
+            Err(err) => {
+                error!(target: "net", "Accept error: {}", err);
+            }
+        }
+    }
+}
+
+/// Establish an outbound connection, and handle it once it's established.
+async fn connect(
+    conn: ConnId,
+    addr: net::SocketAddr,
+    proxy: Option<net::SocketAddr>,
+    outbox: mpsc::UnboundedReceiver<Vec<u8>>,
+    inputs: mpsc::UnboundedSender<Input>,
+) {
+    let dialed = tokio::time::timeout(CONNECT_TIMEOUT, self::dial(&addr, proxy))
+        .await
+        .unwrap_or_else(|_| Err(io::Error::from(io::ErrorKind::TimedOut)))
+        .and_then(|(stream, received)| Ok((stream.local_addr()?, stream, received)));
+
+    match dialed {
+        Ok((local_addr, stream, received)) => {
+            let connected = Input::Connected {
+                conn,
+                local_addr,
+                received,
+            };
+            if inputs.send(connected).is_ok() {
+                self::peer(conn, stream, outbox, inputs).await;
+            }
+        }
+        Err(err) => {
+            inputs
+                .send(Input::Failed {
+                    conn,
+                    dial: true,
+                    err,
+                })
+                .ok();
+        }
+    }
+}
+
+/// Handle a peer connection: forward what is read to the event loop, and write what the
+/// event loop sends, until the connection fails or the event loop drops it.
+async fn peer(
+    conn: ConnId,
+    mut stream: TcpStream,
+    mut outbox: mpsc::UnboundedReceiver<Vec<u8>>,
+    inputs: mpsc::UnboundedSender<Input>,
+) {
+    let mut buffer = vec![0; READ_BUFFER_SIZE];
+
+    let err = loop {
+        tokio::select! {
+            read = stream.read(&mut buffer) => match read {
+                // If we get zero bytes read as a return value, it means the peer has
+                // performed an orderly shutdown.
+                Ok(0) => break io::Error::from(io::ErrorKind::ConnectionReset),
+                Ok(count) => {
+                    if inputs.send(Input::Received(conn, buffer[..count].to_vec())).is_err() {
+                        return;
+                    }
+                }
+                Err(err) => break err,
+            },
+            bytes = outbox.recv() => match bytes {
+                Some(bytes) => {
+                    if let Err(err) = stream.write_all(&bytes).await {
+                        break err;
+                    }
+                }
+                // The event loop dropped the connection, and pending writes were flushed.
+                None => {
+                    stream.shutdown().await.ok();
+                    return;
+                }
+            },
+        }
+    };
+    inputs
+        .send(Input::Failed {
+            conn,
+            dial: false,
+            err,
+        })
+        .ok();
+}
+
+/// Connect to a peer, via the proxy if one is given. Returns the stream, along with any
+/// bytes received past the end of the proxy handshake.
+async fn dial(
+    addr: &net::SocketAddr,
+    proxy: Option<net::SocketAddr>,
+) -> Result<(TcpStream, Vec<u8>), io::Error> {
+    // Onion services are only reachable by their hostname, via a proxy.
+    let onion = match addr {
+        net::SocketAddr::V6(a) if onion::is_onion(a.ip()) => Some(
+            onion::hostname(a.ip())
+                .ok_or_else(|| io::Error::from(io::ErrorKind::AddrNotAvailable))?,
+        ),
+        _ => None,
+    };
+
+    match (proxy, onion) {
+        (Some(proxy), onion) => {
+            let target = match onion {
+                Some(host) => socks5::Target::Domain(host, addr.port()),
+                None => socks5::Target::Addr(*addr),
+            };
+            let mut stream = TcpStream::connect(proxy).await?;
+            let mut handshake = socks5::Handshake::default();
+            let mut buffer = [0; 256];
+
+            stream.write_all(&socks5::request(&target)?).await?;
+
+            loop {
+                let count = stream.read(&mut buffer).await?;
+
+                if count == 0 {
+                    return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+                }
+                if let Some(received) = handshake.received(&buffer[..count])? {
+                    return Ok((stream, received));
+                }
+            }
+        }
+        (None, Some(_)) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "onion peers can only be connected to via a proxy",
+        )),
+        (None, None) => Ok((TcpStream::connect(addr).await?, Vec::new())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_peer() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (outbox_tx, outbox) = mpsc::unbounded_channel();
+        let (inputs_tx, mut inputs) = mpsc::unbounded_channel();
+
+        let task = tokio::spawn(self::connect(7, addr, None, outbox, inputs_tx));
+        let (mut remote, _) = listener.accept().await.unwrap();
+
+        assert!(matches!(
+            inputs.recv().await,
+            Some(Input::Connected { conn: 7, .. })
+        ));
+
+        // Bytes are forwarded both ways.
+        outbox_tx.send(b"ping".to_vec()).unwrap();
+        let mut buffer = [0; 4];
+        remote.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"ping");
+
+        remote.write_all(b"pong").await.unwrap();
+        match inputs.recv().await {
+            Some(Input::Received(7, bytes)) => assert_eq!(bytes, b"pong"),
+            other => panic!("unexpected input {:?}", other),
+        }
+
+        // Dropping the outbox closes the connection.
+        drop(outbox_tx);
+        task.await.unwrap();
+        assert_eq!(remote.read(&mut buffer).await.unwrap(), 0);
+    }
+}
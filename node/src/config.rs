@@ -0,0 +1,262 @@
+//! Configuration file support.
+//!
+//! Settings are read from a TOML file at startup, by default `config.toml` in the platform
+//! configuration directory, eg. `~/.config/nakamoto-cash/config.toml` on Linux, or in the
+//! legacy `~/.nakamoto-cash` directory if it's found there. Every setting is optional, and
+//! command-line flags take precedence over the file. The same file is shared by the node and
+//! the wallet; each one ignores the settings it has no use for.
+//!
+//! ```toml
+//! network = "chipnet"
+//! connect = ["127.0.0.1:48333"]
+//! log = "debug"
+//!
+//! [bloom]
+//! fp-rate = 0.0001
+//!
+//! [limits]
+//! max-inbound-peers = 8
+//! ```
+use std::path::{Path, PathBuf};
+use std::{fs, io, net};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use nakamoto_client::datadir;
+use nakamoto_client::Network;
+
+/// Name of the configuration file.
+pub const FILE_NAME: &str = "config.toml";
+
+/// A configuration file error.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// The file couldn't be read.
+    #[error("error reading {path:?}: {err}")]
+    Io {
+        /// File path.
+        path: PathBuf,
+        /// Underlying error.
+        #[source]
+        err: io::Error,
+    },
+    /// The file couldn't be parsed.
+    #[error("error parsing {path:?}: {err}")]
+    Parse {
+        /// File path.
+        path: PathBuf,
+        /// Underlying error.
+        #[source]
+        err: toml::de::Error,
+    },
+}
+
+/// Settings found in the configuration file.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct File {
+    /// Network to connect to, eg. `mainnet` or `chipnet`.
+    #[serde(with = "network", skip_serializing_if = "Option::is_none")]
+    pub network: Option<Network>,
+    /// Connect to these peers only.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub connect: Vec<net::SocketAddr>,
+    /// Listen on these addresses for peer connections.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub listen: Vec<net::SocketAddr>,
+    /// Connect to peers via this SOCKS5 proxy.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<net::SocketAddr>,
+    /// AS map file, to spread outbound peers across autonomous systems.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub asmap: Option<PathBuf>,
+    /// Data directory for nakamoto files.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub root: Option<PathBuf>,
+    /// Trust block headers up to the checkpoint at or below this height, instead of
+    /// validating them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub assume_valid: Option<u64>,
+    /// Wallet file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wallet: Option<PathBuf>,
+    /// Log level, eg. `info` or `debug`.
+    #[serde(with = "level", skip_serializing_if = "Option::is_none")]
+    pub log: Option<log::Level>,
+    /// Serve the client over gRPC on this address.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grpc: Option<net::SocketAddr>,
+    /// Scan with compact block filters rather than bloom filters, when peers serve them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compact_filters: Option<bool>,
+    /// Bloom filter settings.
+    pub bloom: Bloom,
+    /// Peer limits.
+    pub limits: Limits,
+}
+
+/// Bloom filter settings.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Bloom {
+    /// Serve filtered blocks to BIP 37 clients.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serve: Option<bool>,
+    /// Number of filter segments to split addresses across.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub segments: Option<usize>,
+    /// Filter false-positive rate, between 0 and 1.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fp_rate: Option<f64>,
+    /// Number of decoy elements inserted into filters per address.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub decoys: Option<f64>,
+}
+
+/// Peer limits.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Limits {
+    /// Target outbound peer connections.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_outbound_peers: Option<usize>,
+    /// Maximum inbound peer connections.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_inbound_peers: Option<usize>,
+    /// Minimum outbound peer connections advertising `NODE_BLOOM`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_bloom_peers: Option<usize>,
+    /// Outbound peer connections to maintain during initial sync.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sync_outbound_peers: Option<usize>,
+    /// Peers asked for the same headers during initial sync.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sync_request_peers: Option<usize>,
+}
+
+impl File {
+    /// Default path of the configuration file.
+    pub fn default_path() -> PathBuf {
+        let legacy = datadir::home()
+            .join(datadir::LEGACY_DIR_NAME)
+            .join(FILE_NAME);
+
+        if legacy.is_file() {
+            return legacy;
+        }
+        datadir::config_home()
+            .join(datadir::APP_NAME)
+            .join(FILE_NAME)
+    }
+
+    /// Load the configuration file at the given path.
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let s = fs::read_to_string(path).map_err(|err| Error::Io {
+            path: path.to_owned(),
+            err,
+        })?;
+
+        toml::from_str(&s).map_err(|err| Error::Parse {
+            path: path.to_owned(),
+            err,
+        })
+    }
+
+    /// Load the configuration file at the given path, or at the default path if none is
+    /// given. A missing file at the default path is treated as an empty one.
+    pub fn load_or_default(path: Option<&Path>) -> Result<Self, Error> {
+        if let Some(path) = path {
+            return Self::load(path);
+        }
+        match Self::load(&Self::default_path()) {
+            Err(Error::Io { err, .. }) if err.kind() == io::ErrorKind::NotFound => {
+                Ok(Self::default())
+            }
+            result => result,
+        }
+    }
+
+    /// Render the configuration as TOML.
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("File::to_toml: configuration is serializable")
+    }
+}
+
+/// (De)serialization of networks by name.
+mod network {
+    use super::*;
+    use serde::{de, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &Option<Network>, s: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(network) => s.serialize_some(network.as_str()),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Network>, D::Error> {
+        let s = String::deserialize(d)?;
+
+        s.parse().map(Some).map_err(de::Error::custom)
+    }
+}
+
+/// (De)serialization of log levels by name.
+mod level {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &Option<log::Level>, s: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(level) => s.serialize_some(&level.as_str().to_lowercase()),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<log::Level>, D::Error> {
+        let s = String::deserialize(d)?;
+
+        s.parse().map(Some).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let file: File = toml::from_str(
+            r#"
+            network = "chipnet"
+            connect = ["127.0.0.1:48333"]
+            log = "debug"
+            compact-filters = true
+            assume-valid = 661648
+
+            [bloom]
+            fp-rate = 0.001
+
+            [limits]
+            max-inbound-peers = 8
+            "#,
+        )
+        .unwrap();
+
+        assert!(matches!(file.network, Some(Network::Chipnet)));
+        assert_eq!(file.connect, vec![([127, 0, 0, 1], 48333).into()]);
+        assert_eq!(file.log, Some(log::Level::Debug));
+        assert_eq!(file.compact_filters, Some(true));
+        assert_eq!(file.assume_valid, Some(661648));
+        assert_eq!(file.bloom.fp_rate, Some(0.001));
+        assert_eq!(file.limits.max_inbound_peers, Some(8));
+        assert_eq!(file.limits.max_outbound_peers, None);
+
+        // The rendered configuration parses back to the same settings.
+        let rendered: File = toml::from_str(&file.to_toml()).unwrap();
+        assert_eq!(rendered.to_toml(), file.to_toml());
+
+        assert!(toml::from_str::<File>("netwrok = \"chipnet\"").is_err());
+        assert!(toml::from_str::<File>("network = \"moonnet\"").is_err());
+    }
+}
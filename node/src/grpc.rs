@@ -0,0 +1,195 @@
+//! gRPC service exposing the client handle, so that non-Rust applications can run the
+//! light-client as a sidecar process. The service is defined in `proto/nakamoto.proto`.
+use std::io;
+use std::net;
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+use tonic::{Request, Response, Status};
+
+use nakamoto_client::chan;
+use nakamoto_client::handle::{self, Handle};
+use nakamoto_common::bitcoin::consensus::encode;
+use nakamoto_common::bitcoin::hashes::hex::ToHex;
+use nakamoto_common::bitcoin::Script;
+use nakamoto_common::block::{BlockHash, Transaction};
+
+/// Types and service definitions generated from the protobuf file.
+#[allow(missing_docs)]
+pub mod proto {
+    tonic::include_proto!("nakamoto");
+}
+
+use proto::node_server::{Node, NodeServer};
+
+/// Time to wait for a requested block to be received from the network.
+pub const GET_BLOCK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A gRPC server error.
+#[derive(Error, Debug)]
+pub enum Error {
+    /// An I/O error.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// A transport error.
+    #[error(transparent)]
+    Transport(#[from] tonic::transport::Error),
+}
+
+/// Serve the gRPC API on the given address. Blocks until the server fails.
+pub fn serve<H: Handle + 'static>(addr: net::SocketAddr, handle: H) -> Result<(), Error> {
+    let runtime = tokio::runtime::Runtime::new()?;
+
+    log::info!(target: "grpc", "Listening for gRPC requests on {}..", addr);
+
+    runtime.block_on(
+        tonic::transport::Server::builder()
+            .add_service(NodeServer::new(Service::new(handle)))
+            .serve(addr),
+    )?;
+
+    Ok(())
+}
+
+/// gRPC service, forwarding requests to a client handle.
+#[derive(Debug, Clone)]
+pub struct Service<H> {
+    handle: H,
+}
+
+impl<H: Handle + 'static> Service<H> {
+    /// Create a new service from a client handle.
+    pub fn new(handle: H) -> Self {
+        Self { handle }
+    }
+
+    /// Run a handle operation on a thread where blocking is allowed.
+    async fn blocking<T, F>(&self, f: F) -> Result<T, Status>
+    where
+        T: Send + 'static,
+        F: FnOnce(H) -> Result<T, Status> + Send + 'static,
+    {
+        let handle = self.handle.clone();
+
+        tokio::task::spawn_blocking(move || f(handle))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?
+    }
+}
+
+#[tonic::async_trait]
+impl<H: Handle + 'static> Node for Service<H> {
+    async fn get_tip(
+        &self,
+        _request: Request<proto::GetTipRequest>,
+    ) -> Result<Response<proto::GetTipReply>, Status> {
+        let (height, header, work) = self.blocking(|h| h.get_tip().map_err(status)).await?;
+
+        Ok(Response::new(proto::GetTipReply {
+            height,
+            hash: header.block_hash().to_string(),
+            header: encode::serialize(&header),
+            chainwork: work.to_be_bytes().to_hex(),
+        }))
+    }
+
+    async fn get_block(
+        &self,
+        request: Request<proto::GetBlockRequest>,
+    ) -> Result<Response<proto::GetBlockReply>, Status> {
+        let hash = request
+            .into_inner()
+            .hash
+            .parse::<BlockHash>()
+            .map_err(|e| Status::invalid_argument(format!("invalid block hash: {}", e)))?;
+
+        let (block, height) = self
+            .blocking(move |h| {
+                // Subscribe before requesting the block, so that we don't miss it.
+                let blocks = h.blocks();
+                let deadline = Instant::now() + GET_BLOCK_TIMEOUT;
+
+                h.get_block(&hash).map_err(status)?;
+
+                loop {
+                    match blocks.recv_deadline(deadline) {
+                        Ok((block, height)) if block.block_hash() == hash => {
+                            return Ok((block, height));
+                        }
+                        Ok(_) => continue,
+                        Err(chan::RecvTimeoutError::Timeout) => {
+                            return Err(status(handle::Error::Timeout));
+                        }
+                        Err(chan::RecvTimeoutError::Disconnected) => {
+                            return Err(status(handle::Error::Disconnected));
+                        }
+                    }
+                }
+            })
+            .await?;
+
+        Ok(Response::new(proto::GetBlockReply {
+            height,
+            block: encode::serialize(&block),
+        }))
+    }
+
+    async fn submit_transaction(
+        &self,
+        request: Request<proto::SubmitTransactionRequest>,
+    ) -> Result<Response<proto::SubmitTransactionReply>, Status> {
+        let tx: Transaction = encode::deserialize(&request.into_inner().transaction)
+            .map_err(|e| Status::invalid_argument(format!("invalid transaction: {}", e)))?;
+        let txid = tx.txid();
+        let peers = self
+            .blocking(move |h| h.submit_transaction(tx).map_err(status))
+            .await?;
+
+        Ok(Response::new(proto::SubmitTransactionReply {
+            txid: txid.to_string(),
+            peers: peers.iter().map(|addr| addr.to_string()).collect(),
+        }))
+    }
+
+    async fn rescan(
+        &self,
+        request: Request<proto::RescanRequest>,
+    ) -> Result<Response<proto::RescanReply>, Status> {
+        let proto::RescanRequest { from, to, scripts } = request.into_inner();
+
+        if to.map_or(false, |to| to < from) {
+            return Err(Status::invalid_argument("invalid rescan range"));
+        }
+        let scripts = scripts.into_iter().map(Script::from);
+
+        self.blocking(move |h| match to {
+            Some(to) => h.rescan(from..=to, scripts).map_err(status),
+            None => h.rescan(from.., scripts).map_err(status),
+        })
+        .await?;
+
+        Ok(Response::new(proto::RescanReply {}))
+    }
+
+    async fn watch(
+        &self,
+        request: Request<proto::WatchRequest>,
+    ) -> Result<Response<proto::WatchReply>, Status> {
+        let scripts = request.into_inner().scripts.into_iter().map(Script::from);
+
+        self.blocking(move |h| h.watch(scripts).map_err(status))
+            .await?;
+
+        Ok(Response::new(proto::WatchReply {}))
+    }
+}
+
+/// Map a handle error to a gRPC status.
+fn status(err: handle::Error) -> Status {
+    match err {
+        handle::Error::Disconnected => Status::unavailable(err.to_string()),
+        handle::Error::Timeout => Status::deadline_exceeded(err.to_string()),
+        handle::Error::Command(_) => Status::failed_precondition(err.to_string()),
+        _ => Status::internal(err.to_string()),
+    }
+}
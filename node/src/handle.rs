@@ -0,0 +1,28 @@
+//! Node handle, used to control a running node from other threads.
+use nakamoto_client::handle::{self, Handle as _};
+use nakamoto_net_poll::Waker;
+
+/// A handle to a node.
+#[derive(Clone)]
+pub struct Handle {
+    client: nakamoto_client::Handle<Waker>,
+}
+
+impl Handle {
+    /// Create a new node handle from a client handle.
+    pub(crate) fn new(client: nakamoto_client::Handle<Waker>) -> Self {
+        Self { client }
+    }
+
+    /// Get the underlying client handle, eg. to query the node or submit transactions.
+    pub fn client(&self) -> nakamoto_client::Handle<Waker> {
+        self.client.clone()
+    }
+
+    /// Shut the node down gracefully: block headers are flushed to disk, the address book
+    /// and privacy segment state are saved, and peer connections are closed, before the
+    /// node's `run` function returns.
+    pub fn shutdown(self) -> Result<(), handle::Error> {
+        self.client.shutdown()
+    }
+}
@@ -3,41 +3,185 @@
 
 use std::net;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
 
-pub use nakamoto_client::{Client, Config, Error, Network};
-pub use nakamoto_client::{Domain, LoadingHandler};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
 
+pub use nakamoto_client::{Asmap, Domain, LoadingHandler};
+pub use nakamoto_client::{Client, Config, Error, Limits, Network, ServiceFlags};
+
+pub mod config;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod handle;
 pub mod logger;
 
+pub use handle::Handle;
+
 /// The network reactor we're going to use.
 type Reactor = nakamoto_net_poll::Reactor<net::TcpStream>;
 
-/// Run the light-client. Takes an initial list of peers to connect to, a list of listen addresses,
-/// the client root and the Bitcoin network to connect to.
+/// Run the light-client until it's shut down, either by a `SIGINT` or `SIGTERM` signal, or
+/// through a [`Handle`]. See [`Node::new`] for the parameters.
+#[allow(clippy::too_many_arguments)]
 pub fn run(
     connect: &[net::SocketAddr],
     listen: &[net::SocketAddr],
     root: Option<PathBuf>,
+    assume_valid: Option<u64>,
     domains: &[Domain],
     network: Network,
+    proxy: Option<net::SocketAddr>,
+    asmap: Option<PathBuf>,
+    grpc: Option<net::SocketAddr>,
+    bloom: bool,
+    limits: Limits,
 ) -> Result<(), Error> {
-    let mut cfg = Config {
+    Node::new(
+        connect,
+        listen,
+        root,
+        assume_valid,
+        domains,
         network,
-        connect: connect.to_vec(),
-        domains: domains.to_vec(),
-        listen: if listen.is_empty() {
-            vec![([0, 0, 0, 0], 0).into()]
-        } else {
-            listen.to_vec()
-        },
-        ..Config::default()
-    };
-    if let Some(path) = root {
-        cfg.root = path;
+        proxy,
+        asmap,
+        grpc,
+        bloom,
+        limits,
+    )?
+    .run()
+}
+
+/// A light-client node, ready to run.
+pub struct Node {
+    client: Client<Reactor>,
+    config: Config,
+}
+
+impl Node {
+    /// Create a new node. Takes an initial list of peers to connect to, a list of listen
+    /// addresses, the client data directory and the Bitcoin network to connect to.
+    ///
+    /// If an assume-valid height is given, block headers up to the checkpoint at or below it
+    /// are trusted instead of validated.
+    ///
+    /// If a proxy address is given, all outbound connections are made through it.
+    ///
+    /// If an AS map file is given, outbound peers are spread across the autonomous systems it
+    /// lists.
+    ///
+    /// If a gRPC address is given, the client handle is served on it. This requires the `grpc`
+    /// feature.
+    ///
+    /// If `bloom` is set, filtered blocks are served to BIP 37 clients connecting to us.
+    ///
+    /// Peer connections are bounded by the given limits, except that when peers to connect to
+    /// are given, the number of outbound peers is the number of peers given.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        connect: &[net::SocketAddr],
+        listen: &[net::SocketAddr],
+        root: Option<PathBuf>,
+        assume_valid: Option<u64>,
+        domains: &[Domain],
+        network: Network,
+        proxy: Option<net::SocketAddr>,
+        asmap: Option<PathBuf>,
+        grpc: Option<net::SocketAddr>,
+        bloom: bool,
+        limits: Limits,
+    ) -> Result<Self, Error> {
+        let mut cfg = Config {
+            network,
+            connect: connect.to_vec(),
+            domains: domains.to_vec(),
+            proxy,
+            listen: if listen.is_empty() {
+                vec![([0, 0, 0, 0], 0).into()]
+            } else {
+                listen.to_vec()
+            },
+            limits,
+            assume_valid,
+            ..Config::default()
+        };
+        if let Some(path) = root {
+            cfg.root = path;
+        }
+        if !connect.is_empty() {
+            cfg.limits.max_outbound_peers = connect.len();
+        }
+        if bloom {
+            cfg.services |= ServiceFlags::BLOOM;
+        }
+        if let Some(path) = asmap {
+            let asmap = Asmap::load(&path).map_err(|err| {
+                Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("error loading AS map {:?}: {}", path, err),
+                ))
+            })?;
+            cfg.asmap = Some(Arc::new(asmap));
+        }
+
+        let client = Client::<Reactor>::new()?;
+
+        if let Some(addr) = grpc {
+            #[cfg(feature = "grpc")]
+            {
+                let handle = client.handle();
+
+                std::thread::spawn(move || {
+                    if let Err(err) = grpc::serve(addr, handle) {
+                        log::error!(target: "grpc", "gRPC server on {} failed: {}", addr, err);
+                    }
+                });
+            }
+            #[cfg(not(feature = "grpc"))]
+            {
+                return Err(Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    format!(
+                        "cannot serve gRPC on {}: built without the `grpc` feature",
+                        addr
+                    ),
+                )));
+            }
+        }
+        Ok(Self {
+            client,
+            config: cfg,
+        })
     }
-    if !connect.is_empty() {
-        cfg.limits.max_outbound_peers = connect.len();
+
+    /// Get a handle to the node, eg. to shut it down from another thread.
+    pub fn handle(&self) -> Handle {
+        Handle::new(self.client.handle())
     }
 
-    Client::<Reactor>::new()?.run(cfg)
+    /// Run the node until it's shut down, either by a `SIGINT` or `SIGTERM` signal, or
+    /// through a [`Handle`]. On shutdown, block headers are flushed to disk, the address book
+    /// and privacy segment state are saved, and peer connections are closed.
+    pub fn run(self) -> Result<(), Error> {
+        let mut signals = Signals::new([SIGINT, SIGTERM])?;
+        let signals_handle = signals.handle();
+        let handle = self.handle();
+
+        thread::spawn(move || {
+            if let Some(signal) = signals.forever().next() {
+                log::info!(target: "node", "Received signal {}, shutting down..", signal);
+
+                if let Err(err) = handle.shutdown() {
+                    log::error!(target: "node", "Failed to shut down: {}", err);
+                }
+            }
+        });
+        let result = self.client.run(self.config);
+        signals_handle.close();
+
+        result
+    }
 }
@@ -4,7 +4,8 @@ use std::path::PathBuf;
 use argh::FromArgs;
 
 use nakamoto_client::Network;
-use nakamoto_node::{logger, Domain};
+use nakamoto_node::config;
+use nakamoto_node::{logger, Domain, Limits};
 
 #[derive(FromArgs)]
 /// A Bitcoin light client.
@@ -17,10 +18,27 @@ pub struct Options {
     #[argh(option)]
     pub listen: Vec<net::SocketAddr>,
 
+    /// connect to peers via this SOCKS5 proxy, eg. Tor on `127.0.0.1:9050`
+    #[argh(option)]
+    pub proxy: Option<net::SocketAddr>,
+
+    /// spread outbound peers across the autonomous systems listed in this file, with
+    /// one `<range> <asn>` entry per line
+    #[argh(option)]
+    pub asmap: Option<PathBuf>,
+
     /// use the bitcoin test network (default: false)
     #[argh(switch)]
     pub testnet: bool,
 
+    /// use the bitcoin cash testnet4 network (default: false)
+    #[argh(switch)]
+    pub testnet4: bool,
+
+    /// use the bitcoin cash scalenet network (default: false)
+    #[argh(switch)]
+    pub scalenet: bool,
+
     /// use the bitcoin chipnet network (default: false)
     #[argh(switch)]
     pub chipnet: bool,
@@ -34,12 +52,36 @@ pub struct Options {
     pub ipv6: bool,
 
     /// log level (default: info)
-    #[argh(option, default = "log::Level::Info")]
-    pub log: log::Level,
+    #[argh(option)]
+    pub log: Option<log::Level>,
 
-    /// root directory for nakamoto files (default: ~)
+    /// data directory for nakamoto files, with one sub-directory per network
+    /// (default: the platform data directory, eg. ~/.local/share/nakamoto-cash)
     #[argh(option)]
     pub root: Option<PathBuf>,
+
+    /// trust block headers up to the checkpoint at or below this height, instead of
+    /// validating them, to speed up the initial sync on low-power devices
+    #[argh(option)]
+    pub assume_valid: Option<u64>,
+
+    /// serve the client over gRPC on this address, eg. `127.0.0.1:50051`
+    /// (requires the `grpc` feature)
+    #[argh(option)]
+    pub grpc: Option<net::SocketAddr>,
+
+    /// serve filtered blocks to BIP 37 clients connecting to us (default: false)
+    #[argh(switch)]
+    pub bloom: bool,
+
+    /// read settings from this configuration file; flags take precedence over it
+    /// (default: config.toml in the platform configuration directory)
+    #[argh(option)]
+    pub config: Option<PathBuf>,
+
+    /// print the effective configuration and exit
+    #[argh(switch)]
+    pub print_config: bool,
 }
 
 impl Options {
@@ -50,16 +92,89 @@ impl Options {
 
 fn main() {
     let opts = Options::from_env();
-
-    logger::init(opts.log).expect("initializing logger for the first time");
+    let file = match config::File::load_or_default(opts.config.as_deref()) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("Fatal: {}", err);
+            std::process::exit(1);
+        }
+    };
 
     let network = if opts.testnet {
         Network::Testnet
+    } else if opts.testnet4 {
+        Network::Testnet4
+    } else if opts.scalenet {
+        Network::Scalenet
     } else if opts.chipnet {
         Network::Chipnet
     } else {
-        Network::Mainnet
+        file.network.unwrap_or(Network::Mainnet)
+    };
+    let connect = if opts.connect.is_empty() {
+        file.connect
+    } else {
+        opts.connect
+    };
+    let listen = if opts.listen.is_empty() {
+        file.listen
+    } else {
+        opts.listen
+    };
+    let level = opts.log.or(file.log).unwrap_or(log::Level::Info);
+    let defaults = Limits::default();
+    let limits = Limits {
+        max_outbound_peers: file
+            .limits
+            .max_outbound_peers
+            .unwrap_or(defaults.max_outbound_peers),
+        max_inbound_peers: file
+            .limits
+            .max_inbound_peers
+            .unwrap_or(defaults.max_inbound_peers),
+        min_bloom_peers: file
+            .limits
+            .min_bloom_peers
+            .unwrap_or(defaults.min_bloom_peers),
+        sync_outbound_peers: file
+            .limits
+            .sync_outbound_peers
+            .unwrap_or(defaults.sync_outbound_peers),
+        sync_request_peers: file
+            .limits
+            .sync_request_peers
+            .unwrap_or(defaults.sync_request_peers),
+        ..defaults
     };
+    let effective = config::File {
+        network: Some(network),
+        connect,
+        listen,
+        proxy: opts.proxy.or(file.proxy),
+        asmap: opts.asmap.or(file.asmap),
+        root: opts.root.or(file.root),
+        assume_valid: opts.assume_valid.or(file.assume_valid),
+        log: Some(level),
+        grpc: opts.grpc.or(file.grpc),
+        bloom: config::Bloom {
+            serve: Some(opts.bloom || file.bloom.serve.unwrap_or(false)),
+            ..config::Bloom::default()
+        },
+        limits: config::Limits {
+            max_outbound_peers: Some(limits.max_outbound_peers),
+            max_inbound_peers: Some(limits.max_inbound_peers),
+            min_bloom_peers: Some(limits.min_bloom_peers),
+            sync_outbound_peers: Some(limits.sync_outbound_peers),
+            sync_request_peers: Some(limits.sync_request_peers),
+        },
+        ..config::File::default()
+    };
+
+    if opts.print_config {
+        print!("{}", effective.to_toml());
+        return;
+    }
+    logger::init(level).expect("initializing logger for the first time");
 
     let domains = if opts.ipv4 && opts.ipv6 {
         vec![Domain::IPV4, Domain::IPV6]
@@ -71,7 +186,19 @@ fn main() {
         vec![Domain::IPV4, Domain::IPV6]
     };
 
-    if let Err(e) = nakamoto_node::run(&opts.connect, &opts.listen, opts.root, &domains, network) {
+    if let Err(e) = nakamoto_node::run(
+        &effective.connect,
+        &effective.listen,
+        effective.root,
+        effective.assume_valid,
+        &domains,
+        network,
+        effective.proxy,
+        effective.asmap,
+        effective.grpc,
+        effective.bloom.serve.unwrap_or(false),
+        limits,
+    ) {
         log::error!(target: "node", "Exiting: {}", e);
         std::process::exit(1);
     }
@@ -0,0 +1,80 @@
+//! Compares decoding network messages from a byte stream by re-attempting a partial decode on
+//! every input, against decoding them in place once they are complete.
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+use nakamoto_common::bitcoin::consensus::serialize;
+use nakamoto_common::bitcoin::network::message::{NetworkMessage, RawNetworkMessage};
+use nakamoto_common::bitcoin::network::message_blockdata::Inventory;
+use nakamoto_common::network::Network;
+use nakamoto_p2p::stream::Decoder;
+use nakamoto_test::block::gen;
+
+/// Size of the chunks the stream is received in, as read from a socket.
+const CHUNK_SIZE: usize = 1024;
+
+/// A stream of small messages, followed by blocks.
+fn stream() -> Vec<u8> {
+    let mut rng = fastrand::Rng::with_seed(1);
+    let network = Network::Mainnet;
+    let blocks = gen::blockchain(network.genesis_block(), 8, &mut rng);
+    let mut msgs = Vec::new();
+
+    for i in 0..256 {
+        msgs.push(NetworkMessage::Ping(i));
+        msgs.push(NetworkMessage::Inv(vec![Inventory::Block(
+            network.genesis_hash(),
+        )]));
+    }
+    for block in blocks {
+        msgs.push(NetworkMessage::Block(block));
+    }
+    msgs.into_iter()
+        .flat_map(|payload| {
+            serialize(&RawNetworkMessage {
+                magic: network.magic(),
+                payload,
+            })
+        })
+        .collect()
+}
+
+fn decode(c: &mut Criterion) {
+    let stream = stream();
+    let mut group = c.benchmark_group("decode");
+
+    group.throughput(Throughput::Bytes(stream.len() as u64));
+    group.bench_function("decode_next", |b| {
+        b.iter_batched_ref(
+            || Decoder::new(CHUNK_SIZE),
+            |decoder| {
+                for chunk in stream.chunks(CHUNK_SIZE) {
+                    decoder.input(chunk);
+
+                    while let Some(msg) = decoder.decode_next::<RawNetworkMessage>().unwrap() {
+                        black_box(msg);
+                    }
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("decode_message", |b| {
+        b.iter_batched_ref(
+            || Decoder::new(CHUNK_SIZE),
+            |decoder| {
+                for chunk in stream.chunks(CHUNK_SIZE) {
+                    decoder.input(chunk);
+
+                    while let Some(msg) = decoder.decode_message().unwrap() {
+                        black_box(msg);
+                    }
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
@@ -8,14 +8,20 @@ pub mod event;
 pub mod fees;
 pub mod filter_cache;
 pub mod output;
+pub mod stats;
 
 // Sub-protocols.
 mod addrmgr;
 mod bfmgr;
 mod cbfmgr;
+mod download;
 mod invmgr;
+mod mempoolmgr;
+mod merklemgr;
 mod peermgr;
 mod pingmgr;
+mod ratelimit;
+mod scanmgr;
 mod syncmgr;
 
 #[cfg(test)]
@@ -24,15 +30,24 @@ mod tests;
 use addrmgr::AddressManager;
 use bfmgr::BloomManager;
 use cbfmgr::FilterManager;
+use fees::FeeRate;
 use invmgr::InventoryManager;
-use nakamoto_common::bitcoin::util::bloom::BloomFilter;
+use mempoolmgr::MempoolManager;
+use merklemgr::MerkleManager;
+use nakamoto_common::bitcoin::util::bloom::{BloomFilter, Decoys};
 use output::Outbox;
 use peermgr::PeerManager;
 use pingmgr::PingManager;
+use ratelimit::{RateLimiter, Verdict};
+use scanmgr::ScanManager;
+use stats::Bandwidth;
 use syncmgr::SyncManager;
 
 pub use event::Event;
+pub use invmgr::BroadcastPolicy;
 pub use nakamoto_net::Link;
+pub use scanmgr::Method as ScanMethod;
+pub use stats::PeerStats;
 
 use std::borrow::Cow;
 use std::collections::HashSet;
@@ -47,19 +62,24 @@ use nakamoto_common::bitcoin::consensus::params::Params;
 use nakamoto_common::bitcoin::network::constants::ServiceFlags;
 use nakamoto_common::bitcoin::network::message::{NetworkMessage, RawNetworkMessage};
 use nakamoto_common::bitcoin::network::message_blockdata::Inventory;
+use nakamoto_common::bitcoin::network::message_bloom::BloomFlags;
 use nakamoto_common::bitcoin::network::message_filter::GetCFilters;
-use nakamoto_common::bitcoin::network::message_network::VersionMessage;
+use nakamoto_common::bitcoin::network::message_network::{RejectReason, VersionMessage};
 use nakamoto_common::bitcoin::network::Address;
 use nakamoto_common::bitcoin::util::uint::Uint256;
 use nakamoto_common::bitcoin::{Script, Txid};
 use nakamoto_common::block::filter::Filters;
+use nakamoto_common::block::snapshot::Snapshot;
 use nakamoto_common::block::time::AdjustedClock;
 use nakamoto_common::block::time::{LocalDuration, LocalTime};
 use nakamoto_common::block::tree::{self, BlockReader, BlockTree, ImportResult};
 use nakamoto_common::block::{BlockHash, Height};
 use nakamoto_common::block::{BlockTime, Transaction};
+use nakamoto_common::bloom::store::cache::{PrivacySegment, DEFAULT_FP_RATE};
+use nakamoto_common::collections::HashMap;
 use nakamoto_common::network;
 use nakamoto_common::nonempty::NonEmpty;
+use nakamoto_common::p2p::netgroup::Asmap;
 use nakamoto_common::p2p::{peer, Domain};
 use nakamoto_net as traits;
 
@@ -84,19 +104,19 @@ pub type PeerId = net::SocketAddr;
 
 /// Source of blocks.
 pub trait BlockSource {
-    /// Get a block by asking peers.
+    /// Get the block at the given height by asking peers.
     /// The block is returned asychronously via a [`Event::BlockProcessed`] event.
-    fn get_block(&mut self, hash: BlockHash);
+    fn get_block(&mut self, height: Height, hash: BlockHash);
 }
 
 impl<C: nakamoto_common::block::time::Clock> BlockSource for InventoryManager<C> {
-    fn get_block(&mut self, hash: BlockHash) {
-        self.get_block(hash)
+    fn get_block(&mut self, height: Height, hash: BlockHash) {
+        self.get_block(height, hash)
     }
 }
 
 impl BlockSource for () {
-    fn get_block(&mut self, _hash: BlockHash) {}
+    fn get_block(&mut self, _height: Height, _hash: BlockHash) {}
 }
 
 /// Disconnect reason.
@@ -114,6 +134,8 @@ pub enum DisconnectReason {
     PeerMagic(u32),
     /// Peer timed out.
     PeerTimeout(&'static str),
+    /// Peer sent us more messages of the given type than allowed.
+    Flooding(&'static str),
     /// Connection to self was detected.
     SelfConnection,
     /// Inbound connection limit reached.
@@ -122,6 +144,8 @@ pub enum DisconnectReason {
     DecodeError(Arc<encode::Error>),
     /// Peer was forced to disconnect by external command.
     Command,
+    /// Peer was banned by external command.
+    PeerBanned,
     /// Peer was disconnected for another reason.
     Other(&'static str),
 }
@@ -152,10 +176,12 @@ impl fmt::Display for DisconnectReason {
             Self::PeerHeight(_) => write!(f, "peer is too far behind"),
             Self::PeerMagic(magic) => write!(f, "received message with invalid magic: {}", magic),
             Self::PeerTimeout(s) => write!(f, "peer timed out: {:?}", s),
+            Self::Flooding(cmd) => write!(f, "peer is flooding us with {:?} messages", cmd),
             Self::SelfConnection => write!(f, "detected self-connection"),
             Self::ConnectionLimit => write!(f, "inbound connection limit reached"),
             Self::DecodeError(err) => write!(f, "message decode error: {}", err),
             Self::Command => write!(f, "received external command"),
+            Self::PeerBanned => write!(f, "peer was banned"),
             Self::Other(reason) => write!(f, "{}", reason),
         }
     }
@@ -182,6 +208,8 @@ pub struct Peer {
     pub relay: bool,
     /// latency
     pub latency: LocalDuration,
+    /// Whether a bloom filter is loaded on this peer.
+    pub filter_loaded: bool,
 }
 
 impl Peer {
@@ -205,6 +233,7 @@ impl From<(&peermgr::PeerInfo, &peermgr::Connection, &pingmgr::Peer)> for Peer {
             user_agent: peer.user_agent.clone(),
             relay: peer.relay,
             latency: ping.latency(),
+            filter_loaded: false,
         }
     }
 }
@@ -221,6 +250,7 @@ impl From<(&peermgr::PeerInfo, &peermgr::Connection)> for Peer {
             user_agent: peer.user_agent.clone(),
             relay: peer.relay,
             latency: LocalDuration::from_secs(0),
+            filter_loaded: false,
         }
     }
 }
@@ -234,6 +264,8 @@ pub enum Command {
     GetBlock(BlockHash),
     /// Get connected peers.
     GetPeers(ServiceFlags, chan::Sender<Vec<Peer>>),
+    /// Get the bandwidth used by connected peers.
+    GetPeerStats(chan::Sender<Vec<PeerStats>>),
     /// Get the tip of the active chain.
     GetTip(chan::Sender<(Height, BlockHeader, Uint256)>),
     /// Get a block from the active chain.
@@ -252,6 +284,18 @@ pub enum Command {
         /// Scripts to match on.
         watch: Vec<Script>,
     },
+    /// Rescan the chain for matching scripts with compact block filters. Replies with an error
+    /// if we aren't connected to any peer serving them, in which case no rescan is started.
+    RescanFilters {
+        /// Start scan from this height. If unbounded, start at the current height.
+        from: Bound<Height>,
+        /// Stop scanning at this height. If unbounded, don't stop scanning.
+        to: Bound<Height>,
+        /// Scripts to match on.
+        watch: Vec<Script>,
+        /// Reply channel.
+        reply: chan::Sender<Result<(), GetFiltersError>>,
+    },
     /// Rescan the chain for matching scripts and addresses.
     MerkleBlockRescan {
         /// Start scan from this height. If unbounded, start at the current height.
@@ -261,11 +305,35 @@ pub enum Command {
         /// peers to load bloom filter.
         peers: Vec<PeerId>,
     },
+    /// Scan the chain for matching scripts, with compact block filters when connected to peers
+    /// serving them, and with merkle blocks otherwise. The method is switched as peers come
+    /// and go, and matches are reported with [`Event::ScanMatched`].
+    Scan {
+        /// Start scan from this height. If unbounded, start at the current height.
+        from: Bound<Height>,
+        /// Stop scanning at this height. If unbounded, don't stop scanning.
+        to: Bound<Height>,
+        /// Scripts to match on, with compact filters. Merkle blocks are matched on the
+        /// bloom filters loaded on peers.
+        watch: Vec<Script>,
+    },
+    /// Abort the compact filter and merkle block rescans in progress, if any.
+    AbortRescan,
+    /// Get the progress of the compact filter and merkle block rescans.
+    GetRescanStatus(chan::Sender<RescanStatus>),
     /// Update the watchlist with the provided scripts.
     Watch {
         /// Scripts to watch.
         watch: Vec<Script>,
     },
+    /// Remove the provided scripts from the watchlist. Scripts watched more than once are
+    /// only removed once they have been unwatched as many times.
+    Unwatch {
+        /// Scripts to unwatch.
+        scripts: Vec<Script>,
+    },
+    /// Get the watched scripts, along with the number of times they were watched.
+    ListWatched(chan::Sender<Vec<(Script, usize)>>),
     /// Broadcast to peers matching the predicate.
     Broadcast(NetworkMessage, fn(Peer) -> bool, chan::Sender<Vec<PeerId>>),
     /// Query the block tree.
@@ -274,6 +342,8 @@ pub enum Command {
     Connect(net::SocketAddr),
     /// Disconnect from a peer.
     Disconnect(net::SocketAddr),
+    /// Disconnect from a peer, and don't connect to it again.
+    Ban(net::SocketAddr),
     /// Import headers directly into the block store.
     ImportHeaders(
         Vec<BlockHeader>,
@@ -281,6 +351,8 @@ pub enum Command {
     ),
     /// Import addresses into the address book.
     ImportAddresses(Vec<Address>),
+    /// Import a trusted snapshot. Replies with the snapshot height.
+    ImportSnapshot(Snapshot, chan::Sender<Result<Height, ImportSnapshotError>>),
     /// Submit a transaction to the network.
     SubmitTransaction(
         Transaction,
@@ -288,6 +360,9 @@ pub enum Command {
     ),
     /// Get a previously submitted transaction.
     GetSubmittedTransaction(Txid, chan::Sender<Option<Transaction>>),
+    /// Estimate the fee rate needed for a transaction to confirm within the given number of
+    /// blocks.
+    EstimateFee(usize, chan::Sender<Option<FeeRate>>),
     /// Load Bloom filters to the .
     LoadBloomFilter((BloomFilter, Vec<PeerId>)),
     /// Get mempool
@@ -296,6 +371,24 @@ pub enum Command {
     GetPeersNotBloomFiltered(chan::Sender<Vec<PeerId>>),
     /// Clear Bloom Filters
     BloomFilterClear,
+    /// Rebuild the bloom filters from the given watch set and reload them onto all filtered
+    /// peers, optionally re-scanning from the given height.
+    UpdateBloomFilter {
+        /// Elements to watch.
+        watch: Vec<Vec<u8>>,
+        /// How peers should update the filters with the outpoints of matching outputs.
+        flags: BloomFlags,
+        /// Height to re-scan merkle blocks from.
+        rescan_from: Option<Height>,
+    },
+    /// Add elements to the bloom filters loaded on peers, optionally re-scanning from the
+    /// given height.
+    AddToBloomFilter {
+        /// Elements to add.
+        elements: Vec<Vec<u8>>,
+        /// Height to re-scan merkle blocks from.
+        rescan_from: Option<Height>,
+    },
 }
 
 impl fmt::Debug for Command {
@@ -306,30 +399,67 @@ impl fmt::Debug for Command {
             Self::GetBlockByHeight(height, _) => write!(f, "GetBlockByHeight({})", height),
             Self::GetBlock(hash) => write!(f, "GetBlock({})", hash),
             Self::GetPeers(flags, _) => write!(f, "GetPeers({})", flags),
+            Self::GetPeerStats(_) => write!(f, "GetPeerStats"),
             Self::GetTip(_) => write!(f, "GetTip"),
             Self::RequestBlock(hash) => write!(f, "GetBlock({})", hash),
             Self::RequestFilters(range, _) => write!(f, "GetFilters({:?})", range),
             Self::Rescan { from, to, watch } => {
                 write!(f, "Rescan({:?}, {:?}, {:?})", from, to, watch)
             }
+            Self::RescanFilters {
+                from, to, watch, ..
+            } => {
+                write!(f, "RescanFilters({:?}, {:?}, {:?})", from, to, watch)
+            }
             Self::MerkleBlockRescan { from, to, peers } => {
                 write!(f, "MerkleBlockRescan ({:?}, {:?}, {:?})", from, to, peers)
             }
+            Self::Scan { from, to, watch } => {
+                write!(f, "Scan({:?}, {:?}, {:?})", from, to, watch)
+            }
+            Self::AbortRescan => write!(f, "AbortRescan"),
+            Self::GetRescanStatus(_) => write!(f, "GetRescanStatus"),
             Self::Watch { watch } => {
                 write!(f, "Watch({:?})", watch)
             }
+            Self::Unwatch { scripts } => {
+                write!(f, "Unwatch({:?})", scripts)
+            }
+            Self::ListWatched(_) => write!(f, "ListWatched"),
             Self::Broadcast(msg, _, _) => write!(f, "Broadcast({})", msg.cmd()),
             Self::QueryTree(_) => write!(f, "QueryTree"),
             Self::Connect(addr) => write!(f, "Connect({})", addr),
             Self::Disconnect(addr) => write!(f, "Disconnect({})", addr),
+            Self::Ban(addr) => write!(f, "Ban({})", addr),
             Self::ImportHeaders(_headers, _) => write!(f, "ImportHeaders(..)"),
             Self::ImportAddresses(addrs) => write!(f, "ImportAddresses({:?})", addrs),
+            Self::ImportSnapshot(snapshot, _) => write!(f, "ImportSnapshot({})", snapshot.height),
             Self::SubmitTransaction(tx, _) => write!(f, "SubmitTransaction({:?})", tx),
             Self::GetSubmittedTransaction(txid, _) => write!(f, "GetSubmittedTransaction({txid})"),
+            Self::EstimateFee(blocks, _) => write!(f, "EstimateFee({})", blocks),
             Self::GetPeersNotBloomFiltered(_) => write!(f, "GetPeersNotBloomFilterd"),
             Self::LoadBloomFilter(_) => {
                 write!(f, "LoadBloomFilter Request" /* filter */,)
             }
+            Self::UpdateBloomFilter {
+                watch,
+                flags,
+                rescan_from,
+            } => {
+                write!(
+                    f,
+                    "UpdateBloomFilter({}, {:?}, {:?})",
+                    watch.len(),
+                    flags,
+                    rescan_from
+                )
+            }
+            Self::AddToBloomFilter {
+                elements,
+                rescan_from,
+            } => {
+                write!(f, "AddToBloomFilter({}, {:?})", elements.len(), rescan_from)
+            }
         }
     }
 }
@@ -340,6 +470,48 @@ pub enum CommandError {
     /// Not connected to any peer with the required services.
     #[error("not connected to any peer with the required services")]
     NotConnected,
+    /// The transaction was rejected by peers, and wasn't relayed by any peer.
+    #[error("transaction was rejected by peers: {reason} ({code:?})")]
+    Rejected {
+        /// Rejection code.
+        code: RejectReason,
+        /// Rejection reason given by the peers.
+        reason: String,
+    },
+}
+
+/// An error resulting from importing a [`Snapshot`].
+#[derive(Error, Debug)]
+pub enum ImportSnapshotError {
+    /// The snapshot has no headers.
+    #[error("the snapshot is empty")]
+    Empty,
+    /// The snapshot headers could not be imported.
+    #[error("error importing snapshot headers: {0}")]
+    Headers(#[from] tree::Error),
+    /// The snapshot block is not on the active chain at the snapshot height.
+    #[error("snapshot block {0} is not on the active chain at height {1}")]
+    NotActive(BlockHash, Height),
+}
+
+/// Progress of a rescan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RescanProgress {
+    /// Start height of the rescan.
+    pub start: Height,
+    /// Next height to be scanned.
+    pub current: Height,
+    /// End height of the rescan. If `None`, new blocks are scanned until it is aborted.
+    pub end: Option<Height>,
+}
+
+/// Status of the compact filter and merkle block rescans.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RescanStatus {
+    /// Compact filter rescan progress, if one is in progress.
+    pub filters: Option<RescanProgress>,
+    /// Merkle block rescan progress, if one is in progress.
+    pub merkle_blocks: Option<RescanProgress>,
 }
 
 pub use cbfmgr::GetFiltersError;
@@ -397,10 +569,20 @@ pub struct StateMachine<T, F, P, C> {
     cbfmgr: FilterManager<F, C>,
     /// BFM (Bloom Filter) manager.
     bfmgr: BloomManager<C>,
+    /// Merkle block manager, serving filtered blocks to BIP 37 clients.
+    merklemgr: MerkleManager<C>,
+    /// Hybrid scan manager, choosing between compact filters and merkle blocks.
+    scanmgr: ScanManager,
     /// Peer manager.
     peermgr: PeerManager<C>,
     /// Inventory manager.
     invmgr: InventoryManager<C>,
+    /// Mempool manager.
+    mempoolmgr: MempoolManager<C>,
+    /// Bandwidth used by peers.
+    bandwidth: Bandwidth,
+    /// Inbound message rate limits.
+    ratelimit: RateLimiter,
     /// Network-adjusted clock.
     clock: C,
     /// Last time a "tick" was triggered.
@@ -417,18 +599,28 @@ pub struct StateMachine<T, F, P, C> {
 pub struct Limits {
     /// Target outbound peer connections.
     pub max_outbound_peers: usize,
+    /// Target outbound peer connections during initial block header sync. Once synced, we
+    /// scale back down to `max_outbound_peers`.
+    pub sync_outbound_peers: usize,
     /// Maximum inbound peer connections.
     pub max_inbound_peers: usize,
+    /// Minimum outbound peer connections advertising `NODE_BLOOM`.
+    pub min_bloom_peers: usize,
     /// Size in bytes of the compact filter cache.
     pub filter_cache_size: usize,
+    /// Number of peers asked for the same headers while syncing.
+    pub sync_request_peers: usize,
 }
 
 impl Default for Limits {
     fn default() -> Self {
         Self {
             max_outbound_peers: peermgr::TARGET_OUTBOUND_PEERS,
+            sync_outbound_peers: peermgr::SYNC_OUTBOUND_PEERS,
             max_inbound_peers: peermgr::MAX_INBOUND_PEERS,
+            min_bloom_peers: peermgr::MIN_BLOOM_PEERS,
             filter_cache_size: cbfmgr::DEFAULT_FILTER_CACHE_SIZE,
+            sync_request_peers: syncmgr::SYNC_REQUEST_PEERS,
         }
     }
 }
@@ -442,10 +634,15 @@ pub struct Config {
     pub connect: Vec<net::SocketAddr>,
     /// Supported communication domains.
     pub domains: Vec<Domain>,
+    /// Mapping of IP ranges to autonomous systems, used to diversify outbound peers.
+    pub asmap: Option<Arc<Asmap>>,
     /// Services offered by our peer.
     pub services: ServiceFlags,
     /// Required peer services.
     pub required_services: ServiceFlags,
+    /// Preferred peer services. Outbound peers without them are only kept until enough peers
+    /// with them are found.
+    pub preferred_services: ServiceFlags,
     /// Peer whitelist. Peers in this list are trusted by default.
     pub whitelist: Whitelist,
     /// Consensus parameters.
@@ -453,13 +650,26 @@ pub struct Config {
     /// Our protocol version.
     pub protocol_version: u32,
     /// Our user agent.
-    pub user_agent: &'static str,
+    pub user_agent: String,
+    /// Whether to report our best height to peers. Reporting height zero makes us harder
+    /// to tell apart from other nodes.
+    pub report_height: bool,
+    /// Whether we want peers to announce transactions to us, before we load a bloom filter.
+    pub relay: bool,
     /// Ping timeout, after which remotes are disconnected.
     pub ping_timeout: LocalDuration,
     /// State machine event hooks.
     pub hooks: Hooks,
     /// Configured limits.
     pub limits: Limits,
+    /// Bloom filter privacy segments to load onto peers, keyed by segment id.
+    pub bloom_segments: HashMap<u32, PrivacySegment>,
+    /// False-positive rate of bloom filters rebuilt from the watch set.
+    pub bloom_fp_rate: f64,
+    /// Decoys inserted into bloom filters rebuilt from the watch set.
+    pub bloom_decoys: Decoys,
+    /// How submitted transactions are broadcast.
+    pub broadcast: BroadcastPolicy,
 }
 
 impl Default for Config {
@@ -469,14 +679,22 @@ impl Default for Config {
             params: Params::new(network::Network::default().into()),
             connect: Vec::new(),
             domains: Domain::all(),
+            asmap: None,
             services: ServiceFlags::NONE,
             required_services: ServiceFlags::NETWORK,
+            preferred_services: syncmgr::REQUIRED_SERVICES | bfmgr::REQUIRED_SERVICES,
             whitelist: Whitelist::default(),
             protocol_version: PROTOCOL_VERSION,
             ping_timeout: pingmgr::PING_TIMEOUT,
-            user_agent: USER_AGENT,
+            user_agent: USER_AGENT.to_owned(),
+            report_height: true,
+            relay: false,
             hooks: Hooks::default(),
             limits: Limits::default(),
+            bloom_segments: HashMap::default(),
+            bloom_fp_rate: DEFAULT_FP_RATE,
+            bloom_decoys: Decoys::default(),
+            broadcast: BroadcastPolicy::default(),
         }
     }
 }
@@ -529,15 +747,23 @@ impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> StateMa
             network,
             connect,
             domains,
+            asmap,
             services,
             whitelist,
             protocol_version,
             ping_timeout,
             user_agent,
+            report_height,
+            relay,
             required_services,
+            preferred_services,
             params,
             hooks,
             limits,
+            bloom_segments,
+            bloom_fp_rate,
+            bloom_decoys,
+            broadcast,
         } = config;
 
         let outbox = Outbox::new(protocol_version);
@@ -545,6 +771,7 @@ impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> StateMa
             syncmgr::Config {
                 max_message_headers: syncmgr::MAX_MESSAGE_HEADERS,
                 request_timeout: syncmgr::REQUEST_TIMEOUT,
+                request_peers: limits.sync_request_peers,
                 params,
             },
             rng.clone(),
@@ -560,25 +787,38 @@ impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> StateMa
             filters,
             clock.clone(),
         );
+        // Outbound peers from before the last shutdown, to reconnect to first.
+        let anchors = peers.anchors();
         let peermgr = PeerManager::new(
             peermgr::Config {
                 protocol_version: PROTOCOL_VERSION,
                 whitelist,
                 persistent: connect,
+                anchors,
                 domains: domains.clone(),
+                asmap,
                 target_outbound_peers: limits.max_outbound_peers,
+                sync_outbound_peers: limits.sync_outbound_peers,
                 max_inbound_peers: limits.max_inbound_peers,
+                min_bloom_peers: limits.min_bloom_peers,
                 retry_max_wait: LocalDuration::from_mins(60),
                 retry_min_wait: LocalDuration::from_secs(1),
                 required_services,
-                preferred_services: syncmgr::REQUIRED_SERVICES | bfmgr::REQUIRED_SERVICES,
+                preferred_services,
                 services,
                 user_agent,
+                report_height,
+                relay,
             },
             rng.clone(),
             hooks.clone(),
             clock.clone(),
         );
+        // Privacy segments previously shown to peer groups.
+        let groups = peers
+            .iter()
+            .filter_map(|(ip, ka)| ka.segment.map(|s| (addrmgr::addr_key(ip), s)))
+            .collect();
         let addrmgr = AddressManager::new(
             addrmgr::Config {
                 required_services,
@@ -588,9 +828,31 @@ impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> StateMa
             peers,
             clock.clone(),
         );
-        let invmgr = InventoryManager::new(rng.clone(), clock.clone());
+        let invmgr = InventoryManager::new(rng.clone(), clock.clone()).with_policy(broadcast);
+        let mempoolmgr = MempoolManager::new(rng.clone(), clock.clone());
+        let bandwidth = Bandwidth::new(rng.clone());
+        let ratelimit = RateLimiter::new(rng.clone());
+        let merklemgr = MerkleManager::new(
+            merklemgr::Config {
+                enabled: services.has(ServiceFlags::BLOOM),
+                ..merklemgr::Config::default()
+            },
+            rng.clone(),
+            clock.clone(),
+        );
 
-        let bfmgr = BloomManager::new(rng, clock.clone());
+        let bfmgr = BloomManager::new(
+            bfmgr::Config {
+                segments: bloom_segments,
+                groups,
+                fp_rate: bloom_fp_rate,
+                decoys: bloom_decoys,
+                ..bfmgr::Config::default()
+            },
+            rng,
+            clock.clone(),
+        );
+        let scanmgr = ScanManager::new();
 
         Self {
             tree,
@@ -601,8 +863,13 @@ impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> StateMa
             pingmgr,
             cbfmgr,
             bfmgr,
+            merklemgr,
+            scanmgr,
             peermgr,
             invmgr,
+            mempoolmgr,
+            bandwidth,
+            ratelimit,
             last_tick: LocalTime::default(),
             outbox,
             hooks,
@@ -634,6 +901,34 @@ impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> StateMa
         }
         peers
     }
+
+    /// Import a trusted snapshot. Its headers are imported into the block tree, and its
+    /// block must end up on the active chain at the snapshot height.
+    fn import_snapshot(&mut self, snapshot: Snapshot) -> Result<Height, ImportSnapshotError> {
+        let Some(hash) = snapshot.hash() else {
+            return Err(ImportSnapshotError::Empty);
+        };
+        let height = snapshot.height;
+
+        self.syncmgr
+            .import_block_headers(snapshot.headers.into_iter(), &mut self.tree)?;
+
+        let active = self
+            .tree
+            .get_block_by_height(height)
+            .map(|h| h.block_hash());
+
+        if active != Some(hash) {
+            return Err(ImportSnapshotError::NotActive(hash, height));
+        }
+        self.outbox.event(Event::SnapshotImported {
+            height,
+            hash,
+            utxos: snapshot.utxos.len(),
+        });
+
+        Ok(height)
+    }
 }
 
 impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> Iterator
@@ -648,10 +943,13 @@ impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> Iterato
             .or_else(|| self.peermgr.next())
             .or_else(|| self.syncmgr.next())
             .or_else(|| self.invmgr.next())
+            .or_else(|| self.mempoolmgr.next())
             .or_else(|| self.pingmgr.next())
             .or_else(|| self.addrmgr.next())
             .or_else(|| self.bfmgr.next())
+            .or_else(|| self.merklemgr.next())
             .or_else(|| self.cbfmgr.next())
+            .or_else(|| self.scanmgr.next())
             .map(|io| match io {
                 output::Io::Write(addr, payload) => Io::Write(
                     addr,
@@ -672,6 +970,11 @@ impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> Iterato
 
                 Some(Io::Event(e))
             }
+            Some(Io::Write(addr, msg)) => {
+                self.bandwidth.sent(&addr, &msg);
+
+                Some(Io::Write(addr, msg))
+            }
             other => other,
         }
     }
@@ -680,14 +983,62 @@ impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> Iterato
 impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> StateMachine<T, F, P, C> {
     /// Propagate an event internally to the sub-systems.
     pub fn event(&mut self, e: Event) {
+        let peers_changed = matches!(
+            e,
+            Event::PeerNegotiated { .. }
+                | Event::PeerDisconnected { .. }
+                | Event::PeerLoadedBloomFilter { .. }
+        );
         self.cbfmgr
             .received_event(e.clone(), &self.tree, &mut self.invmgr);
         self.pingmgr.received_event(e.clone(), &self.tree);
         self.invmgr.received_event(e.clone(), &self.tree);
+        self.mempoolmgr.received_event(e.clone());
         self.syncmgr.received_event(e.clone(), &mut self.tree);
         self.addrmgr.received_event(e.clone());
         self.bfmgr.received_event(e.clone(), &mut self.tree);
+        self.merklemgr
+            .received_event(e.clone(), &self.tree, &mut self.invmgr);
+        self.scanmgr.received_event(e.clone());
         self.peermgr.received_event(e, &self.tree);
+
+        if peers_changed {
+            self.scan();
+        }
+    }
+
+    /// Carry on the hybrid scan in progress, switching methods if needed.
+    fn scan(&mut self) {
+        let reached = match self.scanmgr.method() {
+            Some(ScanMethod::Filters) => self.cbfmgr.rescan.current,
+            Some(ScanMethod::MerkleBlocks) => self.bfmgr.rescan.current,
+            None => 0,
+        };
+        let filters = self.cbfmgr.is_connected();
+        let bloom = self.bfmgr.is_connected();
+
+        let Some(switch) = self.scanmgr.select(filters, bloom, reached) else {
+            return;
+        };
+        let from = Bound::Included(switch.from);
+        let to = switch.to.map_or(Bound::Unbounded, Bound::Included);
+
+        info!(target: "p2p", "Scanning from height {} with {}", switch.from, switch.method);
+
+        match switch.method {
+            ScanMethod::Filters => {
+                self.bfmgr.abort_rescan(&self.tree);
+
+                let watch = self.scanmgr.watch().to_vec();
+                for (height, hash) in self.cbfmgr.rescan(from, to, watch, &self.tree) {
+                    self.invmgr.get_block(height, hash);
+                }
+            }
+            ScanMethod::MerkleBlocks => {
+                self.cbfmgr.abort_rescan();
+                self.bfmgr.merkle_scan(from, to, vec![], &self.tree);
+            }
+        }
     }
 
     /// Process a user command.
@@ -698,8 +1049,12 @@ impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> StateMa
             Command::QueryTree(query) => {
                 query(&self.tree);
             }
-            Command::GetBlock(hash) => {
-                self.invmgr.get_block(hash);
+            Command::GetBlock(hash) | Command::RequestBlock(hash) => {
+                if let Some((height, _)) = self.tree.get_block(&hash) {
+                    self.invmgr.get_block(height, hash);
+                } else {
+                    debug!(target: "p2p", "Ignoring request for unknown block {}", hash);
+                }
             }
             Command::GetBlockByHeight(height, reply) => {
                 let header = self.tree.get_block_by_height(height).copied();
@@ -727,12 +1082,18 @@ impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> StateMa
                         {
                             let mut p = peer.clone();
                             p.latency = *latency;
+                            p.filter_loaded = self.bfmgr.has_filter(&p.addr);
                             peers.push(p.clone());
                         }
                     });
 
                 reply.send(peers).ok();
             }
+            Command::GetPeerStats(reply) => {
+                let stats = self.bandwidth.stats(self.clock.local_time());
+
+                reply.send(stats).ok();
+            }
             Command::Connect(addr) => {
                 self.peermgr.whitelist(addr);
                 self.peermgr.connect(&addr);
@@ -740,6 +1101,9 @@ impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> StateMa
             Command::Disconnect(addr) => {
                 self.peermgr.disconnect(addr, DisconnectReason::Command);
             }
+            Command::Ban(addr) => {
+                self.peermgr.ban(addr);
+            }
             Command::Broadcast(msg, predicate, reply) => {
                 let peers = self.broadcast(msg, |p| predicate(p.clone()));
                 reply.send(peers).ok();
@@ -758,6 +1122,9 @@ impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> StateMa
                     }
                 }
             }
+            Command::ImportSnapshot(snapshot, reply) => {
+                reply.send(self.import_snapshot(snapshot)).ok();
+            }
             Command::ImportAddresses(addrs) => {
                 self.addrmgr.insert(
                     // Nb. For imported addresses, the time last active is not relevant.
@@ -776,9 +1143,6 @@ impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> StateMa
                 let result = self.cbfmgr.get_cfilters(range, &self.tree);
                 reply.send(result).ok();
             }
-            Command::RequestBlock(hash) => {
-                self.invmgr.get_block(hash);
-            }
             Command::SubmitTransaction(tx, reply) => {
                 // Update local watchlist to track submitted transactions.
                 //
@@ -789,6 +1153,13 @@ impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> StateMa
                 // NOT USING CBF for now
                 // self.cbfmgr.watch_transaction(&tx);
 
+                // Transactions that were rejected are not announced again.
+                if let Some((code, reason)) = self.invmgr.rejection(&tx.txid()) {
+                    reply
+                        .send(Err(CommandError::Rejected { code, reason }))
+                        .ok();
+                    return;
+                }
                 let peers = self.invmgr.announce(tx.clone());
                 if let Some(peers) = NonEmpty::from_vec(peers) {
                     // self.outbox.message(*peers.first(), NetworkMessage::Tx(tx));
@@ -800,20 +1171,71 @@ impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> StateMa
             }
             Command::Rescan { from, to, watch } => {
                 // A rescan with a new watch list may return matches on cached filters.
-                for (_, hash) in self.cbfmgr.rescan(from, to, watch, &self.tree) {
-                    self.invmgr.get_block(hash);
+                for (height, hash) in self.cbfmgr.rescan(from, to, watch, &self.tree) {
+                    self.invmgr.get_block(height, hash);
                 }
             }
+            Command::RescanFilters {
+                from,
+                to,
+                watch,
+                reply,
+            } => {
+                if !self.cbfmgr.is_connected() {
+                    reply.send(Err(GetFiltersError::NotConnected)).ok();
+                    return;
+                }
+                for (height, hash) in self.cbfmgr.rescan(from, to, watch, &self.tree) {
+                    self.invmgr.get_block(height, hash);
+                }
+                reply.send(Ok(())).ok();
+            }
             Command::MerkleBlockRescan { from, to, peers } => {
                 self.bfmgr.merkle_scan(from, to, peers, &self.tree);
             }
+            Command::Scan { from, to, watch } => {
+                let start = match from {
+                    Bound::Unbounded => self.tree.height() + 1,
+                    Bound::Included(h) => h,
+                    Bound::Excluded(h) => h + 1,
+                };
+                let end = match to {
+                    Bound::Unbounded => None,
+                    Bound::Included(h) => Some(h),
+                    Bound::Excluded(h) => Some(h - 1),
+                };
+                self.scanmgr.start(start, end, watch);
+                self.scan();
+            }
+            Command::AbortRescan => {
+                self.scanmgr.abort();
+                self.cbfmgr.abort_rescan();
+                self.bfmgr.abort_rescan(&self.tree);
+            }
+            Command::GetRescanStatus(reply) => {
+                reply
+                    .send(RescanStatus {
+                        filters: self.cbfmgr.rescan.progress(),
+                        merkle_blocks: self.bfmgr.rescan.progress(),
+                    })
+                    .ok();
+            }
             Command::Watch { watch } => {
                 self.cbfmgr.watch(watch);
             }
+            Command::Unwatch { scripts } => {
+                self.cbfmgr.unwatch(scripts);
+            }
+            Command::ListWatched(reply) => {
+                reply.send(self.cbfmgr.watched()).ok();
+            }
             Command::GetSubmittedTransaction(ref txid, reply) => {
                 let tx = self.invmgr.get_submitted_tx(txid);
                 reply.send(tx).ok();
             }
+            Command::EstimateFee(blocks, reply) => {
+                reply.send(self.invmgr.estimate_fee(blocks)).ok();
+            }
             Command::LoadBloomFilter((filter, peers)) => {
                 self.bfmgr.send_bloom_filter_all_connected(filter, peers);
                 // _ => self.bfmgr.send_bloom_filter_single_peer(filter, peers[0]),
@@ -829,6 +1251,19 @@ impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> StateMa
                  self.bfmgr.by_ref().send_bloom_filter_clear();
 
             }
+            Command::UpdateBloomFilter {
+                watch,
+                flags,
+                rescan_from,
+            } => {
+                self.bfmgr.update_filter(watch, flags, rescan_from, &self.tree);
+            }
+            Command::AddToBloomFilter {
+                elements,
+                rescan_from,
+            } => {
+                self.bfmgr.add_to_filter(elements, rescan_from, &self.tree);
+            }
         }
     }
 }
@@ -873,6 +1308,24 @@ impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> traits:
 
         // debug!(target: "p2p", "Received {:?} from {}", cmd, addr);
 
+        let size = self.bandwidth.received(&addr, &msg);
+
+        match self
+            .ratelimit
+            .received(&addr, cmd, size, self.clock.local_time())
+        {
+            Verdict::Accept => {}
+            Verdict::Drop => {
+                debug!(target: "p2p", "Dropping {:?} from {}: rate limit exceeded", cmd, addr);
+                return;
+            }
+            Verdict::Flooding => {
+                return self
+                    .peermgr
+                    .disconnect(addr, DisconnectReason::Flooding(cmd));
+            }
+        }
+
         if let Err(err) = (self.hooks.on_message)(addr, &msg.payload, &self.outbox) {
             debug!(
                 target: "p2p",
@@ -895,6 +1348,7 @@ impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> traits:
     }
 
     fn connected(&mut self, addr: net::SocketAddr, local_addr: &net::SocketAddr, link: Link) {
+        self.bandwidth.connected(addr, self.clock.local_time());
         self.peermgr
             .peer_connected(addr, *local_addr, link, self.tree.height());
     }
@@ -904,6 +1358,8 @@ impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> traits:
         addr: &net::SocketAddr,
         reason: nakamoto_net::Disconnect<DisconnectReason>,
     ) {
+        self.bandwidth.disconnected(addr);
+        self.ratelimit.disconnected(addr);
         self.peermgr
             .peer_disconnected(addr, &mut self.addrmgr, reason);
     }
@@ -918,6 +1374,7 @@ impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> traits:
         trace!("Received wake");
 
         self.invmgr.timer_expired(&self.tree);
+        self.mempoolmgr.timer_expired();
         self.syncmgr.timer_expired(&self.tree);
         self.pingmgr.timer_expired();
         self.addrmgr.timer_expired();
@@ -943,7 +1400,7 @@ impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> traits:
             let outbound = self.peermgr.negotiated(Link::Outbound).count();
             let inbound = self.peermgr.negotiated(Link::Inbound).count();
             let connecting = self.peermgr.connecting().count();
-            let target = self.peermgr.config.target_outbound_peers;
+            let target = self.peermgr.target();
             let max_inbound = self.peermgr.config.max_inbound_peers;
             let addresses = self.addrmgr.len();
             let preferred = self
@@ -951,6 +1408,11 @@ impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> traits:
                 .negotiated(Link::Outbound)
                 .filter(|(p, _)| p.services.has(self.peermgr.config.preferred_services))
                 .count();
+            let bloom = self
+                .peermgr
+                .negotiated(Link::Outbound)
+                .filter(|(p, _)| p.services.has(ServiceFlags::BLOOM))
+                .count();
 
             // TODO: Add cache sizes on disk
             // TODO: Add protocol state(s)
@@ -973,6 +1435,12 @@ impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> traits:
             ));
             msg.push(format!("connecting = {}/{}", connecting, target));
             msg.push(format!("addresses = {}", addresses));
+            msg.push(format!(
+                "bloom = {}/{} ({} known)",
+                bloom,
+                self.peermgr.config.min_bloom_peers,
+                self.addrmgr.bloom_addresses()
+            ));
 
             log::info!(target: "p2p", "{}", msg.join(", "));
 
@@ -982,13 +1450,24 @@ impl<T: BlockTree, F: Filters, P: peer::Store, C: AdjustedClock<PeerId>> traits:
             }
             log::info!(
                 target: "p2p",
-                "inventory block queue = {}, requested = {}, mempool = {}",
-                self.invmgr.received.len(),
-                self.invmgr.remaining.len(),
+                "inventory block queue = {}, requested = {}, mempool = {}, unconfirmed = {}",
+                self.invmgr.downloads.waiting(),
+                self.invmgr.downloads.requested(),
                 self.invmgr.mempool.len(),
+                self.mempoolmgr.len(),
             );
 
             self.last_tick = local_time;
         }
     }
+
+    fn shutdown(&mut self) {
+        if let Err(err) = self.tree.flush() {
+            error!(target: "p2p", "Failed to flush block headers: {}", err);
+        }
+        if let Err(err) = self.addrmgr.flush() {
+            error!(target: "p2p", "Failed to save peer addresses: {}", err);
+        }
+        self.bfmgr.checkpoint();
+    }
 }
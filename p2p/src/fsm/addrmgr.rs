@@ -4,7 +4,7 @@
 #![warn(missing_docs)]
 use std::net;
 
-use nakamoto_common::bitcoin::network::address::Address;
+use nakamoto_common::bitcoin::network::address::{AddrV2, AddrV2Message, Address};
 use nakamoto_common::bitcoin::network::constants::ServiceFlags;
 use nakamoto_common::bitcoin::network::message::NetworkMessage;
 use nakamoto_common::block::time::Clock;
@@ -13,9 +13,10 @@ use nakamoto_common::block::BlockTime;
 use nakamoto_common::collections::{HashMap, HashSet};
 use nakamoto_common::p2p::peer::{AddressSource, KnownAddress, Source, Store};
 use nakamoto_common::p2p::Domain;
-use nakamoto_net::Disconnect;
+use nakamoto_net::{onion, Disconnect};
 
 use super::output::{Io, Outbox};
+use super::peermgr::MAX_ANCHORS;
 use super::{Event, Link};
 
 /// Time to wait until a request times out.
@@ -97,6 +98,8 @@ pub struct AddressManager<P, C> {
     connected: HashSet<net::IpAddr>,
     sources: HashSet<net::SocketAddr>,
     local_addrs: HashSet<net::SocketAddr>,
+    /// Outbound peers we'd like to reconnect to first after a restart, oldest first.
+    anchors: Vec<net::SocketAddr>,
     /// The last time we asked our peers for new addresses.
     last_request: Option<LocalTime>,
     /// The last time we idled.
@@ -132,12 +135,23 @@ impl<P: Store, C: Clock> AddressManager<P, C> {
                 link,
                 services,
                 receiver,
+                persistent,
                 ..
             } => {
                 if let Ok(addr) = receiver.socket_addr() {
                     self.local_addrs.insert(addr);
                 }
                 self.peer_negotiated(&addr, services, link);
+
+                // Outbound peers that relay blocks to us are remembered as anchors, so that we
+                // can reconnect to them first after a restart.
+                if link.is_outbound()
+                    && !persistent
+                    && services.has(ServiceFlags::NETWORK)
+                    && self.connected.contains(&addr.ip())
+                {
+                    self.anchor(addr);
+                }
             }
             Event::PeerConnecting { addr, .. } => {
                 self.peer_attempted(&addr);
@@ -145,6 +159,16 @@ impl<P: Store, C: Clock> AddressManager<P, C> {
             Event::PeerDisconnected { addr, reason } => {
                 self.peer_disconnected(&addr, reason);
             }
+            Event::PeerLoadedBloomFilter {
+                peer,
+                segment: Some(segment),
+                ..
+            } => {
+                // Remember which segment this peer saw, to keep showing it the same one.
+                if let Some(ka) = self.peers.get_mut(&peer.ip()) {
+                    ka.segment = Some(segment);
+                }
+            }
             Event::MessageReceived { from, message } => {
                 if let Some(ka) = self.peers.get_mut(&from.ip()) {
                     ka.last_active = Some(self.clock.local_time());
@@ -155,6 +179,9 @@ impl<P: Store, C: Clock> AddressManager<P, C> {
                         // TODO: Tick the peer manager, because we may have new addresses to connect to.
                         // TODO: Can do this via `Event::AddressesImported`.
                     }
+                    NetworkMessage::AddrV2(addrs) => {
+                        self.received_addr_v2(from, addrs.clone());
+                    }
                     NetworkMessage::GetAddr => {
                         self.received_getaddr(&from);
                     }
@@ -199,6 +226,12 @@ impl<P: Store, C: Clock> AddressManager<P, C> {
             let ip = range.iter().nth(ix).expect("index must be present");
             let ka = self.peers.get(ip).expect("address must exist");
 
+            // Onion service placeholders are meaningless to other peers.
+            if let net::IpAddr::V6(ip) = ip {
+                if onion::is_onion(ip) {
+                    continue;
+                }
+            }
             addrs.push((
                 ka.last_active.map(|t| t.block_time()).unwrap_or_default(),
                 ka.addr.clone(),
@@ -259,15 +292,34 @@ impl<P: Store, C: Clock> AddressManager<P, C> {
             ka.last_success = Some(time);
             ka.last_active = Some(time);
             ka.addr.services = services;
+            ka.failures = 0;
         }
     }
 
+    /// Remember a peer as an anchor, replacing the oldest anchor if we already have enough.
+    /// Anchors are persisted with the address book.
+    fn anchor(&mut self, addr: net::SocketAddr) {
+        self.anchors.retain(|a| *a != addr);
+        self.anchors.push(addr);
+
+        if self.anchors.len() > MAX_ANCHORS {
+            self.anchors.drain(..self.anchors.len() - MAX_ANCHORS);
+        }
+        self.peers.set_anchors(self.anchors.clone());
+    }
+
     /// Called when a peer disconnected.
     fn peer_disconnected(
         &mut self,
         addr: &net::SocketAddr,
         reason: Disconnect<super::DisconnectReason>,
     ) {
+        if let Some(ka) = self.peers.get_mut(&addr.ip()) {
+            // If we didn't get to handshake since our last attempt, the attempt failed.
+            if ka.last_attempt.is_some() && ka.last_success < ka.last_attempt {
+                ka.failures = ka.failures.saturating_add(1);
+            }
+        }
         if self.connected.remove(&addr.ip()) {
             // Disconnected peers cannot be used as a source for new addresses.
             self.sources.remove(addr);
@@ -300,6 +352,7 @@ impl<P: Store, C: Clock> AddressManager<P, C> {
     /// Create a new, empty address manager.
     pub fn new(cfg: Config, rng: fastrand::Rng, peers: P, clock: C) -> Self {
         let ips = peers.iter().map(|(ip, _)| *ip).collect::<Vec<_>>();
+        let anchors = peers.anchors();
         let mut addrmgr = Self {
             cfg,
             peers,
@@ -308,6 +361,7 @@ impl<P: Store, C: Clock> AddressManager<P, C> {
             connected: HashSet::with_hasher(rng.clone().into()),
             sources: HashSet::with_hasher(rng.clone().into()),
             local_addrs: HashSet::with_hasher(rng.clone().into()),
+            anchors,
             last_request: None,
             last_idle: None,
             outbox: Outbox::default(),
@@ -326,11 +380,26 @@ impl<P: Store, C: Clock> AddressManager<P, C> {
         self.peers.len()
     }
 
+    /// The number of known addresses advertising `NODE_BLOOM`. Services are updated from the
+    /// `version` message of the peers we connect to, so this includes addresses that stopped
+    /// advertising it only once we've connected to them.
+    pub fn bloom_addresses(&self) -> usize {
+        self.peers
+            .iter()
+            .filter(|(_, ka)| ka.addr.services.has(ServiceFlags::BLOOM))
+            .count()
+    }
+
     /// Whether there are any peers known to the address manager.
     pub fn is_empty(&self) -> bool {
         self.peers.is_empty() || self.address_ranges.is_empty()
     }
 
+    /// Save known addresses to the store, eg. before shutting down.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.peers.flush()
+    }
+
     #[cfg(test)]
     /// Clear the address manager of all peers.
     pub fn clear(&mut self) {
@@ -347,6 +416,28 @@ impl<P: Store, C: Clock> AddressManager<P, C> {
         self.insert(addrs.into_iter(), Source::Peer(peer));
     }
 
+    /// Called when we received an `addrv2` message from a peer. Onion services are given a
+    /// placeholder address, and only kept if we can connect to them. Other networks that
+    /// can't be represented as socket addresses are ignored.
+    pub fn received_addr_v2(&mut self, peer: net::SocketAddr, addrs: Vec<AddrV2Message>) {
+        if addrs.is_empty() || addrs.len() > MAX_ADDR_ADDRESSES {
+            // Peer misbehaving, got empty message or too many addresses.
+            return;
+        }
+        let onions = self.cfg.domains.contains(&Domain::Onion);
+        let addrs = addrs.into_iter().filter_map(|a| {
+            let addr = match a.addr {
+                AddrV2::TorV3(pubkey) if onions => {
+                    net::SocketAddr::from((onion::register(pubkey), a.port))
+                }
+                _ => a.socket_addr().ok()?,
+            };
+            Some((a.time, Address::new(&addr, a.services)))
+        });
+
+        self.insert(addrs, Source::Peer(peer));
+    }
+
     /// Add addresses to the address manager. The input matches that of the `addr` message
     /// sent by peers on the network.
     pub fn insert(
@@ -363,9 +454,10 @@ impl<P: Store, C: Clock> AddressManager<P, C> {
             if !addr.services.has(self.cfg.required_services) {
                 continue;
             }
-            // Ignore peers with these antiquated services offered. They are very unlikely to
-            // support compact filters, or have up-to-date clients.
-            if addr.services.has(ServiceFlags::GETUTXO) || addr.services.has(ServiceFlags::BLOOM) {
+            // Ignore peers with this antiquated service offered. They are very unlikely to
+            // have up-to-date clients. Peers offering `NODE_BLOOM` are kept, since we scan the
+            // chain with them.
+            if addr.services.has(ServiceFlags::GETUTXO) {
                 continue;
             }
             // Ignore addresses that don't have a "last active" time.
@@ -457,6 +549,9 @@ impl<P: Store, C: Clock> AddressManager<P, C> {
 
     /// Sample an address using the provided predicate. Only returns addresses which are `true`
     /// according to the predicate.
+    ///
+    /// Addresses with a good connection history are preferred: each candidate address is
+    /// returned with a probability equal to its score. If none are, the best scoring one is.
     pub fn sample_with(
         &mut self,
         predicate: impl Fn(&KnownAddress) -> bool,
@@ -468,6 +563,7 @@ impl<P: Store, C: Clock> AddressManager<P, C> {
             .last_idle
             .expect("AddressManager::sample: manager must be initialized before sampling");
         let domains = &self.cfg.domains;
+        let mut fallback: Option<(net::IpAddr, f64)> = None;
 
         let mut ranges: Vec<_> = self.address_ranges.values().collect();
         self.rng.shuffle(&mut ranges);
@@ -509,6 +605,14 @@ impl<P: Store, C: Clock> AddressManager<P, C> {
                 if !predicate(ka) {
                     continue;
                 }
+                // Give addresses with a poor history a lower chance of being picked.
+                let score = ka.score(time);
+                if score < 1. && self.rng.f64() >= score {
+                    if fallback.map_or(true, |(_, s)| score > s) {
+                        fallback = Some((*ip, score));
+                    }
+                    continue;
+                }
                 // Ok, we've found a worthy address!
                 ka.last_sampled = Some(time);
 
@@ -516,7 +620,11 @@ impl<P: Store, C: Clock> AddressManager<P, C> {
             }
         }
 
-        None
+        let (ip, _) = fallback?;
+        let ka = self.peers.get_mut(&ip).expect("address must exist");
+        ka.last_sampled = Some(time);
+
+        Some((ka.addr.clone(), ka.source))
     }
 
     ////////////////////////////////////////////////////////////////////////////
@@ -562,6 +670,11 @@ impl<P: Store, C: Clock> AddressManager<P, C> {
             self.peers.remove(addr);
             self.bans.insert(*addr);
 
+            if self.anchors.iter().any(|a| a.ip() == *addr) {
+                self.anchors.retain(|a| a.ip() != *addr);
+                self.peers.set_anchors(self.anchors.clone());
+            }
+
             if range.is_empty() {
                 self.address_ranges.remove(&key);
             }
@@ -601,7 +714,7 @@ pub fn is_local(addr: &net::IpAddr) -> bool {
 
 /// Get the 8-bit key of an IP address. This key is based on the IP address's
 /// range, and is used as a key to group IP addresses by range.
-fn addr_key(ip: &net::IpAddr) -> u8 {
+pub fn addr_key(ip: &net::IpAddr) -> u8 {
     match ip {
         net::IpAddr::V4(ip) => {
             // Use the /16 range (first two components) of the IP address to key into the
@@ -865,6 +978,207 @@ mod tests {
         assert!(addrmgr.sample(services).is_none());
     }
 
+    #[test]
+    fn test_failures() {
+        let clock = RefClock::from(LocalTime::now());
+        let mut addrmgr = AddressManager::new(
+            Config::default(),
+            fastrand::Rng::new(),
+            HashMap::new(),
+            clock.clone(),
+        );
+        let services = ServiceFlags::NETWORK;
+        let addr: &net::SocketAddr = &([33, 33, 33, 33], 8333).into();
+
+        addrmgr.initialize();
+        addrmgr.insert(
+            [(clock.block_time(), Address::new(addr, services))],
+            Source::Dns,
+        );
+
+        addrmgr.peer_attempted(addr);
+        addrmgr.peer_connected(addr);
+        addrmgr.peer_negotiated(addr, services, Link::Outbound);
+        addrmgr.peer_disconnected(addr, fsm::DisconnectReason::PeerTimeout("ping").into());
+        assert_eq!(addrmgr.peers.get(&addr.ip()).unwrap().failures, 0);
+
+        // Dialing fails twice.
+        for _ in 0..2 {
+            clock.elapse(LocalDuration::from_mins(1));
+            addrmgr.peer_attempted(addr);
+            addrmgr.peer_disconnected(
+                addr,
+                Disconnect::DialError(std::sync::Arc::new(std::io::ErrorKind::Other.into())),
+            );
+        }
+        assert_eq!(addrmgr.peers.get(&addr.ip()).unwrap().failures, 2);
+
+        // A successful handshake resets the count.
+        clock.elapse(LocalDuration::from_mins(1));
+        addrmgr.peer_attempted(addr);
+        addrmgr.peer_connected(addr);
+        addrmgr.peer_negotiated(addr, services, Link::Outbound);
+        assert_eq!(addrmgr.peers.get(&addr.ip()).unwrap().failures, 0);
+    }
+
+    #[test]
+    fn test_addr_v2() {
+        let time = LocalTime::now();
+        let services = ServiceFlags::NETWORK;
+        let peer: net::SocketAddr = ([44, 44, 44, 44], 8333).into();
+        let pubkey = [9; 32];
+        let addrs = vec![
+            AddrV2Message {
+                time: time.block_time(),
+                services,
+                addr: AddrV2::Ipv4([33, 33, 33, 33].into()),
+                port: 8333,
+            },
+            AddrV2Message {
+                time: time.block_time(),
+                services,
+                addr: AddrV2::TorV3(pubkey),
+                port: 8333,
+            },
+            AddrV2Message {
+                time: time.block_time(),
+                services,
+                addr: AddrV2::I2p([9; 32]),
+                port: 0,
+            },
+        ];
+
+        // Onion services are ignored unless we can connect to them.
+        let mut addrmgr = AddressManager::new(
+            Config::default(),
+            fastrand::Rng::new(),
+            HashMap::new(),
+            time,
+        );
+        addrmgr.initialize();
+        addrmgr.received_addr_v2(peer, addrs.clone());
+        assert_eq!(addrmgr.len(), 1);
+
+        let mut addrmgr = AddressManager::new(
+            Config {
+                domains: vec![Domain::IPV4, Domain::Onion],
+                ..Config::default()
+            },
+            fastrand::Rng::new(),
+            HashMap::new(),
+            time,
+        );
+        addrmgr.initialize();
+        addrmgr.received_addr_v2(peer, addrs);
+        assert_eq!(addrmgr.len(), 2);
+
+        let ip = onion::register(pubkey);
+        assert!(addrmgr.peers.get(&ip.into()).is_some());
+        assert_eq!(onion::hostname(&ip), Some(onion::encode(&pubkey)));
+    }
+
+    #[test]
+    fn test_anchors() {
+        let clock = LocalTime::now();
+        let mut addrmgr = AddressManager::new(
+            Config::default(),
+            fastrand::Rng::new(),
+            HashMap::new(),
+            clock,
+        );
+        let services = ServiceFlags::NETWORK;
+        let addrs: Vec<net::SocketAddr> = (1..=3).map(|i| ([33, 33, 33, i], 8333).into()).collect();
+        let negotiated =
+            |addr: net::SocketAddr, link: Link, persistent: bool| Event::PeerNegotiated {
+                addr,
+                link,
+                services,
+                persistent,
+                height: 0,
+                receiver: Address::new(&([44, 44, 44, 44], 8333).into(), ServiceFlags::NONE),
+                user_agent: String::new(),
+                version: fsm::PROTOCOL_VERSION,
+                relay: true,
+            };
+
+        addrmgr.initialize();
+        addrmgr.insert(
+            addrs
+                .iter()
+                .map(|a| (clock.block_time(), Address::new(a, services))),
+            Source::Dns,
+        );
+
+        for addr in &addrs {
+            addrmgr.received_event(Event::PeerConnected {
+                addr: *addr,
+                local_addr: ([0, 0, 0, 0], 0).into(),
+                link: Link::Outbound,
+            });
+        }
+        // Inbound and persistent peers are not anchors.
+        addrmgr.received_event(negotiated(addrs[0], Link::Inbound, false));
+        addrmgr.received_event(negotiated(addrs[0], Link::Outbound, true));
+        assert!(addrmgr.anchors.is_empty());
+
+        // Only the most recent outbound peers are kept.
+        for addr in &addrs {
+            addrmgr.received_event(negotiated(*addr, Link::Outbound, false));
+        }
+        assert_eq!(addrmgr.anchors, addrs[1..]);
+
+        // Banned peers are no longer anchors.
+        addrmgr.received_event(Event::PeerDisconnected {
+            addr: addrs[2],
+            reason: Disconnect::StateMachine(fsm::DisconnectReason::PeerMisbehaving("test")),
+        });
+        assert_eq!(addrmgr.anchors, addrs[1..2]);
+    }
+
+    #[test]
+    fn test_sample_prefers_history() {
+        let clock = RefClock::from(LocalTime::now());
+        let mut addrmgr = AddressManager::new(
+            Config::default(),
+            fastrand::Rng::new(),
+            HashMap::new(),
+            clock.clone(),
+        );
+        let services = ServiceFlags::NETWORK;
+        let good: net::SocketAddr = ([33, 33, 33, 33], 8333).into();
+        let bad: net::SocketAddr = ([44, 44, 44, 44], 8333).into();
+
+        addrmgr.initialize();
+        addrmgr.insert(
+            [good, bad].map(|a| (clock.block_time(), Address::new(&a, services))),
+            Source::Dns,
+        );
+        for addr in [good, bad] {
+            let ka = addrmgr.peers.get_mut(&addr.ip()).unwrap();
+            ka.last_attempt = Some(clock.local_time());
+            ka.last_success = Some(clock.local_time());
+        }
+        addrmgr.peers.get_mut(&bad.ip()).unwrap().failures = 8;
+
+        let mut picks = HashMap::new();
+        for _ in 0..100 {
+            // Make sure both addresses can be re-sampled.
+            clock.elapse(LocalDuration::from_mins(60));
+            addrmgr.timer_expired();
+
+            let (addr, _) = addrmgr.sample(services).unwrap();
+            *picks.entry(addr.socket_addr().unwrap()).or_insert(0) += 1;
+        }
+        let good = picks.get(&good).copied().unwrap_or_default();
+        let bad = picks.get(&bad).copied().unwrap_or_default();
+
+        assert!(good > bad * 5, "{} vs. {}", good, bad);
+
+        // Addresses with a poor history are still returned if there's nothing better.
+        addrmgr.sample(services).unwrap();
+        assert!(addrmgr.sample(services).is_some());
+    }
+
     #[quickcheck]
     fn prop_sample_no_duplicates(size: usize, seed: u64) -> TestResult {
         let clock = LocalTime::now();
@@ -2,25 +2,38 @@
 //!
 //! Manages BIP 37 compact block filter sync.
 
+use std::collections::{BTreeSet, VecDeque};
 use std::net::SocketAddr;
 use std::ops::{Bound, RangeInclusive};
 
-use nakamoto_common::bitcoin::util::bloom::BloomFilter;
+use nakamoto_common::bitcoin::util::bloom::{
+    BloomFilter, Decoys, BLOOM_UPDATE_ALL, BLOOM_UPDATE_NONE, BLOOM_UPDATE_P2PUBKEY_ONLY,
+    MAX_BLOOM_FILTER_SIZE,
+};
 use thiserror::Error;
 
+mod monitor;
 mod rescan;
+#[cfg(test)]
+mod simulations;
+use super::addrmgr;
 use super::bloom_cache::FilterCache;
+use super::download::Reorder;
+use super::event::TxInclusion;
 use super::output::{Io, Outbox};
 use super::Event;
-use super::{DisconnectReason, Link, Locators, PeerId};
+use super::{DisconnectReason, Link, PeerId};
 
+use monitor::Monitor;
 use nakamoto_common::bitcoin::network::constants::ServiceFlags;
 use nakamoto_common::bitcoin::network::message::NetworkMessage;
 use nakamoto_common::bitcoin::network::message_blockdata::Inventory;
-use nakamoto_common::bitcoin::network::message_bloom::{BloomFlags, FilterLoad};
+use nakamoto_common::bitcoin::network::message_bloom::{BloomFlags, FilterAdd, FilterLoad};
+use nakamoto_common::bitcoin::{MerkleBlock, Txid};
 use nakamoto_common::block::time::{Clock, LocalDuration, LocalTime};
 use nakamoto_common::block::tree::{BlockReader, BlockTree};
-use nakamoto_common::block::{BlockHash, Height};
+use nakamoto_common::block::Height;
+use nakamoto_common::bloom::store::cache::{PrivacySegment, DEFAULT_FP_RATE};
 use nakamoto_common::collections::{AddressBook, HashMap};
 use nakamoto_common::source;
 use rescan::Rescan;
@@ -31,15 +44,73 @@ pub const IDLE_TIMEOUT: LocalDuration = LocalDuration::from_secs(60);
 pub const REQUEST_TIMEOUT: LocalDuration = LocalDuration::from_secs(30);
 /// Services required from peers for header sync.
 pub const REQUIRED_SERVICES: ServiceFlags = ServiceFlags::BLOOM;
+/// Estimated false-positive rate past which filters are reloaded with `filterload`.
+pub const DEFAULT_MAX_FP_RATE: f64 = 0.001;
+/// How many times the target the observed false-positive rate may be before filters are
+/// rebuilt larger.
+pub const DEFAULT_MAX_FP_DRIFT: f64 = 4.;
+/// Number of false positives to observe before comparing the observed rate to the target.
+pub const MIN_FALSE_POSITIVES: u64 = 10;
+/// Maximum size of a `filteradd` element, as specified by BIP 37.
+pub const MAX_FILTER_ADD_SIZE: usize = 520;
+/// Number of blocks scanned between privacy segment checkpoints.
+pub const CHECKPOINT_INTERVAL: Height = 144;
+/// Number of times a merkle block request is retried with other peers before the peer
+/// it was sent to is disconnected.
+pub const MAX_REQUEST_RETRIES: usize = 2;
+/// Number of merkle blocks in a chunk of work assigned to a single peer.
+pub const CHUNK_SIZE: usize = 250;
+/// Maximum number of merkle blocks in flight per peer.
+pub const MAX_INFLIGHT_PER_PEER: usize = 1000;
+/// Latency assumed for peers that haven't been pinged yet.
+pub const DEFAULT_LATENCY: LocalDuration = LocalDuration::from_secs(1);
+/// Depth past which merkle block matches are forgotten, if their transaction wasn't received.
+pub const MAX_MATCH_DEPTH: Height = 144;
 /// Filter cache capacity in bytes.
 pub const DEFAULT_FILTER_CACHE_SIZE: usize = 1024 * 1024 * 4; // 1 MB.
 
+/// Bloom manager configuration.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Privacy segments to load onto bloom peers, keyed by segment id.
+    pub segments: HashMap<u32, PrivacySegment>,
+    /// Privacy segments previously shown to peer groups, keyed by group.
+    pub groups: HashMap<u8, u32>,
+    /// Target false-positive rate of filters rebuilt from the watch set. Filters are built
+    /// for a lower rate if the rate observed on peers drifts past it.
+    pub fp_rate: f64,
+    /// Decoys inserted into filters rebuilt from the watch set.
+    pub decoys: Decoys,
+    /// Estimated false-positive rate past which filters are reloaded instead of added to.
+    pub max_fp_rate: f64,
+    /// How many times the target the false-positive rate observed on peers may be before
+    /// filters are rebuilt larger.
+    pub max_fp_drift: f64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            segments: HashMap::default(),
+            groups: HashMap::default(),
+            fp_rate: DEFAULT_FP_RATE,
+            decoys: Decoys::default(),
+            max_fp_rate: DEFAULT_MAX_FP_RATE,
+            max_fp_drift: DEFAULT_MAX_FP_DRIFT,
+        }
+    }
+}
+
 /// State of a bloom filter peer.
 #[derive(Debug, Clone)]
 pub struct Peer {
     has_filter: bool,
     scan_start: Height,
     scan_stop: Height,
+    /// Privacy segment loaded on this peer, if any.
+    segment: Option<u32>,
+    /// Average ping latency, if known.
+    latency: Option<LocalDuration>,
 }
 
 /// What to do if a timeout for a peer is received.
@@ -55,14 +126,29 @@ enum OnTimeout {
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 struct GetBlocks {
-    /// Locators hashes.
-    locators: Locators,
-    /// Time at which the request was sent.
+    /// Heights requested and not yet received.
+    heights: BTreeSet<Height>,
+    /// Time at which the request was sent, or the last block was received.
     sent_at: LocalTime,
     /// What to do if this request times out.
     on_timeout: OnTimeout,
 }
 
+/// A chunk of merkle blocks waiting to be requested.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Chunk {
+    /// Heights to request.
+    heights: BTreeSet<Height>,
+    /// Peers the chunk may be requested from. Any peer with a filter loaded, if empty.
+    peers: Vec<PeerId>,
+    /// Peer the chunk was reassigned from, if any. Never requested from again.
+    stalled: Option<PeerId>,
+    /// Privacy segment of the peer the chunk was reassigned from, preferred when retrying.
+    segment: Option<u32>,
+    /// What to do if the request for this chunk times out.
+    on_timeout: OnTimeout,
+}
+
 /// An error from attempting to get compact filters.
 #[derive(Error, Debug)]
 pub enum GetMerkleBlocksError {
@@ -82,6 +168,7 @@ pub struct BloomManager<C> {
     /// Rescan state.
     pub rescan: Rescan,
 
+    config: Config,
     clock: C,
     /// Sync-specific peer state.
     peers: AddressBook<PeerId, Peer>,
@@ -93,6 +180,29 @@ pub struct BloomManager<C> {
     blocks_inflight: HashMap<PeerId, GetBlocks>,
     /// How long to wait for a response from a peer.
     request_timeout: LocalDuration,
+    /// Random number generator, used when rebuilding filters.
+    rng: fastrand::Rng,
+    /// Elements watched, if known, used to rebuild filters.
+    watch: Option<Vec<Vec<u8>>>,
+    /// Update flags of the filters built from the watch set.
+    flags: BloomFlags,
+    /// False-positive rate filters are built for, adjusted from the configured target
+    /// according to the rate observed on peers.
+    fp_rate: f64,
+    /// Privacy segment shown to each peer group.
+    groups: HashMap<u8, u32>,
+    /// Inclusion proofs of transactions matched in merkle blocks, awaiting the transaction.
+    matched: HashMap<Txid, TxInclusion>,
+    /// Chunks of merkle blocks waiting for a peer to be requested from.
+    queue: VecDeque<Chunk>,
+    /// Merkle blocks scanned, along with the peer they were received from and their matches,
+    /// held back until the merkle blocks below them are received.
+    order: Reorder<(PeerId, MerkleBlock, Vec<Txid>)>,
+    /// Filter last loaded onto peers outside of privacy segments, if any. Reloaded onto bloom
+    /// peers as they connect, since peers drop their filter along with the connection.
+    filter: Option<FilterLoad>,
+    /// False-positive rate of the filters loaded, as observed on peers.
+    monitor: Monitor,
 }
 
 impl<C> Iterator for BloomManager<C> {
@@ -104,18 +214,33 @@ impl<C> Iterator for BloomManager<C> {
 }
 
 impl<C: Clock> BloomManager<C> {
-    pub fn new(rng: fastrand::Rng, clock: C) -> Self {
+    pub fn new(mut config: Config, rng: fastrand::Rng, clock: C) -> Self {
+        let groups = std::mem::take(&mut config.groups);
         let peers = AddressBook::new(rng.clone());
         let rescan = Rescan::new(DEFAULT_FILTER_CACHE_SIZE);
-        let blocks_inflight = HashMap::with_hasher(rng.into());
+        let blocks_inflight = HashMap::with_hasher(rng.clone().into());
+        let matched = HashMap::with_hasher(rng.clone().into());
+        let monitor = Monitor::new(config.fp_rate, rng.clone());
+        let fp_rate = config.fp_rate;
         Self {
             rescan,
+            config,
             clock,
             peers,
             last_idle: None,
             outbox: Outbox::default(),
             blocks_inflight,
             request_timeout: REQUEST_TIMEOUT,
+            rng,
+            watch: None,
+            flags: BloomFlags::None,
+            fp_rate,
+            groups,
+            matched,
+            queue: VecDeque::new(),
+            order: Reorder::default(),
+            filter: None,
+            monitor,
         }
     }
     pub fn idle<T: BlockReader>(&mut self, tree: &T) {
@@ -144,14 +269,52 @@ impl<C: Clock> BloomManager<C> {
                 self.peer_negotiated(addr, height, services, link, tree);
             }
             Event::PeerDisconnected { addr, .. } => {
+                if let Some(req) = self.blocks_inflight.remove(&addr) {
+                    self.reassign(&addr, req.heights, req.on_timeout);
+                }
                 self.unregister(&addr);
+                self.schedule(tree);
+            }
+            Event::PeerLatencyUpdated { addr, latency } => {
+                if let Some(peer) = self.peers.get_mut(&addr) {
+                    peer.latency = Some(latency);
+                }
             }
 
             Event::BlockHeadersSynced { .. } => {}
+            Event::SnapshotImported { height, .. } => {
+                self.fast_forward(height, tree.height());
+            }
+            Event::BlockHeadersImported { reverted, .. } => {
+                let mut blocks = Vec::new();
+
+                for (height, _) in reverted {
+                    self.matched.retain(|_, m| m.height != height);
+                    blocks.extend(self.order.cancel(height));
+                }
+                self.deliver(blocks, tree.height());
+            }
 
             Event::MessageReceived { from, message } => match message.as_ref() {
                 NetworkMessage::MerkleBlock(block) => {
                     if let Some((height, _)) = tree.get_block(&block.header.block_hash()) {
+                        let mut matches = Vec::new();
+                        let mut indexes = Vec::new();
+
+                        if let Err(err) = block.extract_matches(&mut matches, &mut indexes) {
+                            log::debug!(
+                                target: "p2p",
+                                "Received invalid merkle block {} from {}: {}",
+                                block.header.block_hash(),
+                                from,
+                                err
+                            );
+                            self.outbox.event(Event::PeerMisbehaved {
+                                addr: from,
+                                reason: "invalid `merkleblock` message",
+                            });
+                            return;
+                        }
                         if tree.height() == height {
                             let merkle_stop =
                                 Event::MerkleBlockRescanStopped { height, peer: from };
@@ -165,31 +328,48 @@ impl<C: Clock> BloomManager<C> {
                             }
                         }
 
-                        let event = Event::ReceivedMerkleBlock {
-                            height,
-                            merkle_block: block.clone(),
-                            peer: from,
+                        self.monitor.scanned(block.txn.num_transactions() as u64);
+                        self.matched
+                            .retain(|_, m| m.height + MAX_MATCH_DEPTH >= height);
+
+                        for txid in &matches {
+                            self.matched.insert(
+                                *txid,
+                                TxInclusion {
+                                    height,
+                                    block: block.header.block_hash(),
+                                    proof: block.clone(),
+                                },
+                            );
+                        }
+
+                        // Scanned merkle blocks are delivered in order, while merkle blocks
+                        // that weren't part of a scan, eg. new blocks, are delivered as-is.
+                        let item = (from, block.clone(), matches);
+                        let blocks = if self.order.is_expected(height) {
+                            self.order.received(height, item).unwrap_or_default()
+                        } else {
+                            vec![(height, item)]
                         };
-                        self.outbox.event(event);
+                        self.deliver(blocks, tree.height());
+                        self.received_merkle_block(&from, height);
+                        self.schedule(tree);
                     }
                 }
                 NetworkMessage::Tx(tx) => {
+                    let inclusion = self.matched.remove(&tx.txid());
+
+                    // Only transactions matched in merkle blocks count towards the observed
+                    // false-positive rate, since those are the ones we know were scanned.
+                    if inclusion.is_some() && self.watch.is_some() {
+                        self.monitor.matched(tx);
+                    }
                     self.outbox.event(Event::ReceivedMatchedTx {
                         transaction: tx.to_owned(),
+                        inclusion,
                     });
+                    self.resize(tree);
                 }
-                NetworkMessage::Inv(inv) => {
-                    let mut tx_inv = vec![];
-                    inv.iter().for_each(|i| match i {
-                        Inventory::Transaction(tx) => {
-                            tx_inv.push(Inventory::Transaction(*tx));
-                        }
-                        _ => {}
-                    });
-                    let payload = NetworkMessage::GetData(tx_inv);
-                    self.outbox.message(from, payload);
-                }
-
                 _ => {}
             },
             _ => {}
@@ -197,8 +377,21 @@ impl<C: Clock> BloomManager<C> {
     }
     /// Unregister a peer.
     fn unregister(&mut self, id: &PeerId) {
-        // self.inflight.remove(id);
-        self.peers.remove(id);
+        let segment = self.peers.remove(id).and_then(|p| p.segment);
+
+        for chunk in self.queue.iter_mut() {
+            chunk.peers.retain(|p| p != id);
+        }
+
+        if let Some(segment) = segment {
+            if !self.peers.values().any(|p| p.segment == Some(segment)) {
+                log::debug!(
+                    target: "p2p",
+                    "Bloom filter segment {} has no peers left, reassigning on next connection",
+                    segment
+                );
+            }
+        }
     }
 
     /// Called when a new peer was negotiated.
@@ -210,13 +403,609 @@ impl<C: Clock> BloomManager<C> {
         link: Link,
         tree: &T,
     ) {
-        _ = tree;
         _ = height;
-        _ = addr;
         if link.is_outbound() && !services.has(REQUIRED_SERVICES) {
             return;
         }
         self.register(addr);
+
+        if services.has(REQUIRED_SERVICES) {
+            self.load_segment(addr);
+
+            if !self.has_filter(&addr) {
+                self.load_filter(addr);
+            }
+            self.resume(addr, tree);
+            self.request_missed(addr, tree);
+            self.schedule(tree);
+        }
+    }
+
+    /// Called when a merkle block at the given height was received. Tracks scan progress.
+    fn received_merkle_block(&mut self, from: &PeerId, height: Height) {
+        let Some(req) = self.blocks_inflight.get_mut(from) else {
+            return;
+        };
+        if !req.heights.remove(&height) {
+            return;
+        }
+        req.sent_at = self.clock.local_time();
+
+        if req.heights.is_empty() {
+            self.blocks_inflight.remove(from);
+        }
+        self.rescan.downloaded += 1;
+        self.outbox.event(Event::MerkleBlockScanProgress {
+            current: self.rescan.downloaded,
+            total: self.rescan.total,
+        });
+
+        if self.blocks_inflight.is_empty() && self.queue.is_empty() {
+            self.rescan.downloaded = 0;
+            self.rescan.total = 0;
+            self.outbox.event(Event::MerkleBlockScanCompleted);
+        }
+    }
+
+    /// Deliver received merkle blocks, and record the scan progress of the privacy segments
+    /// they were received for.
+    fn deliver(&mut self, blocks: Vec<(Height, (PeerId, MerkleBlock, Vec<Txid>))>, tip: Height) {
+        for (height, (peer, merkle_block, matches)) in blocks {
+            self.synced(&peer, height, tip);
+            self.rescan.scanned(height);
+            self.outbox.event(Event::ReceivedMerkleBlock {
+                height,
+                merkle_block,
+                matches,
+                peer,
+            });
+        }
+    }
+
+    /// Queue merkle blocks that weren't received from the given peer, to be requested from
+    /// another peer, preferably one with the same privacy segment loaded.
+    fn reassign(&mut self, from: &PeerId, heights: BTreeSet<Height>, on_timeout: OnTimeout) {
+        if heights.is_empty() {
+            return;
+        }
+        log::debug!(
+            target: "p2p",
+            "Reassigning {} merkle block(s) requested from {}",
+            heights.len(),
+            from
+        );
+        self.queue.push_front(Chunk {
+            heights,
+            peers: Vec::new(),
+            stalled: Some(*from),
+            segment: self.peers.get(from).and_then(|p| p.segment),
+            on_timeout,
+        });
+    }
+
+    /// Request queued chunks from peers with room for them.
+    ///
+    /// Each chunk is assigned to the peer expected to complete it first, given its ping
+    /// latency and the merkle blocks it already has in flight.
+    fn schedule<T: BlockReader>(&mut self, tree: &T) {
+        let mut waiting = VecDeque::new();
+
+        while let Some(chunk) = self.queue.pop_front() {
+            if let Some(peer) = self.assign(&chunk) {
+                let start = chunk.heights.first().copied().unwrap_or_default();
+                let stop = chunk.heights.last().copied().unwrap_or_default();
+
+                log::debug!(
+                    target: "p2p",
+                    "Requested merkle blocks(s) in range {} to {} from peer {}",
+                    start,
+                    stop,
+                    peer,
+                );
+                if let Some(peer) = self.peers.get_mut(&peer) {
+                    peer.scan_start = start;
+                    peer.scan_stop = stop;
+                }
+                self.outbox.event(Event::MerkleBlockScanStarted {
+                    start,
+                    stop: Some(stop),
+                    peer,
+                });
+                self.request_merkle_blocks(peer, chunk.heights, chunk.on_timeout, tree);
+            } else {
+                waiting.push_back(chunk);
+            }
+        }
+        self.queue = waiting;
+    }
+
+    /// Pick the peer to request a chunk from, if any has room for it.
+    fn assign(&self, chunk: &Chunk) -> Option<PeerId> {
+        let candidates = self
+            .peers
+            .iter()
+            .filter(|(addr, p)| p.has_filter && Some(**addr) != chunk.stalled)
+            .filter(|(addr, _)| chunk.peers.is_empty() || chunk.peers.contains(*addr))
+            .map(|(addr, p)| {
+                let inflight = self
+                    .blocks_inflight
+                    .get(addr)
+                    .map_or(0, |req| req.heights.len());
+
+                (*addr, p, inflight)
+            })
+            .filter(|(_, _, inflight)| {
+                *inflight == 0 || inflight + chunk.heights.len() <= MAX_INFLIGHT_PER_PEER
+            })
+            .collect::<Vec<_>>();
+
+        // Prefer peers with the same segment as the peer the chunk was reassigned from.
+        let preferred = candidates
+            .iter()
+            .filter(|(_, p, _)| chunk.segment.is_some() && p.segment == chunk.segment)
+            .collect::<Vec<_>>();
+        let candidates = if preferred.is_empty() {
+            candidates.iter().collect()
+        } else {
+            preferred
+        };
+
+        candidates
+            .into_iter()
+            .min_by_key(|(_, p, inflight)| {
+                let latency = p.latency.unwrap_or(DEFAULT_LATENCY);
+                (inflight + chunk.heights.len()) as u128 * latency.as_millis()
+            })
+            .map(|(addr, _, _)| *addr)
+    }
+
+    /// Send a `getdata` for the merkle blocks at the given heights, and track the request.
+    /// Heights already requested from the peer are skipped. Returns the number of merkle
+    /// blocks requested.
+    fn request_merkle_blocks<T: BlockReader>(
+        &mut self,
+        peer: PeerId,
+        heights: impl IntoIterator<Item = Height>,
+        on_timeout: OnTimeout,
+        tree: &T,
+    ) -> usize {
+        let now = self.clock.local_time();
+        let req = self.blocks_inflight.entry(peer).or_insert(GetBlocks {
+            heights: BTreeSet::new(),
+            sent_at: now,
+            on_timeout,
+        });
+        let mut inventory = Vec::new();
+
+        for height in heights {
+            if let Some(header) = tree.get_block_by_height(height) {
+                if req.heights.insert(height) {
+                    inventory.push(Inventory::FilteredBlock(header.block_hash()));
+                }
+            }
+        }
+        if inventory.is_empty() {
+            if req.heights.is_empty() {
+                self.blocks_inflight.remove(&peer);
+            }
+            return 0;
+        }
+        req.sent_at = now;
+
+        let count = inventory.len();
+
+        self.outbox.get_data(peer, inventory);
+        self.outbox.set_timer(self.request_timeout);
+
+        count
+    }
+
+    /// Load a privacy segment onto the given peer.
+    ///
+    /// Peers are grouped by address range, and each group is only ever shown the one segment
+    /// it was first assigned, so that no group learns more than a subset of the watched
+    /// elements. Groups without a segment are assigned the least used one, so that segments
+    /// left without peers through churn rotate onto newly connected groups.
+    fn load_segment(&mut self, addr: PeerId) {
+        let group = addrmgr::addr_key(&addr.ip());
+        let peers = &self.peers;
+        let segments = &self.config.segments;
+        let segment = self
+            .groups
+            .get(&group)
+            .and_then(|s| segments.get(s))
+            .filter(|s| s.is_enabled)
+            .or_else(|| {
+                segments.values().filter(|s| s.is_enabled).min_by_key(|s| {
+                    let usage = peers
+                        .values()
+                        .filter(|p| p.segment == Some(s.segment))
+                        .count();
+                    (usage, s.segment)
+                })
+            });
+
+        if let Some(segment) = segment {
+            let id = segment.segment;
+            let filter = filter_load(&segment.filter);
+
+            if let Some(peer) = self.peers.get_mut(&addr) {
+                peer.has_filter = true;
+                peer.segment = Some(id);
+            }
+            self.groups.insert(group, id);
+            self.outbox.event(Event::PeerLoadedBloomFilter {
+                filter: filter.clone(),
+                peer: addr,
+                segment: Some(id),
+            });
+            self.outbox.send_bloom_filter_load(&addr, filter);
+        }
+    }
+
+    /// Load the filter last loaded outside of privacy segments onto the given peer, if any.
+    fn load_filter(&mut self, addr: PeerId) {
+        let Some(filter) = self.filter.clone() else {
+            return;
+        };
+        if let Some(peer) = self.peers.get_mut(&addr) {
+            peer.has_filter = true;
+        }
+        self.outbox.event(Event::PeerLoadedBloomFilter {
+            filter: filter.clone(),
+            peer: addr,
+            segment: None,
+        });
+        self.outbox.send_bloom_filter_load(&addr, filter);
+    }
+
+    /// Record that the segment loaded on the given peer scanned the block at the given height.
+    /// The segment is checkpointed every [`CHECKPOINT_INTERVAL`] blocks, and when it
+    /// reaches the tip.
+    ///
+    /// Only the block following the ones already scanned advances the segment: a block past
+    /// them, eg. a new tip received mid-scan, would leave the blocks in between unscanned.
+    fn synced(&mut self, addr: &PeerId, height: Height, tip: Height) {
+        let segment = self.peers.get(addr).and_then(|p| p.segment);
+
+        if let Some(s) = segment.and_then(|s| self.config.segments.get_mut(&s)) {
+            let next =
+                height == s.synced_height + 1 || (s.synced_height <= s.birth && height == s.birth);
+            if !next {
+                return;
+            }
+            let previous = s.synced_height;
+            s.synced_height = height;
+
+            if s.synced_height / CHECKPOINT_INTERVAL > previous / CHECKPOINT_INTERVAL
+                || (s.synced_height == tip && previous < tip)
+            {
+                self.outbox
+                    .event(Event::PrivacySegmentUpdated { segment: s.clone() });
+            }
+        }
+    }
+
+    /// Checkpoint the scan progress of all privacy segments, eg. before shutting down.
+    /// Progress is otherwise only checkpointed at regular intervals.
+    pub fn checkpoint(&mut self) {
+        for segment in self.config.segments.values() {
+            self.outbox.event(Event::PrivacySegmentUpdated {
+                segment: segment.clone(),
+            });
+        }
+    }
+
+    /// Consider all privacy segments scanned up to the given height, eg. after importing a
+    /// trusted snapshot. Queued merkle blocks at or below that height are no longer requested.
+    fn fast_forward(&mut self, height: Height, tip: Height) {
+        for segment in self.config.segments.values_mut() {
+            if segment.synced_height < height {
+                segment.synced_height = height;
+
+                self.outbox.event(Event::PrivacySegmentUpdated {
+                    segment: segment.clone(),
+                });
+            }
+        }
+        let mut blocks = Vec::new();
+
+        for chunk in self.queue.iter_mut() {
+            let skipped = chunk.heights.len();
+            chunk.heights.retain(|h| {
+                if *h > height {
+                    return true;
+                }
+                blocks.extend(self.order.cancel(*h));
+                false
+            });
+
+            self.rescan.total = self
+                .rescan
+                .total
+                .saturating_sub(skipped - chunk.heights.len());
+        }
+        self.queue.retain(|c| !c.heights.is_empty());
+        self.deliver(blocks, tip);
+
+        if self.rescan.total > 0 && self.blocks_inflight.is_empty() && self.queue.is_empty() {
+            self.rescan.downloaded = 0;
+            self.rescan.total = 0;
+            self.outbox.event(Event::MerkleBlockScanCompleted);
+        }
+    }
+
+    /// Resume scanning the segment loaded on the given peer, from where it was last synced.
+    fn resume<T: BlockReader>(&mut self, addr: PeerId, tree: &T) {
+        let tip = tree.height();
+        let from = self
+            .peers
+            .get(&addr)
+            .and_then(|p| p.segment)
+            .and_then(|s| self.config.segments.get(&s))
+            .map(|s| {
+                if s.synced_height > s.birth {
+                    s.synced_height + 1
+                } else {
+                    s.birth
+                }
+            });
+
+        if let Some(from) = from.filter(|h| *h <= tip) {
+            log::debug!(
+                target: "p2p",
+                "Resuming merkle block scan from height {} with peer {}",
+                from,
+                addr
+            );
+            if let Err(err) = self.get_merkle_blocks(from..=tip, tree, vec![addr]) {
+                log::warn!(target: "p2p", "Failed to resume merkle block scan: {}", err);
+            }
+        }
+    }
+
+    /// Request the merkle blocks of the scan in progress that are neither queued, in flight nor
+    /// waiting to be delivered from the given peer, eg. because no peer with a filter loaded
+    /// was connected when the scan was started, or when they were mined.
+    fn request_missed<T: BlockReader>(&mut self, addr: PeerId, tree: &T) {
+        if !self.rescan.active || !self.has_filter(&addr) {
+            return;
+        }
+        let tip = tree.height();
+        let stop = self.rescan.end.map_or(tip, |end| end.min(tip));
+        let missed = (self.rescan.current..=stop)
+            .filter(|h| !self.order.is_pending(*h))
+            .collect::<Vec<_>>();
+
+        if !missed.is_empty() {
+            log::debug!(
+                target: "p2p",
+                "Requesting {} missed merkle block(s) from {}",
+                missed.len(),
+                addr
+            );
+            self.enqueue(&missed, vec![addr]);
+        }
+    }
+
+    /// Rebuild the privacy segments from the given watch set, with the given update flags,
+    /// and reload them onto all filtered peers. If a re-scan height is given, blocks from
+    /// that height up to the last synced height are requested again, so that matches of the
+    /// new elements in already scanned blocks aren't missed.
+    pub fn update_filter<T: BlockReader>(
+        &mut self,
+        watch: Vec<Vec<u8>>,
+        flags: BloomFlags,
+        rescan_from: Option<Height>,
+        tree: &T,
+    ) {
+        let segments = &self.config.segments;
+        let birth = segments.values().map(|s| s.birth).min().unwrap_or_default();
+        let synced = self.synced_height(tree);
+        let elements = watch.iter().map(|e| e.as_slice()).collect::<Vec<_>>();
+
+        self.config.segments = PrivacySegment::split(
+            &elements,
+            segments.len(),
+            self.fp_rate,
+            self.config.decoys,
+            birth,
+            self.rng.clone(),
+        );
+        for segment in self.config.segments.values_mut() {
+            segment.synced_height = synced;
+            segment.filter.flags = update_flags(flags);
+
+            self.outbox.event(Event::PrivacySegmentUpdated {
+                segment: segment.clone(),
+            });
+        }
+        log::debug!(
+            target: "p2p",
+            "Rebuilt {} bloom filter segment(s) for {} element(s)",
+            self.config.segments.len(),
+            elements.len()
+        );
+        self.monitor.watch(&watch, update_flags(flags), self.fp_rate);
+        self.watch = Some(watch);
+        self.flags = flags;
+
+        let filtered = self.filtered();
+
+        for addr in &filtered {
+            if let Some(peer) = self.peers.get_mut(addr) {
+                peer.segment = None;
+            }
+        }
+        for addr in &filtered {
+            self.load_segment(*addr);
+        }
+        if let Some(from) = rescan_from {
+            self.rescan(from, synced, filtered, tree);
+        }
+    }
+
+    /// Add elements to the filters loaded on peers with `filteradd`, without reloading them.
+    ///
+    /// Each element is added to the enabled segment with the lowest estimated false-positive
+    /// rate. If a segment's rate degrades past the configured maximum, and the full watch set
+    /// is known, the filters are rebuilt and reloaded instead.
+    pub fn add_to_filter<T: BlockReader>(
+        &mut self,
+        elements: Vec<Vec<u8>>,
+        rescan_from: Option<Height>,
+        tree: &T,
+    ) {
+        if let Some(watch) = &mut self.watch {
+            watch.extend(elements.iter().cloned());
+        }
+        self.monitor.extend(&elements);
+        let mut degraded = false;
+
+        for element in elements {
+            if element.len() > MAX_FILTER_ADD_SIZE {
+                log::warn!(
+                    target: "p2p",
+                    "Bloom filter element of {} byte(s) is too large to add",
+                    element.len()
+                );
+                continue;
+            }
+            let segment = self
+                .config
+                .segments
+                .values_mut()
+                .filter(|s| s.is_enabled)
+                .min_by(|a, b| a.filter.fp_rate().total_cmp(&b.filter.fp_rate()));
+
+            let Some(segment) = segment else {
+                degraded = true;
+                break;
+            };
+            segment.filter.insert(&element);
+
+            if segment.filter.fp_rate() > self.config.max_fp_rate {
+                degraded = true;
+            }
+            let id = segment.segment;
+
+            for (addr, _) in self.peers.iter().filter(|(_, p)| p.segment == Some(id)) {
+                self.outbox.message(
+                    *addr,
+                    NetworkMessage::FilterAdd(FilterAdd {
+                        data: element.clone(),
+                    }),
+                );
+            }
+        }
+
+        if degraded {
+            if let Some(watch) = self.watch.clone() {
+                log::debug!(target: "p2p", "Bloom filter degraded, reloading..");
+
+                return self.update_filter(watch, self.flags, rescan_from, tree);
+            }
+            log::warn!(
+                target: "p2p",
+                "Bloom filter degraded, but the watch set is unknown; not reloading"
+            );
+        }
+        if let Some(from) = rescan_from {
+            let synced = self.synced_height(tree);
+            let filtered = self.filtered();
+
+            self.rescan(from, synced, filtered, tree);
+        }
+    }
+
+    /// Rebuild the filters with a lower false-positive rate, and reload them onto all
+    /// filtered peers, if the rate observed on peers drifted too far from the target, eg.
+    /// because the filters were added to well past what they were sized for.
+    ///
+    /// Filters can't grow past [`MAX_BLOOM_FILTER_SIZE`], and are kept as-is once there.
+    fn resize<T: BlockReader>(&mut self, tree: &T) {
+        let Some(drift) = self.monitor.drift(MIN_FALSE_POSITIVES) else {
+            return;
+        };
+        if drift <= self.config.max_fp_drift {
+            return;
+        }
+        let Some(watch) = self.watch.clone() else {
+            return;
+        };
+        let fp_rate = self.monitor.fp_rate();
+        let target = self.monitor.target();
+        let segments = &self.config.segments;
+
+        if segments
+            .values()
+            .filter(|s| s.is_enabled)
+            .all(|s| s.filter.content.len() >= MAX_BLOOM_FILTER_SIZE)
+        {
+            log::warn!(
+                target: "p2p",
+                "Bloom filter false-positive rate of {:.5} is above target, \
+                but filters are at their maximum size",
+                fp_rate
+            );
+            self.monitor.reset();
+
+            return;
+        }
+        self.fp_rate = self.monitor.adjusted();
+        self.update_filter(watch, self.flags, None, tree);
+
+        let size = self
+            .config
+            .segments
+            .values()
+            .filter(|s| s.is_enabled)
+            .map(|s| s.filter.content.len())
+            .sum();
+
+        self.outbox.event(Event::BloomFilterResized {
+            fp_rate,
+            target,
+            size,
+        });
+    }
+
+    /// The height up to which the privacy segments were scanned.
+    fn synced_height<T: BlockReader>(&self, tree: &T) -> Height {
+        let segments = &self.config.segments;
+        let birth = segments.values().map(|s| s.birth).min().unwrap_or_default();
+
+        segments
+            .values()
+            .map(|s| s.synced_height)
+            .max()
+            .unwrap_or(birth)
+            .min(tree.height())
+    }
+
+    /// Peers with a filter loaded.
+    fn filtered(&self) -> Vec<PeerId> {
+        self.peers
+            .iter()
+            .filter(|(_, p)| p.has_filter)
+            .map(|(addr, _)| *addr)
+            .collect()
+    }
+
+    /// Request merkle blocks from the given height up to the synced height again.
+    fn rescan<T: BlockReader>(
+        &mut self,
+        from: Height,
+        synced: Height,
+        peers: Vec<PeerId>,
+        tree: &T,
+    ) {
+        if from > synced {
+            return;
+        }
+        if let Err(err) = self.get_merkle_blocks(from..=synced, tree, peers) {
+            log::warn!(target: "p2p", "Failed to re-scan merkle blocks from {}: {}", from, err);
+        }
     }
 
     /// Register a new peer.
@@ -227,6 +1016,8 @@ impl<C: Clock> BloomManager<C> {
                 has_filter: false,
                 scan_start: 0,
                 scan_stop: 0,
+                segment: None,
+                latency: None,
             },
         );
     }
@@ -239,32 +1030,27 @@ impl<C: Clock> BloomManager<C> {
                     has_filter: true,
                     scan_start: 0,
                     scan_stop: 0,
+                    segment: None,
+                    latency: None,
                 },
             );
         });
 
-        let bloom_filter = FilterLoad {
-            filter: filter.content,
-            hash_funcs: filter.hashes,
-            tweak: filter.tweak,
-            flags: match filter.flags {
-                0 => BloomFlags::None,
-                1 => BloomFlags::All,
-                2 => BloomFlags::PubkeyOnly,
-                _ => BloomFlags::None,
-            },
-        };
+        let bloom_filter = filter_load(&filter);
+        self.filter = Some(bloom_filter.clone());
 
         for peer in peers.iter() {
             self.outbox.event(Event::PeerLoadedBloomFilter {
                 filter: bloom_filter.clone(),
                 peer: *peer,
+                segment: None,
             });
             self.outbox
                 .send_bloom_filter_load(peer, bloom_filter.clone())
         }
     }
     pub fn send_bloom_filter_clear(&mut self,) {
+        self.filter = None;
 
         for peer in self.peers.iter() {
             self.outbox
@@ -273,21 +1059,21 @@ impl<C: Clock> BloomManager<C> {
     }
 
     pub fn send_bloom_filter_single_peer(&mut self, filter: BloomFilter, peer: PeerId) {
-        let bloom_filter = FilterLoad {
-            filter: filter.content,
-            hash_funcs: filter.hashes,
-            tweak: filter.tweak,
-            flags: match filter.flags {
-                0 => BloomFlags::None,
-                1 => BloomFlags::All,
-                2 => BloomFlags::PubkeyOnly,
-                _ => BloomFlags::None,
-            },
-        };
+        let bloom_filter = filter_load(&filter);
         self.outbox
             .send_bloom_filter_load(&peer, bloom_filter.clone());
     }
 
+    /// Check whether a bloom filter is loaded on the given peer.
+    pub fn has_filter(&self, addr: &PeerId) -> bool {
+        self.peers.get(addr).map_or(false, |p| p.has_filter)
+    }
+
+    /// Check whether we're connected to any peer with a bloom filter loaded.
+    pub fn is_connected(&self) -> bool {
+        self.peers.values().any(|p| p.has_filter)
+    }
+
     /// get bloom filter unset connected peers
     pub fn get_peers_not_filter_loaded(&mut self) -> Vec<SocketAddr> {
         let mut peers_set: Vec<SocketAddr> = Vec::new();
@@ -301,7 +1087,7 @@ impl<C: Clock> BloomManager<C> {
         peers_set
     }
     /// A tick was received.
-    pub fn timer_expired<T: BlockReader>(&mut self, _tree: &T) {
+    pub fn timer_expired<T: BlockReader>(&mut self, tree: &T) {
         let local_time = self.clock.local_time();
         let timeout = self.request_timeout;
         let timed_out = self
@@ -316,20 +1102,30 @@ impl<C: Clock> BloomManager<C> {
             })
             .collect::<Vec<_>>();
 
-        for (peer, on_timeout, _req) in timed_out {
+        for (peer, on_timeout, req) in timed_out {
             self.blocks_inflight.remove(&peer);
 
             match on_timeout {
                 OnTimeout::Ignore => {
                     // It's likely that the peer just didn't have the requested header.
+                    let mut blocks = Vec::new();
+
+                    for height in req.heights {
+                        blocks.extend(self.order.cancel(height));
+                    }
+                    self.deliver(blocks, tree.height());
                 }
                 OnTimeout::Retry(0) | OnTimeout::Disconnect => {
+                    self.reassign(&peer, req.heights, OnTimeout::Disconnect);
                     self.outbox
                         .disconnect(peer, DisconnectReason::PeerTimeout("getmerkleblocks"));
                 }
-                OnTimeout::Retry(_n) => {}
+                OnTimeout::Retry(n) => {
+                    self.reassign(&peer, req.heights, OnTimeout::Retry(n - 1));
+                }
             }
         }
+        self.schedule(tree);
     }
     pub fn get_mempool(&mut self) {
         if let Some(x) = self.peers.sample() {
@@ -352,48 +1148,63 @@ impl<C: Clock> BloomManager<C> {
         // Don't request more than once from the same peer.
         assert!(*range.end() <= tree.height());
 
-        for (range, peer) in self
-            .rescan
-            .requests(range, tree)
-            .into_iter()
-            .zip(peers.iter().cycle())
-        {
-            let timeout = self.request_timeout;
+        let heights = self.rescan.requests(range, tree).into_iter().flatten();
+        let heights = heights.collect::<Vec<_>>();
 
-            log::debug!(
-                target: "p2p",
-                "Requested merkle blocks(s) in range {} to {} from peer {}",
-                range.start(),
-                range.end(),
-                peer,
-            );
+        self.enqueue(&heights, peers);
+        self.rescan.reset();
+        self.schedule(tree);
 
-            if let Some(peer) = self.peers.get_mut(&peer) {
-                peer.scan_start = *range.start();
-                peer.scan_stop = *range.end()
-            }
+        Ok(())
+    }
 
-            self.outbox.event(Event::MerkleBlockScanStarted {
-                start: *range.start(),
-                stop: Some(*range.end()),
-                peer: *peer,
+    /// Split merkle blocks into chunks of work, to be spread across the given peers.
+    fn enqueue(&mut self, heights: &[Height], peers: Vec<PeerId>) {
+        for chunk in heights.chunks(CHUNK_SIZE) {
+            for height in chunk {
+                self.order.expect(*height);
+            }
+            self.rescan.total += chunk.len();
+            self.queue.push_back(Chunk {
+                heights: chunk.iter().copied().collect(),
+                peers: peers.clone(),
+                stalled: None,
+                segment: None,
+                on_timeout: OnTimeout::Retry(MAX_REQUEST_RETRIES),
             });
+        }
+    }
+    /// Abort the merkle block rescan in progress, if any. Queued merkle blocks are no longer
+    /// requested, while those already in flight are still delivered when received.
+    pub fn abort_rescan<T: BlockReader>(&mut self, tree: &T) {
+        let mut blocks = Vec::new();
+        let queued = !self.queue.is_empty();
 
-            let locators: Vec<BlockHash> = tree
-                .range(*range.start()..*range.end() + 1)
-                .map(|(_height, blockhash)| blockhash)
-                .collect();
-            let mut bock_request: Vec<Inventory> = Vec::new();
-            locators.iter().for_each(|block| {
-                bock_request.push(Inventory::FilteredBlock(*block));
-            });
+        for chunk in self.queue.drain(..) {
+            for height in chunk.heights {
+                blocks.extend(self.order.cancel(height));
+            }
+        }
+        if self.rescan.active {
+            log::debug!(
+                target: "p2p",
+                "Merkle block rescan aborted at height {}",
+                self.rescan.current
+            );
+        }
+        self.rescan.active = false;
+        self.rescan.reset();
+        self.deliver(blocks, tree.height());
+
+        // Only the merkle blocks in flight are left to be received.
+        self.rescan.downloaded = 0;
+        self.rescan.total = self.blocks_inflight.values().map(|r| r.heights.len()).sum();
 
-            self.outbox.get_data(*peer, bock_request);
-            self.outbox.set_timer(timeout);
-            self.rescan.reset();
+        if queued && self.rescan.total == 0 {
+            self.outbox.event(Event::MerkleBlockScanCompleted);
         }
-        Ok(())
     }
+
     /// Rescan merkle blocks.
     pub fn merkle_scan<T: BlockReader>(
         &mut self,
@@ -433,6 +1244,30 @@ impl<C: Clock> BloomManager<C> {
     }
 }
 
+/// Build a `filterload` message from a bloom filter.
+fn filter_load(filter: &BloomFilter) -> FilterLoad {
+    FilterLoad {
+        filter: filter.content.clone(),
+        hash_funcs: filter.hashes,
+        tweak: filter.tweak,
+        flags: match filter.flags {
+            0 => BloomFlags::None,
+            1 => BloomFlags::All,
+            2 => BloomFlags::PubkeyOnly,
+            _ => BloomFlags::None,
+        },
+    }
+}
+
+/// The update flags of a bloom filter, as stored in the filter.
+fn update_flags(flags: BloomFlags) -> u8 {
+    match flags {
+        BloomFlags::None => BLOOM_UPDATE_NONE,
+        BloomFlags::All => BLOOM_UPDATE_ALL,
+        BloomFlags::PubkeyOnly => BLOOM_UPDATE_P2PUBKEY_ONLY,
+    }
+}
+
 /// Iterator over height ranges.
 struct HeightIterator {
     start: Height,
@@ -456,3 +1291,746 @@ impl Iterator for HeightIterator {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net;
+    use std::sync::Arc;
+
+    use crate::fsm::network::Network;
+    use crate::fsm::{output, RescanProgress, PROTOCOL_VERSION};
+
+    use nakamoto_common::bitcoin::network::address::Address;
+    use nakamoto_common::bitcoin::util::merkleblock::PartialMerkleTree;
+    use nakamoto_common::bitcoin::MerkleBlock;
+    use nakamoto_common::bitcoin_hashes::Hash as _;
+    use nakamoto_common::nonempty::NonEmpty;
+    use nakamoto_test::block::cache::model;
+    use nakamoto_test::block::gen;
+
+    type RefClock = nakamoto_common::block::time::RefClock<LocalTime>;
+
+    fn negotiated(mgr: &mut BloomManager<RefClock>, addr: PeerId, tree: &mut model::Cache) {
+        mgr.received_event(
+            Event::PeerNegotiated {
+                addr,
+                link: Link::Outbound,
+                services: REQUIRED_SERVICES,
+                persistent: false,
+                height: 0,
+                receiver: Address::new(&addr, ServiceFlags::NONE),
+                user_agent: String::new(),
+                version: PROTOCOL_VERSION,
+                relay: true,
+            },
+            tree,
+        );
+    }
+
+    fn loaded(mgr: &mut BloomManager<RefClock>) -> Vec<(PeerId, Option<u32>)> {
+        output::test::events(mgr.outbox.drain())
+            .filter_map(|e| match e {
+                Event::PeerLoadedBloomFilter { peer, segment, .. } => Some((peer, segment)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_segment_groups() {
+        let rng = fastrand::Rng::with_seed(1);
+        let clock = RefClock::from(LocalTime::now());
+        let mut tree = model::Cache::new(Network::Regtest.genesis());
+
+        let a: net::SocketAddr = ([88, 88, 1, 1], 8333).into();
+        let b: net::SocketAddr = ([88, 88, 2, 2], 8333).into(); // Same group as `a`.
+        let c: net::SocketAddr = ([99, 99, 1, 1], 8333).into();
+        let d: net::SocketAddr = ([77, 77, 1, 1], 8333).into();
+        let e: net::SocketAddr = ([66, 66, 1, 1], 8333).into();
+        let f: net::SocketAddr = ([55, 55, 1, 1], 8333).into();
+
+        let elements: [&[u8]; 2] = [&[1], &[2]];
+        let mut config = Config {
+            segments: PrivacySegment::split(
+                &elements,
+                2,
+                DEFAULT_FP_RATE,
+                Decoys::None,
+                0,
+                rng.clone(),
+            ),
+            ..Config::default()
+        };
+        // Peer `e`'s group was previously shown segment `1`.
+        config.groups.insert(addrmgr::addr_key(&e.ip()), 1);
+
+        let mut mgr = BloomManager::new(config, rng, clock);
+
+        negotiated(&mut mgr, a, &mut tree);
+        negotiated(&mut mgr, c, &mut tree);
+        negotiated(&mut mgr, d, &mut tree);
+        assert_eq!(
+            loaded(&mut mgr),
+            vec![(a, Some(0)), (c, Some(1)), (d, Some(0))]
+        );
+
+        // Segment `1` is the least used, but `b` belongs to the group that was shown `0`,
+        // and `e` to the group that was shown `1`.
+        negotiated(&mut mgr, b, &mut tree);
+        negotiated(&mut mgr, e, &mut tree);
+        assert_eq!(loaded(&mut mgr), vec![(b, Some(0)), (e, Some(1))]);
+
+        // Once segment `1` is left without peers, it is rotated onto the next new group.
+        mgr.received_event(
+            Event::PeerDisconnected {
+                addr: c,
+                reason: DisconnectReason::PeerTimeout("test").into(),
+            },
+            &mut tree,
+        );
+        mgr.received_event(
+            Event::PeerDisconnected {
+                addr: e,
+                reason: DisconnectReason::PeerTimeout("test").into(),
+            },
+            &mut tree,
+        );
+        negotiated(&mut mgr, f, &mut tree);
+        assert_eq!(loaded(&mut mgr), vec![(f, Some(1))]);
+    }
+
+    #[test]
+    fn test_merkle_block_verification() {
+        let rng = fastrand::Rng::with_seed(1);
+        let clock = RefClock::from(LocalTime::now());
+        let mut tree = model::Cache::new(Network::Regtest.genesis());
+        let mut mgr = BloomManager::new(Config::default(), rng, clock);
+
+        let peer: net::SocketAddr = ([88, 88, 1, 1], 8333).into();
+        let genesis = Network::Regtest.genesis_block();
+        let coinbase = genesis.txdata[0].txid();
+
+        negotiated(&mut mgr, peer, &mut tree);
+        mgr.outbox.drain().for_each(drop);
+
+        // A valid proof is forwarded, along with its matches.
+        let valid = MerkleBlock::from_block_with_predicate(&genesis, |_| true);
+        mgr.received_event(
+            Event::MessageReceived {
+                from: peer,
+                message: Arc::new(NetworkMessage::MerkleBlock(valid)),
+            },
+            &mut tree,
+        );
+        let events = output::test::events(mgr.outbox.drain()).collect::<Vec<_>>();
+        assert!(events.iter().any(|e| matches!(e,
+            Event::ReceivedMerkleBlock { matches, .. } if matches == &vec![coinbase]
+        )));
+
+        // A proof that doesn't match the header's merkle root is rejected.
+        let invalid = MerkleBlock {
+            header: genesis.header,
+            txn: PartialMerkleTree::from_txids(&[Txid::all_zeros()], &[true]),
+        };
+        mgr.received_event(
+            Event::MessageReceived {
+                from: peer,
+                message: Arc::new(NetworkMessage::MerkleBlock(invalid)),
+            },
+            &mut tree,
+        );
+        let events = output::test::events(mgr.outbox.drain()).collect::<Vec<_>>();
+        assert!(!events
+            .iter()
+            .any(|e| matches!(e, Event::ReceivedMerkleBlock { .. })));
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, Event::PeerMisbehaved { addr, .. } if *addr == peer)));
+    }
+
+    #[test]
+    fn test_matched_tx_inclusion() {
+        let rng = fastrand::Rng::with_seed(1);
+        let clock = RefClock::from(LocalTime::now());
+        let mut tree = model::Cache::new(Network::Regtest.genesis());
+        let mut mgr = BloomManager::new(Config::default(), rng, clock);
+
+        let peer: net::SocketAddr = ([88, 88, 1, 1], 8333).into();
+        let genesis = Network::Regtest.genesis_block();
+        let coinbase = genesis.txdata[0].clone();
+        let merkle_block = MerkleBlock::from_block_with_predicate(&genesis, |_| true);
+
+        negotiated(&mut mgr, peer, &mut tree);
+
+        for message in [
+            NetworkMessage::MerkleBlock(merkle_block.clone()),
+            NetworkMessage::Tx(coinbase.clone()),
+            NetworkMessage::Tx(coinbase.clone()),
+        ] {
+            mgr.received_event(
+                Event::MessageReceived {
+                    from: peer,
+                    message: Arc::new(message),
+                },
+                &mut tree,
+            );
+        }
+        let inclusions = output::test::events(mgr.outbox.drain())
+            .filter_map(|e| match e {
+                Event::ReceivedMatchedTx { inclusion, .. } => Some(inclusion),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        // The transaction is matched to the merkle block, and only once.
+        assert_eq!(
+            inclusions,
+            vec![
+                Some(TxInclusion {
+                    height: 0,
+                    block: genesis.block_hash(),
+                    proof: merkle_block,
+                }),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resize_on_fp_drift() {
+        let rng = fastrand::Rng::with_seed(1);
+        let clock = RefClock::from(LocalTime::now());
+        let mut tree = model::Cache::new(Network::Regtest.genesis());
+        let mut mgr = BloomManager::new(Config::default(), rng, clock);
+
+        let peer: net::SocketAddr = ([88, 88, 1, 1], 8333).into();
+        let genesis = Network::Regtest.genesis_block();
+        let coinbase = genesis.txdata[0].clone();
+        let merkle_block = MerkleBlock::from_block_with_predicate(&genesis, |_| true);
+        // The coinbase pays to a public key pushed in its output script.
+        let pubkey = coinbase.output[0].script_pubkey.as_bytes()[1..66].to_vec();
+
+        let scan = |mgr: &mut BloomManager<RefClock>, tree: &mut model::Cache| {
+            for _ in 0..MIN_FALSE_POSITIVES {
+                for message in [
+                    NetworkMessage::MerkleBlock(merkle_block.clone()),
+                    NetworkMessage::Tx(coinbase.clone()),
+                ] {
+                    mgr.received_event(
+                        Event::MessageReceived {
+                            from: peer,
+                            message: Arc::new(message),
+                        },
+                        tree,
+                    );
+                }
+            }
+            output::test::events(mgr.outbox.drain()).collect::<Vec<_>>()
+        };
+
+        // Transactions of ours aren't false positives.
+        mgr.update_filter(vec![pubkey], BloomFlags::None, None, &tree);
+        negotiated(&mut mgr, peer, &mut tree);
+
+        let events = scan(&mut mgr, &mut tree);
+        assert!(!events
+            .iter()
+            .any(|e| matches!(e, Event::BloomFilterResized { .. })));
+        assert_eq!(mgr.monitor.fp_rate(), 0.);
+
+        // Once all the transactions matched are false positives, the filters are rebuilt
+        // with a lower false-positive rate, and reloaded onto peers.
+        mgr.update_filter(vec![vec![1], vec![2]], BloomFlags::None, None, &tree);
+        mgr.outbox.drain().for_each(drop);
+
+        let events = scan(&mut mgr, &mut tree);
+        let resized = events
+            .iter()
+            .filter_map(|e| match e {
+                Event::BloomFilterResized {
+                    fp_rate, target, ..
+                } => Some((*fp_rate, *target)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(resized, vec![(1., DEFAULT_FP_RATE)]);
+        assert!(events.iter().any(|e| matches!(
+            e,
+            Event::PeerLoadedBloomFilter { peer: p, segment: Some(0), .. } if *p == peer
+        )));
+        assert_eq!(mgr.config.fp_rate, DEFAULT_FP_RATE, "The target is kept");
+        assert_eq!(mgr.fp_rate, DEFAULT_FP_RATE * DEFAULT_FP_RATE);
+        assert_eq!(mgr.monitor.target(), DEFAULT_FP_RATE);
+        assert_eq!(
+            mgr.monitor.fp_rate(),
+            0.,
+            "Counts are reset with the new filters"
+        );
+    }
+
+    #[test]
+    fn test_scan_progress_and_retry() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        let clock = RefClock::from(LocalTime::now());
+        let chain = gen::blockchain(Network::Regtest.genesis_block(), 4, &mut rng);
+        let headers = NonEmpty::from_vec(chain.iter().map(|b| b.header).collect()).unwrap();
+        let mut tree = model::Cache::from(headers);
+
+        let a: net::SocketAddr = ([88, 88, 1, 1], 8333).into();
+        let b: net::SocketAddr = ([99, 99, 1, 1], 8333).into();
+
+        // The segment is born past the tip, so that nothing is scanned on connection.
+        let elements: [&[u8]; 1] = [&[1]];
+        let config = Config {
+            segments: PrivacySegment::split(
+                &elements,
+                1,
+                DEFAULT_FP_RATE,
+                Decoys::None,
+                5,
+                rng.clone(),
+            ),
+            ..Config::default()
+        };
+        let mut mgr = BloomManager::new(config, rng, clock);
+        let received =
+            |mgr: &mut BloomManager<RefClock>, from, height: usize, tree: &mut model::Cache| {
+                let merkle_block =
+                    MerkleBlock::from_block_with_predicate(&chain[height], |_| false);
+
+                mgr.received_event(
+                    Event::MessageReceived {
+                        from,
+                        message: Arc::new(NetworkMessage::MerkleBlock(merkle_block)),
+                    },
+                    tree,
+                );
+            };
+
+        negotiated(&mut mgr, a, &mut tree);
+        negotiated(&mut mgr, b, &mut tree);
+        mgr.get_merkle_blocks(1..=4, &tree, vec![a]).unwrap();
+        mgr.outbox.drain().for_each(drop);
+
+        received(&mut mgr, a, 1, &mut tree);
+        received(&mut mgr, a, 2, &mut tree);
+
+        let events = output::test::events(mgr.outbox.drain()).collect::<Vec<_>>();
+        assert!(events.iter().any(|e| matches!(
+            e,
+            Event::MerkleBlockScanProgress {
+                current: 2,
+                total: 4
+            }
+        )));
+
+        // The remaining blocks are requested from the other peer once `a` disconnects.
+        mgr.received_event(
+            Event::PeerDisconnected {
+                addr: a,
+                reason: DisconnectReason::PeerTimeout("test").into(),
+            },
+            &mut tree,
+        );
+        let requested = output::test::messages_from(mgr.outbox.drain(), &b)
+            .filter_map(|m| match m {
+                NetworkMessage::GetData(invs) => Some(invs),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            requested,
+            vec![vec![
+                Inventory::FilteredBlock(chain[3].block_hash()),
+                Inventory::FilteredBlock(chain[4].block_hash()),
+            ]]
+        );
+
+        received(&mut mgr, b, 3, &mut tree);
+        received(&mut mgr, b, 4, &mut tree);
+
+        let events = output::test::events(mgr.outbox.drain()).collect::<Vec<_>>();
+        assert!(events.iter().any(|e| matches!(
+            e,
+            Event::MerkleBlockScanProgress {
+                current: 4,
+                total: 4
+            }
+        )));
+        assert!(events
+            .iter()
+            .any(|e| matches!(e, Event::MerkleBlockScanCompleted)));
+    }
+
+    #[test]
+    fn test_scan_ordered_delivery() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        let clock = RefClock::from(LocalTime::now());
+        let chain = gen::blockchain(Network::Regtest.genesis_block(), 4, &mut rng);
+        let headers = NonEmpty::from_vec(chain.iter().map(|b| b.header).collect()).unwrap();
+        let mut tree = model::Cache::from(headers);
+        let peer: net::SocketAddr = ([88, 88, 1, 1], 8333).into();
+
+        let elements: [&[u8]; 1] = [&[1]];
+        let config = Config {
+            segments: PrivacySegment::split(
+                &elements,
+                1,
+                DEFAULT_FP_RATE,
+                Decoys::None,
+                5,
+                rng.clone(),
+            ),
+            ..Config::default()
+        };
+        let mut mgr = BloomManager::new(config, rng, clock);
+
+        negotiated(&mut mgr, peer, &mut tree);
+        mgr.get_merkle_blocks(1..=3, &tree, vec![peer]).unwrap();
+        mgr.outbox.drain().for_each(drop);
+
+        let mut delivered = Vec::new();
+        for height in [3, 2, 4, 1] {
+            let merkle_block = MerkleBlock::from_block_with_predicate(&chain[height], |_| false);
+
+            mgr.received_event(
+                Event::MessageReceived {
+                    from: peer,
+                    message: Arc::new(NetworkMessage::MerkleBlock(merkle_block)),
+                },
+                &mut tree,
+            );
+            delivered.push(
+                output::test::events(mgr.outbox.drain())
+                    .filter_map(|e| match e {
+                        Event::ReceivedMerkleBlock { height, .. } => Some(height),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>(),
+            );
+        }
+
+        // Scanned merkle blocks are held back until the ones below them are received, while
+        // the merkle block that isn't part of the scan is delivered right away.
+        assert_eq!(delivered, vec![vec![], vec![], vec![4], vec![1, 2, 3]]);
+    }
+
+    #[test]
+    fn test_scan_abort() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        let clock = RefClock::from(LocalTime::now());
+        let chain = gen::blockchain(Network::Regtest.genesis_block(), 10, &mut rng);
+        let headers = NonEmpty::from_vec(chain.iter().map(|b| b.header).collect()).unwrap();
+        let mut tree = model::Cache::from(headers);
+
+        let a: net::SocketAddr = ([88, 88, 1, 1], 8333).into();
+        let b: net::SocketAddr = ([99, 99, 1, 1], 8333).into();
+
+        // The segment is born past the tip, so that nothing is scanned on connection.
+        let elements: [&[u8]; 1] = [&[1]];
+        let config = Config {
+            segments: PrivacySegment::split(
+                &elements,
+                1,
+                DEFAULT_FP_RATE,
+                Decoys::None,
+                11,
+                rng.clone(),
+            ),
+            ..Config::default()
+        };
+        let mut mgr = BloomManager::new(config, rng, clock);
+
+        // Queue a scan with a peer that isn't connected yet.
+        negotiated(&mut mgr, a, &mut tree);
+        mgr.merkle_scan(Bound::Included(1), Bound::Included(10), vec![b], &tree);
+        mgr.outbox.drain().for_each(drop);
+
+        assert_eq!(
+            mgr.rescan.progress(),
+            Some(RescanProgress {
+                start: 1,
+                current: 1,
+                end: Some(10),
+            })
+        );
+        mgr.abort_rescan(&tree);
+        assert_eq!(mgr.rescan.progress(), None);
+        assert!(output::test::events(mgr.outbox.drain())
+            .any(|e| matches!(e, Event::MerkleBlockScanCompleted)));
+
+        // The merkle blocks that were queued are no longer requested.
+        negotiated(&mut mgr, b, &mut tree);
+        assert!(!output::test::messages_from(mgr.outbox.drain(), &b)
+            .any(|m| matches!(m, NetworkMessage::GetData(_))));
+    }
+
+    #[test]
+    fn test_reload_filter_on_reconnect() {
+        let rng = fastrand::Rng::with_seed(1);
+        let clock = RefClock::from(LocalTime::now());
+        let mut tree = model::Cache::new(Network::Regtest.genesis());
+        let mut mgr = BloomManager::new(Config::default(), rng, clock);
+
+        let a: net::SocketAddr = ([88, 88, 1, 1], 8333).into();
+        let b: net::SocketAddr = ([99, 99, 1, 1], 8333).into();
+        let c: net::SocketAddr = ([77, 77, 1, 1], 8333).into();
+
+        // Without privacy segments, nothing is loaded until a filter is.
+        negotiated(&mut mgr, a, &mut tree);
+        assert_eq!(loaded(&mut mgr), vec![]);
+
+        mgr.send_bloom_filter_all_connected(BloomFilter::new(1, DEFAULT_FP_RATE, 0, 0), vec![a]);
+        assert_eq!(loaded(&mut mgr), vec![(a, None)]);
+
+        mgr.received_event(
+            Event::PeerDisconnected {
+                addr: a,
+                reason: DisconnectReason::PeerTimeout("test").into(),
+            },
+            &mut tree,
+        );
+        assert!(!mgr.has_filter(&a));
+
+        // The filter is reloaded onto the peer when it reconnects, and onto new peers.
+        negotiated(&mut mgr, a, &mut tree);
+        negotiated(&mut mgr, b, &mut tree);
+        assert_eq!(loaded(&mut mgr), vec![(a, None), (b, None)]);
+        assert!(mgr.has_filter(&a));
+        assert!(mgr.has_filter(&b));
+
+        // Once cleared, it no longer is.
+        mgr.send_bloom_filter_clear();
+        negotiated(&mut mgr, c, &mut tree);
+        assert_eq!(loaded(&mut mgr), vec![]);
+        assert!(!mgr.has_filter(&c));
+    }
+
+    #[test]
+    fn test_request_missed_on_reconnect() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        let clock = RefClock::from(LocalTime::now());
+        let chain = gen::blockchain(Network::Regtest.genesis_block(), 10, &mut rng);
+        let headers = NonEmpty::from_vec(chain.iter().map(|b| b.header).collect()).unwrap();
+        let mut tree = model::Cache::from(headers);
+        let mut mgr = BloomManager::new(Config::default(), rng, clock);
+
+        let a: net::SocketAddr = ([88, 88, 1, 1], 8333).into();
+        let b: net::SocketAddr = ([99, 99, 1, 1], 8333).into();
+        let requested = |outbox: &mut Outbox, addr: &PeerId| {
+            output::test::messages_from(outbox.drain(), addr)
+                .filter_map(|m| match m {
+                    NetworkMessage::GetData(invs) => Some(invs),
+                    _ => None,
+                })
+                .flatten()
+                .collect::<Vec<_>>()
+        };
+
+        negotiated(&mut mgr, a, &mut tree);
+        mgr.send_bloom_filter_all_connected(BloomFilter::new(1, DEFAULT_FP_RATE, 0, 0), vec![a]);
+        mgr.received_event(
+            Event::PeerDisconnected {
+                addr: a,
+                reason: DisconnectReason::PeerTimeout("test").into(),
+            },
+            &mut tree,
+        );
+        mgr.outbox.drain().for_each(drop);
+
+        // The scan is started while no peer is connected, so nothing can be requested.
+        mgr.merkle_scan(Bound::Included(1), Bound::Unbounded, vec![], &tree);
+        assert!(requested(&mut mgr.outbox, &a).is_empty());
+
+        // The missed merkle blocks are requested once a peer with the filter reconnects.
+        negotiated(&mut mgr, a, &mut tree);
+        assert_eq!(
+            requested(&mut mgr.outbox, &a),
+            (1..=10)
+                .map(|h| Inventory::FilteredBlock(chain[h].block_hash()))
+                .collect::<Vec<_>>()
+        );
+
+        // Merkle blocks already in flight aren't requested again.
+        negotiated(&mut mgr, b, &mut tree);
+        assert!(mgr.has_filter(&b));
+        assert!(requested(&mut mgr.outbox, &b).is_empty());
+    }
+
+    #[test]
+    fn test_schedule_by_latency() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        let clock = RefClock::from(LocalTime::now());
+        let chain = gen::blockchain(Network::Regtest.genesis_block(), 1200, &mut rng);
+        let headers = NonEmpty::from_vec(chain.iter().map(|b| b.header).collect()).unwrap();
+        let mut tree = model::Cache::from(headers);
+
+        let fast: net::SocketAddr = ([88, 88, 1, 1], 8333).into();
+        let slow: net::SocketAddr = ([99, 99, 1, 1], 8333).into();
+
+        let elements: [&[u8]; 1] = [&[1]];
+        let config = Config {
+            segments: PrivacySegment::split(
+                &elements,
+                1,
+                DEFAULT_FP_RATE,
+                Decoys::None,
+                1201,
+                rng.clone(),
+            ),
+            ..Config::default()
+        };
+        let mut mgr = BloomManager::new(config, rng, clock);
+
+        for (addr, latency) in [(fast, 10), (slow, 100)] {
+            negotiated(&mut mgr, addr, &mut tree);
+            mgr.received_event(
+                Event::PeerLatencyUpdated {
+                    addr,
+                    latency: LocalDuration::from_millis(latency),
+                },
+                &mut tree,
+            );
+        }
+        mgr.outbox.drain().for_each(drop);
+        mgr.get_merkle_blocks(1..=1200, &tree, vec![fast, slow])
+            .unwrap();
+
+        let mut requested = HashMap::with_hasher(fastrand::Rng::new().into());
+        for (addr, msg) in output::test::messages(mgr.outbox.drain()) {
+            if let NetworkMessage::GetData(invs) = msg {
+                *requested.entry(addr).or_insert(0) += invs.len();
+            }
+        }
+
+        // The fast peer is given work up to its in-flight limit, and the rest goes to
+        // the slow peer.
+        assert_eq!(requested.get(&fast), Some(&MAX_INFLIGHT_PER_PEER));
+        assert_eq!(requested.get(&slow), Some(&(1200 - MAX_INFLIGHT_PER_PEER)));
+    }
+
+    #[test]
+    fn test_synced_contiguous() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        let clock = RefClock::from(LocalTime::now());
+        let chain = gen::blockchain(Network::Regtest.genesis_block(), 10, &mut rng);
+        let headers = NonEmpty::from_vec(chain.iter().map(|b| b.header).collect()).unwrap();
+        let mut tree = model::Cache::from(headers);
+        let peer: net::SocketAddr = ([88, 88, 1, 1], 8333).into();
+
+        let elements: [&[u8]; 1] = [&[1]];
+        let config = Config {
+            segments: PrivacySegment::split(
+                &elements,
+                1,
+                DEFAULT_FP_RATE,
+                Decoys::None,
+                1,
+                rng.clone(),
+            ),
+            ..Config::default()
+        };
+        let mut mgr = BloomManager::new(config, rng, clock);
+        let synced = |mgr: &BloomManager<RefClock>| {
+            mgr.config
+                .segments
+                .values()
+                .map(|s| s.synced_height)
+                .max()
+                .unwrap()
+        };
+        negotiated(&mut mgr, peer, &mut tree);
+
+        for height in 1..=4 {
+            mgr.synced(&peer, height, 10);
+        }
+        assert_eq!(synced(&mgr), 4);
+
+        // A block past the ones scanned, eg. a new tip received mid-scan, doesn't mark the
+        // blocks in between as scanned.
+        mgr.synced(&peer, 10, 10);
+        assert_eq!(synced(&mgr), 4);
+
+        for height in 5..=10 {
+            mgr.synced(&peer, height, 10);
+        }
+        assert_eq!(synced(&mgr), 10);
+    }
+
+    #[test]
+    fn test_snapshot_fast_forward() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        let clock = RefClock::from(LocalTime::now());
+        let chain = gen::blockchain(Network::Regtest.genesis_block(), 10, &mut rng);
+        let headers = NonEmpty::from_vec(chain.iter().map(|b| b.header).collect()).unwrap();
+        let mut tree = model::Cache::from(headers);
+
+        let a: net::SocketAddr = ([88, 88, 1, 1], 8333).into();
+        let b: net::SocketAddr = ([99, 99, 1, 1], 8333).into();
+
+        let elements: [&[u8]; 1] = [&[1]];
+        let config = Config {
+            segments: PrivacySegment::split(
+                &elements,
+                1,
+                DEFAULT_FP_RATE,
+                Decoys::None,
+                11,
+                rng.clone(),
+            ),
+            ..Config::default()
+        };
+        let mut mgr = BloomManager::new(config, rng, clock);
+
+        // Queue a scan with a peer that isn't connected yet.
+        negotiated(&mut mgr, a, &mut tree);
+        mgr.get_merkle_blocks(1..=10, &tree, vec![b]).unwrap();
+        mgr.outbox.drain().for_each(drop);
+
+        mgr.received_event(
+            Event::SnapshotImported {
+                height: 6,
+                hash: chain[6].block_hash(),
+                utxos: 0,
+            },
+            &mut tree,
+        );
+        let synced = output::test::events(mgr.outbox.drain())
+            .filter_map(|e| match e {
+                Event::PrivacySegmentUpdated { segment } => Some(segment.synced_height),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(synced, vec![6]);
+
+        // Only the blocks past the snapshot are requested.
+        negotiated(&mut mgr, b, &mut tree);
+
+        let requested = output::test::messages_from(mgr.outbox.drain(), &b)
+            .filter_map(|m| match m {
+                NetworkMessage::GetData(invs) => Some(invs),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            requested,
+            vec![(7..=10)
+                .map(|h| Inventory::FilteredBlock(chain[h].block_hash()))
+                .collect::<Vec<_>>()]
+        );
+
+        // Progress is checkpointed on demand, eg. on shutdown, even if it's unchanged.
+        mgr.checkpoint();
+
+        let synced = output::test::events(mgr.outbox.drain())
+            .filter_map(|e| match e {
+                Event::PrivacySegmentUpdated { segment } => Some(segment.synced_height),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(synced, vec![6]);
+    }
+}
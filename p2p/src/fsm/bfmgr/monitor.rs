@@ -0,0 +1,182 @@
+//! Bloom filter false-positive rate monitoring.
+//!
+//! Peers match transactions against our filters, and can't tell a false positive apart from
+//! a transaction of ours. We can, by matching the transactions they send us against the
+//! elements we watch. The rate of false positives observed over the transactions scanned
+//! tells whether our filters still perform as they were sized to, or have grown too small.
+use nakamoto_common::bitcoin::util::bloom::{self, Elements};
+use nakamoto_common::bitcoin::Transaction;
+use nakamoto_common::collections::HashSet;
+
+/// Elements watched, matched exactly.
+#[derive(Debug)]
+struct Watched(HashSet<Vec<u8>>);
+
+impl Elements for Watched {
+    fn contains(&self, data: &[u8]) -> bool {
+        self.0.contains(data)
+    }
+
+    fn insert(&mut self, data: &[u8]) {
+        self.0.insert(data.to_vec());
+    }
+}
+
+/// Tracks the false-positive rate of our filters, as observed on peers.
+#[derive(Debug)]
+pub struct Monitor {
+    /// Target false-positive rate.
+    target: f64,
+    /// False-positive rate the filters loaded were built for.
+    built: f64,
+    /// Update flags of the filters loaded.
+    flags: u8,
+    /// Elements watched, used to tell false positives apart. These are updated with the
+    /// outpoints of matching outputs, like peers update the filters loaded.
+    elements: Watched,
+    /// Transactions scanned by peers, according to the merkle blocks received.
+    scanned: u64,
+    /// Transactions matched by peers that don't match any of our elements.
+    false_positives: u64,
+}
+
+impl Monitor {
+    /// Create a new monitor, for filters sized for the given target false-positive rate.
+    pub fn new(target: f64, rng: fastrand::Rng) -> Self {
+        Self {
+            target,
+            built: target,
+            flags: bloom::BLOOM_UPDATE_NONE,
+            elements: Watched(HashSet::with_hasher(rng.into())),
+            scanned: 0,
+            false_positives: 0,
+        }
+    }
+
+    /// Target false-positive rate.
+    pub fn target(&self) -> f64 {
+        self.target
+    }
+
+    /// Watch the given elements, replacing the ones watched until now, as loaded in filters
+    /// with the given update flags, built for the given false-positive rate. Since these are
+    /// new filters, the counts of the previous filters are reset.
+    pub fn watch(&mut self, elements: &[Vec<u8>], flags: u8, built: f64) {
+        self.elements.0.clear();
+        self.elements.0.extend(elements.iter().cloned());
+        self.flags = flags;
+        self.built = built;
+        self.reset();
+    }
+
+    /// Watch additional elements, added to the filters loaded.
+    pub fn extend(&mut self, elements: &[Vec<u8>]) {
+        self.elements.0.extend(elements.iter().cloned());
+    }
+
+    /// Record transactions scanned by a peer.
+    pub fn scanned(&mut self, count: u64) {
+        self.scanned += count;
+    }
+
+    /// Record a transaction matched by a peer. Returns whether it matches one of our
+    /// elements, ie. whether it would have been matched by a filter without false positives.
+    pub fn matched(&mut self, tx: &Transaction) -> bool {
+        let relevant = bloom::match_tx(&mut self.elements, self.flags, tx);
+
+        if !relevant {
+            self.false_positives += 1;
+        }
+        relevant
+    }
+
+    /// False-positive rate observed since the filters were loaded.
+    pub fn fp_rate(&self) -> f64 {
+        if self.scanned == 0 {
+            return 0.;
+        }
+        self.false_positives as f64 / self.scanned as f64
+    }
+
+    /// How many times the target the observed false-positive rate is, if enough false
+    /// positives were observed to tell.
+    pub fn drift(&self, min_false_positives: u64) -> Option<f64> {
+        if self.false_positives < min_false_positives || self.target <= 0. {
+            return None;
+        }
+        Some(self.fp_rate() / self.target)
+    }
+
+    /// False-positive rate to build filters for, for the rate observed on peers to meet the
+    /// target, given how the filters loaded perform compared to the rate they were built for.
+    pub fn adjusted(&self) -> f64 {
+        let fp_rate = self.fp_rate();
+
+        if fp_rate <= 0. {
+            return self.built;
+        }
+        self.target * self.built / fp_rate
+    }
+
+    /// Reset the counts, eg. after reloading filters.
+    pub fn reset(&mut self) {
+        self.scanned = 0;
+        self.false_positives = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nakamoto_common::bitcoin::blockdata::transaction::{OutPoint, TxIn, TxOut};
+    use nakamoto_common::bitcoin::consensus::serialize;
+    use nakamoto_common::bitcoin::{PackedLockTime, PublicKey, Script};
+
+    #[test]
+    fn test_matched_spends_of_updated_outpoints() {
+        let pubkey: PublicKey =
+            "0250863ad64a87ae8a2fe83c1af1a8403cb53f53e486d8511dad8a04887e5b2352"
+                .parse()
+                .unwrap();
+        let tx = |previous_output, script_pubkey| Transaction {
+            version: 1,
+            lock_time: PackedLockTime(0),
+            input: vec![TxIn {
+                previous_output,
+                ..TxIn::default()
+            }],
+            output: vec![TxOut {
+                value: 1000,
+                script_pubkey,
+                ..TxOut::default()
+            }],
+        };
+        let mut monitor = Monitor::new(0.001, fastrand::Rng::with_seed(1));
+        monitor.watch(
+            &[pubkey.to_bytes()],
+            bloom::BLOOM_UPDATE_P2PUBKEY_ONLY,
+            0.001,
+        );
+
+        // Peers add the outpoint of a pay-to-pubkey output to their filter, so the spend
+        // matches, and isn't a false positive.
+        let funding = tx(OutPoint::default(), Script::new_p2pk(&pubkey));
+        let outpoint = OutPoint::new(funding.txid(), 0);
+        assert!(monitor.matched(&funding));
+        assert!(monitor.elements.contains(&serialize(&outpoint)));
+        assert!(monitor.matched(&tx(outpoint, Script::new_op_return(b"memo"))));
+
+        // Unrelated transactions are.
+        let other = tx(
+            OutPoint::new(outpoint.txid, 1),
+            Script::new_op_return(b"memo"),
+        );
+        assert!(!monitor.matched(&other));
+
+        monitor.scanned(4);
+        assert_eq!(monitor.fp_rate(), 0.25);
+        assert_eq!(monitor.drift(1), Some(250.));
+        assert_eq!(monitor.adjusted(), 0.001 * 0.001 / 0.25);
+    }
+}
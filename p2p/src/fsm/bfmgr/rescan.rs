@@ -11,6 +11,7 @@ use nakamoto_common::block::{BlockHash, Height, MerkleBlock};
 use nakamoto_common::collections::{HashMap, HashSet};
 
 use super::{FilterCache, HeightIterator /* MAX_MESSAGE_CFILTERS */};
+use crate::fsm::RescanProgress;
 
 /// Bloom Filter (re)scan state.
 #[derive(Debug, Default)]
@@ -30,6 +31,10 @@ pub struct Rescan {
     pub watch: HashSet<Script>,
     /// Transactions to watch for.
     pub transactions: HashMap<Txid, HashSet<Script>>,
+    /// Merkle blocks requested since the last completed scan.
+    pub total: usize,
+    /// Merkle blocks received since the last completed scan.
+    pub downloaded: usize,
 
     /// Filters requested and remaining to download.
     requested: BTreeSet<Height>,
@@ -66,6 +71,28 @@ impl Rescan {
         self.requested.clear();
     }
 
+    /// A merkle block at the given height was delivered. Stops the rescan once its end height
+    /// is reached.
+    pub fn scanned(&mut self, height: Height) {
+        if !self.active || height < self.current || self.end.map_or(false, |end| height > end) {
+            return;
+        }
+        self.current = height + 1;
+
+        if self.end == Some(height) {
+            self.active = false;
+        }
+    }
+
+    /// Progress of the rescan, if it's in progress.
+    pub fn progress(&self) -> Option<RescanProgress> {
+        self.active.then_some(RescanProgress {
+            start: self.start,
+            current: self.current,
+            end: self.end,
+        })
+    }
+
     /// Given a range of heights, return the ranges that are missing.
     /// This is useful to figure out which ranges to fetch while ensuring we don't request
     /// the same heights more than once.
@@ -0,0 +1,433 @@
+//! Simulations of the bloom manager with synthetic peers.
+//!
+//! Peers either serve the merkle blocks requested from them, in random order, or stall. Time
+//! is advanced by the request timeout between rounds, so that stalled requests are retried
+//! with other peers, and peers are disconnected once retries are exhausted.
+use std::collections::{BTreeMap, BTreeSet};
+use std::net;
+use std::sync::Arc;
+
+use nakamoto_common::bitcoin::network::address::Address;
+use nakamoto_common::bitcoin::Block;
+use nakamoto_common::nonempty::NonEmpty;
+use nakamoto_test::block::cache::model;
+use nakamoto_test::block::gen;
+use quickcheck_macros::quickcheck;
+
+use super::*;
+use crate::fsm::network::Network;
+use crate::fsm::PROTOCOL_VERSION;
+
+type RefClock = nakamoto_common::block::time::RefClock<LocalTime>;
+
+/// Maximum number of rounds a simulation runs for.
+const MAX_ROUNDS: usize = 32;
+
+/// How a synthetic peer responds to merkle block requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Behavior {
+    /// Serves every merkle block requested.
+    Honest,
+    /// Never replies.
+    Stalling,
+}
+
+/// A bloom manager connected to synthetic peers, which serve the merkle blocks of a
+/// generated chain.
+struct Sim {
+    mgr: BloomManager<RefClock>,
+    clock: RefClock,
+    tree: model::Cache,
+    chain: NonEmpty<Block>,
+    rng: fastrand::Rng,
+    /// Connected peers.
+    peers: BTreeMap<PeerId, Behavior>,
+    /// Merkle blocks requested from each peer, in request order.
+    requested: BTreeMap<PeerId, Vec<Height>>,
+    /// Merkle blocks requested from each peer, and not yet served.
+    pending: BTreeMap<PeerId, BTreeSet<Height>>,
+    /// Filters loaded onto each peer, in load order.
+    loaded: Vec<(PeerId, Option<u32>)>,
+    /// Peers disconnected by the manager.
+    disconnected: Vec<PeerId>,
+    /// Events emitted by the manager, in emission order.
+    events: Vec<Event>,
+}
+
+impl Sim {
+    /// Create a simulation over a chain of the given height, with the given number of
+    /// privacy segments. Segments are born past the tip, so that nothing is scanned on
+    /// connection.
+    fn new(height: Height, segments: usize, seed: u64) -> Self {
+        let mut rng = fastrand::Rng::with_seed(seed);
+        let clock = RefClock::from(LocalTime::now());
+        let chain = gen::blockchain(Network::Regtest.genesis_block(), height, &mut rng);
+        let headers = NonEmpty::from_vec(chain.iter().map(|b| b.header).collect()).unwrap();
+        let tree = model::Cache::from(headers);
+
+        let elements: [&[u8]; 2] = [&[1], &[2]];
+        let config = Config {
+            segments: PrivacySegment::split(
+                &elements,
+                segments,
+                DEFAULT_FP_RATE,
+                Decoys::None,
+                height + 1,
+                rng.clone(),
+            ),
+            ..Config::default()
+        };
+        let mgr = BloomManager::new(config, rng.clone(), clock.clone());
+
+        Self {
+            mgr,
+            clock,
+            tree,
+            chain,
+            rng,
+            peers: BTreeMap::new(),
+            requested: BTreeMap::new(),
+            pending: BTreeMap::new(),
+            loaded: Vec::new(),
+            disconnected: Vec::new(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Negotiate a bloom peer, with the given ping latency in milliseconds.
+    fn connect(&mut self, addr: PeerId, behavior: Behavior, latency: u64) {
+        self.peers.insert(addr, behavior);
+        self.mgr.received_event(
+            Event::PeerNegotiated {
+                addr,
+                link: Link::Outbound,
+                services: REQUIRED_SERVICES,
+                persistent: false,
+                height: self.tree.height(),
+                receiver: Address::new(&addr, ServiceFlags::NONE),
+                user_agent: String::new(),
+                version: PROTOCOL_VERSION,
+                relay: true,
+            },
+            &mut self.tree,
+        );
+        self.mgr.received_event(
+            Event::PeerLatencyUpdated {
+                addr,
+                latency: LocalDuration::from_millis(latency),
+            },
+            &mut self.tree,
+        );
+        self.step();
+    }
+
+    /// Disconnect a peer.
+    fn disconnect(&mut self, addr: PeerId) {
+        self.peers.remove(&addr);
+        self.pending.remove(&addr);
+        self.mgr.received_event(
+            Event::PeerDisconnected {
+                addr,
+                reason: DisconnectReason::PeerTimeout("test").into(),
+            },
+            &mut self.tree,
+        );
+        self.step();
+    }
+
+    /// Scan the given range with the given peers.
+    fn scan(&mut self, range: RangeInclusive<Height>, peers: Vec<PeerId>) {
+        self.mgr
+            .get_merkle_blocks(range, &self.tree, peers)
+            .unwrap();
+        self.step();
+    }
+
+    /// Have the honest peers serve their pending merkle blocks, in random order.
+    fn serve(&mut self) {
+        let mut blocks = Vec::new();
+
+        for (addr, heights) in self.pending.iter_mut() {
+            if self.peers.get(addr) == Some(&Behavior::Honest) {
+                blocks.extend(std::mem::take(heights).into_iter().map(|h| (*addr, h)));
+            }
+        }
+        self.rng.shuffle(&mut blocks);
+
+        for (from, height) in blocks {
+            let block =
+                MerkleBlock::from_block_with_predicate(&self.chain[height as usize], |_| false);
+
+            self.mgr.received_event(
+                Event::MessageReceived {
+                    from,
+                    message: Arc::new(NetworkMessage::MerkleBlock(block)),
+                },
+                &mut self.tree,
+            );
+            self.step();
+        }
+    }
+
+    /// Let the request timeout elapse.
+    fn elapse(&mut self) {
+        self.clock.elapse(REQUEST_TIMEOUT);
+        self.mgr.timer_expired(&self.tree);
+        self.step();
+    }
+
+    /// Run serve-and-timeout rounds until the scan completes. Returns whether it did.
+    fn run(&mut self) -> bool {
+        for _ in 0..MAX_ROUNDS {
+            self.serve();
+
+            if self.completed() > 0 {
+                return true;
+            }
+            self.elapse();
+        }
+        false
+    }
+
+    /// Process the manager's output, feeding disconnections back to it.
+    fn step(&mut self) {
+        while let Some(io) = self.mgr.next() {
+            match io {
+                Io::Write(addr, NetworkMessage::GetData(invs)) => {
+                    for inv in invs {
+                        let Inventory::FilteredBlock(hash) = inv else {
+                            continue;
+                        };
+                        let (height, _) = self.tree.get_block(&hash).unwrap();
+
+                        self.requested.entry(addr).or_default().push(height);
+                        self.pending.entry(addr).or_default().insert(height);
+                    }
+                }
+                Io::Event(Event::PeerLoadedBloomFilter { peer, segment, .. }) => {
+                    self.loaded.push((peer, segment));
+                }
+                Io::Event(event) => self.events.push(event),
+                Io::Disconnect(addr, _) => {
+                    self.disconnected.push(addr);
+                    self.disconnect(addr);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Heights of the merkle blocks delivered, in delivery order.
+    fn delivered(&self) -> Vec<Height> {
+        self.events
+            .iter()
+            .filter_map(|e| match e {
+                Event::ReceivedMerkleBlock { height, .. } => Some(*height),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Number of times the scan was reported completed.
+    fn completed(&self) -> usize {
+        self.events
+            .iter()
+            .filter(|e| matches!(e, Event::MerkleBlockScanCompleted))
+            .count()
+    }
+}
+
+/// Address of the `n`th synthetic peer, each in its own peer group.
+fn peer(n: u8) -> PeerId {
+    ([88, n, 1, 1], 8333).into()
+}
+
+#[quickcheck]
+fn prop_scan_delivered_in_order(seed: u64) -> bool {
+    let mut rng = fastrand::Rng::with_seed(seed);
+    let height = rng.usize(1..=CHUNK_SIZE * 2);
+    let mut sim = Sim::new(height as Height, 1, seed);
+
+    // At least one peer is honest, so that the scan can complete.
+    let count = rng.u8(1..=4);
+    let honest = rng.u8(0..count);
+    let mut stalling = Vec::new();
+
+    for n in 0..count {
+        let behavior = if n == honest || rng.bool() {
+            Behavior::Honest
+        } else {
+            stalling.push(peer(n));
+            Behavior::Stalling
+        };
+        sim.connect(peer(n), behavior, rng.u64(1..=500));
+    }
+    sim.scan(1..=height as Height, vec![]);
+
+    if !sim.run() {
+        return false;
+    }
+    let completed = sim
+        .events
+        .iter()
+        .position(|e| matches!(e, Event::MerkleBlockScanCompleted))
+        .unwrap();
+    let last_delivered = sim
+        .events
+        .iter()
+        .rposition(|e| matches!(e, Event::ReceivedMerkleBlock { .. }))
+        .unwrap();
+
+    // Every merkle block is delivered once, in order, before the scan is reported completed,
+    // and only stalling peers are disconnected.
+    sim.delivered() == (1..=height as Height).collect::<Vec<_>>()
+        && sim.completed() == 1
+        && last_delivered < completed
+        && sim.disconnected.iter().all(|p| stalling.contains(p))
+}
+
+#[quickcheck]
+fn prop_scan_progress(seed: u64) -> bool {
+    let mut rng = fastrand::Rng::with_seed(seed);
+    let height = rng.usize(1..=CHUNK_SIZE);
+    let mut sim = Sim::new(height as Height, 1, seed);
+
+    sim.connect(peer(1), Behavior::Honest, rng.u64(1..=500));
+    sim.connect(peer(2), Behavior::Honest, rng.u64(1..=500));
+    sim.scan(1..=height as Height, vec![]);
+
+    if !sim.run() {
+        return false;
+    }
+    let progress = sim
+        .events
+        .iter()
+        .filter_map(|e| match e {
+            Event::MerkleBlockScanProgress { current, total } => Some((*current, *total)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    // Progress is reported once per merkle block, and never goes backwards.
+    progress == (1..=height).map(|n| (n, height)).collect::<Vec<_>>()
+}
+
+#[test]
+fn test_retry_stalled_request() {
+    let mut sim = Sim::new(10, 1, 1);
+    let (a, b) = (peer(1), peer(2));
+
+    sim.connect(a, Behavior::Stalling, 10);
+    sim.connect(b, Behavior::Honest, 100);
+    sim.scan(1..=10, vec![a]);
+
+    assert_eq!(sim.requested.get(&a), Some(&(1..=10).collect()));
+    assert_eq!(sim.requested.get(&b), None);
+
+    // Once the request times out, it is retried with the other peer, and the stalling peer
+    // is kept, since it has retries left.
+    assert!(sim.run());
+    assert_eq!(sim.requested.get(&a), Some(&(1..=10).collect()));
+    assert_eq!(sim.requested.get(&b), Some(&(1..=10).collect()));
+    assert_eq!(sim.disconnected, vec![]);
+    assert_eq!(sim.delivered(), (1..=10).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_retries_exhausted() {
+    let mut sim = Sim::new(10, 1, 1);
+    let (a, b, c) = (peer(1), peer(2), peer(3));
+
+    sim.connect(a, Behavior::Stalling, 10);
+    sim.connect(b, Behavior::Stalling, 10);
+    sim.scan(1..=10, vec![a]);
+
+    // The request bounces between the stalling peers, each retry avoiding the peer that
+    // last stalled. Once its retries are exhausted, the peer it times out with is
+    // disconnected, as is every peer it times out with from then on.
+    for _ in 0..=MAX_REQUEST_RETRIES {
+        sim.elapse();
+    }
+    assert_eq!(sim.requested.get(&a).map(Vec::len), Some(20));
+    assert_eq!(sim.requested.get(&b).map(Vec::len), Some(20));
+    assert_eq!(sim.disconnected, vec![a]);
+    assert_eq!(sim.delivered(), vec![]);
+
+    // The request is finally served by the next peer to connect.
+    sim.connect(c, Behavior::Honest, 10);
+    assert!(sim.run());
+    assert_eq!(sim.requested.get(&c), Some(&(1..=10).collect()));
+    assert_eq!(sim.disconnected, vec![a, b]);
+    assert_eq!(sim.delivered(), (1..=10).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_retry_prefers_segment() {
+    let mut sim = Sim::new(10, 2, 1);
+    // `a` and `c` are in the same peer group, and are shown the same segment.
+    let a: net::SocketAddr = ([88, 88, 1, 1], 8333).into();
+    let b: net::SocketAddr = ([99, 99, 1, 1], 8333).into();
+    let c: net::SocketAddr = ([88, 88, 2, 2], 8333).into();
+
+    sim.connect(a, Behavior::Stalling, 10);
+    sim.connect(b, Behavior::Honest, 10);
+    sim.connect(c, Behavior::Honest, 500);
+    assert_eq!(sim.loaded, vec![(a, Some(0)), (b, Some(1)), (c, Some(0))]);
+
+    // The faster peer `b` is skipped, since it wasn't shown the segment the blocks were
+    // requested for.
+    sim.scan(1..=10, vec![a]);
+    assert!(sim.run());
+    assert_eq!(sim.requested.get(&b), None);
+    assert_eq!(sim.requested.get(&c), Some(&(1..=10).collect()));
+}
+
+#[test]
+fn test_reload_on_reconnect() {
+    let mut sim = Sim::new(10, 2, 1);
+    let (a, b) = (peer(1), peer(2));
+
+    sim.connect(a, Behavior::Honest, 10);
+    sim.connect(b, Behavior::Honest, 10);
+    sim.disconnect(a);
+    assert!(!sim.mgr.has_filter(&a));
+
+    // The peer is shown the same segment as before it disconnected.
+    sim.connect(a, Behavior::Honest, 10);
+    assert_eq!(sim.loaded, vec![(a, Some(0)), (b, Some(1)), (a, Some(0))]);
+    assert!(sim.mgr.has_filter(&a));
+}
+
+#[test]
+fn test_event_ordering() {
+    let mut sim = Sim::new(3, 1, 1);
+    let a = peer(1);
+
+    sim.connect(a, Behavior::Honest, 10);
+    sim.scan(1..=3, vec![a]);
+    assert!(sim.run());
+
+    let events = sim
+        .events
+        .iter()
+        .filter_map(|e| match e {
+            Event::MerkleBlockScanStarted { start, stop, .. } => {
+                Some(format!("started {}..={}", start, stop.unwrap()))
+            }
+            Event::ReceivedMerkleBlock { height, .. } => Some(format!("received {}", height)),
+            Event::MerkleBlockScanCompleted => Some(String::from("completed")),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        events,
+        vec![
+            "started 1..=3",
+            "received 1",
+            "received 2",
+            "received 3",
+            "completed"
+        ]
+    );
+}
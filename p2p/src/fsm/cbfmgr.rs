@@ -13,7 +13,7 @@ use nakamoto_common::bitcoin::network::constants::ServiceFlags;
 use nakamoto_common::bitcoin::network::message::NetworkMessage;
 use nakamoto_common::bitcoin::network::message_filter::{CFHeaders, CFilter, GetCFHeaders};
 use nakamoto_common::bitcoin::{Script, Transaction, Txid};
-use nakamoto_common::block::filter::{self, BlockFilter, Filters};
+use nakamoto_common::block::filter::{self, BlockFilter, Filters, BASIC_FILTER_TYPE};
 use nakamoto_common::block::time::{Clock, LocalDuration, LocalTime};
 use nakamoto_common::block::tree::BlockReader;
 use nakamoto_common::block::{BlockHash, Height};
@@ -283,7 +283,7 @@ impl<F: Filters, C: Clock> FilterManager<F, C> {
                         Ok(matches) => {
                             for (height, hash) in matches {
                                 if self.pending_blocks.insert(height) {
-                                    blocks.get_block(hash);
+                                    blocks.get_block(height, hash);
                                 }
                             }
                             // Filters being processed only updates our progress if there are no
@@ -360,7 +360,22 @@ impl<F: Filters, C: Clock> FilterManager<F, C> {
 
     /// Add scripts to the list of scripts to watch.
     pub fn watch(&mut self, scripts: Vec<Script>) {
-        self.rescan.watch.extend(scripts);
+        self.rescan.watch_scripts(scripts);
+    }
+
+    /// Remove scripts from the list of scripts to watch. Scripts watched more than once are
+    /// only removed once all their watchers have unwatched them.
+    pub fn unwatch(&mut self, scripts: Vec<Script>) {
+        let unwatched = self.rescan.unwatch_scripts(scripts);
+
+        if !unwatched.is_empty() {
+            log::debug!(target: "p2p", "Unwatched {} script(s)", unwatched.len());
+        }
+    }
+
+    /// Watched scripts, with their number of watchers.
+    pub fn watched(&self) -> Vec<(Script, usize)> {
+        self.rescan.watched()
     }
 
     /// Add transaction outputs to list of transactions to watch.
@@ -371,6 +386,19 @@ impl<F: Filters, C: Clock> FilterManager<F, C> {
         );
     }
 
+    /// Abort the rescan in progress, if any.
+    pub fn abort_rescan(&mut self) {
+        if self.rescan.active {
+            log::debug!(target: "p2p", "Rescan aborted at height {}", self.rescan.current);
+        }
+        self.rescan.abort();
+    }
+
+    /// Whether we're connected to any peer serving compact filters.
+    pub fn is_connected(&self) -> bool {
+        !self.peers.is_empty()
+    }
+
     /// Rescan compact block filters.
     pub fn rescan<T: BlockReader>(
         &mut self,
@@ -623,7 +651,7 @@ impl<F: Filters, C: Clock> FilterManager<F, C> {
             });
         }
 
-        if msg.filter_type != 0x0 {
+        if msg.filter_type != BASIC_FILTER_TYPE {
             return Err(Error::InvalidMessage {
                 from,
                 reason: "invalid `cfheaders` filter type",
@@ -730,7 +758,7 @@ impl<F: Filters, C: Clock> FilterManager<F, C> {
     ) -> Result<(), Error> {
         let from = *from;
 
-        if msg.filter_type != 0x0 {
+        if msg.filter_type != BASIC_FILTER_TYPE {
             return Err(Error::InvalidMessage {
                 from,
                 reason: "getcfheaders: invalid filter type",
@@ -785,7 +813,7 @@ impl<F: Filters, C: Clock> FilterManager<F, C> {
     ) -> Result<Vec<(Height, BlockHash)>, Error> {
         let from = *from;
 
-        if msg.filter_type != 0x0 {
+        if msg.filter_type != BASIC_FILTER_TYPE {
             return Err(Error::Ignored {
                 reason: "wrong filter type",
                 from,
@@ -1013,7 +1041,7 @@ mod tests {
     use nakamoto_test::block::gen;
     use nakamoto_test::BITCOIN_HEADERS;
 
-    use crate::fsm::output;
+    use crate::fsm::{output, RescanProgress};
 
     use super::*;
 
@@ -1063,7 +1091,7 @@ mod tests {
                 let filter = gen::cfilter(block);
 
                 CFilter {
-                    filter_type: 0x0,
+                    filter_type: BASIC_FILTER_TYPE,
                     block_hash,
                     filter: filter.content,
                 }
@@ -1081,7 +1109,7 @@ mod tests {
                 .collect::<Vec<_>>();
 
             CFHeaders {
-                filter_type: 0x0,
+                filter_type: BASIC_FILTER_TYPE,
                 stop_hash: tip,
                 previous_filter_header,
                 filter_hashes,
@@ -1176,7 +1204,7 @@ mod tests {
         // Import the headers.
         {
             let msg = CFHeaders {
-                filter_type: 0,
+                filter_type: BASIC_FILTER_TYPE,
                 stop_hash: BlockHash::from_hex(
                     "00000000b3322c8c3ef7d2cf6da009a776e6a99ee65ec5a32f3f345712238473",
                 )
@@ -1201,7 +1229,7 @@ mod tests {
             .iter()
             .zip(BITCOIN_HEADERS.iter())
             .map(|(f, h)| CFilter {
-                filter_type: 0x0,
+                filter_type: BASIC_FILTER_TYPE,
                 block_hash: h.block_hash(),
                 filter: f.to_vec(),
             });
@@ -1296,7 +1324,7 @@ mod tests {
         let (mut cbfmgr, tree, chain) = util::setup(network, best, 0, RefClock::from(time));
         let remote: PeerId = ([88, 88, 88, 88], 8333).into();
         let tip = tree.get_block_by_height(best).unwrap().block_hash();
-        let filter_type = 0x0;
+        let filter_type = BASIC_FILTER_TYPE;
         let previous_filter_header = FilterHeader::genesis(network);
         let filter_hashes = gen::cfheaders_from_blocks(previous_filter_header, chain.iter())
             .into_iter()
@@ -1357,7 +1385,7 @@ mod tests {
         let mut rng = fastrand::Rng::new();
         let time = LocalTime::now();
         let network = Network::Regtest;
-        let filter_type = 0x0;
+        let filter_type = BASIC_FILTER_TYPE;
         let (mut cbfmgr, tree, chain) = util::setup(network, best, 0, RefClock::from(time));
         let remote: PeerId = ([88, 88, 88, 88], 8333).into();
         let previous_filter_header = FilterHeader::genesis(network);
@@ -1433,6 +1461,59 @@ mod tests {
         assert_eq!(cbfmgr.rescan.current, current + 1);
     }
 
+    /// Test that an aborted rescan ignores the filters it requested.
+    #[test]
+    fn test_rescan_abort() {
+        let birth = 11;
+        let best = 42;
+        let mut rng = fastrand::Rng::new();
+        let time = LocalTime::now();
+        let network = Network::Regtest;
+        let (mut cbfmgr, tree, chain) = util::setup(network, best, 0, RefClock::from(time));
+        let remote: PeerId = ([88, 88, 88, 88], 8333).into();
+        let previous_filter_header = FilterHeader::genesis(network);
+        let cfheaders = util::cfheaders(previous_filter_header, &chain.tail);
+        let cfilters = util::cfilters(chain.iter()).collect::<Vec<_>>();
+
+        cbfmgr.filters.clear().unwrap();
+        cbfmgr.initialize(&tree);
+        cbfmgr.peer_negotiated(
+            remote,
+            best,
+            REQUIRED_SERVICES,
+            Link::Outbound,
+            false,
+            &tree,
+        );
+        cbfmgr
+            .received_cfheaders(&remote, cfheaders, &tree)
+            .unwrap();
+
+        cbfmgr.rescan(
+            Bound::Included(birth),
+            Bound::Included(best),
+            vec![gen::script(&mut rng)],
+            &tree,
+        );
+        assert_eq!(
+            cbfmgr.rescan.progress(),
+            Some(RescanProgress {
+                start: birth,
+                current: birth,
+                end: Some(best),
+            })
+        );
+        cbfmgr.abort_rescan();
+        assert_eq!(cbfmgr.rescan.progress(), None);
+
+        // Filters requested before the rescan was aborted are ignored.
+        cbfmgr
+            .received_cfilter(&remote, cfilters[birth as usize].clone(), &tree)
+            .unwrap();
+        assert_eq!(cbfmgr.rescan.current, birth);
+        assert!(cbfmgr.last_processed.is_none());
+    }
+
     /// Test that if we start with our cfheader chain behind our header
     /// chain, we immediately try to catch up.
     #[test]
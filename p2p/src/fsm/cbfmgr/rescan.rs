@@ -11,6 +11,7 @@ use nakamoto_common::block::{BlockHash, Height};
 use nakamoto_common::collections::{HashMap, HashSet};
 
 use super::{Event, FilterCache, HeightIterator, MAX_MESSAGE_CFILTERS};
+use crate::fsm::RescanProgress;
 
 /// Filter (re)scan state.
 #[derive(Debug, Default)]
@@ -31,6 +32,9 @@ pub struct Rescan {
     /// Transactions to watch for.
     pub transactions: HashMap<Txid, HashSet<Script>>,
 
+    /// Number of references to the scripts watched with [`Rescan::watch_scripts`]. These
+    /// scripts stay watched until all their references are removed, even across rescans.
+    refs: HashMap<Script, usize>,
     /// Filters requested and remaining to download.
     requested: BTreeSet<Height>,
     /// Received filters waiting to be matched.
@@ -59,10 +63,52 @@ impl Rescan {
         self.start = start;
         self.current = start;
         self.end = end;
-        self.watch = watch.into_iter().collect();
+        self.watch = watch.into_iter().chain(self.refs.keys().cloned()).collect();
         self.requested.clear();
     }
 
+    /// Watch the given scripts. Each call adds a reference to the scripts.
+    pub fn watch_scripts(&mut self, scripts: impl IntoIterator<Item = Script>) {
+        for script in scripts {
+            *self.refs.entry(script.clone()).or_default() += 1;
+            self.watch.insert(script);
+        }
+    }
+
+    /// Remove a reference to each of the given scripts. Scripts are no longer watched once
+    /// they have no references left. Returns the scripts that are no longer watched.
+    pub fn unwatch_scripts(&mut self, scripts: impl IntoIterator<Item = Script>) -> Vec<Script> {
+        let mut unwatched = Vec::new();
+
+        for script in scripts {
+            match self.refs.get_mut(&script) {
+                Some(refs) if *refs > 1 => {
+                    *refs -= 1;
+                }
+                _ => {
+                    self.refs.remove(&script);
+
+                    if self.watch.remove(&script) {
+                        unwatched.push(script);
+                    }
+                }
+            }
+        }
+        unwatched
+    }
+
+    /// Watched scripts, with their number of references. Scripts that were only given to
+    /// a rescan have no references.
+    pub fn watched(&self) -> Vec<(Script, usize)> {
+        let mut watched = self
+            .watch
+            .iter()
+            .map(|s| (s.clone(), self.refs.get(s).copied().unwrap_or_default()))
+            .collect::<Vec<_>>();
+        watched.sort();
+        watched
+    }
+
     /// Return info string on rescan state.
     #[cfg(not(test))]
     pub fn info(&self) -> String {
@@ -81,6 +127,23 @@ impl Rescan {
         self.requested.clear();
     }
 
+    /// Abort the rescan. Filters requested are ignored when received, and filters waiting to
+    /// be matched are dropped. The watch list is kept.
+    pub fn abort(&mut self) {
+        self.active = false;
+        self.requested.clear();
+        self.received.clear();
+    }
+
+    /// Progress of the rescan, if it's in progress.
+    pub fn progress(&self) -> Option<RescanProgress> {
+        self.active.then_some(RescanProgress {
+            start: self.start,
+            current: self.current,
+            end: self.end,
+        })
+    }
+
     /// Rollback state to height.
     pub fn rollback(&mut self, to: Height) {
         self.cache.rollback(to)
@@ -259,4 +322,29 @@ mod tests {
             vec![0..=3, 7..=8, 10..=11, 15..=16]
         );
     }
+
+    #[test]
+    fn test_watch_refs() {
+        let mut rescan = Rescan::default();
+        let a = Script::from(vec![1]);
+        let b = Script::from(vec![2]);
+        let c = Script::from(vec![3]);
+
+        rescan.watch_scripts([a.clone(), b.clone()]);
+        rescan.watch_scripts([a.clone()]);
+        assert_eq!(rescan.watched(), vec![(a.clone(), 2), (b.clone(), 1)]);
+
+        // Watched scripts are kept across rescans.
+        rescan.restart(0, None, [c.clone()]);
+        assert_eq!(
+            rescan.watched(),
+            vec![(a.clone(), 2), (b.clone(), 1), (c.clone(), 0)]
+        );
+
+        assert!(rescan.unwatch_scripts([a.clone()]).is_empty());
+        assert_eq!(rescan.unwatch_scripts([a.clone(), b.clone()]), vec![a, b]);
+        assert_eq!(rescan.unwatch_scripts([c.clone()]), vec![c]);
+        assert!(rescan.watched().is_empty());
+        assert!(rescan.watch.is_empty());
+    }
 }
@@ -0,0 +1,379 @@
+//! Block download scheduling.
+//!
+//! Blocks are requested within a moving window: no more than a fixed number of blocks are in
+//! flight at any time, spread across peers, lowest heights first. Requests that aren't
+//! fulfilled in time are re-assigned to another peer, and blocks received out of order are
+//! held back until the blocks below them have arrived, so that they are delivered in order.
+use std::collections::{BTreeMap, BTreeSet};
+
+use nakamoto_common::bitcoin::BlockHash;
+use nakamoto_common::block::time::{LocalDuration, LocalTime};
+use nakamoto_common::block::Height;
+use nakamoto_common::collections::HashMap;
+
+use super::PeerId;
+
+/// Default number of blocks in flight at any time.
+pub const DEFAULT_WINDOW: usize = 16;
+
+/// Holds items received out of order, until all expected items below them have arrived.
+#[derive(Debug)]
+pub struct Reorder<T> {
+    /// Heights expected and not yet received.
+    expected: BTreeSet<Height>,
+    /// Items received, waiting for the expected items below them.
+    received: BTreeMap<Height, T>,
+}
+
+impl<T> Default for Reorder<T> {
+    fn default() -> Self {
+        Self {
+            expected: BTreeSet::new(),
+            received: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> Reorder<T> {
+    /// Expect an item at the given height. Returns `false` if it's already expected, or
+    /// was received and is waiting to be delivered.
+    pub fn expect(&mut self, height: Height) -> bool {
+        !self.received.contains_key(&height) && self.expected.insert(height)
+    }
+
+    /// Check whether an item at the given height is expected.
+    pub fn is_expected(&self, height: Height) -> bool {
+        self.expected.contains(&height)
+    }
+
+    /// Stop expecting an item at the given height, and drop it if it was already received.
+    /// Returns the items that can now be delivered, in order.
+    pub fn cancel(&mut self, height: Height) -> Vec<(Height, T)> {
+        self.expected.remove(&height);
+        self.received.remove(&height);
+        self.deliverable()
+    }
+
+    /// Check whether an item at the given height is expected, or was received and is waiting
+    /// to be delivered.
+    pub fn is_pending(&self, height: Height) -> bool {
+        self.expected.contains(&height) || self.received.contains_key(&height)
+    }
+
+    /// Record an item received. Returns the items that can now be delivered, in order, or
+    /// `None` if the item wasn't expected.
+    pub fn received(&mut self, height: Height, item: T) -> Option<Vec<(Height, T)>> {
+        if !self.expected.remove(&height) {
+            return None;
+        }
+        self.received.insert(height, item);
+
+        Some(self.deliverable())
+    }
+
+    /// Check whether nothing is expected or waiting to be delivered.
+    pub fn is_empty(&self) -> bool {
+        self.expected.is_empty() && self.received.is_empty()
+    }
+
+    /// Number of items received, waiting for the expected items below them.
+    pub fn waiting(&self) -> usize {
+        self.received.len()
+    }
+
+    fn deliverable(&mut self) -> Vec<(Height, T)> {
+        let mut items = Vec::new();
+
+        while let Some(entry) = self.received.first_entry() {
+            if self.expected.first().map_or(false, |h| h < entry.key()) {
+                break;
+            }
+            items.push(entry.remove_entry());
+        }
+        items
+    }
+}
+
+/// A block request sent to a peer.
+#[derive(Debug, Copy, Clone)]
+struct Request {
+    /// Height of the requested block.
+    height: Height,
+    /// Peer the block was requested from.
+    peer: PeerId,
+    /// Time at which the request was sent.
+    sent_at: LocalTime,
+}
+
+/// Block download scheduler.
+#[derive(Debug)]
+pub struct Download<T> {
+    /// Maximum number of blocks in flight.
+    window: usize,
+    /// How long to wait for a block before requesting it from another peer.
+    timeout: LocalDuration,
+    /// Blocks waiting to be requested, by height.
+    queue: BTreeMap<Height, BlockHash>,
+    /// Blocks requested and not yet received.
+    inflight: HashMap<BlockHash, Request>,
+    /// Peers that didn't respond in time to a block request.
+    stalled: HashMap<BlockHash, Vec<PeerId>>,
+    /// Blocks received, waiting to be delivered in order.
+    order: Reorder<T>,
+}
+
+impl<T> Download<T> {
+    /// Create a new download scheduler.
+    pub fn new(window: usize, timeout: LocalDuration, rng: fastrand::Rng) -> Self {
+        Self {
+            window,
+            timeout,
+            queue: BTreeMap::new(),
+            inflight: HashMap::with_hasher(rng.clone().into()),
+            stalled: HashMap::with_hasher(rng.into()),
+            order: Reorder::default(),
+        }
+    }
+
+    /// Queue a block to be downloaded. Returns `false` if it's already being downloaded.
+    pub fn get(&mut self, height: Height, hash: BlockHash) -> bool {
+        if self.inflight.contains_key(&hash) || !self.order.expect(height) {
+            return false;
+        }
+        self.queue.insert(height, hash);
+
+        true
+    }
+
+    /// Assign queued blocks to the given peers, lowest heights first, until the window is
+    /// full. Each block is given to the peer with the fewest blocks in flight, avoiding
+    /// peers that already failed to deliver it. Returns the blocks to request from each peer.
+    pub fn schedule(&mut self, peers: &[PeerId], now: LocalTime) -> Vec<(PeerId, Vec<BlockHash>)> {
+        let mut requests: Vec<(PeerId, Vec<BlockHash>)> = Vec::new();
+
+        while self.inflight.len() < self.window {
+            let Some((height, hash)) = self.queue.pop_first() else {
+                break;
+            };
+            let stalled = self.stalled.get(&hash);
+            let peer = peers.iter().min_by_key(|p| {
+                let stalled = stalled.map_or(false, |s| s.contains(p));
+                let load = self.inflight.values().filter(|r| r.peer == **p).count();
+
+                (stalled, load)
+            });
+            let Some(peer) = peer.copied() else {
+                self.queue.insert(height, hash);
+                break;
+            };
+            self.inflight.insert(
+                hash,
+                Request {
+                    height,
+                    peer,
+                    sent_at: now,
+                },
+            );
+
+            match requests.iter_mut().find(|(p, _)| *p == peer) {
+                Some((_, hashes)) => hashes.push(hash),
+                None => requests.push((peer, vec![hash])),
+            }
+        }
+        requests
+    }
+
+    /// Queue requests that weren't fulfilled within the timeout again, so that they are
+    /// assigned to other peers. Returns the peers that timed out.
+    pub fn timed_out(&mut self, now: LocalTime) -> Vec<PeerId> {
+        let timeout = self.timeout;
+        let expired = self
+            .inflight
+            .iter()
+            .filter(|(_, r)| now - r.sent_at >= timeout)
+            .map(|(hash, r)| (*hash, *r))
+            .collect::<Vec<_>>();
+        let mut peers = Vec::new();
+
+        for (hash, req) in expired {
+            self.inflight.remove(&hash);
+            self.queue.insert(req.height, hash);
+            self.stalled.entry(hash).or_default().push(req.peer);
+
+            if !peers.contains(&req.peer) {
+                peers.push(req.peer);
+            }
+        }
+        peers
+    }
+
+    /// Queue the blocks requested from a peer again, eg. after it disconnected.
+    pub fn peer_disconnected(&mut self, peer: &PeerId) {
+        let queue = &mut self.queue;
+
+        self.inflight.retain(|hash, req| {
+            if req.peer == *peer {
+                queue.insert(req.height, *hash);
+                return false;
+            }
+            true
+        });
+    }
+
+    /// Record a block received. Returns the blocks that can now be delivered, in order, or
+    /// `None` if the block wasn't requested.
+    pub fn received(&mut self, hash: &BlockHash, item: T) -> Option<Vec<(Height, T)>> {
+        let height = if let Some(req) = self.inflight.remove(hash) {
+            req.height
+        } else if let Some(height) = self.queued(hash) {
+            self.queue.remove(&height);
+            height
+        } else {
+            return None;
+        };
+        self.stalled.remove(hash);
+        self.order.received(height, item)
+    }
+
+    /// Stop downloading the block at the given height, eg. because it was reverted.
+    /// Returns the blocks that can now be delivered, in order.
+    pub fn cancel(&mut self, height: Height) -> Vec<(Height, T)> {
+        if let Some(hash) = self.queue.remove(&height) {
+            self.stalled.remove(&hash);
+        }
+        let stalled = &mut self.stalled;
+
+        self.inflight.retain(|hash, req| {
+            if req.height == height {
+                stalled.remove(hash);
+                return false;
+            }
+            true
+        });
+        self.order.cancel(height)
+    }
+
+    /// Check whether there are no blocks left to download or deliver.
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// Number of blocks queued or in flight.
+    pub fn requested(&self) -> usize {
+        self.queue.len() + self.inflight.len()
+    }
+
+    /// Number of blocks received, waiting for the blocks below them.
+    pub fn waiting(&self) -> usize {
+        self.order.waiting()
+    }
+
+    fn queued(&self, hash: &BlockHash) -> Option<Height> {
+        self.queue
+            .iter()
+            .find(|(_, h)| *h == hash)
+            .map(|(height, _)| *height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nakamoto_common::bitcoin_hashes::Hash as _;
+
+    fn hash(height: Height) -> BlockHash {
+        let mut bytes = [0; 32];
+        bytes[..8].copy_from_slice(&height.to_le_bytes());
+
+        BlockHash::from_inner(bytes)
+    }
+
+    #[test]
+    fn test_reorder() {
+        let mut order = Reorder::default();
+
+        for height in 1..=3 {
+            assert!(order.expect(height));
+        }
+        assert!(!order.expect(2));
+
+        // Unexpected items aren't held back.
+        assert_eq!(order.received(9, 'x'), None);
+
+        assert_eq!(order.received(3, 'c'), Some(vec![]));
+        assert!(!order.expect(3), "Item is waiting to be delivered");
+        assert_eq!(order.received(1, 'a'), Some(vec![(1, 'a')]));
+        assert_eq!(order.waiting(), 1);
+
+        // Once the missing item is no longer expected, the items above it are delivered.
+        assert_eq!(order.cancel(2), vec![(3, 'c')]);
+        assert!(order.is_empty());
+    }
+
+    #[test]
+    fn test_window() {
+        let now = LocalTime::now();
+        let a: PeerId = ([88, 88, 88, 88], 8333).into();
+        let b: PeerId = ([99, 99, 99, 99], 8333).into();
+        let mut download = Download::new(4, LocalDuration::from_secs(15), fastrand::Rng::new());
+
+        for height in (1..=6).rev() {
+            assert!(download.get(height, hash(height)));
+        }
+        assert!(!download.get(1, hash(1)));
+
+        // The lowest heights are requested first, spread across peers.
+        let requests = download.schedule(&[a, b], now);
+        assert_eq!(
+            requests,
+            vec![(a, vec![hash(1), hash(3)]), (b, vec![hash(2), hash(4)])]
+        );
+        assert!(download.schedule(&[a, b], now).is_empty(), "Window is full");
+
+        // Blocks are delivered in order, and free up room in the window.
+        assert_eq!(download.received(&hash(2), 2), Some(vec![]));
+        assert_eq!(download.received(&hash(1), 1), Some(vec![(1, 1), (2, 2)]));
+        assert_eq!(download.received(&hash(1), 1), None);
+        assert_eq!(
+            download.schedule(&[a, b], now),
+            vec![(a, vec![hash(5)]), (b, vec![hash(6)])]
+        );
+        assert_eq!(download.requested(), 4);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let now = LocalTime::now();
+        let timeout = LocalDuration::from_secs(15);
+        let a: PeerId = ([88, 88, 88, 88], 8333).into();
+        let b: PeerId = ([99, 99, 99, 99], 8333).into();
+        let mut download = Download::new(DEFAULT_WINDOW, timeout, fastrand::Rng::new());
+
+        download.get(1, hash(1));
+        download.get(2, hash(2));
+        assert_eq!(
+            download.schedule(&[a], now),
+            vec![(a, vec![hash(1), hash(2)])]
+        );
+        assert!(download.timed_out(now).is_empty());
+
+        // Blocks that timed out are re-assigned to another peer.
+        assert_eq!(download.timed_out(now + timeout), vec![a]);
+        assert_eq!(
+            download.schedule(&[a, b], now + timeout),
+            vec![(b, vec![hash(1), hash(2)])]
+        );
+
+        // Blocks requested from a disconnected peer are queued again.
+        download.peer_disconnected(&b);
+        assert_eq!(
+            download.schedule(&[a], now + timeout),
+            vec![(a, vec![hash(1), hash(2)])]
+        );
+
+        // Blocks above a cancelled block are delivered.
+        assert_eq!(download.received(&hash(2), 2), Some(vec![]));
+        assert_eq!(download.cancel(1), vec![(2, 2)]);
+        assert!(download.is_empty());
+    }
+}
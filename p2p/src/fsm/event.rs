@@ -6,16 +6,18 @@ use nakamoto_common::bitcoin::network::address::Address;
 use nakamoto_common::bitcoin::network::constants::ServiceFlags;
 use nakamoto_common::bitcoin::network::message::NetworkMessage;
 use nakamoto_common::bitcoin::network::message_bloom::FilterLoad;
-use nakamoto_common::bitcoin::{MerkleBlock, Transaction, Txid};
+use nakamoto_common::bitcoin::network::message_network::RejectReason;
+use nakamoto_common::bitcoin::{DSProofId, MerkleBlock, OutPoint, Transaction, Txid};
 use nakamoto_common::block::filter::BlockFilter;
 use nakamoto_common::block::{Block, BlockHash, BlockHeader, Height};
+use nakamoto_common::bloom::store::cache::PrivacySegment;
 use nakamoto_common::nonempty::NonEmpty;
 use nakamoto_common::p2p::peer::Source;
 use nakamoto_net::Disconnect;
 
 use crate::fsm;
 use crate::fsm::fees::FeeEstimate;
-use crate::fsm::{Link, LocalTime, PeerId};
+use crate::fsm::{Link, LocalDuration, LocalTime, PeerId, ScanMethod};
 
 /// Event emitted by the client, after the "loading" phase is over.
 #[derive(Debug, Clone)]
@@ -38,6 +40,8 @@ pub enum Event {
         filter: FilterLoad,
         /// Peer address.
         peer: PeerId,
+        /// Privacy segment of the filter, if any.
+        segment: Option<u32>,
     },
 
     /// Peer connected. This is fired when the physical TCP/IP connection
@@ -109,6 +113,13 @@ pub enum Event {
         /// Best block height known.
         height: Height,
     },
+    /// A peer's average ping latency was updated.
+    PeerLatencyUpdated {
+        /// Peer address.
+        addr: PeerId,
+        /// Average round-trip latency.
+        latency: LocalDuration,
+    },
     /// A peer misbehaved.
     PeerMisbehaved {
         /// Peer address.
@@ -156,9 +167,23 @@ pub enum Event {
         height: Height,
         /// Matching block.
         merkle_block: MerkleBlock,
+        /// Transactions matched by the block's partial merkle tree, which was verified
+        /// against the header's merkle root.
+        matches: Vec<Txid>,
         /// the peer who sent us the block
         peer: PeerId,
     },
+    /// Block header sync progress. Emitted periodically while catching up to the best
+    /// height known amongst peers, and once more when caught up.
+    Synchronizing {
+        /// Height of our chain tip.
+        current: Height,
+        /// Best height known amongst peers.
+        best: Height,
+        /// Block headers imported per second, since the previous progress event.
+        /// Zero for the first event, since there is nothing to compare to.
+        rate_headers_per_sec: f64,
+    },
     /// Block header chain is in sync with network.
     BlockHeadersSynced {
         /// Block height.
@@ -241,6 +266,22 @@ pub enum Event {
         /// Stop height.
         height: Height,
     },
+    /// A privacy segment was rebuilt, or scanned further. This is emitted periodically
+    /// while scanning, so that the segment's sync state can be checkpointed.
+    PrivacySegmentUpdated {
+        /// The updated segment.
+        segment: PrivacySegment,
+    },
+    /// Bloom filters were rebuilt larger and reloaded onto peers, since the false-positive
+    /// rate observed on peers drifted too far from the target.
+    BloomFilterResized {
+        /// False-positive rate observed, over the transactions scanned.
+        fp_rate: f64,
+        /// Target false-positive rate.
+        target: f64,
+        /// Total size of the new filters, in bytes.
+        size: usize,
+    },
     /// A merkle block rescan has stopped.
     MerkleBlockRescanStopped {
         /// Stop height.
@@ -248,6 +289,25 @@ pub enum Event {
         /// peer
         peer: PeerId,
     },
+    /// A requested merkle block was received.
+    MerkleBlockScanProgress {
+        /// Merkle blocks received since the scan started.
+        current: usize,
+        /// Merkle blocks requested since the scan started.
+        total: usize,
+    },
+    /// All requested merkle blocks were received.
+    MerkleBlockScanCompleted,
+    /// A trusted snapshot was imported. Privacy segments are considered scanned up to its
+    /// height.
+    SnapshotImported {
+        /// Height of the snapshot block.
+        height: Height,
+        /// Hash of the snapshot block.
+        hash: BlockHash,
+        /// Number of unspent outputs in the snapshot.
+        utxos: usize,
+    },
     /// A merkle block rescan has started.
     MerkleBlockScanStarted {
         /// Start height.
@@ -269,16 +329,65 @@ pub enum Event {
         /// The new transaction status.
         status: TxStatus,
     },
+    /// A peer rejected one of our transactions, eg. because its fee is too low, or it is
+    /// non-standard. The transaction is no longer announced to that peer.
+    TxRejected {
+        /// The rejected transaction.
+        txid: Txid,
+        /// Peer rejecting the transaction.
+        peer: PeerId,
+        /// Rejection code.
+        code: RejectReason,
+        /// Rejection reason given by the peer.
+        reason: String,
+    },
+    /// An unconfirmed transaction spending the same outputs as another unconfirmed
+    /// transaction was received. Payments made by either transaction may never confirm.
+    DoubleSpendDetected {
+        /// The transaction seen first.
+        original: Txid,
+        /// The transaction spending some of the same outputs as the original.
+        conflicting: Txid,
+    },
+    /// A valid proof that one of our unconfirmed transactions is double-spent was received.
+    /// Payments made by the transaction should be considered at risk.
+    DoubleSpendProofReceived {
+        /// The transaction being double-spent.
+        txid: Txid,
+        /// The output spent twice.
+        outpoint: OutPoint,
+        /// The proof identifier.
+        proof: DSProofId,
+    },
     /// A matched transaction was receiced.
     ReceivedMatchedTx {
         /// The Transaction.
         transaction: Transaction,
+        /// Proof of the transaction's inclusion in a block, if it was matched by a
+        /// merkle block.
+        inclusion: Option<TxInclusion>,
     },
     /// Scanned the chain up to a certain height.
     Scanned {
         /// Height up to which we've scanned and processed blocks.
         height: Height,
     },
+    /// The hybrid scan switched methods, and carries on from the given height with the new one.
+    ScanMethodChanged {
+        /// Height the new method scans from.
+        height: Height,
+        /// Method the rest of the chain is scanned with.
+        method: ScanMethod,
+    },
+    /// The hybrid scan matched a block. Each block is reported once, whichever method found it.
+    ScanMatched {
+        /// Block height.
+        height: Height,
+        /// Block hash.
+        hash: BlockHash,
+        /// Method that found the match.
+        method: ScanMethod,
+    },
     /// A gossip message was received from a peer.
     MessageReceived {
         /// Peer that sent the message.
@@ -288,6 +397,22 @@ pub enum Event {
     },
     /// Address book exhausted.
     AddressBookExhausted,
+    /// We entered or left initial block header sync, changing the number of outbound peers
+    /// we connect to.
+    OutboundTargetChanged {
+        /// Whether we're in initial sync.
+        syncing: bool,
+        /// Target number of outbound peers.
+        target: usize,
+    },
+    /// Fewer outbound peers advertising `NODE_BLOOM` than the configured minimum are
+    /// connected, and no more could be found in the address book.
+    BloomPeersUnavailable {
+        /// Bloom peers connected, or being connected to.
+        available: usize,
+        /// Configured minimum.
+        minimum: usize,
+    },
     /// An error occured.
     Error {
         /// Error source.
@@ -302,20 +427,72 @@ impl fmt::Display for Event {
                 write!(fmt, "Initializing peer-to-peer system..")
             }
 
-            Self::PeerLoadedBloomFilter { filter, peer } => {
-                _ = filter;
-                write!(fmt, "Bloom filter loaded to peer {}", peer)
-            }
+            Self::PeerLoadedBloomFilter { peer, segment, .. } => match segment {
+                Some(segment) => {
+                    write!(
+                        fmt,
+                        "Bloom filter segment {} loaded to peer {}",
+                        segment, peer
+                    )
+                }
+                None => write!(fmt, "Bloom filter loaded to peer {}", peer),
+            },
             Self::MerkleBlockScanStarted { start, .. } => {
                 write!(fmt, "A merkle block scan started at height {start}")
             }
             Self::MerkleBlockRescanStopped { height, .. } => {
                 write!(fmt, "A merkle block scan stopped {height}")
             }
+            Self::MerkleBlockScanProgress { current, total } => {
+                write!(fmt, "Merkle block scan progress {current}/{total}")
+            }
+            Self::MerkleBlockScanCompleted => {
+                write!(fmt, "Merkle block scan completed")
+            }
+            Self::SnapshotImported {
+                height,
+                hash,
+                utxos,
+            } => {
+                write!(
+                    fmt,
+                    "Imported snapshot at block {} (height = {}) with {} output(s)",
+                    hash, height, utxos
+                )
+            }
+            Self::PrivacySegmentUpdated { segment } => {
+                write!(
+                    fmt,
+                    "Privacy segment {} synced up to height {}",
+                    segment.segment, segment.synced_height
+                )
+            }
+            Self::BloomFilterResized {
+                fp_rate,
+                target,
+                size,
+            } => {
+                write!(
+                    fmt,
+                    "Bloom filters resized to {} byte(s), as their false-positive rate of {:.5} \
+                    drifted from the target of {}",
+                    size, fp_rate, target
+                )
+            }
 
             Self::Ready { .. } => {
                 write!(fmt, "Ready to process events and commands")
             }
+            Self::Synchronizing {
+                current,
+                best,
+                rate_headers_per_sec,
+            } => {
+                write!(
+                    fmt,
+                    "Syncing block headers {current}/{best} ({rate_headers_per_sec:.1} headers/s)"
+                )
+            }
             Self::BlockHeadersSynced { height, hash } => {
                 write!(
                     fmt,
@@ -324,12 +501,15 @@ impl fmt::Display for Event {
             }
             Self::ReceivedMerkleBlock {
                 height,
-                merkle_block,
+                matches,
                 peer,
+                ..
             } => {
-                _ = merkle_block;
-                // let hash = merkle_block.header.block_hash();
-                write!(fmt, "MerkleBlock received at height {height} from {peer}")
+                write!(
+                    fmt,
+                    "MerkleBlock received at height {height} from {peer} ({} match(es))",
+                    matches.len()
+                )
             }
             Self::BlockFilterImported {
                 hash,
@@ -419,6 +599,12 @@ impl fmt::Display for Event {
                 write!(fmt, "Transaction {} status changed: {}", txid, status)
             }
             Self::Scanned { height, .. } => write!(fmt, "Chain scanned up to height {height}"),
+            Self::ScanMethodChanged { height, method } => {
+                write!(fmt, "Scanning with {} from height {}", method, height)
+            }
+            Self::ScanMatched { height, method, .. } => {
+                write!(fmt, "Block matched at height {} with {}", height, method)
+            }
             Self::PeerConnected { addr, link, .. } => {
                 write!(fmt, "Peer {} connected ({:?})", &addr, link)
             }
@@ -432,9 +618,57 @@ impl fmt::Display for Event {
             Self::PeerHeightUpdated { height } => {
                 write!(fmt, "Peer height updated to {}", height)
             }
-            Self::ReceivedMatchedTx { transaction } => {
+            Self::TxRejected {
+                txid,
+                peer,
+                code,
+                reason,
+            } => {
+                write!(
+                    fmt,
+                    "Transaction {} was rejected by peer {}: {} ({:?})",
+                    txid, peer, reason, code
+                )
+            }
+            Self::DoubleSpendDetected {
+                original,
+                conflicting,
+            } => {
+                write!(
+                    fmt,
+                    "Transaction {} is double-spent by transaction {}",
+                    original, conflicting
+                )
+            }
+            Self::DoubleSpendProofReceived {
+                txid,
+                outpoint,
+                proof,
+            } => {
+                write!(
+                    fmt,
+                    "Double-spend proof {} received for transaction {} (outpoint {})",
+                    proof, txid, outpoint
+                )
+            }
+            Self::ReceivedMatchedTx {
+                transaction,
+                inclusion: Some(inclusion),
+            } => {
+                write!(
+                    fmt,
+                    "Received transaction match {} included in block {} at height {}",
+                    transaction.txid(),
+                    inclusion.block,
+                    inclusion.height
+                )
+            }
+            Self::ReceivedMatchedTx { transaction, .. } => {
                 write!(fmt, "Received transaction match {}", transaction.txid())
             }
+            Self::PeerLatencyUpdated { addr, latency } => {
+                write!(fmt, "Peer {addr} latency is {latency}")
+            }
             Self::PeerMisbehaved { addr, reason } => {
                 write!(fmt, "Peer {addr} misbehaved: {reason}")
             }
@@ -466,6 +700,16 @@ impl fmt::Display for Event {
                     "Address book exhausted.. fetching new addresses from peers"
                 )
             }
+            Self::OutboundTargetChanged { syncing, target } => {
+                let phase = if *syncing { "initial sync" } else { "synced" };
+                write!(fmt, "Outbound peer target is now {target} ({phase})")
+            }
+            Self::BloomPeersUnavailable { available, minimum } => {
+                write!(
+                    fmt,
+                    "Only {available} out of a minimum of {minimum} bloom peers available"
+                )
+            }
             Self::Error { error } => {
                 write!(fmt, "Error: {error}")
             }
@@ -473,6 +717,88 @@ impl fmt::Display for Event {
     }
 }
 
+/// Category of an [`Event`], used to subscribe to some events only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Category {
+    /// Client lifecycle, eg. [`Event::Ready`] and [`Event::Error`].
+    Client,
+    /// Block header sync and chain updates, eg. [`Event::BlockHeadersImported`].
+    Headers,
+    /// Full and merkle blocks, and chain scans, eg. [`Event::BlockMatched`].
+    Blocks,
+    /// Peer connections and messages, eg. [`Event::PeerNegotiated`].
+    Peers,
+    /// Compact filters and privacy segments, eg. [`Event::FilterProcessed`].
+    Filters,
+    /// Transactions and fees, eg. [`Event::TxStatusChanged`].
+    Transactions,
+}
+
+impl Event {
+    /// The category of the event.
+    pub fn category(&self) -> Category {
+        match self {
+            Self::Initializing | Self::Ready { .. } | Self::Error { .. } => Category::Client,
+            Self::Synchronizing { .. }
+            | Self::BlockHeadersSynced { .. }
+            | Self::BlockHeadersImported { .. }
+            | Self::BlockConnected { .. }
+            | Self::BlockDisconnected { .. } => Category::Headers,
+            Self::BlockProcessed { .. }
+            | Self::BlockMatched { .. }
+            | Self::ReceivedMerkleBlock { .. }
+            | Self::MerkleBlockScanStarted { .. }
+            | Self::MerkleBlockScanProgress { .. }
+            | Self::MerkleBlockRescanStopped { .. }
+            | Self::MerkleBlockScanCompleted
+            | Self::SnapshotImported { .. }
+            | Self::Scanned { .. }
+            | Self::ScanMethodChanged { .. }
+            | Self::ScanMatched { .. } => Category::Blocks,
+            Self::PeerLoadedBloomFilter { .. }
+            | Self::PeerConnected { .. }
+            | Self::PeerConnecting { .. }
+            | Self::PeerDisconnected { .. }
+            | Self::PeerTimedOut { .. }
+            | Self::PeerConnectionFailed { .. }
+            | Self::PeerNegotiated { .. }
+            | Self::PeerHeightUpdated { .. }
+            | Self::PeerLatencyUpdated { .. }
+            | Self::PeerMisbehaved { .. }
+            | Self::MessageReceived { .. }
+            | Self::AddressBookExhausted
+            | Self::OutboundTargetChanged { .. }
+            | Self::BloomPeersUnavailable { .. } => Category::Peers,
+            Self::BlockFilterImported { .. }
+            | Self::FilterProcessed { .. }
+            | Self::FilterReceived { .. }
+            | Self::FilterRescanStarted { .. }
+            | Self::FilterRescanStopped { .. }
+            | Self::FilterHeadersSynced { .. }
+            | Self::PrivacySegmentUpdated { .. }
+            | Self::BloomFilterResized { .. } => Category::Filters,
+            Self::FeeEstimated { .. }
+            | Self::TxStatusChanged { .. }
+            | Self::TxRejected { .. }
+            | Self::DoubleSpendDetected { .. }
+            | Self::DoubleSpendProofReceived { .. }
+            | Self::ReceivedMatchedTx { .. } => Category::Transactions,
+        }
+    }
+}
+
+/// Proof that a transaction was included in a block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxInclusion {
+    /// Height of the block including the transaction.
+    pub height: Height,
+    /// Hash of the block including the transaction.
+    pub block: BlockHash,
+    /// Merkle block proving the transaction's inclusion. Its partial merkle tree was
+    /// verified against the block header.
+    pub proof: MerkleBlock,
+}
+
 /// Transaction status of a given transaction.
 #[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq)]
 pub enum TxStatus {
@@ -487,6 +813,14 @@ pub enum TxStatus {
         /// Peer acknowledging the transaction.
         peer: net::SocketAddr,
     },
+    /// Transaction was announced to us by a peer, after we broadcast it.
+    ///
+    /// Peers only relay transactions they accepted into their mempool, so unlike
+    /// [`TxStatus::Acknowledged`], this means the transaction is considered valid by the peer.
+    Relayed {
+        /// Peer relaying the transaction.
+        peer: net::SocketAddr,
+    },
     /// Transaction was included in a block. This event is fired after
     /// a block from the main chain is scanned.
     Confirmed {
@@ -521,6 +855,9 @@ impl fmt::Display for TxStatus {
             Self::Acknowledged { peer } => {
                 write!(fmt, "transaction was acknowledged by peer {}", peer)
             }
+            Self::Relayed { peer } => {
+                write!(fmt, "transaction was relayed by peer {}", peer)
+            }
             Self::Confirmed { height, block } => write!(
                 fmt,
                 "transaction was included in block {} at height {}",
@@ -544,6 +881,20 @@ mod test {
     use nakamoto_common::bitcoin_hashes::Hash;
     use nakamoto_test::block::gen;
 
+    #[test]
+    fn test_category() {
+        assert_eq!(Event::Initializing.category(), Category::Client);
+        assert_eq!(
+            Event::TxStatusChanged {
+                txid: Txid::all_zeros(),
+                status: TxStatus::Unconfirmed,
+            }
+            .category(),
+            Category::Transactions
+        );
+        assert_eq!(Event::AddressBookExhausted.category(), Category::Peers);
+    }
+
     #[test]
     fn test_tx_status_ordering() {
         assert!(
@@ -555,6 +906,13 @@ mod test {
         assert!(
             TxStatus::Acknowledged {
                 peer: ([0, 0, 0, 0], 0).into()
+            } < TxStatus::Relayed {
+                peer: ([0, 0, 0, 0], 0).into()
+            }
+        );
+        assert!(
+            TxStatus::Relayed {
+                peer: ([0, 0, 0, 0], 0).into()
             } < TxStatus::Confirmed {
                 height: 0,
                 block: BlockHash::all_zeros(),
@@ -1,4 +1,8 @@
 //! Types and utilities related to transaction fees and fee rates.
+//!
+//! Fee rates are estimated from two sources: the fee rates of transactions included in recent
+//! blocks, and the `feefilter` messages sent by peers, which tell us the lowest fee rate they
+//! relay transactions at.
 use std::collections::VecDeque;
 
 use nakamoto_common::bitcoin::blockdata::constants::WITNESS_SCALE_FACTOR;
@@ -7,13 +11,19 @@ use nakamoto_common::bitcoin::{Block, OutPoint, Transaction, TxOut};
 use nakamoto_common::collections::HashMap;
 use nakamoto_common::nonempty::NonEmpty;
 
-use super::Height;
+use super::{Height, PeerId};
 
 // TODO: Prune UTXO set so that it doesn't grow indefinitely.
 
 /// Maximum depth of a re-org that we are able to handle.
 pub const MAX_UTXO_SNAPSHOTS: usize = 12;
 
+/// Maximum number of recent block fee estimates used to estimate fee rates.
+pub const MAX_BLOCK_ESTIMATES: usize = 12;
+
+/// Lowest fee rate we ever estimate. This is the default minimum relay fee.
+pub const MIN_FEE_RATE: FeeRate = 1;
+
 /// Transaction fee rate in satoshis/vByte.
 pub type FeeRate = u64;
 
@@ -94,6 +104,10 @@ pub struct FeeEstimator {
     /// UTXO set snapshots.
     /// These are used to return to a previous state in the case of a re-org.
     snapshots: VecDeque<(Height, UtxoSet)>,
+    /// Fee estimates of recent blocks, by height.
+    estimates: VecDeque<(Height, FeeEstimate)>,
+    /// Minimum fee rates announced by peers via `feefilter`.
+    fee_filters: HashMap<PeerId, FeeRate>,
 }
 
 impl FeeEstimator {
@@ -120,12 +134,20 @@ impl FeeEstimator {
         }
         self.height = height;
 
-        FeeEstimate::from(fees)
+        let estimate = FeeEstimate::from(fees);
+        if let Some(estimate) = &estimate {
+            self.estimates.push_back((height, estimate.clone()));
+            if self.estimates.len() > MAX_BLOCK_ESTIMATES {
+                self.estimates.pop_front();
+            }
+        }
+        estimate
     }
 
     /// Rollback to a certain height.
     pub fn rollback(&mut self, height: Height) {
         self.snapshots.retain(|(h, _)| h <= &height);
+        self.estimates.retain(|(h, _)| h <= &height);
 
         if let Some((h, snapshot)) = self.snapshots.pop_back() {
             assert!(h <= height);
@@ -135,6 +157,53 @@ impl FeeEstimator {
         }
     }
 
+    /// Record the minimum fee rate a peer relays transactions at, as announced in a `feefilter`
+    /// message, in satoshis per kilobyte.
+    pub fn fee_filter(&mut self, peer: PeerId, rate: i64) {
+        let rate = (rate.max(0) as FeeRate + 999) / 1000;
+
+        self.fee_filters.insert(peer, rate);
+    }
+
+    /// Forget about a disconnected peer.
+    pub fn peer_disconnected(&mut self, peer: &PeerId) {
+        self.fee_filters.remove(peer);
+    }
+
+    /// Estimate the fee rate needed for a transaction to be included within the given number of
+    /// blocks. Returns [`None`] if we have neither processed any blocks nor received any fee
+    /// filters.
+    ///
+    /// The median fee rates of recent blocks are ranked, and the more blocks we are willing to
+    /// wait, the lower the rate picked. The estimate is never lower than the median of the fee
+    /// filters announced by our peers, since they wouldn't relay the transaction otherwise.
+    pub fn estimate(&self, blocks: usize) -> Option<FeeRate> {
+        let mut medians = self
+            .estimates
+            .iter()
+            .map(|(_, e)| e.median)
+            .collect::<Vec<_>>();
+        medians.sort_unstable();
+
+        let confirm = if medians.is_empty() {
+            None
+        } else {
+            Some(medians[medians.len() - blocks.clamp(1, medians.len())])
+        };
+        let relay =
+            FeeEstimate::from(self.fee_filters.values().copied().collect()).map(|e| e.median);
+
+        match (confirm, relay) {
+            (None, None) => None,
+            (confirm, relay) => Some(
+                confirm
+                    .unwrap_or_default()
+                    .max(relay.unwrap_or_default())
+                    .max(MIN_FEE_RATE),
+            ),
+        }
+    }
+
     /// Apply the transaction to the UTXO set and calculate the fee rate.
     fn apply(&mut self, tx: &Transaction) -> Option<FeeRate> {
         let txid = tx.txid();
@@ -213,6 +282,48 @@ mod tests {
         assert_matches!(fe.snapshots.back(), Some((18, _)));
     }
 
+    #[test]
+    fn test_estimate() {
+        let mut fe = FeeEstimator::default();
+        let peer = ([127, 0, 0, 1], 8333).into();
+
+        assert_eq!(fe.estimate(1), None);
+
+        // Fee filters are in satoshis per kilobyte.
+        fe.fee_filter(peer, 1000);
+        assert_eq!(fe.estimate(1), Some(1));
+        fe.fee_filter(peer, 2001);
+        assert_eq!(fe.estimate(1), Some(3));
+        fe.peer_disconnected(&peer);
+        assert_eq!(fe.estimate(1), None);
+
+        for (height, median) in [(1, 4), (2, 1), (3, 9), (4, 2)] {
+            fe.estimates.push_back((
+                height,
+                FeeEstimate {
+                    low: 1,
+                    median,
+                    high: 10,
+                },
+            ));
+        }
+        // The fewer blocks to wait, the higher the estimate.
+        assert_eq!(fe.estimate(0), Some(9));
+        assert_eq!(fe.estimate(1), Some(9));
+        assert_eq!(fe.estimate(2), Some(4));
+        assert_eq!(fe.estimate(3), Some(2));
+        assert_eq!(fe.estimate(4), Some(1));
+        assert_eq!(fe.estimate(100), Some(1));
+
+        // Never below what peers relay.
+        fe.fee_filter(peer, 3000);
+        assert_eq!(fe.estimate(4), Some(3));
+
+        // Reverted blocks are no longer taken into account.
+        fe.rollback(2);
+        assert_eq!(fe.estimate(1), Some(4));
+    }
+
     #[test]
     fn test_rollback_missing_height() {
         let mut fe = FeeEstimator::default();
@@ -19,9 +19,40 @@
 //! the [`InventoryManager::timer_expired`] function is called. Confirmed transactions are removed
 //! after they are burried at a certain depth.
 //!
-use std::collections::BTreeMap;
+//! ## Broadcast tracking
+//!
+//! Submitted transactions are announced to every peer, including peers that connect later,
+//! until they are included in a block. The peers requesting a transaction after it was
+//! announced, and the peers announcing it back to us, which they only do once they accepted
+//! it into their mempool, are recorded, and the user is notified of each of them via a
+//! [`Event::TxStatusChanged`] event.
+//!
+//! Like in Bitcoin Core, announcements are trickled: inventories are queued for each peer, and
+//! announced in a single batch after a random delay, drawn separately for every peer. Peers
+//! thus can't tell, from the order in which they're announced transactions, which node they
+//! originate from. Transactions are only ever sent to the peers requesting them.
+//!
+//! With the [`BroadcastPolicy::Subset`] policy, transactions are first announced to a few
+//! random peers only. Once a peer we didn't announce a transaction to announces it back to us,
+//! we know it propagated through the network. Until then, every [`PROPAGATION_TIMEOUT`], the
+//! transaction is announced to as many other random peers again.
+//!
+//! Peers rejecting a transaction, with a `reject` message, are no longer announced it, and
+//! the user is notified via a [`Event::TxRejected`] event. A transaction rejected by peers,
+//! and relayed by none, can't be submitted again.
+//!
+//! ## Block cache
+//!
+//! Blocks can be requested more than once, eg. by overlapping rescans, or by a rescan
+//! matching a block that was just fetched for another reason. Requests for blocks that are
+//! already queued or in flight are ignored, and the most recently processed blocks are kept
+//! in memory, so that requesting them again doesn't download them again. Blocks served
+//! from memory are processed on the next tick, in order with the blocks being downloaded.
+//!
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 
 use nakamoto_common::bitcoin::network::message::NetworkMessage;
+use nakamoto_common::bitcoin::network::message_network::{Reject, RejectReason};
 use nakamoto_common::bitcoin::network::{constants::ServiceFlags, message_blockdata::Inventory};
 use nakamoto_common::bitcoin::{Block, BlockHash, Transaction, Txid};
 
@@ -32,7 +63,8 @@ use nakamoto_common::block::time::{Clock, LocalDuration, LocalTime};
 use nakamoto_common::block::tree::BlockReader;
 use nakamoto_common::collections::{AddressBook, HashMap};
 
-use super::fees::FeeEstimator;
+use super::download::{Download, DEFAULT_WINDOW};
+use super::fees::{FeeEstimator, FeeRate};
 use super::output::{Io, Outbox};
 use super::{event::TxStatus, Event, Height, PeerId};
 
@@ -48,9 +80,39 @@ pub const MAX_ATTEMPTS: usize = 3;
 /// Time between idles.
 pub const IDLE_TIMEOUT: LocalDuration = LocalDuration::from_secs(30);
 
+/// Average delay before inventories are announced to a peer.
+pub const TRICKLE_INTERVAL: LocalDuration = LocalDuration::from_secs(5);
+
+/// Maximum delay before inventories are announced to a peer.
+pub const MAX_TRICKLE_DELAY: LocalDuration = LocalDuration::from_secs(20);
+
+/// Time to wait for a transaction to propagate, before announcing it to more peers.
+pub const PROPAGATION_TIMEOUT: LocalDuration = LocalDuration::from_secs(30);
+
+/// Number of peers submitted transactions are first announced to, with the
+/// [`BroadcastPolicy::Subset`] policy.
+pub const DEFAULT_BROADCAST_PEERS: usize = 2;
+
 /// Block depth at which confirmed transactions are pruned and no longer reverted after a re-org.
 pub const TRANSACTION_PRUNE_DEPTH: Height = 12;
 
+/// Number of recently processed blocks kept in memory.
+pub const BLOCK_CACHE_SIZE: usize = 16;
+
+/// How submitted transactions are broadcast.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BroadcastPolicy {
+    /// Announce transactions to every peer.
+    #[default]
+    All,
+    /// Announce transactions to the given number of random peers, and to as many other peers
+    /// every time they fail to propagate in time.
+    Subset {
+        /// Number of peers to announce to at a time.
+        peers: usize,
+    },
+}
+
 /// Inventory manager peer.
 #[derive(Debug)]
 pub struct Peer {
@@ -64,6 +126,8 @@ pub struct Peer {
     attempts: usize,
     /// Last time we attempted to send inventories to this peer.
     last_attempt: Option<LocalTime>,
+    /// Time before which queued inventories aren't announced to this peer.
+    trickle: LocalTime,
 
     /// Number of times a certain block was requested.
     #[allow(dead_code)]
@@ -85,6 +149,57 @@ impl Peer {
         self.last_attempt = None;
         self.attempts = 0;
     }
+
+    /// Queue an inventory to be announced. If none were queued, the announcement is delayed
+    /// by a random amount of time, and the delay is returned.
+    fn queue(
+        &mut self,
+        tx: Transaction,
+        now: LocalTime,
+        rng: &fastrand::Rng,
+    ) -> Option<LocalDuration> {
+        let delay = self.outbox.is_empty().then(|| trickle_delay(rng));
+
+        if let Some(delay) = delay {
+            self.trickle = now + delay;
+        }
+        self.outbox.insert(tx.txid(), tx);
+
+        delay
+    }
+}
+
+/// Random delay before announcing inventories to a peer. Delays are exponentially distributed,
+/// averaging [`TRICKLE_INTERVAL`], and capped at [`MAX_TRICKLE_DELAY`].
+fn trickle_delay(rng: &fastrand::Rng) -> LocalDuration {
+    let mean = TRICKLE_INTERVAL.as_millis() as f64;
+    let delay = -(1. - rng.f64()).ln() * mean;
+
+    LocalDuration::from_millis((delay as u128).min(MAX_TRICKLE_DELAY.as_millis()))
+}
+
+/// Broadcast state of a submitted transaction.
+#[derive(Debug, Default)]
+struct Announcement {
+    /// Peers that requested the transaction from us.
+    requested_by: BTreeSet<PeerId>,
+    /// Peers that announced the transaction to us.
+    relayed_by: BTreeSet<PeerId>,
+    /// Peers that rejected the transaction, with their rejection code and reason.
+    rejected_by: BTreeMap<PeerId, (RejectReason, String)>,
+    /// Peers the transaction is announced to, if it isn't announced to every peer.
+    targets: Option<BTreeSet<PeerId>>,
+    /// Last time the transaction was announced to more peers.
+    widened: LocalTime,
+}
+
+impl Announcement {
+    /// Whether the transaction was announced to us by a peer we didn't announce it to.
+    fn is_propagated(&self) -> bool {
+        self.targets.as_ref().map_or(true, |targets| {
+            self.relayed_by.iter().any(|p| !targets.contains(p))
+        })
+    }
 }
 
 /// Inventory manager state.
@@ -94,6 +209,8 @@ pub struct InventoryManager<C> {
     peers: AddressBook<PeerId, Peer>,
     /// Timeout used for retrying broadcasts.
     timeout: LocalDuration,
+    /// How submitted transactions are broadcast.
+    policy: BroadcastPolicy,
     /// Confirmed transactions by block height.
     /// Pruned after a certain depth.
     confirmed: HashMap<Height, Vec<Transaction>>,
@@ -103,10 +220,14 @@ pub struct InventoryManager<C> {
 
     /// Transaction mempool. Stores unconfirmed transactions sent to the network.
     pub mempool: BTreeMap<Txid, Transaction>,
-    /// Blocks requested and the time at which they were last requested.
-    pub remaining: HashMap<BlockHash, Option<LocalTime>>,
-    /// Blocks received, waiting to be processed.
-    pub received: HashMap<Height, Block>,
+    /// Broadcast state of the transactions in the mempool.
+    announcements: HashMap<Txid, Announcement>,
+    /// Blocks requested, and blocks received waiting to be processed in order.
+    pub downloads: Download<Block>,
+    /// Recently processed blocks, least recently used first.
+    recent: VecDeque<(Height, BlockHash, Block)>,
+    /// Blocks served from memory, waiting to be processed.
+    cached: Vec<(Height, Block)>,
 
     last_tick: Option<LocalTime>,
     rng: fastrand::Rng,
@@ -128,11 +249,14 @@ impl<C: Clock> InventoryManager<C> {
         Self {
             peers: AddressBook::new(rng.clone()),
             mempool: BTreeMap::new(),
+            announcements: HashMap::with_hasher(rng.clone().into()),
             estimator: FeeEstimator::default(),
             confirmed: HashMap::with_hasher(rng.clone().into()),
-            remaining: HashMap::with_hasher(rng.clone().into()),
-            received: HashMap::with_hasher(rng.clone().into()),
+            downloads: Download::new(DEFAULT_WINDOW, REQUEST_TIMEOUT, rng.clone()),
+            recent: VecDeque::with_capacity(BLOCK_CACHE_SIZE),
+            cached: Vec::new(),
             timeout: REBROADCAST_TIMEOUT,
+            policy: BroadcastPolicy::default(),
             last_tick: None,
             rng,
             outbox: Outbox::default(),
@@ -140,6 +264,12 @@ impl<C: Clock> InventoryManager<C> {
         }
     }
 
+    /// Set the policy submitted transactions are broadcast with.
+    pub fn with_policy(mut self, policy: BroadcastPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
     #[cfg(test)]
     /// Check whether the inventory is empty.
     pub fn is_empty(&self) -> bool {
@@ -165,11 +295,19 @@ impl<C: Clock> InventoryManager<C> {
             }
             Event::PeerDisconnected { addr, .. } => {
                 self.peers.remove(&addr);
+                self.estimator.peer_disconnected(&addr);
+                self.downloads.peer_disconnected(&addr);
             }
             Event::BlockHeadersImported { reverted, .. } => {
+                let mut blocks = Vec::new();
+
                 for (height, _) in reverted {
                     self.block_reverted(height);
+                    // Reverted blocks are no longer downloaded, which may unblock the
+                    // processing of the blocks received above them.
+                    blocks.extend(self.downloads.cancel(height));
                 }
+                self.process_blocks(blocks, tree);
             }
 
             Event::MessageReceived { from, message } => match message.as_ref() {
@@ -183,12 +321,19 @@ impl<C: Clock> InventoryManager<C> {
                     let txid = tx.txid();
                     log::debug!(target: "p2p", "transaction {} from {}", txid, from)
                 }
+                NetworkMessage::FeeFilter(rate) => {
+                    self.estimator.fee_filter(from, *rate);
+                }
                 NetworkMessage::GetData(invs) => {
                     self.received_getdata(from, invs);
                     // TODO: (*self.hooks.on_getdata)(addr, invs, &self.outbox);
                 }
                 NetworkMessage::Inv(msg) => {
                     log::info!("Received INV message {:?}", msg);
+                    self.received_inv(from, msg);
+                }
+                NetworkMessage::Reject(reject) => {
+                    self.received_reject(from, reject);
                 }
                 _ => {}
             },
@@ -198,25 +343,34 @@ impl<C: Clock> InventoryManager<C> {
 
     /// Called when a peer is negotiated.
     fn peer_negotiated(&mut self, addr: PeerId, services: ServiceFlags, relay: bool) {
-        // Add existing inventories to this peer's outbox so that they are announced.
-        let mut outbox = HashMap::with_hasher(self.rng.clone().into());
-        for (txid, tx) in self.mempool.iter() {
-            outbox.insert(*txid, tx.clone());
+        let now = self.clock.local_time();
+        let mut peer = Peer {
+            services,
+            attempts: 0,
+            relay,
+
+            outbox: HashMap::with_hasher(self.rng.clone().into()),
+            last_attempt: None,
+            trickle: now,
+            requests: HashMap::with_hasher(self.rng.clone().into()),
+        };
+        // Add existing inventories to this peer's outbox so that they are announced, unless
+        // they're only announced to some peers.
+        for (txid, tx) in &self.mempool {
+            if self
+                .announcements
+                .get(txid)
+                .map_or(false, |a| a.targets.is_some())
+            {
+                continue;
+            }
+            if let Some(delay) = peer.queue(tx.clone(), now, &self.rng) {
+                self.outbox.set_timer(delay);
+            }
         }
 
         self.schedule_tick();
-        self.peers.insert(
-            addr,
-            Peer {
-                services,
-                attempts: 0,
-                relay,
-
-                outbox,
-                last_attempt: None,
-                requests: HashMap::with_hasher(self.rng.clone().into()),
-            },
-        );
+        self.peers.insert(addr, peer);
     }
 
     /// Called when a block is reverted.
@@ -234,6 +388,23 @@ impl<C: Clock> InventoryManager<C> {
         }
     }
 
+    /// Estimate the fee rate needed for a transaction to be included within the given number of
+    /// blocks. See [`FeeEstimator::estimate`].
+    pub fn estimate_fee(&self, blocks: usize) -> Option<FeeRate> {
+        self.estimator.estimate(blocks)
+    }
+
+    /// The rejection code and reason of a submitted transaction, if it was rejected by peers
+    /// and wasn't relayed by any peer.
+    pub fn rejection(&self, txid: &Txid) -> Option<(RejectReason, String)> {
+        let announcement = self.announcements.get(txid)?;
+
+        if !announcement.relayed_by.is_empty() {
+            return None;
+        }
+        announcement.rejected_by.values().next().cloned()
+    }
+
     /// Lookup a submitted transaction in the local mempool.
     pub fn get_submitted_tx(&mut self, txid: &Txid) -> Option<Transaction> {
         self.mempool.values().find(|tx| tx.txid() == *txid).cloned()
@@ -253,6 +424,8 @@ impl<C: Clock> InventoryManager<C> {
             self.confirmed
                 .retain(|h, _| height - h <= TRANSACTION_PRUNE_DEPTH);
         }
+        // Announce transactions that didn't propagate to more peers.
+        self.widen(now);
 
         // Handle retries annd disconnects.
         let mut disconnect = Vec::new();
@@ -263,6 +436,9 @@ impl<C: Clock> InventoryManager<C> {
 
             // Peer inventory announce timeout.
             if !peer.outbox.is_empty() {
+                if now < peer.trickle {
+                    continue;
+                }
                 let elapsed = now - peer.last_attempt.unwrap_or_default();
                 if elapsed < self.timeout {
                     continue;
@@ -279,7 +455,11 @@ impl<C: Clock> InventoryManager<C> {
 
                 peer.attempted(now);
 
-                let invs = Vec::with_capacity(peer.outbox.len());
+                let invs = peer
+                    .outbox
+                    .keys()
+                    .map(|txid| Inventory::Transaction(*txid))
+                    .collect();
 
                 self.outbox.inv(*addr, invs);
                 self.outbox.set_timer(self.timeout);
@@ -291,32 +471,16 @@ impl<C: Clock> InventoryManager<C> {
             self.outbox.event(Event::PeerTimedOut { addr });
         }
 
-        // Handle block request queue.
-        let queue = self
-            .remaining
-            .iter_mut()
-            .filter(|(_, t)| now - t.unwrap_or_default() >= REQUEST_TIMEOUT);
+        // Process the blocks served from memory.
+        if !self.cached.is_empty() {
+            self.process_blocks(Vec::new(), tree);
+        }
 
-        for (block_hash, last_request) in queue {
-            if let Some((addr, _)) = self
-                .peers
-                .sample_with(|_, p| p.services.has(ServiceFlags::NETWORK))
-            {
-                log::debug!(target: "p2p", "Requesting block {} from {}", block_hash, addr);
-
-                self.outbox
-                    .get_data(*addr, vec![Inventory::Block(*block_hash)]);
-                self.outbox.set_timer(REQUEST_TIMEOUT);
-
-                *last_request = Some(now);
-            } else {
-                log::debug!(
-                    target: "p2p",
-                    "No peers with required services to request block {} from",
-                    block_hash
-                );
-            }
+        // Handle block request queue. Requests that timed out are assigned to other peers.
+        for addr in self.downloads.timed_out(now) {
+            log::debug!(target: "p2p", "Block request to {} timed out", addr);
         }
+        self.request_blocks(now);
     }
 
     /// Called when a `getdata` is received from a peer.
@@ -339,12 +503,18 @@ impl<C: Clock> InventoryManager<C> {
                                 if peer.outbox.is_empty() {
                                     log::debug!(target: "p2p", "Peer {} transaction outbox is empty", &addr);
                                 }
-                                self.outbox.event(Event::TxStatusChanged {
-                                    txid,
-                                    status: TxStatus::Acknowledged { peer: addr },
-                                });
                             }
                         }
+                        // Peers may request the transaction more than once, eg. after
+                        // reconnecting. Only the first request is reported.
+                        let announcement = self.announcements.entry(txid).or_default();
+
+                        if announcement.requested_by.insert(addr) {
+                            self.outbox.event(Event::TxStatusChanged {
+                                txid,
+                                status: TxStatus::Acknowledged { peer: addr },
+                            });
+                        }
                     }
                 }
 
@@ -353,9 +523,76 @@ impl<C: Clock> InventoryManager<C> {
         }
     }
 
+    /// Called when an `inv` is received from a peer.
+    pub fn received_inv(&mut self, addr: PeerId, invs: &[Inventory]) {
+        for inv in invs {
+            let Inventory::Transaction(txid) = inv else {
+                continue;
+            };
+            let Some(announcement) = self.announcements.get_mut(txid) else {
+                continue;
+            };
+            // The peer already has the transaction, so we no longer need to send it.
+            if let Some(peer) = self.peers.get_mut(&addr) {
+                if peer.outbox.remove(txid).is_some() {
+                    peer.reset();
+                }
+            }
+            if announcement.relayed_by.insert(addr) {
+                self.outbox.event(Event::TxStatusChanged {
+                    txid: *txid,
+                    status: TxStatus::Relayed { peer: addr },
+                });
+            }
+        }
+    }
+
+    /// Called when a `reject` is received from a peer.
+    pub fn received_reject(&mut self, addr: PeerId, reject: &Reject) {
+        if reject.message != "tx" {
+            return;
+        }
+        let txid = Txid::from_hash(reject.hash);
+        let Some(announcement) = self.announcements.get_mut(&txid) else {
+            return;
+        };
+        // The peer already has the transaction, which isn't a rejection.
+        if reject.ccode == RejectReason::Duplicate {
+            return;
+        }
+        if let Some(peer) = self.peers.get_mut(&addr) {
+            if peer.outbox.remove(&txid).is_some() {
+                peer.reset();
+            }
+        }
+        let (code, reason) = (reject.ccode, reject.reason.to_string());
+
+        if announcement
+            .rejected_by
+            .insert(addr, (code, reason.clone()))
+            .is_none()
+        {
+            log::warn!(
+                target: "p2p",
+                "Transaction {} was rejected by {}: {} ({:?})",
+                txid,
+                addr,
+                reason,
+                code
+            );
+            self.outbox.event(Event::TxRejected {
+                txid,
+                peer: addr,
+                code,
+                reason,
+            });
+        }
+    }
+
     /// Called when a block is received from a peer.
     /// Returns the list of confirmed [`Txid`].
     ///
+    /// Blocks are processed in order, once all blocks requested below them were received.
     /// Note that the confirmed transactions don't necessarily pertain to this block.
     pub fn received_block<T: BlockReader>(
         &mut self,
@@ -365,55 +602,49 @@ impl<C: Clock> InventoryManager<C> {
     ) -> Vec<Txid> {
         let hash = block.block_hash();
 
-        if self.remaining.remove(&hash).is_none() {
+        let Some(blocks) = self.downloads.received(&hash, block.clone()) else {
             // Nb. The remote isn't necessarily sending an unsolicited block here.
             // We often have to ask multiple peers to get a response, so we may
             // have already received this block once.
             return vec![];
-        }
+        };
 
         // We're done requesting this block.
         for peer in self.peers.values_mut() {
             peer.requests.remove(&hash);
         }
+        // There is now room in the download window for the next block.
+        self.request_blocks(self.clock.local_time());
+        self.process_blocks(blocks, tree)
+    }
 
-        // Find the block height, otherwise we've somehow requested a block which
-        // isn't part of the active chain. This could happen in the case of a re-org
-        // and a delayed block arrival.
-        let height = if let Some((height, _)) = tree.get_block(&hash) {
-            height
-        } else {
-            return vec![];
-        };
-
-        // Add to processing queue. Blocks are processed in-order only.
-        self.received.insert(height, block.clone());
-
-        // If there are still blocks remaining to download, don't process any of the
-        // received queue yet.
-        if !self.remaining.is_empty() {
-            return vec![];
-        }
-
-        // Now that all blocks to be processed are downloaded, we can start
-        // processing them in order.
+    /// Process downloaded blocks, in order, after the blocks served from memory.
+    /// Returns the list of confirmed [`Txid`].
+    fn process_blocks<T: BlockReader>(
+        &mut self,
+        blocks: Vec<(Height, Block)>,
+        tree: &T,
+    ) -> Vec<Txid> {
         let mut confirmed = Vec::new();
+        let cached = std::mem::take(&mut self.cached);
 
-        while let Some((height, block)) = self
-            .received
-            .keys()
-            .min()
-            .cloned()
-            .and_then(|h| self.received.remove(&h).map(|b| (h, b)))
-        {
+        for (height, block) in cached.into_iter().chain(blocks) {
             let hash = block.block_hash();
 
+            // Skip blocks which aren't part of the active chain anymore. This could happen
+            // in the case of a re-org and a delayed block arrival.
+            if tree.get_block(&hash).map(|(h, _)| h) != Some(height) {
+                log::debug!(target: "p2p", "Skipping block {} which is no longer active", hash);
+                continue;
+            }
+
             for tx in &block.txdata {
                 let txid = tx.txid();
 
                 // Attempt to remove confirmed transaction from mempool.
                 if let Some(transaction) = self.mempool.remove(&txid) {
                     confirmed.push(tx.txid());
+                    self.announcements.remove(&txid);
 
                     // Transactions that have been confirmed no longer need to be announced.
                     for peer in self.peers.values_mut() {
@@ -436,7 +667,15 @@ impl<C: Clock> InventoryManager<C> {
             }
             // Process block through fee estimator.
             let fees = self.estimator.process(block.clone(), height);
+            if let Some(fees) = &fees {
+                self.outbox.event(Event::FeeEstimated {
+                    block: hash,
+                    height,
+                    fees: fees.clone(),
+                });
+            }
 
+            self.remember(height, hash, block.clone());
             self.outbox.event(Event::BlockProcessed {
                 block,
                 height,
@@ -446,38 +685,170 @@ impl<C: Clock> InventoryManager<C> {
         confirmed
     }
 
-    /// Announce inventories to all matching peers. Retries if necessary.
+    /// Keep a processed block in memory, evicting the least recently used block if full.
+    fn remember(&mut self, height: Height, hash: BlockHash, block: Block) {
+        if let Some(ix) = self.recent.iter().position(|(_, h, _)| *h == hash) {
+            self.recent.remove(ix);
+        } else if self.recent.len() >= BLOCK_CACHE_SIZE {
+            self.recent.pop_front();
+        }
+        self.recent.push_back((height, hash, block));
+    }
+
+    /// Announce inventories to all matching peers, or a subset of them, depending on the
+    /// broadcast policy. Retries if necessary.
     pub fn announce(&mut self, tx: Transaction) -> Vec<PeerId> {
         // All peers we are sending inventories to.
-        let mut addrs = Vec::new();
+        let mut addrs = self
+            .peers
+            .iter()
+            .filter(|(_, p)| p.relay)
+            .map(|(addr, _)| *addr)
+            .collect::<Vec<_>>();
 
         let txid = tx.txid();
+        let now = self.clock.local_time();
 
         // Insert transaction into the peer outboxes and keep a local copy for re-broadcasting later.
         self.mempool.insert(txid, tx.clone());
-        if let Some((p, _)) = self.peers.sample() {
-            self.outbox.message(*p, NetworkMessage::Tx(tx.clone()));
-        }
+        let announcement = self.announcements.entry(txid).or_default();
 
-        for (addr, peer) in self.peers.iter_mut().filter(|(_, p)| p.relay) {
-            peer.outbox.insert(txid, tx.clone());
-            addrs.push(*addr);
+        if let BroadcastPolicy::Subset { peers } = self.policy {
+            self.rng.shuffle(&mut addrs);
+            addrs.truncate(peers);
+
+            announcement
+                .targets
+                .get_or_insert_with(BTreeSet::new)
+                .extend(addrs.iter().copied());
+            announcement.widened = now;
+
+            self.outbox.set_timer(PROPAGATION_TIMEOUT);
+        }
+        for addr in &addrs {
+            self.announce_to(addr, tx.clone(), now);
         }
         self.schedule_tick();
 
         addrs
     }
 
-    /// Attempt to get a block from the network. Retries if necessary.
-    pub fn get_block(&mut self, hash: BlockHash) {
-        log::debug!(target: "p2p", "Queueing block {hash} to be requested");
-
-        self.remaining.entry(hash).or_insert(None);
-        self.schedule_tick();
+    /// Attempt to get the block at the given height from the network. Retries if necessary.
+    pub fn get_block(&mut self, height: Height, hash: BlockHash) {
+        if self.cached.iter().any(|(_, b)| b.block_hash() == hash) {
+            return;
+        }
+        if let Some(ix) = self
+            .recent
+            .iter()
+            .position(|(h, b, _)| *h == height && *b == hash)
+        {
+            if !self.downloads.get(height, hash) {
+                return;
+            }
+            // Mark the block as most recently used.
+            let entry = self.recent.remove(ix).expect("index is valid");
+            let block = entry.2.clone();
+            self.recent.push_back(entry);
+
+            // The block goes through the download queue, to be delivered in order.
+            if let Some(blocks) = self.downloads.received(&hash, block) {
+                log::debug!(target: "p2p", "Block {hash} found in memory");
+                self.cached.extend(blocks);
+                self.schedule_tick();
+            }
+        } else if self.downloads.get(height, hash) {
+            log::debug!(target: "p2p", "Queueing block {hash} to be requested");
+            self.schedule_tick();
+        }
     }
 
     ////////////////////////////////////////////////////////////////////////////
 
+    /// Queue a transaction to be announced to a peer.
+    fn announce_to(&mut self, addr: &PeerId, tx: Transaction, now: LocalTime) {
+        if let Some(peer) = self.peers.get_mut(addr) {
+            if let Some(delay) = peer.queue(tx, now, &self.rng) {
+                self.outbox.set_timer(delay);
+            }
+        }
+    }
+
+    /// Announce the transactions that weren't heard back from the network within the
+    /// propagation timeout to more peers, if they're only announced to some peers.
+    fn widen(&mut self, now: LocalTime) {
+        let BroadcastPolicy::Subset { peers: count } = self.policy else {
+            return;
+        };
+        let mut widened = Vec::new();
+
+        for (txid, announcement) in self.announcements.iter_mut() {
+            if announcement.is_propagated() || now - announcement.widened < PROPAGATION_TIMEOUT {
+                continue;
+            }
+            let Some(targets) = &mut announcement.targets else {
+                continue;
+            };
+            let mut addrs = self
+                .peers
+                .iter()
+                .filter(|(addr, p)| {
+                    p.relay
+                        && !targets.contains(*addr)
+                        && !announcement.rejected_by.contains_key(*addr)
+                })
+                .map(|(addr, _)| *addr)
+                .collect::<Vec<_>>();
+
+            self.rng.shuffle(&mut addrs);
+            addrs.truncate(count);
+            targets.extend(addrs.iter().copied());
+            announcement.widened = now;
+
+            if !addrs.is_empty() {
+                widened.push((*txid, addrs));
+            }
+        }
+        for (txid, addrs) in widened {
+            let Some(tx) = self.mempool.get(&txid).cloned() else {
+                continue;
+            };
+            log::debug!(
+                target: "p2p",
+                "Transaction {} didn't propagate, announcing it to {} more peer(s)",
+                txid,
+                addrs.len()
+            );
+            for addr in &addrs {
+                self.announce_to(addr, tx.clone(), now);
+            }
+        }
+    }
+
+    /// Request queued blocks from peers, within the download window.
+    fn request_blocks(&mut self, now: LocalTime) {
+        let peers = self
+            .peers
+            .iter()
+            .filter(|(_, p)| p.services.has(ServiceFlags::NETWORK))
+            .map(|(addr, _)| *addr)
+            .collect::<Vec<_>>();
+
+        if peers.is_empty() {
+            if self.downloads.requested() > 0 {
+                log::debug!(target: "p2p", "No peers with required services to request blocks");
+            }
+            return;
+        }
+        for (addr, hashes) in self.downloads.schedule(&peers, now) {
+            log::debug!(target: "p2p", "Requesting {} block(s) from {}", hashes.len(), addr);
+
+            self.outbox
+                .get_data(addr, hashes.into_iter().map(Inventory::Block).collect());
+            self.outbox.set_timer(REQUEST_TIMEOUT);
+        }
+    }
+
     fn schedule_tick(&mut self) {
         self.last_tick = None; // Disable rate-limiting for the next tick.
         self.outbox.set_timer(LocalDuration::from_secs(1));
@@ -630,7 +1001,7 @@ mod tests {
             // true,
         );
 
-        invmgr.get_block(hash);
+        invmgr.get_block(6, hash);
 
         let mut requested = HashSet::with_hasher(rng.clone().into());
         let mut last_request = LocalTime::default();
@@ -638,7 +1009,7 @@ mod tests {
         loop {
             clock.elapse(LocalDuration::from_secs(rng.u64(10..30)));
             invmgr.timer_expired(&tree);
-            assert!(!invmgr.remaining.is_empty());
+            assert!(!invmgr.downloads.is_empty());
 
             let Some((addr, _)) = output::test::messages(&mut invmgr)
                 .find(|(_, m)| matches!(m, NetworkMessage::GetData(i) if i == &inv))
@@ -661,8 +1032,8 @@ mod tests {
 
             break;
         }
-        assert!(invmgr.remaining.is_empty(), "No more blocks remaining");
-        assert!(invmgr.received.is_empty());
+        assert_eq!(invmgr.downloads.requested(), 0, "No more blocks remaining");
+        assert!(invmgr.downloads.is_empty());
         invmgr
             .find(|io| {
                 matches!(io,
@@ -699,6 +1070,7 @@ mod tests {
 
         invmgr.peer_negotiated(remote, ServiceFlags::NETWORK, true);
         invmgr.announce(tx);
+        clock.elapse(MAX_TRICKLE_DELAY);
         invmgr.timer_expired(&tree);
 
         assert_eq!(
@@ -724,6 +1096,115 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_trickle() {
+        let network = Network::Mainnet;
+        let tree = model::Cache::from(NonEmpty::new(network.genesis()));
+        let mut rng = fastrand::Rng::with_seed(1);
+        let clock = RefClock::from(LocalTime::now());
+        let peers: Vec<net::SocketAddr> = (1..=8).map(|i| ([88, 88, 88, i], 8333).into()).collect();
+        let tx1 = gen::transaction(&mut rng);
+        let tx2 = gen::transaction(&mut rng);
+
+        let mut invmgr = InventoryManager::new(rng, clock.clone());
+
+        for addr in &peers {
+            invmgr.peer_negotiated(*addr, ServiceFlags::NETWORK, true);
+        }
+        invmgr.announce(tx1.clone());
+        invmgr.announce(tx2.clone());
+        invmgr.timer_expired(&tree);
+
+        assert!(
+            output::test::messages(&mut invmgr.outbox).next().is_none(),
+            "Nothing is sent right away"
+        );
+
+        // Every peer is announced both transactions at once, at its own time.
+        let start = clock.local_time();
+        let mut times = BTreeSet::new();
+        let mut announced = BTreeSet::new();
+
+        while clock.local_time() - start <= MAX_TRICKLE_DELAY {
+            clock.elapse(LocalDuration::from_millis(100));
+            invmgr.timer_expired(&tree);
+
+            for (addr, msg) in output::test::messages(&mut invmgr.outbox) {
+                let NetworkMessage::Inv(invs) = msg else {
+                    panic!("Unexpected message {:?}", msg);
+                };
+                assert_eq!(invs.len(), 2);
+                assert!(invs.contains(&Inventory::Transaction(tx1.txid())));
+                assert!(invs.contains(&Inventory::Transaction(tx2.txid())));
+                assert!(announced.insert(addr));
+
+                times.insert(clock.local_time());
+            }
+        }
+        assert_eq!(announced.len(), peers.len());
+        assert!(
+            times.len() > 1,
+            "Peers aren't announced transactions at once"
+        );
+    }
+
+    #[test]
+    fn test_broadcast_subset() {
+        let network = Network::Mainnet;
+        let tree = model::Cache::from(NonEmpty::new(network.genesis()));
+        let mut rng = fastrand::Rng::with_seed(1);
+        let clock = RefClock::from(LocalTime::now());
+        let peers: Vec<net::SocketAddr> = (1..=6).map(|i| ([88, 88, 88, i], 8333).into()).collect();
+        let tx = gen::transaction(&mut rng);
+
+        let mut invmgr = InventoryManager::new(rng, clock.clone())
+            .with_policy(BroadcastPolicy::Subset { peers: 2 });
+
+        for addr in &peers[..5] {
+            invmgr.peer_negotiated(*addr, ServiceFlags::NETWORK, true);
+        }
+        let announced = |invmgr: &mut InventoryManager<_>| {
+            invmgr.timer_expired(&tree);
+            clock.elapse(MAX_TRICKLE_DELAY);
+            invmgr.timer_expired(&tree);
+
+            output::test::messages(&mut invmgr.outbox)
+                .filter(|(_, m)| matches!(m, NetworkMessage::Inv(_)))
+                .map(|(addr, _)| addr)
+                .collect::<BTreeSet<_>>()
+        };
+
+        // The transaction is first announced to two peers only, and not to peers connecting
+        // later.
+        let targets = invmgr.announce(tx.clone());
+        invmgr.peer_negotiated(peers[5], ServiceFlags::NETWORK, true);
+
+        assert_eq!(targets.len(), 2);
+        assert_eq!(
+            announced(&mut invmgr),
+            targets.iter().copied().collect::<BTreeSet<_>>()
+        );
+
+        // Hearing back from the peers it was announced to doesn't tell it propagated.
+        invmgr.received_inv(targets[0], &[Inventory::Transaction(tx.txid())]);
+
+        // Without propagation, it's announced to two other peers.
+        clock.elapse(PROPAGATION_TIMEOUT);
+        let widened = announced(&mut invmgr);
+        assert_eq!(widened.len(), 2);
+        assert!(widened.iter().all(|addr| !targets.contains(addr)));
+
+        // Once a peer it wasn't announced to announces it, it propagated.
+        let other = peers
+            .iter()
+            .find(|addr| !targets.contains(addr) && !widened.contains(addr))
+            .unwrap();
+        invmgr.received_inv(*other, &[Inventory::Transaction(tx.txid())]);
+
+        clock.elapse(PROPAGATION_TIMEOUT);
+        assert!(!announced(&mut invmgr).contains(other));
+    }
+
     #[test]
     fn test_max_attemps() {
         let network = Network::Mainnet;
@@ -739,6 +1220,7 @@ mod tests {
 
         invmgr.peer_negotiated(remote, ServiceFlags::NETWORK, true);
         invmgr.announce(tx.clone());
+        clock.elapse(MAX_TRICKLE_DELAY);
 
         // We attempt to broadcast up to `MAX_ATTEMPTS` times.
         for _ in 0..MAX_ATTEMPTS {
@@ -786,7 +1268,7 @@ mod tests {
 
         invmgr.peer_negotiated(remote, ServiceFlags::NETWORK, true);
         invmgr.announce(tx.clone());
-        invmgr.get_block(main_block1.block_hash());
+        invmgr.get_block(height, main_block1.block_hash());
         invmgr.received_block(&remote, &main_block1, &tree);
 
         assert!(!invmgr.contains(&tx.txid()));
@@ -818,7 +1300,7 @@ mod tests {
             })
             .unwrap();
 
-        invmgr.get_block(fork_block1.block_hash());
+        invmgr.get_block(height, fork_block1.block_hash());
         invmgr.received_block(&remote, &fork_block1, &tree);
 
         events(invmgr.outbox.drain())
@@ -831,23 +1313,76 @@ mod tests {
             .unwrap();
     }
 
+    #[test]
+    fn test_block_cache() {
+        let network = Network::Regtest;
+        let remote: net::SocketAddr = ([88, 88, 88, 88], 8333).into();
+        let mut rng = fastrand::Rng::new();
+
+        let chain = gen::blockchain(network.genesis_block(), 16, &mut rng);
+        let headers = NonEmpty::from_vec(chain.iter().map(|b| b.header).collect()).unwrap();
+        let tree = model::Cache::from(headers);
+        let block = &chain[6];
+        let hash = block.block_hash();
+        let time = LocalTime::now();
+
+        let mut invmgr = InventoryManager::new(rng, time);
+        invmgr.peer_negotiated(remote, ServiceFlags::NETWORK, true);
+
+        // Requesting a block twice only downloads it once.
+        invmgr.get_block(6, hash);
+        invmgr.get_block(6, hash);
+        invmgr.timer_expired(&tree);
+
+        let requests = output::test::messages_from(&mut invmgr.outbox, &remote)
+            .filter(|m| matches!(m, NetworkMessage::GetData(_)))
+            .count();
+        assert_eq!(requests, 1);
+
+        invmgr.received_block(&remote, block, &tree);
+        assert_eq!(
+            events(invmgr.outbox.drain())
+                .filter(|e| matches!(e, Event::BlockProcessed { height: 6, .. }))
+                .count(),
+            1
+        );
+
+        // Once processed, the block is served from memory.
+        invmgr.get_block(6, hash);
+        invmgr.get_block(6, hash);
+        invmgr.timer_expired(&tree);
+
+        let outputs = invmgr.outbox.drain().collect::<Vec<_>>();
+        assert!(!outputs
+            .iter()
+            .any(|o| matches!(o, output::Io::Write(_, NetworkMessage::GetData(_)))));
+        assert_eq!(
+            events(outputs.into_iter())
+                .filter(|e| matches!(e, Event::BlockProcessed { height: 6, .. }))
+                .count(),
+            1
+        );
+        assert!(invmgr.downloads.is_empty());
+    }
+
     #[test]
     fn test_wtx_inv() {
         let network = Network::Mainnet;
         let tree = model::Cache::from(NonEmpty::new(network.genesis()));
 
         let mut rng = fastrand::Rng::with_seed(1);
-        let time = LocalTime::now();
+        let clock = RefClock::from(LocalTime::now());
 
         let remote: net::SocketAddr = ([88, 88, 88, 88], 8333).into();
         let remote2: net::SocketAddr = ([88, 88, 88, 89], 8333).into();
         let tx = gen::transaction(&mut rng);
 
-        let mut invmgr = InventoryManager::new(rng, time);
+        let mut invmgr = InventoryManager::new(rng, clock.clone());
 
         invmgr.peer_negotiated(remote, ServiceFlags::NETWORK, true);
         invmgr.announce(tx);
 
+        clock.elapse(MAX_TRICKLE_DELAY);
         invmgr.timer_expired(&tree);
         let invs = output::test::messages_from(&mut invmgr.outbox, &remote)
             .filter_map(|m| {
@@ -862,6 +1397,7 @@ mod tests {
         assert_matches!(invs.first(), Some(Inventory::Transaction(_)));
 
         invmgr.peer_negotiated(remote2, ServiceFlags::NETWORK, true);
+        clock.elapse(MAX_TRICKLE_DELAY);
         invmgr.timer_expired(&tree);
         let invs = output::test::messages_from(&mut invmgr.outbox, &remote2)
             .filter_map(|m| match m {
@@ -873,6 +1409,113 @@ mod tests {
         assert_matches!(invs.first(), Some(Inventory::Transaction(_)));
     }
 
+    #[test]
+    fn test_broadcast_status() {
+        let network = Network::Regtest;
+        let mut rng = fastrand::Rng::with_seed(1);
+        let clock = RefClock::from(LocalTime::now());
+        let a: net::SocketAddr = ([88, 88, 88, 88], 8333).into();
+        let b: net::SocketAddr = ([99, 99, 99, 99], 8333).into();
+        let c: net::SocketAddr = ([77, 77, 77, 77], 8333).into();
+
+        let mut chain = gen::blockchain(network.genesis_block(), 2, &mut rng);
+        let tx = gen::transaction(&mut rng);
+        let block = gen::block_with(&chain.last().header, vec![tx.clone()], &mut rng);
+        chain.push(block.clone());
+
+        let headers = NonEmpty::from_vec(chain.iter().map(|b| b.header).collect()).unwrap();
+        let tree = model::Cache::from(headers);
+        let mut invmgr = InventoryManager::new(rng, clock.clone());
+        let statuses = |invmgr: &mut InventoryManager<_>| {
+            events(invmgr.outbox.drain())
+                .filter_map(|e| match e {
+                    Event::TxStatusChanged { txid, status } if txid == tx.txid() => Some(status),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        invmgr.peer_negotiated(a, ServiceFlags::NETWORK, true);
+        invmgr.peer_negotiated(b, ServiceFlags::NETWORK, true);
+        invmgr.announce(tx.clone());
+
+        // Only the first request from a peer is reported.
+        invmgr.received_getdata(a, &[Inventory::Transaction(tx.txid())]);
+        invmgr.received_getdata(a, &[Inventory::Transaction(tx.txid())]);
+        assert_eq!(
+            statuses(&mut invmgr),
+            vec![TxStatus::Acknowledged { peer: a }]
+        );
+
+        // A peer relaying the transaction doesn't need it to be announced anymore.
+        invmgr.received_inv(b, &[Inventory::Transaction(tx.txid())]);
+        assert_eq!(statuses(&mut invmgr), vec![TxStatus::Relayed { peer: b }]);
+        assert!(invmgr.peers.get(&b).unwrap().outbox.is_empty());
+
+        // Peers connecting later are announced the transaction.
+        invmgr.peer_negotiated(c, ServiceFlags::NETWORK, true);
+        clock.elapse(REBROADCAST_TIMEOUT);
+        invmgr.timer_expired(&tree);
+
+        let invs = output::test::messages_from(&mut invmgr.outbox, &c)
+            .filter_map(|m| match m {
+                NetworkMessage::Inv(invs) => Some(invs),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(invs, vec![vec![Inventory::Transaction(tx.txid())]]);
+
+        // Once the transaction is in a block, it is no longer tracked nor announced.
+        invmgr.get_block(chain.len() as Height - 1, block.block_hash());
+        invmgr.received_block(&a, &block, &tree);
+        assert!(invmgr.announcements.is_empty());
+        assert!(invmgr.peers.values().all(|p| p.outbox.is_empty()));
+    }
+
+    #[test]
+    fn test_tx_rejected() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        let a: net::SocketAddr = ([88, 88, 88, 88], 8333).into();
+        let b: net::SocketAddr = ([99, 99, 99, 99], 8333).into();
+        let tx = gen::transaction(&mut rng);
+        let reject = |ccode| Reject {
+            message: "tx".into(),
+            ccode,
+            reason: "min relay fee not met".into(),
+            hash: tx.txid().as_hash(),
+        };
+        let mut invmgr = InventoryManager::new(rng, LocalTime::now());
+
+        invmgr.peer_negotiated(a, ServiceFlags::NETWORK, true);
+        invmgr.peer_negotiated(b, ServiceFlags::NETWORK, true);
+        invmgr.announce(tx.clone());
+        invmgr.outbox.drain().for_each(drop);
+
+        // A duplicate isn't a rejection.
+        invmgr.received_reject(b, &reject(RejectReason::Duplicate));
+        assert_eq!(invmgr.rejection(&tx.txid()), None);
+
+        invmgr.received_reject(a, &reject(RejectReason::Fee));
+        events(invmgr.outbox.drain())
+            .find(|e| {
+                matches!(
+                    e,
+                    Event::TxRejected { txid, peer, code: RejectReason::Fee, .. }
+                    if *txid == tx.txid() && *peer == a
+                )
+            })
+            .expect("Rejection is reported");
+        assert!(invmgr.peers.get(&a).unwrap().outbox.is_empty());
+        assert_eq!(
+            invmgr.rejection(&tx.txid()),
+            Some((RejectReason::Fee, String::from("min relay fee not met")))
+        );
+
+        // Once a peer relays the transaction, it is no longer considered rejected.
+        invmgr.received_inv(b, &[Inventory::Transaction(tx.txid())]);
+        assert_eq!(invmgr.rejection(&tx.txid()), None);
+    }
+
     #[test]
     fn test_tx_getdata() {
         let mut rng = fastrand::Rng::with_seed(1);
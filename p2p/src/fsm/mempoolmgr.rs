@@ -0,0 +1,588 @@
+//! Mempool manager.
+//!
+//! Keeps a view of the unconfirmed transactions relevant to us. Peers which have our bloom
+//! filter loaded only announce transactions matching the filter, so every transaction announced
+//! by such a peer is fetched and kept, along with the time at which it was first seen.
+//!
+//! Transactions leave the mempool view once they are confirmed, or once a conflicting
+//! transaction is confirmed in their place. Both cases are reported via
+//! [`Event::TxStatusChanged`], with a [`TxStatus::Confirmed`] or [`TxStatus::Stale`] status.
+//!
+//! Since matched transactions are sent by peers *after* the merkle block that includes them,
+//! transactions matched in a merkle block are remembered until their data is received.
+//!
+//! ## Double-spends
+//!
+//! When an unconfirmed transaction spends an output already spent by another unconfirmed
+//! transaction, an [`Event::DoubleSpendDetected`] event is emitted. Both transactions are kept
+//! until one of them confirms, at which point the other is marked stale.
+//!
+//! Nodes also relay double-spend proofs (`dsproof-beta`), which show that an output was spent
+//! twice without us having to see the second transaction. Proofs announced while we have
+//! unconfirmed transactions are fetched, and those proving that one of our transactions is
+//! double-spent are reported via [`Event::DoubleSpendProofReceived`].
+//!
+use nakamoto_common::bitcoin::network::message::NetworkMessage;
+use nakamoto_common::bitcoin::network::message_blockdata::Inventory;
+use nakamoto_common::bitcoin::network::message_dsproof::DSProof;
+use nakamoto_common::bitcoin::{DSProofId, OutPoint, Transaction, Txid};
+use nakamoto_common::block::time::{Clock, LocalDuration, LocalTime};
+use nakamoto_common::block::{BlockHash, Height};
+use nakamoto_common::collections::{HashMap, HashSet};
+
+use super::output::{Io, Outbox};
+use super::{event::TxStatus, Event, PeerId};
+
+/// Time to wait for a requested transaction before it can be requested again.
+pub const REQUEST_TIMEOUT: LocalDuration = LocalDuration::from_secs(30);
+
+/// Time between idles.
+pub const IDLE_TIMEOUT: LocalDuration = LocalDuration::from_secs(60);
+
+/// Time after which unconfirmed transactions are dropped from the mempool view.
+pub const MEMPOOL_EXPIRY: LocalDuration = LocalDuration::from_mins(60 * 72);
+
+/// Maximum number of transactions kept in the mempool view.
+pub const MAX_MEMPOOL_ENTRIES: usize = 4096;
+
+/// An unconfirmed transaction in the mempool view.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// The transaction.
+    pub transaction: Transaction,
+    /// Time at which the transaction was first seen.
+    pub first_seen: LocalTime,
+    /// Peer that sent us the transaction.
+    pub peer: PeerId,
+}
+
+/// Mempool manager state.
+#[derive(Debug)]
+pub struct MempoolManager<C> {
+    /// Unconfirmed transactions.
+    entries: HashMap<Txid, Entry>,
+    /// Outputs spent by unconfirmed transactions. More than one transaction spending the
+    /// same output means they are double-spends of each other.
+    spent: HashMap<OutPoint, Vec<Txid>>,
+    /// Transactions requested, with the peer they were requested from and when.
+    requested: HashMap<Txid, (PeerId, LocalTime)>,
+    /// Transactions matched in a merkle block, waiting for their data.
+    matched: HashMap<Txid, (Height, BlockHash)>,
+    /// Peers with our bloom filter loaded.
+    filtered: HashSet<PeerId>,
+    /// Double-spend proofs requested or received.
+    proofs: HashSet<DSProofId>,
+
+    last_idle: Option<LocalTime>,
+    outbox: Outbox,
+    clock: C,
+}
+
+impl<C> Iterator for MempoolManager<C> {
+    type Item = Io;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.outbox.next()
+    }
+}
+
+impl<C: Clock> MempoolManager<C> {
+    /// Create a new mempool manager.
+    pub fn new(rng: fastrand::Rng, clock: C) -> Self {
+        Self {
+            entries: HashMap::with_hasher(rng.clone().into()),
+            spent: HashMap::with_hasher(rng.clone().into()),
+            requested: HashMap::with_hasher(rng.clone().into()),
+            matched: HashMap::with_hasher(rng.clone().into()),
+            filtered: HashSet::with_hasher(rng.clone().into()),
+            proofs: HashSet::with_hasher(rng.into()),
+            last_idle: None,
+            outbox: Outbox::default(),
+            clock,
+        }
+    }
+
+    /// Number of transactions in the mempool view.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[cfg(test)]
+    /// Lookup an unconfirmed transaction.
+    pub fn get(&self, txid: &Txid) -> Option<&Entry> {
+        self.entries.get(txid)
+    }
+
+    /// Event received.
+    pub fn received_event(&mut self, event: Event) {
+        match event {
+            Event::PeerLoadedBloomFilter { peer, .. } => {
+                self.filtered.insert(peer);
+            }
+            Event::PeerDisconnected { addr, .. } => {
+                self.filtered.remove(&addr);
+                self.requested.retain(|_, (peer, _)| *peer != addr);
+            }
+            Event::BlockHeadersImported { reverted, .. } => {
+                for (height, _) in reverted {
+                    self.matched.retain(|_, (h, _)| *h != height);
+                }
+            }
+            Event::ReceivedMerkleBlock {
+                height,
+                merkle_block,
+                matches,
+                ..
+            } => {
+                self.received_merkle_block(merkle_block.header.block_hash(), matches, height);
+            }
+            Event::BlockMatched { height, block } => {
+                let hash = block.block_hash();
+
+                for tx in &block.txdata {
+                    self.confirmed(tx, height, hash);
+                }
+            }
+            Event::MessageReceived { from, message } => match message.as_ref() {
+                NetworkMessage::Inv(invs) => {
+                    self.received_inv(from, invs);
+                }
+                NetworkMessage::Tx(tx) => {
+                    self.received_tx(from, tx);
+                }
+                NetworkMessage::DSProof(proof) => {
+                    self.received_dsproof(from, proof);
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    /// Called when we receive a tick.
+    pub fn timer_expired(&mut self) {
+        let now = self.clock.local_time();
+        if now - self.last_idle.unwrap_or_default() >= IDLE_TIMEOUT {
+            self.last_idle = Some(now);
+            self.outbox.set_timer(IDLE_TIMEOUT);
+        }
+        // Allow timed out requests to be made again on the next announcement.
+        self.requested
+            .retain(|_, (_, requested_at)| now - *requested_at < REQUEST_TIMEOUT);
+
+        let expired = self
+            .entries
+            .iter()
+            .filter(|(_, e)| now - e.first_seen >= MEMPOOL_EXPIRY)
+            .map(|(txid, _)| *txid)
+            .collect::<Vec<_>>();
+
+        for txid in expired {
+            if let Some(entry) = self.remove(&txid) {
+                log::debug!(
+                    target: "p2p",
+                    "Transaction {} from {} expired from mempool", txid, entry.peer
+                );
+            }
+        }
+        // Proofs can only be about transactions in the mempool view.
+        if self.entries.is_empty() {
+            self.proofs.clear();
+        }
+    }
+
+    /// Called when an `inv` is received from a peer.
+    fn received_inv(&mut self, from: PeerId, invs: &[Inventory]) {
+        let now = self.clock.local_time();
+        let mut request = Vec::new();
+
+        for inv in invs {
+            match inv {
+                // Peers without our filter announce all transactions, most of which aren't ours.
+                Inventory::Transaction(txid) if self.filtered.contains(&from) => {
+                    if self.entries.contains_key(txid) || self.requested.contains_key(txid) {
+                        continue;
+                    }
+                    self.requested.insert(*txid, (from, now));
+                    request.push(Inventory::Transaction(*txid));
+                }
+                // Without unconfirmed transactions, there is nothing a proof could be about.
+                Inventory::DoubleSpendProof(id) if !self.entries.is_empty() => {
+                    if self.proofs.insert(*id) {
+                        request.push(Inventory::DoubleSpendProof(*id));
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !request.is_empty() {
+            self.outbox.get_data(from, request);
+            self.outbox.set_timer(REQUEST_TIMEOUT);
+        }
+    }
+
+    /// Called when a transaction is received from a peer.
+    fn received_tx(&mut self, from: PeerId, tx: &Transaction) {
+        let txid = tx.txid();
+
+        self.requested.remove(&txid);
+
+        if let Some((height, block)) = self.matched.remove(&txid) {
+            self.confirmed(tx, height, block);
+            return;
+        }
+        if self.entries.contains_key(&txid) {
+            return;
+        }
+        if self.entries.len() >= MAX_MEMPOOL_ENTRIES {
+            log::debug!(target: "p2p", "Mempool is full, ignoring transaction {}", txid);
+            return;
+        }
+        let mut conflicts = Vec::new();
+
+        for input in &tx.input {
+            let spenders = self.spent.entry(input.previous_output).or_default();
+
+            for original in spenders.iter() {
+                if !conflicts.contains(original) {
+                    conflicts.push(*original);
+                }
+            }
+            spenders.push(txid);
+        }
+        for original in conflicts {
+            log::warn!(
+                target: "p2p",
+                "Transaction {} from {} double-spends transaction {}", txid, from, original
+            );
+            self.outbox.event(Event::DoubleSpendDetected {
+                original,
+                conflicting: txid,
+            });
+        }
+        self.entries.insert(
+            txid,
+            Entry {
+                transaction: tx.clone(),
+                first_seen: self.clock.local_time(),
+                peer: from,
+            },
+        );
+    }
+
+    /// Called when a double-spend proof is received from a peer.
+    fn received_dsproof(&mut self, from: PeerId, proof: &DSProof) {
+        let id = proof.id();
+        self.proofs.insert(id);
+
+        let spenders = match self.spent.get(&proof.outpoint) {
+            Some(spenders) => spenders.clone(),
+            None => return,
+        };
+        for txid in spenders {
+            let Some(entry) = self.entries.get(&txid) else {
+                continue;
+            };
+            match proof.validate(&entry.transaction) {
+                Ok(()) => {
+                    log::warn!(
+                        target: "p2p",
+                        "Received proof {} from {} that transaction {} is double-spent", id, from, txid
+                    );
+                    self.outbox.event(Event::DoubleSpendProofReceived {
+                        txid,
+                        outpoint: proof.outpoint,
+                        proof: id,
+                    });
+                }
+                Err(err) => {
+                    log::debug!(
+                        target: "p2p",
+                        "Invalid double-spend proof {} from {}: {}", id, from, err
+                    );
+                }
+            }
+        }
+    }
+
+    /// Called when a merkle block is received.
+    fn received_merkle_block(&mut self, hash: BlockHash, matches: Vec<Txid>, height: Height) {
+        for txid in matches {
+            if let Some(tx) = self.entries.get(&txid).map(|e| e.transaction.clone()) {
+                self.confirmed(&tx, height, hash);
+            } else {
+                self.matched.insert(txid, (height, hash));
+            }
+        }
+    }
+
+    /// Called when a transaction is confirmed in a block. Removes it from the mempool view,
+    /// along with any transaction it conflicts with.
+    fn confirmed(&mut self, tx: &Transaction, height: Height, block: BlockHash) {
+        let txid = tx.txid();
+
+        if self.remove(&txid).is_some() {
+            self.outbox.event(Event::TxStatusChanged {
+                txid,
+                status: TxStatus::Confirmed { height, block },
+            });
+        }
+        let conflicts = tx
+            .input
+            .iter()
+            .filter_map(|input| self.spent.get(&input.previous_output))
+            .flatten()
+            .filter(|other| **other != txid)
+            .copied()
+            .collect::<Vec<_>>();
+
+        for conflict in conflicts {
+            if self.remove(&conflict).is_some() {
+                self.outbox.event(Event::TxStatusChanged {
+                    txid: conflict,
+                    status: TxStatus::Stale {
+                        replaced_by: txid,
+                        block,
+                    },
+                });
+            }
+        }
+    }
+
+    /// Remove a transaction from the mempool view.
+    fn remove(&mut self, txid: &Txid) -> Option<Entry> {
+        let entry = self.entries.remove(txid)?;
+
+        for input in &entry.transaction.input {
+            if let Some(spenders) = self.spent.get_mut(&input.previous_output) {
+                spenders.retain(|t| t != txid);
+
+                if spenders.is_empty() {
+                    self.spent.remove(&input.previous_output);
+                }
+            }
+        }
+        Some(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net;
+
+    use crate::fsm::network::Network;
+    use crate::fsm::output;
+
+    use nakamoto_common::bitcoin::network::message_bloom::{BloomFlags, FilterLoad};
+    use nakamoto_common::bitcoin::network::message_dsproof::Spender;
+    use nakamoto_common::bitcoin::MerkleBlock;
+    use nakamoto_common::block::time::RefClock;
+    use nakamoto_test::assert_matches;
+    use nakamoto_test::block::gen;
+
+    fn filter_loaded(mgr: &mut MempoolManager<RefClock>, peer: PeerId) {
+        mgr.received_event(Event::PeerLoadedBloomFilter {
+            filter: FilterLoad {
+                filter: vec![],
+                hash_funcs: 1,
+                tweak: 0,
+                flags: BloomFlags::None,
+            },
+            peer,
+            segment: None,
+        });
+    }
+
+    fn received(mgr: &mut MempoolManager<RefClock>, from: PeerId, message: NetworkMessage) {
+        mgr.received_event(Event::MessageReceived {
+            from,
+            message: message.into(),
+        });
+    }
+
+    #[test]
+    fn test_inv_getdata() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        let clock = RefClock::from(LocalTime::now());
+        let filtered: net::SocketAddr = ([88, 88, 88, 88], 8333).into();
+        let unfiltered: net::SocketAddr = ([99, 99, 99, 99], 8333).into();
+        let tx = gen::transaction(&mut rng);
+        let inv = vec![Inventory::Transaction(tx.txid())];
+
+        let mut mgr = MempoolManager::new(rng, clock.clone());
+        filter_loaded(&mut mgr, filtered);
+
+        received(&mut mgr, unfiltered, NetworkMessage::Inv(inv.clone()));
+        assert_eq!(output::test::messages(&mut mgr).count(), 0);
+
+        received(&mut mgr, filtered, NetworkMessage::Inv(inv.clone()));
+        output::test::messages(&mut mgr)
+            .find(|(a, m)| a == &filtered && matches!(m, NetworkMessage::GetData(i) if i == &inv))
+            .expect("The transaction is requested");
+
+        // Already requested.
+        received(&mut mgr, filtered, NetworkMessage::Inv(inv.clone()));
+        assert_eq!(output::test::messages(&mut mgr).count(), 0);
+
+        clock.elapse(LocalDuration::from_mins(1));
+        received(&mut mgr, filtered, NetworkMessage::Tx(tx.clone()));
+
+        let entry = mgr.get(&tx.txid()).unwrap();
+        assert_eq!(entry.first_seen, clock.local_time());
+        assert_eq!(entry.peer, filtered);
+        assert!(mgr.requested.is_empty());
+
+        // Expired.
+        clock.elapse(MEMPOOL_EXPIRY);
+        mgr.timer_expired();
+        assert_eq!(mgr.len(), 0);
+    }
+
+    #[test]
+    fn test_double_spend_detected() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        let clock = RefClock::from(LocalTime::now());
+        let peer: net::SocketAddr = ([88, 88, 88, 88], 8333).into();
+        let genesis = Network::Regtest.genesis();
+
+        let original = gen::transaction(&mut rng);
+        let double_spend = gen::transaction_with(original.input[0].previous_output, 1000, &mut rng);
+
+        let mut mgr = MempoolManager::new(rng.clone(), clock);
+        received(&mut mgr, peer, NetworkMessage::Tx(original.clone()));
+        assert_eq!(output::test::events(mgr.outbox.drain()).count(), 0);
+
+        received(&mut mgr, peer, NetworkMessage::Tx(double_spend.clone()));
+        assert_matches!(
+            output::test::events(mgr.outbox.drain()).collect::<Vec<_>>().as_slice(),
+            [Event::DoubleSpendDetected { original: o, conflicting: c }]
+            if *o == original.txid() && *c == double_spend.txid()
+        );
+        assert_eq!(mgr.len(), 2);
+
+        // The double-spend confirms, and the original becomes stale.
+        let block = gen::block_with(
+            &genesis,
+            vec![gen::coinbase(&mut rng), double_spend.clone()],
+            &mut rng,
+        );
+        mgr.received_event(Event::BlockMatched { height: 1, block });
+        assert_eq!(mgr.len(), 0);
+        assert!(mgr.spent.is_empty());
+        assert!(output::test::events(mgr.outbox.drain()).any(|e| matches!(e,
+            Event::TxStatusChanged { txid, status: TxStatus::Stale { replaced_by, .. } }
+            if txid == original.txid() && replaced_by == double_spend.txid()
+        )));
+    }
+
+    #[test]
+    fn test_dsproof() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        let clock = RefClock::from(LocalTime::now());
+        let peer: net::SocketAddr = ([88, 88, 88, 88], 8333).into();
+
+        let tx = gen::transaction(&mut rng);
+        let outpoint = tx.input[0].previous_output;
+        let double_spend = gen::transaction_with(outpoint, 1000, &mut rng);
+        let proof = DSProof {
+            outpoint,
+            first_spender: Spender::from_tx(&tx, 0, vec![vec![1; 65]]),
+            double_spender: Spender::from_tx(&double_spend, 0, vec![vec![2; 65]]),
+        };
+        let proof = if proof.validate(&tx).is_ok() {
+            proof
+        } else {
+            DSProof {
+                outpoint,
+                first_spender: proof.double_spender,
+                double_spender: proof.first_spender,
+            }
+        };
+        let inv = vec![Inventory::DoubleSpendProof(proof.id())];
+
+        let mut mgr = MempoolManager::new(rng, clock);
+
+        // Proofs aren't fetched without unconfirmed transactions.
+        received(&mut mgr, peer, NetworkMessage::Inv(inv.clone()));
+        assert_eq!(output::test::messages(&mut mgr).count(), 0);
+
+        received(&mut mgr, peer, NetworkMessage::Tx(tx.clone()));
+        received(&mut mgr, peer, NetworkMessage::Inv(inv.clone()));
+        output::test::messages(&mut mgr)
+            .find(|(_, m)| matches!(m, NetworkMessage::GetData(i) if i == &inv))
+            .expect("The proof is requested");
+
+        received(&mut mgr, peer, NetworkMessage::DSProof(proof.clone()));
+        assert_matches!(
+            output::test::events(mgr.outbox.drain()).collect::<Vec<_>>().as_slice(),
+            [Event::DoubleSpendProofReceived { txid, outpoint: o, proof: id }]
+            if *txid == tx.txid() && *o == outpoint && *id == proof.id()
+        );
+
+        // Proofs are only requested once.
+        received(&mut mgr, peer, NetworkMessage::Inv(inv));
+        assert_eq!(output::test::messages(&mut mgr).count(), 0);
+    }
+
+    #[test]
+    fn test_confirmed_and_stale() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        let clock = RefClock::from(LocalTime::now());
+        let peer: net::SocketAddr = ([88, 88, 88, 88], 8333).into();
+        let genesis = Network::Regtest.genesis();
+
+        let tx = gen::transaction(&mut rng);
+        let double_spend = gen::transaction_with(tx.input[0].previous_output, 1000, &mut rng);
+        let unrelated = gen::transaction(&mut rng);
+        let block = gen::block_with(
+            &genesis,
+            vec![gen::coinbase(&mut rng), double_spend.clone()],
+            &mut rng,
+        );
+        let merkle_block =
+            MerkleBlock::from_block_with_predicate(&block, |t| *t == double_spend.txid());
+
+        let mut mgr = MempoolManager::new(rng, clock);
+        filter_loaded(&mut mgr, peer);
+        received(&mut mgr, peer, NetworkMessage::Tx(tx.clone()));
+        received(&mut mgr, peer, NetworkMessage::Tx(unrelated.clone()));
+        assert_eq!(mgr.len(), 2);
+
+        // The double-spend is confirmed once its data is received after the merkle block.
+        mgr.received_event(Event::ReceivedMerkleBlock {
+            height: 1,
+            merkle_block,
+            matches: vec![double_spend.txid()],
+            peer,
+        });
+        assert_eq!(mgr.len(), 2);
+        received(&mut mgr, peer, NetworkMessage::Tx(double_spend.clone()));
+
+        assert!(mgr.get(&tx.txid()).is_none());
+        assert!(mgr.get(&double_spend.txid()).is_none());
+        assert!(mgr.get(&unrelated.txid()).is_some());
+
+        let events = output::test::events(mgr.outbox.drain()).collect::<Vec<_>>();
+        assert!(events.iter().any(|e| matches!(e,
+            Event::TxStatusChanged { txid, status: TxStatus::Stale { replaced_by, .. } }
+            if *txid == tx.txid() && *replaced_by == double_spend.txid()
+        )));
+
+        // A transaction in the view is confirmed by a full block.
+        let block = gen::block_with(
+            &genesis,
+            vec![gen::coinbase(&mut fastrand::Rng::new()), unrelated.clone()],
+            &mut fastrand::Rng::new(),
+        );
+        mgr.received_event(Event::BlockMatched { height: 1, block });
+        assert_eq!(mgr.len(), 0);
+
+        let events = output::test::events(mgr.outbox.drain()).collect::<Vec<_>>();
+        assert!(events.iter().any(|e| matches!(e,
+            Event::TxStatusChanged { txid, status: TxStatus::Confirmed { height: 1, .. } }
+            if *txid == unrelated.txid()
+        )));
+    }
+}
@@ -0,0 +1,551 @@
+//! Merkle block manager.
+//!
+//! Serves filtered blocks to BIP 37 clients, when we advertise `NODE_BLOOM`.
+//! Inbound peers may load a bloom filter with `filterload`, update it with `filteradd` and
+//! remove it with `filterclear`. Requests for filtered blocks are answered with a
+//! `merkleblock` built from the peer's filter, followed by the matched transactions.
+//!
+//! Since we don't keep full blocks, the most recently processed blocks are cached, and
+//! recent blocks that aren't cached are downloaded on demand. Requests for blocks that
+//! aren't on the active chain, or are buried too deep, are answered with a `notfound`.
+//!
+//! Filter sizes are bounded as specified by BIP 37, and the number of filtered blocks each
+//! peer can request is rate-limited, so that peers can't use this to exhaust our resources.
+use std::collections::VecDeque;
+
+use nakamoto_common::bitcoin::blockdata::opcodes;
+use nakamoto_common::bitcoin::blockdata::script::Instruction;
+use nakamoto_common::bitcoin::consensus::encode::serialize;
+use nakamoto_common::bitcoin::network::message::NetworkMessage;
+use nakamoto_common::bitcoin::network::message_blockdata::Inventory;
+use nakamoto_common::bitcoin::network::message_bloom::{BloomFlags, FilterLoad};
+use nakamoto_common::bitcoin::util::bloom::{BloomFilter, MAX_BLOOM_FILTER_SIZE, MAX_HASH_FUNCS};
+use nakamoto_common::bitcoin::{Block, MerkleBlock, OutPoint, Script, Transaction, Txid};
+use nakamoto_common::block::time::{Clock, LocalDuration, LocalTime};
+use nakamoto_common::block::tree::BlockReader;
+use nakamoto_common::block::{BlockHash, Height};
+use nakamoto_common::collections::{AddressBook, HashMap, HashSet};
+
+use super::bfmgr::MAX_FILTER_ADD_SIZE;
+use super::output::{Io, Outbox};
+use super::{BlockSource, Event, Link, PeerId};
+
+/// Number of recently processed blocks kept in memory, to be served as filtered blocks.
+pub const MAX_CACHED_BLOCKS: usize = 6;
+/// Depth past which blocks are no longer downloaded to be served as filtered blocks.
+pub const MAX_BLOCK_DEPTH: Height = 144;
+/// Maximum number of filtered blocks a peer may request per [`RATE_LIMIT_INTERVAL`].
+pub const MAX_FILTERED_BLOCKS: usize = 120;
+/// Interval over which filtered block requests are counted.
+pub const RATE_LIMIT_INTERVAL: LocalDuration = LocalDuration::from_mins(1);
+
+/// Merkle block manager configuration.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Whether filtered blocks are served. This is the case if we advertise `NODE_BLOOM`.
+    pub enabled: bool,
+    /// Maximum number of filtered blocks a peer may request per [`RATE_LIMIT_INTERVAL`].
+    pub max_filtered_blocks: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_filtered_blocks: MAX_FILTERED_BLOCKS,
+        }
+    }
+}
+
+/// A BIP 37 client.
+#[derive(Debug)]
+struct Peer {
+    /// Filter loaded by the peer, if any.
+    filter: Option<(BloomFilter, BloomFlags)>,
+    /// Start of the current rate-limiting interval.
+    since: LocalTime,
+    /// Filtered blocks requested during the current rate-limiting interval.
+    requested: usize,
+}
+
+impl Peer {
+    /// Count a filtered block request. Returns `false` if the peer is over its limit.
+    fn request(&mut self, now: LocalTime, limit: usize) -> bool {
+        if now - self.since >= RATE_LIMIT_INTERVAL {
+            self.since = now;
+            self.requested = 0;
+        }
+        self.requested += 1;
+        self.requested <= limit
+    }
+}
+
+/// Merkle block manager state.
+#[derive(Debug)]
+pub struct MerkleManager<C> {
+    config: Config,
+    /// BIP 37 clients.
+    peers: AddressBook<PeerId, Peer>,
+    /// Recently processed blocks, oldest first.
+    blocks: VecDeque<Block>,
+    /// Blocks being downloaded, and the peers waiting for them.
+    pending: HashMap<BlockHash, Vec<PeerId>>,
+    outbox: Outbox,
+    clock: C,
+}
+
+impl<C> Iterator for MerkleManager<C> {
+    type Item = Io;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.outbox.next()
+    }
+}
+
+impl<C: Clock> MerkleManager<C> {
+    /// Create a new merkle block manager.
+    pub fn new(config: Config, rng: fastrand::Rng, clock: C) -> Self {
+        Self {
+            config,
+            peers: AddressBook::new(rng.clone()),
+            blocks: VecDeque::new(),
+            pending: HashMap::with_hasher(rng.into()),
+            outbox: Outbox::default(),
+            clock,
+        }
+    }
+
+    /// Event received.
+    pub fn received_event<T: BlockReader, B: BlockSource>(
+        &mut self,
+        event: Event,
+        tree: &T,
+        blocks: &mut B,
+    ) {
+        if !self.config.enabled {
+            return;
+        }
+        match event {
+            Event::PeerNegotiated {
+                addr,
+                link: Link::Inbound,
+                ..
+            } => {
+                self.peers.insert(
+                    addr,
+                    Peer {
+                        filter: None,
+                        since: self.clock.local_time(),
+                        requested: 0,
+                    },
+                );
+            }
+            Event::PeerDisconnected { addr, .. } => {
+                self.peers.remove(&addr);
+
+                for peers in self.pending.values_mut() {
+                    peers.retain(|p| *p != addr);
+                }
+            }
+            Event::BlockProcessed { block, .. } => {
+                self.block_processed(block);
+            }
+            Event::MessageReceived { from, message } => match message.as_ref() {
+                NetworkMessage::FilterLoad(msg) => {
+                    self.received_filterload(from, msg);
+                }
+                NetworkMessage::FilterAdd(msg) => {
+                    self.received_filteradd(from, &msg.data);
+                }
+                NetworkMessage::FilterClear => {
+                    if let Some(peer) = self.peers.get_mut(&from) {
+                        peer.filter = None;
+                    }
+                }
+                NetworkMessage::GetData(invs) => {
+                    self.received_getdata(from, invs, tree, blocks);
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+
+    /// Called when a `filterload` is received from a peer.
+    fn received_filterload(&mut self, addr: PeerId, msg: &FilterLoad) {
+        let Some(peer) = self.peers.get_mut(&addr) else {
+            return;
+        };
+        if msg.filter.len() > MAX_BLOOM_FILTER_SIZE || msg.hash_funcs > MAX_HASH_FUNCS {
+            self.outbox.event(Event::PeerMisbehaved {
+                addr,
+                reason: "invalid `filterload` message",
+            });
+            return;
+        }
+        let filter = BloomFilter {
+            content: msg.filter.clone(),
+            hashes: msg.hash_funcs,
+            tweak: msg.tweak,
+            flags: 0,
+        };
+        peer.filter = Some((filter, msg.flags));
+    }
+
+    /// Called when a `filteradd` is received from a peer.
+    fn received_filteradd(&mut self, addr: PeerId, data: &[u8]) {
+        let Some(peer) = self.peers.get_mut(&addr) else {
+            return;
+        };
+        match &mut peer.filter {
+            Some((filter, _)) if data.len() <= MAX_FILTER_ADD_SIZE => {
+                filter.insert(data);
+            }
+            _ => {
+                self.outbox.event(Event::PeerMisbehaved {
+                    addr,
+                    reason: "invalid `filteradd` message",
+                });
+            }
+        }
+    }
+
+    /// Called when a `getdata` is received from a peer. Only filtered blocks are handled.
+    fn received_getdata<T: BlockReader, B: BlockSource>(
+        &mut self,
+        addr: PeerId,
+        invs: &[Inventory],
+        tree: &T,
+        blocks: &mut B,
+    ) {
+        let now = self.clock.local_time();
+        let limit = self.config.max_filtered_blocks;
+        let Some(peer) = self.peers.get_mut(&addr) else {
+            return;
+        };
+        if peer.filter.is_none() {
+            return;
+        }
+        let mut notfound = Vec::new();
+
+        for inv in invs {
+            let Inventory::FilteredBlock(hash) = inv else {
+                continue;
+            };
+            if !peer.request(now, limit) {
+                log::debug!(target: "p2p", "Peer {} exceeded its filtered block limit", addr);
+                notfound.push(inv.clone());
+                continue;
+            }
+            if let Some(block) = self.blocks.iter().find(|b| b.block_hash() == *hash) {
+                if let Some((filter, flags)) = &mut peer.filter {
+                    for msg in self::filtered(block, filter, *flags) {
+                        self.outbox.message(addr, msg);
+                    }
+                }
+                continue;
+            }
+            // Recent blocks on the active chain are downloaded, and served once processed.
+            match tree.get_block(hash) {
+                Some((height, _)) if tree.height() - height <= MAX_BLOCK_DEPTH => {
+                    if !self.pending.contains_key(hash) {
+                        blocks.get_block(height, *hash);
+                    }
+                    let peers = self.pending.entry(*hash).or_default();
+                    if !peers.contains(&addr) {
+                        peers.push(addr);
+                    }
+                }
+                _ => notfound.push(inv.clone()),
+            }
+        }
+        if !notfound.is_empty() {
+            self.outbox
+                .message(addr, NetworkMessage::NotFound(notfound));
+        }
+    }
+
+    /// Called when a block was processed. Serves the peers waiting for it.
+    fn block_processed(&mut self, block: Block) {
+        let hash = block.block_hash();
+
+        for addr in self.pending.remove(&hash).into_iter().flatten() {
+            if let Some((filter, flags)) = self.peers.get_mut(&addr).and_then(|p| p.filter.as_mut())
+            {
+                for msg in self::filtered(&block, filter, *flags) {
+                    self.outbox.message(addr, msg);
+                }
+            }
+        }
+        if self.blocks.iter().all(|b| b.block_hash() != hash) {
+            self.blocks.push_back(block);
+        }
+        if self.blocks.len() > MAX_CACHED_BLOCKS {
+            self.blocks.pop_front();
+        }
+    }
+}
+
+/// Build the messages answering a request for a filtered block: a `merkleblock`, followed by
+/// the matched transactions.
+fn filtered(block: &Block, filter: &mut BloomFilter, flags: BloomFlags) -> Vec<NetworkMessage> {
+    let matches = block
+        .txdata
+        .iter()
+        .filter(|tx| self::is_match(tx, filter, flags))
+        .collect::<Vec<_>>();
+    let txids = matches
+        .iter()
+        .map(|tx| tx.txid())
+        .collect::<HashSet<Txid>>();
+    let merkle_block = MerkleBlock::from_block_with_predicate(block, |txid| txids.contains(txid));
+
+    let mut msgs = vec![NetworkMessage::MerkleBlock(merkle_block)];
+    msgs.extend(matches.into_iter().cloned().map(NetworkMessage::Tx));
+    msgs
+}
+
+/// Check whether a transaction matches a filter, as specified by BIP 37. The outpoints of
+/// matching outputs are inserted into the filter, according to its update flags.
+fn is_match(tx: &Transaction, filter: &mut BloomFilter, flags: BloomFlags) -> bool {
+    let txid = tx.txid();
+    let mut matched = filter.contains(&txid[..]);
+
+    for (vout, output) in tx.output.iter().enumerate() {
+        let script = &output.script_pubkey;
+
+        if !self::pushes(script).any(|data| filter.contains(data)) {
+            continue;
+        }
+        matched = true;
+
+        let update = match flags {
+            BloomFlags::None => false,
+            BloomFlags::All => true,
+            BloomFlags::PubkeyOnly => script.is_p2pk() || self::is_multisig(script),
+        };
+        if update {
+            filter.insert(&serialize(&OutPoint::new(txid, vout as u32)));
+        }
+    }
+    if matched {
+        return true;
+    }
+    tx.input.iter().any(|input| {
+        filter.contains(&serialize(&input.previous_output))
+            || self::pushes(&input.script_sig).any(|data| filter.contains(data))
+    })
+}
+
+/// Non-empty data pushes of a script.
+fn pushes(script: &Script) -> impl Iterator<Item = &[u8]> {
+    script.instructions().filter_map(|i| match i {
+        Ok(Instruction::PushBytes(data)) if !data.is_empty() => Some(data),
+        _ => None,
+    })
+}
+
+/// Check whether a script is a bare multisig output script.
+fn is_multisig(script: &Script) -> bool {
+    script.as_bytes().last() == Some(&opcodes::all::OP_CHECKMULTISIG.to_u8())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use crate::fsm::network::Network;
+    use crate::fsm::{output, PROTOCOL_VERSION};
+
+    use nakamoto_common::bitcoin::network::address::Address;
+    use nakamoto_common::bitcoin::network::constants::ServiceFlags;
+    use nakamoto_common::bitcoin::network::message_bloom::FilterAdd;
+    use nakamoto_common::nonempty::NonEmpty;
+    use nakamoto_test::block::cache::model;
+    use nakamoto_test::block::gen;
+
+    type RefClock = nakamoto_common::block::time::RefClock<LocalTime>;
+
+    impl BlockSource for Vec<(Height, BlockHash)> {
+        fn get_block(&mut self, height: Height, hash: BlockHash) {
+            self.push((height, hash));
+        }
+    }
+
+    fn negotiated(mgr: &mut MerkleManager<RefClock>, addr: PeerId, tree: &model::Cache) {
+        mgr.received_event(
+            Event::PeerNegotiated {
+                addr,
+                link: Link::Inbound,
+                services: ServiceFlags::NONE,
+                persistent: false,
+                height: 0,
+                receiver: Address::new(&addr, ServiceFlags::NONE),
+                user_agent: String::new(),
+                version: PROTOCOL_VERSION,
+                relay: true,
+            },
+            tree,
+            &mut vec![],
+        );
+    }
+
+    fn received(
+        mgr: &mut MerkleManager<RefClock>,
+        from: PeerId,
+        message: NetworkMessage,
+        tree: &model::Cache,
+        blocks: &mut Vec<(Height, BlockHash)>,
+    ) {
+        mgr.received_event(
+            Event::MessageReceived {
+                from,
+                message: Arc::new(message),
+            },
+            tree,
+            blocks,
+        );
+    }
+
+    fn filter_load(elements: &[&[u8]]) -> FilterLoad {
+        let mut filter = BloomFilter::new(elements.len(), 0.0001, 1, 0);
+        for element in elements {
+            filter.insert(element);
+        }
+        FilterLoad {
+            filter: filter.content,
+            hash_funcs: filter.hashes,
+            tweak: filter.tweak,
+            flags: BloomFlags::None,
+        }
+    }
+
+    #[test]
+    fn test_serve_filtered_block() {
+        let mut rng = fastrand::Rng::with_seed(1);
+        let clock = RefClock::from(LocalTime::now());
+        let chain = gen::blockchain(Network::Regtest.genesis_block(), 4, &mut rng);
+        let headers = NonEmpty::from_vec(chain.iter().map(|b| b.header).collect()).unwrap();
+        let tree = model::Cache::from(headers);
+        let peer: PeerId = ([88, 88, 88, 88], 8333).into();
+        let block = &chain[3];
+        let tx = &block.txdata[0];
+        let mut requested = Vec::new();
+
+        let mut mgr = MerkleManager::new(
+            Config {
+                enabled: true,
+                ..Config::default()
+            },
+            rng,
+            clock,
+        );
+        negotiated(&mut mgr, peer, &tree);
+
+        // Filtered blocks aren't served without a filter loaded.
+        let getdata = NetworkMessage::GetData(vec![Inventory::FilteredBlock(block.block_hash())]);
+        received(&mut mgr, peer, getdata.clone(), &tree, &mut requested);
+        assert!(requested.is_empty());
+
+        let load = NetworkMessage::FilterLoad(filter_load(&[&tx.txid()[..]]));
+        received(&mut mgr, peer, load, &tree, &mut requested);
+        received(&mut mgr, peer, getdata, &tree, &mut requested);
+        assert_eq!(requested, vec![(3, block.block_hash())]);
+        assert_eq!(mgr.outbox.drain().count(), 0);
+
+        // Once the block is downloaded, the peer gets a merkle block, and the matching
+        // transaction.
+        mgr.received_event(
+            Event::BlockProcessed {
+                block: block.clone(),
+                height: 3,
+                fees: None,
+            },
+            &tree,
+            &mut requested,
+        );
+        let msgs = output::test::messages_from(mgr.outbox.drain(), &peer).collect::<Vec<_>>();
+        assert_eq!(msgs.len(), 2);
+        assert!(matches!(
+            &msgs[0],
+            NetworkMessage::MerkleBlock(mb) if mb.header == block.header
+        ));
+        assert_eq!(msgs[1], NetworkMessage::Tx(tx.clone()));
+
+        // Blocks off the active chain aren't found.
+        let unknown = gen::block(&block.header, &mut fastrand::Rng::with_seed(2));
+        let getdata = NetworkMessage::GetData(vec![Inventory::FilteredBlock(unknown.block_hash())]);
+        received(&mut mgr, peer, getdata, &tree, &mut requested);
+        assert!(matches!(
+            output::test::messages_from(mgr.outbox.drain(), &peer).next(),
+            Some(NetworkMessage::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_filter_limits() {
+        let rng = fastrand::Rng::with_seed(1);
+        let clock = RefClock::from(LocalTime::now());
+        let tree = model::Cache::new(Network::Regtest.genesis());
+        let genesis = Network::Regtest.genesis_block();
+        let peer: PeerId = ([88, 88, 88, 88], 8333).into();
+        let config = Config {
+            enabled: true,
+            max_filtered_blocks: 1,
+        };
+        let mut mgr = MerkleManager::new(config, rng, clock.clone());
+        let misbehaved = |mgr: &mut MerkleManager<RefClock>| {
+            output::test::events(mgr.outbox.drain())
+                .any(|e| matches!(e, Event::PeerMisbehaved { addr, .. } if addr == peer))
+        };
+        negotiated(&mut mgr, peer, &tree);
+
+        // Filters can't be added to before they're loaded.
+        let add = NetworkMessage::FilterAdd(FilterAdd { data: vec![1] });
+        received(&mut mgr, peer, add.clone(), &tree, &mut vec![]);
+        assert!(misbehaved(&mut mgr));
+
+        // Filters can't be larger than allowed.
+        let mut load = filter_load(&[&[1]]);
+        load.filter = vec![0; MAX_BLOOM_FILTER_SIZE + 1];
+        received(
+            &mut mgr,
+            peer,
+            NetworkMessage::FilterLoad(load),
+            &tree,
+            &mut vec![],
+        );
+        assert!(misbehaved(&mut mgr));
+
+        let load = filter_load(&[&[1]]);
+        received(
+            &mut mgr,
+            peer,
+            NetworkMessage::FilterLoad(load),
+            &tree,
+            &mut vec![],
+        );
+        received(&mut mgr, peer, add, &tree, &mut vec![]);
+        assert!(!misbehaved(&mut mgr));
+
+        // Requests past the rate limit aren't served until the interval is over.
+        mgr.block_processed(genesis.clone());
+        let getdata = NetworkMessage::GetData(vec![
+            Inventory::FilteredBlock(genesis.block_hash()),
+            Inventory::FilteredBlock(genesis.block_hash()),
+        ]);
+        received(&mut mgr, peer, getdata.clone(), &tree, &mut vec![]);
+
+        let msgs = output::test::messages_from(mgr.outbox.drain(), &peer).collect::<Vec<_>>();
+        assert!(matches!(msgs[0], NetworkMessage::MerkleBlock(_)));
+        assert!(matches!(&msgs[1], NetworkMessage::NotFound(invs) if invs.len() == 1));
+
+        clock.elapse(RATE_LIMIT_INTERVAL);
+        received(&mut mgr, peer, getdata, &tree, &mut vec![]);
+
+        let msgs = output::test::messages_from(mgr.outbox.drain(), &peer).collect::<Vec<_>>();
+        assert!(matches!(msgs[0], NetworkMessage::MerkleBlock(_)));
+    }
+}
@@ -17,6 +17,7 @@ use nakamoto_common::bitcoin::network::message_filter::{
 };
 use nakamoto_common::bitcoin::network::message_network::VersionMessage;
 use nakamoto_common::bitcoin::Transaction;
+use nakamoto_common::block::filter::BASIC_FILTER_TYPE;
 use nakamoto_common::block::time::LocalDuration;
 use nakamoto_common::block::{BlockHash, BlockHeader, BlockTime, Height};
 
@@ -126,6 +127,12 @@ impl Outbox {
         self
     }
 
+    /// Send a `sendaddrv2` message.
+    pub fn send_addr_v2(&mut self, addr: PeerId) -> &mut Self {
+        self.message(addr, NetworkMessage::SendAddrV2);
+        self
+    }
+
     /// Send a `sendheaders` message.
     pub fn send_headers(&mut self, addr: PeerId) -> &mut Self {
         self.message(addr, NetworkMessage::SendHeaders);
@@ -184,7 +191,7 @@ impl Outbox {
         self.message(
             addr,
             NetworkMessage::GetCFHeaders(GetCFHeaders {
-                filter_type: 0x0,
+                filter_type: BASIC_FILTER_TYPE,
                 start_height: start_height as u32,
                 stop_hash,
             }),
@@ -208,7 +215,7 @@ impl Outbox {
         self.message(
             addr,
             NetworkMessage::GetCFilters(GetCFilters {
-                filter_type: 0x0,
+                filter_type: BASIC_FILTER_TYPE,
                 start_height: start_height as u32,
                 stop_hash,
             }),
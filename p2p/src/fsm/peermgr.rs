@@ -15,6 +15,7 @@
 //!   4. Expect `verack` message from remote.
 //!
 use std::net;
+use std::sync::Arc;
 
 use nakamoto_common::bitcoin::network::address::Address;
 use nakamoto_common::bitcoin::network::constants::ServiceFlags;
@@ -22,7 +23,8 @@ use nakamoto_common::bitcoin::network::message::NetworkMessage;
 use nakamoto_common::bitcoin::network::message_network::VersionMessage;
 use nakamoto_common::block::tree::BlockReader;
 
-use nakamoto_common::p2p::peer::AddressSource;
+use nakamoto_common::p2p::netgroup::{Asmap, NetGroup};
+use nakamoto_common::p2p::peer::{AddressSource, Source};
 use nakamoto_common::p2p::Domain;
 
 use nakamoto_common::block::time::{AdjustedClock, Clock, LocalDuration, LocalTime};
@@ -46,8 +48,21 @@ pub const CONNECTION_TIMEOUT: LocalDuration = LocalDuration::from_secs(6);
 pub const IDLE_TIMEOUT: LocalDuration = LocalDuration::from_mins(1);
 /// Target number of concurrent outbound peer connections.
 pub const TARGET_OUTBOUND_PEERS: usize = 8;
+/// Target number of concurrent outbound peer connections during initial block header sync,
+/// so that headers can be fetched from more peers.
+pub const SYNC_OUTBOUND_PEERS: usize = 12;
+/// Number of blocks we must fall behind our best peer by, once synced, to go back to the
+/// initial sync peer target. Keeps us from switching back and forth as new blocks are mined.
+pub const SYNC_PHASE_THRESHOLD: Height = 144;
 /// Maximum number of inbound peer connections.
 pub const MAX_INBOUND_PEERS: usize = 16;
+/// Minimum number of outbound peers advertising `NODE_BLOOM` to maintain. Bloom peers are the
+/// only ones we can scan the chain with, and are increasingly rare.
+pub const MIN_BLOOM_PEERS: usize = 2;
+/// Maximum number of anchor peers, ie. outbound peers we reconnect to first on startup.
+/// Reconnecting to peers we used before a restart makes it harder for an attacker to
+/// eclipse us by filling our address book.
+pub const MAX_ANCHORS: usize = 2;
 
 /// Maximum height difference for a stale peer, to maintain the connection (2 weeks).
 const MAX_STALE_HEIGHT_DIFFERENCE: Height = 2016;
@@ -73,6 +88,9 @@ pub struct Config {
     pub services: ServiceFlags,
     /// Peer addresses to persist connections with.
     pub persistent: Vec<net::SocketAddr>,
+    /// Outbound peers we were connected to before shutting down, to connect to first.
+    /// Ignored if persistent peers are configured.
+    pub anchors: Vec<net::SocketAddr>,
     /// Services required by peers.
     pub required_services: ServiceFlags,
     /// Peer services preferred. We try to maintain as many
@@ -80,16 +98,29 @@ pub struct Config {
     pub preferred_services: ServiceFlags,
     /// Target number of outbound peer connections.
     pub target_outbound_peers: usize,
+    /// Target number of outbound peer connections during initial block header sync. Only
+    /// used if above the steady-state target.
+    pub sync_outbound_peers: usize,
     /// Maximum number of inbound peer connections.
     pub max_inbound_peers: usize,
+    /// Minimum number of outbound peers advertising `NODE_BLOOM`. Outbound peers without it
+    /// are evicted to make room for bloom peers, if needed.
+    pub min_bloom_peers: usize,
     /// Maximum time to wait between reconnection attempts.
     pub retry_max_wait: LocalDuration,
     /// Minimum time to wait between reconnection attempts.
     pub retry_min_wait: LocalDuration,
     /// Our user agent.
-    pub user_agent: &'static str,
+    pub user_agent: String,
+    /// Whether to report our best height in `version` messages. If not, we report height zero.
+    pub report_height: bool,
+    /// Whether we want peers to announce transactions to us, before we load a bloom filter.
+    pub relay: bool,
     /// Supported communication domains.
     pub domains: Vec<Domain>,
+    /// Mapping of IP ranges to autonomous systems. When set, outbound peers are spread across
+    /// autonomous systems rather than address ranges.
+    pub asmap: Option<Arc<Asmap>>,
 }
 
 /// Peer negotiation (handshake) state.
@@ -181,6 +212,15 @@ pub struct PeerManager<C> {
     peers: HashMap<net::SocketAddr, Peer>,
     /// Peers that have been disconnected and a retry attempt is scheduled.
     disconnected: HashMap<net::SocketAddr, (Option<LocalTime>, usize)>,
+    /// Addresses banned by the user. We don't connect to, or accept connections from these.
+    banned: HashSet<net::IpAddr>,
+    /// Outbound peers advertising `NODE_BLOOM` that are being connected to, and haven't yet
+    /// negotiated.
+    bloom_pending: HashSet<net::SocketAddr>,
+    /// Last time we warned about a lack of bloom peers.
+    last_bloom_warning: Option<LocalTime>,
+    /// Whether we're in initial block header sync. We assume we are until told otherwise.
+    syncing: bool,
     outbox: Outbox,
     rng: fastrand::Rng,
     hooks: Hooks,
@@ -200,12 +240,18 @@ impl<C: AdjustedClock<PeerId>> PeerManager<C> {
     pub fn new(config: Config, rng: fastrand::Rng, hooks: Hooks, clock: C) -> Self {
         let peers = HashMap::with_hasher(rng.clone().into());
         let disconnected = HashMap::with_hasher(rng.clone().into());
+        let banned = HashSet::with_hasher(rng.clone().into());
+        let bloom_pending = HashSet::with_hasher(rng.clone().into());
 
         Self {
             config,
             last_idle: None,
             peers,
             disconnected,
+            banned,
+            bloom_pending,
+            last_bloom_warning: None,
+            syncing: true,
             outbox: Outbox::default(),
             rng,
             hooks,
@@ -222,6 +268,19 @@ impl<C: AdjustedClock<PeerId>> PeerManager<C> {
                 self.outbox.error(Error::ConnectionFailed { addr });
             }
         }
+        if self.config.persistent.is_empty() {
+            let anchors = self.config.anchors.clone();
+
+            for addr in anchors.into_iter().take(MAX_ANCHORS) {
+                if self.connect(&addr) {
+                    self.outbox.event(Event::PeerConnecting {
+                        addr,
+                        source: Source::Imported,
+                        services: ServiceFlags::NONE,
+                    });
+                }
+            }
+        }
         self.outbox.set_timer(IDLE_TIMEOUT);
         self.maintain_connections(addrs);
     }
@@ -235,6 +294,14 @@ impl<C: AdjustedClock<PeerId>> PeerManager<C> {
             Event::PeerMisbehaved { addr, reason } => {
                 self.disconnect(addr, DisconnectReason::PeerMisbehaving(reason));
             }
+            Event::Synchronizing { current, best, .. }
+                if !self.syncing && best.saturating_sub(current) > SYNC_PHASE_THRESHOLD =>
+            {
+                self.set_syncing(true);
+            }
+            Event::BlockHeadersSynced { .. } if self.syncing => {
+                self.set_syncing(false);
+            }
             Event::MessageReceived { from, message } => match message.as_ref() {
                 NetworkMessage::Version(msg) => {
                     self.received_version(&from, msg, tree.height());
@@ -323,6 +390,9 @@ impl<C: AdjustedClock<PeerId>> PeerManager<C> {
         self.disconnected.remove(&addr);
 
         match link {
+            Link::Inbound if self.banned.contains(&addr.ip()) => {
+                self._disconnect(addr, DisconnectReason::PeerBanned);
+            }
             Link::Inbound => {
                 if self.connected().filter(|c| c.link.is_inbound()).count()
                     >= self.config.max_inbound_peers
@@ -377,8 +447,9 @@ impl<C: AdjustedClock<PeerId>> PeerManager<C> {
             }
         }
         self.peers.remove(addr);
+        self.bloom_pending.remove(addr);
 
-        if self.config.persistent.contains(addr) {
+        if self.config.persistent.contains(addr) && !self.is_banned(addr) {
             self.persistent_disconnected(addr, local_time);
         } else {
             // If an outbound peer disconnected, we should make sure to maintain
@@ -423,7 +494,7 @@ impl<C: AdjustedClock<PeerId>> PeerManager<C> {
                 ..
             } = msg.clone();
 
-            let target = self.config.target_outbound_peers;
+            let target = self.target();
             let preferred = self.config.preferred_services;
             let trusted = self.config.whitelist.contains(&addr.ip(), &user_agent)
                 || addrmgr::is_local(&addr.ip());
@@ -469,26 +540,22 @@ impl<C: AdjustedClock<PeerId>> PeerManager<C> {
                 return Err(DisconnectReason::Other(reason));
             }
 
-            match conn.link {
-                Link::Inbound => {
-                    self.outbox
-                        .version(
-                            conn.addr,
-                            self.version(conn.addr, conn.local_addr, nonce, height, now),
-                        )
-                        // .wtxid_relay(conn.addr)
-                        .verack(conn.addr)
-                        .send_headers(conn.addr)
-                        .set_timer(HANDSHAKE_TIMEOUT);
-                }
-                Link::Outbound => {
-                    self.outbox
-                        // .wtxid_relay(conn.addr)
-                        .verack(conn.addr)
-                        .send_headers(conn.addr)
-                        .set_timer(HANDSHAKE_TIMEOUT);
-                }
+            if conn.link.is_inbound() {
+                self.outbox.version(
+                    conn.addr,
+                    self.version(conn.addr, conn.local_addr, nonce, height, now),
+                );
+            }
+            // Ask for `addrv2` messages if we can connect to onion services. This has to be
+            // sent before `verack`.
+            if self.config.domains.contains(&Domain::Onion) {
+                self.outbox.send_addr_v2(conn.addr);
             }
+            self.outbox
+                // .wtxid_relay(conn.addr)
+                .verack(conn.addr)
+                .send_headers(conn.addr)
+                .set_timer(HANDSHAKE_TIMEOUT);
             let conn = conn.clone();
             let persistent = self.config.persistent.contains(&conn.addr);
 
@@ -536,6 +603,7 @@ impl<C: AdjustedClock<PeerId>> PeerManager<C> {
                     relay: peer.relay,
                 });
                 self.clock.record_offset(*addr, peer.time_offset);
+                self.bloom_pending.remove(addr);
 
                 peer.state = HandshakeState::ReceivedVerack {
                     since: self.clock.local_time(),
@@ -605,7 +673,11 @@ impl<C: AdjustedClock<PeerId>> PeerManager<C> {
         start_height: Height,
         local_time: LocalTime,
     ) -> VersionMessage {
-        let start_height = start_height as i32;
+        let start_height = if self.config.report_height {
+            start_height as i32
+        } else {
+            0
+        };
         let timestamp = local_time.block_time() as i64;
 
         VersionMessage {
@@ -622,11 +694,11 @@ impl<C: AdjustedClock<PeerId>> PeerManager<C> {
             // A nonce to detect connections to self.
             nonce,
             // Our user agent string.
-            user_agent: self.config.user_agent.to_owned(),
+            user_agent: self.config.user_agent.clone(),
             // Our best height.
             start_height,
             // Whether we want to receive transaction `inv` messages.
-            relay: false,
+            relay: self.config.relay,
         }
     }
 }
@@ -703,6 +775,22 @@ impl<C: Clock> PeerManager<C> {
         })
     }
 
+    /// Target number of outbound peers, given the sync phase we're in.
+    pub fn target(&self) -> usize {
+        if self.syncing {
+            self.config
+                .sync_outbound_peers
+                .max(self.config.target_outbound_peers)
+        } else {
+            self.config.target_outbound_peers
+        }
+    }
+
+    /// Check whether we're in initial block header sync.
+    pub fn is_syncing(&self) -> bool {
+        self.syncing
+    }
+
     /// Iterator over fully negotiated peers.
     pub fn negotiated(&self, link: Link) -> impl Iterator<Item = (&PeerInfo, &Connection)> + Clone {
         self.peers()
@@ -716,6 +804,9 @@ impl<C: Clock> PeerManager<C> {
         if self.is_connected(addr) || self.is_connecting(addr) {
             return true;
         }
+        if self.banned.contains(&addr.ip()) {
+            return false;
+        }
         if !self.is_disconnected(addr) && !self.is_disconnecting(addr) {
             return false;
         }
@@ -736,6 +827,20 @@ impl<C: Clock> PeerManager<C> {
         }
     }
 
+    /// Ban a peer, disconnecting it if connected. The peer isn't connected to again, and its
+    /// inbound connections are refused.
+    pub fn ban(&mut self, addr: PeerId) {
+        self.banned.insert(addr.ip());
+        self.config.whitelist.addr.remove(&addr.ip());
+        self.disconnected.remove(&addr);
+        self.disconnect(addr, DisconnectReason::PeerBanned);
+    }
+
+    /// Check whether a peer is banned.
+    pub fn is_banned(&self, addr: &PeerId) -> bool {
+        self.banned.contains(&addr.ip())
+    }
+
     /// Disconnect a peer (internal).
     fn _disconnect(&mut self, addr: PeerId, reason: DisconnectReason) {
         self.outbox.disconnect(addr, reason);
@@ -767,7 +872,7 @@ impl<C: Clock> PeerManager<C> {
         // Above the target count, all peer connections without the preferred services are
         // automatically dropped. This ensures we never have more than the target of secondary
         // peers.
-        let target = self.config.target_outbound_peers;
+        let target = self.target();
         let unknown = connecting + connected;
         let total = primary + secondary + unknown;
         let max = target + target / 2;
@@ -792,11 +897,14 @@ impl<C: Clock> PeerManager<C> {
 
         let delta = self.delta();
         let negotiated = self.negotiated(Link::Outbound).count();
-        let target = self.config.target_outbound_peers;
+        let target = self.target();
 
         // Keep track of new addresses we're connecting to, and loop until
         // we've connected to enough addresses.
         let mut connecting = HashSet::with_hasher(self.rng.clone().into());
+        // Network groups we already have outbound connections to. We only make one outbound
+        // connection per group, so that a single operator can't control all of our peers.
+        let mut groups = self.outbound_groups();
 
         while connecting.len() < delta {
             if let Some((addr, source)) =
@@ -821,13 +929,14 @@ impl<C: Clock> PeerManager<C> {
                     // connections.
                     debug_assert!(!self.is_connected(&sockaddr));
 
+                    let group = self.group(&sockaddr);
+                    if group.map_or(false, |g| groups.contains(&g)) {
+                        continue;
+                    }
                     if self.connect(&sockaddr) {
                         connecting.insert(sockaddr);
-                        self.outbox.event(Event::PeerConnecting {
-                            addr: sockaddr,
-                            source,
-                            services: addr.services,
-                        });
+                        groups.extend(group);
+                        self.connecting_to(sockaddr, source, addr.services);
                     }
                 }
             } else {
@@ -838,6 +947,171 @@ impl<C: Clock> PeerManager<C> {
                 break;
             }
         }
+        self.maintain_bloom_peers(addrs);
+    }
+
+    /// Attempt to maintain the minimum number of outbound bloom peers, evicting outbound
+    /// peers without `NODE_BLOOM` to make room for them. Warns if not enough bloom peers can
+    /// be found.
+    fn maintain_bloom_peers<A: AddressSource>(&mut self, addrs: &mut A) {
+        let target = self.target();
+        let minimum = self.config.min_bloom_peers.min(target);
+        let services = self.config.required_services | ServiceFlags::BLOOM;
+        let connected = self
+            .negotiated(Link::Outbound)
+            .filter(|(p, _)| p.services.has(ServiceFlags::BLOOM))
+            .count();
+        let mut groups = self.outbound_groups();
+
+        while connected + self.bloom_pending.len() < minimum {
+            let Some((addr, source)) = addrs.sample(services) else {
+                break;
+            };
+            let Ok(sockaddr) = addr.socket_addr() else {
+                continue;
+            };
+            if !addr.services.has(ServiceFlags::BLOOM) {
+                continue;
+            }
+            let group = self.group(&sockaddr);
+            if group.map_or(false, |g| groups.contains(&g)) {
+                continue;
+            }
+            let outbound = self.negotiated(Link::Outbound).count() + self.connecting().count();
+
+            if self.connect(&sockaddr) {
+                groups.extend(group);
+                self.connecting_to(sockaddr, source, addr.services);
+
+                if outbound >= target {
+                    self.evict_non_bloom();
+                }
+            }
+        }
+
+        let available = connected + self.bloom_pending.len();
+        let local_time = self.clock.local_time();
+
+        if available < minimum
+            && self
+                .last_bloom_warning
+                .map_or(true, |t| local_time - t >= IDLE_TIMEOUT)
+        {
+            self.outbox
+                .event(Event::BloomPeersUnavailable { available, minimum });
+            self.last_bloom_warning = Some(local_time);
+        }
+    }
+
+    /// Enter or leave initial block header sync, and scale the outbound peer target
+    /// accordingly. More peers are connected to as connections are maintained, while excess
+    /// peers are evicted right away.
+    fn set_syncing(&mut self, syncing: bool) {
+        let previous = self.target();
+
+        self.syncing = syncing;
+
+        let target = self.target();
+
+        self.outbox
+            .event(Event::OutboundTargetChanged { syncing, target });
+
+        if target < previous {
+            self.scale_down();
+        }
+    }
+
+    /// Evict outbound peers above our target, starting with peers without our preferred
+    /// services. Persistent peers, and the minimum number of bloom peers, are kept.
+    fn scale_down(&mut self) {
+        let preferred = self.config.preferred_services;
+        let excess = self
+            .negotiated(Link::Outbound)
+            .count()
+            .saturating_sub(self.target());
+        let mut bloom = self
+            .negotiated(Link::Outbound)
+            .filter(|(p, _)| p.services.has(ServiceFlags::BLOOM))
+            .count();
+        let mut candidates = self
+            .negotiated(Link::Outbound)
+            .filter(|(p, _)| !p.persistent)
+            .map(|(p, c)| (c.addr, p.services))
+            .collect::<Vec<_>>();
+
+        self.rng.shuffle(&mut candidates);
+        candidates.sort_by_key(|(_, services)| services.has(preferred));
+
+        let mut evicted = 0;
+        for (addr, services) in candidates {
+            if evicted == excess {
+                break;
+            }
+            if services.has(ServiceFlags::BLOOM) {
+                if bloom <= self.config.min_bloom_peers {
+                    continue;
+                }
+                bloom -= 1;
+            }
+            self._disconnect(addr, DisconnectReason::ConnectionLimit);
+            evicted += 1;
+        }
+    }
+
+    /// Record an outbound connection attempt made by the peer manager.
+    fn connecting_to(&mut self, addr: PeerId, source: Source, services: ServiceFlags) {
+        if services.has(ServiceFlags::BLOOM) {
+            self.bloom_pending.insert(addr);
+        }
+        self.outbox.event(Event::PeerConnecting {
+            addr,
+            source,
+            services,
+        });
+    }
+
+    /// Disconnect a random outbound peer that doesn't advertise `NODE_BLOOM`, to make room
+    /// for one that does. Persistent peers are never evicted.
+    fn evict_non_bloom(&mut self) {
+        let candidates = self
+            .negotiated(Link::Outbound)
+            .filter(|(p, _)| !p.services.has(ServiceFlags::BLOOM) && !p.persistent)
+            .map(|(_, c)| c.addr)
+            .collect::<Vec<_>>();
+
+        if candidates.is_empty() {
+            return;
+        }
+        let addr = candidates[self.rng.usize(..candidates.len())];
+
+        log::debug!(target: "p2p", "Evicting {} to make room for a bloom peer", addr);
+        self._disconnect(addr, DisconnectReason::ConnectionLimit);
+    }
+
+    /// Network group of a peer, or `None` if the peer is on the local network, in which case
+    /// it isn't subject to diversity requirements.
+    fn group(&self, addr: &PeerId) -> Option<NetGroup> {
+        if addrmgr::is_local(&addr.ip()) {
+            return None;
+        }
+        Some(NetGroup::of(&addr.ip(), self.config.asmap.as_deref()))
+    }
+
+    /// Network groups of peers we're connected or connecting to via an outbound link.
+    fn outbound_groups(&self) -> HashSet<NetGroup> {
+        let mut groups = HashSet::with_hasher(self.rng.clone().into());
+
+        for (addr, peer) in self.peers.iter() {
+            let outbound = match peer {
+                Peer::Connecting { .. } => true,
+                Peer::Connected { conn, .. } => conn.link.is_outbound(),
+                Peer::Disconnecting => false,
+            };
+            if outbound {
+                groups.extend(self.group(addr));
+            }
+        }
+        groups
     }
 
     /// Peers that have been idle longer than [`CONNECTION_TIMEOUT`].
@@ -860,8 +1134,10 @@ mod tests {
 
     use nakamoto_common::bitcoin::network::address::Address;
     use nakamoto_common::block::time::{AdjustedTime, RefClock};
-    use nakamoto_common::p2p::peer::Source;
     use nakamoto_test::assert_matches;
+    use nakamoto_test::block::cache::model;
+
+    use crate::fsm::network::Network;
 
     mod util {
         use super::*;
@@ -870,10 +1146,16 @@ mod tests {
             Config {
                 protocol_version: crate::fsm::PROTOCOL_VERSION,
                 target_outbound_peers: TARGET_OUTBOUND_PEERS,
+                sync_outbound_peers: 0,
                 max_inbound_peers: MAX_INBOUND_PEERS,
+                min_bloom_peers: 0,
                 domains: Domain::all(),
-                user_agent: crate::fsm::USER_AGENT,
+                user_agent: crate::fsm::USER_AGENT.to_owned(),
+                report_height: true,
+                relay: false,
                 persistent: vec![],
+                anchors: vec![],
+                asmap: None,
                 retry_max_wait: LocalDuration::from_mins(60),
                 retry_min_wait: LocalDuration::from_secs(1),
                 services: ServiceFlags::NONE,
@@ -937,6 +1219,341 @@ mod tests {
         assert_eq!(peermgr.connecting().next(), Some(&remote));
     }
 
+    #[test]
+    fn test_ban() {
+        let rng = fastrand::Rng::with_seed(1);
+        let time = RefClock::from(AdjustedTime::new(LocalTime::now()));
+        let height = 144;
+
+        let local = ([99, 99, 99, 99], 9999).into();
+        let remote: PeerId = ([124, 43, 110, 1], 8333).into();
+        let inbound: PeerId = ([124, 43, 110, 1], 49152).into();
+
+        let mut addrs = VecDeque::new();
+        let cfg = Config {
+            persistent: vec![remote],
+            ..util::config()
+        };
+        let mut peermgr = PeerManager::new(cfg, rng, Hooks::default(), time.clone());
+
+        peermgr.initialize(&mut addrs);
+        peermgr.peer_connected(remote, local, Link::Outbound, height);
+        peermgr.by_ref().for_each(drop);
+
+        peermgr.ban(remote);
+        assert!(peermgr.is_banned(&remote));
+        assert!(peermgr
+            .by_ref()
+            .any(|o| matches!(o, Io::Disconnect(a, DisconnectReason::PeerBanned) if a == remote)));
+
+        // Banned peers aren't reconnected to, even if persistent.
+        peermgr.peer_disconnected(&remote, &mut addrs, DisconnectReason::PeerBanned.into());
+        time.elapse(LocalDuration::from_mins(60));
+        peermgr.timer_expired(&mut addrs);
+        assert_eq!(peermgr.connecting().next(), None);
+        assert!(!peermgr.connect(&remote));
+
+        // Inbound connections from banned addresses are refused.
+        peermgr.peer_connected(inbound, local, Link::Inbound, height);
+        assert!(peermgr
+            .by_ref()
+            .any(|o| matches!(o, Io::Disconnect(a, DisconnectReason::PeerBanned) if a == inbound)));
+    }
+
+    #[test]
+    fn test_anchors() {
+        let rng = fastrand::Rng::with_seed(1);
+        let time = AdjustedTime::new(LocalTime::now());
+        let anchors: Vec<PeerId> = (1..=3).map(|i| ([124, 43, 110, i], 8333).into()).collect();
+
+        // Anchors are connected to first, up to the maximum.
+        let mut addrs = VecDeque::new();
+        let cfg = Config {
+            anchors: anchors.clone(),
+            target_outbound_peers: MAX_ANCHORS,
+            ..util::config()
+        };
+        let mut peermgr = PeerManager::new(cfg, rng.clone(), Hooks::default(), time.clone());
+
+        peermgr.initialize(&mut addrs);
+
+        let mut connecting = peermgr.connecting().copied().collect::<Vec<_>>();
+        connecting.sort();
+        assert_eq!(connecting, anchors[..MAX_ANCHORS]);
+
+        // Anchors are ignored when persistent peers are configured.
+        let cfg = Config {
+            anchors: anchors.clone(),
+            persistent: vec![([88, 88, 88, 88], 8333).into()],
+            ..util::config()
+        };
+        let mut peermgr = PeerManager::new(cfg, rng, Hooks::default(), time);
+
+        peermgr.initialize(&mut addrs);
+        assert!(peermgr.connecting().all(|a| !anchors.contains(a)));
+    }
+
+    #[test]
+    fn test_netgroup_diversity() {
+        let rng = fastrand::Rng::with_seed(1);
+        let time = AdjustedTime::new(LocalTime::now());
+        let services = ServiceFlags::NETWORK;
+        let candidates: Vec<PeerId> = vec![
+            ([124, 43, 110, 1], 8333).into(),
+            ([124, 43, 12, 2], 8333).into(),
+            ([124, 44, 110, 1], 8333).into(),
+            ([192, 168, 1, 1], 8333).into(),
+            ([192, 168, 1, 2], 8333).into(),
+            ([8, 8, 4, 4], 8333).into(),
+            ([8, 8, 8, 8], 8333).into(),
+        ];
+        let sample = |candidates: &[PeerId]| {
+            candidates
+                .iter()
+                .map(|a| (Address::new(a, services), Source::Dns))
+                .collect::<VecDeque<_>>()
+        };
+
+        // Only one outbound peer per /16 range, except for local peers.
+        let mut addrs = sample(&candidates);
+        let mut peermgr =
+            PeerManager::new(util::config(), rng.clone(), Hooks::default(), time.clone());
+
+        peermgr.initialize(&mut addrs);
+
+        let mut connecting = peermgr.connecting().copied().collect::<Vec<_>>();
+        connecting.sort();
+        assert_eq!(
+            connecting,
+            vec![
+                candidates[5],
+                candidates[0],
+                candidates[2],
+                candidates[3],
+                candidates[4]
+            ]
+        );
+
+        // With an AS map, peers are grouped by autonomous system instead.
+        let asmap: Asmap = "124.0.0.0/8 1\n8.8.4.0/24 2".parse().unwrap();
+        let cfg = Config {
+            asmap: Some(Arc::new(asmap)),
+            ..util::config()
+        };
+        let mut addrs = sample(&candidates);
+        let mut peermgr = PeerManager::new(cfg, rng, Hooks::default(), time);
+
+        peermgr.initialize(&mut addrs);
+
+        let mut connecting = peermgr.connecting().copied().collect::<Vec<_>>();
+        connecting.sort();
+        assert_eq!(
+            connecting,
+            vec![
+                candidates[5],
+                candidates[6],
+                candidates[0],
+                candidates[3],
+                candidates[4]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_maintain_bloom_peers() {
+        let rng = fastrand::Rng::with_seed(1);
+        let time = RefClock::from(AdjustedTime::new(LocalTime::now()));
+        let height = 144;
+        let local = ([99, 99, 99, 99], 9999).into();
+        let cfg = Config {
+            target_outbound_peers: 2,
+            min_bloom_peers: 1,
+            preferred_services: ServiceFlags::NETWORK,
+            ..util::config()
+        };
+        let a: PeerId = ([124, 43, 110, 1], 8333).into();
+        let b: PeerId = ([125, 43, 110, 1], 8333).into();
+        let c: PeerId = ([126, 43, 110, 1], 8333).into();
+
+        let mut addrs = VecDeque::new();
+        let mut peermgr = PeerManager::new(cfg, rng.clone(), Hooks::default(), time.clone());
+
+        // Without bloom peers to connect to, we warn.
+        peermgr.initialize(&mut addrs);
+        assert!(
+            crate::fsm::output::test::events(peermgr.outbox.drain()).any(|e| matches!(
+                e,
+                Event::BloomPeersUnavailable {
+                    available: 0,
+                    minimum: 1
+                }
+            ))
+        );
+
+        for remote in [a, b] {
+            let version = VersionMessage {
+                services: ServiceFlags::NETWORK,
+                ..peermgr.version(local, remote, rng.u64(..), height, time.local_time())
+            };
+            peermgr.connect(&remote);
+            peermgr.peer_connected(remote, local, Link::Outbound, height);
+            peermgr.received_version(&remote, &version, height);
+            peermgr.received_verack(&remote);
+        }
+        assert_eq!(peermgr.negotiated(Link::Outbound).count(), 2);
+
+        // At our target, a peer without `NODE_BLOOM` is evicted to make room for a bloom peer.
+        // Addresses without it aren't connected to.
+        let mut addrs = [
+            Address::new(&([127, 43, 110, 1], 8333).into(), ServiceFlags::NETWORK),
+            Address::new(&c, ServiceFlags::NETWORK | ServiceFlags::BLOOM),
+        ]
+        .into_iter()
+        .map(|a| (a, Source::Dns))
+        .collect::<VecDeque<_>>();
+
+        time.elapse(IDLE_TIMEOUT);
+        peermgr.timer_expired(&mut addrs);
+
+        assert_eq!(peermgr.connecting().collect::<Vec<_>>(), vec![&c]);
+        assert_eq!(
+            [a, b]
+                .iter()
+                .filter(|addr| peermgr.is_disconnecting(addr))
+                .count(),
+            1
+        );
+
+        // If the bloom peer can't be connected to, and there are no others, we warn again.
+        peermgr.peer_disconnected(&c, &mut addrs, DisconnectReason::PeerTimeout("").into());
+        assert!(
+            crate::fsm::output::test::events(peermgr.outbox.drain()).any(|e| matches!(
+                e,
+                Event::BloomPeersUnavailable {
+                    available: 0,
+                    minimum: 1
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_sync_phase() {
+        let rng = fastrand::Rng::with_seed(1);
+        let time = AdjustedTime::new(LocalTime::now());
+        let tree = model::Cache::new(Network::Regtest.genesis());
+        let height = 144;
+        let local = ([99, 99, 99, 99], 9999).into();
+        let cfg = Config {
+            target_outbound_peers: 2,
+            sync_outbound_peers: 4,
+            ..util::config()
+        };
+        let mut addrs = VecDeque::new();
+        let mut peermgr = PeerManager::new(cfg.clone(), rng.clone(), Hooks::default(), time);
+
+        peermgr.initialize(&mut addrs);
+        assert!(peermgr.is_syncing());
+        assert_eq!(peermgr.target(), 4);
+
+        // During initial sync, we connect to more peers than our steady-state target.
+        let peers: Vec<PeerId> = (1..=4).map(|i| ([88, i, 1, 1], 8333).into()).collect();
+        for (i, remote) in peers.iter().enumerate() {
+            let services = if i == 0 {
+                cfg.preferred_services
+            } else {
+                cfg.required_services
+            };
+            let version = VersionMessage {
+                services,
+                ..peermgr.version(local, *remote, rng.u64(..), height, time.local_time())
+            };
+            peermgr.connect(remote);
+            peermgr.peer_connected(*remote, local, Link::Outbound, height);
+            peermgr.received_version(remote, &version, height);
+            peermgr.received_verack(remote);
+        }
+        assert_eq!(peermgr.negotiated(Link::Outbound).count(), 4);
+        peermgr.outbox.drain().for_each(drop);
+
+        // Once synced, we scale down, evicting peers without our preferred services first.
+        peermgr.received_event(
+            Event::BlockHeadersSynced {
+                height: 0,
+                hash: Network::Regtest.genesis_hash(),
+            },
+            &tree,
+        );
+        assert!(!peermgr.is_syncing());
+        assert_eq!(peermgr.target(), 2);
+        assert!(
+            crate::fsm::output::test::events(peermgr.outbox.drain()).any(|e| matches!(
+                e,
+                Event::OutboundTargetChanged {
+                    syncing: false,
+                    target: 2
+                }
+            ))
+        );
+        assert!(!peermgr.is_disconnecting(&peers[0]));
+        assert_eq!(
+            peers.iter().filter(|p| peermgr.is_disconnecting(p)).count(),
+            2
+        );
+
+        // Falling slightly behind doesn't put us back in initial sync.
+        let synchronizing = |best| Event::Synchronizing {
+            current: 0,
+            best,
+            rate_headers_per_sec: 0.,
+        };
+        peermgr.received_event(synchronizing(SYNC_PHASE_THRESHOLD), &tree);
+        assert!(!peermgr.is_syncing());
+
+        peermgr.received_event(synchronizing(SYNC_PHASE_THRESHOLD + 1), &tree);
+        assert!(peermgr.is_syncing());
+        assert_eq!(peermgr.target(), 4);
+    }
+
+    #[test]
+    fn test_send_addr_v2() {
+        let rng = fastrand::Rng::with_seed(1);
+        let time = AdjustedTime::new(LocalTime::now());
+        let height = 144;
+        let local = ([99, 99, 99, 99], 9999).into();
+        let remote = ([124, 43, 110, 1], 8333).into();
+
+        for domains in [Domain::all(), vec![Domain::IPV4, Domain::Onion]] {
+            let onion = domains.contains(&Domain::Onion);
+            let cfg = Config {
+                domains,
+                ..util::config()
+            };
+            let mut addrs = VecDeque::new();
+            let mut peermgr = PeerManager::new(cfg, rng.clone(), Hooks::default(), time.clone());
+            let version = VersionMessage {
+                services: ServiceFlags::NETWORK,
+                ..peermgr.version(local, remote, rng.u64(..), height, time.local_time())
+            };
+
+            peermgr.initialize(&mut addrs);
+            peermgr.connect(&remote);
+            peermgr.peer_connected(remote, local, Link::Outbound, height);
+            peermgr.outbox.drain().for_each(drop);
+            peermgr.received_version(&remote, &version, height);
+
+            let msgs = crate::fsm::output::test::messages_from(peermgr.outbox.drain(), &remote)
+                .map(|m| m.cmd())
+                .collect::<Vec<_>>();
+
+            if onion {
+                assert_eq!(msgs[..2], ["sendaddrv2", "verack"]);
+            } else {
+                assert_eq!(msgs[0], "verack");
+            }
+        }
+    }
+
     #[test]
     fn test_wtxidrelay_misbehavior() {
         let rng = fastrand::Rng::with_seed(1);
@@ -1175,4 +1792,32 @@ mod tests {
             assert_eq!(peermgr.delta(), delta, "{:?}", case);
         }
     }
+
+    #[test]
+    fn test_version() {
+        let rng = fastrand::Rng::with_seed(1);
+        let time = RefClock::from(AdjustedTime::new(LocalTime::now()));
+        let local = ([99, 99, 99, 99], 9999).into();
+        let remote = ([124, 43, 110, 1], 8333).into();
+
+        let peermgr = PeerManager::new(util::config(), rng.clone(), Hooks::default(), time.clone());
+        let version = peermgr.version(remote, local, 1, 144, LocalTime::now());
+
+        assert_eq!(version.user_agent, crate::fsm::USER_AGENT);
+        assert_eq!(version.start_height, 144);
+        assert!(!version.relay);
+
+        let cfg = Config {
+            user_agent: String::from("/wallet:1.0.0/"),
+            report_height: false,
+            relay: true,
+            ..util::config()
+        };
+        let peermgr = PeerManager::new(cfg, rng, Hooks::default(), time);
+        let version = peermgr.version(remote, local, 1, 144, LocalTime::now());
+
+        assert_eq!(version.user_agent, "/wallet:1.0.0/");
+        assert_eq!(version.start_height, 0);
+        assert!(version.relay);
+    }
 }
@@ -202,6 +202,11 @@ impl<C: Clock> PingManager<C> {
                         peer.record_latency(now - since);
                         peer.state = State::Idle { since: now };
 
+                        self.outbox.event(Event::PeerLatencyUpdated {
+                            addr,
+                            latency: peer.latency(),
+                        });
+
                         return true;
                     }
                 }
@@ -0,0 +1,266 @@
+//! Inbound message rate limiting.
+//!
+//! Every connected peer has a budget of messages and bytes for each message type, eg. `inv`,
+//! which refills at a fixed [`Rate`], up to [`BURST`] seconds worth of messages. Unsolicited
+//! messages received over budget are dropped before they reach the protocol managers, and a
+//! peer which keeps sending messages over budget, ie. more than [`MAX_EXCESS_MESSAGES`] within
+//! a [`FLOOD_WINDOW`], is flooding us and should be disconnected.
+//!
+//! Messages we request, such as blocks and headers, have a generous budget, since their rate
+//! is bound by our own requests. They are never dropped, since we'd keep waiting for them and
+//! stall: a peer sending too many of them is disconnected instead.
+use std::collections::BTreeMap;
+
+use nakamoto_common::block::time::{LocalDuration, LocalTime};
+use nakamoto_common::collections::HashMap;
+
+use super::PeerId;
+
+/// Number of seconds worth of messages a peer can send at once.
+pub const BURST: f64 = 10.;
+
+/// Number of messages over budget within a [`FLOOD_WINDOW`] after which a peer is considered
+/// to be flooding us.
+pub const MAX_EXCESS_MESSAGES: usize = 1000;
+
+/// Period over which messages over budget are counted. Peers that only occasionally go over
+/// budget are not disconnected.
+pub const FLOOD_WINDOW: LocalDuration = LocalDuration::from_mins(1);
+
+/// Rate at which a peer may send messages of a certain type.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate {
+    /// Messages per second.
+    pub messages: f64,
+    /// Bytes per second.
+    pub bytes: f64,
+}
+
+impl Rate {
+    /// Rate of messages we request, and which can be large, eg. blocks. These are never
+    /// dropped.
+    pub const REQUESTED: Self = Self {
+        messages: 1000.,
+        bytes: f64::INFINITY,
+    };
+    /// Rate of other messages, eg. announcements and relayed transactions.
+    pub const UNSOLICITED: Self = Self {
+        messages: 100.,
+        bytes: 1024. * 1024.,
+    };
+
+    /// Get the rate allowed for the given message type.
+    pub fn of(cmd: &str) -> Self {
+        if Self::is_requested(cmd) {
+            Self::REQUESTED
+        } else {
+            Self::UNSOLICITED
+        }
+    }
+
+    /// Whether the given message type is sent in response to our requests.
+    pub fn is_requested(cmd: &str) -> bool {
+        matches!(
+            cmd,
+            "block"
+                | "merkleblock"
+                | "headers"
+                | "cfilter"
+                | "cfheaders"
+                | "cfcheckpt"
+                | "notfound"
+        )
+    }
+}
+
+/// Budget left for a message type.
+#[derive(Debug)]
+struct Bucket {
+    messages: f64,
+    bytes: f64,
+    refilled: LocalTime,
+}
+
+impl Bucket {
+    fn new(rate: Rate, time: LocalTime) -> Self {
+        Self {
+            messages: rate.messages * BURST,
+            bytes: rate.bytes * BURST,
+            refilled: time,
+        }
+    }
+
+    /// Spend budget on a message, if there is enough left.
+    fn spend(&mut self, rate: Rate, size: u64, now: LocalTime) -> bool {
+        let elapsed = (now - self.refilled).as_millis() as f64 / 1000.;
+
+        self.messages = (self.messages + rate.messages * elapsed).min(rate.messages * BURST);
+        self.bytes = (self.bytes + rate.bytes * elapsed).min(rate.bytes * BURST);
+        self.refilled = now;
+
+        if self.messages < 1. || self.bytes < size as f64 {
+            return false;
+        }
+        self.messages -= 1.;
+        self.bytes -= size as f64;
+
+        true
+    }
+}
+
+/// Budget left for a peer.
+#[derive(Debug, Default)]
+struct Budget {
+    buckets: BTreeMap<&'static str, Bucket>,
+    /// Messages over budget in the current flood window.
+    excess: usize,
+    /// Start of the current flood window.
+    window: Option<LocalTime>,
+}
+
+impl Budget {
+    /// Count a message over budget. Returns the number of messages over budget in the current
+    /// flood window.
+    fn count_excess(&mut self, now: LocalTime) -> usize {
+        match self.window {
+            Some(start) if now - start < FLOOD_WINDOW => {}
+            _ => {
+                self.window = Some(now);
+                self.excess = 0;
+            }
+        }
+        self.excess += 1;
+        self.excess
+    }
+}
+
+/// Outcome of rate limiting a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// The message should be processed. Messages we requested are, even over budget.
+    Accept,
+    /// The message is unsolicited and over budget, and should be dropped.
+    Drop,
+    /// The peer is flooding us, and should be disconnected.
+    Flooding,
+}
+
+/// Limits the rate of messages received from connected peers.
+#[derive(Debug)]
+pub struct RateLimiter {
+    peers: HashMap<PeerId, Budget>,
+}
+
+impl RateLimiter {
+    /// Create a new rate limiter.
+    pub fn new(rng: fastrand::Rng) -> Self {
+        Self {
+            peers: HashMap::with_hasher(rng.into()),
+        }
+    }
+
+    /// Stop tracking a peer.
+    pub fn disconnected(&mut self, addr: &PeerId) {
+        self.peers.remove(addr);
+    }
+
+    /// Record a message of the given type and size received from a peer.
+    pub fn received(
+        &mut self,
+        addr: &PeerId,
+        cmd: &'static str,
+        size: u64,
+        now: LocalTime,
+    ) -> Verdict {
+        let rate = Rate::of(cmd);
+        let budget = self.peers.entry(*addr).or_default();
+        let bucket = budget
+            .buckets
+            .entry(cmd)
+            .or_insert_with(|| Bucket::new(rate, now));
+
+        if bucket.spend(rate, size, now) {
+            return Verdict::Accept;
+        }
+        if budget.count_excess(now) > MAX_EXCESS_MESSAGES {
+            Verdict::Flooding
+        } else if Rate::is_requested(cmd) {
+            Verdict::Accept
+        } else {
+            Verdict::Drop
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit() {
+        let now = LocalTime::now();
+        let addr: PeerId = ([88, 88, 88, 88], 8333).into();
+        let mut limiter = RateLimiter::new(fastrand::Rng::new());
+        let burst = (Rate::UNSOLICITED.messages * BURST) as usize;
+
+        for _ in 0..burst {
+            assert_eq!(limiter.received(&addr, "inv", 37, now), Verdict::Accept);
+        }
+        assert_eq!(limiter.received(&addr, "inv", 37, now), Verdict::Drop);
+
+        // Other message types have their own budget.
+        assert_eq!(limiter.received(&addr, "addr", 31, now), Verdict::Accept);
+        assert_eq!(
+            limiter.received(&addr, "block", 32 * 1024 * 1024, now),
+            Verdict::Accept
+        );
+
+        // The budget refills over time.
+        let later = now + LocalDuration::from_secs(1);
+        for _ in 0..Rate::UNSOLICITED.messages as usize {
+            assert_eq!(limiter.received(&addr, "inv", 37, later), Verdict::Accept);
+        }
+        assert_eq!(limiter.received(&addr, "inv", 37, later), Verdict::Drop);
+
+        // Messages over the byte budget are dropped too.
+        let size = (Rate::UNSOLICITED.bytes * BURST) as u64 + 1;
+        assert_eq!(limiter.received(&addr, "getdata", size, now), Verdict::Drop);
+
+        // A peer that keeps sending over budget is flooding us.
+        let verdicts = (0..MAX_EXCESS_MESSAGES)
+            .map(|_| limiter.received(&addr, "inv", 37, later))
+            .collect::<Vec<_>>();
+        assert_eq!(verdicts.last(), Some(&Verdict::Flooding));
+
+        // Dropped messages are only counted within a flood window.
+        let later = later + FLOOD_WINDOW;
+        let verdicts = (0..burst + MAX_EXCESS_MESSAGES)
+            .map(|_| limiter.received(&addr, "inv", 37, later))
+            .collect::<Vec<_>>();
+        assert_eq!(verdicts.last(), Some(&Verdict::Drop));
+        assert_eq!(limiter.received(&addr, "inv", 37, later), Verdict::Flooding);
+    }
+
+    #[test]
+    fn test_rate_limit_requested() {
+        let now = LocalTime::now();
+        let addr: PeerId = ([88, 88, 88, 88], 8333).into();
+        let mut limiter = RateLimiter::new(fastrand::Rng::new());
+        let burst = (Rate::REQUESTED.messages * BURST) as usize;
+
+        // Relayed transactions aren't requested, and are dropped over budget.
+        assert_eq!(Rate::of("tx"), Rate::UNSOLICITED);
+
+        // Messages we requested are never dropped, even over budget.
+        let verdicts = (0..burst + MAX_EXCESS_MESSAGES)
+            .map(|_| limiter.received(&addr, "headers", 162, now))
+            .collect::<Vec<_>>();
+        assert!(verdicts.iter().all(|v| *v == Verdict::Accept));
+
+        // A peer that keeps sending them is flooding us.
+        assert_eq!(
+            limiter.received(&addr, "headers", 162, now),
+            Verdict::Flooding
+        );
+    }
+}
@@ -0,0 +1,315 @@
+//! Hybrid Scan Manager.
+//!
+//! Scans the chain for blocks matching our scripts, with BIP 157/158 compact filters when
+//! connected to peers serving them, and with BIP 37 merkle blocks otherwise. Filters are
+//! matched locally, and don't reveal anything about our scripts, so they are always preferred.
+//!
+//! The choice is made per height range: whenever the peer set changes, the scan manager is
+//! asked which method to scan with, and if it's not the one in use, the scan in progress is
+//! stopped and the other method carries on from the height it reached. Matches of both methods
+//! are reported as [`Event::ScanMatched`], along with the method that found them.
+//!
+use std::collections::BTreeSet;
+use std::fmt;
+
+use nakamoto_common::bitcoin::Script;
+use nakamoto_common::block::{BlockHash, Height};
+
+use super::output::{Io, Outbox};
+use super::Event;
+
+/// A method of scanning the chain for matching blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// BIP 157/158 compact block filters, matched locally.
+    Filters,
+    /// BIP 37 merkle blocks, matched by peers against our bloom filters.
+    MerkleBlocks,
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Filters => write!(f, "compact filters"),
+            Self::MerkleBlocks => write!(f, "merkle blocks"),
+        }
+    }
+}
+
+/// A scan to start, with the given method, replacing the one in progress.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Switch {
+    /// Method to scan with.
+    pub method: Method,
+    /// Height to start scanning from.
+    pub from: Height,
+    /// Height to stop scanning at. If `None`, keeps scanning new blocks.
+    pub to: Option<Height>,
+}
+
+/// Hybrid scan manager.
+#[derive(Debug, Default)]
+pub struct ScanManager {
+    /// Whether a hybrid scan is in progress.
+    pub active: bool,
+    /// Start height of the scan.
+    start: Height,
+    /// End height of the scan. If `None`, keeps scanning new blocks until aborted.
+    end: Option<Height>,
+    /// Scripts to match on.
+    watch: Vec<Script>,
+    /// Height ranges scanned with each method, as the height each range starts at. Every
+    /// range ends where the next one starts; the last one extends to the end of the scan.
+    ranges: Vec<(Height, Method)>,
+    /// Heights of the matches reported so far.
+    matched: BTreeSet<Height>,
+    /// State machine output.
+    outbox: Outbox,
+}
+
+impl Iterator for ScanManager {
+    type Item = Io;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.outbox.next()
+    }
+}
+
+impl ScanManager {
+    /// Create a new scan manager.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start a hybrid scan, replacing the one in progress. Nothing is scanned until a method
+    /// is selected with [`ScanManager::select`].
+    pub fn start(&mut self, start: Height, end: Option<Height>, watch: Vec<Script>) {
+        self.active = true;
+        self.start = start;
+        self.end = end;
+        self.watch = watch;
+        self.ranges.clear();
+        self.matched.clear();
+    }
+
+    /// Abort the hybrid scan in progress, if any.
+    pub fn abort(&mut self) {
+        self.active = false;
+    }
+
+    /// Scripts the hybrid scan matches on.
+    pub fn watch(&self) -> &[Script] {
+        &self.watch
+    }
+
+    /// Method the scan in progress uses.
+    pub fn method(&self) -> Option<Method> {
+        self.active
+            .then(|| self.ranges.last().map(|(_, m)| *m))
+            .flatten()
+    }
+
+    /// Method used to scan the given height, if it's part of the hybrid scan.
+    pub fn method_at(&self, height: Height) -> Option<Method> {
+        if height < self.start || self.end.map_or(false, |end| height > end) {
+            return None;
+        }
+        self.ranges
+            .iter()
+            .rev()
+            .find(|(from, _)| *from <= height)
+            .map(|(_, m)| *m)
+    }
+
+    /// Select the method to scan the rest of the chain with, given whether we're connected to
+    /// peers serving compact filters, and to peers with our bloom filters loaded. The scan in
+    /// progress reached the given height, ie. all blocks below it were scanned.
+    ///
+    /// Returns the scan to start, if the method changes. If neither method is available, the
+    /// scan in progress is kept, to carry on once its peers are back.
+    pub fn select(&mut self, filters: bool, bloom: bool, reached: Height) -> Option<Switch> {
+        if !self.active {
+            return None;
+        }
+        let method = if filters {
+            Method::Filters
+        } else if bloom {
+            Method::MerkleBlocks
+        } else {
+            return None;
+        };
+        if self.method() == Some(method) {
+            return None;
+        }
+        let from = match self.ranges.last() {
+            Some((from, _)) => reached.max(*from),
+            None => self.start,
+        };
+        if self.end.map_or(false, |end| from > end) {
+            self.active = false;
+
+            return None;
+        }
+        self.ranges.push((from, method));
+        self.outbox.event(Event::ScanMethodChanged {
+            height: from,
+            method,
+        });
+
+        Some(Switch {
+            method,
+            from,
+            to: self.end,
+        })
+    }
+
+    /// Event received.
+    pub fn received_event(&mut self, event: Event) {
+        match event {
+            Event::BlockMatched { height, block } => {
+                self.matched(height, block.block_hash(), Method::Filters);
+            }
+            Event::ReceivedMerkleBlock {
+                height,
+                merkle_block,
+                matches,
+                ..
+            } if !matches.is_empty() => {
+                self.matched(
+                    height,
+                    merkle_block.header.block_hash(),
+                    Method::MerkleBlocks,
+                );
+            }
+            _ => {}
+        }
+    }
+
+    /// Report a match, if it was found by the method the height is scanned with.
+    fn matched(&mut self, height: Height, hash: BlockHash, method: Method) {
+        if self.method_at(height) != Some(method) || !self.matched.insert(height) {
+            return;
+        }
+        self.outbox.event(Event::ScanMatched {
+            height,
+            hash,
+            method,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nakamoto_common::bitcoin::{MerkleBlock, Txid};
+    use nakamoto_common::bitcoin_hashes::Hash as _;
+    use nakamoto_test::block::gen;
+
+    use crate::fsm::network::Network;
+    use crate::fsm::output;
+
+    #[test]
+    fn test_select() {
+        let mut scanmgr = ScanManager::new();
+
+        assert_eq!(
+            scanmgr.select(true, true, 0),
+            None,
+            "No scan is in progress"
+        );
+
+        scanmgr.start(10, Some(100), vec![]);
+        assert_eq!(
+            scanmgr.select(false, false, 10),
+            None,
+            "No method is available"
+        );
+        assert_eq!(
+            scanmgr.select(false, true, 10),
+            Some(Switch {
+                method: Method::MerkleBlocks,
+                from: 10,
+                to: Some(100),
+            })
+        );
+        assert_eq!(scanmgr.select(false, true, 20), None);
+
+        // Compact filters take over from the height merkle blocks reached.
+        assert_eq!(
+            scanmgr.select(true, true, 40),
+            Some(Switch {
+                method: Method::Filters,
+                from: 40,
+                to: Some(100),
+            })
+        );
+        // Without peers, the scan in progress is kept.
+        assert_eq!(scanmgr.select(false, false, 50), None);
+        assert_eq!(scanmgr.method(), Some(Method::Filters));
+
+        assert_eq!(scanmgr.method_at(9), None);
+        assert_eq!(scanmgr.method_at(39), Some(Method::MerkleBlocks));
+        assert_eq!(scanmgr.method_at(40), Some(Method::Filters));
+        assert_eq!(scanmgr.method_at(101), None);
+
+        assert!(
+            output::test::events(scanmgr.outbox.drain()).any(|e| matches!(
+                e,
+                Event::ScanMethodChanged {
+                    height: 40,
+                    method: Method::Filters
+                }
+            ))
+        );
+
+        // Once the end is reached, the scan is over.
+        assert_eq!(scanmgr.select(false, true, 101), None);
+        assert!(!scanmgr.active);
+    }
+
+    #[test]
+    fn test_matched() {
+        let mut rng = fastrand::Rng::new();
+        let block = gen::block(&Network::Regtest.genesis(), &mut rng);
+        let hash = block.block_hash();
+        let mut scanmgr = ScanManager::new();
+
+        scanmgr.start(1, None, vec![]);
+        scanmgr.select(false, true, 1);
+        scanmgr.select(true, true, 5);
+        scanmgr.outbox.drain().for_each(drop);
+
+        let merkle_block = |height| Event::ReceivedMerkleBlock {
+            height,
+            merkle_block: MerkleBlock::from_block_with_predicate(&block, |_| false),
+            matches: vec![Txid::all_zeros()],
+            peer: ([88, 88, 88, 88], 8333).into(),
+        };
+
+        // Matches are reported once, when found by the method their height is scanned with.
+        scanmgr.received_event(merkle_block(3));
+        scanmgr.received_event(merkle_block(3));
+        scanmgr.received_event(merkle_block(7));
+        scanmgr.received_event(Event::BlockMatched {
+            height: 7,
+            block: block.clone(),
+        });
+
+        let matches = output::test::events(scanmgr.outbox.drain())
+            .filter_map(|e| match e {
+                Event::ScanMatched {
+                    height,
+                    hash,
+                    method,
+                } => Some((height, hash, method)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            matches,
+            vec![(3, hash, Method::MerkleBlocks), (7, hash, Method::Filters)]
+        );
+    }
+}
@@ -0,0 +1,191 @@
+//! Per-peer bandwidth accounting.
+//!
+//! Every message sent to or received from a connected peer is counted, including its header,
+//! both in total and by message type.
+use std::collections::BTreeMap;
+use std::io;
+use std::ops::AddAssign;
+
+use nakamoto_common::bitcoin::consensus::Encodable;
+use nakamoto_common::bitcoin::network::message::RawNetworkMessage;
+use nakamoto_common::block::time::LocalTime;
+use nakamoto_common::collections::HashMap;
+
+use super::PeerId;
+
+/// Bytes sent to and received from a peer.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Traffic {
+    /// Bytes sent.
+    pub sent: u64,
+    /// Bytes received.
+    pub received: u64,
+}
+
+impl Traffic {
+    /// Bytes transferred in both directions.
+    pub fn total(&self) -> u64 {
+        self.sent + self.received
+    }
+}
+
+impl AddAssign for Traffic {
+    fn add_assign(&mut self, other: Self) {
+        self.sent += other.sent;
+        self.received += other.received;
+    }
+}
+
+/// Bandwidth used by a connected peer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerStats {
+    /// Peer address.
+    pub addr: PeerId,
+    /// Time the peer connected.
+    pub since: LocalTime,
+    /// Bytes transferred.
+    pub traffic: Traffic,
+    /// Bytes transferred, by message type, eg. `headers`.
+    pub messages: BTreeMap<&'static str, Traffic>,
+    /// Average send rate since the peer connected, in bytes per second.
+    pub send_rate: f64,
+    /// Average receive rate since the peer connected, in bytes per second.
+    pub receive_rate: f64,
+}
+
+/// Bandwidth used by a peer so far.
+#[derive(Debug)]
+struct Usage {
+    since: LocalTime,
+    traffic: Traffic,
+    messages: BTreeMap<&'static str, Traffic>,
+}
+
+impl Usage {
+    fn record(&mut self, cmd: &'static str, traffic: Traffic) {
+        self.traffic += traffic;
+        *self.messages.entry(cmd).or_default() += traffic;
+    }
+}
+
+/// Tracks the bandwidth used by connected peers.
+#[derive(Debug)]
+pub struct Bandwidth {
+    peers: HashMap<PeerId, Usage>,
+}
+
+impl Bandwidth {
+    /// Create a new bandwidth tracker.
+    pub fn new(rng: fastrand::Rng) -> Self {
+        Self {
+            peers: HashMap::with_hasher(rng.into()),
+        }
+    }
+
+    /// Start tracking a peer.
+    pub fn connected(&mut self, addr: PeerId, time: LocalTime) {
+        self.peers.insert(
+            addr,
+            Usage {
+                since: time,
+                traffic: Traffic::default(),
+                messages: BTreeMap::new(),
+            },
+        );
+    }
+
+    /// Stop tracking a peer.
+    pub fn disconnected(&mut self, addr: &PeerId) {
+        self.peers.remove(addr);
+    }
+
+    /// Record a message sent to a peer.
+    pub fn sent(&mut self, addr: &PeerId, msg: &RawNetworkMessage) {
+        if let Some(usage) = self.peers.get_mut(addr) {
+            let sent = self::size(msg);
+
+            usage.record(msg.cmd(), Traffic { sent, received: 0 });
+        }
+    }
+
+    /// Record a message received from a peer. Returns the size of the message.
+    pub fn received(&mut self, addr: &PeerId, msg: &RawNetworkMessage) -> u64 {
+        let received = self::size(msg);
+
+        if let Some(usage) = self.peers.get_mut(addr) {
+            usage.record(msg.cmd(), Traffic { sent: 0, received });
+        }
+        received
+    }
+
+    /// Get the bandwidth used by each connected peer, as of the given time.
+    pub fn stats(&self, now: LocalTime) -> Vec<PeerStats> {
+        self.peers
+            .iter()
+            .map(|(addr, usage)| {
+                // Avoid inflated rates for peers that just connected.
+                let secs = (now - usage.since).as_secs().max(1) as f64;
+
+                PeerStats {
+                    addr: *addr,
+                    since: usage.since,
+                    traffic: usage.traffic,
+                    messages: usage.messages.clone(),
+                    send_rate: usage.traffic.sent as f64 / secs,
+                    receive_rate: usage.traffic.received as f64 / secs,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Size of a message on the wire, including its header.
+fn size(msg: &RawNetworkMessage) -> u64 {
+    msg.consensus_encode(&mut io::sink())
+        .map_or(0, |len| len as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nakamoto_common::bitcoin::network::message::NetworkMessage;
+    use nakamoto_common::block::time::LocalDuration;
+
+    #[test]
+    fn test_bandwidth() {
+        let mut bandwidth = Bandwidth::new(fastrand::Rng::with_seed(1));
+        let time = LocalTime::now();
+        let addr: PeerId = ([88, 88, 88, 88], 8333).into();
+        let ping = RawNetworkMessage {
+            magic: 0,
+            payload: NetworkMessage::Ping(1),
+        };
+        let verack = RawNetworkMessage {
+            magic: 0,
+            payload: NetworkMessage::Verack,
+        };
+        let traffic = |sent, received| Traffic { sent, received };
+
+        // Messages to or from unknown peers aren't counted.
+        bandwidth.sent(&addr, &ping);
+        assert!(bandwidth.stats(time).is_empty());
+
+        bandwidth.connected(addr, time);
+        bandwidth.sent(&addr, &ping);
+        bandwidth.sent(&addr, &verack);
+        bandwidth.received(&addr, &ping);
+
+        let stats = bandwidth.stats(time + LocalDuration::from_secs(2));
+        let stats = &stats[0];
+
+        // A `ping` is a header followed by an 8-byte nonce.
+        assert_eq!(stats.traffic, traffic(56, 32));
+        assert_eq!(stats.messages["ping"], traffic(32, 32));
+        assert_eq!(stats.messages["verack"], traffic(24, 0));
+        assert_eq!(stats.send_rate, 28.);
+        assert_eq!(stats.receive_rate, 16.);
+
+        bandwidth.disconnected(&addr);
+        assert!(bandwidth.stats(time).is_empty());
+    }
+}
@@ -24,6 +24,11 @@ pub const REQUEST_TIMEOUT: LocalDuration = LocalDuration::from_secs(30);
 pub const TIP_STALE_DURATION: LocalDuration = LocalDuration::from_mins(60 * 2);
 /// Maximum number of headers sent in a `headers` message.
 pub const MAX_MESSAGE_HEADERS: usize = 2000;
+/// Maximum number of locator hashes accepted in a `getheaders` message.
+pub const MAX_LOCATOR_HASHES: usize = 101;
+/// How old our tip can be before we consider ourselves to be in initial sync, and stop
+/// serving headers to peers.
+pub const MAX_TIP_AGE: LocalDuration = LocalDuration::from_mins(60 * 24);
 /// Maximum number of inventories sent in an `inv` message.
 pub const MAX_MESSAGE_INVS: usize = 50000;
 // Number of blocks that can be requested at any given time from a single peer
@@ -32,6 +37,15 @@ pub const MAX_MESSAGE_INVS: usize = 50000;
 pub const IDLE_TIMEOUT: LocalDuration = LocalDuration::BLOCK_INTERVAL;
 /// Services required from peers for header sync.
 pub const REQUIRED_SERVICES: ServiceFlags = ServiceFlags::NETWORK;
+/// How often header sync progress is reported, at most.
+pub const SYNC_PROGRESS_INTERVAL: LocalDuration = LocalDuration::from_secs(1);
+/// Number of peers asked for the same headers while syncing. Whichever responds first is
+/// imported, so that sync is as fast as the fastest of them.
+///
+/// This is redundant fan-out, not pipelining: headers can only be requested from the last
+/// one we have, so the peers are sent the same locators, and the extra responses are
+/// discarded. It trades bandwidth for latency, and can be set to `1` to disable it.
+pub const SYNC_REQUEST_PEERS: usize = 3;
 
 /// Maximum headers announced in a `headers` message, when unsolicited.
 const MAX_UNSOLICITED_HEADERS: usize = 24;
@@ -58,6 +72,9 @@ struct Peer {
     link: Link,
     last_active: Option<LocalTime>,
     last_asked: Option<Locators>,
+    /// Number of header requests in a row this peer was beaten to by another peer, or let
+    /// time out. Peers are asked in order of lateness, so slow peers are rotated out.
+    late: usize,
 }
 
 /// Sync manager configuration.
@@ -67,6 +84,8 @@ pub struct Config {
     pub max_message_headers: usize,
     /// How long to wait for a response from a peer.
     pub request_timeout: LocalDuration,
+    /// Number of peers asked for the same headers. See [`SYNC_REQUEST_PEERS`].
+    pub request_peers: usize,
     /// Consensus parameters.
     pub params: Params,
 }
@@ -85,6 +104,8 @@ pub struct SyncManager<C> {
     last_peer_sample: Option<LocalTime>,
     /// Last time we idled.
     last_idle: Option<LocalTime>,
+    /// Time and height of the last sync progress report, if syncing.
+    last_progress: Option<(LocalTime, Height)>,
     /// In-flight header requests to peers.
     inflight: HashMap<PeerId, GetHeaders>,
     /// State-machine output.
@@ -119,6 +140,7 @@ impl<C: Clock> SyncManager<C> {
         let last_tip_update = None;
         let last_peer_sample = None;
         let last_idle = None;
+        let last_progress = None;
         let inflight = HashMap::with_hasher(rng.clone().into());
         let outbox = Outbox::default();
 
@@ -128,6 +150,7 @@ impl<C: Clock> SyncManager<C> {
             last_tip_update,
             last_peer_sample,
             last_idle,
+            last_progress,
             inflight,
             outbox,
             clock,
@@ -227,22 +250,51 @@ impl<C: Clock> SyncManager<C> {
     }
 
     /// Called when we received a `getheaders` message from a peer.
+    ///
+    /// Headers are served from the first locator hash found on our active chain, up to and
+    /// including the stop hash, and no more than the maximum allowed per message. Nothing is
+    /// served during initial sync, since our chain is likely to be outdated.
     pub fn received_getheaders<T: BlockReader>(
         &mut self,
         addr: &PeerId,
         (locator_hashes, stop_hash): Locators,
         tree: &T,
     ) {
-        let max = self.config.max_message_headers;
+        let max = self.config.max_message_headers.min(MAX_MESSAGE_HEADERS);
 
-        if self.is_syncing() || max == 0 {
+        if max == 0 {
+            return;
+        }
+        if locator_hashes.len() > MAX_LOCATOR_HASHES {
+            self.record_misbehavior(addr, "too many locator hashes in `getheaders` message");
+            return;
+        }
+        if self.is_initial_sync(tree) {
+            log::debug!(target: "p2p", "Ignoring `getheaders` from {} during initial sync", addr);
             return;
         }
         let headers = tree.locate_headers(&locator_hashes, stop_hash, max);
 
-        if headers.is_empty() {
+        let Some(last) = headers.last() else {
+            // If the peer's best block is our tip, let it know that it's in sync with us.
+            let (tip, _) = tree.tip();
+
+            if locator_hashes.iter().find(|h| tree.contains(h)) == Some(&tip) {
+                self.outbox.headers(*addr, vec![]);
+            }
             return;
+        };
+        // The peer now knows of the headers we sent, so we don't need to announce them.
+        let hash = last.block_hash();
+
+        if let (Some((height, _)), Some(peer)) = (tree.get_block(&hash), self.peers.get_mut(addr)) {
+            if height > peer.height {
+                peer.height = height;
+                peer.tip = hash;
+            }
         }
+        log::debug!(target: "p2p", "Serving {} header(s) to {}", headers.len(), addr);
+
         self.outbox.headers(*addr, headers);
     }
 
@@ -278,11 +330,39 @@ impl<C: Clock> SyncManager<C> {
                 connected: connected.clone(),
                 reverted: reverted.clone(),
             });
+            self.report_progress(*height);
             self.broadcast_tip(hash, tree);
         }
         result
     }
 
+    /// Report header sync progress while catching up to the best known height, at most once
+    /// every [`SYNC_PROGRESS_INTERVAL`], and once more when caught up.
+    fn report_progress(&mut self, current: Height) {
+        let best = self.best_height().unwrap_or(current).max(current);
+        let now = self.clock.local_time();
+
+        let rate = match self.last_progress {
+            Some((since, _)) if now - since < SYNC_PROGRESS_INTERVAL && current < best => {
+                return;
+            }
+            Some((since, height)) => {
+                let elapsed = (now - since).as_millis().max(1) as f64 / 1000.;
+
+                current.saturating_sub(height) as f64 / elapsed
+            }
+            // Nothing to report if we weren't behind to begin with.
+            None if current >= best => return,
+            None => 0.,
+        };
+        self.outbox.event(Event::Synchronizing {
+            current,
+            best,
+            rate_headers_per_sec: rate,
+        });
+        self.last_progress = (current < best).then_some((now, current));
+    }
+
     /// Called when we receive headers from a peer.
     pub fn received_headers<T: BlockTree>(
         &mut self,
@@ -320,6 +400,12 @@ impl<C: Clock> SyncManager<C> {
         let best = headers.last().block_hash();
 
         if tree.contains(&best) {
+            // Another peer asked for the same headers was quicker.
+            if request.is_some() {
+                if let Some(peer) = self.peers.get_mut(from) {
+                    peer.late += 1;
+                }
+            }
             return;
         }
 
@@ -339,6 +425,7 @@ impl<C: Clock> SyncManager<C> {
                         peer.tip = hash;
                         peer.height = height;
                     }
+                    peer.late = 0;
                 }
                 // Keep track of when we last updated our tip. This is useful to check
                 // whether our tip is stale.
@@ -353,10 +440,17 @@ impl<C: Clock> SyncManager<C> {
                     self.sync(tree);
                 } else {
                     let locators = (vec![hash], BlockHash::all_zeros());
-
                     let timeout = self.config.request_timeout;
 
-                    self.request(*from, locators, timeout, OnTimeout::Disconnect);
+                    self.request(*from, locators.clone(), timeout, OnTimeout::Disconnect);
+
+                    // Ask other peers for the same headers, in case they're quicker. If they
+                    // return a different chain, the one with the most work is kept.
+                    let count = self.config.request_peers.saturating_sub(1);
+
+                    for addr in self.sync_peers(&locators, tree, count) {
+                        self.request(addr, locators.clone(), timeout, OnTimeout::Ignore);
+                    }
                 }
             }
             // If this is an error with the underlying store, we have to propagate
@@ -383,6 +477,10 @@ impl<C: Clock> SyncManager<C> {
             Err(Error::BlockImportAborted(h, b, c)) => {
                 log::warn!(target: "p2p",  "BlockImportAborted {h}\n{b}\n{c}");
             }
+            // The difficulty couldn't be computed from our own headers.
+            Err(e @ Error::BlockUnavailable(_) | e @ Error::InsufficientHistory(_)) => {
+                log::warn!(target: "p2p", "Couldn't validate headers from {from}: {e}");
+            }
             // These shouldn't happen here.
             // TODO: Perhaps there's a better way to have this error not show up here.
             Err(Error::Interrupted | Error::GenesisMismatch) => {
@@ -485,7 +583,11 @@ impl<C: Clock> SyncManager<C> {
 
             match on_timeout {
                 OnTimeout::Ignore => {
-                    // It's likely that the peer just didn't have the requested header.
+                    // It's likely that the peer just didn't have the requested header, but it
+                    // isn't asked first anymore, in case it's slow.
+                    if let Some(peer) = self.peers.get_mut(&peer) {
+                        peer.late += 1;
+                    }
                 }
                 OnTimeout::Retry(0) | OnTimeout::Disconnect => {
                     self.outbox
@@ -562,6 +664,7 @@ impl<C: Clock> SyncManager<C> {
     fn register(&mut self, addr: PeerId, height: Height, preferred: bool, link: Link) {
         let last_active = None;
         let last_asked = None;
+        let late = 0;
         let tip = BlockHash::all_zeros();
 
         self.peers.insert(
@@ -573,6 +676,7 @@ impl<C: Clock> SyncManager<C> {
                 preferred,
                 last_active,
                 last_asked,
+                late,
             },
         );
     }
@@ -597,6 +701,25 @@ impl<C: Clock> SyncManager<C> {
             .map(|(a, _)| **a)
     }
 
+    /// Select up to the given number of peers with a longer chain than ours, to ask for the
+    /// headers following the given locators. Preferred peers come first, then the least late.
+    fn sync_peers<T: BlockReader>(
+        &self,
+        locators: &Locators,
+        tree: &T,
+        count: usize,
+    ) -> Vec<PeerId> {
+        let height = tree.height();
+        let mut peers = self
+            .peers
+            .shuffled()
+            .filter(|(a, p)| self.is_request_candidate(a, p, &locators.0) && p.height > height)
+            .collect::<Vec<_>>();
+
+        peers.sort_by_key(|(_, p)| (!p.preferred, p.late));
+        peers.into_iter().take(count).map(|(a, _)| *a).collect()
+    }
+
     /// Check whether a peer is a good request candidate for the given locators.
     /// This function ensures that we don't ask the same peer twice for the same locators.
     fn is_request_candidate(&self, addr: &PeerId, peer: &Peer, locators: &[BlockHash]) -> bool {
@@ -621,6 +744,16 @@ impl<C: Clock> SyncManager<C> {
         false
     }
 
+    /// Check whether we're in initial sync, ie. our tip is old, and peers know of a longer
+    /// chain.
+    fn is_initial_sync<T: BlockReader>(&self, tree: &T) -> bool {
+        let (_, tip) = tree.tip();
+        let time = LocalTime::from_block_time(tip.time);
+
+        self.clock.local_time() - time >= MAX_TIP_AGE
+            && self.best_height().map_or(false, |h| h > tree.height())
+    }
+
     /// Check if we're currently syncing with these locators.
     fn syncing(&self, locators: &Locators) -> bool {
         self.inflight.values().any(|r| &r.locators == locators)
@@ -649,8 +782,20 @@ impl<C: Clock> SyncManager<C> {
 
         let locators = (tree.locator_hashes(tree.height()), BlockHash::all_zeros());
 
-        // If we're already fetching these headers, just wait.
+        // If we're already fetching these headers, ask more peers in case they're quicker,
+        // and wait.
         if self.syncing(&locators) {
+            let asked = self
+                .inflight
+                .values()
+                .filter(|r| r.locators == locators)
+                .count();
+            let timeout = self.config.request_timeout;
+            let count = self.config.request_peers.saturating_sub(asked);
+
+            for addr in self.sync_peers(&locators, tree, count) {
+                self.request(addr, locators.clone(), timeout, OnTimeout::Ignore);
+            }
             return false;
         }
 
@@ -661,6 +806,13 @@ impl<C: Clock> SyncManager<C> {
 
             if best > current {
                 self.request(addr, locators.clone(), timeout, OnTimeout::Ignore);
+
+                // Ask other peers for the same headers, in case they're quicker.
+                let count = self.config.request_peers.saturating_sub(1);
+
+                for addr in self.sync_peers(&locators, tree, count) {
+                    self.request(addr, locators.clone(), timeout, OnTimeout::Ignore);
+                }
                 return true;
             }
         }
@@ -17,7 +17,7 @@ use nakamoto_common::bitcoin::util::bloom::BloomFilter;
 use nakamoto_common::bitcoin_hashes::hex::ToHex;
 
 use super::event::TxStatus;
-use super::{addrmgr, cbfmgr, peermgr, pingmgr, syncmgr};
+use super::{addrmgr, cbfmgr, invmgr, peermgr, pingmgr, ratelimit, syncmgr};
 use super::{
     chan, network::Network, BlockHash, BlockHeader, Command, Config, DisconnectReason, Event,
     HashSet, Height, Io, Limits, NetworkMessage, PeerId, RawNetworkMessage, ServiceFlags,
@@ -143,6 +143,41 @@ fn test_initial_sync() {
     }
 }
 
+#[test]
+fn test_sync_progress() {
+    let rng = fastrand::Rng::new();
+    let network = Network::Mainnet;
+    let headers = BITCOIN_HEADERS.tail[0..30].to_vec();
+    let time = LocalTime::from_block_time(headers.last().unwrap().time);
+    let mut alice = Peer::genesis("alice", [48, 48, 48, 48], network, vec![], rng);
+    let remote: PeerId = ([241, 19, 44, 18], 8333).into();
+    let progress = |events: &mut dyn Iterator<Item = Event>| {
+        events.find_map(|e| match e {
+            Event::Synchronizing {
+                current,
+                best,
+                rate_headers_per_sec,
+            } => Some((current, best, rate_headers_per_sec)),
+            _ => None,
+        })
+    };
+
+    alice.elapse(time - alice.local_time());
+    alice.connect_addr(&remote, Link::Outbound);
+
+    // The first report has no rate, since there is nothing to compare to.
+    alice.received(&remote, NetworkMessage::Headers(headers[..10].to_vec()));
+    assert_eq!(progress(&mut alice.events()), Some((10, 144, 0.)));
+
+    // Progress isn't reported more often than the interval.
+    alice.received(&remote, NetworkMessage::Headers(headers[10..20].to_vec()));
+    assert_eq!(progress(&mut alice.events()), None);
+
+    alice.elapse(syncmgr::SYNC_PROGRESS_INTERVAL * 2);
+    alice.received(&remote, NetworkMessage::Headers(headers[20..30].to_vec()));
+    assert_eq!(progress(&mut alice.events()), Some((30, 144, 10.)));
+}
+
 /// Test what happens when a peer is idle for too long.
 #[test]
 fn test_idle_disconnect() {
@@ -190,6 +225,73 @@ fn test_inv_getheaders() {
         .expect("a timer should be returned");
 }
 
+#[test]
+fn test_serve_getheaders() {
+    let rng = fastrand::Rng::new();
+    let network = Network::Mainnet;
+    let headers = BITCOIN_HEADERS.tail[0..30].to_vec();
+    let mut alice = Peer::new(
+        "alice",
+        [48, 48, 48, 48],
+        network,
+        headers,
+        vec![],
+        vec![],
+        rng,
+    );
+    let remote: PeerId = ([241, 19, 44, 18], 8333).into();
+    let hash = |alice: &Peer<Protocol>, height| {
+        alice
+            .protocol
+            .tree
+            .get_block_by_height(height)
+            .unwrap()
+            .block_hash()
+    };
+    // Some hash for a nonexistent block.
+    let unknown =
+        BlockHash::from_hex("0000000000b7b2c71f2a345e3a4fc328bf5bbb436012afca590b1a11466e2206")
+            .unwrap();
+    let getheaders = |alice: &mut Peer<Protocol>, locators: Vec<BlockHash>, stop_hash| {
+        alice.received(
+            &remote,
+            NetworkMessage::GetHeaders(GetHeadersMessage::new(locators, stop_hash)),
+        );
+        alice.messages(&remote).find_map(|m| match m {
+            NetworkMessage::Headers(headers) => Some(headers),
+            _ => None,
+        })
+    };
+
+    alice.connect_addr(&remote, Link::Inbound);
+
+    // Headers are served from the first known locator, up to the tip.
+    let locators = vec![unknown, hash(&alice, 10), hash(&alice, 5)];
+    let served = getheaders(&mut alice, locators, unknown).unwrap();
+    assert_eq!(served.len(), 20);
+    assert_eq!(served[0].block_hash(), hash(&alice, 11));
+
+    // ... Or up to and including the stop hash.
+    let (start, stop) = (hash(&alice, 10), hash(&alice, 15));
+    let served = getheaders(&mut alice, vec![start], stop).unwrap();
+    assert_eq!(served.last().map(|h| h.block_hash()), Some(stop));
+
+    // A peer that's in sync with us is told so.
+    let tip = hash(&alice, 30);
+    assert_eq!(getheaders(&mut alice, vec![tip], unknown), Some(vec![]));
+
+    // Too many locators is considered misbehavior.
+    let locators = vec![tip; syncmgr::MAX_LOCATOR_HASHES + 1];
+    alice.received(
+        &remote,
+        NetworkMessage::GetHeaders(GetHeadersMessage::new(locators, unknown)),
+    );
+    alice
+        .events()
+        .find(|e| matches!(e, Event::PeerMisbehaved { addr, .. } if *addr == remote))
+        .expect("Remote is misbehaving");
+}
+
 #[test]
 fn test_bad_magic() {
     let rng = fastrand::Rng::new();
@@ -265,6 +367,51 @@ fn test_maintain_connections() {
     assert!(addrs.is_empty());
 }
 
+#[test]
+fn test_parallel_sync() {
+    let rng = fastrand::Rng::new();
+    let network = Network::Mainnet;
+    let headers = BITCOIN_HEADERS.tail[0..10].to_vec();
+    let mut alice = Peer::genesis("alice", [48, 48, 48, 48], network, vec![], rng);
+    let peers: Vec<PeerId> = vec![
+        ([55, 55, 55, 55], network.port()).into(),
+        ([66, 66, 66, 66], network.port()).into(),
+        ([77, 77, 77, 77], network.port()).into(),
+        ([88, 88, 88, 88], network.port()).into(),
+    ];
+    for peer in peers.iter() {
+        alice.connect(
+            &PeerDummy {
+                addr: *peer,
+                height: 144,
+                protocol_version: PROTOCOL_VERSION,
+                services: syncmgr::REQUIRED_SERVICES,
+                relay: true,
+                time: alice.local_time(),
+            },
+            Link::Outbound,
+        );
+    }
+
+    // The same headers are asked from several peers.
+    let asked = alice
+        .writes()
+        .filter(|(_, m)| matches!(m, NetworkMessage::GetHeaders(_)))
+        .map(|(addr, _)| addr)
+        .collect::<Vec<_>>();
+    assert_eq!(asked.len(), syncmgr::SYNC_REQUEST_PEERS);
+    assert_eq!(asked.iter().collect::<HashSet<_>>().len(), asked.len());
+
+    // The first to respond is imported, and the others are ignored.
+    for addr in asked.iter() {
+        alice.received(addr, NetworkMessage::Headers(headers.clone()));
+    }
+    assert_eq!(alice.protocol.tree.height(), headers.len() as Height);
+    assert!(!alice
+        .events()
+        .any(|e| matches!(e, Event::PeerMisbehaved { .. })));
+}
+
 #[test]
 fn test_getheaders_retry() {
     let rng = fastrand::Rng::new();
@@ -449,6 +596,45 @@ fn test_handshake_version_hook() {
         .expect("peer should send a 'verack' message back");
 }
 
+#[test]
+fn test_handshake_required_services() {
+    let network = Network::Mainnet;
+    let rng = fastrand::Rng::new();
+    let cfg = Config {
+        required_services: ServiceFlags::NETWORK | ServiceFlags::BLOOM,
+        preferred_services: ServiceFlags::NETWORK | ServiceFlags::BLOOM,
+        ..Config::default()
+    };
+    let mut peer = Peer::config("alice", [48, 48, 48, 48], vec![], vec![], vec![], cfg, rng);
+    let full = PeerDummy::new([131, 31, 11, 33], network, 144, ServiceFlags::NETWORK);
+    let bloom = PeerDummy::new(
+        [131, 31, 11, 66],
+        network,
+        144,
+        ServiceFlags::NETWORK | ServiceFlags::BLOOM,
+    );
+    peer.init();
+
+    for remote in [&full, &bloom] {
+        peer.protocol.peermgr.connect(&remote.addr);
+        peer.protocol
+            .connected(remote.addr, &peer.addr, Link::Outbound);
+        peer.received(
+            &remote.addr,
+            NetworkMessage::Version(remote.version(peer.addr, 0)),
+        );
+    }
+    let disconnected = peer
+        .outputs()
+        .filter_map(|o| match o {
+            Io::Disconnect(addr, DisconnectReason::PeerServices(_)) => Some(addr),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(disconnected, vec![full.addr]);
+}
+
 #[test]
 fn test_handshake_initial_messages() {
     let rng = fastrand::Rng::new();
@@ -851,7 +1037,7 @@ fn test_submit_transactions() {
     assert_eq!(Vec::from(remotes), vec![remote1.addr]);
     assert!(alice.protocol.invmgr.contains(&tx.txid()));
 
-    alice.tock();
+    alice.elapse(invmgr::MAX_TRICKLE_DELAY);
     alice
         .messages(&remote1.addr)
         .find(|msg| msg == &NetworkMessage::Inv(inventory.clone()))
@@ -865,6 +1051,79 @@ fn test_submit_transactions() {
         .expect("Alice responds to `getdata` with a `tx` message");
 }
 
+#[test]
+fn test_peer_stats() {
+    let network = Network::Mainnet;
+    let rng = fastrand::Rng::new();
+    let mut alice = Peer::genesis("alice", [48, 48, 48, 48], network, vec![], rng);
+    let remote = PeerDummy::new([131, 31, 11, 33], network, 144, ServiceFlags::NETWORK);
+
+    alice.connect(&remote, Link::Outbound);
+    alice.received(&remote.addr, NetworkMessage::Ping(42));
+    alice.drain();
+
+    let (transmit, receive) = chan::bounded(1);
+    alice.command(Command::GetPeerStats(transmit));
+
+    let stats = receive.recv().unwrap();
+    assert_eq!(stats.len(), 1);
+
+    let stats = &stats[0];
+    assert_eq!(stats.addr, remote.addr);
+    assert!(stats.messages["version"].sent > 0);
+    assert!(stats.messages["version"].received > 0);
+    assert_eq!(stats.messages["ping"].received, 32);
+    assert_eq!(stats.messages["pong"].sent, 32);
+    assert_eq!(
+        stats.traffic.total(),
+        stats.messages.values().map(|t| t.total()).sum::<u64>()
+    );
+
+    alice.disconnected(
+        &remote.addr,
+        DisconnectReason::PeerTimeout("timeout").into(),
+    );
+
+    let (transmit, receive) = chan::bounded(1);
+    alice.command(Command::GetPeerStats(transmit));
+    assert!(receive.recv().unwrap().is_empty());
+}
+
+#[test]
+fn test_flooding() {
+    let network = Network::Mainnet;
+    let rng = fastrand::Rng::new();
+    let mut alice = Peer::genesis("alice", [48, 48, 48, 48], network, vec![], rng);
+    let remote = PeerDummy::new([131, 31, 11, 33], network, 144, ServiceFlags::NETWORK);
+    let burst = (ratelimit::Rate::UNSOLICITED.messages * ratelimit::BURST) as u64;
+
+    alice.connect(&remote, Link::Outbound);
+    alice.drain();
+
+    // Messages over the rate limit are dropped.
+    for nonce in 0..=burst {
+        alice.received(&remote.addr, NetworkMessage::Ping(nonce));
+    }
+    assert_eq!(
+        alice
+            .messages(&remote.addr)
+            .filter(|m| matches!(m, NetworkMessage::Pong(_)))
+            .count() as u64,
+        burst
+    );
+
+    // Peers that keep sending them are disconnected.
+    for nonce in 0..ratelimit::MAX_EXCESS_MESSAGES as u64 {
+        alice.received(&remote.addr, NetworkMessage::Ping(nonce));
+    }
+    alice
+        .outputs()
+        .find(|o| {
+            matches!(o, Io::Disconnect(a, DisconnectReason::Flooding("ping")) if a == &remote.addr)
+        })
+        .expect("Alice disconnects the remote for flooding");
+}
+
 /// Should rebroadcast `inv` when no `getdata` is received.
 /// Should rebroadcast when a new peer connects.
 #[test]
@@ -883,7 +1142,7 @@ fn test_inv_rebroadcast() {
     alice.connect_addr(&remote1, Link::Outbound);
     alice.command(Command::SubmitTransaction(tx1, transmit.clone()));
     alice.command(Command::SubmitTransaction(tx2, transmit));
-    alice.tock(); // Broadcasting doesn't happen immediately
+    alice.elapse(invmgr::MAX_TRICKLE_DELAY); // Broadcasting doesn't happen immediately
     alice
         .messages(&remote1)
         .find(|m| {
@@ -896,7 +1155,7 @@ fn test_inv_rebroadcast() {
 
     alice.outputs().count(); // Drain outputs
     alice.connect_addr(&remote2, Link::Outbound); // A new peer connects
-    alice.tock(); // No delay until invs are sent to it
+    alice.elapse(invmgr::MAX_TRICKLE_DELAY); // Invs are sent to it after a random delay
     alice
         .messages(&remote2)
         .find(|m| matches!(m, NetworkMessage::Inv(_)))
@@ -1054,8 +1313,10 @@ fn test_confirmed_transaction() {
         rng.clone(),
     );
 
-    let blk1 = &chain[rng.usize(1..chain.len() / 2)];
-    let blk2 = &chain[rng.usize(chain.len() / 2..chain.len())];
+    let blk1_height = rng.usize(1..chain.len() / 2);
+    let blk2_height = rng.usize(chain.len() / 2..chain.len());
+    let blk1 = &chain[blk1_height];
+    let blk2 = &chain[blk2_height];
     let tx1 = &blk1.txdata[rng.usize(0..blk1.txdata.len())];
     let tx2 = &blk2.txdata[rng.usize(0..blk2.txdata.len())];
 
@@ -1067,8 +1328,14 @@ fn test_confirmed_transaction() {
     assert!(alice.protocol.invmgr.contains(&tx1.txid()));
     assert!(alice.protocol.invmgr.contains(&tx2.txid()));
 
-    alice.protocol.invmgr.get_block(blk1.block_hash());
-    alice.protocol.invmgr.get_block(blk2.block_hash());
+    alice
+        .protocol
+        .invmgr
+        .get_block(blk1_height as Height, blk1.block_hash());
+    alice
+        .protocol
+        .invmgr
+        .get_block(blk2_height as Height, blk2.block_hash());
 
     alice.tock();
     alice.received(&remote, NetworkMessage::Block(blk2.clone()));
@@ -1244,6 +1511,56 @@ fn test_submitted_transaction_filtering() {
     );
 }
 
+#[test]
+fn test_rescan_filters() {
+    let network = Network::Regtest;
+    let mut rng = fastrand::Rng::new();
+    let remote: PeerId = ([88, 88, 88, 88], 8333).into();
+    let watch = vec![gen::script(&mut rng)];
+    let mut alice = Peer::genesis("alice", [48, 48, 48, 48], network, vec![], rng);
+
+    // Without a peer serving compact filters, the rescan isn't started.
+    let (transmit, receive) = chan::bounded(1);
+    alice.command(Command::RescanFilters {
+        from: Bound::Unbounded,
+        to: Bound::Unbounded,
+        watch: watch.clone(),
+        reply: transmit,
+    });
+    assert_matches!(
+        receive.try_recv(),
+        Ok(Err(cbfmgr::GetFiltersError::NotConnected))
+    );
+    assert!(!alice.protocol.cbfmgr.rescan.active);
+
+    alice.connect(
+        &PeerDummy {
+            addr: remote,
+            height: 0,
+            protocol_version: PROTOCOL_VERSION,
+            services: cbfmgr::REQUIRED_SERVICES | syncmgr::REQUIRED_SERVICES,
+            relay: true,
+            time: alice.local_time(),
+        },
+        Link::Outbound,
+    );
+
+    let (transmit, receive) = chan::bounded(1);
+    alice.command(Command::RescanFilters {
+        from: Bound::Unbounded,
+        to: Bound::Unbounded,
+        watch,
+        reply: transmit,
+    });
+    assert_matches!(receive.try_recv(), Ok(Ok(())));
+    assert!(alice.protocol.cbfmgr.rescan.active);
+
+    alice
+        .events()
+        .find(|e| matches!(e, Event::FilterRescanStarted { start: 1, stop: None }))
+        .expect("Alice starts rescanning from the next block");
+}
+
 #[test]
 fn test_transaction_reverted_reconfirm() {
     let height = 16;
@@ -365,6 +365,7 @@ pub fn network(network: Network, size: usize, rng: fastrand::Rng) -> Vec<Peer<Pr
                 // These nodes don't need to try connecting to other nodes.
                 limits: Limits {
                     max_outbound_peers: 0,
+                    sync_outbound_peers: 0,
                     ..Limits::default()
                 },
                 // These are full nodes.
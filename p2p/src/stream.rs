@@ -2,6 +2,7 @@
 use std::io;
 
 use nakamoto_common::bitcoin::consensus::{encode, Decodable};
+use nakamoto_common::bitcoin::network::message::RawNetworkMessage;
 
 /// Message stream decoder.
 ///
@@ -9,6 +10,12 @@ use nakamoto_common::bitcoin::consensus::{encode, Decodable};
 #[derive(Debug)]
 pub struct Decoder {
     unparsed: Vec<u8>,
+    /// Number of bytes decoded at the start of the buffer. These are only discarded when more
+    /// input comes in, so that decoding many messages at once doesn't shift the buffer each
+    /// time.
+    decoded: usize,
+    /// Initial capacity of the buffer.
+    capacity: usize,
 }
 
 impl Decoder {
@@ -16,20 +23,57 @@ impl Decoder {
     pub fn new(capacity: usize) -> Self {
         Self {
             unparsed: Vec::with_capacity(capacity),
+            decoded: 0,
+            capacity,
         }
     }
 
     /// Input bytes into the decoder.
     pub fn input(&mut self, bytes: &[u8]) {
+        if self.decoded > 0 {
+            self.unparsed.drain(..self.decoded);
+            self.decoded = 0;
+        }
         self.unparsed.extend_from_slice(bytes);
     }
 
+    /// Discard all input, keeping the buffer for reuse. The buffer is shrunk back to its
+    /// initial capacity if it grew past it, eg. to decode a block.
+    pub fn reset(&mut self) {
+        self.unparsed.clear();
+        self.unparsed.shrink_to(self.capacity);
+        self.decoded = 0;
+    }
+
     /// Decode and return the next message. Returns [`None`] if nothing was decoded.
     pub fn decode_next<D: Decodable>(&mut self) -> Result<Option<D>, encode::Error> {
-        match encode::deserialize_partial::<D>(&self.unparsed) {
+        self.decode_with(encode::deserialize_partial::<D>)
+    }
+
+    /// Decode and return the next network message. Returns [`None`] if the message wasn't
+    /// entirely received yet.
+    ///
+    /// Unlike [`Decoder::decode_next`], which attempts to decode the message again every time
+    /// more of it is received, this only decodes it once it's complete, and without copying
+    /// its payload out of the buffer. The only allocations made are for the message's own
+    /// fields, eg. a block's transactions: messages such as `ping` are decoded without any.
+    pub fn decode_message(&mut self) -> Result<Option<RawNetworkMessage>, encode::Error> {
+        self.decode_with(RawNetworkMessage::decode_from_slice)
+    }
+
+    fn decode_with<D>(
+        &mut self,
+        decode: impl FnOnce(&[u8]) -> Result<(D, usize), encode::Error>,
+    ) -> Result<Option<D>, encode::Error> {
+        match decode(&self.unparsed[self.decoded..]) {
             Ok((msg, index)) => {
-                // Drain deserialized bytes only.
-                self.unparsed.drain(..index);
+                // Skip over deserialized bytes only.
+                self.decoded += index;
+
+                if self.decoded == self.unparsed.len() {
+                    self.unparsed.clear();
+                    self.decoded = 0;
+                }
                 Ok(Some(msg))
             }
 
@@ -41,10 +85,51 @@ impl Decoder {
     }
 }
 
+/// Pool of stream decoders, so that their buffers are reused across connections instead of
+/// being allocated for each one.
+#[derive(Debug)]
+pub struct Pool {
+    decoders: Vec<Decoder>,
+    /// Maximum number of decoders kept in the pool.
+    size: usize,
+    /// Initial buffer capacity of new decoders.
+    capacity: usize,
+}
+
+impl Pool {
+    /// Create a new pool, keeping up to `size` decoders with the given buffer capacity.
+    pub fn new(size: usize, capacity: usize) -> Self {
+        Self {
+            decoders: Vec::with_capacity(size),
+            size,
+            capacity,
+        }
+    }
+
+    /// Take a decoder from the pool, or create one if the pool is empty.
+    pub fn take(&mut self) -> Decoder {
+        self.decoders
+            .pop()
+            .unwrap_or_else(|| Decoder::new(self.capacity))
+    }
+
+    /// Return a decoder to the pool, once its connection is closed.
+    pub fn put(&mut self, mut decoder: Decoder) {
+        if self.decoders.len() < self.size {
+            decoder.reset();
+            self.decoders.push(decoder);
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use nakamoto_common::bitcoin::network::message::{NetworkMessage, RawNetworkMessage};
+    use nakamoto_common::bitcoin::consensus::serialize;
+    use nakamoto_common::bitcoin::network::message::NetworkMessage;
+    use nakamoto_test::block::gen;
+
+    use crate::fsm::network::Network;
     use quickcheck_macros::quickcheck;
 
     const MSG_VERACK: [u8; 24] = [
@@ -94,4 +179,54 @@ mod test {
             }
         );
     }
+    #[quickcheck]
+    fn prop_decode_message(chunk_size: usize) {
+        let mut rng = fastrand::Rng::new();
+        let mut bytes = vec![];
+        let mut msgs = vec![];
+        let mut decoder = Decoder::new(1024);
+
+        let chunk_size = 1 + chunk_size % decoder.unparsed.capacity();
+        let block = gen::block(&Network::Regtest.genesis(), &mut rng);
+        let expected = vec![
+            NetworkMessage::Verack,
+            NetworkMessage::Block(block),
+            NetworkMessage::Ping(100),
+        ];
+        for payload in expected.iter().cloned() {
+            bytes.extend(serialize(&RawNetworkMessage {
+                magic: 3652501241,
+                payload,
+            }));
+        }
+
+        for chunk in bytes.as_slice().chunks(chunk_size) {
+            decoder.input(chunk);
+
+            while let Some(msg) = decoder.decode_message().unwrap() {
+                msgs.push(msg.payload);
+            }
+        }
+        assert_eq!(decoder.unparsed.len(), 0);
+        assert_eq!(msgs, expected);
+    }
+
+    #[test]
+    fn test_pool() {
+        let mut pool = Pool::new(1, 1024);
+        let mut decoder = pool.take();
+
+        decoder.input(&[0; 4096]);
+        decoder.input(&MSG_VERACK[..12]);
+        pool.put(decoder);
+        pool.put(Decoder::new(1024));
+
+        let mut decoder = pool.take();
+        assert_eq!(decoder.unparsed.len(), 0);
+        assert!(decoder.unparsed.capacity() < 4096);
+
+        decoder.input(&MSG_VERACK);
+        assert!(decoder.decode_message().unwrap().is_some());
+        assert!(pool.decoders.is_empty());
+    }
 }
@@ -0,0 +1,104 @@
+//! Checks that decoding network messages from a byte stream doesn't allocate, besides what the
+//! decoded messages hold.
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use nakamoto_common::bitcoin::consensus::serialize;
+use nakamoto_common::bitcoin::network::message::{NetworkMessage, RawNetworkMessage};
+use nakamoto_common::network::Network;
+use nakamoto_p2p::stream::Decoder;
+
+/// Size of the chunks the stream is received in. Small enough for messages to span chunks.
+const CHUNK_SIZE: usize = 7;
+
+/// Allocator counting the allocations made by each thread, since tests run concurrently.
+struct Counter;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counter {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|n| n.set(n.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, size: usize) -> *mut u8 {
+        ALLOCATIONS.with(|n| n.set(n.get() + 1));
+        System.realloc(ptr, layout, size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counter = Counter;
+
+/// Number of allocations made by the current thread while running the given function.
+fn allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+/// Messages without fields of their own, and their serialization as a stream.
+fn stream() -> (Vec<NetworkMessage>, Vec<u8>) {
+    let magic = Network::Mainnet.magic();
+    let msgs = vec![
+        NetworkMessage::Verack,
+        NetworkMessage::Ping(1),
+        NetworkMessage::Pong(1),
+        NetworkMessage::SendHeaders,
+        NetworkMessage::FeeFilter(1000),
+        NetworkMessage::GetAddr,
+    ];
+    let bytes = msgs
+        .iter()
+        .cloned()
+        .flat_map(|payload| serialize(&RawNetworkMessage { magic, payload }))
+        .collect();
+
+    (msgs, bytes)
+}
+
+#[test]
+fn test_decode_message_without_allocating() {
+    let (expected, bytes) = stream();
+    let mut decoder = Decoder::new(1024);
+    let mut msgs = Vec::with_capacity(expected.len());
+
+    let count = allocations(|| {
+        for chunk in bytes.chunks(CHUNK_SIZE) {
+            decoder.input(chunk);
+
+            while let Some(msg) = decoder.decode_message().unwrap() {
+                msgs.push(msg.payload);
+            }
+        }
+    });
+    assert_eq!(msgs, expected);
+    assert_eq!(count, 0);
+}
+
+#[test]
+fn test_decode_next_allocates() {
+    let (expected, bytes) = stream();
+    let mut decoder = Decoder::new(1024);
+    let mut msgs = Vec::with_capacity(expected.len());
+
+    // The payload is copied out of the buffer, and the command is allocated.
+    let count = allocations(|| {
+        for chunk in bytes.chunks(CHUNK_SIZE) {
+            decoder.input(chunk);
+
+            while let Some(msg) = decoder.decode_next::<RawNetworkMessage>().unwrap() {
+                msgs.push(msg.payload);
+            }
+        }
+    });
+    assert_eq!(msgs, expected);
+    assert!(count >= expected.len() * 2);
+}
@@ -64,4 +64,6 @@ pub mod net {
     pub use nakamoto_net::*;
     #[cfg(feature = "nakamoto-net-poll")]
     pub use nakamoto_net_poll as poll;
+    #[cfg(feature = "nakamoto-net-tokio")]
+    pub use nakamoto_net_tokio as tokio;
 }
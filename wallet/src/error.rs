@@ -1,11 +1,13 @@
 use std::io;
 
 use nakamoto_client::handle;
+use nakamoto_common::bitcoin::consensus::encode;
+use nakamoto_common::bitcoin::policy;
 use thiserror::Error;
 
 use crate::{
     input,
-    wallet::{db, hw, ui},
+    wallet::{backend, db, descriptor, hw, tx, ui},
 };
 
 /// An error occuring in the wallet.
@@ -27,4 +29,18 @@ pub enum Error {
     Db(#[from] db::Error),
     #[error(transparent)]
     Hw(#[from] hw::Error),
+    #[error(transparent)]
+    Descriptor(#[from] descriptor::Error),
+    #[error(transparent)]
+    Tx(#[from] tx::Error),
+    #[error("transaction would not be relayed: {0}")]
+    Policy(#[from] policy::Error),
+    #[error("error decoding snapshot: {0}")]
+    SnapshotDecoding(#[from] encode::Error),
+    #[error(transparent)]
+    Backend(#[from] backend::Error),
+    #[error("the wallet birth is unknown; give one with `--birth-height` or `--birth-date`")]
+    UnknownBirth,
+    #[error("wallet error: {0}")]
+    Remote(String),
 }
@@ -1,7 +1,7 @@
 use std::io;
 
 use crossbeam_channel as chan;
-use termion::event::{Event, Key};
+use termion::event::Event;
 use termion::input::TermRead;
 use thiserror::Error;
 
@@ -31,9 +31,6 @@ pub fn run(channel: chan::Sender<Event>, exit: chan::Receiver<()>) -> Result<(),
         if exit.try_recv().is_ok() {
             return Ok(());
         }
-        if let Event::Key(Key::Char('q')) | Event::Key(Key::Esc) = event {
-            return Ok(());
-        }
         channel.send(event)?;
     }
     Ok(())
@@ -42,11 +39,11 @@ pub fn run(channel: chan::Sender<Event>, exit: chan::Receiver<()>) -> Result<(),
 pub fn signals(channel: chan::Sender<Signal>) -> Result<(), Error> {
     use signal_hook::consts::signal::*;
 
-    let mut signals = signal_hook::iterator::Signals::new([SIGWINCH, SIGINT])?;
+    let mut signals = signal_hook::iterator::Signals::new([SIGWINCH, SIGINT, SIGTERM])?;
     for signal in signals.forever() {
         match signal {
             SIGWINCH => channel.send(Signal::WindowResized)?,
-            SIGINT => channel.send(Signal::Interrupted)?,
+            SIGINT | SIGTERM => channel.send(Signal::Interrupted)?,
             _ => {}
         }
     }
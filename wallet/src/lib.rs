@@ -3,16 +3,13 @@
 pub mod error;
 pub mod input;
 pub mod logger;
+pub mod rpc;
 pub mod wallet;
 
-use std::path::Path;
-use std::{io, net, thread};
+use std::path::{Path, PathBuf};
+use std::{fs, io, net, thread};
 
-use nakamoto_common::bitcoin::network::message_bloom::{BloomFlags, FilterLoad};
-use nakamoto_common::bitcoin::util::bloom::BloomFilter;
-use nakamoto_common::bitcoin_hashes::hex::FromHex;
-use nakamoto_common::bloom::store::cache::PrivacySegment;
-use nakamoto_common::collections::HashMap;
+use serde_json::json;
 use termion::raw::IntoRawMode;
 use termion::screen::IntoAlternateScreen;
 
@@ -20,10 +17,23 @@ use nakamoto_client::chan;
 use nakamoto_client::handle::Handle;
 use nakamoto_client::Network;
 use nakamoto_client::{Client, Config};
+use nakamoto_common::bitcoin::consensus::encode;
+use nakamoto_common::bitcoin::network::constants::ServiceFlags;
 use nakamoto_common::bitcoin::util::bip32::DerivationPath;
+use nakamoto_common::bitcoin::Address;
+use nakamoto_common::block::snapshot::Snapshot;
 use nakamoto_common::block::Height;
 
 use crate::error::Error;
+use crate::wallet::backend::{self, Backend, Electrum, P2p};
+use crate::wallet::bcmr::Registries;
+use crate::wallet::bloom;
+use crate::wallet::db::{Read as _, Write as _};
+use crate::wallet::hw::{SignatureType, Signer};
+use crate::wallet::notify::{Hook, Notifier};
+use crate::wallet::utxo;
+use crate::wallet::Account;
+use crate::wallet::Birth;
 use crate::wallet::Db;
 use crate::wallet::Hw;
 use crate::wallet::Wallet;
@@ -32,77 +42,332 @@ use crate::wallet::Wallet;
 type Reactor = nakamoto_net_poll::Reactor<net::TcpStream>;
 
 /// Entry point for running the wallet.
+///
+/// If an Electrum server address is given, the wallet syncs from it instead of scanning
+/// blocks from the peer-to-peer network. If an RPC address is given, a JSON-RPC server is
+/// started on it, authenticating TCP requests with a cookie written next to the wallet file.
+/// In daemon mode, the wallet runs without its terminal UI. The given hooks are notified of
+/// payments, confirmations and re-orgs affecting the wallet. The given
+/// addresses are watched along with the wallet's own, eg. to follow contracts. Blocks are
+/// scanned with compact block filters rather than bloom filters if these are preferred, and
+/// served by a peer. Block headers and compact filter headers are stored in the given data
+/// directory, or the platform's, so that they aren't downloaded again on each start. If token
+/// registries are given, token metadata is fetched from them. If a log buffer is given, its
+/// lines can be shown in the terminal UI. If no birth is given, the one stored in the wallet
+/// file by [`setup`] is used. A birth date is looked up in the block headers, once loaded.
 pub fn run(
     wallet: &Path,
-    birth: Height,
+    root: Option<&Path>,
+    birth: Option<Birth>,
+    snapshot: Option<&Path>,
     hd_path: DerivationPath,
+    watch: Vec<Address>,
+    signer: Option<Signer>,
+    signature_type: SignatureType,
     network: Network,
     connect: Vec<net::SocketAddr>,
+    electrum: Option<net::SocketAddr>,
+    rpc: Option<rpc::Listen>,
+    daemon: bool,
+    hooks: Vec<Hook>,
+    registries: Vec<String>,
     offline: bool,
+    bloom_segments: usize,
+    bloom_fp_rate: f64,
+    bloom_decoys: bloom::Decoys,
+    compact_filters: bool,
+    log: Option<logger::Buffer>,
 ) -> Result<(), Error> {
-    let mut script_hash = Vec::from_hex("347eeb9896b64a484d1019a16075c194a17e6081").unwrap();
-    // Vec::from_hex("64462479fb3bf5b307ab42123dea68d9ec6db353").unwrap();
-    // Vec::from_hex("7dcc5bd98ad7f437957c28d4d0312d91818d1d236531b5ae78e59e10b9610155").unwrap();
-    // Vec::from_hex("84487d5b5448dcb272921965eebb266728b25853").unwrap();
-
-    let mut bf = BloomFilter::new(1000, 0.0001, 987987, 0);
-    bf.insert(&mut script_hash);
-    // let data = bf.content;
-
-    // let bloom_filters = FilterLoad {
-    //     filter: data,
-    //     hash_funcs: bf.hashes,
-    //     tweak: bf.tweak,
-    //     flags: match bf.flags {
-    //         0 => BloomFlags::None,
-    //         1 => BloomFlags::All,
-    //         2 => BloomFlags::PubkeyOnly,
-    //         _ => BloomFlags::None,
-    //     },
-    // };
-    let privacy_segment = PrivacySegment {
-        filter: bf,
-        ..Default::default()
+    log::info!("Opening wallet file `{}`..", wallet.display());
+
+    let db = Db::open(wallet)?;
+    let rpc = rpc.map(|listen| (listen, rpc::cookie_path(wallet)));
+    let birth = match birth {
+        Some(birth) => birth,
+        None => Birth::load(&db)?.ok_or(Error::UnknownBirth)?,
+    };
+    let snapshot = match snapshot {
+        Some(path) => {
+            log::info!("Reading snapshot file `{}`..", path.display());
+            Some(encode::deserialize(&fs::read(path)?)?)
+        }
+        None => None,
+    };
+    let mut hw = Hw::new(hd_path).with_signature_type(signature_type);
+    if let Some(signer) = signer {
+        hw = hw.with_signer(signer);
+    }
+    let mut addresses = crate::wallet::addresses(&db, &mut hw, network.into())?;
+
+    for addr in &watch {
+        log::info!("Watching address {}..", addr);
+    }
+    addresses.extend(watch.iter().cloned());
+
+    log::info!(
+        "Building {} bloom filter segment(s) for {} address(es)..",
+        bloom_segments,
+        addresses.len()
+    );
+    // A birth date is only looked up once the block headers are loaded, so segments are
+    // then born at genesis. Scanning still starts at the birth date's height.
+    let segment_birth = match birth {
+        Birth::Height(height) => height,
+        Birth::Date(_) => 0,
+    };
+    let pubkeys = crate::wallet::pubkeys(&db)?;
+    let utxos = db.utxos()?;
+    let segments = bloom::segments(
+        &addresses,
+        &pubkeys,
+        &utxos,
+        bloom_segments,
+        bloom_fp_rate,
+        bloom_decoys,
+        segment_birth,
+        fastrand::Rng::new(),
+    );
+    for hook in &hooks {
+        log::info!("Adding notification hook {}..", hook);
+    }
+    let notifier = Notifier::spawn(hooks);
+
+    for registry in &registries {
+        log::info!("Adding token registry {}..", registry);
+    }
+    let registries = if registries.is_empty() || offline {
+        Registries::default()
+    } else {
+        Registries::spawn(registries)
     };
-    let mut bf_map = HashMap::with_hasher(fastrand::Rng::new().into());
-    bf_map.insert(0, privacy_segment);
-    let cfg = Config {
+
+    if let Some(addr) = electrum.filter(|_| !offline) {
+        log::info!("Connecting to Electrum server {}..", addr);
+
+        let (server, events) = Electrum::connect(addr).map_err(backend::Error::from)?;
+        // There is no loading phase when syncing from an Electrum server.
+        let (_, loading) = chan::bounded(0);
+
+        run_wallet(
+            Wallet::new(server.clone(), network, db, hw)
+                .with_watched(watch)
+                .with_notifier(notifier)
+                .with_registries(registries),
+            birth,
+            snapshot,
+            loading,
+            events,
+            rpc,
+            daemon,
+            offline,
+            log,
+        )?;
+
+        log::info!("Disconnecting from Electrum server..");
+        server.shutdown().map_err(backend::Error::from)?;
+
+        return Ok(());
+    }
+
+    let mut cfg = Config {
         network,
         connect,
         listen: vec![], // Don't listen for incoming connections.
-        bloom_segments: bf_map,
+        bloom_segments: segments,
+        bloom_fp_rate,
+        bloom_decoys,
         ..Config::default()
     };
+    if let Some(root) = root {
+        cfg.root = root.to_owned();
+    }
+    if compact_filters {
+        log::info!("Preferring peers serving compact block filters..");
+        cfg.preferred_services |= ServiceFlags::COMPACT_FILTERS;
+    }
 
     // Create a new client using `Reactor` for networking.
     let client = Client::<Reactor>::new()?;
     let handle = client.handle();
     let client_recv = handle.events();
     let (loading_send, loading_recv) = chan::unbounded();
+    let (events_send, events_recv) = chan::unbounded();
+
+    let backend = P2p::new(handle.clone()).with_compact_filters(compact_filters);
+
+    log::info!("Spawning client threads..");
+
+    // Forward client events to the wallet.
+    thread::spawn(move || backend::p2p::forward(client_recv, events_send));
+    // Start the network client in the background.
+    let client_thread = thread::spawn(move || {
+        if offline {
+            Ok(())
+        } else {
+            client.load(cfg, loading_send)?.run()
+        }
+    });
+
+    run_wallet(
+        Wallet::new(backend, network, db, hw)
+            .with_watched(watch)
+            .with_notifier(notifier)
+            .with_registries(registries),
+        birth,
+        snapshot,
+        loading_recv,
+        events_recv,
+        rpc,
+        daemon,
+        offline,
+        log,
+    )?;
+
+    // Shutdown the client, since the main loop exited.
+    log::info!("Shutting down client..");
+    handle.shutdown()?;
+
+    client_thread.join().unwrap()?;
+
+    Ok(())
+}
 
+/// Set up a wallet file, creating it if needed, without connecting to the network.
+///
+/// The birth is stored in the wallet file, for scanning to start from it. The given accounts
+/// are added, and their addresses derived. If there are none, addresses are requested from
+/// the signer. If a number of confirmations is given, it is stored as the wallet's setting
+/// for when payments are final. Returns the wallet's addresses.
+pub fn setup(
+    wallet: &Path,
+    birth: Birth,
+    hd_path: DerivationPath,
+    accounts: Vec<Account>,
+    signer: Option<Signer>,
+    signature_type: SignatureType,
+    network: Network,
+    min_confirmations: Option<Height>,
+) -> Result<Vec<Address>, Error> {
     log::info!("Opening wallet file `{}`..", wallet.display());
 
     let db = Db::open(wallet)?;
-    let hw = Hw::new(hd_path);
 
-    let (inputs_tx, inputs_rx) = crossbeam_channel::unbounded();
-    let (exit_tx, exit_rx) = crossbeam_channel::bounded(1);
+    log::info!("Setting the wallet birth to {}..", birth);
+    birth.save(&db)?;
+
+    for account in &accounts {
+        log::info!(
+            "Adding account `{}` ({})..",
+            account.label,
+            account.descriptor
+        );
+        db.add_account(account)?;
+    }
+    if let Some(min_confirmations) = min_confirmations {
+        log::info!(
+            "Setting the number of confirmations for payments to be final to {}..",
+            min_confirmations
+        );
+        utxo::Settings {
+            min_confirmations,
+            ..utxo::Settings::load(&db)?
+        }
+        .save(&db)?;
+    }
+    let mut hw = Hw::new(hd_path).with_signature_type(signature_type);
+    if let Some(signer) = signer {
+        hw = hw.with_signer(signer);
+    }
+    crate::wallet::addresses(&db, &mut hw, network.into())
+}
+
+/// Export what a wallet file needs to be restored: its birth, accounts and addresses.
+pub fn export(wallet: &Path) -> Result<serde_json::Value, Error> {
+    let db = Db::open(wallet)?;
+    let birth = Birth::load(&db)?;
+    let accounts = db.accounts()?;
+    let addresses = db.addresses()?;
+
+    Ok(json!({
+        "birth": birth.map(|b| b.to_string()),
+        "accounts": accounts.iter().map(|a| a.to_string()).collect::<Vec<_>>(),
+        "addresses": addresses
+            .iter()
+            .map(|a| json!({
+                "address": a.address.to_string(),
+                "index": a.index,
+                "label": a.label,
+                "account": a.account,
+            }))
+            .collect::<Vec<_>>(),
+    }))
+}
+
+/// Run the main wallet loop, along with the input threads, until the wallet exits.
+///
+/// In daemon mode, the terminal is left alone: there is no user input, and the UI isn't
+/// drawn anywhere. The wallet is then driven over RPC, served along with the path of its
+/// authentication cookie.
+fn run_wallet<B: Backend>(
+    mut wallet: Wallet<B>,
+    birth: Birth,
+    snapshot: Option<Snapshot>,
+    loading: chan::Receiver<nakamoto_client::Loading>,
+    events: chan::Receiver<backend::Event>,
+    rpc: Option<(rpc::Listen, PathBuf)>,
+    daemon: bool,
+    offline: bool,
+    log: Option<logger::Buffer>,
+) -> Result<(), Error> {
     let (signals_tx, signals_rx) = crossbeam_channel::unbounded();
+    // The wallet holds on to a sender, so that the channel stays open without a server.
+    let (calls_tx, calls_rx) = crossbeam_channel::unbounded();
 
-    log::info!("Spawning client threads..");
+    if let Some((listen, cookie)) = rpc {
+        let calls = calls_tx.clone();
+
+        // Start the RPC server in the background.
+        thread::spawn(move || {
+            if let Err(err) = rpc::serve(&listen, &cookie, calls) {
+                log::error!("RPC server failed: {}", err);
+            }
+        });
+    }
+
+    if daemon {
+        // Start the signal handler thread. It blocks waiting for signals, so it isn't joined.
+        thread::spawn(|| input::signals(signals_tx));
+
+        // Run the main wallet loop. This will block until the wallet exits.
+        log::info!("Running main wallet loop in daemon mode..");
+        wallet.headless().run(
+            birth,
+            snapshot,
+            chan::never(),
+            signals_rx,
+            loading,
+            events,
+            calls_rx,
+            offline,
+            io::sink(),
+        )?;
+        drop(calls_tx);
+
+        log::info!("Exiting..");
+
+        return Ok(());
+    }
+
+    if let Some(buffer) = log {
+        wallet = wallet.with_log(buffer);
+    }
+    let (inputs_tx, inputs_rx) = crossbeam_channel::unbounded();
+    let (exit_tx, exit_rx) = crossbeam_channel::bounded(1);
 
     // Start the UI loop in the background.
     let t1 = thread::spawn(|| input::run(inputs_tx, exit_rx));
     // Start the signal handler thread.
     let t2 = thread::spawn(|| input::signals(signals_tx));
-    // Start the network client in the background.
-    let t3 = thread::spawn(move || {
-        if offline {
-            Ok(())
-        } else {
-            client.load(cfg, loading_send)?.run()
-        }
-    });
 
     log::info!("Switching to alternative screen..");
 
@@ -112,27 +377,17 @@ pub fn run(
 
     // Run the main wallet loop. This will block until the wallet exits.
     log::info!("Running main wallet loop..");
-    Wallet::new(handle.clone(), network, db, hw).run(
-        birth,
-        inputs_rx,
-        signals_rx,
-        loading_recv,
-        client_recv,
-        offline,
-        term,
+    wallet.run(
+        birth, snapshot, inputs_rx, signals_rx, loading, events, calls_rx, offline, term,
     )?;
+    drop(calls_tx);
 
     // Tell other threads that they should exit.
     log::info!("Exiting..");
     exit_tx.send(()).unwrap();
 
-    // Shutdown the client, since the main loop exited.
-    log::info!("Shutting down client..");
-    handle.shutdown()?;
-
     t1.join().unwrap()?;
     t2.join().unwrap()?;
-    t3.join().unwrap()?;
 
     Ok(())
 }
@@ -1,43 +1,194 @@
 //! Logging module.
-use std::{io, time::SystemTime};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::{fs, io, time::SystemTime};
 
 use chrono::prelude::*;
 use log::{Level, Log, Metadata, Record, SetLoggerError};
+use thiserror::Error;
+
+/// A logger initialization error.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    SetLogger(#[from] SetLoggerError),
+}
+
+/// Maximum level of the records kept in a [`Buffer`], regardless of the logger level.
+pub const BUFFER_LEVEL: Level = Level::Debug;
+
+/// A log line, kept in a [`Buffer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    /// Level of the record.
+    pub level: Level,
+    /// Time of the record, formatted.
+    pub time: String,
+    /// Record message.
+    pub message: String,
+}
+
+/// A ring buffer of the latest log lines, shared with the logger. Once it's full, the
+/// oldest lines are dropped.
+#[derive(Debug, Clone)]
+pub struct Buffer {
+    capacity: usize,
+    inner: Arc<Mutex<Lines>>,
+}
+
+#[derive(Debug, Default)]
+struct Lines {
+    lines: VecDeque<Line>,
+    /// Number of lines pushed since the buffer was created.
+    pushed: u64,
+}
+
+impl Buffer {
+    /// Create a new buffer, holding up to the given number of lines.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Arc::new(Mutex::new(Lines::default())),
+        }
+    }
+
+    /// Add a line, dropping the oldest line if the buffer is full.
+    pub fn push(&self, line: Line) {
+        let mut inner = self.inner.lock().unwrap();
+
+        if inner.lines.len() >= self.capacity {
+            inner.lines.pop_front();
+        }
+        inner.lines.push_back(line);
+        inner.pushed += 1;
+    }
+
+    /// The lines at or above the given level, oldest first.
+    pub fn lines(&self, level: Level) -> Vec<Line> {
+        self.inner
+            .lock()
+            .unwrap()
+            .lines
+            .iter()
+            .filter(|l| l.level <= level)
+            .cloned()
+            .collect()
+    }
+
+    /// Number of lines pushed since the buffer was created. Changes whenever a line is added.
+    pub fn pushed(&self) -> u64 {
+        self.inner.lock().unwrap().pushed
+    }
+}
 
 struct Logger {
     level: Level,
-    stream: io::Stderr,
+    stream: Mutex<Box<dyn io::Write + Send>>,
+    buffer: Option<Buffer>,
 }
 
 impl Log for Logger {
     fn enabled(&self, metadata: &Metadata) -> bool {
         metadata.level() <= self.level
+            || (self.buffer.is_some() && metadata.level() <= BUFFER_LEVEL)
     }
 
     fn log(&self, record: &Record) {
-        if self.enabled(record.metadata()) {
-            write(record, &self.stream);
-
-            fn write(record: &log::Record, mut stream: impl io::Write) {
-                let now =
-                    DateTime::from(SystemTime::now()).to_rfc3339_opts(SecondsFormat::Millis, true);
-                writeln!(stream, "{} {}", now, record.args()).expect("write shouldn't fail");
-            }
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let now = DateTime::from(SystemTime::now()).to_rfc3339_opts(SecondsFormat::Millis, true);
+
+        if record.level() <= self.level {
+            let mut stream = self.stream.lock().unwrap();
+            writeln!(stream, "{} {}", now, record.args()).expect("write shouldn't fail");
+        }
+        if let Some(buffer) = &self.buffer {
+            buffer.push(Line {
+                level: record.level(),
+                time: now,
+                message: record.args().to_string(),
+            });
         }
     }
 
-    fn flush(&self) {}
+    fn flush(&self) {
+        self.stream.lock().unwrap().flush().ok();
+    }
+}
+
+/// Initialize a new logger. If a buffer is given, records up to [`BUFFER_LEVEL`] are also
+/// kept in it, whatever the logger level.
+pub fn init(level: Level, buffer: Option<Buffer>) -> Result<(), SetLoggerError> {
+    set(level, Box::new(io::stderr()), buffer)
 }
 
-/// Initialize a new logger.
-pub fn init(level: Level) -> Result<(), SetLoggerError> {
+/// Initialize a new logger, appending to the file at the given path.
+pub fn init_file<P: AsRef<Path>>(
+    level: Level,
+    path: P,
+    buffer: Option<Buffer>,
+) -> Result<(), Error> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+
+    set(level, Box::new(file), buffer).map_err(Error::from)
+}
+
+fn set(
+    level: Level,
+    stream: Box<dyn io::Write + Send>,
+    buffer: Option<Buffer>,
+) -> Result<(), SetLoggerError> {
+    let max = if buffer.is_some() {
+        level.max(BUFFER_LEVEL)
+    } else {
+        level
+    };
     let logger = Logger {
         level,
-        stream: io::stderr(),
+        stream: Mutex::new(stream),
+        buffer,
     };
 
     log::set_boxed_logger(Box::new(logger))?;
-    log::set_max_level(level.to_level_filter());
+    log::set_max_level(max.to_level_filter());
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(level: Level, message: &str) -> Line {
+        Line {
+            level,
+            time: String::new(),
+            message: message.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_buffer() {
+        let buffer = Buffer::new(2);
+
+        buffer.push(line(Level::Info, "a"));
+        buffer.push(line(Level::Debug, "b"));
+        assert_eq!(buffer.lines(Level::Debug).len(), 2);
+        assert_eq!(buffer.lines(Level::Info), vec![line(Level::Info, "a")]);
+
+        // The oldest line is dropped once the buffer is full.
+        buffer.push(line(Level::Error, "c"));
+        assert_eq!(
+            buffer.lines(Level::Trace),
+            vec![line(Level::Debug, "b"), line(Level::Error, "c")]
+        );
+        assert_eq!(buffer.pushed(), 3);
+    }
+}
@@ -1,68 +1,560 @@
+use std::fmt;
+use std::fs;
 use std::net;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use argh::FromArgs;
+use chrono::NaiveDate;
+use clap::{Args, Parser, Subcommand};
+use serde_json::{json, Value};
 
-use nakamoto_common::bitcoin::util::bip32::DerivationPath;
+use nakamoto_common::bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey};
 use nakamoto_common::bitcoin::Address;
 use nakamoto_common::block::Height;
 use nakamoto_common::network::Network;
+use nakamoto_node::config;
 use nakamoto_wallet::logger;
+use nakamoto_wallet::rpc::{self, Listen};
+use nakamoto_wallet::wallet::bloom;
+use nakamoto_wallet::wallet::hw::{hwi, Hwi, SignatureType, Signer};
+use nakamoto_wallet::wallet::notify::Hook;
+use nakamoto_wallet::wallet::{Account, Birth};
+
+/// Number of log lines kept for the log pane of the terminal UI.
+const LOG_BUFFER_LINES: usize = 1000;
 
 /// A Bitcoin wallet.
-#[derive(FromArgs)]
+#[derive(Parser)]
+#[command(version)]
 pub struct Options {
-    /// watch the following addresses
-    #[argh(option)]
+    /// wallet file
+    #[arg(long, global = true)]
+    pub wallet: Option<PathBuf>,
+    /// network to connect to, eg. `testnet` (default: mainnet)
+    #[arg(long, global = true)]
+    pub network: Option<Network>,
+    /// log to this file, instead of standard error (default in daemon mode: the wallet
+    /// file path, with a `.log` extension)
+    #[arg(long, global = true)]
+    pub log_file: Option<PathBuf>,
+    /// enable debug logging
+    #[arg(long, global = true)]
+    pub debug: bool,
+    /// read settings from this configuration file; flags take precedence over it
+    /// (default: config.toml in the platform configuration directory)
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// create a new wallet file, born today unless a birth is given
+    Init(Setup),
+    /// set up a wallet file again from its birth and accounts, eg. as exported; the chain
+    /// is scanned from the birth on the next run
+    Restore(Setup),
+    /// run the wallet, with its terminal UI, or as a daemon; the chain is scanned from the
+    /// birth saved in the wallet file, unless another one is given
+    Run(Run),
+    /// re-scan the chain from the given height, or `YYYY-MM-DD` date, in a running wallet
+    Rescan {
+        /// height or date to scan from
+        from: Birth,
+        #[command(flatten)]
+        remote: Remote,
+    },
+    /// get an unused receive address from a running wallet
+    Address(Remote),
+    /// get the balance of a running wallet
+    Balance(Remote),
+    /// send an amount, in satoshis, to an address from a running wallet
+    Send {
+        /// address to send to, as a cashaddr
+        address: String,
+        /// amount to send, in satoshis
+        amount: u64,
+        /// attach an `OP_RETURN` output with this data, given as hex
+        #[arg(long)]
+        data: Option<String>,
+        #[command(flatten)]
+        remote: Remote,
+    },
+    /// print the wallet's birth, accounts and addresses as JSON, to restore it with
+    Export {
+        /// write to this file, instead of standard output
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+/// Options to set up a wallet file with.
+#[derive(Args)]
+pub struct Setup {
+    #[command(flatten)]
+    pub birth: BirthOptions,
+    /// add a descriptor-based account, eg. `savings:20:pkh(xpub.../0/*)`, where the
+    /// gap limit is optional
+    #[arg(long)]
+    pub account: Vec<Account>,
+    /// number of confirmations after which payments are final, and count towards the
+    /// confirmed balance; saved in the wallet file (default: 1)
+    #[arg(long)]
+    pub min_confirmations: Option<Height>,
+    #[command(flatten)]
+    pub signing: Signing,
+}
+
+/// Options to run the wallet with.
+#[derive(Args)]
+pub struct Run {
+    #[command(flatten)]
+    pub birth: BirthOptions,
+    /// watch the following addresses along with the wallet's own, eg. P2SH32 contract
+    /// addresses, given as cashaddrs
+    #[arg(long)]
     pub addresses: Vec<Address>,
-    /// wallet birth height, from which to start scanning
-    #[argh(option)]
-    pub birth_height: Height,
-    /// network to connect to, eg. `testnet`
-    #[argh(option, default = "Network::default()")]
-    pub network: Network,
+    /// import a trusted snapshot of block headers and unspent outputs from this file,
+    /// and start scanning past its height
+    #[arg(long)]
+    pub snapshot: Option<PathBuf>,
     /// connect to this node
-    #[argh(option)]
+    #[arg(long)]
     pub connect: Vec<net::SocketAddr>,
-    /// wallet file
-    #[argh(option)]
-    pub wallet: PathBuf,
-    /// wallet derivation path, eg. m/84'/0'/0'/0.
-    #[argh(option)]
-    pub hd_path: DerivationPath,
+    /// sync from this Electrum or Fulcrum server, instead of scanning blocks from the
+    /// peer-to-peer network
+    #[arg(long)]
+    pub electrum: Option<net::SocketAddr>,
+    /// serve a JSON-RPC API on this unix socket path or localhost address,
+    /// eg. `127.0.0.1:8332`; requests over TCP are authenticated with a cookie, written to
+    /// the wallet file path with a `.cookie` extension
+    #[arg(long)]
+    pub rpc: Option<Listen>,
+    /// data directory for block headers and compact filter headers, kept across runs
+    /// (default: the platform data directory, eg. ~/.local/share/nakamoto-cash)
+    #[arg(long)]
+    pub root: Option<PathBuf>,
+    #[command(flatten)]
+    pub signing: Signing,
     /// offline mode; doesn't connect to the network
-    #[argh(switch)]
+    #[arg(long)]
     pub offline: bool,
-    /// enable debug logging
-    #[argh(switch)]
-    pub debug: bool,
+    /// daemon mode; runs without the terminal UI, to be driven over RPC
+    #[arg(long)]
+    pub daemon: bool,
+    /// notify this hook of payments, confirmations and re-orgs, either an `http://` URL
+    /// to POST to, or a shell command, passed the event as JSON on its standard input
+    #[arg(long)]
+    pub notify: Vec<Hook>,
+    /// fetch token names, symbols and decimals from this Bitcoin Cash Metadata Registry
+    /// URL; registries published in transactions of ours are then fetched too
+    #[arg(long)]
+    pub bcmr: Vec<String>,
+    /// number of bloom filter segments to split addresses across (default: 1)
+    #[arg(long)]
+    pub bloom_segments: Option<usize>,
+    /// bloom filter false-positive rate, between 0 and 1 (default: 0.0001)
+    #[arg(long)]
+    pub bloom_fp_rate: Option<f64>,
+    /// number of decoy elements to insert into bloom filters per address, trading
+    /// bandwidth for privacy (default: 0)
+    #[arg(long)]
+    pub bloom_decoys: Option<f64>,
+    /// scan blocks with BIP 157/158 compact block filters rather than BIP 37 bloom filters,
+    /// when connected to peers serving them
+    #[arg(long)]
+    pub compact_filters: bool,
+    /// print the effective configuration and exit
+    #[arg(long)]
+    pub print_config: bool,
+}
+
+/// Wallet birth, from which to start scanning.
+#[derive(Args)]
+pub struct BirthOptions {
+    /// wallet birth height, from which to start scanning
+    #[arg(long, conflicts_with = "birth_date")]
+    pub birth_height: Option<Height>,
+    /// wallet birth date, as `YYYY-MM-DD`, from which to start scanning instead of a
+    /// birth height; looked up in the block headers
+    #[arg(long)]
+    pub birth_date: Option<NaiveDate>,
+}
+
+impl BirthOptions {
+    /// The birth given, if any.
+    pub fn birth(&self) -> Option<Birth> {
+        self.birth_height
+            .map(Birth::Height)
+            .or(self.birth_date.map(Birth::Date))
+    }
+}
+
+/// How transactions are signed.
+#[derive(Args)]
+pub struct Signing {
+    /// wallet derivation path, eg. m/84'/0'/0'/0.
+    #[arg(long)]
+    pub hd_path: DerivationPath,
+    /// sign transactions with this hardware device type over HWI, eg. `ledger` or `trezor`
+    #[arg(long)]
+    pub hwi: Option<String>,
+    /// path to the `hwi` executable
+    #[arg(long, default_value = hwi::DEFAULT_BINARY)]
+    pub hwi_path: PathBuf,
+    /// sign transactions with the extended private key found in this file, instead of a
+    /// hardware device
+    #[arg(long, conflicts_with = "hwi")]
+    pub xprv_file: Option<PathBuf>,
+    /// signature type used when signing with a private key, `schnorr` or `ecdsa`
+    #[arg(long, default_value_t)]
+    pub signature_type: SignatureType,
 }
 
-impl Options {
-    pub fn from_env() -> Self {
-        argh::from_env()
+impl Signing {
+    /// Build the signer, if any.
+    pub fn signer(&self, network: Network) -> Result<Option<Signer>, String> {
+        if let Some(device) = &self.hwi {
+            return Ok(Some(Signer::Hwi(Hwi::new(
+                self.hwi_path.clone(),
+                device.clone(),
+                network.into(),
+            ))));
+        }
+        let Some(path) = &self.xprv_file else {
+            return Ok(None);
+        };
+        fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|s| {
+                s.trim()
+                    .parse::<ExtendedPrivKey>()
+                    .map_err(|e| e.to_string())
+            })
+            .map(|xprv| Some(Signer::Key(xprv)))
+            .map_err(|err| format!("reading `{}`: {}", path.display(), err))
     }
 }
 
+/// Connection to a running wallet.
+#[derive(Args)]
+pub struct Remote {
+    /// JSON-RPC unix socket path or localhost address of the running wallet, as given
+    /// to `run` with `--rpc`
+    #[arg(long)]
+    pub rpc: Listen,
+}
+
+/// Log the error, and exit.
+fn fatal(err: impl fmt::Display) -> ! {
+    log::error!("Fatal: {}", err);
+    std::process::exit(1);
+}
+
 fn main() {
-    let opts = Options::from_env();
+    let opts = Options::parse();
+    let file = match config::File::load_or_default(opts.config.as_deref()) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("Fatal: {}", err);
+            std::process::exit(1);
+        }
+    };
+    let daemon = matches!(&opts.command, Command::Run(run) if run.daemon);
 
     let level = if opts.debug {
         log::Level::Debug
+    } else if let Some(level) = file.log {
+        level
+    } else if daemon {
+        log::Level::Info
     } else {
         log::Level::Error
     };
-    logger::init(level).expect("initializing logger for the first time");
+    let Some(wallet) = opts.wallet.or(file.wallet) else {
+        eprintln!("Fatal: a wallet file must be given, with `--wallet` or in the configuration");
+        std::process::exit(1);
+    };
+    let network = opts.network.or(file.network).unwrap_or_default();
+
+    let run = match opts.command {
+        Command::Run(run) => run,
+        command => {
+            init_logger(level, opts.log_file.as_deref(), None);
+            execute(command, &wallet, network);
+
+            return;
+        }
+    };
+
+    let root = run.root.or(file.root);
+    let connect = if run.connect.is_empty() {
+        file.connect
+    } else {
+        run.connect
+    };
+    let bloom_segments = run
+        .bloom_segments
+        .or(file.bloom.segments)
+        .unwrap_or(bloom::DEFAULT_SEGMENTS);
+    let bloom_fp_rate = run
+        .bloom_fp_rate
+        .or(file.bloom.fp_rate)
+        .unwrap_or(bloom::DEFAULT_FP_RATE);
+    let bloom_decoys = run.bloom_decoys.or(file.bloom.decoys).unwrap_or(0.);
+    let compact_filters = run.compact_filters || file.compact_filters.unwrap_or(false);
+
+    if run.print_config {
+        let effective = config::File {
+            network: Some(network),
+            connect,
+            root,
+            wallet: Some(wallet),
+            log: Some(level),
+            compact_filters: Some(compact_filters),
+            bloom: config::Bloom {
+                segments: Some(bloom_segments),
+                fp_rate: Some(bloom_fp_rate),
+                decoys: Some(bloom_decoys),
+                ..config::Bloom::default()
+            },
+            ..config::File::default()
+        };
+        print!("{}", effective.to_toml());
+        return;
+    }
+
+    let log_file = opts
+        .log_file
+        .or_else(|| daemon.then(|| wallet.with_extension("log")));
+
+    // In the terminal UI, recent log lines can be shown in the log pane.
+    let log_buffer = (!daemon).then(|| logger::Buffer::new(LOG_BUFFER_LINES));
+
+    init_logger(level, log_file.as_deref(), log_buffer.clone());
+
+    if daemon && run.rpc.is_none() {
+        fatal("`--daemon` requires `--rpc`, to be able to drive the wallet");
+    }
+
+    if !(bloom_fp_rate > 0. && bloom_fp_rate < 1.) {
+        fatal("bloom filter false-positive rate must be between 0 and 1");
+    }
+    if !(bloom_decoys >= 0.) {
+        fatal("bloom filter decoy ratio must not be negative");
+    }
+    let bloom_decoys = if bloom_decoys > 0. {
+        bloom::Decoys::Ratio(bloom_decoys)
+    } else {
+        bloom::Decoys::None
+    };
+
+    let birth = run.birth.birth();
+    if run.electrum.is_some() && matches!(birth, Some(Birth::Date(_))) {
+        fatal("`--birth-date` can't be used with `--electrum`");
+    }
+    if run.electrum.is_some() && run.snapshot.is_some() {
+        fatal("`--snapshot` can't be used with `--electrum`");
+    }
+    let signer = run.signing.signer(network).unwrap_or_else(|err| fatal(err));
 
     if let Err(err) = nakamoto_wallet::run(
-        &opts.wallet,
-        opts.birth_height,
-        opts.hd_path,
-        opts.network,
-        opts.connect,
-        opts.offline,
+        &wallet,
+        root.as_deref(),
+        birth,
+        run.snapshot.as_deref(),
+        run.signing.hd_path,
+        run.addresses,
+        signer,
+        run.signing.signature_type,
+        network,
+        connect,
+        run.electrum,
+        run.rpc,
+        daemon,
+        run.notify,
+        run.bcmr,
+        run.offline,
+        bloom_segments,
+        bloom_fp_rate,
+        bloom_decoys,
+        compact_filters,
+        log_buffer,
     ) {
-        log::error!("Fatal: {}", err);
-        std::process::exit(1);
+        fatal(err);
+    }
+}
+
+/// Log to the given file, or to standard error.
+fn init_logger(level: log::Level, path: Option<&Path>, buffer: Option<logger::Buffer>) {
+    if let Some(path) = path {
+        if let Err(err) = logger::init_file(level, path, buffer) {
+            eprintln!("Fatal: opening log file `{}`: {}", path.display(), err);
+            std::process::exit(1);
+        }
+    } else {
+        logger::init(level, buffer).expect("initializing logger for the first time");
+    }
+}
+
+/// Execute a command other than `run`, which doesn't start the wallet.
+fn execute(command: Command, wallet: &Path, network: Network) {
+    match command {
+        Command::Init(setup) => {
+            if wallet.exists() {
+                fatal(format_args!(
+                    "wallet file `{}` already exists; set it up again with `restore`",
+                    wallet.display()
+                ));
+            }
+            let birth = setup.birth.birth().unwrap_or_else(Birth::today);
+
+            self::setup(wallet, birth, setup, network);
+        }
+        Command::Restore(setup) => {
+            let Some(birth) = setup.birth.birth() else {
+                fatal("a wallet birth must be given, with `--birth-height` or `--birth-date`");
+            };
+            self::setup(wallet, birth, setup, network);
+        }
+        Command::Rescan { from, remote } => {
+            print(request(
+                &remote,
+                wallet,
+                "rescan",
+                json!([from.to_string()]),
+            ));
+        }
+        Command::Address(remote) => {
+            print(request(&remote, wallet, "getnewaddress", json!([])));
+        }
+        Command::Balance(remote) => {
+            print(request(&remote, wallet, "getbalance", json!([])));
+        }
+        Command::Send {
+            address,
+            amount,
+            data,
+            remote,
+        } => {
+            print(request(
+                &remote,
+                wallet,
+                "sendtoaddress",
+                json!([address, amount, data]),
+            ));
+        }
+        Command::Export { output } => {
+            if !wallet.exists() {
+                fatal(format_args!("wallet file `{}` not found", wallet.display()));
+            }
+            let export = nakamoto_wallet::export(wallet).unwrap_or_else(|err| fatal(err));
+
+            if let Some(path) = output {
+                if let Err(err) = fs::write(&path, format!("{:#}\n", export)) {
+                    fatal(format_args!("writing `{}`: {}", path.display(), err));
+                }
+            } else {
+                println!("{:#}", export);
+            }
+        }
+        Command::Run(_) => unreachable!("the wallet is run from `main`"),
+    }
+}
+
+/// Set up the wallet file, and print its addresses.
+fn setup(wallet: &Path, birth: Birth, setup: Setup, network: Network) {
+    let signer = setup
+        .signing
+        .signer(network)
+        .unwrap_or_else(|err| fatal(err));
+    let addresses = nakamoto_wallet::setup(
+        wallet,
+        birth,
+        setup.signing.hd_path,
+        setup.account,
+        signer,
+        setup.signing.signature_type,
+        network,
+        setup.min_confirmations,
+    )
+    .unwrap_or_else(|err| fatal(err));
+
+    for addr in addresses {
+        println!("{}", addr);
+    }
+}
+
+/// Send a request to the running wallet, authenticated with the cookie next to its file.
+fn request(remote: &Remote, wallet: &Path, method: &str, params: Value) -> Value {
+    rpc::request(&remote.rpc, &rpc::cookie_path(wallet), method, params)
+        .unwrap_or_else(|err| fatal(err))
+}
+
+/// Print a result, strings as they are.
+fn print(result: Value) {
+    match result {
+        Value::String(s) => println!("{}", s),
+        other => println!("{:#}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_options() {
+        let opts = Options::try_parse_from([
+            "wallet",
+            "init",
+            "--wallet",
+            "wallet.db",
+            "--hd-path",
+            "m/44'/145'/0'/0",
+            "--birth-date",
+            "2023-05-15",
+        ])
+        .unwrap();
+        assert_eq!(opts.wallet, Some(PathBuf::from("wallet.db")));
+        assert!(matches!(
+            opts.command,
+            Command::Init(Setup { ref birth, .. })
+                if birth.birth() == Some("2023-05-15".parse().unwrap())
+        ));
+
+        let opts = Options::try_parse_from([
+            "wallet",
+            "send",
+            "bitcoincash:qq",
+            "1000",
+            "--rpc",
+            "127.0.0.1:8332",
+        ])
+        .unwrap();
+        assert!(matches!(
+            opts.command,
+            Command::Send {
+                amount: 1000,
+                data: None,
+                ..
+            }
+        ));
+
+        // A birth is given either as a height or as a date.
+        assert!(Options::try_parse_from([
+            "wallet",
+            "restore",
+            "--hd-path",
+            "m/44'/145'/0'/0",
+            "--birth-height",
+            "800000",
+            "--birth-date",
+            "2023-05-15",
+        ])
+        .is_err());
+        // Commands sent to a running wallet need its address.
+        assert!(Options::try_parse_from(["wallet", "balance"]).is_err());
+        assert!(Options::try_parse_from(["wallet"]).is_err());
     }
 }
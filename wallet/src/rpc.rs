@@ -0,0 +1,691 @@
+//! JSON-RPC server, letting scripts and other software drive the running wallet.
+//!
+//! Requests are read one per line, over a unix socket or a localhost TCP connection, and
+//! handed to the wallet loop as [`Call`]s. The wallet replies once it has handled them.
+//! Requests can be sent to a running wallet with [`request`].
+//!
+//! Since any local user can connect to a TCP port, requests made over TCP must carry a
+//! random cookie as their `auth` member. The cookie is generated on startup, and written
+//! to a file only readable by the wallet's owner, next to the wallet file. Unix sockets
+//! are only accessible to the wallet's owner, so requests made over them don't need it.
+use std::io::{self, BufRead, BufReader, Read as _, Write};
+use std::os::unix::fs::{DirBuilderExt as _, FileTypeExt as _};
+use std::os::unix::fs::{OpenOptionsExt as _, PermissionsExt as _};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::{fs, net, thread};
+
+use crossbeam_channel as chan;
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use nakamoto_common::bitcoin::hashes::hex::ToHex as _;
+use nakamoto_common::bitcoin::Txid;
+use nakamoto_p2p::fsm::fees::FeeRate;
+
+use crate::wallet::tx::{DEFAULT_CONSOLIDATION_THRESHOLD, DEFAULT_FEE_RATE};
+use crate::wallet::ui::send::parse_data;
+use crate::wallet::Birth;
+
+/// Size of the authentication cookie, in bytes.
+pub const COOKIE_SIZE: usize = 32;
+
+/// Where the server listens for connections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Listen {
+    /// A localhost TCP address.
+    Tcp(net::SocketAddr),
+    /// A unix socket path.
+    Unix(PathBuf),
+}
+
+impl FromStr for Listen {
+    type Err = String;
+
+    /// Parse a socket address, eg. `127.0.0.1:8332`, or otherwise a unix socket path.
+    /// Only loopback addresses are accepted, since requests aren't encrypted.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<net::SocketAddr>() {
+            Ok(addr) if addr.ip().is_loopback() => Ok(Self::Tcp(addr)),
+            Ok(addr) => Err(format!("refusing to listen on non-local address {}", addr)),
+            Err(_) => Ok(Self::Unix(PathBuf::from(s))),
+        }
+    }
+}
+
+/// An error returned to RPC clients.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    #[error("parse error: {0}")]
+    Parse(String),
+    #[error("invalid request")]
+    InvalidRequest,
+    #[error("unauthorized: missing or invalid cookie")]
+    Unauthorized,
+    #[error("method not found: {0}")]
+    MethodNotFound(String),
+    #[error("invalid params: missing or invalid `{0}`")]
+    InvalidParams(&'static str),
+    #[error("{0}")]
+    Wallet(String),
+}
+
+impl Error {
+    /// The JSON-RPC error code.
+    pub fn code(&self) -> i64 {
+        match self {
+            Self::Parse(_) => -32700,
+            Self::InvalidRequest => -32600,
+            Self::Unauthorized => -32001,
+            Self::MethodNotFound(_) => -32601,
+            Self::InvalidParams(_) => -32602,
+            Self::Wallet(_) => -32000,
+        }
+    }
+}
+
+impl From<crate::error::Error> for Error {
+    fn from(err: crate::error::Error) -> Self {
+        Self::Wallet(err.to_string())
+    }
+}
+
+/// A request to the wallet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    /// Get the wallet's confirmed and unconfirmed balances.
+    GetBalance,
+    /// List the wallet's unspent outputs.
+    ListUnspent,
+    /// Send an amount, in satoshis, to an address or to the contact with the given label,
+    /// optionally attaching data in an `OP_RETURN` output.
+    SendToAddress {
+        address: String,
+        amount: u64,
+        data: Option<Vec<u8>>,
+    },
+    /// Sweep the coins worth less than a threshold, in satoshis, to an address of ours,
+    /// at the given fee rate.
+    Consolidate { threshold: u64, fee_rate: FeeRate },
+    /// Speed up an unconfirmed transaction we sent, by spending one of its outputs in a
+    /// child transaction, so that both pay the given fee rate.
+    SpeedUp { txid: Txid, fee_rate: FeeRate },
+    /// Get an unused receive address.
+    GetNewAddress,
+    /// Get the height of the chain tip.
+    GetBlockCount,
+    /// Get how many of the transactions peers matched against our bloom filters weren't
+    /// ours, a measure of how much the filters hide.
+    GetPrivacyInfo,
+    /// Re-scan the chain from the given height, or date.
+    Rescan { from: Birth },
+}
+
+impl Request {
+    /// Build a request from a method name and its params, given either by position or
+    /// by name.
+    pub fn new(method: &str, params: &Value) -> Result<Self, Error> {
+        let param = |ix: usize, name: &'static str| match params {
+            Value::Array(params) => params.get(ix).unwrap_or(&Value::Null),
+            params => &params[name],
+        };
+        match method {
+            "getbalance" => Ok(Self::GetBalance),
+            "listunspent" => Ok(Self::ListUnspent),
+            "sendtoaddress" => Ok(Self::SendToAddress {
+                address: param(0, "address")
+                    .as_str()
+                    .ok_or(Error::InvalidParams("address"))?
+                    .to_owned(),
+                amount: param(1, "amount")
+                    .as_u64()
+                    .filter(|a| *a > 0)
+                    .ok_or(Error::InvalidParams("amount"))?,
+                data: match param(2, "data") {
+                    Value::Null => None,
+                    v => v
+                        .as_str()
+                        .and_then(|s| parse_data(s).ok())
+                        .ok_or(Error::InvalidParams("data"))?,
+                },
+            }),
+            "consolidate" => Ok(Self::Consolidate {
+                threshold: match param(0, "threshold") {
+                    Value::Null => DEFAULT_CONSOLIDATION_THRESHOLD,
+                    v => v.as_u64().ok_or(Error::InvalidParams("threshold"))?,
+                },
+                fee_rate: match param(1, "feerate") {
+                    Value::Null => DEFAULT_FEE_RATE,
+                    v => v
+                        .as_u64()
+                        .filter(|r| *r > 0)
+                        .ok_or(Error::InvalidParams("feerate"))?,
+                },
+            }),
+            "speedup" => Ok(Self::SpeedUp {
+                txid: param(0, "txid")
+                    .as_str()
+                    .and_then(|s| s.parse().ok())
+                    .ok_or(Error::InvalidParams("txid"))?,
+                fee_rate: param(1, "feerate")
+                    .as_u64()
+                    .filter(|r| *r > 0)
+                    .ok_or(Error::InvalidParams("feerate"))?,
+            }),
+            "getnewaddress" => Ok(Self::GetNewAddress),
+            "getblockcount" => Ok(Self::GetBlockCount),
+            "getprivacyinfo" => Ok(Self::GetPrivacyInfo),
+            "rescan" => Ok(Self::Rescan {
+                from: match param(0, "from") {
+                    Value::String(s) => s.parse().ok(),
+                    v => v.as_u64().map(Birth::Height),
+                }
+                .ok_or(Error::InvalidParams("from"))?,
+            }),
+            other => Err(Error::MethodNotFound(other.to_owned())),
+        }
+    }
+}
+
+/// A request, along with the channel the wallet replies on.
+#[derive(Debug)]
+pub struct Call {
+    /// The request.
+    pub request: Request,
+    /// Channel to send the result on.
+    pub reply: chan::Sender<Result<Value, Error>>,
+}
+
+/// Parse a request line, checking that it carries the given cookie, if any. Returns the
+/// request id, which is `null` if it couldn't be read, along with the request.
+pub fn parse(line: &str, cookie: Option<&str>) -> (Value, Result<Request, Error>) {
+    let value: Value = match serde_json::from_str(line) {
+        Ok(value) => value,
+        Err(err) => return (Value::Null, Err(Error::Parse(err.to_string()))),
+    };
+    let id = value["id"].clone();
+
+    if let Some(cookie) = cookie {
+        match value["auth"].as_str() {
+            Some(auth) if constant_time_eq(auth.as_bytes(), cookie.as_bytes()) => {}
+            _ => return (id, Err(Error::Unauthorized)),
+        }
+    }
+    let request = match value["method"].as_str() {
+        Some(method) => Request::new(method, &value["params"]),
+        None => Err(Error::InvalidRequest),
+    };
+    (id, request)
+}
+
+/// Compare two byte strings in time that only depends on their length, so that a client
+/// can't guess the cookie one byte at a time by timing our replies.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Path of the authentication cookie of the wallet with the given file.
+pub fn cookie_path(wallet: &Path) -> PathBuf {
+    wallet.with_extension("cookie")
+}
+
+/// Generate a random authentication cookie, and write it to the given path, only readable
+/// by the current user.
+fn write_cookie(path: &Path) -> io::Result<String> {
+    let mut bytes = [0; COOKIE_SIZE];
+    fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+
+    let cookie = bytes.to_hex();
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    // The file may have been created with other permissions.
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    file.write_all(cookie.as_bytes())?;
+
+    Ok(cookie)
+}
+
+/// Build a response to the request with the given id.
+pub fn response(id: Value, result: Result<Value, Error>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(err) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": err.code(), "message": err.to_string() },
+        }),
+    }
+}
+
+/// Accept connections and serve requests, passing them on to the wallet. Blocks until the
+/// listener fails. When listening on TCP, the authentication cookie is written to the
+/// given path.
+pub fn serve(listen: &Listen, cookie: &Path, calls: chan::Sender<Call>) -> io::Result<()> {
+    match listen {
+        Listen::Tcp(addr) => {
+            let listener = net::TcpListener::bind(addr)?;
+            let cookie = write_cookie(cookie)?;
+            log::info!("Listening for RPC requests on {}..", addr);
+
+            for stream in listener.incoming() {
+                let stream = stream?;
+                let writer = stream.try_clone()?;
+
+                spawn(stream, writer, calls.clone(), Some(cookie.clone()));
+            }
+        }
+        Listen::Unix(path) => {
+            // Remove the socket left behind by a previous run, if any. Anything else is
+            // left alone, and binding fails.
+            if let Ok(meta) = fs::symlink_metadata(path) {
+                if !meta.file_type().is_socket() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("`{}` exists and is not a socket", path.display()),
+                    ));
+                }
+                fs::remove_file(path)?;
+            }
+            let listener = bind_private(path)?;
+            log::info!("Listening for RPC requests on {}..", path.display());
+
+            for stream in listener.incoming() {
+                let stream = stream?;
+                let writer = stream.try_clone()?;
+
+                spawn(stream, writer, calls.clone(), None);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Bind a unix socket only accessible to the current user. The socket is bound in a private
+/// directory, and only moved to the given path once its permissions are restricted, so that
+/// other users can't connect in between.
+fn bind_private(path: &Path) -> io::Result<UnixListener> {
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("`{}` is not a valid socket path", path.display()),
+        )
+    })?;
+    let dir = path.with_file_name(format!(
+        ".{}.{}",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+    fs::DirBuilder::new().mode(0o700).create(&dir)?;
+
+    let socket = dir.join("socket");
+    let result = UnixListener::bind(&socket).and_then(|listener| {
+        fs::set_permissions(&socket, fs::Permissions::from_mode(0o600))?;
+        fs::rename(&socket, path)?;
+
+        Ok(listener)
+    });
+    // Nb. The socket is only left behind if it couldn't be moved.
+    fs::remove_file(&socket).ok();
+    fs::remove_dir(&dir).ok();
+
+    result
+}
+
+/// Serve a connection in the background.
+fn spawn<R, W>(reader: R, writer: W, calls: chan::Sender<Call>, cookie: Option<String>)
+where
+    R: io::Read + Send + 'static,
+    W: io::Write + Send + 'static,
+{
+    thread::spawn(move || {
+        if let Err(err) = handle(reader, writer, &calls, cookie.as_deref()) {
+            log::debug!("RPC connection closed: {}", err);
+        }
+    });
+}
+
+/// Serve requests from a connection until it is closed. Requests must carry the given
+/// cookie, if any.
+fn handle<R: io::Read, W: io::Write>(
+    reader: R,
+    mut writer: W,
+    calls: &chan::Sender<Call>,
+    cookie: Option<&str>,
+) -> io::Result<()> {
+    for line in BufReader::new(reader).lines() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (id, request) = parse(&line, cookie);
+        let result = request.and_then(|request| call(calls, request));
+
+        writeln!(writer, "{}", response(id, result))?;
+    }
+    Ok(())
+}
+
+/// Send a request to the wallet serving RPC requests on the given address, and wait for
+/// its result. Over TCP, the request is authenticated with the cookie read from the given
+/// path.
+pub fn request(
+    listen: &Listen,
+    cookie: &Path,
+    method: &str,
+    params: Value,
+) -> Result<Value, crate::error::Error> {
+    let mut request = json!({ "jsonrpc": "2.0", "id": 0, "method": method, "params": params });
+    let response = match listen {
+        Listen::Tcp(addr) => {
+            request["auth"] = json!(fs::read_to_string(cookie)?.trim());
+            exchange(net::TcpStream::connect(addr)?, &request)?
+        }
+        Listen::Unix(path) => exchange(UnixStream::connect(path)?, &request)?,
+    };
+    match &response["error"] {
+        Value::Null => Ok(response["result"].clone()),
+        err => Err(crate::error::Error::Remote(
+            err["message"].as_str().unwrap_or_default().to_owned(),
+        )),
+    }
+}
+
+/// Write a request to a connection, and read the response.
+fn exchange<S: io::Read + io::Write>(mut stream: S, request: &Value) -> io::Result<Value> {
+    let mut line = String::new();
+
+    writeln!(stream, "{}", request)?;
+    BufReader::new(stream).read_line(&mut line)?;
+
+    serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Pass a request on to the wallet, and wait for its reply.
+fn call(calls: &chan::Sender<Call>, request: Request) -> Result<Value, Error> {
+    let (reply, result) = chan::bounded(1);
+    let stopped = || Error::Wallet(String::from("the wallet is not running"));
+
+    calls.send(Call { request, reply }).map_err(|_| stopped())?;
+    result.recv().map_err(|_| stopped())?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nakamoto_common::bitcoin::hashes::Hash;
+
+    #[test]
+    fn test_listen() {
+        assert_eq!(
+            "127.0.0.1:8332".parse::<Listen>(),
+            Ok(Listen::Tcp(([127, 0, 0, 1], 8332).into()))
+        );
+        assert_eq!(
+            "/tmp/wallet.sock".parse::<Listen>(),
+            Ok(Listen::Unix(PathBuf::from("/tmp/wallet.sock")))
+        );
+        assert!("0.0.0.0:8332".parse::<Listen>().is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        let (id, request) = parse(
+            r#"{"jsonrpc": "2.0", "id": 1, "method": "getbalance"}"#,
+            None,
+        );
+        assert_eq!(id, json!(1));
+        assert_eq!(request, Ok(Request::GetBalance));
+
+        let (_, request) = parse(
+            r#"{"id": 2, "method": "sendtoaddress", "params": ["bchtest:qq", 1000]}"#,
+            None,
+        );
+        assert_eq!(
+            request,
+            Ok(Request::SendToAddress {
+                address: String::from("bchtest:qq"),
+                amount: 1000,
+                data: None,
+            })
+        );
+
+        let (_, request) = parse(
+            r#"{"id": 2, "method": "sendtoaddress",
+                "params": {"address": "bchtest:qq", "amount": 1000, "data": "0x6d02"}}"#,
+            None,
+        );
+        assert_eq!(
+            request,
+            Ok(Request::SendToAddress {
+                address: String::from("bchtest:qq"),
+                amount: 1000,
+                data: Some(vec![0x6d, 0x02]),
+            })
+        );
+
+        let (_, request) = parse(
+            r#"{"id": 2, "method": "sendtoaddress", "params": ["bchtest:qq", 1000, "0xzz"]}"#,
+            None,
+        );
+        assert_eq!(request, Err(Error::InvalidParams("data")));
+
+        let (_, request) = parse(
+            r#"{"id": 3, "method": "rescan", "params": {"from": 144}}"#,
+            None,
+        );
+        assert_eq!(
+            request,
+            Ok(Request::Rescan {
+                from: Birth::Height(144)
+            })
+        );
+        let (_, request) = parse(
+            r#"{"id": 3, "method": "rescan", "params": ["2023-05-15"]}"#,
+            None,
+        );
+        assert_eq!(
+            request,
+            Ok(Request::Rescan {
+                from: "2023-05-15".parse().unwrap()
+            })
+        );
+
+        let (_, request) = parse(
+            r#"{"id": 4, "method": "sendtoaddress", "params": ["bchtest:qq"]}"#,
+            None,
+        );
+        assert_eq!(request, Err(Error::InvalidParams("amount")));
+
+        let (_, request) = parse(
+            r#"{"id": 4, "method": "consolidate", "params": [5000]}"#,
+            None,
+        );
+        assert_eq!(
+            request,
+            Ok(Request::Consolidate {
+                threshold: 5000,
+                fee_rate: DEFAULT_FEE_RATE
+            })
+        );
+
+        let (_, request) = parse(
+            r#"{"id": 4, "method": "consolidate", "params": {"feerate": 0}}"#,
+            None,
+        );
+        assert_eq!(request, Err(Error::InvalidParams("feerate")));
+
+        let txid = Txid::all_zeros();
+        let (_, request) = parse(
+            &format!(
+                r#"{{"id": 5, "method": "speedup", "params": ["{}", 2]}}"#,
+                txid
+            ),
+            None,
+        );
+        assert_eq!(request, Ok(Request::SpeedUp { txid, fee_rate: 2 }));
+
+        let (_, request) = parse(
+            r#"{"id": 5, "method": "speedup", "params": ["00", 2]}"#,
+            None,
+        );
+        assert_eq!(request, Err(Error::InvalidParams("txid")));
+
+        let (_, request) = parse(r#"{"id": 5, "method": "getprivacyinfo"}"#, None);
+        assert_eq!(request, Ok(Request::GetPrivacyInfo));
+
+        let (_, request) = parse(r#"{"id": 5, "method": "stop"}"#, None);
+        assert_eq!(request, Err(Error::MethodNotFound(String::from("stop"))));
+
+        let (_, request) = parse(r#"{"id": 6}"#, None);
+        assert_eq!(request, Err(Error::InvalidRequest));
+
+        let (id, request) = parse("{", None);
+        assert_eq!(id, Value::Null);
+        assert!(matches!(request, Err(Error::Parse(_))));
+
+        let line = r#"{"id": 7, "method": "getbalance", "auth": "c00c1e"}"#;
+        assert_eq!(parse(line, Some("c00c1e")).1, Ok(Request::GetBalance));
+        assert_eq!(
+            parse(line, Some("beef")),
+            (json!(7), Err(Error::Unauthorized))
+        );
+
+        let (_, request) = parse(r#"{"id": 8, "method": "getbalance"}"#, Some("c00c1e"));
+        assert_eq!(request, Err(Error::Unauthorized));
+
+        let line = r#"{"id": 9, "method": "getbalance", "auth": "c00c1f"}"#;
+        assert_eq!(parse(line, Some("c00c1e")).1, Err(Error::Unauthorized));
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"c00c1e", b"c00c1e"));
+        assert!(constant_time_eq(b"", b""));
+        assert!(!constant_time_eq(b"c00c1e", b"c00c1f"));
+        assert!(!constant_time_eq(b"c00c1e", b"c00c1"));
+        assert!(!constant_time_eq(b"", b"c00c1e"));
+    }
+
+    #[test]
+    fn test_handle() {
+        let (calls, requests) = chan::unbounded::<Call>();
+        let wallet = thread::spawn(move || {
+            for call in requests {
+                let result = match call.request {
+                    Request::GetBlockCount => Ok(json!(144)),
+                    _ => Err(Error::Wallet(String::from("unsupported"))),
+                };
+                call.reply.send(result).unwrap();
+            }
+        });
+        let input = concat!(
+            r#"{"id": 1, "method": "getblockcount"}"#,
+            "\n\n",
+            r#"{"id": 2, "method": "getbalance"}"#,
+            "\n",
+            r#"{"id": 3, "method": "unknown"}"#,
+            "\n",
+        );
+        let mut output = Vec::new();
+
+        handle(input.as_bytes(), &mut output, &calls, None).unwrap();
+        drop(calls);
+        wallet.join().unwrap();
+
+        let responses = output
+            .split(|b| *b == b'\n')
+            .filter(|l| !l.is_empty())
+            .map(|l| serde_json::from_slice::<Value>(l).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0]["result"], 144);
+        assert_eq!(responses[1]["error"]["message"], "unsupported");
+        assert_eq!(responses[2]["id"], 3);
+        assert_eq!(responses[2]["error"]["code"], -32601);
+    }
+
+    #[test]
+    fn test_request() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("nakamoto-rpc-{}.sock", std::process::id()));
+        let cookie = dir.join(format!("nakamoto-rpc-{}.cookie", std::process::id()));
+        let (calls, requests) = chan::unbounded::<Call>();
+
+        thread::spawn(move || {
+            for call in requests {
+                let result = match call.request {
+                    Request::GetBlockCount => Ok(json!(144)),
+                    _ => Err(Error::Wallet(String::from("unsupported"))),
+                };
+                call.reply.send(result).unwrap();
+            }
+        });
+
+        // Files other than sockets aren't replaced.
+        fs::write(&path, b"wallet").unwrap();
+        assert!(serve(&Listen::Unix(path.clone()), &cookie, calls.clone()).is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"wallet");
+        fs::remove_file(&path).unwrap();
+
+        let listen = Listen::Unix(path.clone());
+        thread::spawn({
+            let (listen, cookie, calls) = (listen.clone(), cookie.clone(), calls.clone());
+            move || serve(&listen, &cookie, calls)
+        });
+        while !path.exists() {
+            thread::sleep(std::time::Duration::from_millis(1));
+        }
+        // The socket only shows up once it is private.
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        assert_eq!(
+            request(&listen, &cookie, "getblockcount", Value::Null).unwrap(),
+            json!(144)
+        );
+        assert!(matches!(
+            request(&listen, &cookie, "getbalance", Value::Null),
+            Err(crate::error::Error::Remote(msg)) if msg == "unsupported"
+        ));
+        fs::remove_file(&path).ok();
+
+        // Over TCP, requests are authenticated with the cookie.
+        let addr = net::TcpListener::bind(([127, 0, 0, 1], 0))
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let listen = Listen::Tcp(addr);
+
+        fs::remove_file(&cookie).ok();
+        thread::spawn({
+            let (listen, cookie) = (listen.clone(), cookie.clone());
+            move || serve(&listen, &cookie, calls)
+        });
+        while fs::read(&cookie).map(|c| c.len()).ok() != Some(COOKIE_SIZE * 2) {
+            thread::sleep(std::time::Duration::from_millis(1));
+        }
+        let mode = fs::metadata(&cookie).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        assert_eq!(
+            request(&listen, &cookie, "getblockcount", Value::Null).unwrap(),
+            json!(144)
+        );
+        fs::write(&cookie, b"c00c1e").unwrap();
+        assert!(matches!(
+            request(&listen, &cookie, "getblockcount", Value::Null),
+            Err(crate::error::Error::Remote(msg)) if msg == Error::Unauthorized.to_string()
+        ));
+        fs::remove_file(&cookie).ok();
+    }
+}
@@ -1,31 +1,97 @@
+pub mod account;
+pub mod backend;
+pub mod bcmr;
+pub mod birth;
+pub mod bloom;
 pub mod db;
+pub mod descriptor;
 pub mod hw;
+pub mod notify;
+pub mod resolve;
+pub mod tx;
 pub mod ui;
+pub mod utxo;
 
-use std::collections::HashSet;
 use std::io;
 use std::ops::ControlFlow;
 use std::ops::ControlFlow::*;
+use std::time;
 
 use crossbeam_channel as chan;
+use serde_json::json;
 use termion::event::Event;
 
 use nakamoto_client as client;
-use nakamoto_client::handle::Handle;
-use nakamoto_common::bitcoin::Address;
-use nakamoto_common::bitcoin::{OutPoint, Script, Transaction, TxOut};
+use nakamoto_common::bitcoin::{self, policy, Address};
+use nakamoto_common::bitcoin::{OutPoint, TokenID, Transaction, TxOut, Txid};
+use nakamoto_common::block::snapshot::Snapshot;
 use nakamoto_common::block::Height;
+use nakamoto_p2p::fsm::fees::FeeRate;
 
 use crate::error::Error;
 use crate::input::Signal;
+use crate::logger;
+use crate::rpc;
+use crate::wallet::backend::Backend;
+use crate::wallet::bcmr::{Registries, TokenMetadata};
+use crate::wallet::notify::{Notification, Notifier};
+use crate::wallet::resolve::{Resolved, Resolver, Resolvers};
+use crate::wallet::tx::{TxBuilder, UnsignedTx};
+use crate::wallet::ui::{contacts, peers, send};
 
+pub use account::Account;
+pub use birth::Birth;
 pub use db::Db;
 pub use db::{Read as _, Write as _};
 pub use hw::Hw;
 pub use ui::Ui;
+pub use utxo::UtxoSet;
 
 pub type Utxos = Vec<(OutPoint, TxOut)>;
 
+/// Number of addresses requested from the hardware device at a time.
+pub const DEVICE_ADDRESS_BATCH: usize = 16;
+/// How often peer statistics are refreshed, while they're shown.
+pub const PEER_STATS_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+/// Load the wallet's addresses, deriving them from the wallet's accounts, or requesting
+/// them from the hardware device if there are no accounts and none are stored yet.
+pub fn addresses(db: &Db, hw: &mut Hw, network: bitcoin::Network) -> Result<Vec<Address>, Error> {
+    for account in db.accounts()? {
+        account::derive(db, &account, network)?;
+    }
+    let addresses = db.addresses()?;
+    if !addresses.is_empty() {
+        return Ok(addresses.into_iter().map(|a| a.address).collect());
+    }
+    log::info!("No addresses found, requesting from hardware device..");
+
+    match hw.request_addresses(0..DEVICE_ADDRESS_BATCH, hw::AddressFormat::P2PKH) {
+        Ok(addrs) => {
+            for (ix, addr) in &addrs {
+                db.add_address(addr, *ix, None, None)?;
+            }
+            Ok(addrs.into_iter().map(|(_, addr)| addr).collect())
+        }
+        Err(err) => {
+            log::warn!("Failed to request addresses from hardware device: {err}");
+
+            Ok(Vec::new())
+        }
+    }
+}
+
+/// Load the public keys of the wallet's addresses, where known, ie. for the addresses
+/// derived from its accounts. Addresses from the hardware device only have their hash.
+pub fn pubkeys(db: &Db) -> Result<Vec<bitcoin::PublicKey>, Error> {
+    let mut pubkeys = Vec::new();
+
+    for account in db.accounts()? {
+        pubkeys.extend(account::pubkeys(db, &account)?);
+    }
+    Ok(pubkeys)
+}
+
 #[derive(Default)]
 pub struct Tips {
     header: Height,
@@ -33,111 +99,705 @@ pub struct Tips {
 }
 
 /// Wallet state.
-pub struct Wallet<H> {
-    client: H,
+pub struct Wallet<B> {
+    backend: B,
     db: Db,
     ui: Ui,
     hw: Hw,
     network: client::Network,
-    watch: HashSet<Address>,
+    accounts: Vec<Account>,
+    watch: utxo::Watch,
+    utxos: UtxoSet,
+    settings: utxo::Settings,
     tips: Tips,
+    notifier: Notifier,
+    resolvers: Resolvers,
+    registries: Registries,
 }
 
-impl<H: Handle> Wallet<H> {
-    /// Create a new wallet.
-    pub fn new(client: H, network: client::Network, db: Db, hw: Hw) -> Self {
+impl<B: Backend> Wallet<B> {
+    /// Create a new wallet, syncing from the given backend.
+    pub fn new(backend: B, network: client::Network, db: Db, hw: Hw) -> Self {
         Self {
-            client,
+            backend,
             db,
             hw,
             network,
-            watch: HashSet::new(),
+            accounts: Vec::new(),
+            watch: utxo::Watch::default(),
+            utxos: UtxoSet::default(),
+            settings: utxo::Settings::default(),
             ui: Ui::default(),
             tips: Tips::default(),
+            notifier: Notifier::default(),
+            resolvers: Resolvers::default(),
+            registries: Registries::default(),
         }
     }
 
+    /// Run the wallet without a terminal. Nothing is drawn, and the wallet is expected to
+    /// be driven over RPC.
+    pub fn headless(mut self) -> Self {
+        self.ui.headless = true;
+        self
+    }
+
+    /// Show the lines of the given log buffer in the log pane of the terminal UI.
+    pub fn with_log(mut self, buffer: logger::Buffer) -> Self {
+        self.ui.set_log(buffer);
+        self
+    }
+
+    /// Notify hooks of payments, confirmations and re-orgs affecting the wallet.
+    pub fn with_notifier(mut self, notifier: Notifier) -> Self {
+        self.notifier = notifier;
+        self
+    }
+
+    /// Fetch token metadata from the given registries, and from those published in
+    /// transactions of ours.
+    pub fn with_registries(mut self, registries: Registries) -> Self {
+        self.registries = registries;
+        self
+    }
+
+    /// Watch the given addresses along with the wallet's own, eg. the P2SH32 addresses of
+    /// covenant contracts. Coins received on them are tracked, but not spent.
+    pub fn with_watched(mut self, addresses: Vec<Address>) -> Self {
+        self.watch.extend(addresses);
+        self
+    }
+
+    /// Resolve names entered as recipients with the given resolver, after the resolvers
+    /// already registered.
+    pub fn with_resolver(mut self, resolver: impl Resolver + 'static) -> Self {
+        self.resolvers.register(resolver);
+        self
+    }
+
     /// Calculate the wallet balance.
     pub fn balance(&self) -> Result<u64, Error> {
         self.db.balance().map_err(Error::from)
     }
 
-    /// Apply a transaction to the wallet's UTXO set.
-    pub fn apply(&mut self, tx: &Transaction, scripts: &[Script]) {
-        // Look for outputs.
-        for (vout, output) in tx.output.iter().enumerate() {
-            // Received coin. Mark the address as *used*, and update the balance for that
-            // address.
-            if scripts.contains(&output.script_pubkey) {
-                // Update UTXOs.
-                let txid = tx.txid();
-                let addr =
-                    Address::from_script(&output.script_pubkey, self.network.into()).unwrap();
+    /// Calculate the wallet's confirmed and unconfirmed balances, according to the wallet's
+    /// confirmation settings.
+    pub fn balances(&self) -> Result<utxo::Balance, Error> {
+        let unspent = self.db.unspent()?;
 
-                self.db
-                    .add_utxo(txid, vout as u32, addr, output.value)
-                    .unwrap();
+        Ok(utxo::Balance::new(
+            &unspent,
+            self.tips.header,
+            &self.settings,
+        ))
+    }
+
+    /// Apply a transaction to the wallet's UTXO set. The height is that of the block
+    /// including the transaction, if known.
+    pub fn apply(&mut self, tx: &Transaction, height: Option<Height>) -> Result<(), Error> {
+        let txid = tx.txid();
+        let before = if self.notifier.is_enabled() {
+            self.db.transaction_height(&txid)?
+        } else {
+            None
+        };
+        let received = self.utxos.apply(&self.db, tx, height, &self.watch)?;
+
+        if self.registries.is_enabled() {
+            for source in tx
+                .output
+                .iter()
+                .filter_map(|o| bcmr::Source::parse(&o.script_pubkey))
+                .flatten()
+            {
+                log::info!("Found token registry {} published in {}", source.uri, txid);
+                self.registries.fetch(source);
+            }
+        }
+
+        if self.notifier.is_enabled() {
+            let after = self.db.transaction_height(&txid)?.flatten();
+
+            if !received.is_empty() {
+                let value = tx
+                    .output
+                    .iter()
+                    .filter(|o| {
+                        received
+                            .iter()
+                            .any(|a| a.script_pubkey() == o.script_pubkey)
+                    })
+                    .map(|o| o.value)
+                    .sum();
+
+                self.notifier.notify(Notification::PaymentReceived {
+                    txid,
+                    value,
+                    addresses: received.clone(),
+                    height: after,
+                });
+            } else {
+                self.notify_confirmed(txid, before, after);
+            }
+        }
+
+        // Once our own transaction is confirmed, there's no need to broadcast it anymore.
+        if height.is_some() && self.db.remove_broadcast(&txid)? {
+            self.ui.set_pending(self.db.pending_broadcasts()?.len());
+        }
+
+        // Keep enough unused addresses derived past the ones we just used.
+        if !received.is_empty() {
+            self.derive(height)?;
+        }
+        Ok(())
+    }
+
+    /// Notify hooks if a transaction we already knew of, unconfirmed, was just confirmed.
+    fn notify_confirmed(&self, txid: Txid, before: Option<Option<Height>>, after: Option<Height>) {
+        if let (Some(None), Some(height)) = (before, after) {
+            self.notifier
+                .notify(Notification::TransactionConfirmed { txid, height });
+        }
+    }
+
+    /// Sign a transaction with the configured signer, showing the payments to confirm while a
+    /// hardware device awaits confirmation.
+    ///
+    /// Only coins received on addresses under the wallet's derivation path can be signed for.
+    /// The signed transaction is checked against the network's relay policy, since
+    /// non-standard transactions would otherwise be silently dropped once broadcast.
+    pub fn sign<W: io::Write>(
+        &mut self,
+        unsigned: &UnsignedTx,
+        term: &mut W,
+    ) -> Result<Transaction, Error> {
+        let addresses = self.db.addresses()?;
+        let indices = unsigned
+            .inputs
+            .iter()
+            .map(|(_, txout)| {
+                addresses
+                    .iter()
+                    .find(|a| {
+                        a.account.is_none() && a.address.script_pubkey() == txout.script_pubkey
+                    })
+                    .map(|a| a.index)
+            })
+            .collect::<Vec<_>>();
+        let outputs = unsigned
+            .tx
+            .output
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| Some(*i) != unsigned.change)
+            .filter_map(|(_, o)| {
+                Address::from_script(&o.script_pubkey, self.network.into())
+                    .ok()
+                    .map(|addr| (addr, o.value))
+            })
+            .collect();
+
+        self.ui.handle_signing(ui::Signing::Confirm {
+            outputs,
+            fee: unsigned.fee,
+        });
+        ui::refresh(&mut self.ui, &self.db, term)?;
+
+        let result = self
+            .hw
+            .sign(unsigned, &indices)
+            .map_err(Error::from)
+            .and_then(|tx| {
+                let spent = unsigned
+                    .inputs
+                    .iter()
+                    .map(|(_, txout)| txout.clone())
+                    .collect::<Vec<_>>();
+                policy::check_transaction(&tx, &spent)?;
+
+                Ok(tx)
+            });
+
+        match result {
+            Ok(tx) => {
+                self.ui
+                    .handle_signing(ui::Signing::Signed { txid: tx.txid() });
+                Ok(tx)
             }
+            Err(err) => {
+                self.ui.handle_signing(ui::Signing::Failed {
+                    reason: err.to_string(),
+                });
+                Err(err)
+            }
+        }
+    }
+
+    /// Build the transaction described by the send form. Each row is validated in turn,
+    /// and the first invalid one is reported.
+    fn preview(&self) -> Result<UnsignedTx, String> {
+        let form = self.ui.send();
+        let mut payments = Vec::with_capacity(form.recipients.len());
+
+        for (i, recipient) in form.recipients.iter().enumerate() {
+            let row = |err: String| {
+                if form.recipients.len() > 1 {
+                    format!("Recipient #{}: {}", i + 1, err)
+                } else {
+                    err
+                }
+            };
+            let address = self
+                .recipient(&recipient.address)
+                .or_else(|err| {
+                    form.resolved
+                        .get(recipient.address.trim())
+                        .map(|r| r.address.clone())
+                        .ok_or(err)
+                })
+                .map_err(row)?;
+            let value = send::parse_amount(&recipient.amount).map_err(row)?;
+
+            payments.push((address, value));
         }
+        let data = send::parse_data(&form.data)?;
+
+        self.build(&payments, data)
+    }
 
-        // Look for inputs.
-        for input in tx.input.iter() {
-            // Spent coin. Remove the address from the set, since it is no longer ours.
-            if let Ok(Some((_, _output))) = self.db.remove_utxo(&input.previous_output) {
-                // TODO: Handle change addresses?
+    /// Resolve the names entered as recipients in the send form, that are neither contacts
+    /// nor cashaddrs, and weren't resolved already. Returns the newly resolved names, to be
+    /// confirmed by the user before the transaction is built.
+    fn resolve_recipients(&self) -> Result<Vec<(String, Resolved)>, String> {
+        let form = self.ui.send();
+        let mut resolved: Vec<(String, Resolved)> = Vec::new();
+
+        if self.resolvers.is_empty() {
+            return Ok(resolved);
+        }
+        for recipient in &form.recipients {
+            let name = recipient.address.trim();
+
+            if name.is_empty()
+                || form.resolved.contains_key(name)
+                || resolved.iter().any(|(n, _)| n == name)
+                || self.recipient(name).is_ok()
+            {
+                continue;
+            }
+            if let Some(r) = self
+                .resolvers
+                .resolve(name, self.network.into())
+                .map_err(|e| e.to_string())?
+            {
+                resolved.push((name.to_owned(), r));
             }
         }
+        Ok(resolved)
+    }
+
+    /// Parse a recipient, given either as the label of a contact, or as a cashaddr.
+    fn recipient(&self, recipient: &str) -> Result<Address, String> {
+        let address = match self
+            .db
+            .contact(recipient.trim())
+            .map_err(|e| e.to_string())?
+        {
+            Some(contact) => contact.address,
+            None => recipient.to_owned(),
+        };
+        send::parse_address(&address, self.network.into())
+    }
+
+    /// Save a contact, replacing the contact with the original label, if any. Returns a
+    /// status message, or the reason the contact couldn't be saved.
+    fn save_contact(
+        &mut self,
+        original: Option<String>,
+        contact: db::Contact,
+    ) -> Result<Result<String, String>, Error> {
+        if contact.label.is_empty() {
+            return Ok(Err(String::from("A label is required")));
+        }
+        if let Err(err) = send::parse_address(&contact.address, self.network.into()) {
+            return Ok(Err(format!("Invalid address: {}", err)));
+        }
+        // Don't overwrite another contact when adding or renaming one.
+        let relabeled = original.as_deref() != Some(contact.label.as_str());
+
+        if relabeled && self.db.contact(&contact.label)?.is_some() {
+            return Ok(Err(format!(
+                "A contact labeled `{}` already exists",
+                contact.label
+            )));
+        }
+        if let Some(original) = original.filter(|_| relabeled) {
+            self.db.remove_contact(&original)?;
+        }
+        self.db.add_contact(&contact)?;
+
+        Ok(Ok(format!("Saved contact `{}`", contact.label)))
+    }
+
+    /// Build a transaction paying the given values to their recipients, and optionally
+    /// carrying data, at the send form's fee rate.
+    fn build(
+        &self,
+        payments: &[(Address, u64)],
+        data: Option<Vec<u8>>,
+    ) -> Result<UnsignedTx, String> {
+        let builder = payments.iter().fold(
+            TxBuilder::new(self.change_address()?, self.ui.send().fee_rate),
+            |builder, (recipient, value)| builder.output(recipient, *value),
+        );
+        match data {
+            Some(data) => builder.data(data),
+            None => builder,
+        }
+        .build_from_db(&self.db, self.tips.header)
+        .map_err(|e| e.to_string())
+    }
+
+    /// Build the transaction described by the consolidate form.
+    fn consolidation_preview(&self) -> Result<UnsignedTx, String> {
+        let form = self.ui.consolidate();
+        let threshold = send::parse_amount(&form.threshold)?;
+
+        self.consolidate(threshold, form.fee_rate)
+    }
+
+    /// Build a transaction sweeping the coins worth less than the threshold to a fresh
+    /// address of ours.
+    fn consolidate(&self, threshold: u64, fee_rate: FeeRate) -> Result<UnsignedTx, String> {
+        let utxos = tx::spendable(&self.db, self.tips.header).map_err(|e| e.to_string())?;
+
+        tx::consolidate(utxos, threshold, &self.change_address()?, fee_rate)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Build a child transaction spending our largest unconfirmed output of a transaction we
+    /// sent, so that both pay the given fee rate together.
+    fn speed_up(&self, txid: &Txid, fee_rate: FeeRate) -> Result<UnsignedTx, String> {
+        let (parent, fee) = self
+            .db
+            .sent_transaction(txid)
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("transaction {} wasn't sent by this wallet", txid))?;
+
+        if let Some(Some(height)) = self
+            .db
+            .transaction_height(txid)
+            .map_err(|e| e.to_string())?
+        {
+            return Err(format!(
+                "transaction {} is already confirmed at height {}",
+                txid, height
+            ));
+        }
+        let input = self
+            .db
+            .unspent()
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .filter(|u| u.outpoint.txid == *txid)
+            .max_by_key(|u| u.txout.value)
+            .map(|u| (u.outpoint, u.txout))
+            .ok_or_else(|| format!("transaction {} has no unspent output of ours", txid))?;
+
+        tx::cpfp(&parent, fee, input, &self.change_address()?, fee_rate).map_err(|e| e.to_string())
+    }
+
+    /// The address change is sent to: the first unused address.
+    fn change_address(&self) -> Result<Address, String> {
+        let addresses = self.db.addresses().map_err(|e| e.to_string())?;
+
+        addresses
+            .iter()
+            .find(|a| !a.used)
+            .or_else(|| addresses.first())
+            .map(|a| a.address.clone())
+            .ok_or_else(|| String::from("no change address available"))
+    }
+
+    /// Sign a previewed transaction and submit it to the network. When offline, the
+    /// transaction is queued, and broadcast the next time the wallet is online. Returns a
+    /// status message, or the reason the transaction couldn't be signed or submitted.
+    fn submit<W: io::Write>(
+        &mut self,
+        unsigned: &UnsignedTx,
+        offline: bool,
+        term: &mut W,
+    ) -> Result<Result<String, String>, Error> {
+        let tx = match self.sign(unsigned, term) {
+            Ok(tx) => tx,
+            Err(Error::Hw(err)) => return Ok(Err(err.to_string())),
+            Err(err @ Error::Policy(_)) => return Ok(Err(err.to_string())),
+            Err(err) => return Err(err),
+        };
+        self.broadcast(tx, offline)
+    }
+
+    /// Submit a signed transaction to the network, or queue it for broadcast when offline,
+    /// and apply it to the wallet. Returns a status message, or the reason the transaction
+    /// couldn't be submitted.
+    fn broadcast(
+        &mut self,
+        tx: Transaction,
+        offline: bool,
+    ) -> Result<Result<String, String>, Error> {
+        let txid = tx.txid();
+        let fee = self.fee(&tx)?;
+
+        if offline {
+            self.db.queue_broadcast(&tx)?;
+            self.db.add_sent(&tx, fee)?;
+            self.apply(&tx, None)?;
+
+            let balances = self.balances()?;
+            self.ui.set_balance(balances);
+            self.ui.set_pending(self.db.pending_broadcasts()?.len());
+            log::info!("Queued transaction {} for broadcast", txid);
+
+            return Ok(Ok(format!("Transaction {} pending broadcast", txid)));
+        }
+
+        match self.backend.submit_transaction(tx.clone()) {
+            Ok(nodes) => {
+                self.db.add_sent(&tx, fee)?;
+                self.apply(&tx, None)?;
+                let balances = self.balances()?;
+                self.ui.set_balance(balances);
+                log::info!("Submitted transaction {}", txid);
+
+                Ok(Ok(format!(
+                    "Transaction {} sent to {} node(s)",
+                    txid, nodes
+                )))
+            }
+            Err(err) => Ok(Err(format!("Failed to submit transaction: {}", err))),
+        }
+    }
+
+    /// The fee paid by a transaction of ours, whose inputs are all unspent coins of ours.
+    fn fee(&self, tx: &Transaction) -> Result<u64, Error> {
+        let mut input = 0;
+
+        for txin in &tx.input {
+            if let Some((_, txout)) = self.db.utxo(&txin.previous_output)? {
+                input += txout.value;
+            }
+        }
+        Ok(input.saturating_sub(tx.output.iter().map(|o| o.value).sum()))
+    }
+
+    /// Submit the transactions queued while offline. They are kept in the queue until
+    /// sent to at least one node, and submitted again as peers connect.
+    fn broadcast_pending(&mut self) -> Result<(), Error> {
+        for tx in self.db.pending_broadcasts()? {
+            let txid = tx.txid();
+
+            match self.backend.submit_transaction(tx) {
+                Ok(nodes) => {
+                    self.db.remove_broadcast(&txid)?;
+                    log::info!("Broadcast queued transaction {} to {} node(s)", txid, nodes);
+                }
+                Err(err) => {
+                    log::warn!("Failed to broadcast queued transaction {}: {}", txid, err);
+                }
+            }
+        }
+        self.ui.set_pending(self.db.pending_broadcasts()?.len());
+
+        Ok(())
+    }
+
+    /// Cache the token metadata fetched from a registry.
+    fn handle_token_metadata(
+        &mut self,
+        tokens: Vec<(TokenID, TokenMetadata)>,
+    ) -> Result<(), Error> {
+        let mut changed = false;
+
+        for (id, metadata) in &tokens {
+            changed |= self.db.add_token_metadata(id, metadata)?;
+        }
+        if changed {
+            self.ui.handle_token_metadata();
+        }
+        Ok(())
+    }
+
+    /// Ask the backend for a fee rate estimate, and use it in the send form.
+    fn update_fee_estimate(&mut self) {
+        match self.backend.estimate_fee(send::CONFIRMATION_TARGET) {
+            Ok(Some(rate)) => {
+                self.ui.handle_fee_estimate(rate);
+            }
+            Ok(None) => {}
+            Err(err) => {
+                log::warn!("Failed to estimate fee rate: {}", err);
+            }
+        }
+    }
+
+    /// Ask the backend for the connected peers and the bandwidth they used, if they're shown.
+    fn update_peers(&mut self) {
+        if !self.ui.is_viewing_peers() {
+            return;
+        }
+        let peers = self.backend.peers().and_then(|peers| {
+            let stats = self.backend.peer_stats()?;
+            Ok((peers, stats))
+        });
+        match peers {
+            Ok((peers, stats)) => {
+                self.ui.set_peers(peers, stats);
+            }
+            Err(err) => {
+                log::warn!("Failed to get peers: {}", err);
+            }
+        }
+    }
+
+    /// Disconnect or ban a peer, as requested from the peers view.
+    fn handle_peers_action(&self, action: peers::Action) -> Result<String, String> {
+        let result = match action {
+            peers::Action::Disconnect(addr) => self
+                .backend
+                .disconnect(addr)
+                .map(|()| format!("Disconnected from {}", addr)),
+            peers::Action::Ban(addr) => self.backend.ban(addr).map(|()| format!("Banned {}", addr)),
+        };
+        result.map_err(|err| err.to_string())
+    }
+
+    /// Derive new addresses as needed, and start watching them.
+    ///
+    /// Account addresses are derived from their descriptors. Without accounts, more
+    /// addresses are requested from the hardware device once all known ones are used.
+    ///
+    /// The backend starts watching the new addresses, re-scanning blocks from the given
+    /// height if needed.
+    fn derive(&mut self, rescan_from: Option<Height>) -> Result<(), Error> {
+        let mut derived = Vec::new();
+
+        for acc in &self.accounts {
+            derived.extend(account::derive(&self.db, acc, self.network.into())?);
+        }
+        if self.accounts.is_empty() {
+            let addresses = self.db.addresses()?;
+
+            if addresses.iter().all(|a| a.used) {
+                let next = addresses.iter().map(|a| a.index + 1).max().unwrap_or(0);
+
+                match self
+                    .hw
+                    .request_addresses(next..next + DEVICE_ADDRESS_BATCH, hw::AddressFormat::P2PKH)
+                {
+                    Ok(addrs) => {
+                        for (ix, addr) in addrs {
+                            self.db.add_address(&addr, ix, None, None)?;
+                            derived.push(addr);
+                        }
+                    }
+                    Err(err) => {
+                        log::warn!("Failed to request addresses from hardware device: {err}");
+                    }
+                }
+            }
+        }
+        if derived.is_empty() {
+            return Ok(());
+        }
+        self.watch.extend(derived.iter().cloned());
+
+        let watch = self.watch.addresses().cloned().collect::<Vec<_>>();
+        let pubkeys = self::pubkeys(&self.db)?;
+        let outpoints = self
+            .db
+            .utxos()?
+            .into_iter()
+            .map(|(outpoint, _)| outpoint)
+            .collect::<Vec<_>>();
+        self.backend
+            .watch(&derived, &watch, &pubkeys, &outpoints, rescan_from)?;
+
+        Ok(())
+    }
+
+    /// Import a trusted snapshot into the backend, and its unspent outputs into the wallet.
+    /// Returns the snapshot height.
+    fn import_snapshot(&mut self, snapshot: Snapshot) -> Result<Height, Error> {
+        let utxos = snapshot.utxos.clone();
+        let height = self.backend.import_snapshot(snapshot)?;
+        let imported = self.utxos.import(&self.db, &utxos, &self.watch)?;
+
+        log::info!(
+            "Imported snapshot at height #{} with {} output(s) of ours",
+            height,
+            imported
+        );
+        Ok(height)
     }
 
     /// Run the wallet loop until it exits.
+    ///
+    /// If a snapshot is given, it is imported, and scanning starts past its height instead
+    /// of the birth height. A birth date is looked up in the block headers known.
     pub fn run<W: io::Write>(
         &mut self,
-        birth: Height,
+        birth: Birth,
+        snapshot: Option<Snapshot>,
         inputs: chan::Receiver<Event>,
         signals: chan::Receiver<Signal>,
         loading: chan::Receiver<client::Loading>,
-        events: chan::Receiver<client::Event>,
+        events: chan::Receiver<backend::Event>,
+        rpc: chan::Receiver<rpc::Call>,
         offline: bool,
         mut term: W,
     ) -> Result<(), Error> {
-        let addresses = self.db.addresses()?;
-        if addresses.is_empty() {
-            log::info!("No addresses found, requesting from hardware device..");
-
-            match self.hw.request_addresses(0..16, hw::AddressFormat::P2PKH) {
-                Ok(addrs) => {
-                    for (ix, addr) in addrs {
-                        self.db.add_address(&addr, ix, None)?;
-                        self.watch.insert(addr);
-                    }
-                }
-                Err(err) => {
-                    log::warn!("Failed to request addresses from hardware device: {err}");
-                }
-            }
-        } else {
-            for addr in addresses {
-                self.watch.insert(addr.address);
-            }
+        self.accounts = self.db.accounts()?;
+        self.settings = utxo::Settings::load(&self.db)?;
+
+        for addr in self.db.addresses()? {
+            self.watch.insert(addr.address);
         }
 
         // TODO: Don't rescan if watch list is empty.
 
+        let mut from = match birth {
+            Birth::Height(height) => height,
+            // Offline, nothing is scanned, so there's no need to look the date up.
+            Birth::Date(_) if offline => 0,
+            Birth::Date(_) => {
+                let height = birth.height(&self.backend)?;
+                log::info!("Wallet birth date {} is at block height {}", birth, height);
+
+                height
+            }
+        };
+
+        if let Some(snapshot) = snapshot.filter(|_| !offline) {
+            from = from.max(self.import_snapshot(snapshot)? + 1);
+        }
+
         // Convert our address list into scripts.
-        let watch: Vec<_> = self.watch.iter().map(|a| a.script_pubkey()).collect();
-        let balance = self.db.balance()?;
+        let watch: Vec<_> = self.watch.scripts().cloned().collect();
+        let balances = self.balances()?;
 
-        self.ui.message = format!("Scanning from block height {}", birth);
+        self.ui.message = format!("Scanning from block height {}", from);
         self.ui.reset(&mut term)?;
         self.ui.decorations(&mut term)?;
-        self.ui.set_balance(balance);
+        self.ui.set_balance(balances);
+        self.ui.set_pending(self.db.pending_broadcasts()?.len());
         self.ui.offline(offline);
 
         if offline {
             ui::refresh(&mut self.ui, &self.db, &mut term)?;
         } else {
             // Start a re-scan from the birht height, which keeps scanning as new blocks arrive.
-            self.client.rescan(birth.., watch.iter().cloned())?;
+            self.backend.rescan(from, watch)?;
 
             // Loading...
             loop {
@@ -145,7 +805,7 @@ impl<H: Handle> Wallet<H> {
                     recv(inputs) -> input => {
                         let input = input?;
 
-                        if let Break(()) = self.ui.handle_input_event(input)? {
+                        if let Break(()) = self.handle_input(input, offline, &mut term)? {
                             return Ok(());
                         }
                     }
@@ -156,6 +816,9 @@ impl<H: Handle> Wallet<H> {
                             return Ok(());
                         }
                     }
+                    recv(rpc) -> call => {
+                        self.handle_rpc(call?, offline, &mut term)?;
+                    }
                     recv(loading) -> event => {
                         if let Ok(event) = event {
                             if let Break(()) = self.ui.handle_loading_event(event)? {
@@ -170,13 +833,20 @@ impl<H: Handle> Wallet<H> {
             }
         }
 
+        let ticker = if offline {
+            chan::never()
+        } else {
+            chan::tick(PEER_STATS_INTERVAL)
+        };
+        let metadata = self.registries.results().clone();
+
         // Running...
         loop {
             chan::select! {
                 recv(inputs) -> input => {
                     let input = input?;
 
-                    if let Break(()) = self.handle_input(input)? {
+                    if let Break(()) = self.handle_input(input, offline, &mut term)? {
                         return Ok(());
                     }
                 }
@@ -187,32 +857,125 @@ impl<H: Handle> Wallet<H> {
                         return Ok(());
                     }
                 }
+                recv(rpc) -> call => {
+                    self.handle_rpc(call?, offline, &mut term)?;
+                }
                 recv(events) -> event => {
                     let event = event?;
 
-                    if let Break(()) = self.handle_client_event(event, &watch, offline, &mut term)? {
+                    if let Break(()) = self.handle_backend_event(event, offline, &mut term)? {
                         break;
                     }
                 }
+                recv(ticker) -> _ => {
+                    self.update_peers();
+                }
+                recv(metadata) -> tokens => {
+                    if let Ok(tokens) = tokens {
+                        self.handle_token_metadata(tokens)?;
+                    }
+                }
             }
             ui::refresh(&mut self.ui, &self.db, &mut term)?;
         }
         Ok(())
     }
 
-    fn handle_input(&mut self, input: Event) -> Result<ControlFlow<()>, Error> {
+    fn handle_input<W: io::Write>(
+        &mut self,
+        input: Event,
+        offline: bool,
+        term: &mut W,
+    ) -> Result<ControlFlow<()>, Error> {
         use termion::event::Key;
 
         match input {
             Event::Key(Key::F(1)) => {
                 self.hw.connect()?;
             }
-            _ => return self.ui.handle_input_event(input).map_err(Error::from),
+            // The log pane keys work on every tab, including the ones taking text input.
+            Event::Key(key @ (Key::F(2) | Key::F(3) | Key::PageUp | Key::PageDown)) => {
+                self.ui.handle_log_key(key);
+            }
+            Event::Key(key) if self.ui.is_sending() => match self.ui.handle_send_key(key) {
+                Some(send::Action::Preview) => match self.resolve_recipients() {
+                    Ok(resolved) if !resolved.is_empty() => {
+                        self.ui.handle_send_resolved(resolved);
+                    }
+                    Ok(_) => {
+                        let preview = self.preview();
+                        self.ui.handle_send_preview(preview);
+                    }
+                    Err(err) => self.ui.handle_send_preview(Err(err)),
+                },
+                Some(send::Action::Submit) => {
+                    if let Some(unsigned) = self.ui.send().preview.clone() {
+                        let status = self.submit(&unsigned, offline, term)?;
+                        self.ui.handle_send_status(status);
+                    }
+                }
+                Some(send::Action::Cancel) | None => {}
+            },
+            Event::Key(key) if self.ui.is_consolidating() => {
+                match self.ui.handle_consolidate_key(key) {
+                    Some(send::Action::Preview) => {
+                        let preview = self.consolidation_preview();
+                        self.ui.handle_consolidate_preview(preview);
+                    }
+                    Some(send::Action::Submit) => {
+                        if let Some(unsigned) = self.ui.consolidate().preview.clone() {
+                            let status = self.submit(&unsigned, offline, term)?;
+                            self.ui.handle_consolidate_status(status);
+                        }
+                    }
+                    Some(send::Action::Cancel) | None => {}
+                }
+            }
+            Event::Key(key) if self.ui.is_managing_contacts() => {
+                match self.ui.handle_contacts_key(key) {
+                    Some(contacts::Action::Save { original, contact }) => {
+                        let status = self.save_contact(original, contact)?;
+                        self.ui.handle_contacts_status(status);
+                    }
+                    Some(contacts::Action::Delete(label)) => {
+                        self.db.remove_contact(&label)?;
+                        self.ui
+                            .handle_contacts_status(Ok(format!("Deleted contact `{}`", label)));
+                    }
+                    _ => {}
+                }
+                self.refresh_contacts()?;
+            }
+            _ => {
+                if let Event::Key(key) = input {
+                    if let Some(action) = self.ui.handle_peers_key(key) {
+                        let status = self.handle_peers_action(action);
+                        self.ui.handle_peers_status(status);
+                    }
+                }
+                let flow = self.ui.handle_input_event(input)?;
+                self.refresh_contacts()?;
+
+                if !offline {
+                    self.update_peers();
+                }
+
+                return Ok(flow);
+            }
         }
 
         Ok(Continue(()))
     }
 
+    /// Update the contacts shown, if the contacts view is active.
+    fn refresh_contacts(&mut self) -> Result<(), Error> {
+        if self.ui.is_managing_contacts() {
+            let matches = self.db.contacts(&self.ui.contacts().query)?;
+            self.ui.set_contacts(matches);
+        }
+        Ok(())
+    }
+
     fn handle_signal<W: io::Write>(
         &mut self,
         signal: Signal,
@@ -229,57 +992,297 @@ impl<H: Handle> Wallet<H> {
         Ok(Continue(()))
     }
 
-    fn handle_client_event<W: io::Write>(
+    fn handle_rpc<W: io::Write>(
+        &mut self,
+        call: rpc::Call,
+        offline: bool,
+        term: &mut W,
+    ) -> Result<(), Error> {
+        log::debug!("Received RPC request: {:?}", call.request);
+
+        let result = self.rpc(call.request, offline, term);
+        call.reply.send(result).ok();
+
+        Ok(())
+    }
+
+    /// Handle an RPC request.
+    fn rpc<W: io::Write>(
         &mut self,
-        event: client::Event,
-        watch: &[Script],
+        request: rpc::Request,
+        offline: bool,
+        term: &mut W,
+    ) -> Result<serde_json::Value, rpc::Error> {
+        match request {
+            rpc::Request::GetBalance => {
+                let balances = self.balances()?;
+
+                Ok(json!({
+                    "confirmed": balances.confirmed,
+                    "unconfirmed": balances.unconfirmed,
+                    "total": balances.total(),
+                }))
+            }
+            rpc::Request::ListUnspent => {
+                let utxos = self.db.unspent().map_err(Error::from)?;
+
+                Ok(utxos
+                    .iter()
+                    .map(|u| {
+                        let address =
+                            Address::from_script(&u.txout.script_pubkey, self.network.into())
+                                .ok()
+                                .map(|a| a.to_string());
+
+                        json!({
+                            "txid": u.outpoint.txid.to_string(),
+                            "vout": u.outpoint.vout,
+                            "address": address,
+                            "amount": u.txout.value,
+                            "confirmations": u.confirmations(self.tips.header),
+                            "coinbase": u.coinbase,
+                            "final": u.is_final(self.tips.header, &self.settings),
+                        })
+                    })
+                    .collect())
+            }
+            rpc::Request::SendToAddress {
+                address,
+                amount,
+                data,
+            } => {
+                let recipient = self
+                    .recipient(&address)
+                    .map_err(|_| rpc::Error::InvalidParams("address"))?;
+                let unsigned = self
+                    .build(&[(recipient, amount)], data)
+                    .map_err(rpc::Error::Wallet)?;
+                let tx = self.sign(&unsigned, term)?;
+                let txid = tx.txid();
+
+                self.broadcast(tx, offline)?.map_err(rpc::Error::Wallet)?;
+
+                Ok(json!(txid.to_string()))
+            }
+            rpc::Request::Consolidate {
+                threshold,
+                fee_rate,
+            } => {
+                let unsigned = self
+                    .consolidate(threshold, fee_rate)
+                    .map_err(rpc::Error::Wallet)?;
+                let tx = self.sign(&unsigned, term)?;
+                let txid = tx.txid();
+
+                self.broadcast(tx, offline)?.map_err(rpc::Error::Wallet)?;
+
+                Ok(json!({
+                    "txid": txid.to_string(),
+                    "inputs": unsigned.inputs.len(),
+                    "fee": unsigned.fee,
+                }))
+            }
+            rpc::Request::SpeedUp { txid, fee_rate } => {
+                let unsigned = self.speed_up(&txid, fee_rate).map_err(rpc::Error::Wallet)?;
+                let tx = self.sign(&unsigned, term)?;
+                let child = tx.txid();
+
+                self.broadcast(tx, offline)?.map_err(rpc::Error::Wallet)?;
+
+                Ok(json!({
+                    "txid": child.to_string(),
+                    "fee": unsigned.fee,
+                }))
+            }
+            rpc::Request::GetNewAddress => {
+                // Addresses are derived ahead of use, so the next unused one is stored.
+                self.db
+                    .addresses()
+                    .map_err(Error::from)?
+                    .into_iter()
+                    .find(|a| !a.used)
+                    .map(|a| json!(a.address.to_string()))
+                    .ok_or_else(|| rpc::Error::Wallet(String::from("no unused address available")))
+            }
+            rpc::Request::GetBlockCount => Ok(json!(self.tips.header)),
+            rpc::Request::GetPrivacyInfo => {
+                let matches = self.utxos.matches();
+
+                Ok(json!({
+                    "matched": matches.total,
+                    "falsepositives": matches.false_positives,
+                    "fprate": matches.fp_rate(),
+                }))
+            }
+            rpc::Request::Rescan { from } => {
+                let from = from.height(&self.backend).map_err(Error::from)?;
+                let watch = self.watch.scripts().cloned().collect();
+                self.backend.rescan(from, watch).map_err(Error::from)?;
+
+                Ok(serde_json::Value::Null)
+            }
+        }
+    }
+
+    fn handle_backend_event<W: io::Write>(
+        &mut self,
+        event: backend::Event,
         offline: bool,
         term: &mut W,
     ) -> Result<ControlFlow<()>, Error> {
-        log::debug!("Received event: {}", event);
+        log::debug!("Received backend event: {:?}", event);
 
         match event {
-            client::Event::Ready { tip, .. } => {
+            backend::Event::Ready { tip } => {
                 self.ui.handle_ready(tip, offline);
+
+                if !offline {
+                    self.broadcast_pending()?;
+                    self.update_fee_estimate();
+                }
             }
-            client::Event::PeerHeightUpdated { height } => {
+            backend::Event::PeerConnected => {
+                // Transactions queued before any peer was connected can be broadcast now.
+                if !offline {
+                    self.broadcast_pending()?;
+                }
+            }
+            backend::Event::Disconnected => {
+                log::warn!("Disconnected from chain source, reconnecting..");
+
+                self.ui.handle_disconnected();
+            }
+            backend::Event::FeeEstimateUpdated => {
+                self.update_fee_estimate();
+            }
+            backend::Event::NetworkHeight { height } => {
                 self.ui.handle_peer_height(height);
             }
-            client::Event::FilterProcessed { height, .. } => {
+            backend::Event::Synchronizing {
+                current,
+                best,
+                rate_headers_per_sec,
+            } => {
+                self.ui
+                    .handle_header_sync(current, best, rate_headers_per_sec);
+            }
+            backend::Event::Processed { height } => {
                 self.ui.handle_filter_processed(height);
                 self.tips.cfilter = height;
             }
-            client::Event::BlockHeadersImported { height, .. } => {
+            backend::Event::TipUpdated { height } => {
                 self.tips.header = height;
+                self.ui.handle_tip(height);
+                // Outputs may have become final or mature.
+                self.ui.set_balance(self.balances()?);
             }
-            client::Event::BlockMatched { block, height } => {
-                for t in &block.txdata {
-                    self.apply(t, watch);
+            backend::Event::BlockDisconnected { height } => {
+                let txids = if self.notifier.is_enabled() {
+                    self.db.confirmed_since(height)?
+                } else {
+                    Vec::new()
+                };
+                self.utxos.disconnect(&self.db, height)?;
+
+                if !txids.is_empty() {
+                    self.notifier.notify(Notification::Reorg { height, txids });
                 }
-                let balance = self.balance()?;
-                self.ui.set_balance(balance);
+                self.ui.set_balance(self.balances()?);
+
+                log::info!("Reverted block at height #{}", height);
+            }
+            backend::Event::Block {
+                height,
+                transactions,
+            } => {
+                for t in &transactions {
+                    self.apply(t, Some(height))?;
+                }
+                let balances = self.balances()?;
+                self.ui.set_balance(balances);
                 self.ui.redraw(&self.db, term)?;
 
                 log::info!(
                     "Processed block at height #{} (balance = {})",
                     height,
-                    balance,
+                    balances.total(),
                 );
             }
-            client::Event::ReceivedMatchedTx { transaction } => {
-                // for t in &matches {
-                self.apply(&transaction, watch);
-                // }
-                let balance = self.balance()?;
-                self.ui.set_balance(balance);
+            backend::Event::Confirmed { height, txids } => {
+                let before = if self.notifier.is_enabled() {
+                    txids
+                        .iter()
+                        .map(|txid| self.db.transaction_height(txid))
+                        .collect::<Result<Vec<_>, _>>()?
+                } else {
+                    Vec::new()
+                };
+                self.utxos.merkle_block(&self.db, &txids, height)?;
+
+                // Transactions that aren't known yet are notified once received.
+                for (txid, before) in txids.into_iter().zip(before) {
+                    let after = self.db.transaction_height(&txid)?.flatten();
+                    self.notify_confirmed(txid, before, after);
+                }
+
+                let balances = self.balances()?;
+                self.ui.set_balance(balances);
+                self.ui.redraw(&self.db, term)?;
+            }
+            backend::Event::Transaction {
+                transaction,
+                height,
+            } => {
+                if !self
+                    .utxos
+                    .check_match(&self.db, &transaction, &self.watch)?
+                {
+                    let matches = self.utxos.matches();
+
+                    log::debug!(
+                        "Ignoring tx {} matched by peers, but not ours ({}/{} false positives)",
+                        transaction.txid(),
+                        matches.false_positives,
+                        matches.total,
+                    );
+                    return Ok(ControlFlow::Continue(()));
+                }
+                self.apply(&transaction, height)?;
+
+                let balances = self.balances()?;
+                self.ui.set_balance(balances);
                 self.ui.redraw(&self.db, term)?;
 
-                log::info!("Processed tx (balance = {})", balance,);
+                log::info!("Processed tx (balance = {})", balances.total());
             }
-            client::Event::Scanned { height, .. } => {
+            backend::Event::DoubleSpend {
+                original,
+                conflicting,
+            } => {
+                self.ui.handle_double_spend(original, conflicting);
+
+                log::warn!(
+                    "Transaction {} is being double-spent by {}",
+                    original,
+                    conflicting
+                );
+            }
+            backend::Event::DoubleSpendProof { txid, proof } => {
+                self.ui.handle_double_spend_proof(txid);
+
+                log::warn!("Transaction {} is double-spent (proof {})", txid, proof);
+            }
+            backend::Event::Synced { height } => {
                 self.ui.handle_synced(height, self.tips.header);
             }
-            _ => {}
+            backend::Event::FilterResized { fp_rate, target } => {
+                log::info!(
+                    "Bloom filters resized, as their false-positive rate of {:.5} \
+                    drifted from the target of {}",
+                    fp_rate,
+                    target
+                );
+            }
         }
         Ok(ControlFlow::Continue(()))
     }
@@ -0,0 +1,161 @@
+//! Descriptor-based wallet accounts.
+use std::fmt;
+use std::str::FromStr;
+
+use nakamoto_common::bitcoin::secp256k1::Secp256k1;
+use nakamoto_common::bitcoin::{Address, Network, PublicKey};
+
+use crate::error::Error;
+use crate::wallet::db::{Db, Read as _, Write as _};
+use crate::wallet::descriptor::{self, Descriptor};
+
+/// Default number of unused addresses to keep derived past the last used one.
+pub const DEFAULT_GAP_LIMIT: usize = 20;
+
+/// A wallet account, deriving its addresses from an output descriptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Account {
+    /// Account label. Uniquely identifies the account.
+    pub label: String,
+    /// Descriptor used to derive the account's addresses.
+    pub descriptor: Descriptor,
+    /// Number of unused addresses to keep derived past the last used one.
+    pub gap_limit: usize,
+}
+
+/// Formats the account the way it's parsed, eg. to export it.
+impl fmt::Display for Account {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.label, self.gap_limit, self.descriptor)
+    }
+}
+
+impl FromStr for Account {
+    type Err = descriptor::Error;
+
+    /// Parse an account of the form `<label>:[<gap limit>:]<descriptor>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s.splitn(3, ':').collect::<Vec<_>>();
+        let (label, gap_limit, desc) = match parts.as_slice() {
+            [label, desc] => (label, DEFAULT_GAP_LIMIT, desc),
+            [label, gap_limit, desc] => (
+                label,
+                gap_limit
+                    .parse()
+                    .map_err(|_| descriptor::Error::Unsupported(s.to_owned()))?,
+                desc,
+            ),
+            _ => return Err(descriptor::Error::Unsupported(s.to_owned())),
+        };
+        if label.is_empty() {
+            return Err(descriptor::Error::Unsupported(s.to_owned()));
+        }
+
+        Ok(Self {
+            label: label.to_string(),
+            descriptor: desc.parse()?,
+            gap_limit,
+        })
+    }
+}
+
+/// Derive and store new addresses for the given account, so that at least `gap_limit`
+/// unused addresses follow the last used one. Returns the newly derived addresses.
+pub fn derive(db: &Db, account: &Account, network: Network) -> Result<Vec<Address>, Error> {
+    let addresses = db.account_addresses(&account.label)?;
+    let next = addresses.iter().map(|a| a.index + 1).max().unwrap_or(0);
+    let used = addresses
+        .iter()
+        .filter(|a| a.used)
+        .map(|a| a.index + 1)
+        .max()
+        .unwrap_or(0);
+    let target = used + account.gap_limit;
+
+    if next >= target {
+        return Ok(Vec::new());
+    }
+    let secp = Secp256k1::verification_only();
+    let mut derived = Vec::with_capacity(target - next);
+
+    for ix in next..target {
+        let addr = account.descriptor.derive(&secp, ix as u32, network)?;
+
+        db.add_address(&addr, ix, None, Some(&account.label))?;
+        derived.push(addr);
+    }
+    log::debug!(
+        "Derived {} address(es) for account `{}`",
+        derived.len(),
+        account.label
+    );
+
+    Ok(derived)
+}
+
+/// Get the public keys of the addresses derived for the given account, so that spends from
+/// them can be recognized.
+pub fn pubkeys(db: &Db, account: &Account) -> Result<Vec<PublicKey>, Error> {
+    let secp = Secp256k1::verification_only();
+
+    db.account_addresses(&account.label)?
+        .iter()
+        .map(|a| {
+            account
+                .descriptor
+                .derive_pubkey(&secp, a.index as u32)
+                .map_err(Error::from)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XPUB: &str = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+
+    #[test]
+    fn test_parse() {
+        let acc = format!("savings:5:pkh({XPUB}/0/*)")
+            .parse::<Account>()
+            .unwrap();
+        assert_eq!(acc.label, "savings");
+        assert_eq!(acc.gap_limit, 5);
+
+        let acc = format!("spending:pkh({XPUB}/0/*)")
+            .parse::<Account>()
+            .unwrap();
+        assert_eq!(acc.label, "spending");
+        assert_eq!(acc.gap_limit, DEFAULT_GAP_LIMIT);
+
+        assert!(format!("pkh({XPUB}/0/*)").parse::<Account>().is_err());
+        assert!(format!(":pkh({XPUB}/0/*)").parse::<Account>().is_err());
+        assert!(format!("savings:x:pkh({XPUB}/0/*)")
+            .parse::<Account>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_derive() {
+        let db = Db::memory().unwrap();
+        let acc = format!("savings:3:pkh({XPUB}/0/*)")
+            .parse::<Account>()
+            .unwrap();
+        db.add_account(&acc).unwrap();
+
+        let derived = derive(&db, &acc, Network::Bitcoin).unwrap();
+        assert_eq!(derived.len(), 3);
+        assert!(derive(&db, &acc, Network::Bitcoin).unwrap().is_empty());
+
+        // Using an address moves the gap forward.
+        db.mark_used(&derived[1], 1000).unwrap();
+
+        let more = derive(&db, &acc, Network::Bitcoin).unwrap();
+        assert_eq!(more.len(), 2);
+
+        let stored = db.account_addresses("savings").unwrap();
+        assert_eq!(stored.len(), 5);
+        assert_eq!(stored.iter().map(|a| a.index).max(), Some(4));
+    }
+}
@@ -0,0 +1,166 @@
+//! Chain backends the wallet syncs from.
+//!
+//! The wallet talks to the chain through the [`Backend`] trait, and is driven by the
+//! backend-agnostic [`Event`]s it emits. This lets it sync either from the peer-to-peer
+//! network using bloom filters, or from an Electrum server.
+pub mod electrum;
+pub mod p2p;
+
+use nakamoto_client::handle;
+use nakamoto_common::bitcoin::{
+    Address, DSProofId, OutPoint, PublicKey, Script, Transaction, Txid,
+};
+use nakamoto_common::block::snapshot::Snapshot;
+use nakamoto_common::block::{BlockTime, Height};
+use nakamoto_p2p::fsm::fees::FeeRate;
+use nakamoto_p2p::fsm::{ImportSnapshotError, Peer, PeerId, PeerStats};
+use thiserror::Error;
+
+pub use electrum::Electrum;
+pub use p2p::P2p;
+
+/// A backend error.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("client handle error: {0}")]
+    Handle(#[from] handle::Error),
+    #[error("electrum error: {0}")]
+    Electrum(#[from] electrum::Error),
+    #[error("error importing snapshot: {0}")]
+    Snapshot(#[from] ImportSnapshotError),
+    #[error("{0} is not supported by this backend")]
+    Unsupported(&'static str),
+}
+
+/// A chain event, as seen by the wallet.
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// The backend is ready, with the given chain tip.
+    Ready {
+        /// Height of the chain tip.
+        tip: Height,
+    },
+    /// A peer relaying transactions was connected. Transactions can't be broadcast until
+    /// then.
+    PeerConnected,
+    /// The connection to the chain source was lost. The backend keeps trying to reconnect,
+    /// and sends [`Event::Ready`] once reconnected.
+    Disconnected,
+    /// The best height known on the network was updated.
+    NetworkHeight {
+        /// Best known height.
+        height: Height,
+    },
+    /// Block headers are being synced from the network.
+    Synchronizing {
+        /// Height of our chain tip.
+        current: Height,
+        /// Best height known on the network.
+        best: Height,
+        /// Block headers synced per second, or zero if not known yet.
+        rate_headers_per_sec: f64,
+    },
+    /// The chain tip was updated.
+    TipUpdated {
+        /// Height of the new tip.
+        height: Height,
+    },
+    /// Blocks were processed up to the given height.
+    Processed {
+        /// Height of the last processed block.
+        height: Height,
+    },
+    /// The block at the given height was disconnected from the chain, along with any
+    /// blocks above it.
+    BlockDisconnected {
+        /// Height of the disconnected block.
+        height: Height,
+    },
+    /// Transactions of ours were found in a block.
+    Block {
+        /// Height of the block.
+        height: Height,
+        /// Matching transactions.
+        transactions: Vec<Transaction>,
+    },
+    /// Transactions were confirmed in the block at the given height. Those that aren't
+    /// known yet are confirmed once received.
+    Confirmed {
+        /// Height of the block.
+        height: Height,
+        /// Confirmed transactions.
+        txids: Vec<Txid>,
+    },
+    /// A transaction of ours was found.
+    Transaction {
+        /// The transaction.
+        transaction: Transaction,
+        /// Height of the block including the transaction, if known.
+        height: Option<Height>,
+    },
+    /// A new fee rate estimate may be available.
+    FeeEstimateUpdated,
+    /// A transaction of ours is being double-spent.
+    DoubleSpend {
+        /// The transaction being double-spent.
+        original: Txid,
+        /// The conflicting transaction.
+        conflicting: Txid,
+    },
+    /// A double-spend proof was received for a transaction of ours.
+    DoubleSpendProof {
+        /// The transaction being double-spent.
+        txid: Txid,
+        /// The proof identifier.
+        proof: DSProofId,
+    },
+    /// The wallet is synced up to the given height.
+    Synced {
+        /// Height up to which the wallet is synced.
+        height: Height,
+    },
+    /// Bloom filters were rebuilt larger, as too many transactions matched by peers
+    /// weren't ours.
+    FilterResized {
+        /// False-positive rate observed.
+        fp_rate: f64,
+        /// Target false-positive rate.
+        target: f64,
+    },
+}
+
+/// A source of chain data for the wallet.
+pub trait Backend {
+    /// Start syncing transactions of the given scripts from the given height, and keep
+    /// syncing as new blocks arrive.
+    fn rescan(&self, from: Height, watch: Vec<Script>) -> Result<(), Error>;
+    /// Start watching newly derived addresses. The full watch set, including them, is also
+    /// given, along with the known public keys and unspent outputs of its addresses, which
+    /// spends from them are matched on. If a height is given, blocks are re-scanned from it.
+    fn watch(
+        &self,
+        derived: &[Address],
+        watch: &[Address],
+        pubkeys: &[PublicKey],
+        outpoints: &[OutPoint],
+        rescan_from: Option<Height>,
+    ) -> Result<(), Error>;
+    /// Broadcast a transaction. Returns the number of nodes it was sent to.
+    fn submit_transaction(&self, tx: Transaction) -> Result<usize, Error>;
+    /// Estimate the fee rate, in satoshis per byte, needed for a transaction to be included
+    /// within the given number of blocks. Returns `None` if no estimate is available.
+    fn estimate_fee(&self, blocks: usize) -> Result<Option<FeeRate>, Error>;
+    /// Height of the first block with a timestamp at or after the given time, or if there
+    /// is none yet, of the next block.
+    fn height_at(&self, time: BlockTime) -> Result<Height, Error>;
+    /// Import a trusted snapshot. Returns the snapshot height.
+    fn import_snapshot(&self, snapshot: Snapshot) -> Result<Height, Error>;
+    /// Get connected peers.
+    fn peers(&self) -> Result<Vec<Peer>, Error>;
+    /// Get the bandwidth used by each connected peer.
+    fn peer_stats(&self) -> Result<Vec<PeerStats>, Error>;
+    /// Disconnect from a peer.
+    fn disconnect(&self, addr: PeerId) -> Result<(), Error>;
+    /// Disconnect from a peer, and don't connect to it again.
+    fn ban(&self, addr: PeerId) -> Result<(), Error>;
+}
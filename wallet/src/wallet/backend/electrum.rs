@@ -0,0 +1,940 @@
+//! Electrum backend, syncing from an Electrum or Fulcrum server.
+//!
+//! Watched scripts are subscribed to by script hash, and their history is fetched with
+//! `blockchain.scripthash.get_history` whenever their status changes. This reveals the
+//! wallet's addresses to the server, in exchange for not having to scan blocks.
+//!
+//! If the connection to the server is lost, the wallet is sent [`Event::Disconnected`],
+//! and the backend reconnects with exponential backoff. Once reconnected, everything is
+//! subscribed to again, and the history of all scripts is synced, to catch up with what
+//! happened in the meantime.
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, BufRead, BufReader, Write as _};
+use std::net;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crossbeam_channel as chan;
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use nakamoto_common::bitcoin::consensus::encode;
+use nakamoto_common::bitcoin::hashes::hex::{FromHex, ToHex};
+use nakamoto_common::bitcoin::hashes::{sha256, Hash};
+use nakamoto_common::bitcoin::{Address, OutPoint, PublicKey, Script, Transaction, Txid};
+use nakamoto_common::block::snapshot::Snapshot;
+use nakamoto_common::block::{BlockHash, BlockHeader, BlockTime, Height};
+use nakamoto_p2p::fsm::fees::{FeeRate, MIN_FEE_RATE};
+use nakamoto_p2p::fsm::{Peer, PeerId, PeerStats};
+
+use super::{Backend, Event};
+
+/// Client name sent to the server.
+pub const CLIENT_NAME: &str = "nakamoto-wallet";
+/// Electrum protocol version requested from the server.
+pub const PROTOCOL_VERSION: &str = "1.4";
+/// Time to wait for the connection to the server to be established.
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Time to wait for a server response.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+/// Interval at which the server is pinged, to keep the connection alive.
+pub const PING_INTERVAL: Duration = Duration::from_secs(60);
+/// Time to wait before reconnecting to the server. Doubled after every failed attempt.
+pub const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// Maximum time to wait between reconnection attempts.
+pub const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// An Electrum backend error.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid server response: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid server response: missing or invalid `{0}`")]
+    Decode(&'static str),
+    #[error("error decoding server data: {0}")]
+    Encoding(#[from] encode::Error),
+    #[error("server error: {0}")]
+    Server(String),
+    #[error("request timed out")]
+    Timeout,
+    #[error("disconnected from server")]
+    Disconnected,
+}
+
+/// Compute the Electrum script hash of a script, ie. its SHA-256 hash in reverse byte
+/// order, hex-encoded.
+pub fn script_hash(script: &Script) -> String {
+    let mut hash = sha256::Hash::hash(script.as_bytes()).into_inner();
+    hash.reverse();
+    hash.to_hex()
+}
+
+/// A notification sent by the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Notification {
+    /// A new chain tip.
+    Header { height: Height, hash: BlockHash },
+    /// The status of a subscribed script hash changed.
+    Status { script_hash: String },
+}
+
+/// A message received from the server.
+#[derive(Debug)]
+enum Message {
+    /// A response to one of our requests.
+    Response {
+        id: u64,
+        result: Result<Value, Error>,
+    },
+    /// A subscription notification.
+    Notification(Notification),
+}
+
+/// Parse a message received from the server.
+fn parse(line: &str) -> Result<Message, Error> {
+    let value: Value = serde_json::from_str(line)?;
+
+    if let Some(id) = value["id"].as_u64() {
+        let result = match &value["error"] {
+            Value::Null => Ok(value["result"].clone()),
+            err => Err(Error::Server(
+                err["message"]
+                    .as_str()
+                    .map(ToOwned::to_owned)
+                    .unwrap_or_else(|| err.to_string()),
+            )),
+        };
+        return Ok(Message::Response { id, result });
+    }
+    let notification = match value["method"].as_str() {
+        Some("blockchain.headers.subscribe") => {
+            let (height, hash) = parse_header(&value["params"][0])?;
+
+            Notification::Header { height, hash }
+        }
+        Some("blockchain.scripthash.subscribe") => Notification::Status {
+            script_hash: value["params"][0]
+                .as_str()
+                .ok_or(Error::Decode("params"))?
+                .to_owned(),
+        },
+        _ => return Err(Error::Decode("method")),
+    };
+    Ok(Message::Notification(notification))
+}
+
+/// Parse a block header notification, returning the block height and hash.
+fn parse_header(value: &Value) -> Result<(Height, BlockHash), Error> {
+    let height = value["height"].as_u64().ok_or(Error::Decode("height"))?;
+    let header: BlockHeader = encode::deserialize(&parse_hex(&value["hex"], "hex")?)?;
+
+    Ok((height, header.block_hash()))
+}
+
+/// Parse a script history, returning the transaction ids along with the heights of the
+/// blocks including them. Unconfirmed transactions have no height.
+fn parse_history(value: &Value) -> Result<Vec<(Txid, Option<Height>)>, Error> {
+    value
+        .as_array()
+        .ok_or(Error::Decode("history"))?
+        .iter()
+        .map(|entry| {
+            let txid = entry["tx_hash"]
+                .as_str()
+                .and_then(|s| Txid::from_hex(s).ok())
+                .ok_or(Error::Decode("tx_hash"))?;
+            // Unconfirmed transactions have a height of zero, or `-1` if they have
+            // unconfirmed parents.
+            let height = entry["height"].as_i64().ok_or(Error::Decode("height"))?;
+
+            Ok((txid, u64::try_from(height).ok().filter(|h| *h > 0)))
+        })
+        .collect()
+}
+
+/// Parse a hex-encoded field.
+fn parse_hex(value: &Value, field: &'static str) -> Result<Vec<u8>, Error> {
+    value
+        .as_str()
+        .and_then(|s| Vec::<u8>::from_hex(s).ok())
+        .ok_or(Error::Decode(field))
+}
+
+/// Requests awaiting a response, by request id.
+type Pending = Arc<Mutex<HashMap<u64, chan::Sender<Result<Value, Error>>>>>;
+
+/// A connection to an Electrum server. Requests can be made from any thread.
+#[derive(Debug, Clone)]
+struct Connection {
+    stream: Arc<Mutex<net::TcpStream>>,
+    pending: Pending,
+    next_id: Arc<AtomicU64>,
+    /// Whether the connection was shut down by us, and shouldn't be re-established.
+    closed: Arc<AtomicBool>,
+}
+
+impl Connection {
+    /// Connect to a server. Returns the connection, along with the channel notifications
+    /// are sent on, which is closed once the connection is lost.
+    fn open(addr: net::SocketAddr) -> Result<(Self, chan::Receiver<Notification>), Error> {
+        let stream = net::TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+        let conn = Self {
+            stream: Arc::new(Mutex::new(stream.try_clone()?)),
+            pending: Pending::default(),
+            next_id: Arc::new(AtomicU64::new(0)),
+            closed: Arc::new(AtomicBool::new(false)),
+        };
+        let notifications = conn.spawn_reader(stream);
+        conn.handshake(addr)?;
+
+        Ok((conn, notifications))
+    }
+
+    /// Re-establish a lost connection. Clones of this connection use the new one.
+    /// The handshake is left to the caller.
+    fn reopen(&self, addr: net::SocketAddr) -> Result<chan::Receiver<Notification>, Error> {
+        let stream = net::TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+        *self.stream.lock().unwrap() = stream.try_clone()?;
+
+        Ok(self.spawn_reader(stream))
+    }
+
+    /// Negotiate the protocol version with the server.
+    fn handshake(&self, addr: net::SocketAddr) -> Result<(), Error> {
+        let version = self.request("server.version", json!([CLIENT_NAME, PROTOCOL_VERSION]))?;
+        log::info!("Connected to Electrum server {} ({})", addr, version);
+
+        Ok(())
+    }
+
+    /// Spawn a thread reading messages from the given stream.
+    fn spawn_reader(&self, stream: net::TcpStream) -> chan::Receiver<Notification> {
+        let (tx, rx) = chan::unbounded();
+        let conn = self.clone();
+
+        thread::spawn(move || conn.read(BufReader::new(stream), tx));
+
+        rx
+    }
+
+    /// Shut the connection down. The reading thread stops, and pending requests fail.
+    fn shutdown(&self) -> Result<(), Error> {
+        self.stream
+            .lock()
+            .unwrap()
+            .shutdown(net::Shutdown::Both)
+            .map_err(Error::from)
+    }
+
+    /// Whether the connection was shut down by us.
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Make a request, and wait for its response.
+    fn request(&self, method: &str, params: Value) -> Result<Value, Error> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (tx, rx) = chan::bounded(1);
+        let mut msg = serde_json::to_vec(&json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        }))?;
+        msg.push(b'\n');
+
+        self.pending.lock().unwrap().insert(id, tx);
+
+        if let Err(err) = self.stream.lock().unwrap().write_all(&msg) {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(err.into());
+        }
+        match rx.recv_timeout(REQUEST_TIMEOUT) {
+            Ok(result) => result,
+            Err(chan::RecvTimeoutError::Timeout) => {
+                self.pending.lock().unwrap().remove(&id);
+                Err(Error::Timeout)
+            }
+            Err(chan::RecvTimeoutError::Disconnected) => Err(Error::Disconnected),
+        }
+    }
+
+    /// Read messages from the server, dispatching responses to the pending requests and
+    /// sending notifications on the given channel, until the connection is closed.
+    fn read<R: BufRead>(&self, reader: R, notifications: chan::Sender<Notification>) {
+        for line in reader.lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(err) => {
+                    log::warn!("Failed to read from Electrum server: {}", err);
+                    break;
+                }
+            };
+            match parse(&line) {
+                Ok(Message::Response { id, result }) => {
+                    if let Some(tx) = self.pending.lock().unwrap().remove(&id) {
+                        tx.send(result).ok();
+                    }
+                }
+                Ok(Message::Notification(notification)) => {
+                    if notifications.send(notification).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    log::warn!("Invalid message from Electrum server: {}", err);
+                }
+            }
+        }
+        // Fail any requests still waiting for a response.
+        self.pending.lock().unwrap().clear();
+    }
+}
+
+/// A command sent to the sync thread.
+#[derive(Debug)]
+enum Command {
+    /// Sync the given scripts from the given height.
+    Rescan { from: Height, watch: Vec<Script> },
+    /// Start syncing the given scripts.
+    Watch(Vec<Script>),
+}
+
+/// Syncs the history of watched scripts, turning it into wallet events.
+struct Sync {
+    addr: net::SocketAddr,
+    conn: Connection,
+    /// Notifications received on the current connection.
+    notifications: chan::Receiver<Notification>,
+    events: chan::Sender<Event>,
+    /// Height from which transactions are synced.
+    from: Height,
+    /// Current chain tip.
+    tip: (Height, BlockHash),
+    /// Watched script hashes.
+    scripts: HashSet<String>,
+    /// Transactions of ours, along with the height of the block including them, if any.
+    seen: HashMap<Txid, Option<Height>>,
+}
+
+impl Sync {
+    /// Sync until the backend is dropped or shut down, reconnecting whenever the connection
+    /// to the server is lost.
+    fn run(mut self, commands: chan::Receiver<Command>) {
+        let mut result = self.start().and_then(|()| self.process(&commands));
+        let mut delay = RECONNECT_DELAY;
+
+        loop {
+            if self.conn.is_closed() {
+                return;
+            }
+            match result {
+                Ok(()) => return,
+                Err(err) => log::error!("Electrum sync failed: {}", err),
+            }
+            if self.events.send(Event::Disconnected).is_err() {
+                return;
+            }
+            result = loop {
+                if !self.wait(&commands, delay) || self.conn.is_closed() {
+                    return;
+                }
+                log::info!("Reconnecting to Electrum server {}..", self.addr);
+
+                match self.reconnect() {
+                    Ok(tip) => {
+                        delay = RECONNECT_DELAY;
+                        break self.resume(tip).and_then(|()| self.process(&commands));
+                    }
+                    Err(err) => {
+                        log::warn!("Failed to reconnect to Electrum server: {}", err);
+                        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+                    }
+                }
+            };
+        }
+    }
+
+    /// Tell the wallet we're ready to sync.
+    fn start(&self) -> Result<(), Error> {
+        self.emit(Event::Ready { tip: self.tip.0 })?;
+        self.emit(Event::NetworkHeight { height: self.tip.0 })?;
+        self.emit(Event::TipUpdated { height: self.tip.0 })
+    }
+
+    /// Wait before reconnecting, keeping track of the commands received meanwhile. Their
+    /// scripts are subscribed to once reconnected. Returns `false` if the backend was dropped.
+    fn wait(&mut self, commands: &chan::Receiver<Command>, delay: Duration) -> bool {
+        let timeout = chan::after(delay);
+
+        loop {
+            chan::select! {
+                recv(commands) -> cmd => match cmd {
+                    Ok(Command::Rescan { from, watch }) => {
+                        self.from = from;
+                        self.seen.clear();
+                        self.scripts.extend(watch.iter().map(script_hash));
+                    }
+                    Ok(Command::Watch(watch)) => {
+                        self.scripts.extend(watch.iter().map(script_hash));
+                    }
+                    Err(_) => return false,
+                },
+                recv(timeout) -> _ => return true,
+            }
+        }
+    }
+
+    /// Reconnect to the server, and subscribe to its chain tip and to the watched scripts
+    /// again. Returns the server's chain tip.
+    fn reconnect(&mut self) -> Result<(Height, BlockHash), Error> {
+        // Make sure the previous connection's reader is done, so that it doesn't fail
+        // the requests made on the new connection.
+        self.conn.shutdown().ok();
+        while self.notifications.recv().is_ok() {}
+
+        self.notifications = self.conn.reopen(self.addr)?;
+        self.conn.handshake(self.addr)?;
+
+        let tip = parse_header(
+            &self
+                .conn
+                .request("blockchain.headers.subscribe", json!([]))?,
+        )?;
+        for script_hash in &self.scripts {
+            self.conn
+                .request("blockchain.scripthash.subscribe", json!([script_hash]))?;
+        }
+        Ok(tip)
+    }
+
+    /// Catch up with what happened while disconnected from the server.
+    fn resume(&mut self, (height, hash): (Height, BlockHash)) -> Result<(), Error> {
+        self.emit(Event::Ready { tip: height })?;
+        self.tip_updated(height, hash)?;
+        self.sync_all()?;
+        self.emit(Event::Synced { height: self.tip.0 })
+    }
+
+    /// Process commands and server notifications. Returns once the backend is dropped, or
+    /// with an error if the connection to the server is lost.
+    fn process(&mut self, commands: &chan::Receiver<Command>) -> Result<(), Error> {
+        let ping = chan::tick(PING_INTERVAL);
+        let notifications = self.notifications.clone();
+
+        loop {
+            chan::select! {
+                recv(commands) -> cmd => match cmd {
+                    Ok(Command::Rescan { from, watch }) => {
+                        self.rescan(from, watch)?;
+                    }
+                    Ok(Command::Watch(watch)) => {
+                        for script_hash in self.subscribe(watch)? {
+                            self.sync(&script_hash)?;
+                        }
+                    }
+                    Err(_) => return Ok(()),
+                },
+                recv(notifications) -> notification => match notification {
+                    Ok(Notification::Header { height, hash }) => {
+                        self.tip_updated(height, hash)?;
+                    }
+                    Ok(Notification::Status { script_hash }) => {
+                        self.sync(&script_hash)?;
+                    }
+                    Err(_) => return Err(Error::Disconnected),
+                },
+                recv(ping) -> _ => {
+                    self.conn.request("server.ping", json!([]))?;
+                    continue;
+                }
+            }
+            self.emit(Event::Synced { height: self.tip.0 })?;
+        }
+    }
+
+    /// Send an event to the wallet.
+    fn emit(&self, event: Event) -> Result<(), Error> {
+        self.events.send(event).map_err(|_| Error::Disconnected)
+    }
+
+    /// Subscribe to the given scripts. Returns the hashes of the scripts that weren't
+    /// watched yet.
+    fn subscribe(&mut self, watch: Vec<Script>) -> Result<Vec<String>, Error> {
+        let mut subscribed = Vec::new();
+
+        for script in watch {
+            let script_hash = script_hash(&script);
+
+            if self.scripts.insert(script_hash.clone()) {
+                self.conn
+                    .request("blockchain.scripthash.subscribe", json!([script_hash]))?;
+                subscribed.push(script_hash);
+            }
+        }
+        Ok(subscribed)
+    }
+
+    /// Sync the given scripts from the given height, along with all scripts already watched.
+    /// What was synced so far is forgotten, so that their history is fetched again.
+    fn rescan(&mut self, from: Height, watch: Vec<Script>) -> Result<(), Error> {
+        self.from = from;
+        self.seen.clear();
+        self.subscribe(watch)?;
+        self.sync_all()
+    }
+
+    /// Sync the history of all watched scripts.
+    fn sync_all(&mut self) -> Result<(), Error> {
+        for script_hash in self.scripts.clone() {
+            self.sync(&script_hash)?;
+        }
+        Ok(())
+    }
+
+    /// Handle a new chain tip.
+    ///
+    /// The server only tells us about the new tip, so if it doesn't extend the previous one,
+    /// blocks are disconnected down to its height, and all scripts are synced again.
+    fn tip_updated(&mut self, height: Height, hash: BlockHash) -> Result<(), Error> {
+        if (height, hash) == self.tip {
+            return Ok(());
+        }
+        let reorg = height <= self.tip.0;
+
+        self.tip = (height, hash);
+        self.emit(Event::NetworkHeight { height })?;
+        self.emit(Event::TipUpdated { height })?;
+
+        if reorg {
+            self.disconnect(height)?;
+        }
+        Ok(())
+    }
+
+    /// Disconnect blocks from the given height, and sync all scripts again, so that
+    /// transactions still in the chain are re-confirmed.
+    fn disconnect(&mut self, height: Height) -> Result<(), Error> {
+        log::info!("Disconnecting blocks from height #{}", height);
+
+        self.emit(Event::BlockDisconnected { height })?;
+
+        for h in self.seen.values_mut() {
+            if matches!(h, Some(h) if *h >= height) {
+                *h = None;
+            }
+        }
+        self.sync_all()
+    }
+
+    /// Sync the history of a script, emitting events for any changes since the last sync.
+    fn sync(&mut self, script_hash: &str) -> Result<(), Error> {
+        let history = parse_history(
+            &self
+                .conn
+                .request("blockchain.scripthash.get_history", json!([script_hash]))?,
+        )?;
+        // If a transaction we had in a block moved, the chain was reorganized.
+        let reorg = history
+            .iter()
+            .filter_map(|(txid, height)| match self.seen.get(txid) {
+                Some(Some(h)) if Some(*h) != *height => Some(height.map_or(*h, |x| x.min(*h))),
+                _ => None,
+            })
+            .min();
+        if let Some(height) = reorg {
+            return self.disconnect(height);
+        }
+
+        let mut blocks = BTreeMap::<Height, Vec<Transaction>>::new();
+        let mut confirmed = BTreeMap::<Height, Vec<Txid>>::new();
+        let mut unconfirmed = Vec::new();
+
+        for (txid, height) in history {
+            if matches!(height, Some(h) if h < self.from) {
+                continue;
+            }
+            match self.seen.get(&txid) {
+                Some(seen) if *seen == height => continue,
+                Some(_) => {
+                    if let Some(h) = height {
+                        confirmed.entry(h).or_default().push(txid);
+                    }
+                }
+                None => {
+                    let tx = self.transaction(&txid)?;
+
+                    match height {
+                        Some(h) => blocks.entry(h).or_default().push(tx),
+                        None => unconfirmed.push(tx),
+                    }
+                }
+            }
+            self.seen.insert(txid, height);
+        }
+
+        for (height, txids) in confirmed {
+            self.emit(Event::Confirmed { height, txids })?;
+        }
+        for (height, transactions) in blocks {
+            self.emit(Event::Block {
+                height,
+                transactions,
+            })?;
+        }
+        for transaction in unconfirmed {
+            self.emit(Event::Transaction {
+                transaction,
+                height: None,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Fetch a transaction from the server.
+    fn transaction(&self, txid: &Txid) -> Result<Transaction, Error> {
+        let raw = self
+            .conn
+            .request("blockchain.transaction.get", json!([txid.to_hex()]))?;
+
+        encode::deserialize(&parse_hex(&raw, "transaction")?).map_err(Error::from)
+    }
+}
+
+/// Backend syncing from an Electrum server.
+#[derive(Debug, Clone)]
+pub struct Electrum {
+    conn: Connection,
+    commands: chan::Sender<Command>,
+}
+
+impl Electrum {
+    /// Connect to an Electrum server, and subscribe to its chain tip. Returns the backend,
+    /// along with the channel its events are sent on.
+    pub fn connect(addr: net::SocketAddr) -> Result<(Self, chan::Receiver<Event>), Error> {
+        let (conn, notifications) = Connection::open(addr)?;
+        let (commands_tx, commands_rx) = chan::unbounded();
+        let (events_tx, events_rx) = chan::unbounded();
+
+        let tip = parse_header(&conn.request("blockchain.headers.subscribe", json!([]))?)?;
+        let sync = Sync {
+            addr,
+            conn: conn.clone(),
+            notifications,
+            events: events_tx,
+            from: 0,
+            tip,
+            scripts: HashSet::new(),
+            seen: HashMap::new(),
+        };
+        thread::spawn(move || sync.run(commands_rx));
+
+        Ok((
+            Self {
+                conn,
+                commands: commands_tx,
+            },
+            events_rx,
+        ))
+    }
+
+    /// Disconnect from the server, without reconnecting.
+    pub fn shutdown(&self) -> Result<(), Error> {
+        self.conn.closed.store(true, Ordering::SeqCst);
+        self.conn.shutdown()
+    }
+
+    /// Send a command to the sync thread.
+    fn command(&self, cmd: Command) -> Result<(), Error> {
+        self.commands.send(cmd).map_err(|_| Error::Disconnected)
+    }
+}
+
+impl Backend for Electrum {
+    fn rescan(&self, from: Height, watch: Vec<Script>) -> Result<(), super::Error> {
+        self.command(Command::Rescan { from, watch })?;
+
+        Ok(())
+    }
+
+    /// Subscribe to the derived addresses. Their full history is fetched, so there's
+    /// nothing to re-scan.
+    fn watch(
+        &self,
+        derived: &[Address],
+        _watch: &[Address],
+        _pubkeys: &[PublicKey],
+        _outpoints: &[OutPoint],
+        _rescan_from: Option<Height>,
+    ) -> Result<(), super::Error> {
+        self.command(Command::Watch(
+            derived.iter().map(|addr| addr.script_pubkey()).collect(),
+        ))?;
+
+        Ok(())
+    }
+
+    fn submit_transaction(&self, tx: Transaction) -> Result<usize, super::Error> {
+        self.conn.request(
+            "blockchain.transaction.broadcast",
+            json!([encode::serialize_hex(&tx)]),
+        )?;
+
+        Ok(1)
+    }
+
+    /// Estimate the fee rate using `blockchain.estimatefee`, which returns a rate in coins
+    /// per kilobyte, or `-1` if the server has no estimate.
+    fn estimate_fee(&self, blocks: usize) -> Result<Option<FeeRate>, super::Error> {
+        let rate = self
+            .conn
+            .request("blockchain.estimatefee", json!([blocks]))?
+            .as_f64()
+            .ok_or(Error::Decode("fee"))?;
+
+        if rate <= 0. {
+            return Ok(None);
+        }
+        let rate = (rate * 100_000.).ceil() as FeeRate;
+
+        Ok(Some(rate.max(MIN_FEE_RATE)))
+    }
+
+    fn height_at(&self, _time: BlockTime) -> Result<Height, super::Error> {
+        Err(super::Error::Unsupported("looking up blocks by date"))
+    }
+
+    fn import_snapshot(&self, _snapshot: Snapshot) -> Result<Height, super::Error> {
+        Err(super::Error::Unsupported("snapshot import"))
+    }
+
+    fn peers(&self) -> Result<Vec<Peer>, super::Error> {
+        // We sync from a single server, not from peers.
+        Ok(Vec::new())
+    }
+
+    fn peer_stats(&self) -> Result<Vec<PeerStats>, super::Error> {
+        Ok(Vec::new())
+    }
+
+    fn disconnect(&self, _addr: PeerId) -> Result<(), super::Error> {
+        Err(super::Error::Unsupported("disconnecting peers"))
+    }
+
+    fn ban(&self, _addr: PeerId) -> Result<(), super::Error> {
+        Err(super::Error::Unsupported("banning peers"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nakamoto_common::network::Network;
+
+    #[test]
+    fn test_script_hash() {
+        // Example from the Electrum protocol documentation, for the genesis block address.
+        let script =
+            Script::from_hex("76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac").unwrap();
+
+        assert_eq!(
+            script_hash(&script),
+            "8b01df4e368ea28f8dc0423bcf7a4923e3a12d307c875e47a0cfbf90b5c39161"
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        let msg = parse(r#"{"jsonrpc": "2.0", "id": 3, "result": "pong"}"#).unwrap();
+        assert!(matches!(msg, Message::Response { id: 3, result: Ok(v) } if v == "pong"));
+
+        let msg = parse(r#"{"id": 4, "error": {"code": 1, "message": "bad tx"}}"#).unwrap();
+        assert!(matches!(
+            msg,
+            Message::Response { id: 4, result: Err(Error::Server(e)) } if e == "bad tx"
+        ));
+
+        let header = Network::Regtest.genesis();
+        let msg = parse(
+            &json!({
+                "method": "blockchain.headers.subscribe",
+                "params": [{"height": 7, "hex": encode::serialize_hex(&header)}],
+            })
+            .to_string(),
+        )
+        .unwrap();
+        assert!(matches!(
+            msg,
+            Message::Notification(Notification::Header { height: 7, hash })
+                if hash == header.block_hash()
+        ));
+
+        let msg =
+            parse(r#"{"method": "blockchain.scripthash.subscribe", "params": ["8b01", "f00d"]}"#)
+                .unwrap();
+        assert!(matches!(
+            msg,
+            Message::Notification(Notification::Status { script_hash }) if script_hash == "8b01"
+        ));
+
+        assert!(parse(r#"{"method": "unknown"}"#).is_err());
+        assert!(parse("not json").is_err());
+    }
+
+    #[test]
+    fn test_parse_history() {
+        let txid =
+            Txid::from_hex("68f7e1a4ea8f8dd4bc47b1e3a7c8e2f1a2cb4e3b1e8c1bc8e8d1a1e2c6b5a4d3")
+                .unwrap();
+        let history = parse_history(&json!([
+            {"tx_hash": txid.to_hex(), "height": 200004},
+            {"tx_hash": txid.to_hex(), "height": 0, "fee": 225},
+            {"tx_hash": txid.to_hex(), "height": -1, "fee": 225},
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            history,
+            vec![(txid, Some(200004)), (txid, None), (txid, None)]
+        );
+        assert!(parse_history(&json!([{"tx_hash": "00", "height": 1}])).is_err());
+        assert!(parse_history(&json!({})).is_err());
+    }
+
+    /// Serve a client as a server with a single confirmed transaction for its script, until
+    /// the client disconnects, or until the given number of requests was served.
+    fn serve(stream: net::TcpStream, tx: &Transaction, limit: usize) {
+        let header = Network::Regtest.genesis();
+        let mut writer = stream.try_clone().unwrap();
+
+        for line in BufReader::new(stream).lines().take(limit) {
+            let request: Value = serde_json::from_str(&line.unwrap()).unwrap();
+            let result = match request["method"].as_str().unwrap() {
+                "server.version" => json!(["Fulcrum 1.9", "1.4"]),
+                "blockchain.headers.subscribe" => json!({
+                    "height": 10,
+                    "hex": encode::serialize_hex(&header),
+                }),
+                "blockchain.scripthash.subscribe" => json!("f00d"),
+                "blockchain.scripthash.get_history" => json!([
+                    {"tx_hash": tx.txid().to_hex(), "height": 5},
+                ]),
+                "blockchain.transaction.get" => json!(encode::serialize_hex(tx)),
+                method => panic!("unexpected method {}", method),
+            };
+            let response = json!({"jsonrpc": "2.0", "id": request["id"], "result": result});
+
+            writeln!(writer, "{}", response).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_sync() {
+        let listener = net::TcpListener::bind(([127, 0, 0, 1], 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let tx = Network::Regtest.genesis_block().txdata[0].clone();
+        let script = tx.output[0].script_pubkey.clone();
+
+        let server = thread::spawn({
+            let tx = tx.clone();
+            move || serve(listener.accept().unwrap().0, &tx, usize::MAX)
+        });
+
+        let (electrum, events) = Electrum::connect(addr).unwrap();
+        electrum.rescan(1, vec![script.clone()]).unwrap();
+
+        assert!(matches!(events.recv().unwrap(), Event::Ready { tip: 10 }));
+        assert!(matches!(
+            events.recv().unwrap(),
+            Event::NetworkHeight { height: 10 }
+        ));
+        assert!(matches!(
+            events.recv().unwrap(),
+            Event::TipUpdated { height: 10 }
+        ));
+        assert!(matches!(
+            events.recv().unwrap(),
+            Event::Block { height: 5, transactions } if transactions == vec![tx]
+        ));
+        assert!(matches!(
+            events.recv().unwrap(),
+            Event::Synced { height: 10 }
+        ));
+
+        // Watching the same script again doesn't yield any transactions.
+        electrum
+            .command(Command::Watch(vec![script.clone()]))
+            .unwrap();
+        assert!(matches!(
+            events.recv().unwrap(),
+            Event::Synced { height: 10 }
+        ));
+
+        // Rescanning fetches the history of the script again.
+        electrum.rescan(1, vec![script]).unwrap();
+        assert!(matches!(
+            events.recv().unwrap(),
+            Event::Block { height: 5, transactions } if transactions == vec![tx]
+        ));
+        assert!(matches!(
+            events.recv().unwrap(),
+            Event::Synced { height: 10 }
+        ));
+
+        electrum.shutdown().unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let listener = net::TcpListener::bind(([127, 0, 0, 1], 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let tx = Network::Regtest.genesis_block().txdata[0].clone();
+        let script = tx.output[0].script_pubkey.clone();
+
+        // The server drops the first connection once the script is synced.
+        let server = thread::spawn({
+            let tx = tx.clone();
+            move || {
+                serve(listener.accept().unwrap().0, &tx, 5);
+                serve(listener.accept().unwrap().0, &tx, usize::MAX);
+            }
+        });
+
+        let (electrum, events) = Electrum::connect(addr).unwrap();
+        electrum.rescan(1, vec![script]).unwrap();
+
+        assert!(matches!(events.recv().unwrap(), Event::Ready { tip: 10 }));
+        assert!(matches!(
+            events.recv().unwrap(),
+            Event::NetworkHeight { height: 10 }
+        ));
+        assert!(matches!(
+            events.recv().unwrap(),
+            Event::TipUpdated { height: 10 }
+        ));
+        assert!(matches!(
+            events.recv().unwrap(),
+            Event::Block { height: 5, .. }
+        ));
+        assert!(matches!(
+            events.recv().unwrap(),
+            Event::Synced { height: 10 }
+        ));
+        assert!(matches!(events.recv().unwrap(), Event::Disconnected));
+
+        // Once reconnected, the script is synced again, without yielding known transactions.
+        assert!(matches!(events.recv().unwrap(), Event::Ready { tip: 10 }));
+        assert!(matches!(
+            events.recv().unwrap(),
+            Event::Synced { height: 10 }
+        ));
+
+        electrum.shutdown().unwrap();
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,295 @@
+//! Peer-to-peer backend, scanning blocks for our transactions using BIP37 bloom filters.
+//!
+//! Optionally, BIP157/158 compact block filters are preferred when connected to peers serving
+//! them: filters are matched locally, which doesn't reveal our addresses to peers.
+use crossbeam_channel as chan;
+
+use nakamoto_client as client;
+use nakamoto_client::handle::Handle;
+use nakamoto_common::bitcoin::network::constants::ServiceFlags;
+use nakamoto_common::bitcoin::network::message::NetworkMessage;
+use nakamoto_common::bitcoin::network::message_bloom::BloomFlags;
+use nakamoto_common::bitcoin::{Address, OutPoint, PublicKey, Script, Transaction};
+use nakamoto_common::block::snapshot::Snapshot;
+use nakamoto_common::block::{BlockTime, Height};
+use nakamoto_p2p::fsm::fees::FeeRate;
+use nakamoto_p2p::fsm::{GetFiltersError, Peer, PeerId, PeerStats};
+
+use super::{Backend, Error, Event};
+use crate::wallet::bloom;
+
+/// Backend syncing from the peer-to-peer network, through a client handle.
+#[derive(Debug, Clone)]
+pub struct P2p<H> {
+    handle: H,
+    compact_filters: bool,
+}
+
+impl<H: Handle> P2p<H> {
+    /// Create a new peer-to-peer backend.
+    pub fn new(handle: H) -> Self {
+        Self {
+            handle,
+            compact_filters: false,
+        }
+    }
+
+    /// Prefer scanning with compact block filters over bloom filters, when connected to
+    /// peers serving them.
+    pub fn with_compact_filters(mut self, compact_filters: bool) -> Self {
+        self.compact_filters = compact_filters;
+        self
+    }
+
+    /// Whether we scan with compact block filters, ie. they're preferred and served by a
+    /// connected peer.
+    fn has_filter_peers(&self) -> Result<bool, Error> {
+        if !self.compact_filters {
+            return Ok(false);
+        }
+        let peers = self.handle.get_peers(ServiceFlags::COMPACT_FILTERS)?;
+
+        Ok(!peers.is_empty())
+    }
+
+    /// Rescan with compact block filters, if they're preferred. Returns `false` if the rescan
+    /// wasn't started, because no connected peer serves them.
+    fn rescan_cf(&self, from: Height, watch: impl Iterator<Item = Script>) -> Result<bool, Error> {
+        if !self.compact_filters {
+            return Ok(false);
+        }
+        match self.handle.rescan_cf(from.., watch) {
+            Ok(()) => Ok(true),
+            Err(client::handle::Error::GetFilters(GetFiltersError::NotConnected)) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl<H: Handle> Backend for P2p<H> {
+    /// Rescan with compact block filters if preferred and served by a peer. Otherwise, the
+    /// rescan starts once such a peer connects, and blocks are scanned with bloom filters
+    /// meanwhile.
+    fn rescan(&self, from: Height, watch: Vec<Script>) -> Result<(), Error> {
+        if self.rescan_cf(from, watch.iter().cloned())? {
+            return Ok(());
+        }
+        if self.compact_filters {
+            log::info!("No peer serves compact filters, scanning with bloom filters..");
+        }
+        self.handle.rescan(from.., watch.into_iter())?;
+
+        Ok(())
+    }
+
+    /// Watch the derived addresses, and rebuild the peers' bloom filters from the whole
+    /// watch set, since they don't match the new addresses. When scanning with compact
+    /// block filters, bloom filters are left as they are.
+    fn watch(
+        &self,
+        derived: &[Address],
+        watch: &[Address],
+        pubkeys: &[PublicKey],
+        outpoints: &[OutPoint],
+        rescan_from: Option<Height>,
+    ) -> Result<(), Error> {
+        self.handle
+            .watch(derived.iter().map(|addr| addr.script_pubkey()))?;
+
+        let filtered = match rescan_from {
+            Some(height) => self.rescan_cf(height, watch.iter().map(|a| a.script_pubkey()))?,
+            None => self.has_filter_peers()?,
+        };
+        if filtered {
+            return Ok(());
+        }
+        // Rebuilt filters are updated by peers like the wallet's segments, see [`bloom::segments`].
+        let elements = bloom::elements(watch, pubkeys, outpoints);
+        self.handle
+            .update_bloom_filter(elements, BloomFlags::PubkeyOnly, rescan_from)?;
+
+        Ok(())
+    }
+
+    fn submit_transaction(&self, tx: Transaction) -> Result<usize, Error> {
+        let peers = self.handle.submit_transaction(tx)?;
+
+        Ok(peers.len())
+    }
+
+    fn estimate_fee(&self, blocks: usize) -> Result<Option<FeeRate>, Error> {
+        self.handle.estimate_fee(blocks).map_err(Error::from)
+    }
+
+    fn height_at(&self, time: BlockTime) -> Result<Height, Error> {
+        let (tx, rx) = chan::bounded(1);
+
+        self.handle.query_tree(move |tree| {
+            tx.send(tree.find_by_time(time).unwrap_or(tree.height() + 1))
+                .ok();
+        })?;
+        let height = rx.recv().map_err(client::handle::Error::from)?;
+
+        Ok(height)
+    }
+
+    fn import_snapshot(&self, snapshot: Snapshot) -> Result<Height, Error> {
+        let height = self.handle.import_snapshot(snapshot)??;
+
+        Ok(height)
+    }
+
+    fn peers(&self) -> Result<Vec<Peer>, Error> {
+        self.handle
+            .get_peers(ServiceFlags::NONE)
+            .map_err(Error::from)
+    }
+
+    fn peer_stats(&self) -> Result<Vec<PeerStats>, Error> {
+        self.handle.peer_stats().map_err(Error::from)
+    }
+
+    fn disconnect(&self, addr: PeerId) -> Result<(), Error> {
+        self.handle.disconnect(addr).map_err(Error::from)
+    }
+
+    fn ban(&self, addr: PeerId) -> Result<(), Error> {
+        self.handle.ban(addr).map_err(Error::from)
+    }
+}
+
+/// Translate a client event into a wallet event, if it's relevant to the wallet.
+pub fn translate(event: client::Event) -> Option<Event> {
+    match event {
+        client::Event::Ready { tip, .. } => Some(Event::Ready { tip }),
+        client::Event::FeeEstimated { .. } => Some(Event::FeeEstimateUpdated),
+        client::Event::MessageReceived { message, .. }
+            if matches!(*message, NetworkMessage::FeeFilter(_)) =>
+        {
+            Some(Event::FeeEstimateUpdated)
+        }
+        client::Event::PeerNegotiated { relay: true, .. } => Some(Event::PeerConnected),
+        client::Event::PeerHeightUpdated { height } => Some(Event::NetworkHeight { height }),
+        client::Event::Synchronizing {
+            current,
+            best,
+            rate_headers_per_sec,
+        } => Some(Event::Synchronizing {
+            current,
+            best,
+            rate_headers_per_sec,
+        }),
+        client::Event::FilterProcessed { height, .. } => Some(Event::Processed { height }),
+        client::Event::BlockHeadersImported { height, .. } => Some(Event::TipUpdated { height }),
+        client::Event::BlockDisconnected { height, .. } => {
+            Some(Event::BlockDisconnected { height })
+        }
+        client::Event::BlockMatched { block, height } => Some(Event::Block {
+            height,
+            transactions: block.txdata,
+        }),
+        client::Event::ReceivedMerkleBlock {
+            height, matches, ..
+        } => Some(Event::Confirmed {
+            height,
+            txids: matches,
+        }),
+        client::Event::ReceivedMatchedTx {
+            transaction,
+            inclusion,
+        } => Some(Event::Transaction {
+            transaction,
+            height: inclusion.map(|i| i.height),
+        }),
+        client::Event::DoubleSpendDetected {
+            original,
+            conflicting,
+        } => Some(Event::DoubleSpend {
+            original,
+            conflicting,
+        }),
+        client::Event::DoubleSpendProofReceived { txid, proof, .. } => {
+            Some(Event::DoubleSpendProof { txid, proof })
+        }
+        client::Event::Scanned { height, .. } => Some(Event::Synced { height }),
+        client::Event::BloomFilterResized {
+            fp_rate, target, ..
+        } => Some(Event::FilterResized { fp_rate, target }),
+        _ => None,
+    }
+}
+
+/// Forward client events to the wallet, until either side goes away.
+pub fn forward(events: chan::Receiver<client::Event>, wallet: chan::Sender<Event>) {
+    for event in events {
+        log::debug!("Received event: {}", event);
+
+        if let Some(event) = translate(event) {
+            if wallet.send(event).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use nakamoto_common::bitcoin::network::address;
+
+    #[test]
+    fn test_translate() {
+        let block = client::Network::Regtest.genesis_block();
+
+        assert!(matches!(
+            translate(client::Event::BlockMatched {
+                height: 7,
+                block: block.clone(),
+            }),
+            Some(Event::Block { height: 7, transactions }) if transactions == block.txdata
+        ));
+        assert!(matches!(
+            translate(client::Event::BlockDisconnected {
+                header: block.header,
+                height: 7,
+            }),
+            Some(Event::BlockDisconnected { height: 7 })
+        ));
+        assert!(matches!(
+            translate(client::Event::Scanned { height: 9 }),
+            Some(Event::Synced { height: 9 })
+        ));
+        assert!(matches!(
+            translate(client::Event::Synchronizing {
+                current: 7,
+                best: 9,
+                rate_headers_per_sec: 2.,
+            }),
+            Some(Event::Synchronizing {
+                current: 7,
+                best: 9,
+                ..
+            })
+        ));
+        assert!(matches!(
+            translate(client::Event::PeerNegotiated {
+                addr: ([88, 88, 88, 88], 8333).into(),
+                link: client::Link::Outbound,
+                services: ServiceFlags::NETWORK,
+                persistent: false,
+                height: 9,
+                receiver: address::Address::new(&([0, 0, 0, 0], 0).into(), ServiceFlags::NONE),
+                user_agent: String::new(),
+                version: 70016,
+                relay: true,
+            }),
+            Some(Event::PeerConnected)
+        ));
+        assert!(translate(client::Event::BlockHeadersSynced {
+            height: 9,
+            hash: block.block_hash(),
+        })
+        .is_none());
+    }
+}
@@ -0,0 +1,342 @@
+//! Token metadata, from Bitcoin Cash Metadata Registries (BCMR).
+//!
+//! A registry is a JSON document describing token categories: their name, ticker symbol,
+//! number of decimals and icon. Registries are fetched from the URLs configured by the user,
+//! and from the publications found in transactions of ours, ie. outputs of the form
+//! `OP_RETURN <"BCMR"> <sha256> <uri>..`, whose registries are only accepted if their hash
+//! matches. Following the authchain of tokens we merely hold, to their latest publication,
+//! would require an indexer, so those are only described by the configured registries.
+//!
+//! Registries are fetched with `curl`, on a dedicated thread, since HTTPS isn't otherwise
+//! supported. The metadata found is cached in the wallet database.
+use std::io;
+use std::process::{self, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use crossbeam_channel as chan;
+use serde_json::Value;
+use thiserror::Error;
+
+use nakamoto_common::bitcoin::blockdata::opcodes;
+use nakamoto_common::bitcoin::blockdata::script::Instruction;
+use nakamoto_common::bitcoin::hashes::{sha256, Hash};
+use nakamoto_common::bitcoin::{Script, TokenID};
+
+/// Identifier of registry publications, pushed after `OP_RETURN`.
+pub const PROTOCOL: &[u8] = b"BCMR";
+/// Maximum size of a registry, in bytes.
+pub const MAX_REGISTRY_SIZE: usize = 4 * 1024 * 1024;
+/// Time to wait for a registry to be fetched.
+pub const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A registry error.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+    #[error("fetching registry failed with {0}")]
+    Fetch(process::ExitStatus),
+    #[error("unsupported registry uri `{0}`")]
+    Uri(String),
+    #[error("invalid registry: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("registry hash {found} doesn't match published hash {expected}")]
+    Hash {
+        expected: sha256::Hash,
+        found: sha256::Hash,
+    },
+}
+
+/// Metadata of a token category.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenMetadata {
+    /// Token name.
+    pub name: String,
+    /// Ticker symbol, if any.
+    pub symbol: Option<String>,
+    /// Number of decimal places fungible amounts are shown with.
+    pub decimals: u8,
+    /// Icon URI, if any.
+    pub icon: Option<String>,
+}
+
+impl TokenMetadata {
+    /// Format a fungible token amount, in the token's units.
+    pub fn amount(&self, amount: u64) -> String {
+        let decimals = self.decimals as usize;
+        let digits = format!("{:0>width$}", amount, width = decimals + 1);
+        let (units, fraction) = digits.split_at(digits.len() - decimals);
+        let mut s = if fraction.is_empty() {
+            units.to_owned()
+        } else {
+            format!("{}.{}", units, fraction)
+        };
+        if let Some(symbol) = &self.symbol {
+            s.push(' ');
+            s.push_str(symbol);
+        }
+        s
+    }
+}
+
+/// A registry to fetch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Source {
+    /// Registry URI.
+    pub uri: String,
+    /// Hash the registry must have, if it was published on-chain.
+    pub hash: Option<sha256::Hash>,
+}
+
+impl Source {
+    /// Parse a registry publication, returning the registry's sources, one per URI it's
+    /// published at. URIs without a scheme are fetched over HTTPS, as per the specification.
+    pub fn parse(script: &Script) -> Option<Vec<Self>> {
+        let mut instructions = script.instructions();
+
+        match instructions.next()? {
+            Ok(Instruction::Op(op)) if op == opcodes::all::OP_RETURN => {}
+            _ => return None,
+        }
+        match instructions.next()? {
+            Ok(Instruction::PushBytes(PROTOCOL)) => {}
+            _ => return None,
+        }
+        let hash = match instructions.next()? {
+            Ok(Instruction::PushBytes(bytes)) => sha256::Hash::from_slice(bytes).ok()?,
+            _ => return None,
+        };
+        let mut sources = Vec::new();
+
+        for instruction in instructions {
+            let Ok(Instruction::PushBytes(bytes)) = instruction else {
+                return None;
+            };
+            let uri = String::from_utf8(bytes.to_vec()).ok()?;
+            let uri = if uri.contains("://") {
+                uri
+            } else {
+                format!("https://{}", uri)
+            };
+            sources.push(Self {
+                uri,
+                hash: Some(hash),
+            });
+        }
+        Some(sources)
+    }
+}
+
+/// Parse a registry, returning the metadata of the token categories it describes, as of
+/// each identity's latest snapshot. Identities that aren't tokens are skipped.
+pub fn parse(registry: &[u8]) -> Result<Vec<(TokenID, TokenMetadata)>, Error> {
+    let registry: Value = serde_json::from_slice(registry)?;
+    let mut tokens = Vec::new();
+
+    let Some(identities) = registry["identities"].as_object() else {
+        return Ok(tokens);
+    };
+    for history in identities.values().filter_map(|h| h.as_object()) {
+        // Snapshots are keyed by ISO 8601 timestamps, which sort chronologically.
+        let Some((_, snapshot)) = history.iter().max_by(|(a, _), (b, _)| a.cmp(b)) else {
+            continue;
+        };
+        let token = &snapshot["token"];
+        let Some(id) = token["category"]
+            .as_str()
+            .and_then(|c| c.parse::<TokenID>().ok())
+        else {
+            continue;
+        };
+        tokens.push((
+            id,
+            TokenMetadata {
+                name: snapshot["name"].as_str().unwrap_or_default().to_owned(),
+                symbol: token["symbol"].as_str().map(ToOwned::to_owned),
+                decimals: token["decimals"]
+                    .as_u64()
+                    .map_or(0, |d| d.min(u8::MAX as u64) as u8),
+                icon: snapshot["uris"]["icon"].as_str().map(ToOwned::to_owned),
+            },
+        ));
+    }
+    Ok(tokens)
+}
+
+/// Fetch a registry, and parse it.
+pub fn fetch(source: &Source) -> Result<Vec<(TokenID, TokenMetadata)>, Error> {
+    if !source.uri.starts_with("https://") && !source.uri.starts_with("http://") {
+        return Err(Error::Uri(source.uri.clone()));
+    }
+    let output = process::Command::new("curl")
+        .arg("--fail")
+        .arg("--silent")
+        .arg("--location")
+        .arg("--max-time")
+        .arg(FETCH_TIMEOUT.as_secs().to_string())
+        .arg("--max-filesize")
+        .arg(MAX_REGISTRY_SIZE.to_string())
+        .arg(&source.uri)
+        .stdin(Stdio::null())
+        .output()?;
+
+    if !output.status.success() {
+        return Err(Error::Fetch(output.status));
+    }
+    if let Some(expected) = source.hash {
+        let found = sha256::Hash::hash(&output.stdout);
+
+        if found != expected {
+            return Err(Error::Hash { expected, found });
+        }
+    }
+    self::parse(&output.stdout)
+}
+
+/// Fetches registries in the background.
+#[derive(Debug, Clone)]
+pub struct Registries {
+    sender: Option<chan::Sender<Source>>,
+    results: chan::Receiver<Vec<(TokenID, TokenMetadata)>>,
+}
+
+impl Default for Registries {
+    fn default() -> Self {
+        Self {
+            sender: None,
+            results: chan::never(),
+        }
+    }
+}
+
+impl Registries {
+    /// Start fetching registries in the background, beginning with the given ones.
+    pub fn spawn(uris: Vec<String>) -> Self {
+        let (sender, sources) = chan::unbounded::<Source>();
+        let (results_send, results) = chan::unbounded();
+
+        for uri in uris {
+            sender.send(Source { uri, hash: None }).ok();
+        }
+        thread::spawn(move || {
+            for source in sources {
+                match self::fetch(&source) {
+                    Ok(tokens) => {
+                        log::info!(
+                            "Fetched metadata of {} token(s) from {}",
+                            tokens.len(),
+                            source.uri
+                        );
+                        if results_send.send(tokens).is_err() {
+                            break;
+                        }
+                    }
+                    Err(err) => {
+                        log::warn!("Failed to fetch token registry {}: {}", source.uri, err);
+                    }
+                }
+            }
+        });
+
+        Self {
+            sender: Some(sender),
+            results,
+        }
+    }
+
+    /// Whether token metadata is fetched.
+    pub fn is_enabled(&self) -> bool {
+        self.sender.is_some()
+    }
+
+    /// Queue a registry to be fetched.
+    pub fn fetch(&self, source: Source) {
+        if let Some(sender) = &self.sender {
+            if sender.send(source).is_err() {
+                log::error!("Token registry fetching is no longer running");
+            }
+        }
+    }
+
+    /// The metadata fetched, one batch per registry.
+    pub fn results(&self) -> &chan::Receiver<Vec<(TokenID, TokenMetadata)>> {
+        &self.results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nakamoto_common::bitcoin::blockdata::script::Builder;
+
+    #[test]
+    fn test_parse_publication() {
+        let hash = sha256::Hash::hash(b"registry");
+        let script = Builder::new()
+            .push_opcode(opcodes::all::OP_RETURN)
+            .push_slice(PROTOCOL)
+            .push_slice(&hash[..])
+            .push_slice(b"example.com/bcmr.json")
+            .push_slice(b"ipfs://bafy")
+            .into_script();
+
+        assert_eq!(
+            Source::parse(&script).unwrap(),
+            vec![
+                Source {
+                    uri: String::from("https://example.com/bcmr.json"),
+                    hash: Some(hash),
+                },
+                Source {
+                    uri: String::from("ipfs://bafy"),
+                    hash: Some(hash),
+                },
+            ]
+        );
+        assert_eq!(Source::parse(&Script::new_op_return(b"memo")), None);
+    }
+
+    #[test]
+    fn test_parse_registry() {
+        let id = TokenID::from_inner([7; 32]);
+        let registry = format!(
+            r#"{{
+                "version": {{ "major": 2, "minor": 0, "patch": 0 }},
+                "identities": {{
+                    "{id}": {{
+                        "2023-01-01T00:00:00.000Z": {{
+                            "name": "Old",
+                            "token": {{ "category": "{id}", "symbol": "OLD", "decimals": 0 }}
+                        }},
+                        "2024-01-01T00:00:00.000Z": {{
+                            "name": "Example",
+                            "token": {{ "category": "{id}", "symbol": "XMPL", "decimals": 2 }},
+                            "uris": {{ "icon": "https://example.com/icon.svg" }}
+                        }}
+                    }},
+                    "not-a-token": {{
+                        "2024-01-01T00:00:00.000Z": {{ "name": "Org" }}
+                    }}
+                }}
+            }}"#
+        );
+        let tokens = parse(registry.as_bytes()).unwrap();
+
+        assert_eq!(
+            tokens,
+            vec![(
+                id,
+                TokenMetadata {
+                    name: String::from("Example"),
+                    symbol: Some(String::from("XMPL")),
+                    decimals: 2,
+                    icon: Some(String::from("https://example.com/icon.svg")),
+                }
+            )]
+        );
+        assert_eq!(tokens[0].1.amount(12345), "123.45 XMPL");
+        assert_eq!(tokens[0].1.amount(5), "0.05 XMPL");
+        assert!(parse(b"{").is_err());
+    }
+}
@@ -0,0 +1,141 @@
+//! Wallet birth, ie. where the chain starts being scanned for the wallet's transactions.
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, NaiveDate};
+
+use nakamoto_common::block::time::LocalTime;
+use nakamoto_common::block::{BlockTime, Height};
+
+use crate::wallet::backend::{self, Backend};
+use crate::wallet::db::{self, Db, Read, Write as _};
+
+/// How long before a birth date blocks are scanned from. Block timestamps only roughly
+/// increase with height, and a date given in the user's timezone can start up to a day
+/// before the same date in UTC.
+pub const BIRTH_DATE_MARGIN: BlockTime = 60 * 60 * 24;
+
+/// When the wallet was created: no transaction of ours is found before. Formatted the way
+/// it's parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Birth {
+    /// A block height.
+    Height(Height),
+    /// A date, looked up in the block headers.
+    Date(NaiveDate),
+}
+
+impl Birth {
+    /// Today's date in UTC, eg. for a new wallet.
+    pub fn today() -> Self {
+        let now = LocalTime::now().as_secs() as i64;
+
+        Self::Date(
+            DateTime::from_timestamp(now, 0)
+                .unwrap_or_default()
+                .date_naive(),
+        )
+    }
+
+    /// Time from which blocks are scanned, if born at a date.
+    pub fn time(&self) -> Option<BlockTime> {
+        match self {
+            Self::Height(_) => None,
+            Self::Date(date) => {
+                let midnight = date.and_hms_opt(0, 0, 0)?.and_utc().timestamp();
+
+                Some((midnight.max(0) as BlockTime).saturating_sub(BIRTH_DATE_MARGIN))
+            }
+        }
+    }
+
+    /// Load the birth stored in the wallet database, if any.
+    pub fn load<D: Read>(db: &D) -> Result<Option<Self>, db::Error> {
+        db.setting("birth")?
+            .map(|value| value.parse().map_err(|_| db::Error::Decoding("birth")))
+            .transpose()
+    }
+
+    /// Store the birth in the wallet database.
+    pub fn save(&self, db: &Db) -> Result<(), db::Error> {
+        db.set_setting("birth", &self.to_string())?;
+
+        Ok(())
+    }
+
+    /// Height of the first block to scan. Dates are looked up in the backend's block
+    /// headers; if no block is that recent yet, scanning starts with the next one.
+    pub fn height<B: Backend>(&self, backend: &B) -> Result<Height, backend::Error> {
+        match *self {
+            Self::Height(height) => Ok(height),
+            Self::Date(_) => backend.height_at(self.time().unwrap_or_default()),
+        }
+    }
+}
+
+impl fmt::Display for Birth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Height(height) => write!(f, "{}", height),
+            Self::Date(date) => write!(f, "{}", date.format("%Y-%m-%d")),
+        }
+    }
+}
+
+impl FromStr for Birth {
+    type Err = String;
+
+    /// Parse a block height, or a `YYYY-MM-DD` date.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(height) = s.parse() {
+            return Ok(Self::Height(height));
+        }
+        NaiveDate::parse_from_str(s, "%Y-%m-%d")
+            .map(Self::Date)
+            .map_err(|_| {
+                format!(
+                    "invalid birth `{}`, expected a height or a YYYY-MM-DD date",
+                    s
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_birth() {
+        assert_eq!("800000".parse::<Birth>(), Ok(Birth::Height(800000)));
+        assert_eq!(
+            "2023-05-15".parse::<Birth>(),
+            Ok(Birth::Date(NaiveDate::from_ymd_opt(2023, 5, 15).unwrap()))
+        );
+        assert!("2023-13-01".parse::<Birth>().is_err());
+        assert!("yesterday".parse::<Birth>().is_err());
+
+        // 2023-05-15T00:00:00Z, less a day.
+        assert_eq!(
+            "2023-05-15".parse::<Birth>().unwrap().time(),
+            Some(1684108800 - BIRTH_DATE_MARGIN)
+        );
+        assert_eq!(Birth::Height(800000).time(), None);
+        assert!(Birth::today().time().is_some());
+        assert_eq!(
+            "1970-01-01".parse::<Birth>().unwrap().time(),
+            Some(0),
+            "Times before the epoch are clamped"
+        );
+    }
+
+    #[test]
+    fn test_birth_save_load() {
+        let db = Db::memory().unwrap();
+        assert_eq!(Birth::load(&db).unwrap(), None);
+
+        for birth in [Birth::Height(800000), "2023-05-15".parse().unwrap()] {
+            birth.save(&db).unwrap();
+            assert_eq!(Birth::load(&db).unwrap(), Some(birth));
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! Bloom filter privacy segments built from the wallet's addresses.
+use std::collections::HashSet;
+
+use nakamoto_common::bitcoin::consensus::encode::serialize;
+use nakamoto_common::bitcoin::util::bloom::{BloomFilter, BLOOM_UPDATE_P2PUBKEY_ONLY};
+use nakamoto_common::bitcoin::{Address, OutPoint, PublicKey, Script, TxOut};
+use nakamoto_common::block::Height;
+use nakamoto_common::bloom::store::cache::PrivacySegment;
+use nakamoto_common::collections::HashMap;
+
+pub use nakamoto_common::bitcoin::util::bloom::Decoys;
+pub use nakamoto_common::bloom::store::cache::DEFAULT_FP_RATE;
+
+/// Default number of privacy segments.
+pub const DEFAULT_SEGMENTS: usize = 1;
+
+/// Split the given addresses into `count` privacy segments, each with its own
+/// bloom filter sized for the given false-positive rate, including decoys.
+///
+/// Along with its addresses, a segment's filter includes their public keys and unspent
+/// outputs among the ones given, so that transactions spending from them match too. Peers
+/// are asked to only add the outputs of pay-to-pubkey scripts to the filter as they match,
+/// since spends of our other outputs are matched by public key.
+///
+/// Segments that end up without any address are disabled.
+pub fn segments(
+    addresses: &[Address],
+    pubkeys: &[PublicKey],
+    utxos: &[(OutPoint, TxOut)],
+    count: usize,
+    fp_rate: f64,
+    decoys: Decoys,
+    birth: Height,
+    rng: fastrand::Rng,
+) -> HashMap<u32, PrivacySegment> {
+    let count = count.max(1);
+    let mut segments = HashMap::with_hasher(rng.clone().into());
+
+    for id in 0..count {
+        let scripts = addresses
+            .iter()
+            .skip(id)
+            .step_by(count)
+            .map(|a| a.script_pubkey())
+            .collect::<HashSet<_>>();
+        let pubkeys = pubkeys
+            .iter()
+            .filter(|pk| scripts.contains(&Script::new_p2pkh(&pk.pubkey_hash())))
+            .collect::<Vec<_>>();
+        let outpoints = utxos
+            .iter()
+            .filter(|(_, out)| scripts.contains(&out.script_pubkey))
+            .map(|(outpoint, _)| outpoint)
+            .collect::<Vec<_>>();
+
+        // Public keys are inserted along with their hash.
+        let elements = scripts.len() + pubkeys.len() * 2 + outpoints.len();
+        let decoys = decoys.count(elements);
+        let mut filter = BloomFilter::new(
+            elements + decoys,
+            fp_rate,
+            rng.u32(..),
+            BLOOM_UPDATE_P2PUBKEY_ONLY,
+        );
+        for script in &scripts {
+            filter.insert_script(script);
+        }
+        for pubkey in pubkeys {
+            filter.insert_pubkey(pubkey);
+        }
+        for outpoint in outpoints {
+            filter.insert_outpoint(outpoint);
+        }
+        filter.insert_decoys(decoys, rng.u64(..));
+
+        segments.insert(
+            id as u32,
+            PrivacySegment {
+                segment: id as u32,
+                filter,
+                birth,
+                synced_height: birth,
+                is_enabled: !scripts.is_empty(),
+            },
+        );
+    }
+    segments
+}
+
+/// The bloom filter elements matching payments to the given addresses, and spends from the
+/// given public keys and outputs.
+pub fn elements(
+    addresses: &[Address],
+    pubkeys: &[PublicKey],
+    outpoints: &[OutPoint],
+) -> Vec<Vec<u8>> {
+    addresses
+        .iter()
+        .map(|a| a.payload.as_bytes().to_vec())
+        .chain(pubkeys.iter().map(|pk| pk.to_bytes()))
+        .chain(outpoints.iter().map(serialize))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nakamoto_common::bitcoin::secp256k1::Secp256k1;
+    use nakamoto_common::bitcoin::{Network, PrivateKey};
+    use nakamoto_test::block::gen;
+
+    #[test]
+    fn test_segments() {
+        let mut rng = fastrand::Rng::new();
+        let addresses = (0..7)
+            .map(|_| Address::from_script(&gen::script(&mut rng), Network::Bitcoin).unwrap())
+            .collect::<Vec<_>>();
+        let segments = segments(
+            &addresses,
+            &[],
+            &[],
+            3,
+            DEFAULT_FP_RATE,
+            Decoys::None,
+            42,
+            rng,
+        );
+
+        assert_eq!(segments.len(), 3);
+
+        for (i, addr) in addresses.iter().enumerate() {
+            let segment = &segments[&((i % 3) as u32)];
+
+            assert!(segment.is_enabled);
+            assert_eq!(segment.birth, 42);
+            assert!(segment.filter.contains(addr.payload.as_bytes()));
+        }
+    }
+
+    #[test]
+    fn test_segments_spends() {
+        let mut rng = fastrand::Rng::new();
+        let secp = Secp256k1::new();
+        let pubkey = PrivateKey::from_slice(&[7; 32], Network::Bitcoin)
+            .unwrap()
+            .public_key(&secp);
+        let addr = Address::p2pkh(&pubkey, Network::Bitcoin);
+        let mine = OutPoint::new(gen::transaction(&mut rng).txid(), 0);
+        let utxos = [(
+            mine,
+            TxOut {
+                value: 1,
+                script_pubkey: addr.script_pubkey(),
+                token: None,
+            },
+        )];
+        let segments = segments(
+            &[addr],
+            &[pubkey],
+            &utxos,
+            1,
+            DEFAULT_FP_RATE,
+            Decoys::None,
+            0,
+            rng,
+        );
+        let filter = &segments[&0].filter;
+
+        assert_eq!(filter.flags, BLOOM_UPDATE_P2PUBKEY_ONLY);
+        // Inputs spending from the address push its public key.
+        assert!(filter.contains(&pubkey.to_bytes()));
+        assert!(filter.contains_outpoint(&mine));
+    }
+
+    #[test]
+    fn test_segments_empty() {
+        let segments = segments(
+            &[],
+            &[],
+            &[],
+            2,
+            DEFAULT_FP_RATE,
+            Decoys::Ratio(1.),
+            0,
+            fastrand::Rng::new(),
+        );
+
+        assert_eq!(segments.len(), 2);
+        assert!(segments.values().all(|s| !s.is_enabled));
+    }
+}
@@ -2,14 +2,24 @@ mod types;
 
 use std::path::Path;
 use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use nakamoto_common::bitcoin::blockdata::token::OutputData;
+use nakamoto_common::bitcoin::consensus::encode;
 use nakamoto_common::bitcoin::Address;
 use nakamoto_common::bitcoin::OutPoint;
+use nakamoto_common::bitcoin::TokenID;
+use nakamoto_common::bitcoin::Transaction;
 use nakamoto_common::bitcoin::TxOut;
 use nakamoto_common::bitcoin::Txid;
+use nakamoto_common::block::Height;
 
 use sqlite as sql;
 
+use crate::wallet::account::Account;
+use crate::wallet::bcmr::TokenMetadata;
+use crate::wallet::utxo;
+
 pub use types::*;
 
 #[derive(thiserror::Error, Debug)]
@@ -30,27 +40,101 @@ pub enum Error {
 pub trait Read {
     /// Get the wallet balance.
     fn balance(&self) -> Result<u64, Error>;
+    /// Get the wallet balance, split into confirmed and unconfirmed.
+    fn balances(&self) -> Result<utxo::Balance, Error>;
     /// Get a UTXO.
     fn utxo(&self, outpoint: &OutPoint) -> Result<Option<(OutPoint, TxOut)>, Error>;
     /// Get all UTXOs.
     fn utxos(&self) -> Result<Vec<(OutPoint, TxOut)>, Error>;
+    /// Get all UTXOs, along with their confirmation height.
+    fn unspent(&self) -> Result<Vec<utxo::Utxo>, Error>;
     /// Get all addresses.
     fn addresses(&self) -> Result<Vec<AddressRecord>, Error>;
+    /// Get the addresses derived for the given account.
+    fn account_addresses(&self, account: &str) -> Result<Vec<AddressRecord>, Error>;
+    /// Get all accounts.
+    fn accounts(&self) -> Result<Vec<Account>, Error>;
+    /// Get the transactions waiting to be broadcast, oldest first.
+    fn pending_broadcasts(&self) -> Result<Vec<Transaction>, Error>;
+    /// Get a transaction we sent, along with the fee it pays.
+    fn sent_transaction(&self, txid: &Txid) -> Result<Option<(Transaction, u64)>, Error>;
+    /// Get the height of the block including a transaction of ours. Returns `None` if the
+    /// transaction isn't known, and `Some(None)` if it's unconfirmed.
+    fn transaction_height(&self, txid: &Txid) -> Result<Option<Option<Height>>, Error>;
+    /// Get our transactions confirmed at the given height or above.
+    fn confirmed_since(&self, height: Height) -> Result<Vec<Txid>, Error>;
+    /// Get the contact with the given label.
+    fn contact(&self, label: &str) -> Result<Option<Contact>, Error>;
+    /// Get the contacts whose label or address contains the given text, ordered by label.
+    /// All contacts are returned if the text is empty.
+    fn contacts(&self, search: &str) -> Result<Vec<Contact>, Error>;
+    /// Get a wallet setting.
+    fn setting(&self, key: &str) -> Result<Option<String>, Error>;
+    /// Get the cached metadata of a token category.
+    fn token_metadata(&self, id: &TokenID) -> Result<Option<TokenMetadata>, Error>;
 }
 
 /// Write to the database.
 pub trait Write {
-    /// Add a UTXO. Returns `true` if it didn't exist.
-    fn add_utxo(&self, txid: Txid, vout: u32, address: Address, value: u64) -> Result<bool, Error>;
+    /// Add a UTXO, optionally carrying tokens, confirmed at the given height. Returns `true`
+    /// if it didn't exist.
+    fn add_utxo(
+        &self,
+        txid: Txid,
+        vout: u32,
+        address: Address,
+        value: u64,
+        token: Option<&OutputData>,
+        height: Option<Height>,
+    ) -> Result<bool, Error>;
     /// Remove a UTXO. Returns the removed UTXO.
     fn remove_utxo(&self, prev_out: &OutPoint) -> Result<Option<(OutPoint, TxOut)>, Error>;
-    /// Add an address we own.
+    /// Mark a UTXO as spent by the given transaction, confirmed at the given height.
+    /// Returns `true` if the UTXO was ours.
+    fn spend_utxo(
+        &self,
+        prev_out: &OutPoint,
+        txid: &Txid,
+        height: Option<Height>,
+    ) -> Result<bool, Error>;
+    /// Confirm a transaction at the given height, updating the outputs it creates and spends.
+    /// Returns `true` if the transaction is known.
+    fn confirm(&self, txid: &Txid, height: Height) -> Result<bool, Error>;
+    /// Unconfirm all transactions confirmed at the given height or above.
+    fn unconfirm(&self, height: Height) -> Result<(), Error>;
+    /// Add an address we own, optionally belonging to an account.
     fn add_address(
         &self,
         address: &Address,
         index: usize,
         label: Option<&str>,
+        account: Option<&str>,
     ) -> Result<bool, Error>;
+    /// Mark an address as used, having received the given value. Returns `true` if the
+    /// address is ours.
+    fn mark_used(&self, address: &Address, value: u64) -> Result<bool, Error>;
+    /// Add an account. If an account with the same label exists, its gap limit is updated.
+    fn add_account(&self, account: &Account) -> Result<bool, Error>;
+    /// Queue a transaction for broadcast. Returns `true` if it wasn't already queued.
+    fn queue_broadcast(&self, tx: &Transaction) -> Result<bool, Error>;
+    /// Remove a transaction from the broadcast queue. Returns `true` if it was queued.
+    fn remove_broadcast(&self, txid: &Txid) -> Result<bool, Error>;
+    /// Record a transaction we sent, paying the given fee. Returns `true` if it wasn't
+    /// already recorded.
+    fn add_sent(&self, tx: &Transaction, fee: u64) -> Result<bool, Error>;
+    /// Add a contact. If a contact with the same label exists, its address is updated.
+    /// Returns `true` if the contact was added or changed.
+    fn add_contact(&self, contact: &Contact) -> Result<bool, Error>;
+    /// Remove a contact. Returns `true` if it existed.
+    fn remove_contact(&self, label: &str) -> Result<bool, Error>;
+    /// Mark the outputs created by a transaction as coinbase outputs. Returns `true` if
+    /// any of them are ours.
+    fn mark_coinbase(&self, txid: &Txid) -> Result<bool, Error>;
+    /// Set a wallet setting. Returns `true` if it changed.
+    fn set_setting(&self, key: &str, value: &str) -> Result<bool, Error>;
+    /// Cache the metadata of a token category, replacing any cached before. Returns `true`
+    /// if it changed.
+    fn add_token_metadata(&self, id: &TokenID, metadata: &TokenMetadata) -> Result<bool, Error>;
 }
 
 /// Wallet database.
@@ -60,7 +144,9 @@ pub struct Db {
 
 impl Read for Db {
     fn balance(&self) -> Result<u64, Error> {
-        let mut stmt = self.raw.prepare("SELECT SUM(value) FROM utxos")?;
+        let mut stmt = self
+            .raw
+            .prepare("SELECT COALESCE(SUM(value), 0) FROM utxos WHERE spent IS NULL")?;
         stmt.next()?;
 
         let balance = stmt.read::<i64>(0)? as u64;
@@ -68,14 +154,31 @@ impl Read for Db {
         Ok(balance)
     }
 
+    fn balances(&self) -> Result<utxo::Balance, Error> {
+        let mut stmt = self.raw.prepare(
+            "SELECT
+                COALESCE(SUM(CASE WHEN height IS NOT NULL THEN value ELSE 0 END), 0),
+                COALESCE(SUM(CASE WHEN height IS NULL THEN value ELSE 0 END), 0)
+             FROM utxos
+             WHERE spent IS NULL",
+        )?;
+        stmt.next()?;
+
+        Ok(utxo::Balance {
+            confirmed: stmt.read::<i64>(0)? as u64,
+            unconfirmed: stmt.read::<i64>(1)? as u64,
+        })
+    }
+
     fn utxo(&self, outpoint: &OutPoint) -> Result<Option<(OutPoint, TxOut)>, Error> {
         let row = self
             .raw
             .prepare(
-                "SELECT address, value
+                "SELECT address, value, token
                  FROM utxos
                  WHERE txid = ?
-                 AND vout = ?",
+                 AND vout = ?
+                 AND spent IS NULL",
             )?
             .into_cursor()
             .bind(&[
@@ -90,13 +193,14 @@ impl Read for Db {
                 .map_err(|_| Error::Decoding("address"))?
                 .script_pubkey();
             let value = row.get::<i64, _>("value") as u64;
+            let token = types::token(row.get("token"))?;
 
             return Ok(Some((
                 *outpoint,
                 TxOut {
                     script_pubkey,
                     value,
-                    token: None,
+                    token,
                 },
             )));
         }
@@ -106,11 +210,12 @@ impl Read for Db {
     fn utxos(&self) -> Result<Vec<(OutPoint, TxOut)>, Error> {
         let mut stmt = self
             .raw
-            .prepare("SELECT txid, vout, address, value FROM utxos")?
+            .prepare("SELECT txid, vout, address, value, token FROM utxos WHERE spent IS NULL")?
             .into_cursor();
 
         let mut utxos = Vec::new();
         while let Some(Ok(row)) = stmt.next() {
+            let token = types::token(row.get("token"))?;
             let Record((txid, vout, address, value)): Record<(String, i64, String, Balance)> =
                 row.try_into()?;
             let txid = txid.parse().map_err(|_| Error::Decoding("txid"))?;
@@ -125,8 +230,7 @@ impl Read for Db {
                 },
                 TxOut {
                     script_pubkey: address.script_pubkey(),
-
-                    token: None,
+                    token,
                     value: *value,
                 },
             ));
@@ -134,10 +238,31 @@ impl Read for Db {
         Ok(utxos)
     }
 
+    fn unspent(&self) -> Result<Vec<utxo::Utxo>, Error> {
+        let mut stmt = self
+            .raw
+            .prepare(
+                "SELECT txid, vout, address, value, height, token, coinbase
+                 FROM utxos
+                 WHERE spent IS NULL
+                 ORDER BY height IS NULL, height",
+            )
+            .map_err(|e| Error::Query(e, "loading unspent outputs"))?
+            .into_cursor();
+
+        let mut utxos = Vec::new();
+        while let Some(Ok(row)) = stmt.next() {
+            utxos.push(utxo::Utxo::try_from(&row)?);
+        }
+        Ok(utxos)
+    }
+
     fn addresses(&self) -> Result<Vec<AddressRecord>, Error> {
         let mut stmt = self
             .raw
-            .prepare("SELECT `id`, `index`, `label`, `received`, `used` FROM `addresses`")
+            .prepare(
+                "SELECT `id`, `index`, `label`, `received`, `used`, `account` FROM `addresses`",
+            )
             .map_err(|e| Error::Query(e, "loading addresses"))?
             .into_cursor();
         let mut addrs = Vec::new();
@@ -148,14 +273,202 @@ impl Read for Db {
         }
         Ok(addrs)
     }
+
+    fn account_addresses(&self, account: &str) -> Result<Vec<AddressRecord>, Error> {
+        let mut stmt = self
+            .raw
+            .prepare(
+                "SELECT `id`, `index`, `label`, `received`, `used`, `account`
+                 FROM `addresses`
+                 WHERE `account` = ?
+                 ORDER BY `index`",
+            )
+            .map_err(|e| Error::Query(e, "loading account addresses"))?
+            .into_cursor()
+            .bind(&[sql::Value::String(account.to_owned())])?;
+        let mut addrs = Vec::new();
+
+        while let Some(Ok(row)) = stmt.next() {
+            let addr = AddressRecord::try_from(&row)?;
+            addrs.push(addr);
+        }
+        Ok(addrs)
+    }
+
+    fn accounts(&self) -> Result<Vec<Account>, Error> {
+        let mut stmt = self
+            .raw
+            .prepare("SELECT `id`, `descriptor`, `gap_limit` FROM `accounts` ORDER BY `id`")
+            .map_err(|e| Error::Query(e, "loading accounts"))?
+            .into_cursor();
+        let mut accounts = Vec::new();
+
+        while let Some(Ok(row)) = stmt.next() {
+            let account = Account::try_from(&row)?;
+            accounts.push(account);
+        }
+        Ok(accounts)
+    }
+
+    fn pending_broadcasts(&self) -> Result<Vec<Transaction>, Error> {
+        let mut stmt = self
+            .raw
+            .prepare("SELECT `tx` FROM `broadcasts` ORDER BY `date`, `rowid`")
+            .map_err(|e| Error::Query(e, "loading pending broadcasts"))?
+            .into_cursor();
+        let mut txs = Vec::new();
+
+        while let Some(Ok(row)) = stmt.next() {
+            let tx = encode::deserialize(&row.get::<Vec<u8>, _>(0))
+                .map_err(|_| Error::Decoding("tx"))?;
+            txs.push(tx);
+        }
+        Ok(txs)
+    }
+
+    fn sent_transaction(&self, txid: &Txid) -> Result<Option<(Transaction, u64)>, Error> {
+        let row = self
+            .raw
+            .prepare("SELECT `tx`, `fee` FROM `sent` WHERE `txid` = ?")
+            .map_err(|e| Error::Query(e, "loading sent transaction"))?
+            .into_cursor()
+            .bind(&[sql::Value::String(txid.to_string())])?
+            .next();
+
+        match row {
+            Some(row) => {
+                let row = row?;
+                let tx = encode::deserialize(&row.get::<Vec<u8>, _>(0))
+                    .map_err(|_| Error::Decoding("tx"))?;
+
+                Ok(Some((tx, row.get::<i64, _>(1) as u64)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn transaction_height(&self, txid: &Txid) -> Result<Option<Option<Height>>, Error> {
+        let row = self
+            .raw
+            .prepare(
+                "SELECT CASE WHEN txid = ?1 THEN height ELSE spent_height END
+                 FROM utxos
+                 WHERE txid = ?1 OR spent = ?1
+                 LIMIT 1",
+            )
+            .map_err(|e| Error::Query(e, "loading transaction height"))?
+            .into_cursor()
+            .bind(&[sql::Value::String(txid.to_string())])?
+            .next();
+
+        match row {
+            Some(row) => Ok(Some(row?.get::<Option<i64>, _>(0).map(|h| h as Height))),
+            None => Ok(None),
+        }
+    }
+
+    fn confirmed_since(&self, height: Height) -> Result<Vec<Txid>, Error> {
+        let mut stmt = self
+            .raw
+            .prepare(
+                "SELECT txid FROM utxos WHERE height >= ?1
+                 UNION
+                 SELECT spent FROM utxos WHERE spent_height >= ?1",
+            )
+            .map_err(|e| Error::Query(e, "loading confirmed transactions"))?
+            .into_cursor()
+            .bind(&[sql::Value::Integer(height as i64)])?;
+        let mut txids = Vec::new();
+
+        while let Some(Ok(row)) = stmt.next() {
+            let txid = row
+                .get::<String, _>(0)
+                .parse()
+                .map_err(|_| Error::Decoding("txid"))?;
+            txids.push(txid);
+        }
+        Ok(txids)
+    }
+
+    fn contact(&self, label: &str) -> Result<Option<Contact>, Error> {
+        let row = self
+            .raw
+            .prepare("SELECT `label`, `address` FROM `contacts` WHERE `label` = ?")
+            .map_err(|e| Error::Query(e, "loading contact"))?
+            .into_cursor()
+            .bind(&[sql::Value::String(label.to_owned())])?
+            .next();
+
+        match row {
+            Some(row) => Ok(Some(Contact::try_from(&row?)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn setting(&self, key: &str) -> Result<Option<String>, Error> {
+        let row = self
+            .raw
+            .prepare("SELECT `value` FROM `settings` WHERE `key` = ?")?
+            .into_cursor()
+            .bind(&[sql::Value::String(key.to_owned())])?
+            .next();
+
+        match row {
+            Some(row) => Ok(Some(row?.get::<String, _>("value"))),
+            None => Ok(None),
+        }
+    }
+
+    fn token_metadata(&self, id: &TokenID) -> Result<Option<TokenMetadata>, Error> {
+        let row = self
+            .raw
+            .prepare(
+                "SELECT `name`, `symbol`, `decimals`, `icon` FROM `tokens` WHERE `category` = ?",
+            )?
+            .into_cursor()
+            .bind(&[sql::Value::String(id.to_string())])?
+            .next();
+
+        match row {
+            Some(row) => Ok(Some(TokenMetadata::try_from(&row?)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn contacts(&self, search: &str) -> Result<Vec<Contact>, Error> {
+        let mut stmt = self
+            .raw
+            .prepare(
+                "SELECT `label`, `address` FROM `contacts`
+                 WHERE instr(lower(`label`), lower(?1)) > 0 OR instr(`address`, lower(?1)) > 0
+                 ORDER BY lower(`label`)",
+            )
+            .map_err(|e| Error::Query(e, "loading contacts"))?
+            .into_cursor()
+            .bind(&[sql::Value::String(search.to_owned())])?;
+        let mut contacts = Vec::new();
+
+        while let Some(Ok(row)) = stmt.next() {
+            contacts.push(Contact::try_from(&row)?);
+        }
+        Ok(contacts)
+    }
 }
 
 impl Write for Db {
-    fn add_utxo(&self, txid: Txid, vout: u32, address: Address, value: u64) -> Result<bool, Error> {
+    fn add_utxo(
+        &self,
+        txid: Txid,
+        vout: u32,
+        address: Address,
+        value: u64,
+        token: Option<&OutputData>,
+        height: Option<Height>,
+    ) -> Result<bool, Error> {
         self.raw
             .prepare(
-                "INSERT INTO utxos (txid, vout, address, value, date)
-                 VALUES (?, ?, ?, ?, ?)
+                "INSERT INTO utxos (txid, vout, address, value, token, date, height)
+                 VALUES (?, ?, ?, ?, ?, ?, ?)
                  ON CONFLICT DO NOTHING",
             )?
             .into_cursor()
@@ -164,7 +477,13 @@ impl Write for Db {
                 sql::Value::Integer(vout as i64),
                 sql::Value::String(address.to_string()),
                 sql::Value::Integer(value as i64),
+                token
+                    .map(|t| sql::Value::Binary(encode::serialize(t)))
+                    .unwrap_or(sql::Value::Null),
                 sql::Value::Integer(0), // TODO: Set transaction time
+                height
+                    .map(|h| sql::Value::Integer(h as i64))
+                    .unwrap_or(sql::Value::Null),
             ])?
             .next();
 
@@ -185,16 +504,80 @@ impl Write for Db {
         Ok(utxo)
     }
 
+    fn spend_utxo(
+        &self,
+        prev_out: &OutPoint,
+        txid: &Txid,
+        height: Option<Height>,
+    ) -> Result<bool, Error> {
+        self.raw
+            .prepare(
+                "UPDATE utxos
+                 SET spent = ?1, spent_height = ?2
+                 WHERE txid = ?3 AND vout = ?4 AND (spent IS NULL OR spent = ?1)",
+            )?
+            .into_cursor()
+            .bind(&[
+                sql::Value::String(txid.to_string()),
+                height
+                    .map(|h| sql::Value::Integer(h as i64))
+                    .unwrap_or(sql::Value::Null),
+                sql::Value::String(prev_out.txid.to_string()),
+                sql::Value::Integer(prev_out.vout as i64),
+            ])?
+            .next();
+
+        Ok(self.raw.change_count() > 0)
+    }
+
+    fn confirm(&self, txid: &Txid, height: Height) -> Result<bool, Error> {
+        let txid = sql::Value::String(txid.to_string());
+        let height = sql::Value::Integer(height as i64);
+
+        self.raw
+            .prepare("UPDATE utxos SET height = ?1 WHERE txid = ?2")?
+            .into_cursor()
+            .bind(&[height.clone(), txid.clone()])?
+            .next();
+        let created = self.raw.change_count();
+
+        self.raw
+            .prepare("UPDATE utxos SET spent_height = ?1 WHERE spent = ?2")?
+            .into_cursor()
+            .bind(&[height, txid])?
+            .next();
+        let spent = self.raw.change_count();
+
+        Ok(created + spent > 0)
+    }
+
+    fn unconfirm(&self, height: Height) -> Result<(), Error> {
+        let mut stmt = self
+            .raw
+            .prepare("UPDATE utxos SET height = NULL WHERE height >= ?")?;
+        stmt.bind(1, height as i64)?;
+        stmt.next()?;
+
+        let mut stmt = self
+            .raw
+            .prepare("UPDATE utxos SET spent_height = NULL WHERE spent_height >= ?")?;
+        stmt.bind(1, height as i64)?;
+        stmt.next()?;
+
+        Ok(())
+    }
+
     fn add_address(
         &self,
         address: &Address,
         index: usize,
         label: Option<&str>,
+        account: Option<&str>,
     ) -> Result<bool, Error> {
         self.raw
             .prepare(
-                "INSERT INTO addresses (`id`, `index`, `label`)
-                 VALUES (?1, ?2, ?3)
+                "INSERT INTO addresses (`id`, `index`, `label`, `account`)
+                 VALUES (?1, ?2, ?3, ?4)
                  ON CONFLICT DO UPDATE
                  SET label = ?3",
             )?
@@ -205,6 +588,182 @@ impl Write for Db {
                 label
                     .map(|s| sql::Value::String(s.to_owned()))
                     .unwrap_or(sql::Value::Null),
+                account
+                    .map(|s| sql::Value::String(s.to_owned()))
+                    .unwrap_or(sql::Value::Null),
+            ])?
+            .next();
+
+        Ok(self.raw.change_count() > 0)
+    }
+
+    fn mark_used(&self, address: &Address, value: u64) -> Result<bool, Error> {
+        self.raw
+            .prepare(
+                "UPDATE addresses
+                 SET used = true, received = received + ?1
+                 WHERE id = ?2",
+            )?
+            .into_cursor()
+            .bind(&[
+                sql::Value::Integer(value as i64),
+                sql::Value::String(address.to_string()),
+            ])?
+            .next();
+
+        Ok(self.raw.change_count() > 0)
+    }
+
+    fn add_account(&self, account: &Account) -> Result<bool, Error> {
+        self.raw
+            .prepare(
+                "INSERT INTO accounts (`id`, `descriptor`, `gap_limit`)
+                 VALUES (?1, ?2, ?3)
+                 ON CONFLICT (`id`) DO UPDATE
+                 SET gap_limit = ?3",
+            )?
+            .into_cursor()
+            .bind(&[
+                sql::Value::String(account.label.clone()),
+                sql::Value::String(account.descriptor.to_string()),
+                sql::Value::Integer(account.gap_limit as i64),
+            ])?
+            .next();
+
+        Ok(self.raw.change_count() > 0)
+    }
+
+    fn queue_broadcast(&self, tx: &Transaction) -> Result<bool, Error> {
+        let date = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        self.raw
+            .prepare(
+                "INSERT INTO broadcasts (`txid`, `tx`, `date`)
+                 VALUES (?, ?, ?)
+                 ON CONFLICT DO NOTHING",
+            )?
+            .into_cursor()
+            .bind(&[
+                sql::Value::String(tx.txid().to_string()),
+                sql::Value::Binary(encode::serialize(tx)),
+                sql::Value::Integer(date as i64),
+            ])?
+            .next();
+
+        Ok(self.raw.change_count() > 0)
+    }
+
+    fn remove_broadcast(&self, txid: &Txid) -> Result<bool, Error> {
+        self.raw
+            .prepare("DELETE FROM broadcasts WHERE txid = ?")?
+            .into_cursor()
+            .bind(&[sql::Value::String(txid.to_string())])?
+            .next();
+
+        Ok(self.raw.change_count() > 0)
+    }
+
+    fn add_sent(&self, tx: &Transaction, fee: u64) -> Result<bool, Error> {
+        let date = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        self.raw
+            .prepare(
+                "INSERT INTO sent (`txid`, `tx`, `fee`, `date`)
+                 VALUES (?, ?, ?, ?)
+                 ON CONFLICT DO NOTHING",
+            )?
+            .into_cursor()
+            .bind(&[
+                sql::Value::String(tx.txid().to_string()),
+                sql::Value::Binary(encode::serialize(tx)),
+                sql::Value::Integer(fee as i64),
+                sql::Value::Integer(date as i64),
+            ])?
+            .next();
+
+        Ok(self.raw.change_count() > 0)
+    }
+
+    fn add_contact(&self, contact: &Contact) -> Result<bool, Error> {
+        self.raw
+            .prepare(
+                "INSERT INTO contacts (`label`, `address`)
+                 VALUES (?1, ?2)
+                 ON CONFLICT (`label`) DO UPDATE
+                 SET address = ?2 WHERE address != ?2",
+            )?
+            .into_cursor()
+            .bind(&[
+                sql::Value::String(contact.label.clone()),
+                sql::Value::String(contact.address.clone()),
+            ])?
+            .next();
+
+        Ok(self.raw.change_count() > 0)
+    }
+
+    fn remove_contact(&self, label: &str) -> Result<bool, Error> {
+        self.raw
+            .prepare("DELETE FROM contacts WHERE label = ?")?
+            .into_cursor()
+            .bind(&[sql::Value::String(label.to_owned())])?
+            .next();
+
+        Ok(self.raw.change_count() > 0)
+    }
+
+    fn mark_coinbase(&self, txid: &Txid) -> Result<bool, Error> {
+        self.raw
+            .prepare("UPDATE utxos SET coinbase = true WHERE txid = ? AND NOT coinbase")?
+            .into_cursor()
+            .bind(&[sql::Value::String(txid.to_string())])?
+            .next();
+
+        Ok(self.raw.change_count() > 0)
+    }
+
+    fn set_setting(&self, key: &str, value: &str) -> Result<bool, Error> {
+        self.raw
+            .prepare(
+                "INSERT INTO settings (`key`, `value`)
+                 VALUES (?1, ?2)
+                 ON CONFLICT (`key`) DO UPDATE
+                 SET value = ?2 WHERE value != ?2",
+            )?
+            .into_cursor()
+            .bind(&[
+                sql::Value::String(key.to_owned()),
+                sql::Value::String(value.to_owned()),
+            ])?
+            .next();
+
+        Ok(self.raw.change_count() > 0)
+    }
+
+    fn add_token_metadata(&self, id: &TokenID, metadata: &TokenMetadata) -> Result<bool, Error> {
+        let optional = |s: &Option<String>| s.clone().map_or(sql::Value::Null, sql::Value::String);
+
+        self.raw
+            .prepare(
+                "INSERT INTO tokens (`category`, `name`, `symbol`, `decimals`, `icon`)
+                 VALUES (?1, ?2, ?3, ?4, ?5)
+                 ON CONFLICT (`category`) DO UPDATE
+                 SET name = ?2, symbol = ?3, decimals = ?4, icon = ?5
+                 WHERE name != ?2 OR symbol IS NOT ?3 OR decimals != ?4 OR icon IS NOT ?5",
+            )?
+            .into_cursor()
+            .bind(&[
+                sql::Value::String(id.to_string()),
+                sql::Value::String(metadata.name.clone()),
+                optional(&metadata.symbol),
+                sql::Value::Integer(metadata.decimals as i64),
+                optional(&metadata.icon),
             ])?
             .next();
 
@@ -215,6 +774,9 @@ impl Write for Db {
 impl Db {
     /// The database schema.
     const SCHEMA: &str = include_str!("schema.sql");
+    /// Version of the database schema, stored as the database's `user_version`. Bumped with
+    /// every migration.
+    const VERSION: i64 = 4;
 
     /// Open a wallet database at the given path. If none exists, an empty database is created.
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
@@ -228,10 +790,79 @@ impl Db {
         .map_err(Error::Open)?;
 
         raw.execute(Db::SCHEMA).map_err(Error::Schema)?;
+        Self::migrate(&raw).map_err(Error::Schema)?;
 
         Ok(Self { raw })
     }
 
+    /// Migrate a database created by an earlier version to the current schema, and record
+    /// the schema version migrated to. Tables added since are created along with the schema,
+    /// while columns and constraints added to existing tables are migrated here. Databases
+    /// created from the current schema start at version zero too, so every migration first
+    /// checks whether it's needed.
+    fn migrate(raw: &sql::Connection) -> Result<(), sql::Error> {
+        let version = {
+            let mut stmt = raw.prepare("PRAGMA user_version")?;
+            stmt.next()?;
+            stmt.read::<i64>(0)?
+        };
+
+        if version < 1 && !Self::has_column(raw, "addresses", "account")? {
+            // Addresses are now unique per account, rather than by index. Changing the
+            // constraint takes rebuilding the table.
+            let tx = DbTransaction::begin(raw)?;
+
+            raw.execute("ALTER TABLE `addresses` RENAME TO `addresses_old`;")?;
+            raw.execute(Self::SCHEMA)?;
+            raw.execute(
+                "INSERT INTO `addresses` (`id`, `index`, `label`, `received`, `used`)
+                 SELECT `id`, `index`, `label`, `received`, `used` FROM `addresses_old`;
+                 DROP TABLE `addresses_old`;",
+            )?;
+            tx.commit()?;
+        }
+        if version < 2 {
+            Self::add_column(raw, "utxos", "coinbase", "integer NOT NULL DEFAULT false")?;
+        }
+        if version < 3 {
+            Self::add_column(raw, "utxos", "height", "integer DEFAULT NULL")?;
+            Self::add_column(raw, "utxos", "spent", "text DEFAULT NULL")?;
+            Self::add_column(raw, "utxos", "spent_height", "integer DEFAULT NULL")?;
+        }
+        if version < 4 {
+            Self::add_column(raw, "utxos", "token", "blob DEFAULT NULL")?;
+        }
+        if version < Self::VERSION {
+            raw.execute(format!("PRAGMA user_version = {}", Self::VERSION))?;
+        }
+        Ok(())
+    }
+
+    /// Add a column to a table, unless it already has it.
+    fn add_column(
+        raw: &sql::Connection,
+        table: &str,
+        column: &str,
+        definition: &str,
+    ) -> Result<(), sql::Error> {
+        if !Self::has_column(raw, table, column)? {
+            raw.execute(format!(
+                "ALTER TABLE `{table}` ADD COLUMN `{column}` {definition}"
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Check whether a table has the given column.
+    fn has_column(raw: &sql::Connection, table: &str, column: &str) -> Result<bool, sql::Error> {
+        let mut stmt = raw.prepare(format!(
+            "SELECT COUNT(*) FROM pragma_table_info('{table}') WHERE name = '{column}'"
+        ))?;
+        stmt.next()?;
+
+        Ok(stmt.read::<i64>(0)? > 0)
+    }
+
     /// Create a new in-memory database.
     pub fn memory() -> Result<Self, Error> {
         let raw = sql::Connection::open(":memory:")?;
@@ -247,9 +878,45 @@ impl Db {
     }
 }
 
+/// A database transaction, rolled back when dropped unless committed, eg. when a statement
+/// fails half-way through a migration.
+struct DbTransaction<'a> {
+    raw: &'a sql::Connection,
+    committed: bool,
+}
+
+impl<'a> DbTransaction<'a> {
+    /// Begin a transaction.
+    fn begin(raw: &'a sql::Connection) -> Result<Self, sql::Error> {
+        raw.execute("BEGIN")?;
+
+        Ok(Self {
+            raw,
+            committed: false,
+        })
+    }
+
+    /// Commit the transaction.
+    fn commit(mut self) -> Result<(), sql::Error> {
+        self.raw.execute("COMMIT")?;
+        self.committed = true;
+
+        Ok(())
+    }
+}
+
+impl Drop for DbTransaction<'_> {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.raw.execute("ROLLBACK").ok();
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use nakamoto_common::bitcoin::hashes::Hash;
     use nakamoto_common::bitcoin::Network;
     use nakamoto_test::block::gen;
     use nakamoto_test::fastrand;
@@ -267,12 +934,26 @@ mod tests {
         };
 
         let added = db
-            .add_utxo(out.txid, out.vout, address.clone(), tx.output[0].value)
+            .add_utxo(
+                out.txid,
+                out.vout,
+                address.clone(),
+                tx.output[0].value,
+                None,
+                None,
+            )
             .unwrap();
         assert!(added);
 
         let added = db
-            .add_utxo(out.txid, out.vout, address.clone(), tx.output[0].value)
+            .add_utxo(
+                out.txid,
+                out.vout,
+                address.clone(),
+                tx.output[0].value,
+                None,
+                None,
+            )
             .unwrap();
         assert!(!added);
 
@@ -294,7 +975,7 @@ mod tests {
         };
 
         let added = db
-            .add_utxo(out.txid, out.vout, address, tx.output[0].value)
+            .add_utxo(out.txid, out.vout, address, tx.output[0].value, None, None)
             .unwrap();
         assert!(added);
 
@@ -303,6 +984,141 @@ mod tests {
         assert!(db.utxo(&out).unwrap().is_none());
     }
 
+    #[test]
+    fn test_accounts() {
+        let db = Db::memory().unwrap();
+        let xpub = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+        let mut acc = format!("savings:5:pkh({xpub}/0/*)")
+            .parse::<Account>()
+            .unwrap();
+
+        assert!(db.add_account(&acc).unwrap());
+        assert_eq!(db.accounts().unwrap(), vec![acc.clone()]);
+
+        acc.gap_limit = 10;
+        db.add_account(&acc).unwrap();
+        assert_eq!(db.accounts().unwrap(), vec![acc]);
+    }
+
+    #[test]
+    fn test_migrate() {
+        // Schema of wallets created before accounts, confirmation heights and tokens were
+        // introduced.
+        let raw = sql::Connection::open(":memory:").unwrap();
+        raw.execute(
+            r#"CREATE TABLE "utxos" (
+                 "id"       integer  PRIMARY KEY,
+                 "txid"     text     NOT NULL,
+                 "vout"     integer  NOT NULL,
+                 "address"  text     NOT NULL REFERENCES "address" ("id"),
+                 "value"    integer  NOT NULL,
+                 "date"     integer  NOT NULL,
+
+                 UNIQUE ("txid", "vout")
+               ) STRICT;
+               CREATE TABLE "addresses" (
+                 "id"        text     PRIMARY KEY,
+                 "index"     integer  NOT NULL UNIQUE,
+                 "label"     text     DEFAULT NULL,
+                 "received"  integer  NOT NULL DEFAULT 0,
+                 "used"      integer  NOT NULL DEFAULT false
+               ) STRICT;"#,
+        )
+        .unwrap();
+
+        let mut rng = fastrand::Rng::new();
+        let tx = gen::transaction(&mut rng);
+        let [address, other] = [(); 2].map(|_| {
+            let tx = gen::transaction(&mut rng);
+            Address::from_script(&tx.output[0].script_pubkey, Network::Bitcoin).unwrap()
+        });
+
+        raw.execute(format!(
+            "INSERT INTO addresses (id, `index`, label) VALUES ('{address}', 0, 'old');
+             INSERT INTO utxos (txid, vout, address, value, date)
+             VALUES ('{}', 0, '{address}', 1000, 0)",
+            tx.txid()
+        ))
+        .unwrap();
+
+        raw.execute(Db::SCHEMA).unwrap();
+        Db::migrate(&raw).unwrap();
+        Db::migrate(&raw).unwrap();
+
+        let db = Db { raw };
+        // Addresses of different accounts can share an index.
+        assert!(db.add_address(&other, 0, None, Some("savings")).unwrap());
+        assert_eq!(db.addresses().unwrap().len(), 2);
+        assert_eq!(db.account_addresses("savings").unwrap().len(), 1);
+        assert_eq!(
+            db.addresses().unwrap()[0].label.as_deref(),
+            Some("old"),
+            "Existing addresses are kept"
+        );
+
+        // Existing outputs can be loaded, confirmed and spent.
+        assert_eq!(db.utxos().unwrap().len(), 1);
+        assert_eq!(db.transaction_height(&tx.txid()).unwrap(), Some(None));
+        assert!(db.confirm(&tx.txid(), 5).unwrap());
+        assert_eq!(db.transaction_height(&tx.txid()).unwrap(), Some(Some(5)));
+    }
+
+    #[test]
+    fn test_migrate_rollback() {
+        let raw = sql::Connection::open(":memory:").unwrap();
+        raw.execute(
+            r#"CREATE TABLE "addresses" (
+                 "id"        text     PRIMARY KEY,
+                 "index"     integer  NOT NULL UNIQUE,
+                 "label"     text     DEFAULT NULL,
+                 "received"  integer  NOT NULL DEFAULT 0,
+                 "used"      integer  NOT NULL DEFAULT false
+               ) STRICT;
+               INSERT INTO addresses (id, `index`) VALUES ('address', 0);
+               CREATE TABLE "addresses_old" ("id" text PRIMARY KEY) STRICT;"#,
+        )
+        .unwrap();
+
+        // The table can't be renamed, so the migration fails, and is rolled back.
+        assert!(Db::migrate(&raw).is_err());
+        assert!(!Db::has_column(&raw, "addresses", "account").unwrap());
+
+        // No transaction is left open.
+        raw.execute("BEGIN; ROLLBACK;").unwrap();
+    }
+
+    #[test]
+    fn test_broadcasts() {
+        let db = Db::memory().unwrap();
+        let mut rng = fastrand::Rng::new();
+        let tx1 = gen::transaction(&mut rng);
+        let tx2 = gen::transaction(&mut rng);
+
+        assert!(db.queue_broadcast(&tx1).unwrap());
+        assert!(db.queue_broadcast(&tx2).unwrap());
+        assert!(!db.queue_broadcast(&tx1).unwrap());
+        assert_eq!(
+            db.pending_broadcasts().unwrap(),
+            vec![tx1.clone(), tx2.clone()]
+        );
+
+        assert!(db.remove_broadcast(&tx1.txid()).unwrap());
+        assert!(!db.remove_broadcast(&tx1.txid()).unwrap());
+        assert_eq!(db.pending_broadcasts().unwrap(), vec![tx2]);
+    }
+
+    #[test]
+    fn test_sent() {
+        let db = Db::memory().unwrap();
+        let mut rng = fastrand::Rng::new();
+        let tx = gen::transaction(&mut rng);
+
+        assert_eq!(db.sent_transaction(&tx.txid()).unwrap(), None);
+        assert!(db.add_sent(&tx, 226).unwrap());
+        assert!(!db.add_sent(&tx, 452).unwrap());
+        assert_eq!(db.sent_transaction(&tx.txid()).unwrap(), Some((tx, 226)));
+    }
+
     #[test]
     fn test_utxos() {
         let db = Db::memory().unwrap();
@@ -315,11 +1131,11 @@ mod tests {
             vout: rng.u32(..),
         };
 
-        db.add_utxo(out.txid, 1, address.clone(), tx.output[0].value)
+        db.add_utxo(out.txid, 1, address.clone(), tx.output[0].value, None, None)
             .unwrap();
-        db.add_utxo(out.txid, 2, address.clone(), tx.output[0].value)
+        db.add_utxo(out.txid, 2, address.clone(), tx.output[0].value, None, None)
             .unwrap();
-        db.add_utxo(out.txid, 3, address, tx.output[0].value)
+        db.add_utxo(out.txid, 3, address, tx.output[0].value, None, None)
             .unwrap();
 
         let utxos = db
@@ -342,4 +1158,87 @@ mod tests {
             vout: 3
         }));
     }
+
+    #[test]
+    fn test_transaction_height() {
+        let db = Db::memory().unwrap();
+        let mut rng = fastrand::Rng::new();
+        let tx = gen::transaction(&mut rng);
+        let spend = gen::transaction(&mut rng);
+        let address = Address::from_script(&tx.output[0].script_pubkey, Network::Bitcoin).unwrap();
+        let out = OutPoint {
+            txid: tx.txid(),
+            vout: 0,
+        };
+
+        assert_eq!(db.transaction_height(&out.txid).unwrap(), None);
+
+        db.add_utxo(out.txid, out.vout, address, tx.output[0].value, None, None)
+            .unwrap();
+        assert_eq!(db.transaction_height(&out.txid).unwrap(), Some(None));
+
+        db.confirm(&out.txid, 5).unwrap();
+        assert_eq!(db.transaction_height(&out.txid).unwrap(), Some(Some(5)));
+
+        db.spend_utxo(&out, &spend.txid(), Some(7)).unwrap();
+        assert_eq!(db.transaction_height(&spend.txid()).unwrap(), Some(Some(7)));
+        assert_eq!(db.confirmed_since(6).unwrap(), vec![spend.txid()]);
+        assert_eq!(db.confirmed_since(5).unwrap().len(), 2);
+        assert!(db.confirmed_since(8).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_contacts() {
+        let db = Db::memory().unwrap();
+        let alice = Contact {
+            label: String::from("Alice"),
+            address: String::from("bitcoincash:qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekg2"),
+        };
+        let bob = Contact {
+            label: String::from("bob"),
+            address: String::from("bitcoincash:rqv53dwyatxse2xh7nnlqhyr6ryjgfdtag38xjkhc5"),
+        };
+
+        assert!(db.add_contact(&bob).unwrap());
+        assert!(db.add_contact(&alice).unwrap());
+        assert!(!db.add_contact(&alice).unwrap());
+
+        assert_eq!(db.contacts("").unwrap(), vec![alice.clone(), bob.clone()]);
+        assert_eq!(db.contacts("ALI").unwrap(), vec![alice.clone()]);
+        assert_eq!(db.contacts("rqv53").unwrap(), vec![bob.clone()]);
+        assert_eq!(db.contact("bob").unwrap(), Some(bob.clone()));
+        assert_eq!(db.contact("Bob").unwrap(), None);
+
+        let moved = Contact {
+            address: alice.address.clone(),
+            ..bob.clone()
+        };
+        assert!(db.add_contact(&moved).unwrap());
+        assert_eq!(db.contact("bob").unwrap(), Some(moved));
+
+        assert!(db.remove_contact("Alice").unwrap());
+        assert!(!db.remove_contact("Alice").unwrap());
+        assert_eq!(db.contacts("").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_token_metadata() {
+        let db = Db::memory().unwrap();
+        let id = TokenID::from_inner([1; 32]);
+        let mut metadata = TokenMetadata {
+            name: String::from("Example"),
+            symbol: None,
+            decimals: 2,
+            icon: None,
+        };
+
+        assert_eq!(db.token_metadata(&id).unwrap(), None);
+        assert!(db.add_token_metadata(&id, &metadata).unwrap());
+        assert!(!db.add_token_metadata(&id, &metadata).unwrap());
+        assert_eq!(db.token_metadata(&id).unwrap(), Some(metadata.clone()));
+
+        metadata.symbol = Some(String::from("XMPL"));
+        assert!(db.add_token_metadata(&id, &metadata).unwrap());
+        assert_eq!(db.token_metadata(&id).unwrap(), Some(metadata));
+    }
 }
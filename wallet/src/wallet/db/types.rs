@@ -1,7 +1,12 @@
-use nakamoto_common::bitcoin::Address;
+use nakamoto_common::bitcoin::blockdata::token::OutputData;
+use nakamoto_common::bitcoin::consensus::encode;
+use nakamoto_common::bitcoin::{Address, OutPoint, TxOut};
 use sqlite as sql;
 
 use super::Error;
+use crate::wallet::account::Account;
+use crate::wallet::bcmr::TokenMetadata;
+use crate::wallet::utxo::Utxo;
 
 /// Wraps a type, enabling it to be converted to SQL types.
 pub struct Record<T>(pub T);
@@ -64,6 +69,7 @@ pub struct AddressRecord {
     pub label: Option<String>,
     pub received: u64,
     pub used: bool,
+    pub account: Option<String>,
 }
 
 impl<'a> TryFrom<&'a sql::Row> for AddressRecord {
@@ -80,10 +86,96 @@ impl<'a> TryFrom<&'a sql::Row> for AddressRecord {
             label: row.get(2),
             received: row.get::<i64, _>(3) as u64,
             used: row.get::<i64, _>(4) > 0,
+            account: row.get(5),
         })
     }
 }
 
+/// A contact, ie. a labeled address we pay to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contact {
+    /// Unique label.
+    pub label: String,
+    /// Cashaddr, including its prefix.
+    pub address: String,
+}
+
+impl<'a> TryFrom<&'a sql::Row> for Contact {
+    type Error = Error;
+
+    fn try_from(row: &'a sql::Row) -> Result<Self, Self::Error> {
+        Ok(Self {
+            label: row.get(0),
+            address: row.get(1),
+        })
+    }
+}
+
+impl<'a> TryFrom<&'a sql::Row> for TokenMetadata {
+    type Error = Error;
+
+    fn try_from(row: &'a sql::Row) -> Result<Self, Self::Error> {
+        Ok(Self {
+            name: row.get(0),
+            symbol: row.get(1),
+            decimals: row.get::<i64, _>(2) as u8,
+            icon: row.get(3),
+        })
+    }
+}
+
+impl<'a> TryFrom<&'a sql::Row> for Account {
+    type Error = Error;
+
+    fn try_from(row: &'a sql::Row) -> Result<Self, Self::Error> {
+        Ok(Self {
+            label: row.get(0),
+            descriptor: row
+                .get::<String, _>(1)
+                .as_str()
+                .parse()
+                .map_err(|_| Error::Decoding("descriptor"))?,
+            gap_limit: row.get::<i64, _>(2) as usize,
+        })
+    }
+}
+
+impl<'a> TryFrom<&'a sql::Row> for Utxo {
+    type Error = Error;
+
+    fn try_from(row: &'a sql::Row) -> Result<Self, Self::Error> {
+        let txid = row
+            .get::<String, _>(0)
+            .parse()
+            .map_err(|_| Error::Decoding("txid"))?;
+        let address = row
+            .get::<String, _>(2)
+            .parse::<Address>()
+            .map_err(|_| Error::Decoding("address"))?;
+
+        Ok(Self {
+            outpoint: OutPoint {
+                txid,
+                vout: row.get::<i64, _>(1) as u32,
+            },
+            txout: TxOut {
+                value: row.get::<i64, _>(3) as u64,
+                script_pubkey: address.script_pubkey(),
+                token: token(row.get(5))?,
+            },
+            height: row.get::<Option<i64>, _>(4).map(|h| h as u64),
+            coinbase: row.get::<i64, _>(6) != 0,
+        })
+    }
+}
+
+/// Decode token data stored in a `token` column.
+pub fn token(bytes: Option<Vec<u8>>) -> Result<Option<OutputData>, Error> {
+    bytes
+        .map(|bytes| encode::deserialize(&bytes).map_err(|_| Error::Decoding("token")))
+        .transpose()
+}
+
 /// A balance in satoshis.
 pub struct Balance(u64);
 
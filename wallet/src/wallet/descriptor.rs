@@ -0,0 +1,155 @@
+//! Output descriptors, eg. `pkh(xpub.../0/*)`.
+//!
+//! Only ranged `pkh` descriptors over an extended public key are supported, since these are
+//! the only ones we can derive scripts from without the help of a signing device.
+use std::fmt;
+use std::str::FromStr;
+
+use nakamoto_common::bitcoin::secp256k1::{Secp256k1, Verification};
+use nakamoto_common::bitcoin::util::bip32::{self, ChildNumber, DerivationPath, ExtendedPubKey};
+use nakamoto_common::bitcoin::{Address, Network, PublicKey};
+
+use thiserror::Error;
+
+/// A descriptor error.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("unsupported descriptor `{0}`")]
+    Unsupported(String),
+    #[error("descriptor `{0}` must end with a `/*` wildcard")]
+    Wildcard(String),
+    #[error("descriptor `{0}` has hardened steps after the extended public key")]
+    Hardened(String),
+    #[error("descriptor key error: {0}")]
+    Key(#[from] bip32::Error),
+}
+
+/// A ranged `pkh` output descriptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Descriptor {
+    /// Extended public key to derive from.
+    pub xpub: ExtendedPubKey,
+    /// Derivation path from the extended public key, not including the wildcard step.
+    pub path: DerivationPath,
+}
+
+impl Descriptor {
+    /// Derive the address at the given wildcard index.
+    pub fn derive<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+        network: Network,
+    ) -> Result<Address, Error> {
+        let pubkey = self.derive_pubkey(secp, index)?;
+
+        Ok(Address::p2pkh(&pubkey, network))
+    }
+
+    /// Derive the public key at the given wildcard index.
+    pub fn derive_pubkey<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        index: u32,
+    ) -> Result<PublicKey, Error> {
+        let path = self.path.child(ChildNumber::from_normal_idx(index)?);
+        let xpub = self.xpub.derive_pub(secp, &path)?;
+
+        Ok(xpub.to_pub())
+    }
+}
+
+impl fmt::Display for Descriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "pkh({}", self.xpub)?;
+        for child in &self.path {
+            write!(f, "/{child}")?;
+        }
+        write!(f, "/*)")
+    }
+}
+
+impl FromStr for Descriptor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let inner = s
+            .trim()
+            .strip_prefix("pkh(")
+            .and_then(|s| s.strip_suffix(')'))
+            .ok_or_else(|| Error::Unsupported(s.to_owned()))?;
+        let inner = inner
+            .strip_suffix("/*")
+            .ok_or_else(|| Error::Wildcard(s.to_owned()))?;
+
+        let mut parts = inner.split('/');
+        let xpub = parts
+            .next()
+            .ok_or_else(|| Error::Unsupported(s.to_owned()))?
+            .parse::<ExtendedPubKey>()?;
+        let path = parts
+            .map(ChildNumber::from_str)
+            .collect::<Result<DerivationPath, _>>()?;
+
+        if path.as_ref().iter().any(|c| c.is_hardened()) {
+            return Err(Error::Hardened(s.to_owned()));
+        }
+        Ok(Self { xpub, path })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XPUB: &str = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+
+    #[test]
+    fn test_parse_display() {
+        let s = format!("pkh({XPUB}/0/*)");
+        let desc = s.parse::<Descriptor>().unwrap();
+
+        assert_eq!(desc.xpub.to_string(), XPUB);
+        assert_eq!(desc.path, DerivationPath::from(vec![ChildNumber::from(0)]));
+        assert_eq!(desc.to_string(), s);
+
+        let desc = format!("pkh({XPUB}/*)").parse::<Descriptor>().unwrap();
+        assert!(desc.path.is_empty());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(matches!(
+            format!("wpkh({XPUB}/0/*)").parse::<Descriptor>(),
+            Err(Error::Unsupported(_))
+        ));
+        assert!(matches!(
+            format!("pkh({XPUB}/0/1)").parse::<Descriptor>(),
+            Err(Error::Wildcard(_))
+        ));
+        assert!(matches!(
+            format!("pkh({XPUB}/0'/*)").parse::<Descriptor>(),
+            Err(Error::Hardened(_))
+        ));
+        assert!(matches!(
+            "pkh(xpub/0/*)".parse::<Descriptor>(),
+            Err(Error::Key(_))
+        ));
+    }
+
+    #[test]
+    fn test_derive() {
+        let secp = Secp256k1::verification_only();
+        let desc = format!("pkh({XPUB}/1/*)").parse::<Descriptor>().unwrap();
+        let xpub = XPUB.parse::<ExtendedPubKey>().unwrap();
+
+        for i in 0..4 {
+            let child = xpub
+                .derive_pub(&secp, &vec![ChildNumber::from(1), ChildNumber::from(i)])
+                .unwrap();
+            let expected = Address::p2pkh(&child.to_pub(), Network::Bitcoin);
+
+            assert_eq!(desc.derive(&secp, i, Network::Bitcoin).unwrap(), expected);
+        }
+    }
+}
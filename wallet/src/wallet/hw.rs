@@ -1,12 +1,26 @@
-use std::{ops::Range, str::FromStr};
+pub mod hwi;
 
-use bitcoin::{util::bip32::DerivationPath, Address};
+use std::{fmt, ops::Range, str::FromStr};
+
+use bitcoin::blockdata::interpreter;
+use bitcoin::blockdata::script::Builder;
+use bitcoin::hashes::Hash as _;
+use bitcoin::psbt::{self, PartiallySignedTransaction, PsbtSighashType};
+use bitcoin::secp256k1::{self, Message, Secp256k1};
+use bitcoin::util::bip32::{self, ChildNumber, DerivationPath, ExtendedPrivKey};
+use bitcoin::util::cash_schnorr::{self, CashSchnorrSig};
+use bitcoin::util::ecdsa::EcdsaSig;
+use bitcoin::util::sighash::{self, forkid_script_code, ForkIdSighashType, Prevouts, SighashCache};
+use bitcoin::{Address, OutPoint, Transaction};
 use nakamoto_common::bitcoin;
 
 pub use coldcard::protocol::AddressFormat;
+pub use hwi::Hwi;
 
 use thiserror::Error;
 
+use crate::wallet::tx::UnsignedTx;
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("no hardware device found")]
@@ -19,6 +33,70 @@ pub enum Error {
     DerivationPath(coldcard::protocol::derivation_path::Error),
     #[error("device error: {0}")]
     Device(#[from] coldcard::Error),
+    #[error("no signing device configured")]
+    NoSigner,
+    #[error("no derivation path known for input {0}")]
+    UnknownInput(OutPoint),
+    #[error("device did not sign input {0}")]
+    MissingSignature(OutPoint),
+    #[error("key derivation error: {0}")]
+    Bip32(#[from] bip32::Error),
+    #[error("psbt error: {0}")]
+    Psbt(#[from] psbt::Error),
+    #[error(transparent)]
+    Hwi(#[from] hwi::Error),
+    #[error("sighash error: {0}")]
+    Sighash(#[from] sighash::Error),
+    #[error("signing error: {0}")]
+    Schnorr(#[from] cash_schnorr::Error),
+    #[error("signing error: {0}")]
+    Secp256k1(#[from] secp256k1::Error),
+    #[error("signed input {0} doesn't validate: {1}")]
+    Invalid(OutPoint, interpreter::Error),
+}
+
+/// Signature scheme used when signing with a private key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureType {
+    /// Schnorr signatures. These are smaller than ECDSA signatures, and hence cheaper.
+    Schnorr,
+    /// ECDSA signatures.
+    Ecdsa,
+}
+
+impl Default for SignatureType {
+    fn default() -> Self {
+        Self::Schnorr
+    }
+}
+
+impl fmt::Display for SignatureType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Schnorr => write!(f, "schnorr"),
+            Self::Ecdsa => write!(f, "ecdsa"),
+        }
+    }
+}
+
+impl FromStr for SignatureType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "schnorr" => Ok(Self::Schnorr),
+            "ecdsa" => Ok(Self::Ecdsa),
+            _ => Err(format!("unknown signature type `{}`", s)),
+        }
+    }
+}
+
+/// Signs transactions.
+pub enum Signer {
+    /// A hardware device, over HWI. Devices choose their own signature scheme.
+    Hwi(Hwi),
+    /// An extended private key, from which our derivation path starts.
+    Key(ExtendedPrivKey),
 }
 
 pub struct Hw {
@@ -26,6 +104,10 @@ pub struct Hw {
     device: Option<coldcard::Coldcard>,
     /// Hardware device address derivation path.
     hd_path: DerivationPath,
+    /// Signer, if any.
+    signer: Option<Signer>,
+    /// Signature scheme used when signing with a private key.
+    signature_type: SignatureType,
 }
 
 impl Hw {
@@ -33,9 +115,50 @@ impl Hw {
         Self {
             hd_path,
             device: None,
+            signer: None,
+            signature_type: SignatureType::default(),
         }
     }
 
+    /// Sign transactions with the given signer.
+    pub fn with_signer(mut self, signer: Signer) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// Use the given signature scheme when signing with a private key.
+    pub fn with_signature_type(mut self, signature_type: SignatureType) -> Self {
+        self.signature_type = signature_type;
+        self
+    }
+
+    /// Whether a signing device is configured.
+    pub fn can_sign(&self) -> bool {
+        self.signer.is_some()
+    }
+
+    /// Sign a transaction with the configured signer. When signing on a device, this blocks
+    /// until the user confirms or rejects the transaction on the device.
+    ///
+    /// Each input is signed with the key at the given index under our derivation path. The
+    /// signed inputs are then checked to validate, so that a faulty signer is caught before
+    /// the transaction is broadcast.
+    pub fn sign(
+        &mut self,
+        unsigned: &UnsignedTx,
+        indices: &[Option<usize>],
+    ) -> Result<Transaction, Error> {
+        let tx = match self.signer.as_mut().ok_or(Error::NoSigner)? {
+            Signer::Hwi(hwi) => sign_psbt(hwi, &self.hd_path, unsigned, indices),
+            Signer::Key(xprv) => {
+                sign_with_key(xprv, &self.hd_path, self.signature_type, unsigned, indices)
+            }
+        }?;
+        verify(&tx, unsigned)?;
+
+        Ok(tx)
+    }
+
     pub fn connect(&mut self) -> Result<&mut coldcard::Coldcard, Error> {
         if let Some(ref mut device) = self.device {
             return Ok(device);
@@ -82,3 +205,218 @@ impl Hw {
         Ok(addrs)
     }
 }
+
+/// Sign a transaction on an HWI device, by way of a PSBT.
+fn sign_psbt(
+    signer: &mut Hwi,
+    hd_path: &DerivationPath,
+    unsigned: &UnsignedTx,
+    indices: &[Option<usize>],
+) -> Result<Transaction, Error> {
+    let fingerprint = signer.fingerprint()?;
+    // Signatures commit to the value and token data of the spent outputs.
+    let mut psbt = PartiallySignedTransaction::from_unsigned_tx_and_utxos(
+        unsigned.tx.clone(),
+        unsigned
+            .inputs
+            .iter()
+            .map(|(_, txout)| txout.clone())
+            .collect(),
+    )?;
+    let mut keys = Vec::with_capacity(psbt.inputs.len());
+
+    for ((input, (outpoint, _)), ix) in psbt.inputs.iter_mut().zip(&unsigned.inputs).zip(indices) {
+        let ix = ix.ok_or(Error::UnknownInput(*outpoint))?;
+        let path = hd_path.child(ChildNumber::from_normal_idx(ix as u32)?);
+        let xpub = signer.xpub(&path)?;
+
+        input.sighash_type = Some(PsbtSighashType::from_u32(ForkIdSighashType::ALL.to_u32()));
+        input
+            .bip32_derivation
+            .insert(xpub.public_key, (fingerprint, path));
+
+        keys.push(xpub.to_pub());
+    }
+    // Merge the device's signatures back. This fails if the device returned a different
+    // transaction.
+    psbt.combine(signer.sign(&psbt)?)?;
+
+    let mut tx = psbt.unsigned_tx;
+
+    // Finalize the P2PKH inputs.
+    for ((txin, input), key) in tx.input.iter_mut().zip(&psbt.inputs).zip(&keys) {
+        let sig = input
+            .partial_sigs
+            .get(key)
+            .ok_or(Error::MissingSignature(txin.previous_output))?;
+
+        txin.script_sig = Builder::new()
+            .push_slice(&sig.to_vec())
+            .push_key(key)
+            .into_script();
+    }
+    Ok(tx)
+}
+
+/// Sign a transaction with keys derived from an extended private key.
+fn sign_with_key(
+    xprv: &ExtendedPrivKey,
+    hd_path: &DerivationPath,
+    signature_type: SignatureType,
+    unsigned: &UnsignedTx,
+    indices: &[Option<usize>],
+) -> Result<Transaction, Error> {
+    let secp = Secp256k1::new();
+    let mut cache = SighashCache::new(&unsigned.tx);
+    let mut tx = unsigned.tx.clone();
+    let spent = unsigned
+        .inputs
+        .iter()
+        .map(|(_, txout)| txout)
+        .collect::<Vec<_>>();
+    let prevouts = Prevouts::All(&spent);
+
+    for (i, ((outpoint, txout), ix)) in unsigned.inputs.iter().zip(indices).enumerate() {
+        let ix = ix.ok_or(Error::UnknownInput(*outpoint))?;
+        let path = hd_path.child(ChildNumber::from_normal_idx(ix as u32)?);
+        let key = xprv.derive_priv(&secp, &path)?.to_priv();
+        let script_code = forkid_script_code(&txout.script_pubkey, None)?;
+        let sighash =
+            cache.forkid_signature_hash(i, &script_code, &prevouts, ForkIdSighashType::ALL)?;
+
+        let sig = match signature_type {
+            SignatureType::Schnorr => CashSchnorrSig {
+                sig: cash_schnorr::sign(&secp, &sighash.into_inner(), &key.inner)?,
+                hash_ty: ForkIdSighashType::ALL.to_u32() as u8,
+            }
+            .to_vec(),
+            SignatureType::Ecdsa => EcdsaSig::sighash_all_forkid(
+                secp.sign_ecdsa(&Message::from_slice(&sighash[..])?, &key.inner),
+            )
+            .to_vec(),
+        };
+        tx.input[i].script_sig = Builder::new()
+            .push_slice(&sig)
+            .push_key(&key.public_key(&secp))
+            .into_script();
+    }
+    Ok(tx)
+}
+
+/// Check that each input of a signed transaction validly spends its output.
+fn verify(tx: &Transaction, unsigned: &UnsignedTx) -> Result<(), Error> {
+    let spent = unsigned
+        .inputs
+        .iter()
+        .map(|(_, txout)| txout.clone())
+        .collect::<Vec<_>>();
+
+    for (ix, txin) in tx.input.iter().enumerate() {
+        interpreter::verify_input(tx, ix, &spent)
+            .map_err(|err| Error::Invalid(txin.previous_output, err))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::blockdata::script::Instruction;
+    use bitcoin::{Network, PackedLockTime, TxIn, TxOut};
+
+    #[test]
+    fn test_sign_with_key() {
+        let secp = Secp256k1::new();
+        let xprv = ExtendedPrivKey::new_master(Network::Bitcoin, &[7; 32]).unwrap();
+        let hd_path = "m/44'/145'/0'/0".parse::<DerivationPath>().unwrap();
+        let key = xprv
+            .derive_priv(&secp, &hd_path.child(ChildNumber::from(3)))
+            .unwrap()
+            .to_priv();
+        let pubkey = key.public_key(&secp);
+        let txout = TxOut {
+            value: 10_000,
+            script_pubkey: Address::p2pkh(&pubkey, Network::Bitcoin).script_pubkey(),
+            token: None,
+        };
+        let outpoint = OutPoint::default();
+        let unsigned = UnsignedTx {
+            tx: Transaction {
+                version: 2,
+                lock_time: PackedLockTime::ZERO,
+                input: vec![TxIn {
+                    previous_output: outpoint,
+                    ..TxIn::default()
+                }],
+                output: vec![TxOut {
+                    value: 9_000,
+                    ..txout.clone()
+                }],
+            },
+            inputs: vec![(outpoint, txout.clone())],
+            fee: 1_000,
+            change: None,
+        };
+        let sighash = SighashCache::new(&unsigned.tx)
+            .forkid_signature_hash(
+                0,
+                &txout.script_pubkey,
+                &Prevouts::One(0, &txout),
+                ForkIdSighashType::ALL,
+            )
+            .unwrap();
+
+        for signature_type in [SignatureType::Schnorr, SignatureType::Ecdsa] {
+            let tx = sign_with_key(&xprv, &hd_path, signature_type, &unsigned, &[Some(3)]).unwrap();
+            let pushes = tx.input[0]
+                .script_sig
+                .instructions()
+                .map(|i| match i.unwrap() {
+                    Instruction::PushBytes(bytes) => bytes.to_vec(),
+                    Instruction::Op(op) => panic!("unexpected opcode {}", op),
+                })
+                .collect::<Vec<_>>();
+            let (hash_ty, sig) = pushes[0].split_last().unwrap();
+
+            assert_eq!(*hash_ty as u32, ForkIdSighashType::ALL.to_u32());
+            assert_eq!(pushes[1], pubkey.to_bytes());
+
+            match signature_type {
+                SignatureType::Schnorr => {
+                    let sig = cash_schnorr::Signature::from_slice(sig).unwrap();
+                    assert!(cash_schnorr::verify(
+                        &secp,
+                        &sighash.into_inner(),
+                        &sig,
+                        &pubkey.inner
+                    ));
+                }
+                SignatureType::Ecdsa => {
+                    let sig = secp256k1::ecdsa::Signature::from_der(sig).unwrap();
+                    let msg = Message::from_slice(&sighash[..]).unwrap();
+                    assert!(secp.verify_ecdsa(&msg, &sig, &pubkey.inner).is_ok());
+                }
+            }
+            verify(&tx, &unsigned).unwrap();
+
+            // Signatures commit to the outputs.
+            let mut tampered = tx.clone();
+            tampered.output[0].value -= 1;
+            assert!(matches!(
+                verify(&tampered, &unsigned),
+                Err(Error::Invalid(_, interpreter::Error::SigNullFail))
+            ));
+        }
+        // Inputs must belong to our derivation path.
+        assert!(matches!(
+            sign_with_key(
+                &xprv,
+                &hd_path,
+                SignatureType::default(),
+                &unsigned,
+                &[None]
+            ),
+            Err(Error::UnknownInput(_))
+        ));
+    }
+}
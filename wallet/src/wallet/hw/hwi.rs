@@ -0,0 +1,200 @@
+//! Hardware wallet signing over the HWI bridge.
+//!
+//! Talks to Ledger, Trezor and other devices supported by the `hwi` command-line tool, which
+//! is expected to be installed separately.
+use std::io;
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Output};
+
+use nakamoto_common::bitcoin::psbt::PartiallySignedTransaction;
+use nakamoto_common::bitcoin::util::bip32::{DerivationPath, ExtendedPubKey, Fingerprint};
+use nakamoto_common::bitcoin::Network;
+
+use serde_json::Value;
+use thiserror::Error;
+
+/// Default name of the HWI executable.
+pub const DEFAULT_BINARY: &str = "hwi";
+
+/// An HWI error.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("failed to run hwi: {0}")]
+    Spawn(#[from] io::Error),
+    #[error("hwi error: {0}")]
+    Command(String),
+    #[error("hwi failed with {0}: {1}")]
+    Failed(ExitStatus, String),
+    #[error("invalid hwi output: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid hwi output: missing or invalid `{0}`")]
+    Decode(&'static str),
+    #[error("no `{0}` device found")]
+    NoDevice(String),
+}
+
+/// A hardware device, accessed through the `hwi` command-line tool.
+#[derive(Debug, Clone)]
+pub struct Hwi {
+    /// Path to the `hwi` executable.
+    binary: PathBuf,
+    /// Device type, eg. `ledger` or `trezor`.
+    device_type: String,
+    /// Network the device is used on.
+    network: Network,
+    /// Fingerprint of the device's master key, once known.
+    fingerprint: Option<Fingerprint>,
+}
+
+impl Hwi {
+    /// Create a new HWI device of the given type.
+    pub fn new(binary: impl Into<PathBuf>, device_type: impl ToString, network: Network) -> Self {
+        Self {
+            binary: binary.into(),
+            device_type: device_type.to_string(),
+            network,
+            fingerprint: None,
+        }
+    }
+
+    /// Get the device's master key fingerprint, looking up the device if needed.
+    pub fn fingerprint(&mut self) -> Result<Fingerprint, Error> {
+        if let Some(fingerprint) = self.fingerprint {
+            return Ok(fingerprint);
+        }
+        let devices = self.run(&["enumerate"])?;
+        let device = devices
+            .as_array()
+            .ok_or(Error::Decode("devices"))?
+            .iter()
+            .find(|d| d["type"].as_str() == Some(self.device_type.as_str()))
+            .ok_or_else(|| Error::NoDevice(self.device_type.clone()))?;
+
+        if let Some(err) = device["error"].as_str() {
+            return Err(Error::Command(err.to_owned()));
+        }
+        let fingerprint = device["fingerprint"]
+            .as_str()
+            .and_then(|s| s.parse().ok())
+            .ok_or(Error::Decode("fingerprint"))?;
+
+        Ok(*self.fingerprint.insert(fingerprint))
+    }
+
+    /// Get the extended public key at the given derivation path.
+    pub fn xpub(&mut self, path: &DerivationPath) -> Result<ExtendedPubKey, Error> {
+        let path = path.to_string();
+        let output = self.device(&["getxpub", path.as_str()])?;
+
+        output["xpub"]
+            .as_str()
+            .and_then(|s| s.parse().ok())
+            .ok_or(Error::Decode("xpub"))
+    }
+
+    /// Sign a PSBT on the device. This blocks until the user confirms or rejects the
+    /// transaction on the device.
+    pub fn sign(
+        &mut self,
+        psbt: &PartiallySignedTransaction,
+    ) -> Result<PartiallySignedTransaction, Error> {
+        let psbt = psbt.to_string();
+        let output = self.device(&["signtx", psbt.as_str()])?;
+
+        output["psbt"]
+            .as_str()
+            .and_then(|s| s.parse().ok())
+            .ok_or(Error::Decode("psbt"))
+    }
+
+    /// Run a command against our device.
+    fn device(&mut self, args: &[&str]) -> Result<Value, Error> {
+        let fingerprint = self.fingerprint()?.to_string();
+        let chain = match self.network {
+            Network::Bitcoin => "main",
+            Network::Regtest => "regtest",
+            _ => "test",
+        };
+        let mut cmd = vec!["--fingerprint", fingerprint.as_str(), "--chain", chain];
+        cmd.extend_from_slice(args);
+
+        self.run(&cmd)
+    }
+
+    /// Run an `hwi` command and return its parsed output.
+    fn run(&self, args: &[&str]) -> Result<Value, Error> {
+        log::debug!("Running `{} {}`..", self.binary.display(), args.join(" "));
+
+        let output = Command::new(&self.binary).args(args).output()?;
+
+        check(&output)
+    }
+}
+
+/// Check the outcome of an `hwi` command, and parse its output if it succeeded.
+fn check(output: &Output) -> Result<Value, Error> {
+    if output.status.success() {
+        return parse(&output.stdout);
+    }
+    // Errors are usually reported on standard output, along with the failed status.
+    if let Err(err @ Error::Command(_)) = parse(&output.stdout) {
+        return Err(err);
+    }
+    Err(Error::Failed(
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+    ))
+}
+
+/// Parse the output of an `hwi` command, turning reported errors into an [`Error`].
+fn parse(output: &[u8]) -> Result<Value, Error> {
+    let value: Value = serde_json::from_slice(output)?;
+
+    if let Some(err) = value.get("error") {
+        return Err(Error::Command(
+            err.as_str()
+                .map(ToOwned::to_owned)
+                .unwrap_or_else(|| err.to_string()),
+        ));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let value = parse(br#"{"xpub": "xpub123"}"#).unwrap();
+        assert_eq!(value["xpub"], "xpub123");
+
+        let err = parse(br#"{"error": "Device is locked", "code": -13}"#).unwrap_err();
+        assert!(matches!(err, Error::Command(msg) if msg == "Device is locked"));
+
+        assert!(matches!(parse(b"not json"), Err(Error::Json(_))));
+    }
+
+    #[test]
+    fn test_check() {
+        use std::os::unix::process::ExitStatusExt as _;
+
+        let output = |code: i32, stdout: &[u8], stderr: &[u8]| Output {
+            status: ExitStatus::from_raw(code << 8),
+            stdout: stdout.to_vec(),
+            stderr: stderr.to_vec(),
+        };
+        assert!(check(&output(0, br#"{"xpub": "xpub123"}"#, b"")).is_ok());
+
+        let err = check(&output(1, br#"{"error": "Device is locked"}"#, b"")).unwrap_err();
+        assert!(matches!(err, Error::Command(msg) if msg == "Device is locked"));
+
+        match check(&output(2, b"", b"usage: hwi\n")).unwrap_err() {
+            Error::Failed(status, msg) => {
+                assert_eq!(status.code(), Some(2));
+                assert_eq!(msg, "usage: hwi");
+            }
+            err => panic!("unexpected error: {err}"),
+        }
+    }
+}
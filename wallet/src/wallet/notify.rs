@@ -0,0 +1,416 @@
+//! Notification hooks, run when wallet events occur.
+//!
+//! A hook either runs a shell command, passing it the notification as JSON on its standard
+//! input, or POSTs the notification to an HTTP URL. Notifications are delivered in order,
+//! from a dedicated thread, and failed deliveries are retried with exponential backoff.
+use std::io::{self, BufRead, BufReader, Write as _};
+use std::net::{self, ToSocketAddrs};
+use std::process::{self, Stdio};
+use std::str::FromStr;
+use std::time::Duration;
+use std::{fmt, thread};
+
+use crossbeam_channel as chan;
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use nakamoto_common::bitcoin::{Address, Txid};
+use nakamoto_common::block::Height;
+
+/// Number of times delivery of a notification to a hook is attempted.
+pub const MAX_ATTEMPTS: usize = 5;
+/// Time to wait before retrying a failed delivery. Doubles with every attempt.
+pub const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// Time to wait for an HTTP hook to respond.
+pub const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A hook error.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid hook url `{0}`")]
+    Url(String),
+    #[error("hook command failed with {0}")]
+    Command(process::ExitStatus),
+    #[error("hook url responded with `{0}`")]
+    Http(String),
+}
+
+/// A notification of a wallet event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notification {
+    /// A payment was received.
+    PaymentReceived {
+        /// The paying transaction.
+        txid: Txid,
+        /// Total value received, in satoshis.
+        value: u64,
+        /// Addresses that received the payment.
+        addresses: Vec<Address>,
+        /// Height of the block including the transaction, if confirmed.
+        height: Option<Height>,
+    },
+    /// A transaction of ours, previously unconfirmed, was included in a block.
+    TransactionConfirmed {
+        /// The confirmed transaction.
+        txid: Txid,
+        /// Height of the block including the transaction.
+        height: Height,
+    },
+    /// Blocks were disconnected from the chain, un-confirming transactions of ours.
+    Reorg {
+        /// Height of the first disconnected block.
+        height: Height,
+        /// Transactions that are no longer confirmed.
+        txids: Vec<Txid>,
+    },
+}
+
+impl Notification {
+    /// The kind of event, as used in payloads.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::PaymentReceived { .. } => "payment_received",
+            Self::TransactionConfirmed { .. } => "transaction_confirmed",
+            Self::Reorg { .. } => "reorg",
+        }
+    }
+
+    /// The JSON payload sent to hooks.
+    pub fn to_json(&self) -> Value {
+        match self {
+            Self::PaymentReceived {
+                txid,
+                value,
+                addresses,
+                height,
+            } => json!({
+                "event": self.kind(),
+                "txid": txid.to_string(),
+                "value": value,
+                "addresses": addresses.iter().map(|a| a.to_string()).collect::<Vec<_>>(),
+                "height": height,
+            }),
+            Self::TransactionConfirmed { txid, height } => json!({
+                "event": self.kind(),
+                "txid": txid.to_string(),
+                "height": height,
+            }),
+            Self::Reorg { height, txids } => json!({
+                "event": self.kind(),
+                "height": height,
+                "txids": txids.iter().map(|t| t.to_string()).collect::<Vec<_>>(),
+            }),
+        }
+    }
+}
+
+/// An HTTP URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    /// Host name or address.
+    pub host: String,
+    /// Port.
+    pub port: u16,
+    /// Request path.
+    pub path: String,
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}:{}{}", self.host, self.port, self.path)
+    }
+}
+
+impl FromStr for Url {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::Url(s.to_owned());
+        let rest = s.strip_prefix("http://").ok_or_else(invalid)?;
+        let (authority, path) = match rest.find('/') {
+            Some(ix) => rest.split_at(ix),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+        })
+    }
+}
+
+/// A notification hook.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Hook {
+    /// Run a shell command.
+    Command(String),
+    /// POST to an HTTP URL.
+    Url(Url),
+}
+
+impl fmt::Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Command(cmd) => write!(f, "`{}`", cmd),
+            Self::Url(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+impl FromStr for Hook {
+    type Err = Error;
+
+    /// Parse an `http://` URL, or otherwise a shell command. HTTPS isn't supported; a
+    /// command hook, eg. using `curl`, can be used instead.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("http://") {
+            s.parse().map(Self::Url)
+        } else if s.starts_with("https://") {
+            Err(Error::Url(s.to_owned()))
+        } else {
+            Ok(Self::Command(s.to_owned()))
+        }
+    }
+}
+
+impl Hook {
+    /// Deliver a notification to the hook.
+    pub fn run(&self, notification: &Notification) -> Result<(), Error> {
+        let payload = notification.to_json().to_string();
+
+        match self {
+            Self::Command(cmd) => {
+                let mut child = process::Command::new("sh")
+                    .arg("-c")
+                    .arg(cmd)
+                    .env("NAKAMOTO_EVENT", notification.kind())
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .spawn()?;
+
+                if let Some(mut stdin) = child.stdin.take() {
+                    // The command may not read its input, so ignore write errors.
+                    stdin.write_all(payload.as_bytes()).ok();
+                }
+                let status = child.wait()?;
+
+                if !status.success() {
+                    return Err(Error::Command(status));
+                }
+                Ok(())
+            }
+            Self::Url(url) => {
+                let addr = (url.host.as_str(), url.port)
+                    .to_socket_addrs()?
+                    .next()
+                    .ok_or_else(|| Error::Url(url.to_string()))?;
+                let mut stream = net::TcpStream::connect_timeout(&addr, HTTP_TIMEOUT)?;
+
+                stream.set_read_timeout(Some(HTTP_TIMEOUT))?;
+                stream.set_write_timeout(Some(HTTP_TIMEOUT))?;
+
+                write!(
+                    stream,
+                    "POST {} HTTP/1.1\r\n\
+                     Host: {}\r\n\
+                     Content-Type: application/json\r\n\
+                     Content-Length: {}\r\n\
+                     Connection: close\r\n\r\n{}",
+                    url.path,
+                    url.host,
+                    payload.len(),
+                    payload
+                )?;
+
+                let mut status = String::new();
+                BufReader::new(stream).read_line(&mut status)?;
+
+                let status = status.trim();
+                match status.split_whitespace().nth(1) {
+                    Some(code) if code.starts_with('2') => Ok(()),
+                    _ => Err(Error::Http(status.to_owned())),
+                }
+            }
+        }
+    }
+}
+
+/// Sends notifications to the hooks' delivery thread.
+#[derive(Debug, Clone, Default)]
+pub struct Notifier {
+    sender: Option<chan::Sender<Notification>>,
+}
+
+impl Notifier {
+    /// Start delivering notifications to the given hooks in the background. Without hooks,
+    /// notifications are dropped.
+    pub fn spawn(hooks: Vec<Hook>) -> Self {
+        if hooks.is_empty() {
+            return Self::default();
+        }
+        let (sender, receiver) = chan::unbounded();
+
+        thread::spawn(move || deliver(&hooks, receiver, INITIAL_BACKOFF));
+
+        Self {
+            sender: Some(sender),
+        }
+    }
+
+    /// Whether there are any hooks to notify.
+    pub fn is_enabled(&self) -> bool {
+        self.sender.is_some()
+    }
+
+    /// Queue a notification for delivery.
+    pub fn notify(&self, notification: Notification) {
+        if let Some(sender) = &self.sender {
+            log::debug!("Notifying hooks of {} event", notification.kind());
+
+            if sender.send(notification).is_err() {
+                log::error!("Notification hooks are no longer running");
+            }
+        }
+    }
+}
+
+/// Deliver notifications to the hooks, until the channel is closed.
+fn deliver(hooks: &[Hook], notifications: chan::Receiver<Notification>, backoff: Duration) {
+    for notification in notifications {
+        for hook in hooks {
+            retry(hook, &notification, backoff);
+        }
+    }
+}
+
+/// Deliver a notification to a hook, retrying with exponential backoff if it fails.
+/// Returns whether the notification was delivered.
+fn retry(hook: &Hook, notification: &Notification, backoff: Duration) -> bool {
+    let mut delay = backoff;
+
+    for attempt in 1..=MAX_ATTEMPTS {
+        match hook.run(notification) {
+            Ok(()) => return true,
+            Err(err) => {
+                log::warn!(
+                    "Notification hook {} failed (attempt {}/{}): {}",
+                    hook,
+                    attempt,
+                    MAX_ATTEMPTS,
+                    err
+                );
+            }
+        }
+        if attempt < MAX_ATTEMPTS {
+            thread::sleep(delay);
+            delay *= 2;
+        }
+    }
+    log::error!(
+        "Giving up on delivering {} event to hook {}",
+        notification.kind(),
+        hook
+    );
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nakamoto_common::bitcoin::hashes::Hash;
+
+    fn notification() -> Notification {
+        Notification::TransactionConfirmed {
+            txid: Txid::all_zeros(),
+            height: 144,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "http://localhost:8080/hooks/wallet"
+                .parse::<Hook>()
+                .unwrap(),
+            Hook::Url(Url {
+                host: String::from("localhost"),
+                port: 8080,
+                path: String::from("/hooks/wallet"),
+            })
+        );
+        assert_eq!(
+            "http://example.com".parse::<Hook>().unwrap(),
+            Hook::Url(Url {
+                host: String::from("example.com"),
+                port: 80,
+                path: String::from("/"),
+            })
+        );
+        assert_eq!(
+            "notify-send paid".parse::<Hook>().unwrap(),
+            Hook::Command(String::from("notify-send paid"))
+        );
+        assert!("https://example.com".parse::<Hook>().is_err());
+        assert!("http://:80/".parse::<Hook>().is_err());
+        assert!("http://example.com:port/".parse::<Hook>().is_err());
+    }
+
+    #[test]
+    fn test_payload() {
+        let payload = notification().to_json();
+
+        assert_eq!(payload["event"], "transaction_confirmed");
+        assert_eq!(payload["height"], 144);
+        assert_eq!(payload["txid"], Txid::all_zeros().to_string());
+    }
+
+    #[test]
+    fn test_command() {
+        let hook = Hook::Command(String::from(
+            r#"test "$NAKAMOTO_EVENT" = transaction_confirmed && grep -q '"height":144'"#,
+        ));
+        hook.run(&notification()).unwrap();
+
+        let hook = Hook::Command(String::from("exit 3"));
+        assert!(matches!(hook.run(&notification()), Err(Error::Command(_))));
+        assert!(!retry(&hook, &notification(), Duration::ZERO));
+    }
+
+    #[test]
+    fn test_url() {
+        let listener = net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+
+            for status in ["500 Internal Server Error", "204 No Content"] {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+
+                reader.read_line(&mut request).unwrap();
+                requests.push(request);
+
+                write!(&stream, "HTTP/1.1 {}\r\n\r\n", status).unwrap();
+            }
+            requests
+        });
+        let hook = Hook::Url(Url {
+            host: String::from("127.0.0.1"),
+            port,
+            path: String::from("/notify"),
+        });
+
+        assert!(retry(&hook, &notification(), Duration::ZERO));
+        assert_eq!(server.join().unwrap(), vec!["POST /notify HTTP/1.1\r\n"; 2]);
+    }
+}
@@ -0,0 +1,141 @@
+//! Resolution of payment names, eg. CashAccounts or LNS names, to addresses.
+//!
+//! Recipients that are neither contacts nor valid cashaddrs are passed to the registered
+//! [`Resolver`]s, in registration order, until one of them resolves the name. Since names
+//! are resolved by third parties, resolved addresses are shown to the user for confirmation
+//! before a transaction paying them is built.
+use std::fmt;
+
+use thiserror::Error;
+
+use nakamoto_common::bitcoin::{Address, Network};
+
+/// A resolution error.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("{resolver}: {reason}")]
+    Failed {
+        /// Name of the resolver that failed.
+        resolver: String,
+        /// Reason for the failure.
+        reason: String,
+    },
+}
+
+/// Resolves names to addresses.
+pub trait Resolver {
+    /// Name of the resolver, shown along with the addresses it resolves.
+    fn name(&self) -> &str;
+    /// Resolve a name to an address on the given network. Returns `None` if the name isn't
+    /// handled by this resolver, or isn't registered.
+    fn resolve(&self, name: &str, network: Network) -> Result<Option<Address>, String>;
+}
+
+/// An address resolved from a name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolved {
+    /// The resolved address.
+    pub address: Address,
+    /// Name of the resolver that resolved it.
+    pub resolver: String,
+}
+
+impl fmt::Display for Resolved {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (via {})", self.address, self.resolver)
+    }
+}
+
+/// The registered resolvers.
+#[derive(Default)]
+pub struct Resolvers {
+    resolvers: Vec<Box<dyn Resolver>>,
+}
+
+impl Resolvers {
+    /// Register a resolver, to be tried after the ones already registered.
+    pub fn register(&mut self, resolver: impl Resolver + 'static) {
+        self.resolvers.push(Box::new(resolver));
+    }
+
+    /// Whether no resolvers are registered.
+    pub fn is_empty(&self) -> bool {
+        self.resolvers.is_empty()
+    }
+
+    /// Resolve a name with the first resolver that handles it. Returns `None` if none do.
+    pub fn resolve(&self, name: &str, network: Network) -> Result<Option<Resolved>, Error> {
+        for resolver in &self.resolvers {
+            match resolver.resolve(name, network) {
+                Ok(Some(address)) => {
+                    return Ok(Some(Resolved {
+                        address,
+                        resolver: resolver.name().to_owned(),
+                    }));
+                }
+                Ok(None) => continue,
+                Err(reason) => {
+                    return Err(Error::Failed {
+                        resolver: resolver.name().to_owned(),
+                        reason,
+                    });
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nakamoto_test::block::gen;
+    use nakamoto_test::fastrand;
+
+    struct Suffix(&'static str, Address);
+
+    impl Resolver for Suffix {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn resolve(&self, name: &str, _network: Network) -> Result<Option<Address>, String> {
+            if name == "broken" {
+                return Err(String::from("service unavailable"));
+            }
+            Ok(name.ends_with(self.0).then(|| self.1.clone()))
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut rng = fastrand::Rng::new();
+        let alice = Address::from_script(&gen::script(&mut rng), Network::Bitcoin).unwrap();
+        let bob = Address::from_script(&gen::script(&mut rng), Network::Bitcoin).unwrap();
+        let mut resolvers = Resolvers::default();
+
+        assert!(resolvers.is_empty());
+        resolvers.register(Suffix(".bch", alice.clone()));
+        resolvers.register(Suffix("#100", bob.clone()));
+
+        assert_eq!(
+            resolvers.resolve("alice.bch", Network::Bitcoin).unwrap(),
+            Some(Resolved {
+                address: alice,
+                resolver: String::from(".bch"),
+            })
+        );
+        assert_eq!(
+            resolvers
+                .resolve("bob#100", Network::Bitcoin)
+                .unwrap()
+                .map(|r| r.address),
+            Some(bob)
+        );
+        assert_eq!(resolvers.resolve("carol", Network::Bitcoin).unwrap(), None);
+        assert!(matches!(
+            resolvers.resolve("broken", Network::Bitcoin),
+            Err(Error::Failed { .. })
+        ));
+    }
+}
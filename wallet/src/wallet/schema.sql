@@ -4,15 +4,60 @@ CREATE TABLE IF NOT EXISTS "utxos" (
   "vout"           integer     NOT NULL,
   "address"        text        NOT NULL REFERENCES "address" ("id"),
   "value"          integer     NOT NULL,
+  "token"          blob        DEFAULT NULL,
   "date"           integer     NOT NULL,
+  "height"         integer     DEFAULT NULL,
+  "spent"          text        DEFAULT NULL,
+  "spent_height"   integer     DEFAULT NULL,
+  "coinbase"       integer     NOT NULL DEFAULT false,
 
   UNIQUE ("txid", "vout")
 ) STRICT;
 
+CREATE TABLE IF NOT EXISTS "accounts" (
+  "id"          text             PRIMARY KEY,
+  "descriptor"  text             NOT NULL UNIQUE,
+  "gap_limit"   integer          NOT NULL
+) STRICT;
+
 CREATE TABLE IF NOT EXISTS "addresses" (
   "id"          text             PRIMARY KEY,
-  "index"       integer          NOT NULL UNIQUE,
+  "index"       integer          NOT NULL,
   "label"       text             DEFAULT NULL,
   "received"    integer          NOT NULL DEFAULT 0,
-  "used"        integer          NOT NULL DEFAULT false
+  "used"        integer          NOT NULL DEFAULT false,
+  "account"     text             DEFAULT NULL REFERENCES "accounts" ("id"),
+
+  UNIQUE ("account", "index")
+) STRICT;
+
+CREATE TABLE IF NOT EXISTS "broadcasts" (
+  "txid"        text             PRIMARY KEY,
+  "tx"          blob             NOT NULL,
+  "date"        integer          NOT NULL
+) STRICT;
+
+CREATE TABLE IF NOT EXISTS "sent" (
+  "txid"        text             PRIMARY KEY,
+  "tx"          blob             NOT NULL,
+  "fee"         integer          NOT NULL,
+  "date"        integer          NOT NULL
+) STRICT;
+
+CREATE TABLE IF NOT EXISTS "contacts" (
+  "label"       text             PRIMARY KEY,
+  "address"     text             NOT NULL
+) STRICT;
+
+CREATE TABLE IF NOT EXISTS "tokens" (
+  "category"    text             PRIMARY KEY,
+  "name"        text             NOT NULL,
+  "symbol"      text             DEFAULT NULL,
+  "decimals"    integer          NOT NULL DEFAULT 0,
+  "icon"        text             DEFAULT NULL
+) STRICT;
+
+CREATE TABLE IF NOT EXISTS "settings" (
+  "key"         text             PRIMARY KEY,
+  "value"       text             NOT NULL
 ) STRICT;
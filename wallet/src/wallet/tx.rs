@@ -0,0 +1,897 @@
+//! Transaction building and coin selection.
+//!
+//! Coins are selected with a branch-and-bound search for a changeless solution first, falling
+//! back to largest-first selection with a change output.
+//!
+//! Token outputs are funded by selecting UTXOs carrying the same tokens first. Leftover
+//! fungible tokens are returned to the change address.
+//!
+//! Arbitrary data, eg. a memo, can be attached to a transaction in an `OP_RETURN` output.
+//!
+//! Small coins can also be swept into a single output with [`consolidate`].
+//!
+//! Since transactions can't be replaced on Bitcoin Cash, a stuck transaction is sped up by
+//! spending one of its outputs in a child paying for both, with [`cpfp`].
+use std::collections::BTreeMap;
+
+use nakamoto_common::bitcoin::blockdata::token::{self, OutputData, Structure};
+use nakamoto_common::bitcoin::policy;
+use nakamoto_common::bitcoin::{
+    Address, OutPoint, PackedLockTime, Script, Sequence, TokenID, Transaction, TxIn, TxOut, VarInt,
+};
+use nakamoto_common::block::Height;
+use nakamoto_p2p::fsm::fees::FeeRate;
+
+use thiserror::Error;
+
+use crate::wallet::db::{self, Read as _};
+use crate::wallet::Db;
+
+/// P2PKH outputs below this value are not relayed by the network. See [`dust_limit`] for
+/// other outputs.
+pub const DUST_LIMIT: u64 = 546;
+/// Default fee rate, in satoshis per byte.
+pub const DEFAULT_FEE_RATE: FeeRate = 1;
+/// Default value below which coins are swept when consolidating, in satoshis.
+pub const DEFAULT_CONSOLIDATION_THRESHOLD: u64 = 10_000;
+/// Maximum size of the data carried by an `OP_RETURN` output, for it to be relayed. The
+/// output script, including the opcode and data push, is then at most 223 bytes.
+pub const MAX_DATA_SIZE: usize = 220;
+
+/// Size of the transaction version, lock time, and input and output counts.
+const TX_OVERHEAD_SIZE: usize = 10;
+/// Size of a signed P2PKH input, assuming the largest possible signature.
+const P2PKH_INPUT_SIZE: usize = 148;
+/// Size of a P2PKH output.
+const P2PKH_OUTPUT_SIZE: usize = 34;
+/// Maximum number of branches explored by the branch-and-bound search.
+const BNB_MAX_TRIES: usize = 100_000;
+
+/// A transaction building error.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("transaction has no outputs")]
+    NoOutputs,
+    #[error("output value of {0} is below the dust limit")]
+    Dust(u64),
+    #[error("insufficient funds: {needed} needed, {available} available")]
+    InsufficientFunds { needed: u64, available: u64 },
+    #[error("insufficient tokens of category {id}: {needed} needed, {available} available")]
+    InsufficientTokens {
+        id: TokenID,
+        needed: u64,
+        available: u64,
+    },
+    #[error("no output holds the requested NFT of category {0}")]
+    MissingNft(TokenID),
+    #[error("only {0} coin(s) below the threshold are worth consolidating")]
+    NothingToConsolidate(usize),
+    #[error("transaction already pays {0} sat/byte")]
+    FeeRateReached(FeeRate),
+    #[error("data of {0} bytes exceeds the {} byte limit", MAX_DATA_SIZE)]
+    DataTooLarge(usize),
+    #[error(transparent)]
+    Db(#[from] db::Error),
+}
+
+/// An unsigned transaction, along with the outputs it spends.
+#[derive(Debug, Clone)]
+pub struct UnsignedTx {
+    /// The transaction, with empty input scripts.
+    pub tx: Transaction,
+    /// The outputs spent by the transaction, in input order.
+    pub inputs: Vec<(OutPoint, TxOut)>,
+    /// The transaction fee, in satoshis.
+    pub fee: u64,
+    /// The index of the change output, if any.
+    pub change: Option<usize>,
+}
+
+impl UnsignedTx {
+    /// Total value paid by the transaction, excluding change and fees.
+    pub fn amount(&self) -> u64 {
+        self.tx
+            .output
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| Some(*i) != self.change)
+            .map(|(_, o)| o.value)
+            .sum()
+    }
+}
+
+/// Builds transactions paying to a set of outputs, selecting coins and computing change.
+#[derive(Debug, Clone)]
+pub struct TxBuilder {
+    outputs: Vec<TxOut>,
+    data: Option<Vec<u8>>,
+    change: Address,
+    fee_rate: FeeRate,
+}
+
+impl TxBuilder {
+    /// Create a new builder sending change to the given address.
+    pub fn new(change: Address, fee_rate: FeeRate) -> Self {
+        Self {
+            outputs: Vec::new(),
+            data: None,
+            change,
+            fee_rate,
+        }
+    }
+
+    /// Pay the given value to an address.
+    pub fn output(mut self, address: &Address, value: u64) -> Self {
+        self.outputs.push(TxOut {
+            value,
+            script_pubkey: address.script_pubkey(),
+            token: None,
+        });
+        self
+    }
+
+    /// Send tokens to an address. This can be a fungible token amount, an NFT, or both.
+    /// The output carries the minimum non-dust BCH value.
+    pub fn token_output(mut self, address: &Address, token: OutputData) -> Self {
+        let mut output = TxOut {
+            value: 0,
+            script_pubkey: address.script_pubkey(),
+            token: Some(token),
+        };
+        output.value = dust_limit(&output);
+
+        self.outputs.push(output);
+        self
+    }
+
+    /// Attach an `OP_RETURN` output carrying the given data, of at most [`MAX_DATA_SIZE`]
+    /// bytes. The data is pushed as-is, in a single push. Only one data output is attached.
+    pub fn data(mut self, data: Vec<u8>) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    /// Build an unsigned transaction, selecting coins from the wallet database. Coinbase
+    /// outputs that haven't matured as of the given tip aren't selected.
+    pub fn build_from_db(&self, db: &Db, tip: Height) -> Result<UnsignedTx, Error> {
+        self.build(spendable(db, tip)?)
+    }
+
+    /// Build an unsigned transaction, selecting coins from the given UTXOs.
+    ///
+    /// UTXOs carrying tokens are only selected to fund token outputs, so that tokens are
+    /// never burned.
+    pub fn build(&self, utxos: Vec<(OutPoint, TxOut)>) -> Result<UnsignedTx, Error> {
+        if self.outputs.is_empty() {
+            return Err(Error::NoOutputs);
+        }
+        if let Some(out) = self.outputs.iter().find(|o| o.value < dust_limit(o)) {
+            return Err(Error::Dust(out.value));
+        }
+        if let Some(data) = self.data.as_ref().filter(|d| d.len() > MAX_DATA_SIZE) {
+            return Err(Error::DataTooLarge(data.len()));
+        }
+        let (token_inputs, token_change) = self.select_tokens(&utxos)?;
+        let mut outputs = self.outputs.clone();
+        outputs.extend(token_change);
+        outputs.extend(self.data.as_ref().map(|data| TxOut {
+            value: 0,
+            script_pubkey: Script::new_op_return(data),
+            token: None,
+        }));
+
+        let input_fee = P2PKH_INPUT_SIZE as u64 * self.fee_rate;
+        let change_fee = P2PKH_OUTPUT_SIZE as u64 * self.fee_rate;
+        let size = TX_OVERHEAD_SIZE
+            + outputs.iter().map(output_size).sum::<usize>()
+            + token_inputs.len() * P2PKH_INPUT_SIZE;
+        let target = outputs.iter().map(|o| o.value).sum::<u64>() + size as u64 * self.fee_rate;
+        // The BCH carried by token inputs goes towards the target.
+        let funded = token_inputs.iter().map(|(_, o)| o.value).sum::<u64>();
+        let target = target.saturating_sub(funded);
+
+        // Only consider coins that are worth more than what it costs to spend them.
+        let mut candidates = utxos
+            .into_iter()
+            .filter(|(_, out)| !out.has_token() && out.value > input_fee)
+            .collect::<Vec<_>>();
+        candidates.sort_by(|(_, a), (_, b)| b.value.cmp(&a.value));
+
+        let values = candidates
+            .iter()
+            .map(|(_, out)| out.value - input_fee)
+            .collect::<Vec<_>>();
+        let available = values.iter().sum::<u64>();
+
+        if available < target {
+            return Err(Error::InsufficientFunds {
+                needed: target,
+                available,
+            });
+        }
+        // The cost of adding a change output now, and spending it later.
+        let cost_of_change = change_fee + input_fee;
+        let (selected, change) = if target == 0 {
+            // Token inputs cover everything. Keep the excess as change, if it's worth it.
+            let excess =
+                funded - outputs.iter().map(|o| o.value).sum::<u64>() - size as u64 * self.fee_rate;
+            let change = excess.checked_sub(change_fee).filter(|c| *c >= DUST_LIMIT);
+
+            (Vec::new(), change)
+        } else if let Some(selected) = branch_and_bound(&values, target, target + cost_of_change) {
+            (selected, None)
+        } else {
+            largest_first(&values, target, change_fee)
+        };
+
+        let inputs = token_inputs
+            .into_iter()
+            .chain(selected.into_iter().map(|i| candidates[i].clone()))
+            .collect::<Vec<_>>();
+        let change = change.map(|value| {
+            outputs.push(TxOut {
+                value,
+                script_pubkey: self.change.script_pubkey(),
+                token: None,
+            });
+            outputs.len() - 1
+        });
+        let fee = inputs.iter().map(|(_, o)| o.value).sum::<u64>()
+            - outputs.iter().map(|o| o.value).sum::<u64>();
+
+        let tx = Transaction {
+            version: 2,
+            lock_time: PackedLockTime::ZERO,
+            input: inputs
+                .iter()
+                .map(|(outpoint, _)| TxIn {
+                    previous_output: *outpoint,
+                    script_sig: Script::new(),
+                    sequence: Sequence::MAX,
+                })
+                .collect(),
+            output: outputs,
+        };
+
+        Ok(UnsignedTx {
+            tx,
+            inputs,
+            fee,
+            change,
+        })
+    }
+
+    /// Select the token UTXOs needed to fund the token outputs. Returns the selected UTXOs,
+    /// along with the outputs returning leftover fungible tokens to the change address.
+    #[allow(clippy::type_complexity)]
+    fn select_tokens(
+        &self,
+        utxos: &[(OutPoint, TxOut)],
+    ) -> Result<(Vec<(OutPoint, TxOut)>, Vec<TxOut>), Error> {
+        let mut selected: Vec<(OutPoint, TxOut)> = Vec::new();
+        let tokens = self
+            .outputs
+            .iter()
+            .filter_map(|o| o.token.as_ref())
+            .collect::<Vec<_>>();
+
+        // Each NFT sent must be held by one of our outputs.
+        for nft in tokens.iter().filter(|t| t.has_nft()) {
+            let utxo = utxos
+                .iter()
+                .filter(|(outpoint, _)| !selected.iter().any(|(o, _)| o == outpoint))
+                .find(|(_, out)| {
+                    out.token.as_ref().map_or(false, |t| {
+                        t.id == nft.id
+                            && t.has_nft()
+                            && t.capability() == nft.capability()
+                            && t.commitment == nft.commitment
+                    })
+                })
+                .ok_or(Error::MissingNft(nft.id))?;
+
+            selected.push(utxo.clone());
+        }
+
+        // Fungible amounts needed, per category.
+        let mut needed = BTreeMap::<TokenID, u64>::new();
+        for t in tokens.iter().filter(|t| t.has_amount()) {
+            *needed.entry(t.id).or_default() += t.amount as u64;
+        }
+        for (id, amount) in needed.iter() {
+            let mut available = fungible(&selected, id);
+            // Only pick outputs without NFTs, so that NFTs aren't moved unintentionally.
+            let mut candidates = utxos
+                .iter()
+                .filter(|(outpoint, _)| !selected.iter().any(|(o, _)| o == outpoint))
+                .filter(|(_, out)| {
+                    out.token
+                        .as_ref()
+                        .map_or(false, |t| t.id == *id && t.has_amount() && !t.has_nft())
+                })
+                .collect::<Vec<_>>();
+            candidates.sort_by_key(|(_, out)| {
+                std::cmp::Reverse(out.token.as_ref().map_or(0, |t| t.amount))
+            });
+
+            for utxo in candidates {
+                if available >= *amount {
+                    break;
+                }
+                available += utxo.1.token.as_ref().map_or(0, |t| t.amount as u64);
+                selected.push(utxo.clone());
+            }
+            if available < *amount {
+                return Err(Error::InsufficientTokens {
+                    id: *id,
+                    needed: *amount,
+                    available,
+                });
+            }
+        }
+
+        // Return leftover fungible tokens, including those held alongside NFTs.
+        let mut categories = selected
+            .iter()
+            .filter_map(|(_, o)| o.token.as_ref().map(|t| t.id))
+            .collect::<Vec<_>>();
+        categories.sort();
+        categories.dedup();
+
+        let mut change = Vec::new();
+        for id in categories {
+            let leftover = fungible(&selected, &id) - needed.get(&id).copied().unwrap_or(0);
+
+            if leftover > 0 {
+                let mut output = TxOut {
+                    value: 0,
+                    script_pubkey: self.change.script_pubkey(),
+                    token: Some(OutputData {
+                        id,
+                        bitfield: Structure::HasAmount as u8,
+                        amount: leftover as i64,
+                        commitment: Vec::new(),
+                    }),
+                };
+                output.value = dust_limit(&output);
+                change.push(output);
+            }
+        }
+        Ok((selected, change))
+    }
+}
+
+/// The wallet's coins that can be spent in the next block, as of the given tip.
+///
+/// Only coins paying to public key hashes can be signed for; coins received on watched
+/// script hashes, eg. contracts, are left alone.
+pub fn spendable(db: &Db, tip: Height) -> Result<Vec<(OutPoint, TxOut)>, db::Error> {
+    Ok(db
+        .unspent()?
+        .into_iter()
+        .filter(|u| u.is_mature(tip) && u.txout.script_pubkey.is_p2pkh())
+        .map(|u| (u.outpoint, u.txout))
+        .collect())
+}
+
+/// Build a transaction sweeping all coins worth less than `threshold` into a single output
+/// paying to the given address, at the given fee rate.
+///
+/// Coins carrying tokens are left alone, as are coins worth less than what it costs to spend
+/// them. At least two coins must be swept for the transaction to be worth making.
+pub fn consolidate(
+    utxos: Vec<(OutPoint, TxOut)>,
+    threshold: u64,
+    address: &Address,
+    fee_rate: FeeRate,
+) -> Result<UnsignedTx, Error> {
+    let input_fee = P2PKH_INPUT_SIZE as u64 * fee_rate;
+    let inputs = utxos
+        .into_iter()
+        .filter(|(_, out)| !out.has_token() && out.value < threshold && out.value > input_fee)
+        .collect::<Vec<_>>();
+
+    if inputs.len() < 2 {
+        return Err(Error::NothingToConsolidate(inputs.len()));
+    }
+    let size = TX_OVERHEAD_SIZE + P2PKH_OUTPUT_SIZE + inputs.len() * P2PKH_INPUT_SIZE;
+    let fee = size as u64 * fee_rate;
+    let value = inputs.iter().map(|(_, o)| o.value).sum::<u64>() - fee;
+
+    if value < DUST_LIMIT {
+        return Err(Error::Dust(value));
+    }
+    let tx = Transaction {
+        version: 2,
+        lock_time: PackedLockTime::ZERO,
+        input: inputs
+            .iter()
+            .map(|(outpoint, _)| TxIn {
+                previous_output: *outpoint,
+                script_sig: Script::new(),
+                sequence: Sequence::MAX,
+            })
+            .collect(),
+        output: vec![TxOut {
+            value,
+            script_pubkey: address.script_pubkey(),
+            token: None,
+        }],
+    };
+
+    Ok(UnsignedTx {
+        tx,
+        inputs,
+        fee,
+        change: None,
+    })
+}
+
+/// Build a child transaction spending an unconfirmed output of `parent` to the given
+/// address, so that the parent and child together pay the given fee rate. Miners consider
+/// the package as a whole, which gets the parent confirmed along with its child.
+///
+/// The parent fee is the fee it already pays. Tokens carried by the spent output are kept.
+pub fn cpfp(
+    parent: &Transaction,
+    parent_fee: u64,
+    input: (OutPoint, TxOut),
+    address: &Address,
+    fee_rate: FeeRate,
+) -> Result<UnsignedTx, Error> {
+    let parent_size = parent.size();
+    let parent_rate = package_fee_rate(parent_fee, parent_size);
+
+    if parent_rate >= fee_rate {
+        return Err(Error::FeeRateReached(parent_rate));
+    }
+    let mut output = TxOut {
+        value: 0,
+        script_pubkey: address.script_pubkey(),
+        token: input.1.token.clone(),
+    };
+    let size = TX_OVERHEAD_SIZE + P2PKH_INPUT_SIZE + output_size(&output);
+    let fee = (parent_size + size) as u64 * fee_rate - parent_fee;
+    let needed = fee + dust_limit(&output);
+
+    if input.1.value < needed {
+        return Err(Error::InsufficientFunds {
+            needed,
+            available: input.1.value,
+        });
+    }
+    output.value = input.1.value - fee;
+
+    let tx = Transaction {
+        version: 2,
+        lock_time: PackedLockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: input.0,
+            script_sig: Script::new(),
+            sequence: Sequence::MAX,
+        }],
+        output: vec![output],
+    };
+
+    Ok(UnsignedTx {
+        tx,
+        inputs: vec![input],
+        fee,
+        change: None,
+    })
+}
+
+/// Fee rate of a transaction, or of a package of transactions, given the total fee and size.
+pub fn package_fee_rate(fee: u64, size: usize) -> FeeRate {
+    fee / size.max(1) as u64
+}
+
+/// Total fungible amount of the given token category held by a set of outputs.
+fn fungible(utxos: &[(OutPoint, TxOut)], id: &TokenID) -> u64 {
+    utxos
+        .iter()
+        .filter_map(|(_, o)| o.token.as_ref())
+        .filter(|t| t.id == *id && t.has_amount())
+        .map(|t| t.amount as u64)
+        .sum()
+}
+
+/// Serialized size of a transaction output. Outputs carrying tokens have their token data
+/// prefixed to the script.
+fn output_size(output: &TxOut) -> usize {
+    let script = token::wrap_scriptpubkey(output.script_pubkey.clone(), &output.token);
+
+    8 + VarInt(script.len() as u64).len() + script.len()
+}
+
+/// Minimum value of an output for it to be relayed: three times the cost of creating and
+/// spending it, at the minimum relay fee. This is [`DUST_LIMIT`] for P2PKH outputs, and more
+/// for outputs carrying tokens.
+pub fn dust_limit(output: &TxOut) -> u64 {
+    policy::dust_threshold(output)
+}
+
+/// Search for a subset of values that adds up to at least `target` and at most `upper`,
+/// wasting as little as possible. Returns the indices of the selected values.
+///
+/// Values are expected to be sorted in descending order.
+fn branch_and_bound(values: &[u64], target: u64, upper: u64) -> Option<Vec<usize>> {
+    struct Search<'a> {
+        values: &'a [u64],
+        target: u64,
+        upper: u64,
+        tries: usize,
+        selected: Vec<usize>,
+        best: Option<(u64, Vec<usize>)>,
+    }
+
+    impl Search<'_> {
+        fn run(&mut self, i: usize, value: u64, remaining: u64) {
+            if self.tries == 0 || value > self.upper {
+                return;
+            }
+            self.tries -= 1;
+
+            if value >= self.target {
+                let waste = value - self.target;
+
+                if self.best.as_ref().map_or(true, |(w, _)| waste < *w) {
+                    self.best = Some((waste, self.selected.clone()));
+                }
+                return;
+            }
+            if i == self.values.len() || value + remaining < self.target {
+                return;
+            }
+            let v = self.values[i];
+
+            // Explore the branch including this value first, then the one omitting it.
+            self.selected.push(i);
+            self.run(i + 1, value + v, remaining - v);
+            self.selected.pop();
+            self.run(i + 1, value, remaining - v);
+        }
+    }
+
+    let mut search = Search {
+        values,
+        target,
+        upper,
+        tries: BNB_MAX_TRIES,
+        selected: Vec::new(),
+        best: None,
+    };
+    search.run(0, 0, values.iter().sum());
+    search.best.map(|(_, selected)| selected)
+}
+
+/// Select the largest values until `target` plus the cost of a change output is covered.
+/// Returns the indices of the selected values and the change, if it isn't dust.
+///
+/// Values are expected to be sorted in descending order, and to add up to at least `target`.
+fn largest_first(values: &[u64], target: u64, change_fee: u64) -> (Vec<usize>, Option<u64>) {
+    let mut selected = Vec::new();
+    let mut value = 0;
+
+    for (i, v) in values.iter().enumerate() {
+        selected.push(i);
+        value += v;
+
+        if value >= target + change_fee {
+            break;
+        }
+    }
+    let change = value
+        .checked_sub(target + change_fee)
+        .filter(|c| *c >= DUST_LIMIT);
+
+    (selected, change)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nakamoto_common::bitcoin::hashes::Hash;
+    use nakamoto_common::bitcoin::Network;
+    use nakamoto_test::block::gen;
+
+    fn address(rng: &mut fastrand::Rng) -> Address {
+        Address::from_script(&gen::script(rng), Network::Bitcoin).unwrap()
+    }
+
+    fn token_utxo(rng: &mut fastrand::Rng, token: OutputData) -> (OutPoint, TxOut) {
+        let (outpoint, mut txout) = utxo(rng, 1000);
+        txout.token = Some(token);
+
+        (outpoint, txout)
+    }
+
+    fn fungible(id: TokenID, amount: i64) -> OutputData {
+        OutputData {
+            id,
+            bitfield: Structure::HasAmount as u8,
+            amount,
+            commitment: vec![],
+        }
+    }
+
+    fn nft(id: TokenID, commitment: &[u8]) -> OutputData {
+        OutputData {
+            id,
+            bitfield: Structure::HasNFT as u8 | Structure::HasCommitmentLength as u8,
+            amount: 0,
+            commitment: commitment.to_vec(),
+        }
+    }
+
+    fn utxo(rng: &mut fastrand::Rng, value: u64) -> (OutPoint, TxOut) {
+        (
+            OutPoint {
+                txid: gen::transaction(rng).txid(),
+                vout: 0,
+            },
+            TxOut {
+                value,
+                script_pubkey: gen::script(rng),
+                token: None,
+            },
+        )
+    }
+
+    #[test]
+    fn test_build_changeless() {
+        let mut rng = fastrand::Rng::new();
+        let builder = TxBuilder::new(address(&mut rng), 1).output(&address(&mut rng), 10_000);
+        // Overhead, one output and one input.
+        let exact = 10_000 + (TX_OVERHEAD_SIZE + P2PKH_OUTPUT_SIZE + P2PKH_INPUT_SIZE) as u64;
+        let utxos = vec![
+            utxo(&mut rng, 50_000),
+            utxo(&mut rng, exact),
+            utxo(&mut rng, 3_000),
+        ];
+        let unsigned = builder.build(utxos.clone()).unwrap();
+
+        assert_eq!(unsigned.change, None);
+        assert_eq!(unsigned.inputs, vec![utxos[1].clone()]);
+        assert_eq!(unsigned.tx.output.len(), 1);
+        assert_eq!(unsigned.fee, exact - 10_000);
+    }
+
+    #[test]
+    fn test_build_with_data() {
+        let mut rng = fastrand::Rng::new();
+        let builder = TxBuilder::new(address(&mut rng), 1)
+            .output(&address(&mut rng), 10_000)
+            .data(b"hello".to_vec());
+        let unsigned = builder.build(vec![utxo(&mut rng, 50_000)]).unwrap();
+        let data = &unsigned.tx.output[1];
+
+        assert!(data.script_pubkey.is_op_return());
+        assert_eq!(data.value, 0);
+        assert_eq!(&data.script_pubkey.as_bytes()[2..], b"hello");
+        assert_eq!(unsigned.change, Some(2));
+        assert_eq!(unsigned.amount(), 10_000);
+
+        // The data output is paid for.
+        let size = TX_OVERHEAD_SIZE + 2 * P2PKH_OUTPUT_SIZE + P2PKH_INPUT_SIZE + output_size(data);
+        assert_eq!(unsigned.fee, size as u64);
+
+        let builder = TxBuilder::new(address(&mut rng), 1)
+            .output(&address(&mut rng), 10_000)
+            .data(vec![0; MAX_DATA_SIZE + 1]);
+        assert!(matches!(
+            builder.build(vec![utxo(&mut rng, 50_000)]),
+            Err(Error::DataTooLarge(221))
+        ));
+    }
+
+    #[test]
+    fn test_build_with_change() {
+        let mut rng = fastrand::Rng::new();
+        let change = address(&mut rng);
+        let builder = TxBuilder::new(change.clone(), 2).output(&address(&mut rng), 60_000);
+        let utxos = vec![
+            utxo(&mut rng, 40_000),
+            utxo(&mut rng, 30_000),
+            utxo(&mut rng, 25_000),
+        ];
+        let unsigned = builder.build(utxos).unwrap();
+        let ix = unsigned.change.unwrap();
+
+        assert_eq!(unsigned.inputs.len(), 2);
+        assert_eq!(unsigned.tx.input.len(), 2);
+        assert_eq!(unsigned.tx.output[ix].script_pubkey, change.script_pubkey());
+        assert_eq!(
+            unsigned.fee,
+            (TX_OVERHEAD_SIZE + 2 * P2PKH_OUTPUT_SIZE + 2 * P2PKH_INPUT_SIZE) as u64 * 2
+        );
+        assert_eq!(unsigned.tx.output[ix].value, 70_000 - 60_000 - unsigned.fee);
+    }
+
+    #[test]
+    fn test_build_errors() {
+        let mut rng = fastrand::Rng::new();
+        let builder = TxBuilder::new(address(&mut rng), 1);
+
+        assert!(matches!(builder.build(vec![]), Err(Error::NoOutputs)));
+        assert!(matches!(
+            builder
+                .clone()
+                .output(&address(&mut rng), 100)
+                .build(vec![]),
+            Err(Error::Dust(100))
+        ));
+        assert!(matches!(
+            builder
+                .output(&address(&mut rng), 10_000)
+                .build(vec![utxo(&mut rng, 5_000)]),
+            Err(Error::InsufficientFunds { .. })
+        ));
+    }
+
+    #[test]
+    fn test_build_fungible_tokens() {
+        let mut rng = fastrand::Rng::new();
+        let change = address(&mut rng);
+        let id = TokenID::from_inner([1; 32]);
+        let other = TokenID::from_inner([2; 32]);
+        let builder =
+            TxBuilder::new(change.clone(), 1).token_output(&address(&mut rng), fungible(id, 70));
+        let utxos = vec![
+            utxo(&mut rng, 50_000),
+            token_utxo(&mut rng, fungible(id, 50)),
+            token_utxo(&mut rng, fungible(id, 40)),
+            token_utxo(&mut rng, fungible(other, 1000)),
+            token_utxo(&mut rng, nft(id, b"keep")),
+        ];
+        let unsigned = builder.build(utxos).unwrap();
+        let tokens = unsigned
+            .tx
+            .output
+            .iter()
+            .filter_map(|o| o.token.as_ref())
+            .collect::<Vec<_>>();
+
+        // Both fungible outputs of the category are spent, and nothing else carrying tokens.
+        assert_eq!(
+            unsigned
+                .inputs
+                .iter()
+                .filter(|(_, o)| o.has_token())
+                .count(),
+            2
+        );
+        assert!(unsigned
+            .inputs
+            .iter()
+            .filter_map(|(_, o)| o.token.as_ref())
+            .all(|t| t.id == id && !t.has_nft()));
+        // The leftover 20 tokens are returned as change.
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].amount, 70);
+        assert_eq!(tokens[1].amount, 20);
+        assert!(unsigned.tx.output.iter().all(|o| o.value >= dust_limit(o)));
+        assert!(unsigned
+            .tx
+            .output
+            .iter()
+            .filter(|o| o.has_token())
+            .all(|o| o.value > DUST_LIMIT));
+    }
+
+    #[test]
+    fn test_build_nft() {
+        let mut rng = fastrand::Rng::new();
+        let id = TokenID::from_inner([1; 32]);
+        let builder =
+            TxBuilder::new(address(&mut rng), 1).token_output(&address(&mut rng), nft(id, b"art"));
+        let utxos = vec![
+            utxo(&mut rng, 50_000),
+            token_utxo(&mut rng, nft(id, b"other")),
+            token_utxo(&mut rng, nft(id, b"art")),
+        ];
+        let unsigned = builder.build(utxos.clone()).unwrap();
+
+        assert_eq!(unsigned.inputs[0], utxos[2]);
+        assert_eq!(
+            unsigned.tx.output[0].token.as_ref().unwrap().commitment,
+            b"art"
+        );
+
+        let builder = TxBuilder::new(address(&mut rng), 1)
+            .token_output(&address(&mut rng), nft(id, b"missing"));
+        assert!(matches!(builder.build(utxos), Err(Error::MissingNft(_))));
+    }
+
+    #[test]
+    fn test_consolidate() {
+        let mut rng = fastrand::Rng::new();
+        let id = TokenID::from_inner([1; 32]);
+        let to = address(&mut rng);
+        let utxos = vec![
+            utxo(&mut rng, 50_000),
+            utxo(&mut rng, 2_000),
+            utxo(&mut rng, 600),
+            utxo(&mut rng, 100),
+            token_utxo(&mut rng, fungible(id, 10)),
+        ];
+        let unsigned = consolidate(utxos.clone(), 10_000, &to, 2).unwrap();
+
+        // Only the small coins worth spending are swept, leaving tokens alone.
+        assert_eq!(unsigned.inputs, vec![utxos[1].clone(), utxos[2].clone()]);
+        assert_eq!(unsigned.change, None);
+        assert_eq!(
+            unsigned.fee,
+            (TX_OVERHEAD_SIZE + P2PKH_OUTPUT_SIZE + 2 * P2PKH_INPUT_SIZE) as u64 * 2
+        );
+        assert_eq!(unsigned.tx.output.len(), 1);
+        assert_eq!(unsigned.tx.output[0].script_pubkey, to.script_pubkey());
+        assert_eq!(unsigned.tx.output[0].value, 2_600 - unsigned.fee);
+
+        assert!(matches!(
+            consolidate(utxos.clone(), 1_000, &to, 1),
+            Err(Error::NothingToConsolidate(1))
+        ));
+        // Sweeping would cost almost everything.
+        let utxos = vec![utxo(&mut rng, 600), utxo(&mut rng, 600)];
+        assert!(matches!(
+            consolidate(utxos, 10_000, &to, 3),
+            Err(Error::Dust(180))
+        ));
+    }
+
+    #[test]
+    fn test_cpfp() {
+        let mut rng = fastrand::Rng::new();
+        let to = address(&mut rng);
+        let parent = gen::transaction(&mut rng);
+        let parent_size = parent.size();
+        let parent_fee = parent_size as u64;
+        let input = utxo(&mut rng, 10_000);
+        let unsigned = cpfp(&parent, parent_fee, input.clone(), &to, 3).unwrap();
+        let child_size = TX_OVERHEAD_SIZE + P2PKH_INPUT_SIZE + P2PKH_OUTPUT_SIZE;
+
+        // The child pays for itself and for what the parent is short of.
+        assert_eq!(unsigned.inputs, vec![input.clone()]);
+        assert_eq!(unsigned.fee, child_size as u64 * 3 + parent_size as u64 * 2);
+        assert_eq!(
+            package_fee_rate(parent_fee + unsigned.fee, parent_size + child_size),
+            3
+        );
+        assert_eq!(unsigned.tx.output[0].script_pubkey, to.script_pubkey());
+        assert_eq!(unsigned.tx.output[0].value, 10_000 - unsigned.fee);
+
+        assert!(matches!(
+            cpfp(&parent, parent_fee, input, &to, 1),
+            Err(Error::FeeRateReached(1))
+        ));
+        assert!(matches!(
+            cpfp(&parent, parent_fee, utxo(&mut rng, 1_000), &to, 3),
+            Err(Error::InsufficientFunds {
+                available: 1_000,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_build_insufficient_tokens() {
+        let mut rng = fastrand::Rng::new();
+        let id = TokenID::from_inner([1; 32]);
+        let builder = TxBuilder::new(address(&mut rng), 1)
+            .token_output(&address(&mut rng), fungible(id, 100));
+        let utxos = vec![
+            utxo(&mut rng, 50_000),
+            token_utxo(&mut rng, fungible(id, 60)),
+        ];
+
+        assert!(matches!(
+            builder.build(utxos),
+            Err(Error::InsufficientTokens {
+                needed: 100,
+                available: 60,
+                ..
+            })
+        ));
+    }
+}
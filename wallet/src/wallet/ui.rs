@@ -1,3 +1,9 @@
+pub mod consolidate;
+pub mod contacts;
+pub mod logs;
+pub mod peers;
+pub mod receive;
+pub mod send;
 mod table;
 
 use std::ops::ControlFlow;
@@ -9,10 +15,20 @@ use termion::{clear, color, cursor, style};
 
 use nakamoto_client as client;
 use nakamoto_common::bitcoin;
-use nakamoto_common::bitcoin::Address;
+use nakamoto_common::bitcoin::hashes::hex::ToHex;
+use nakamoto_common::bitcoin::{Address, Txid};
 use nakamoto_common::block::Height;
+use nakamoto_p2p::fsm::fees::FeeRate;
+use nakamoto_p2p::fsm::stats::{PeerStats, Traffic};
+use nakamoto_p2p::fsm::Peer;
 
+use crate::logger;
 use crate::wallet::db;
+use crate::wallet::db::Contact;
+use crate::wallet::resolve::Resolved;
+use crate::wallet::tx::UnsignedTx;
+use crate::wallet::utxo;
+use send::Form;
 use table::Table;
 
 /// Redraw flags. Sets what needs redrawing.
@@ -26,12 +42,16 @@ const REDRAW_HEADER: Redraw = 0b0001;
 const REDRAW_MAIN: Redraw = 0b0010;
 /// Redraw the footer.
 const REDRAW_FOOTER: Redraw = 0b0100;
+/// Redraw the log pane.
+const REDRAW_LOG: Redraw = 0b1000;
 /// Redraw everything.
-const REDRAW_ALL: Redraw = REDRAW_MAIN | REDRAW_HEADER | REDRAW_FOOTER;
+const REDRAW_ALL: Redraw = REDRAW_MAIN | REDRAW_HEADER | REDRAW_FOOTER | REDRAW_LOG;
 /// Row number at which header area starts (1-indexed).
 const HEADER_ROW: u16 = 1;
 /// Row number at which main area starts (1-indexed).
 const MAIN_ROW: u16 = 3;
+/// Number of log lines shown in the log pane.
+const LOG_ROWS: u16 = 10;
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -53,6 +73,45 @@ impl std::fmt::Display for Balance {
     }
 }
 
+/// A number of bytes, shown in the largest unit that keeps it above one.
+#[derive(Debug)]
+pub struct Bytes(f64);
+
+impl std::fmt::Display for Bytes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut number = self.0;
+
+        for unit in ["B", "KiB", "MiB", "GiB"] {
+            if number < 1024. || unit == "GiB" {
+                let formatted = if unit == "B" {
+                    format!("{:.0} {}", number, unit)
+                } else {
+                    format!("{:.1} {}", number, unit)
+                };
+                return f.pad(&formatted);
+            }
+            number /= 1024.;
+        }
+        unreachable!()
+    }
+}
+
+/// Estimated time left, rounded up to the minute.
+#[derive(Debug)]
+pub struct Eta(time::Duration);
+
+impl std::fmt::Display for Eta {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mins = (self.0.as_secs() + 59) / 60;
+
+        match (mins / 60, mins % 60) {
+            (0, 0 | 1) => write!(f, "<1m"),
+            (0, m) => write!(f, "{}m", m),
+            (h, m) => write!(f, "{}h {}m", h, m),
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, Default)]
 struct Vec2D {
     x: u16,
@@ -109,14 +168,62 @@ impl Aligned {
     }
 }
 
+/// State of a transaction being signed on the hardware device.
+#[derive(Debug, Clone)]
+pub enum Signing {
+    /// Waiting for the user to confirm the payments on the device.
+    Confirm {
+        outputs: Vec<(Address, u64)>,
+        fee: u64,
+    },
+    /// The transaction was signed.
+    Signed { txid: Txid },
+    /// Signing failed, or was rejected on the device.
+    Failed { reason: String },
+}
+
+impl fmt::Display for Signing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Confirm { outputs, fee } => {
+                let outputs = outputs
+                    .iter()
+                    .map(|(addr, value)| format!("{} to {}", Balance(*value), addr))
+                    .collect::<Vec<_>>();
+
+                write!(
+                    f,
+                    "Confirm on device: {} (fee {} sat)",
+                    outputs.join(", "),
+                    fee
+                )
+            }
+            Self::Signed { txid } => write!(f, "Transaction {} signed", txid),
+            Self::Failed { reason } => write!(f, "Signing failed: {}", reason),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Ui {
     pub message: String,
+    /// Whether the UI is drawn without a terminal, in which case nothing is drawn.
+    pub headless: bool,
 
     status: Status,
-    balance: Balance,
+    signing: Option<Signing>,
+    send: Form,
+    consolidate: consolidate::Form,
+    /// Amount to request on the receive tab, as typed, in BCH.
+    request: String,
+    contacts: contacts::View,
+    peers: peers::View,
+    logs: logs::View,
+    pending: usize,
+    balance: utxo::Balance,
     tab: Tab,
     tip: Height,
+    header: Height,
     size: Vec2D,
 
     last_redraw: Option<time::Instant>,
@@ -126,12 +233,22 @@ pub struct Ui {
 impl Default for Ui {
     fn default() -> Self {
         Self {
-            balance: Balance(0),
+            balance: utxo::Balance::default(),
             tab: Tab::Utxos,
             size: Vec2D::default(),
             tip: 0,
+            header: 0,
             status: Status::LoadingBlockHeaders { height: 0 },
+            signing: None,
+            send: Form::default(),
+            consolidate: consolidate::Form::default(),
+            request: String::new(),
+            contacts: contacts::View::default(),
+            peers: peers::View::default(),
+            logs: logs::View::default(),
+            pending: 0,
             message: String::new(),
+            headless: false,
             last_redraw: None,
             redraw: REDRAW_ALL,
         }
@@ -140,6 +257,9 @@ impl Default for Ui {
 
 impl Ui {
     pub fn redraw<D: db::Read, W: io::Write>(&mut self, db: &D, term: &mut W) -> Result<(), Error> {
+        if self.headless {
+            return Ok(());
+        }
         self.redraw = REDRAW_ALL;
         self.reset(term)?;
         self.decorations(term)?;
@@ -155,6 +275,9 @@ impl Ui {
     }
 
     pub fn reset<W: io::Write>(&mut self, term: &mut W) -> io::Result<()> {
+        if self.headless {
+            return Ok(());
+        }
         self.size = termion::terminal_size()?.into();
 
         write!(
@@ -177,11 +300,29 @@ impl Ui {
         )
     }
 
-    pub fn set_balance(&mut self, balance: u64) {
-        self.balance = Balance(balance);
+    pub fn set_balance(&mut self, balance: utxo::Balance) {
+        self.balance = balance;
         self.redraw |= REDRAW_HEADER;
     }
 
+    /// Set the number of transactions waiting to be broadcast.
+    pub fn set_pending(&mut self, pending: usize) {
+        self.pending = pending;
+        self.redraw |= REDRAW_FOOTER;
+    }
+
+    /// Token metadata was fetched, and the tokens should be shown with it.
+    pub fn handle_token_metadata(&mut self) {
+        if self.tab == Tab::Tokens {
+            self.redraw |= REDRAW_MAIN;
+        }
+    }
+
+    pub fn handle_tip(&mut self, height: Height) {
+        self.header = height;
+        self.redraw |= REDRAW_MAIN;
+    }
+
     pub fn handle_ready(&mut self, height: Height, offline: bool) {
         self.tip = height;
         self.status = Status::Ready { height, offline };
@@ -200,6 +341,16 @@ impl Ui {
         self.tip = height;
     }
 
+    /// Show block header sync progress, estimating the time left from the sync rate.
+    pub fn handle_header_sync(&mut self, height: Height, best: Height, rate: f64) {
+        let remaining = best.saturating_sub(height);
+        let eta = (rate > 0. && remaining > 0)
+            .then(|| time::Duration::from_secs_f64(remaining as f64 / rate));
+
+        self.status = Status::SyncingHeaders { height, best, eta };
+        self.redraw |= REDRAW_HEADER;
+    }
+
     pub fn handle_synced(&mut self, height: Height, tip: Height) {
         self.status = if tip == height {
             Status::Synced { height }
@@ -210,8 +361,239 @@ impl Ui {
         self.redraw |= REDRAW_HEADER;
     }
 
+    pub fn handle_disconnected(&mut self) {
+        self.status = Status::Disconnected;
+        self.redraw |= REDRAW_HEADER;
+    }
+
+    pub fn handle_signing(&mut self, signing: Signing) {
+        self.signing = Some(signing);
+        self.redraw |= REDRAW_FOOTER;
+    }
+
+    /// Whether the send view is active. Key presses should then go to [`Ui::handle_send_key`].
+    pub fn is_sending(&self) -> bool {
+        self.tab == Tab::Send
+    }
+
+    /// The send form.
+    pub fn send(&self) -> &Form {
+        &self.send
+    }
+
+    pub fn handle_send_key(&mut self, key: Key) -> Option<send::Action> {
+        let action = self.send.handle_key(key);
+
+        if let Some(send::Action::Cancel) = action {
+            self.tab = Tab::Utxos;
+            self.redraw |= REDRAW_HEADER;
+        }
+        self.redraw |= REDRAW_MAIN;
+
+        action
+    }
+
+    /// Whether the consolidate view is active. Key presses should then go to
+    /// [`Ui::handle_consolidate_key`].
+    pub fn is_consolidating(&self) -> bool {
+        self.tab == Tab::Consolidate
+    }
+
+    /// The consolidate form.
+    pub fn consolidate(&self) -> &consolidate::Form {
+        &self.consolidate
+    }
+
+    pub fn handle_consolidate_key(&mut self, key: Key) -> Option<send::Action> {
+        let action = self.consolidate.handle_key(key);
+
+        if let Some(send::Action::Cancel) = action {
+            self.tab = Tab::Utxos;
+            self.redraw |= REDRAW_HEADER;
+        }
+        self.redraw |= REDRAW_MAIN;
+
+        action
+    }
+
+    pub fn handle_consolidate_preview(&mut self, preview: Result<UnsignedTx, String>) {
+        match preview {
+            Ok(unsigned) => self.consolidate.preview = Some(unsigned),
+            Err(err) => self.consolidate.status = Some(Err(err)),
+        }
+        self.redraw |= REDRAW_MAIN;
+    }
+
+    pub fn handle_consolidate_status(&mut self, status: Result<String, String>) {
+        if status.is_ok() {
+            self.consolidate.reset();
+        }
+        self.consolidate.status = Some(status);
+        self.redraw |= REDRAW_MAIN;
+    }
+
+    /// Whether the peers view is active. Peers should then be kept up to date with
+    /// [`Ui::set_peers`].
+    pub fn is_viewing_peers(&self) -> bool {
+        self.tab == Tab::Peers
+    }
+
+    /// Set the connected peers, and the bandwidth they used.
+    pub fn set_peers(&mut self, peers: Vec<Peer>, stats: Vec<PeerStats>) {
+        self.peers.set_peers(peers, stats);
+        self.redraw |= REDRAW_MAIN;
+    }
+
+    /// Handle a key press on the peers view. Returns the action requested, if any.
+    pub fn handle_peers_key(&mut self, key: Key) -> Option<peers::Action> {
+        if !self.is_viewing_peers() {
+            return None;
+        }
+        self.redraw |= REDRAW_MAIN;
+        self.peers.handle_key(key)
+    }
+
+    /// Show the lines of the given buffer in the log pane.
+    pub fn set_log(&mut self, buffer: logger::Buffer) {
+        self.logs.buffer = Some(buffer);
+    }
+
+    /// Handle a key press on the log pane. Returns whether the key was handled.
+    pub fn handle_log_key(&mut self, key: Key) -> bool {
+        if !self.logs.handle_key(key, LOG_ROWS as usize) {
+            return false;
+        }
+        // Hiding the pane uncovers the main area.
+        self.redraw |= REDRAW_MAIN | REDRAW_LOG;
+
+        true
+    }
+
+    pub fn handle_peers_status(&mut self, status: Result<String, String>) {
+        self.peers.set_status(status);
+        self.redraw |= REDRAW_MAIN;
+    }
+
+    /// Whether the contacts view is active. Key presses should then go to
+    /// [`Ui::handle_contacts_key`].
+    pub fn is_managing_contacts(&self) -> bool {
+        self.tab == Tab::Contacts
+    }
+
+    /// The contacts view.
+    pub fn contacts(&self) -> &contacts::View {
+        &self.contacts
+    }
+
+    /// Handle a key press in the contacts view. Paying a contact opens the send form, with
+    /// the contact added as a recipient. Returns the actions left to the caller, ie. saving and
+    /// deleting contacts.
+    pub fn handle_contacts_key(&mut self, key: Key) -> Option<contacts::Action> {
+        self.redraw |= REDRAW_MAIN;
+
+        // Switch tabs while browsing contacts.
+        if self.contacts.is_searching() {
+            match key {
+                Key::Right => {
+                    self.tab.next();
+                    self.redraw |= REDRAW_HEADER;
+                    return None;
+                }
+                Key::Left => {
+                    self.tab.prev();
+                    self.redraw |= REDRAW_HEADER;
+                    return None;
+                }
+                _ => {}
+            }
+        }
+        match self.contacts.handle_key(key) {
+            Some(contacts::Action::Cancel) => {
+                self.tab = Tab::Utxos;
+                self.redraw |= REDRAW_HEADER;
+                None
+            }
+            Some(contacts::Action::Pay(contact)) => {
+                self.send.add_recipient(contact.address);
+                self.tab = Tab::Send;
+                self.redraw |= REDRAW_HEADER;
+                None
+            }
+            action => action,
+        }
+    }
+
+    /// Set the contacts matching the search.
+    pub fn set_contacts(&mut self, matches: Vec<Contact>) {
+        if matches != self.contacts.matches {
+            self.contacts.set_matches(matches);
+            self.redraw |= REDRAW_MAIN;
+        }
+    }
+
+    pub fn handle_contacts_status(&mut self, status: Result<String, String>) {
+        self.contacts.set_status(status);
+        self.redraw |= REDRAW_MAIN;
+    }
+
+    pub fn handle_send_preview(&mut self, preview: Result<UnsignedTx, String>) {
+        match preview {
+            Ok(unsigned) => self.send.preview = Some(unsigned),
+            Err(err) => self.send.status = Some(Err(err)),
+        }
+        self.redraw |= REDRAW_MAIN;
+    }
+
+    /// Warn the user that a payment may never confirm.
+    pub fn handle_double_spend(&mut self, original: Txid, conflicting: Txid) {
+        self.message = format!(
+            "Warning: transaction {} is being double-spent by {}",
+            original, conflicting
+        );
+        self.redraw |= REDRAW_FOOTER;
+    }
+
+    /// Warn the user that a payment was proven to be double-spent.
+    pub fn handle_double_spend_proof(&mut self, txid: Txid) {
+        self.message = format!(
+            "Warning: transaction {} is being double-spent, it may never confirm",
+            txid
+        );
+        self.redraw |= REDRAW_FOOTER;
+    }
+
+    pub fn handle_fee_estimate(&mut self, estimate: FeeRate) {
+        self.send.set_estimate(estimate);
+        self.redraw |= REDRAW_MAIN;
+    }
+
+    /// Show the addresses that names entered as recipients resolved to, for the user to
+    /// confirm by previewing the transaction again.
+    pub fn handle_send_resolved(&mut self, resolved: Vec<(String, Resolved)>) {
+        let status = resolved
+            .iter()
+            .map(|(name, r)| format!("{} is {}", name, r))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        self.send.resolved.extend(resolved);
+        self.send.status = Some(Ok(format!("{}. Press enter to continue", status)));
+        self.redraw |= REDRAW_MAIN;
+    }
+
+    pub fn handle_send_status(&mut self, status: Result<String, String>) {
+        if status.is_ok() {
+            self.send.reset();
+        }
+        self.send.status = Some(status);
+        self.redraw |= REDRAW_MAIN;
+    }
+
     pub fn handle_input_event(&mut self, input: Event) -> io::Result<ControlFlow<()>> {
         match input {
+            Event::Key(Key::Char('q') | Key::Esc) if !self.is_sending() => {
+                return Ok(ControlFlow::Break(()));
+            }
             // Switch tabs.
             Event::Key(Key::Right | Key::Char('\t')) => {
                 self.tab.next();
@@ -221,6 +603,17 @@ impl Ui {
                 self.tab.prev();
                 self.redraw |= REDRAW_HEADER | REDRAW_MAIN;
             }
+            // Edit the amount requested on the receive tab.
+            Event::Key(Key::Char(c))
+                if self.tab == Tab::Receive && (c.is_ascii_digit() || c == '.') =>
+            {
+                self.request.push(c);
+                self.redraw |= REDRAW_MAIN;
+            }
+            Event::Key(Key::Backspace) if self.tab == Tab::Receive => {
+                self.request.pop();
+                self.redraw |= REDRAW_MAIN;
+            }
             Event::Mouse(MouseEvent::Press(_, _x, _y)) => {}
             _ => (),
         }
@@ -260,13 +653,36 @@ impl Ui {
 
 #[derive(Debug)]
 enum Status {
-    Ready { height: Height, offline: bool },
-    LoadingBlockHeaders { height: Height },
-    LoadingFilterHeaders { height: Height },
-    Scanning { height: Height, tip: Height },
-    VerifyingFilterHeaders { height: Height },
-    Syncing { height: Height, tip: Height },
-    Synced { height: Height },
+    Ready {
+        height: Height,
+        offline: bool,
+    },
+    LoadingBlockHeaders {
+        height: Height,
+    },
+    LoadingFilterHeaders {
+        height: Height,
+    },
+    Scanning {
+        height: Height,
+        tip: Height,
+    },
+    VerifyingFilterHeaders {
+        height: Height,
+    },
+    SyncingHeaders {
+        height: Height,
+        best: Height,
+        eta: Option<time::Duration>,
+    },
+    Syncing {
+        height: Height,
+        tip: Height,
+    },
+    Synced {
+        height: Height,
+    },
+    Disconnected,
 }
 
 impl fmt::Display for Status {
@@ -274,6 +690,12 @@ impl fmt::Display for Status {
         fn percent(a: f64, b: f64) -> String {
             format!("{:.2}%", a / b * 100.)
         }
+        fn bar(a: f64, b: f64) -> String {
+            const WIDTH: usize = 10;
+            let filled = ((a / b * WIDTH as f64) as usize).min(WIDTH);
+
+            format!("{}{}", "█".repeat(filled), "░".repeat(WIDTH - filled))
+        }
 
         match self {
             Self::Ready { height, offline } => {
@@ -292,6 +714,18 @@ impl fmt::Display for Status {
             Self::VerifyingFilterHeaders { height } => {
                 write!(f, "Verifying filter header {}..", height)
             }
+            Self::SyncingHeaders { height, best, eta } => {
+                write!(
+                    f,
+                    "Syncing headers {} {}",
+                    bar(*height as f64, *best as f64),
+                    percent(*height as f64, *best as f64)
+                )?;
+                if let Some(eta) = eta {
+                    write!(f, ", {} left", Eta(*eta))?;
+                }
+                Ok(())
+            }
             Self::Syncing { height, tip } => {
                 write!(
                     f,
@@ -313,6 +747,9 @@ impl fmt::Display for Status {
             Self::Synced { height } => {
                 write!(f, "Synced to block {}", height)
             }
+            Self::Disconnected => {
+                write!(f, "Disconnected, reconnecting..")
+            }
         }
     }
 }
@@ -321,23 +758,41 @@ impl fmt::Display for Status {
 enum Tab {
     Utxos,
     History,
+    Tokens,
     Addresses,
+    Receive,
+    Peers,
+    Contacts,
+    Send,
+    Consolidate,
 }
 
 impl Tab {
     fn next(&mut self) {
         match self {
             Self::Utxos => *self = Self::History,
-            Self::History => *self = Self::Addresses,
-            Self::Addresses => *self = Self::Utxos,
+            Self::History => *self = Self::Tokens,
+            Self::Tokens => *self = Self::Addresses,
+            Self::Addresses => *self = Self::Receive,
+            Self::Receive => *self = Self::Peers,
+            Self::Peers => *self = Self::Contacts,
+            Self::Contacts => *self = Self::Send,
+            Self::Send => *self = Self::Consolidate,
+            Self::Consolidate => *self = Self::Utxos,
         }
     }
 
     fn prev(&mut self) {
         match self {
-            Self::Utxos => *self = Self::Addresses,
+            Self::Utxos => *self = Self::Consolidate,
             Self::History => *self = Self::Utxos,
-            Self::Addresses => *self = Self::History,
+            Self::Tokens => *self = Self::History,
+            Self::Addresses => *self = Self::Tokens,
+            Self::Receive => *self = Self::Addresses,
+            Self::Peers => *self = Self::Receive,
+            Self::Contacts => *self = Self::Peers,
+            Self::Send => *self = Self::Contacts,
+            Self::Consolidate => *self = Self::Send,
         }
     }
 }
@@ -347,27 +802,48 @@ impl fmt::Display for Tab {
         match self {
             Self::Utxos => write!(f, "UTXOs"),
             Self::History => write!(f, "History"),
+            Self::Tokens => write!(f, "Tokens"),
             Self::Addresses => write!(f, "Addresses"),
+            Self::Receive => write!(f, "Receive"),
+            Self::Peers => write!(f, "Peers"),
+            Self::Contacts => write!(f, "Contacts"),
+            Self::Send => write!(f, "Send"),
+            Self::Consolidate => write!(f, "Consolidate"),
         }
     }
 }
 
 pub fn refresh<D: db::Read, W: io::Write>(ui: &mut Ui, db: &D, term: &mut W) -> Result<(), Error> {
+    if ui.headless {
+        return Ok(());
+    }
     ui.size = termion::terminal_size()?.into();
 
+    if ui.logs.is_visible() && ui.logs.is_stale() {
+        ui.redraw |= REDRAW_LOG;
+    }
     if ui.redraw | REDRAW_HEADER == ui.redraw {
         draw_header(ui, term)?;
     }
     if ui.redraw | REDRAW_MAIN == ui.redraw {
         write!(term, "{}{}", cursor::Goto(1, MAIN_ROW), clear::AfterCursor)?;
-        ui.redraw |= REDRAW_FOOTER;
+        ui.redraw |= REDRAW_FOOTER | REDRAW_LOG;
 
         match ui.tab {
-            Tab::Utxos => draw_utxo_tab(db, term)?,
+            Tab::Utxos => draw_utxo_tab(ui.header, db, term)?,
             Tab::Addresses => draw_addresses_tab(ui, db, term)?,
             Tab::History => draw_history_tab(db, term)?,
+            Tab::Tokens => draw_tokens_tab(ui, db, term)?,
+            Tab::Receive => draw_receive_tab(&ui.request, db, term)?,
+            Tab::Peers => draw_peers_tab(ui, term)?,
+            Tab::Contacts => draw_contacts_tab(&ui.contacts, term)?,
+            Tab::Send => draw_send_tab(&ui.send, term)?,
+            Tab::Consolidate => draw_consolidate_tab(&ui.consolidate, ui.send.estimate, term)?,
         }
     }
+    if ui.redraw | REDRAW_LOG == ui.redraw && ui.logs.is_visible() {
+        draw_log_pane(ui, term)?;
+    }
     if ui.redraw | REDRAW_FOOTER == ui.redraw {
         draw_footer(ui, term)?;
     }
@@ -389,7 +865,16 @@ pub fn refresh<D: db::Read, W: io::Write>(ui: &mut Ui, db: &D, term: &mut W) ->
 }
 
 pub fn draw_header<W: io::Write>(ui: &Ui, term: &mut W) -> io::Result<()> {
-    let balance = ui.align(&ui.balance).right();
+    let balance = if ui.balance.unconfirmed > 0 {
+        format!(
+            "{} (+{} unconfirmed)",
+            Balance(ui.balance.confirmed),
+            Balance(ui.balance.unconfirmed)
+        )
+    } else {
+        Balance(ui.balance.confirmed).to_string()
+    };
+    let balance = ui.align(balance).right();
 
     write!(
         term,
@@ -418,7 +903,17 @@ pub fn draw_header<W: io::Write>(ui: &Ui, term: &mut W) -> io::Result<()> {
 
     write!(term, "{}", cursor::Goto(1, HEADER_ROW))?;
 
-    for tab in [Tab::Utxos, Tab::History, Tab::Addresses] {
+    for tab in [
+        Tab::Utxos,
+        Tab::History,
+        Tab::Tokens,
+        Tab::Addresses,
+        Tab::Receive,
+        Tab::Peers,
+        Tab::Contacts,
+        Tab::Send,
+        Tab::Consolidate,
+    ] {
         if ui.tab == tab {
             tabs.push(format!(
                 "{}{} {} {}",
@@ -441,24 +936,37 @@ pub fn draw_header<W: io::Write>(ui: &Ui, term: &mut W) -> io::Result<()> {
     Ok(())
 }
 
-pub fn draw_utxo_tab<D: db::Read, W: io::Write>(db: &D, term: &mut W) -> Result<(), Error> {
-    let utxos = db.utxos()?;
-
-    for (i, (outpoint, txout)) in utxos.iter().enumerate() {
-        let addr = Address::from_script(&txout.script_pubkey, bitcoin::Network::Bitcoin).unwrap();
+pub fn draw_utxo_tab<D: db::Read, W: io::Write>(
+    tip: Height,
+    db: &D,
+    term: &mut W,
+) -> Result<(), Error> {
+    let utxos = db.unspent()?;
+
+    for (i, utxo) in utxos.iter().enumerate() {
+        let addr =
+            Address::from_script(&utxo.txout.script_pubkey, bitcoin::Network::Bitcoin).unwrap();
+        let confirmations = match utxo.confirmations(tip) {
+            0 => String::from("unconfirmed"),
+            n if !utxo.is_mature(tip) => format!("{}/{} conf", n, utxo::COINBASE_MATURITY),
+            n => format!("{} conf", n),
+        };
 
         write!(
             term,
-            "{}{}{}{}{:.7} {}{} {}{:>13}",
+            "{}{}{}{}{:.7} {}{} {}{:>13} {}{}{:>12}",
             cursor::Goto(1, MAIN_ROW + i as u16),
             clear::CurrentLine,
             color::Fg(color::Reset),
             style::Faint,
-            outpoint.txid,
+            utxo.outpoint.txid,
             style::NoFaint,
             addr,
             color::Fg(color::LightCyan),
-            Balance(txout.value),
+            Balance(utxo.txout.value),
+            color::Fg(color::Reset),
+            style::Faint,
+            confirmations,
         )?;
     }
     Ok(())
@@ -484,12 +992,600 @@ pub fn draw_addresses_tab<D: db::Read, W: io::Write>(
     Ok(())
 }
 
+pub fn draw_tokens_tab<D: db::Read, W: io::Write>(
+    ui: &Ui,
+    db: &D,
+    term: &mut W,
+) -> Result<(), Error> {
+    let tokens = utxo::tokens(&db.unspent()?);
+    let mut table = Table::default();
+
+    for token in tokens.iter() {
+        let (name, amount) = match db.token_metadata(&token.id)? {
+            Some(metadata) => (metadata.name.clone(), metadata.amount(token.amount)),
+            None => (String::from("-"), format!("{} fungible", token.amount)),
+        };
+        table.push([
+            name,
+            token.id.to_string(),
+            amount,
+            format!("{} NFT(s)", token.nfts.len()),
+        ]);
+        for nft in token.nfts.iter() {
+            let commitment = if nft.commitment.is_empty() {
+                String::from("(no commitment)")
+            } else {
+                nft.commitment.to_hex()
+            };
+            table.push([
+                String::new(),
+                String::new(),
+                nft.capability_name().to_owned(),
+                commitment,
+            ]);
+        }
+    }
+    table.render(ui.size.x as usize, MAIN_ROW, term)?;
+
+    Ok(())
+}
+
 pub fn draw_history_tab<D: db::Read, W: io::Write>(_db: &D, _term: &mut W) -> Result<(), Error> {
     Ok(())
 }
 
+pub fn draw_receive_tab<D: db::Read, W: io::Write>(
+    request: &str,
+    db: &D,
+    term: &mut W,
+) -> Result<(), Error> {
+    // Addresses are derived ahead of use, so the next unused one is always stored.
+    let next = db.addresses()?.into_iter().find(|a| !a.used);
+    let next = match next {
+        Some(next) => next,
+        None => {
+            write!(
+                term,
+                "{}{}No unused address available",
+                cursor::Goto(1, MAIN_ROW),
+                color::Fg(color::Red),
+            )?;
+            return Ok(());
+        }
+    };
+    let address = receive::cashaddr(&next.address).unwrap_or_else(|| next.address.to_string());
+    let source = match &next.account {
+        Some(account) => format!("account `{}`, index {}", account, next.index),
+        None => format!("device, index {}", next.index),
+    };
+
+    write!(
+        term,
+        "{}{}{}{} {}{}({})",
+        cursor::Goto(1, MAIN_ROW),
+        color::Fg(color::LightCyan),
+        address,
+        color::Fg(color::Reset),
+        style::Faint,
+        source,
+        style::Reset,
+    )?;
+
+    let amount = match request.trim() {
+        "" => Ok(None),
+        amount => send::parse_amount(amount).map(Some),
+    };
+    let uri = receive::uri(&address, amount.clone().unwrap_or(None));
+
+    write!(term, "{}", cursor::Goto(1, MAIN_ROW + 1))?;
+    match amount {
+        Ok(None) => write!(term, "{}Type an amount to request it", style::Faint)?,
+        Ok(Some(_)) => write!(term, "{}{}", color::Fg(color::LightCyan), uri)?,
+        Err(err) => write!(
+            term,
+            "{}Invalid amount `{}`: {}",
+            color::Fg(color::Red),
+            request,
+            err
+        )?,
+    }
+    write!(term, "{}{}", style::Reset, color::Fg(color::Reset))?;
+
+    match receive::qr(&uri) {
+        Ok(lines) => {
+            for (i, line) in lines.iter().enumerate() {
+                write!(term, "{}{}", cursor::Goto(1, MAIN_ROW + 3 + i as u16), line)?;
+            }
+        }
+        Err(err) => {
+            write!(
+                term,
+                "{}{}Failed to render QR code: {}",
+                cursor::Goto(1, MAIN_ROW + 3),
+                color::Fg(color::Red),
+                err
+            )?;
+        }
+    }
+    Ok(())
+}
+
+pub fn draw_peers_tab<W: io::Write>(ui: &Ui, term: &mut W) -> io::Result<()> {
+    let view = &ui.peers;
+    let mut total = Traffic::default();
+    let (mut send_rate, mut receive_rate) = (0., 0.);
+
+    for stats in view.rows.iter().filter_map(|r| r.stats.as_ref()) {
+        total += stats.traffic;
+        send_rate += stats.send_rate;
+        receive_rate += stats.receive_rate;
+    }
+    write!(
+        term,
+        "{}{}{}{} peer(s), ↑ {} ({}/s) ↓ {} ({}/s){}",
+        cursor::Goto(1, MAIN_ROW),
+        clear::CurrentLine,
+        color::Fg(color::LightCyan),
+        view.rows.len(),
+        Bytes(total.sent as f64),
+        Bytes(send_rate),
+        Bytes(total.received as f64),
+        Bytes(receive_rate),
+        color::Fg(color::Reset),
+    )?;
+
+    let mut table = Table::default();
+
+    for (i, row) in view.rows.iter().enumerate() {
+        let peer = &row.peer;
+        let stats = row.stats.as_ref();
+        let sent = stats.map_or(0, |s| s.traffic.sent);
+        let received = stats.map_or(0, |s| s.traffic.received);
+
+        table.push([
+            if i == view.selected { "›" } else { " " }.to_owned(),
+            peer.addr.to_string(),
+            peer.user_agent.clone(),
+            format!("{} ms", peer.latency.as_millis()),
+            peers::services(peer.services),
+            if peer.filter_loaded { "bloom" } else { "-" }.to_owned(),
+            format!(
+                "↑ {} ({}/s)",
+                Bytes(sent as f64),
+                Bytes(stats.map_or(0., |s| s.send_rate))
+            ),
+            format!(
+                "↓ {} ({}/s)",
+                Bytes(received as f64),
+                Bytes(stats.map_or(0., |s| s.receive_rate))
+            ),
+        ]);
+    }
+    let row = MAIN_ROW + 3 + view.rows.len() as u16;
+
+    table.render(ui.size.x as usize, MAIN_ROW + 2, term)?;
+
+    match &view.status {
+        Some(Ok(msg)) => write!(
+            term,
+            "{}{}{}",
+            cursor::Goto(1, row),
+            color::Fg(color::Green),
+            msg
+        )?,
+        Some(Err(err)) => write!(
+            term,
+            "{}{}{}",
+            cursor::Goto(1, row),
+            color::Fg(color::Red),
+            err
+        )?,
+        None => {}
+    }
+    write!(
+        term,
+        "{}{}{}{}up/down: select, d: disconnect, b: ban",
+        cursor::Goto(1, row + 2),
+        style::Reset,
+        color::Fg(color::Reset),
+        style::Faint,
+    )
+}
+
+pub fn draw_contacts_tab<W: io::Write>(view: &contacts::View, term: &mut W) -> io::Result<()> {
+    let mut row = MAIN_ROW;
+
+    match &view.mode {
+        contacts::Mode::Search => {
+            write!(
+                term,
+                "{}{}{:<10}{} {}",
+                cursor::Goto(1, row),
+                color::Fg(color::Red),
+                "Search",
+                style::Reset,
+                view.query,
+            )?;
+            row += 2;
+
+            if view.matches.is_empty() {
+                write!(
+                    term,
+                    "{}{}No contacts found",
+                    cursor::Goto(1, row),
+                    style::Faint
+                )?;
+                row += 1;
+            }
+            for (i, contact) in view.matches.iter().enumerate() {
+                let focus = if i == view.selected {
+                    style::Invert.to_string()
+                } else {
+                    String::new()
+                };
+                write!(
+                    term,
+                    "{}{}{:<20}{} {}{}{}",
+                    cursor::Goto(1, row),
+                    focus,
+                    contact.label,
+                    style::Reset,
+                    color::Fg(color::LightCyan),
+                    contact.address,
+                    color::Fg(color::Reset),
+                )?;
+                row += 1;
+            }
+        }
+        contacts::Mode::Edit { field, .. } => {
+            let fields = [
+                (contacts::Field::Label, "Label", &view.label),
+                (contacts::Field::Address, "Address", &view.address),
+            ];
+            for (f, label, value) in fields {
+                let focus = if f == *field {
+                    style::Invert.to_string()
+                } else {
+                    String::new()
+                };
+                write!(
+                    term,
+                    "{}{}{}{:<10}{} {}",
+                    cursor::Goto(1, row),
+                    color::Fg(color::Red),
+                    focus,
+                    label,
+                    style::Reset,
+                    value,
+                )?;
+                row += 1;
+            }
+        }
+    }
+    row += 1;
+
+    match &view.status {
+        Some(Ok(msg)) => write!(
+            term,
+            "{}{}{}",
+            cursor::Goto(1, row),
+            color::Fg(color::Green),
+            msg
+        )?,
+        Some(Err(err)) => write!(
+            term,
+            "{}{}{}",
+            cursor::Goto(1, row),
+            color::Fg(color::Red),
+            err
+        )?,
+        None => {}
+    }
+    let help = if view.is_searching() {
+        "type: search, up/down: select, enter: pay, ctrl-n: new, ctrl-e: edit, ctrl-d: delete, \
+         esc: back"
+    } else {
+        "enter: save, up/down: select field, esc: back"
+    };
+    write!(
+        term,
+        "{}{}{}{}{}",
+        cursor::Goto(1, row + 2),
+        style::Reset,
+        color::Fg(color::Reset),
+        style::Faint,
+        help
+    )
+}
+
+pub fn draw_send_tab<W: io::Write>(form: &Form, term: &mut W) -> io::Result<()> {
+    let batch = form.recipients.len() > 1;
+    let mut fields = Vec::new();
+
+    for (i, recipient) in form.recipients.iter().enumerate() {
+        let (to, amount) = if batch {
+            (format!("To #{}", i + 1), format!("Amount #{}", i + 1))
+        } else {
+            (String::from("To"), String::from("Amount"))
+        };
+        let address = match form.resolved.get(recipient.address.trim()) {
+            Some(resolved) => format!("{} → {}", recipient.address, resolved),
+            None => recipient.address.clone(),
+        };
+        fields.push((Some(i), send::Field::Address, to, address));
+        fields.push((
+            Some(i),
+            send::Field::Amount,
+            amount,
+            recipient.amount.clone(),
+        ));
+    }
+    fields.push((
+        None,
+        send::Field::Data,
+        String::from("Data"),
+        form.data.clone(),
+    ));
+    fields.push((
+        None,
+        send::Field::FeeRate,
+        String::from("Fee rate"),
+        match form.estimate {
+            Some(estimate) => format!(
+                "{} (estimated: {} sat/B)",
+                send::Slider(form.fee_rate),
+                estimate
+            ),
+            None => send::Slider(form.fee_rate).to_string(),
+        },
+    ));
+    let mut row = MAIN_ROW;
+
+    for (ix, field, label, value) in fields {
+        let focus = if field == form.field && ix.map_or(true, |ix| ix == form.row) {
+            style::Invert.to_string()
+        } else {
+            String::new()
+        };
+        write!(
+            term,
+            "{}{}{}{}{:<10}{} {}",
+            cursor::Goto(1, row),
+            clear::CurrentLine,
+            color::Fg(color::Red),
+            focus,
+            label,
+            style::Reset,
+            value,
+        )?;
+        row += 1;
+    }
+    row += 1;
+
+    if let Some(unsigned) = &form.preview {
+        let change = unsigned
+            .change
+            .map(|ix| Balance(unsigned.tx.output[ix].value).to_string())
+            .unwrap_or_else(|| String::from("none"));
+
+        let amount = unsigned.amount();
+
+        write!(
+            term,
+            "{}{}{} recipient(s), {} + fee {} sat = {}, change {}{}{}{}{} input(s), txid {}",
+            cursor::Goto(1, row),
+            color::Fg(color::LightCyan),
+            form.recipients.len(),
+            Balance(amount),
+            unsigned.fee,
+            Balance(amount + unsigned.fee),
+            change,
+            color::Fg(color::Reset),
+            cursor::Goto(1, row + 1),
+            style::Faint,
+            unsigned.inputs.len(),
+            unsigned.tx.txid(),
+        )?;
+        row += 3;
+    }
+    match &form.status {
+        Some(Ok(msg)) => write!(
+            term,
+            "{}{}{}",
+            cursor::Goto(1, row),
+            color::Fg(color::Green),
+            msg
+        )?,
+        Some(Err(err)) => write!(
+            term,
+            "{}{}{}",
+            cursor::Goto(1, row),
+            color::Fg(color::Red),
+            err
+        )?,
+        None => {}
+    }
+    let help = if form.preview.is_some() {
+        "enter: sign and send, esc: back"
+    } else {
+        "enter: preview, up/down: select field, ctrl-n/ctrl-d: add/remove recipient, \
+         left/right: fee rate, esc: back"
+    };
+    write!(
+        term,
+        "{}{}{}{}{}",
+        cursor::Goto(1, row + 2),
+        style::Reset,
+        color::Fg(color::Reset),
+        style::Faint,
+        help
+    )
+}
+
+pub fn draw_consolidate_tab<W: io::Write>(
+    form: &consolidate::Form,
+    estimate: Option<FeeRate>,
+    term: &mut W,
+) -> io::Result<()> {
+    let fields = [
+        (
+            consolidate::Field::Threshold,
+            "Below",
+            form.threshold.clone(),
+        ),
+        (
+            consolidate::Field::FeeRate,
+            "Fee rate",
+            match estimate {
+                Some(estimate) => format!(
+                    "{} (estimated: {} sat/B)",
+                    send::Slider(form.fee_rate),
+                    estimate
+                ),
+                None => send::Slider(form.fee_rate).to_string(),
+            },
+        ),
+    ];
+    let mut row = MAIN_ROW;
+
+    for (field, label, value) in fields {
+        let focus = if field == form.field {
+            style::Invert.to_string()
+        } else {
+            String::new()
+        };
+        write!(
+            term,
+            "{}{}{}{}{:<10}{} {}",
+            cursor::Goto(1, row),
+            clear::CurrentLine,
+            color::Fg(color::Red),
+            focus,
+            label,
+            style::Reset,
+            value,
+        )?;
+        row += 1;
+    }
+    row += 1;
+
+    if let Some(unsigned) = &form.preview {
+        let swept = unsigned.inputs.iter().map(|(_, o)| o.value).sum::<u64>();
+        let fee = unsigned.fee as f64 / swept as f64 * 100.;
+
+        write!(
+            term,
+            "{}{}{} coin(s) worth {} swept, fee {} sat ({:.1}%){}{}{}txid {}",
+            cursor::Goto(1, row),
+            color::Fg(color::LightCyan),
+            unsigned.inputs.len(),
+            Balance(swept),
+            unsigned.fee,
+            fee,
+            color::Fg(color::Reset),
+            cursor::Goto(1, row + 1),
+            style::Faint,
+            unsigned.tx.txid(),
+        )?;
+        row += 3;
+    }
+    match &form.status {
+        Some(Ok(msg)) => write!(
+            term,
+            "{}{}{}",
+            cursor::Goto(1, row),
+            color::Fg(color::Green),
+            msg
+        )?,
+        Some(Err(err)) => write!(
+            term,
+            "{}{}{}",
+            cursor::Goto(1, row),
+            color::Fg(color::Red),
+            err
+        )?,
+        None => {}
+    }
+    let help = if form.preview.is_some() {
+        "enter: sign and send, esc: back"
+    } else {
+        "enter: preview, up/down: select field, left/right: fee rate, esc: back"
+    };
+    write!(
+        term,
+        "{}{}{}{}{}",
+        cursor::Goto(1, row + 2),
+        style::Reset,
+        color::Fg(color::Reset),
+        style::Faint,
+        help
+    )
+}
+
+pub fn draw_log_pane<W: io::Write>(ui: &mut Ui, term: &mut W) -> io::Result<()> {
+    let Vec2D {
+        x: width,
+        y: height,
+    } = ui.size;
+    // The pane sits above the footer, and never covers the header.
+    let top = height.saturating_sub(LOG_ROWS + 3).max(MAIN_ROW);
+    let rows = height.saturating_sub(top + 3) as usize;
+    let lines = ui.logs.lines(rows);
+
+    write!(
+        term,
+        "{}{}{}{}",
+        cursor::Goto(1, top),
+        clear::CurrentLine,
+        color::Fg(color::Red),
+        "─".repeat(width as usize),
+    )?;
+    write!(
+        term,
+        "{}{}{} Log ({}) {}{}F2: hide, F3: level, page up/down: scroll",
+        cursor::Goto(2, top),
+        style::Invert,
+        color::Fg(color::Red),
+        ui.logs.level.as_str().to_lowercase(),
+        style::Reset,
+        style::Faint,
+    )?;
+    write!(term, "{}", style::Reset)?;
+
+    for i in 0..rows {
+        write!(
+            term,
+            "{}{}",
+            cursor::Goto(1, top + 1 + i as u16),
+            clear::CurrentLine
+        )?;
+
+        if let Some(line) = lines.get(i) {
+            let level = match line.level {
+                log::Level::Error => format!("{}", color::Fg(color::Red)),
+                log::Level::Warn => format!("{}", color::Fg(color::Yellow)),
+                log::Level::Info => format!("{}", color::Fg(color::Reset)),
+                _ => format!("{}", style::Faint),
+            };
+            let text = format!("{} {:5} {}", line.time, line.level, line.message);
+
+            write!(
+                term,
+                "{}{}{}",
+                level,
+                text.chars().take(width as usize).collect::<String>(),
+                style::Reset,
+            )?;
+        }
+    }
+    Ok(())
+}
+
 pub fn draw_footer<W: io::Write>(ui: &Ui, term: &mut W) -> io::Result<()> {
-    let Vec2D { y: height, .. } = ui.size;
+    let Vec2D {
+        x: width,
+        y: height,
+    } = ui.size;
 
     write!(
         term,
@@ -500,6 +1596,27 @@ pub fn draw_footer<W: io::Write>(ui: &Ui, term: &mut W) -> io::Result<()> {
         ui.message,
     )?;
 
+    if ui.pending > 0 {
+        let pending = format!("{} pending broadcast", ui.pending);
+
+        write!(
+            term,
+            "{}{}{}",
+            cursor::Goto(width - pending.len() as u16 + 1, height - 1),
+            color::Fg(color::Yellow),
+            pending,
+        )?;
+    }
+    if let Some(signing) = &ui.signing {
+        write!(
+            term,
+            "{}{}{}{}",
+            cursor::Goto(1, height - 2),
+            clear::CurrentLine,
+            color::Fg(color::Yellow),
+            signing,
+        )?;
+    }
     Ok(())
 }
 
@@ -512,4 +1629,49 @@ mod test {
         let output = Balance(14912334245).to_string();
         assert_eq!(output, "149.1233 BTC");
     }
+
+    #[test]
+    fn test_bytes_fmt() {
+        assert_eq!(Bytes(512.).to_string(), "512 B");
+        assert_eq!(Bytes(1536.).to_string(), "1.5 KiB");
+        assert_eq!(Bytes(3. * 1024. * 1024.).to_string(), "3.0 MiB");
+        assert_eq!(
+            Bytes(2048. * 1024. * 1024. * 1024.).to_string(),
+            "2048.0 GiB"
+        );
+    }
+
+    #[test]
+    fn test_status_fmt() {
+        let status = Status::SyncingHeaders {
+            height: 250,
+            best: 1000,
+            eta: Some(time::Duration::from_secs(3 * 3600 + 90)),
+        };
+        assert_eq!(
+            status.to_string(),
+            "Syncing headers ██░░░░░░░░ 25.00%, 3h 2m left"
+        );
+        assert_eq!(Eta(time::Duration::from_secs(30)).to_string(), "<1m");
+        assert_eq!(Eta(time::Duration::from_secs(600)).to_string(), "10m");
+    }
+
+    #[test]
+    fn test_signing_fmt() {
+        let addr = Address::from_script(
+            &nakamoto_test::block::gen::script(&mut fastrand::Rng::new()),
+            bitcoin::Network::Bitcoin,
+        )
+        .unwrap();
+        let output = Signing::Confirm {
+            outputs: vec![(addr.clone(), 100000000)],
+            fee: 226,
+        }
+        .to_string();
+
+        assert_eq!(
+            output,
+            format!("Confirm on device: 1.0000 BTC to {} (fee 226 sat)", addr)
+        );
+    }
 }
@@ -0,0 +1,126 @@
+//! Consolidate view.
+use termion::event::Key;
+
+use nakamoto_p2p::fsm::fees::FeeRate;
+
+use crate::wallet::tx::{UnsignedTx, DEFAULT_CONSOLIDATION_THRESHOLD, DEFAULT_FEE_RATE};
+use crate::wallet::ui::send::{Action, MAX_FEE_RATE};
+
+/// A field of the consolidate form.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Threshold,
+    FeeRate,
+}
+
+/// Consolidate form state.
+#[derive(Debug)]
+pub struct Form {
+    /// Value below which coins are swept, as typed. In BCH, unless suffixed with `sat`.
+    pub threshold: String,
+    /// Fee rate, in satoshis per byte. Consolidating isn't urgent, so this starts at the
+    /// lowest rate, rather than the estimate.
+    pub fee_rate: FeeRate,
+    /// Focused field.
+    pub field: Field,
+    /// Transaction built from the form, awaiting confirmation.
+    pub preview: Option<UnsignedTx>,
+    /// Result of the last action.
+    pub status: Option<Result<String, String>>,
+}
+
+impl Default for Form {
+    fn default() -> Self {
+        Self {
+            threshold: format!("{} sat", DEFAULT_CONSOLIDATION_THRESHOLD),
+            fee_rate: DEFAULT_FEE_RATE,
+            field: Field::Threshold,
+            preview: None,
+            status: None,
+        }
+    }
+}
+
+impl Form {
+    /// Handle a key press. Returns the action requested, if any.
+    pub fn handle_key(&mut self, key: Key) -> Option<Action> {
+        match key {
+            Key::Esc => return Some(Action::Cancel),
+            Key::Char('\n') => {
+                if self.preview.is_some() {
+                    return Some(Action::Submit);
+                }
+                return Some(Action::Preview);
+            }
+            Key::Up | Key::Down | Key::BackTab | Key::Char('\t') => {
+                self.field = match self.field {
+                    Field::Threshold => Field::FeeRate,
+                    Field::FeeRate => Field::Threshold,
+                };
+                return None;
+            }
+            Key::Left | Key::Char('-') if self.field == Field::FeeRate => {
+                self.fee_rate = self.fee_rate.saturating_sub(1).max(1);
+            }
+            Key::Right | Key::Char('+') if self.field == Field::FeeRate => {
+                self.fee_rate = (self.fee_rate + 1).min(MAX_FEE_RATE);
+            }
+            Key::Backspace if self.field == Field::Threshold => {
+                self.threshold.pop();
+            }
+            Key::Char(c) if !c.is_control() && self.field == Field::Threshold => {
+                self.threshold.push(c);
+            }
+            _ => return None,
+        }
+        // Any change to the form invalidates the preview.
+        self.preview = None;
+        self.status = None;
+
+        None
+    }
+
+    /// Clear the form, keeping the threshold and fee rate.
+    pub fn reset(&mut self) {
+        self.preview = None;
+        self.status = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_form() {
+        let mut form = Form::default();
+
+        for _ in 0..form.threshold.len() {
+            form.handle_key(Key::Backspace);
+        }
+        for c in "0.001".chars() {
+            form.handle_key(Key::Char(c));
+        }
+        assert_eq!(form.threshold, "0.001");
+
+        form.handle_key(Key::Down);
+        form.handle_key(Key::Right);
+        form.handle_key(Key::Char('x'));
+        assert_eq!(form.fee_rate, DEFAULT_FEE_RATE + 1);
+        assert_eq!(form.threshold, "0.001");
+
+        assert_eq!(form.handle_key(Key::Char('\n')), Some(Action::Preview));
+        form.preview = Some(UnsignedTx {
+            tx: nakamoto_test::block::gen::transaction(&mut fastrand::Rng::new()),
+            inputs: vec![],
+            fee: 0,
+            change: None,
+        });
+        assert_eq!(form.handle_key(Key::Char('\n')), Some(Action::Submit));
+
+        // Changing the fee rate discards the preview.
+        form.handle_key(Key::Left);
+        assert!(form.preview.is_none());
+        assert_eq!(form.handle_key(Key::Esc), Some(Action::Cancel));
+    }
+}
@@ -0,0 +1,259 @@
+//! Contacts view.
+use termion::event::Key;
+
+use crate::wallet::db::Contact;
+
+/// Action requested from the contacts view.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Pay a contact.
+    Pay(Contact),
+    /// Save a contact, replacing the contact with the original label, if any.
+    Save {
+        original: Option<String>,
+        contact: Contact,
+    },
+    /// Delete the contact with the given label.
+    Delete(String),
+    /// Leave the contacts view.
+    Cancel,
+}
+
+/// A field of the contact form.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Label,
+    Address,
+}
+
+/// What the contacts view is doing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mode {
+    /// Browsing and searching contacts.
+    Search,
+    /// Editing a contact, which is new unless its original label is given.
+    Edit {
+        original: Option<String>,
+        field: Field,
+    },
+}
+
+/// Contacts view state.
+#[derive(Debug)]
+pub struct View {
+    /// Search text, as typed.
+    pub query: String,
+    /// Contacts matching the search.
+    pub matches: Vec<Contact>,
+    /// Index of the selected contact.
+    pub selected: usize,
+    /// Current mode.
+    pub mode: Mode,
+    /// Label of the contact being edited, as typed.
+    pub label: String,
+    /// Address of the contact being edited, as typed.
+    pub address: String,
+    /// Result of the last action.
+    pub status: Option<Result<String, String>>,
+}
+
+impl Default for View {
+    fn default() -> Self {
+        Self {
+            query: String::new(),
+            matches: Vec::new(),
+            selected: 0,
+            mode: Mode::Search,
+            label: String::new(),
+            address: String::new(),
+            status: None,
+        }
+    }
+}
+
+impl View {
+    /// Whether contacts are being browsed, rather than edited.
+    pub fn is_searching(&self) -> bool {
+        self.mode == Mode::Search
+    }
+
+    /// The selected contact, if any.
+    pub fn selected(&self) -> Option<&Contact> {
+        self.matches.get(self.selected)
+    }
+
+    /// Set the contacts matching the search, keeping the selection within bounds.
+    pub fn set_matches(&mut self, matches: Vec<Contact>) {
+        self.selected = self.selected.min(matches.len().saturating_sub(1));
+        self.matches = matches;
+    }
+
+    /// Go back to browsing contacts, showing the given status.
+    pub fn set_status(&mut self, status: Result<String, String>) {
+        if status.is_ok() {
+            self.mode = Mode::Search;
+        }
+        self.status = Some(status);
+    }
+
+    /// Handle a key press. Returns the action requested, if any.
+    pub fn handle_key(&mut self, key: Key) -> Option<Action> {
+        self.status = None;
+
+        match self.mode.clone() {
+            Mode::Search => match key {
+                Key::Esc => return Some(Action::Cancel),
+                Key::Char('\n') => return self.selected().cloned().map(Action::Pay),
+                Key::Up => self.selected = self.selected.saturating_sub(1),
+                Key::Down => {
+                    self.selected = (self.selected + 1).min(self.matches.len().saturating_sub(1))
+                }
+                Key::Ctrl('n') => self.edit(None),
+                Key::Ctrl('e') => {
+                    if let Some(contact) = self.selected().cloned() {
+                        self.edit(Some(contact));
+                    }
+                }
+                Key::Ctrl('d') => {
+                    return self.selected().map(|c| Action::Delete(c.label.clone()));
+                }
+                Key::Backspace => {
+                    self.query.pop();
+                    self.selected = 0;
+                }
+                Key::Char(c) if !c.is_control() => {
+                    self.query.push(c);
+                    self.selected = 0;
+                }
+                _ => {}
+            },
+            Mode::Edit { original, field } => match key {
+                Key::Esc => self.mode = Mode::Search,
+                Key::Char('\n') => {
+                    return Some(Action::Save {
+                        original,
+                        contact: Contact {
+                            label: self.label.trim().to_owned(),
+                            address: self.address.trim().to_owned(),
+                        },
+                    });
+                }
+                Key::Up | Key::Down | Key::BackTab | Key::Char('\t') => {
+                    let field = match field {
+                        Field::Label => Field::Address,
+                        Field::Address => Field::Label,
+                    };
+                    self.mode = Mode::Edit { original, field };
+                }
+                Key::Backspace => {
+                    self.field(field).pop();
+                }
+                Key::Char(c) if !c.is_control() => self.field(field).push(c),
+                _ => {}
+            },
+        }
+        None
+    }
+
+    /// Start editing a contact, or a new contact if none is given.
+    fn edit(&mut self, contact: Option<Contact>) {
+        let (original, label, address) = match contact {
+            Some(c) => (Some(c.label.clone()), c.label, c.address),
+            None => (None, String::new(), String::new()),
+        };
+        self.label = label;
+        self.address = address;
+        self.mode = Mode::Edit {
+            original,
+            field: Field::Label,
+        };
+    }
+
+    fn field(&mut self, field: Field) -> &mut String {
+        match field {
+            Field::Label => &mut self.label,
+            Field::Address => &mut self.address,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contact(label: &str) -> Contact {
+        Contact {
+            label: label.to_owned(),
+            address: format!("bitcoincash:{}", label),
+        }
+    }
+
+    #[test]
+    fn test_search() {
+        let mut view = View::default();
+
+        view.handle_key(Key::Char('a'));
+        assert_eq!(view.query, "a");
+
+        view.set_matches(vec![contact("alice"), contact("carol")]);
+        view.handle_key(Key::Down);
+        view.handle_key(Key::Down);
+        assert_eq!(view.selected().unwrap().label, "carol");
+
+        view.set_matches(vec![contact("alice")]);
+        assert_eq!(view.selected, 0);
+        assert_eq!(
+            view.handle_key(Key::Char('\n')),
+            Some(Action::Pay(contact("alice")))
+        );
+        assert_eq!(
+            view.handle_key(Key::Ctrl('d')),
+            Some(Action::Delete(String::from("alice")))
+        );
+        assert_eq!(view.handle_key(Key::Esc), Some(Action::Cancel));
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut view = View::default();
+
+        view.set_matches(vec![contact("alice")]);
+        view.handle_key(Key::Ctrl('e'));
+        assert!(!view.is_searching());
+
+        view.handle_key(Key::Char('s'));
+        view.handle_key(Key::Char('\t'));
+        for _ in 0.."alice".len() {
+            view.handle_key(Key::Backspace);
+        }
+        view.handle_key(Key::Char('q'));
+
+        assert_eq!(
+            view.handle_key(Key::Char('\n')),
+            Some(Action::Save {
+                original: Some(String::from("alice")),
+                contact: Contact {
+                    label: String::from("alices"),
+                    address: String::from("bitcoincash:q"),
+                },
+            })
+        );
+        view.set_status(Err(String::from("invalid address")));
+        assert!(!view.is_searching());
+
+        view.set_status(Ok(String::from("saved")));
+        assert!(view.is_searching());
+
+        view.handle_key(Key::Ctrl('n'));
+        assert_eq!(
+            view.mode,
+            Mode::Edit {
+                original: None,
+                field: Field::Label
+            }
+        );
+        assert!(view.label.is_empty());
+        view.handle_key(Key::Esc);
+        assert!(view.is_searching());
+    }
+}
@@ -0,0 +1,148 @@
+//! Log pane.
+use log::Level;
+use termion::event::Key;
+
+use crate::logger::{Buffer, Line};
+
+/// Levels the pane can be set to, from least to most verbose.
+const LEVELS: [Level; 4] = [Level::Error, Level::Warn, Level::Info, Level::Debug];
+
+/// Log pane state.
+#[derive(Debug)]
+pub struct View {
+    /// Log lines, fed from the logger. Without a buffer, the pane can't be shown.
+    pub buffer: Option<Buffer>,
+    /// Whether the pane is shown.
+    pub visible: bool,
+    /// Most verbose level shown.
+    pub level: Level,
+    /// Number of lines scrolled up from the latest line.
+    pub scroll: usize,
+    /// Number of lines pushed to the buffer when the pane was last drawn.
+    pub drawn: u64,
+}
+
+impl Default for View {
+    fn default() -> Self {
+        Self {
+            buffer: None,
+            visible: false,
+            level: Level::Info,
+            scroll: 0,
+            drawn: 0,
+        }
+    }
+}
+
+impl View {
+    /// Whether the pane is shown.
+    pub fn is_visible(&self) -> bool {
+        self.visible && self.buffer.is_some()
+    }
+
+    /// Whether lines were logged since the pane was last drawn.
+    pub fn is_stale(&self) -> bool {
+        self.buffer
+            .as_ref()
+            .map_or(false, |b| b.pushed() != self.drawn)
+    }
+
+    /// The lines to show in a pane of the given height, oldest first.
+    pub fn lines(&mut self, height: usize) -> Vec<Line> {
+        let Some(buffer) = &self.buffer else {
+            return Vec::new();
+        };
+        let lines = buffer.lines(self.level);
+
+        self.drawn = buffer.pushed();
+        self.scroll = self.scroll.min(lines.len().saturating_sub(height));
+
+        let end = lines.len() - self.scroll;
+        let start = end.saturating_sub(height);
+
+        lines[start..end].to_vec()
+    }
+
+    /// Handle a key press. Returns whether the key was handled.
+    ///
+    /// `F2` toggles the pane. While it's shown, `F3` cycles through the levels shown, and
+    /// `PageUp`/`PageDown` scroll through the lines, by the given page size.
+    pub fn handle_key(&mut self, key: Key, page: usize) -> bool {
+        if self.buffer.is_none() {
+            return false;
+        }
+        match key {
+            Key::F(2) => {
+                self.visible = !self.visible;
+                self.scroll = 0;
+            }
+            Key::F(3) if self.visible => {
+                let i = LEVELS.iter().position(|l| *l == self.level).unwrap_or(0);
+
+                self.level = LEVELS[(i + 1) % LEVELS.len()];
+                self.scroll = 0;
+            }
+            Key::PageUp if self.visible => self.scroll += page,
+            Key::PageDown if self.visible => self.scroll = self.scroll.saturating_sub(page),
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_view() {
+        let buffer = Buffer::new(64);
+        let mut view = View::default();
+
+        assert!(!view.handle_key(Key::F(2), 2), "No buffer to show");
+
+        for i in 0..8 {
+            let level = if i % 2 == 0 {
+                Level::Info
+            } else {
+                Level::Debug
+            };
+
+            buffer.push(Line {
+                level,
+                time: String::new(),
+                message: i.to_string(),
+            });
+        }
+        view.buffer = Some(buffer);
+        assert!(!view.handle_key(Key::PageUp, 2), "Pane is hidden");
+        assert!(view.handle_key(Key::F(2), 2));
+        assert!(view.is_visible());
+        assert!(view.is_stale());
+
+        let messages = |view: &mut View| {
+            view.lines(2)
+                .into_iter()
+                .map(|l| l.message)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(messages(&mut view), vec!["4", "6"]);
+        assert!(!view.is_stale());
+
+        // Scrolling stops at the oldest line.
+        view.handle_key(Key::PageUp, 2);
+        assert_eq!(messages(&mut view), vec!["0", "2"]);
+        view.handle_key(Key::PageUp, 2);
+        assert_eq!(messages(&mut view), vec!["0", "2"]);
+        view.handle_key(Key::PageDown, 2);
+        assert_eq!(messages(&mut view), vec!["4", "6"]);
+
+        // Debug lines are shown at the debug level.
+        view.handle_key(Key::F(3), 2);
+        assert_eq!(view.level, Level::Debug);
+        assert_eq!(messages(&mut view), vec!["6", "7"]);
+        view.handle_key(Key::F(3), 2);
+        assert_eq!(view.level, Level::Error);
+        assert!(messages(&mut view).is_empty());
+    }
+}
@@ -0,0 +1,179 @@
+//! Peers view.
+use termion::event::Key;
+
+use nakamoto_common::bitcoin::network::constants::ServiceFlags;
+use nakamoto_p2p::fsm::stats::PeerStats;
+use nakamoto_p2p::fsm::{Peer, PeerId};
+
+/// Action requested from the peers view.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Disconnect from a peer.
+    Disconnect(PeerId),
+    /// Disconnect from a peer, and don't connect to it again.
+    Ban(PeerId),
+}
+
+/// A connected peer, as shown.
+#[derive(Debug, Clone)]
+pub struct Row {
+    /// The peer.
+    pub peer: Peer,
+    /// Bandwidth used by the peer, if known.
+    pub stats: Option<PeerStats>,
+}
+
+/// Peers view state.
+#[derive(Debug, Default)]
+pub struct View {
+    /// Connected peers.
+    pub rows: Vec<Row>,
+    /// Index of the selected peer.
+    pub selected: usize,
+    /// Result of the last action.
+    pub status: Option<Result<String, String>>,
+}
+
+impl View {
+    /// The selected peer, if any.
+    pub fn selected(&self) -> Option<&Row> {
+        self.rows.get(self.selected)
+    }
+
+    /// Set the connected peers, and the bandwidth they used. The same peer stays selected
+    /// while it's connected.
+    pub fn set_peers(&mut self, mut peers: Vec<Peer>, stats: Vec<PeerStats>) {
+        // Oldest connections first, so that rows don't move around as peers come and go.
+        peers.sort_by_key(|p| (p.since, p.addr));
+
+        let selected = self.selected().map(|r| r.peer.addr);
+
+        self.rows = peers
+            .into_iter()
+            .map(|peer| {
+                let stats = stats.iter().find(|s| s.addr == peer.addr).cloned();
+
+                Row { peer, stats }
+            })
+            .collect();
+        self.selected = selected
+            .and_then(|addr| self.rows.iter().position(|r| r.peer.addr == addr))
+            .unwrap_or(self.selected)
+            .min(self.rows.len().saturating_sub(1));
+    }
+
+    /// Show the result of the last action.
+    pub fn set_status(&mut self, status: Result<String, String>) {
+        self.status = Some(status);
+    }
+
+    /// Handle a key press. Returns the action requested, if any.
+    pub fn handle_key(&mut self, key: Key) -> Option<Action> {
+        match key {
+            Key::Up => self.selected = self.selected.saturating_sub(1),
+            Key::Down => self.selected = (self.selected + 1).min(self.rows.len().saturating_sub(1)),
+            Key::Char('d') => {
+                return self.selected().map(|r| Action::Disconnect(r.peer.addr));
+            }
+            Key::Char('b') => {
+                return self.selected().map(|r| Action::Ban(r.peer.addr));
+            }
+            _ => return None,
+        }
+        self.status = None;
+
+        None
+    }
+}
+
+/// Short names of the services a peer offers, eg. `network,bloom`.
+pub fn services(flags: ServiceFlags) -> String {
+    let names = [
+        (ServiceFlags::NETWORK, "network"),
+        (ServiceFlags::NETWORK_LIMITED, "limited"),
+        (ServiceFlags::BLOOM, "bloom"),
+        (ServiceFlags::COMPACT_FILTERS, "filters"),
+    ];
+    let names = names
+        .iter()
+        .filter(|(flag, _)| flags.has(*flag))
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>();
+
+    if names.is_empty() {
+        String::from("none")
+    } else {
+        names.join(",")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nakamoto_common::block::time::{LocalDuration, LocalTime};
+    use nakamoto_p2p::fsm::Link;
+
+    fn peer(addr: PeerId, since: LocalTime) -> Peer {
+        Peer {
+            addr,
+            local_addr: ([0, 0, 0, 0], 0).into(),
+            link: Link::Outbound,
+            since,
+            height: 0,
+            services: ServiceFlags::NETWORK | ServiceFlags::BLOOM,
+            user_agent: String::from("/Bitcoin Cash Node:26.0.0/"),
+            relay: true,
+            latency: LocalDuration::from_millis(120),
+            filter_loaded: true,
+        }
+    }
+
+    #[test]
+    fn test_view() {
+        let time = LocalTime::now();
+        let alice: PeerId = ([88, 88, 88, 88], 8333).into();
+        let bob: PeerId = ([99, 99, 99, 99], 8333).into();
+        let mut view = View::default();
+
+        assert_eq!(view.handle_key(Key::Char('d')), None);
+
+        view.set_peers(
+            vec![
+                peer(bob, time + LocalDuration::from_secs(1)),
+                peer(alice, time),
+            ],
+            vec![],
+        );
+        assert_eq!(view.rows[0].peer.addr, alice);
+        assert!(view.rows[0].stats.is_none());
+
+        view.handle_key(Key::Down);
+        view.handle_key(Key::Down);
+        assert_eq!(view.handle_key(Key::Char('b')), Some(Action::Ban(bob)));
+
+        // Bob stays selected when peers are refreshed.
+        let carol: PeerId = ([77, 77, 77, 77], 8333).into();
+        view.set_peers(
+            vec![peer(alice, time), peer(bob, time), peer(carol, time)],
+            vec![],
+        );
+        assert_eq!(view.selected().map(|r| r.peer.addr), Some(bob));
+
+        // The selection stays in bounds when peers disconnect.
+        view.handle_key(Key::Down);
+        view.set_peers(vec![peer(alice, time)], vec![]);
+        assert_eq!(
+            view.handle_key(Key::Char('d')),
+            Some(Action::Disconnect(alice))
+        );
+    }
+
+    #[test]
+    fn test_services() {
+        assert_eq!(
+            services(ServiceFlags::NETWORK | ServiceFlags::BLOOM),
+            "network,bloom"
+        );
+        assert_eq!(services(ServiceFlags::NONE), "none");
+    }
+}
@@ -0,0 +1,99 @@
+//! Receive view.
+use nakamoto_common::bitcoin::cash_addr;
+use nakamoto_common::bitcoin::util::bip21::Uri;
+use nakamoto_common::bitcoin::{Address, Amount};
+
+use qrcode::render::unicode::Dense1x2;
+use qrcode::types::QrError;
+use qrcode::QrCode;
+
+/// Encode an address as a cashaddr. Returns `None` for addresses that have no cashaddr
+/// representation, such as segwit addresses.
+pub fn cashaddr(address: &Address) -> Option<String> {
+    cash_addr::Address::try_from(address.clone())
+        .ok()
+        .map(|addr| addr.to_string())
+}
+
+/// Build a payment URI for a cashaddr, requesting the given amount in satoshis, if any.
+pub fn uri(address: &str, amount: Option<u64>) -> String {
+    match Uri::new(address) {
+        Ok(mut uri) => {
+            uri.amount = amount.map(Amount::from_sat);
+            uri.to_string()
+        }
+        Err(_) => address.to_owned(),
+    }
+}
+
+/// Render data as a QR code made of unicode half blocks, two modules per character.
+///
+/// Colors are inverted, so that the code scans on terminals with a dark background. Addresses
+/// are uppercased, which lets the more compact alphanumeric mode be used, but URI parameters
+/// are left alone, since they are case-sensitive.
+pub fn qr(data: &str) -> Result<Vec<String>, QrError> {
+    let data = match data.find('?') {
+        Some(ix) => format!("{}{}", data[..ix].to_uppercase(), &data[ix..]),
+        None => data.to_uppercase(),
+    };
+    let code = QrCode::new(data.as_bytes())?;
+    let rendered = code
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .quiet_zone(true)
+        .build();
+
+    Ok(rendered.lines().map(ToOwned::to_owned).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nakamoto_common::bitcoin::hashes::{hex::FromHex, Hash};
+    use nakamoto_common::bitcoin::util::address::Payload;
+    use nakamoto_common::bitcoin::{Network, PubkeyHash, ScriptHash32};
+
+    #[test]
+    fn test_cashaddr() {
+        let hash = PubkeyHash::from_slice(
+            &Vec::<u8>::from_hex("f5bf48b397dae70be82b3cca4793f8eb2b6cdac9").unwrap(),
+        )
+        .unwrap();
+        let addr = Address {
+            payload: Payload::PubkeyHash(hash),
+            network: Network::Bitcoin,
+        };
+        assert_eq!(
+            cashaddr(&addr).unwrap(),
+            "bitcoincash:qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekg2"
+        );
+
+        let addr = Address {
+            payload: Payload::ScriptHash32(ScriptHash32::from_inner([0xff; 32])),
+            network: Network::Bitcoin,
+        };
+        assert_eq!(
+            cashaddr(&addr).unwrap(),
+            "bitcoincash:p0llllllllllllllllllllllllllllllllllllllllllllllllll7x3vthu35"
+        );
+    }
+
+    #[test]
+    fn test_uri() {
+        let addr = "bitcoincash:qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekg2";
+
+        assert_eq!(uri(addr, None), addr);
+        assert_eq!(uri(addr, Some(25_000)), format!("{}?amount=0.00025", addr));
+    }
+
+    #[test]
+    fn test_qr() {
+        let lines = qr("bitcoincash:qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekg2").unwrap();
+        let width = lines[0].chars().count();
+
+        assert!(lines.iter().all(|l| l.chars().count() == width));
+        // Two rows of modules per line.
+        assert_eq!(lines.len(), (width + 1) / 2);
+    }
+}
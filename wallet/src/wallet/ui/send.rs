@@ -0,0 +1,536 @@
+//! Send view.
+//!
+//! Several recipients can be paid in a single transaction, one per row of the form. Data,
+//! eg. a memo, can also be attached to the transaction.
+use std::collections::BTreeMap;
+use std::fmt;
+
+use termion::event::Key;
+
+use nakamoto_common::bitcoin::cash_addr;
+use nakamoto_common::bitcoin::hashes::hex::FromHex;
+use nakamoto_common::bitcoin::util::bip21::Uri;
+use nakamoto_common::bitcoin::{Address, Amount, Denomination, Network};
+use nakamoto_p2p::fsm::fees::FeeRate;
+
+use crate::wallet::resolve::Resolved;
+use crate::wallet::tx::{UnsignedTx, DEFAULT_FEE_RATE};
+
+/// Maximum fee rate selectable with the fee slider, in satoshis per byte.
+pub const MAX_FEE_RATE: FeeRate = 20;
+
+/// Number of blocks within which we'd like transactions to confirm, when estimating fees.
+pub const CONFIRMATION_TARGET: usize = 2;
+
+/// Action requested from the send view.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Build the transaction and show a preview.
+    Preview,
+    /// Sign and submit the previewed transaction.
+    Submit,
+    /// Leave the send view.
+    Cancel,
+}
+
+/// A field of the send form. The address and amount fields are those of the focused row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Address,
+    Amount,
+    Data,
+    FeeRate,
+}
+
+/// A row of the send form, paying one recipient.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recipient {
+    /// Recipient cashaddr or contact label, as typed. A payment URI may also be entered, in
+    /// which case it is replaced by its address once the field is left.
+    pub address: String,
+    /// Amount, as typed. In BCH, unless suffixed with `sat`.
+    pub amount: String,
+}
+
+impl Recipient {
+    /// Whether nothing was typed in the row.
+    pub fn is_empty(&self) -> bool {
+        self.address.trim().is_empty() && self.amount.trim().is_empty()
+    }
+}
+
+/// Send form state.
+#[derive(Debug)]
+pub struct Form {
+    /// Recipients, one per row. There is always at least one.
+    pub recipients: Vec<Recipient>,
+    /// Index of the focused row.
+    pub row: usize,
+    /// Data attached to the transaction, as typed. See [`parse_data`].
+    pub data: String,
+    /// Addresses resolved from the names entered as recipients, and shown to the user.
+    pub resolved: BTreeMap<String, Resolved>,
+    /// Fee rate, in satoshis per byte.
+    pub fee_rate: FeeRate,
+    /// Fee rate estimated by the node, if any.
+    pub estimate: Option<FeeRate>,
+    /// Whether the fee rate was chosen by the user, rather than estimated.
+    pub fee_rate_set: bool,
+    /// Focused field.
+    pub field: Field,
+    /// Transaction built from the form, awaiting confirmation.
+    pub preview: Option<UnsignedTx>,
+    /// Result of the last action.
+    pub status: Option<Result<String, String>>,
+}
+
+impl Default for Form {
+    fn default() -> Self {
+        Self {
+            recipients: vec![Recipient::default()],
+            row: 0,
+            data: String::new(),
+            resolved: BTreeMap::new(),
+            fee_rate: DEFAULT_FEE_RATE,
+            estimate: None,
+            fee_rate_set: false,
+            field: Field::Address,
+            preview: None,
+            status: None,
+        }
+    }
+}
+
+impl Form {
+    /// Handle a key press. Returns the action requested, if any.
+    ///
+    /// `Ctrl-n` adds a recipient row below the focused one, and `Ctrl-d` removes the
+    /// focused row, unless it's the only one.
+    pub fn handle_key(&mut self, key: Key) -> Option<Action> {
+        if self.field == Field::Address
+            && matches!(
+                key,
+                Key::Char('\n' | '\t') | Key::Up | Key::Down | Key::BackTab | Key::Ctrl('n')
+            )
+        {
+            self.apply_uri();
+        }
+        let last = self.recipients.len() - 1;
+
+        match key {
+            Key::Esc => return Some(Action::Cancel),
+            Key::Char('\n') => {
+                if self.preview.is_some() {
+                    return Some(Action::Submit);
+                }
+                return Some(Action::Preview);
+            }
+            Key::Up | Key::BackTab => {
+                self.field = match self.field {
+                    Field::Address if self.row > 0 => {
+                        self.row -= 1;
+                        Field::Amount
+                    }
+                    Field::Address => Field::FeeRate,
+                    Field::Amount => Field::Address,
+                    Field::Data => {
+                        self.row = last;
+                        Field::Amount
+                    }
+                    Field::FeeRate => Field::Data,
+                };
+                return None;
+            }
+            Key::Down | Key::Char('\t') => {
+                self.field = match self.field {
+                    Field::Address => Field::Amount,
+                    Field::Amount if self.row < last => {
+                        self.row += 1;
+                        Field::Address
+                    }
+                    Field::Amount => Field::Data,
+                    Field::Data => Field::FeeRate,
+                    Field::FeeRate => {
+                        self.row = 0;
+                        Field::Address
+                    }
+                };
+                return None;
+            }
+            Key::Ctrl('n') if matches!(self.field, Field::Address | Field::Amount) => {
+                self.row += 1;
+                self.recipients.insert(self.row, Recipient::default());
+                self.field = Field::Address;
+            }
+            Key::Ctrl('d') if matches!(self.field, Field::Address | Field::Amount) && last > 0 => {
+                self.recipients.remove(self.row);
+                self.row = self.row.min(last - 1);
+            }
+            Key::Left | Key::Char('-') if self.field == Field::FeeRate => {
+                self.fee_rate = self.fee_rate.saturating_sub(1).max(1);
+                self.fee_rate_set = true;
+            }
+            Key::Right | Key::Char('+') if self.field == Field::FeeRate => {
+                self.fee_rate = (self.fee_rate + 1).min(MAX_FEE_RATE);
+                self.fee_rate_set = true;
+            }
+            Key::Backspace => match self.field {
+                Field::Address => {
+                    self.recipients[self.row].address.pop();
+                }
+                Field::Amount => {
+                    self.recipients[self.row].amount.pop();
+                }
+                Field::Data => {
+                    self.data.pop();
+                }
+                Field::FeeRate => {}
+            },
+            Key::Char(c) if !c.is_control() => match self.field {
+                Field::Address => self.recipients[self.row].address.push(c),
+                Field::Amount => self.recipients[self.row].amount.push(c),
+                Field::Data => self.data.push(c),
+                Field::FeeRate => {}
+            },
+            _ => return None,
+        }
+        // Any change to the form invalidates the preview.
+        self.preview = None;
+        self.status = None;
+
+        None
+    }
+
+    /// Pay the given address, in the focused row if it's empty, or in a new row otherwise.
+    /// The amount field of that row is focused.
+    pub fn add_recipient(&mut self, address: String) {
+        if !self.recipients[self.row].is_empty() {
+            self.row = self.recipients.len();
+            self.recipients.push(Recipient::default());
+        }
+        self.recipients[self.row].address = address;
+        self.field = Field::Amount;
+        self.preview = None;
+        self.status = None;
+    }
+
+    /// If a payment URI was entered as the focused address, replace it with the address it
+    /// pays to, and fill in the amount it requests. The label and message are shown as the
+    /// status.
+    fn apply_uri(&mut self) {
+        let recipient = &mut self.recipients[self.row];
+
+        if !recipient.address.contains('?') {
+            return;
+        }
+        let uri = match recipient.address.parse::<Uri>() {
+            Ok(uri) => uri,
+            Err(err) => {
+                self.status = Some(Err(format!("Invalid payment URI: {}", err)));
+                return;
+            }
+        };
+        if uri.token.is_some() {
+            self.status = Some(Err(String::from("Token payments aren't supported")));
+            return;
+        }
+        recipient.address = uri.address;
+
+        if let Some(amount) = uri.amount {
+            recipient.amount = format!("{} sat", amount.to_sat());
+        }
+        let description = [uri.label, uri.message]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+
+        self.status = if description.is_empty() {
+            None
+        } else {
+            Some(Ok(description.join(": ")))
+        };
+        self.preview = None;
+    }
+
+    /// Set the estimated fee rate. Unless the user already chose a fee rate, the estimate is
+    /// used as the fee rate. Any pending preview is kept, since it was built with the fee rate
+    /// shown to the user.
+    pub fn set_estimate(&mut self, estimate: FeeRate) {
+        self.estimate = Some(estimate);
+
+        if !self.fee_rate_set && self.preview.is_none() {
+            self.fee_rate = estimate.clamp(1, MAX_FEE_RATE);
+        }
+    }
+
+    /// Clear the form, keeping the fee rate.
+    pub fn reset(&mut self) {
+        *self = Self {
+            fee_rate: self.fee_rate,
+            estimate: self.estimate,
+            fee_rate_set: self.fee_rate_set,
+            ..Self::default()
+        };
+    }
+}
+
+/// Fee rate slider.
+pub struct Slider(pub FeeRate);
+
+impl fmt::Display for Slider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let filled = self.0.min(MAX_FEE_RATE) as usize;
+
+        write!(
+            f,
+            "[{}{}] {} sat/B",
+            "#".repeat(filled),
+            "-".repeat(MAX_FEE_RATE as usize - filled),
+            self.0
+        )
+    }
+}
+
+/// Parse a cashaddr for the given network. Token-aware addresses are accepted.
+pub fn parse_address(s: &str, network: Network) -> Result<Address, String> {
+    let addr = s
+        .trim()
+        .parse::<cash_addr::Address>()
+        .map_err(|e| e.to_string())?;
+
+    if !addr.is_valid_for_network(network) {
+        return Err(format!("address is not valid on {}", network));
+    }
+    Ok(Address {
+        network,
+        ..addr.into()
+    })
+}
+
+/// Parse an amount, in BCH by default, or in satoshis when suffixed with `sat` or `sats`.
+pub fn parse_amount(s: &str) -> Result<u64, String> {
+    let s = s.trim().to_lowercase();
+
+    if let Some(sats) = s.strip_suffix("sats").or_else(|| s.strip_suffix("sat")) {
+        return sats
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| format!("invalid amount `{}`", s));
+    }
+    let bch = s.strip_suffix("bch").unwrap_or(&s).trim();
+
+    Amount::from_str_in(bch, Denomination::Bitcoin)
+        .map(|a| a.to_sat())
+        .map_err(|e| e.to_string())
+}
+
+/// Parse data to attach to a transaction: hex-encoded bytes when prefixed with `0x`, or
+/// otherwise UTF-8 text. Returns `None` if there's no data.
+pub fn parse_data(s: &str) -> Result<Option<Vec<u8>>, String> {
+    if s.is_empty() {
+        return Ok(None);
+    }
+    match s.strip_prefix("0x") {
+        Some(hex) => Vec::<u8>::from_hex(hex)
+            .map(Some)
+            .map_err(|e| format!("invalid hex data: {}", e)),
+        None => Ok(Some(s.as_bytes().to_vec())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nakamoto_common::bitcoin::util::address::Payload;
+
+    #[test]
+    fn test_parse_address() {
+        let addr = parse_address(
+            "bitcoincash:qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekg2",
+            Network::Bitcoin,
+        )
+        .unwrap();
+        assert!(matches!(addr.payload, Payload::PubkeyHash(_)));
+
+        // Token-aware.
+        let addr = parse_address(
+            "bitcoincash:rqv53dwyatxse2xh7nnlqhyr6ryjgfdtag38xjkhc5",
+            Network::Bitcoin,
+        )
+        .unwrap();
+        assert!(matches!(addr.payload, Payload::ScriptHash(_)));
+
+        // 32-byte P2SH.
+        let addr = parse_address(
+            "bitcoincash:p0llllllllllllllllllllllllllllllllllllllllllllllllll7x3vthu35",
+            Network::Bitcoin,
+        )
+        .unwrap();
+        assert!(addr.script_pubkey().is_p2sh32());
+
+        // Wrong network.
+        assert!(parse_address(
+            "bitcoincash:qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekg2",
+            Network::Chipnet
+        )
+        .is_err());
+        // Bad checksum, with the likely address suggested.
+        assert_eq!(
+            parse_address(
+                "bitcoincash:qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekg3",
+                Network::Bitcoin
+            )
+            .unwrap_err(),
+            "invalid checksum, did you mean \
+             bitcoincash:qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekg2?"
+        );
+    }
+
+    #[test]
+    fn test_parse_amount() {
+        assert_eq!(parse_amount("0.5").unwrap(), 50_000_000);
+        assert_eq!(parse_amount("1.25 BCH").unwrap(), 125_000_000);
+        assert_eq!(parse_amount("1000 sat").unwrap(), 1000);
+        assert_eq!(parse_amount("42sats").unwrap(), 42);
+        assert!(parse_amount("abc").is_err());
+        assert!(parse_amount("1.5 sat").is_err());
+    }
+
+    #[test]
+    fn test_parse_data() {
+        assert_eq!(parse_data("").unwrap(), None);
+        assert_eq!(parse_data("gm").unwrap(), Some(b"gm".to_vec()));
+        assert_eq!(parse_data("0x6d02").unwrap(), Some(vec![0x6d, 0x02]));
+        assert!(parse_data("0x6d0").is_err());
+    }
+
+    #[test]
+    fn test_payment_uri() {
+        let mut send = Form::default();
+
+        for c in
+            "bitcoincash:qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekg2?amount=0.5&label=Alice".chars()
+        {
+            send.handle_key(Key::Char(c));
+        }
+        assert_eq!(send.handle_key(Key::Char('\n')), Some(Action::Preview));
+        assert_eq!(
+            send.recipients[0].address,
+            "bitcoincash:qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekg2"
+        );
+        assert_eq!(
+            parse_amount(&send.recipients[0].amount).unwrap(),
+            50_000_000
+        );
+        assert_eq!(send.status, Some(Ok(String::from("Alice"))));
+
+        let mut send = Form {
+            recipients: vec![Recipient {
+                address: String::from(
+                    "bitcoincash:qr6m7j9njldwwzlg9v7v53unlr4jkmx6eylep8ekg2?amount=x",
+                ),
+                amount: String::new(),
+            }],
+            ..Form::default()
+        };
+        send.handle_key(Key::Down);
+        assert_eq!(send.field, Field::Amount);
+        assert!(matches!(send.status, Some(Err(_))));
+        assert!(send.recipients[0].amount.is_empty());
+    }
+
+    #[test]
+    fn test_recipients() {
+        let mut send = Form::default();
+        let type_str = |send: &mut Form, s: &str| {
+            for c in s.chars() {
+                send.handle_key(Key::Char(c));
+            }
+        };
+        type_str(&mut send, "alice");
+        send.handle_key(Key::Down);
+        type_str(&mut send, "1 sat");
+
+        // Add a second row, below the first.
+        send.handle_key(Key::Ctrl('n'));
+        assert_eq!((send.row, send.field), (1, Field::Address));
+        type_str(&mut send, "bob");
+        send.handle_key(Key::Down);
+        type_str(&mut send, "2 sat");
+        assert_eq!(send.recipients.len(), 2);
+        assert_eq!(send.recipients[1].address, "bob");
+        assert_eq!(send.recipients[1].amount, "2 sat");
+
+        // Moving past the last row reaches the data and fee rate, then wraps around.
+        send.handle_key(Key::Down);
+        assert_eq!(send.field, Field::Data);
+        type_str(&mut send, "memo");
+        assert_eq!(send.data, "memo");
+        send.handle_key(Key::Down);
+        assert_eq!(send.field, Field::FeeRate);
+        send.handle_key(Key::Down);
+        assert_eq!((send.row, send.field), (0, Field::Address));
+        send.handle_key(Key::Up);
+        assert_eq!(send.field, Field::FeeRate);
+        send.handle_key(Key::Up);
+        send.handle_key(Key::Up);
+        assert_eq!((send.row, send.field), (1, Field::Amount));
+        send.handle_key(Key::Up);
+        send.handle_key(Key::Up);
+        assert_eq!((send.row, send.field), (0, Field::Amount));
+
+        // Rows can be removed, but not the last one.
+        send.handle_key(Key::Ctrl('d'));
+        assert_eq!(send.row, 0);
+        assert_eq!(send.recipients[0].address, "bob");
+        send.handle_key(Key::Ctrl('d'));
+        assert_eq!(send.recipients.len(), 1);
+
+        // Contacts fill the focused row if it's empty, or get a new row.
+        send.add_recipient(String::from("carol"));
+        assert_eq!(send.recipients.len(), 2);
+        assert_eq!((send.row, send.field), (1, Field::Amount));
+
+        let mut send = Form::default();
+        send.add_recipient(String::from("carol"));
+        assert_eq!(send.recipients.len(), 1);
+        assert_eq!(send.recipients[0].address, "carol");
+    }
+
+    #[test]
+    fn test_fee_slider() {
+        let mut send = Form {
+            field: Field::FeeRate,
+            ..Form::default()
+        };
+        send.handle_key(Key::Left);
+        assert_eq!(send.fee_rate, 1);
+
+        for _ in 0..MAX_FEE_RATE * 2 {
+            send.handle_key(Key::Right);
+        }
+        assert_eq!(send.fee_rate, MAX_FEE_RATE);
+        assert_eq!(
+            Slider(2).to_string(),
+            format!("[##{}] 2 sat/B", "-".repeat(MAX_FEE_RATE as usize - 2))
+        );
+    }
+
+    #[test]
+    fn test_fee_estimate() {
+        let mut send = Form::default();
+
+        send.set_estimate(3);
+        assert_eq!(send.fee_rate, 3);
+        send.set_estimate(MAX_FEE_RATE * 2);
+        assert_eq!(send.fee_rate, MAX_FEE_RATE);
+        assert_eq!(send.estimate, Some(MAX_FEE_RATE * 2));
+
+        // Once the user picks a fee rate, estimates no longer override it.
+        send.field = Field::FeeRate;
+        send.handle_key(Key::Left);
+        send.set_estimate(5);
+        assert_eq!(send.fee_rate, MAX_FEE_RATE - 1);
+        assert_eq!(send.estimate, Some(5));
+    }
+}
@@ -0,0 +1,683 @@
+//! UTXO set tracking.
+//!
+//! Matched transactions are applied to the UTXO set stored in the wallet database. Outputs
+//! are confirmed once the block that includes them is known, and unconfirmed again if that
+//! block is reverted.
+//!
+//! How many confirmations make an output final is a wallet setting, see [`Settings`].
+//! Coinbase outputs can't be spent before they mature, regardless of the setting.
+//!
+//! Outputs may carry CashTokens, which are tracked alongside their BCH value.
+use std::collections::{BTreeMap, HashMap};
+
+use nakamoto_common::bitcoin::blockdata::token::Capability;
+use nakamoto_common::bitcoin::{Address, OutPoint, Script, TokenID, Transaction, TxOut, Txid};
+use nakamoto_common::block::snapshot::SnapshotUtxo;
+use nakamoto_common::block::Height;
+
+use crate::wallet::db::{self, Db, Read, Write as _};
+
+/// Number of confirmations a coinbase output needs before it can be spent.
+pub const COINBASE_MATURITY: Height = 100;
+/// Number of confirmations after which outputs are final, unless configured otherwise.
+pub const DEFAULT_MIN_CONFIRMATIONS: Height = 1;
+
+/// Wallet settings governing when outputs are final.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    /// Number of confirmations after which an output counts towards the confirmed balance.
+    /// Cold storage may want to require more than one, to guard against re-orgs.
+    pub min_confirmations: Height,
+}
+
+impl Settings {
+    /// Load the settings stored in the wallet database, using defaults for those missing.
+    pub fn load<D: Read>(db: &D) -> Result<Self, db::Error> {
+        let mut settings = Self::default();
+
+        if let Some(value) = db.setting("min_confirmations")? {
+            settings.min_confirmations = value
+                .parse()
+                .map_err(|_| db::Error::Decoding("min_confirmations"))?;
+        }
+        Ok(settings)
+    }
+
+    /// Store the settings in the wallet database.
+    pub fn save(&self, db: &Db) -> Result<(), db::Error> {
+        db.set_setting("min_confirmations", &self.min_confirmations.to_string())?;
+
+        Ok(())
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            min_confirmations: DEFAULT_MIN_CONFIRMATIONS,
+        }
+    }
+}
+
+/// Wallet balance, in satoshis.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Balance {
+    /// Value of final outputs.
+    pub confirmed: u64,
+    /// Value of outputs not yet final, or not yet mature.
+    pub unconfirmed: u64,
+}
+
+impl Balance {
+    /// Compute the balance of the given outputs, as of the given tip. Outputs count as
+    /// confirmed once final, see [`Utxo::is_final`].
+    pub fn new<'a>(
+        utxos: impl IntoIterator<Item = &'a Utxo>,
+        tip: Height,
+        settings: &Settings,
+    ) -> Self {
+        let mut balance = Self::default();
+
+        for utxo in utxos {
+            if utxo.is_final(tip, settings) {
+                balance.confirmed += utxo.txout.value;
+            } else {
+                balance.unconfirmed += utxo.txout.value;
+            }
+        }
+        balance
+    }
+
+    /// Total balance, including unconfirmed outputs.
+    pub fn total(&self) -> u64 {
+        self.confirmed + self.unconfirmed
+    }
+}
+
+/// An unspent output owned by the wallet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Utxo {
+    /// The output's outpoint.
+    pub outpoint: OutPoint,
+    /// The output.
+    pub txout: TxOut,
+    /// Height of the block including the output, if confirmed.
+    pub height: Option<Height>,
+    /// Whether the output was created by a coinbase transaction.
+    pub coinbase: bool,
+}
+
+impl Utxo {
+    /// Number of confirmations of this output, given the current chain tip.
+    pub fn confirmations(&self, tip: Height) -> Height {
+        match self.height {
+            Some(height) if height <= tip => tip - height + 1,
+            _ => 0,
+        }
+    }
+
+    /// Whether the output can be spent in the next block. Coinbase outputs need
+    /// [`COINBASE_MATURITY`] confirmations first.
+    pub fn is_mature(&self, tip: Height) -> bool {
+        !self.coinbase || self.confirmations(tip) >= COINBASE_MATURITY
+    }
+
+    /// Whether the output is mature, and has the number of confirmations required by the
+    /// wallet settings.
+    pub fn is_final(&self, tip: Height, settings: &Settings) -> bool {
+        self.is_mature(tip) && self.confirmations(tip) >= settings.min_confirmations
+    }
+}
+
+/// A non-fungible token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nft {
+    /// The NFT's capability, eg. [`Capability::Minting`].
+    pub capability: u8,
+    /// The NFT's commitment.
+    pub commitment: Vec<u8>,
+    /// The output holding the NFT.
+    pub outpoint: OutPoint,
+}
+
+impl Nft {
+    /// Name of the NFT's capability.
+    pub fn capability_name(&self) -> &'static str {
+        if self.capability == Capability::Minting as u8 {
+            "minting"
+        } else if self.capability == Capability::Mutable as u8 {
+            "mutable"
+        } else {
+            "immutable"
+        }
+    }
+}
+
+/// Tokens held by the wallet for a single token category.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenBalance {
+    /// Token category.
+    pub id: TokenID,
+    /// Fungible token amount.
+    pub amount: u64,
+    /// Non-fungible tokens.
+    pub nfts: Vec<Nft>,
+}
+
+/// Compute token balances from a set of unspent outputs, per token category.
+pub fn tokens<'a>(utxos: impl IntoIterator<Item = &'a Utxo>) -> Vec<TokenBalance> {
+    let mut balances = BTreeMap::<TokenID, TokenBalance>::new();
+
+    for utxo in utxos {
+        let token = match &utxo.txout.token {
+            Some(token) => token,
+            None => continue,
+        };
+        let balance = balances.entry(token.id).or_insert_with(|| TokenBalance {
+            id: token.id,
+            amount: 0,
+            nfts: Vec::new(),
+        });
+
+        if token.has_amount() {
+            balance.amount += token.amount as u64;
+        }
+        if token.has_nft() {
+            balance.nfts.push(Nft {
+                capability: token.capability(),
+                commitment: token.commitment.clone(),
+                outpoint: utxo.outpoint,
+            });
+        }
+    }
+    balances.into_values().collect()
+}
+
+/// Addresses watched by the wallet, keyed by their output script, so that outputs can be
+/// matched without rebuilding each address' script.
+#[derive(Debug, Default, Clone)]
+pub struct Watch {
+    scripts: HashMap<Script, Address>,
+}
+
+impl Watch {
+    /// Start watching an address.
+    pub fn insert(&mut self, addr: Address) {
+        self.scripts.insert(addr.script_pubkey(), addr);
+    }
+
+    /// The watched address paying to the given script, if any.
+    pub fn get(&self, script: &Script) -> Option<&Address> {
+        self.scripts.get(script)
+    }
+
+    /// Whether the given script pays to a watched address.
+    pub fn contains(&self, script: &Script) -> bool {
+        self.scripts.contains_key(script)
+    }
+
+    /// Watched addresses.
+    pub fn addresses(&self) -> impl Iterator<Item = &Address> {
+        self.scripts.values()
+    }
+
+    /// Output scripts of the watched addresses.
+    pub fn scripts(&self) -> impl Iterator<Item = &Script> {
+        self.scripts.keys()
+    }
+}
+
+impl Extend<Address> for Watch {
+    fn extend<I: IntoIterator<Item = Address>>(&mut self, iter: I) {
+        for addr in iter {
+            self.insert(addr);
+        }
+    }
+}
+
+impl FromIterator<Address> for Watch {
+    fn from_iter<I: IntoIterator<Item = Address>>(iter: I) -> Self {
+        let mut watch = Self::default();
+        watch.extend(iter);
+        watch
+    }
+}
+
+/// Transactions peers matched against our bloom filters, re-checked against our own watch set.
+///
+/// Filters match more than what we watch, by design: false positives hide our transactions
+/// among others, so the higher their share, the less peers learn about which ones are ours.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Matches {
+    /// Transactions matched, including false positives.
+    pub total: u64,
+    /// Transactions matched that weren't ours.
+    pub false_positives: u64,
+}
+
+impl Matches {
+    /// Share of matched transactions that weren't ours.
+    pub fn fp_rate(&self) -> f64 {
+        if self.total == 0 {
+            return 0.;
+        }
+        self.false_positives as f64 / self.total as f64
+    }
+}
+
+/// Keeps the wallet's UTXO set up to date.
+#[derive(Debug, Default)]
+pub struct UtxoSet {
+    /// Transactions matched in merkle blocks that we haven't received yet, along with the
+    /// height of the block including them.
+    pending: HashMap<Txid, Height>,
+    /// Transactions matched by peers so far.
+    matches: Matches,
+}
+
+impl UtxoSet {
+    /// Process the verified matches of a merkle block. Transactions we already know about are
+    /// confirmed, and the others will be once we receive them.
+    pub fn merkle_block(
+        &mut self,
+        db: &Db,
+        matches: &[Txid],
+        height: Height,
+    ) -> Result<(), db::Error> {
+        for txid in matches {
+            if !db.confirm(txid, height)? {
+                self.pending.insert(*txid, height);
+            }
+        }
+        Ok(())
+    }
+
+    /// Check a transaction matched by peers against our own watch set, rather than trusting
+    /// their bloom filter matching. It's ours if it pays to a watched address, spends one of
+    /// our outputs, or is already known. Others are counted as false positives, and shouldn't
+    /// be applied.
+    pub fn check_match(
+        &mut self,
+        db: &Db,
+        tx: &Transaction,
+        watch: &Watch,
+    ) -> Result<bool, db::Error> {
+        let mut ours = tx.output.iter().any(|o| watch.contains(&o.script_pubkey))
+            || db.transaction_height(&tx.txid())?.is_some();
+
+        for input in tx.input.iter() {
+            if ours {
+                break;
+            }
+            ours = db.utxo(&input.previous_output)?.is_some();
+        }
+        self.matches.total += 1;
+        if !ours {
+            self.matches.false_positives += 1;
+        }
+        Ok(ours)
+    }
+
+    /// Transactions matched by peers so far, see [`UtxoSet::check_match`].
+    pub fn matches(&self) -> Matches {
+        self.matches
+    }
+
+    /// Apply a transaction to the UTXO set. If the height of the block including it isn't
+    /// given, it is taken from a previously processed merkle block, if any.
+    ///
+    /// Returns the addresses that received new outputs.
+    pub fn apply(
+        &mut self,
+        db: &Db,
+        tx: &Transaction,
+        height: Option<Height>,
+        watch: &Watch,
+    ) -> Result<Vec<Address>, db::Error> {
+        let txid = tx.txid();
+        let height = height.or_else(|| self.pending.remove(&txid));
+        let mut received = Vec::new();
+
+        // Look for outputs.
+        for (vout, output) in tx.output.iter().enumerate() {
+            if let Some(addr) = watch.get(&output.script_pubkey) {
+                if db.add_utxo(
+                    txid,
+                    vout as u32,
+                    addr.clone(),
+                    output.value,
+                    output.token.as_ref(),
+                    height,
+                )? {
+                    db.mark_used(addr, output.value)?;
+                    received.push(addr.clone());
+                }
+            }
+        }
+        if tx.is_coin_base() {
+            db.mark_coinbase(&txid)?;
+        } else {
+            // Look for inputs.
+            for input in tx.input.iter() {
+                db.spend_utxo(&input.previous_output, &txid, height)?;
+            }
+        }
+        // The transaction may have been seen before it was included in a block.
+        if let Some(height) = height {
+            db.confirm(&txid, height)?;
+        }
+        Ok(received)
+    }
+
+    /// Import the unspent outputs of a trusted snapshot. Outputs that don't pay to a watched
+    /// address are ignored.
+    ///
+    /// Returns the number of outputs imported.
+    pub fn import(
+        &mut self,
+        db: &Db,
+        utxos: &[SnapshotUtxo],
+        watch: &Watch,
+    ) -> Result<usize, db::Error> {
+        let mut imported = 0;
+
+        for utxo in utxos {
+            let output = &utxo.txout;
+
+            if let Some(addr) = watch.get(&output.script_pubkey) {
+                if db.add_utxo(
+                    utxo.outpoint.txid,
+                    utxo.outpoint.vout,
+                    addr.clone(),
+                    output.value,
+                    output.token.as_ref(),
+                    Some(utxo.height),
+                )? {
+                    db.mark_used(addr, output.value)?;
+                    imported += 1;
+                }
+            }
+        }
+        Ok(imported)
+    }
+
+    /// Revert the block at the given height. Transactions in this block or above become
+    /// unconfirmed.
+    pub fn disconnect(&mut self, db: &Db, height: Height) -> Result<(), db::Error> {
+        self.pending.retain(|_, h| *h < height);
+        db.unconfirm(height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet::db::Read as _;
+    use nakamoto_common::bitcoin::blockdata::token::{OutputData, Structure};
+    use nakamoto_common::bitcoin::hashes::Hash;
+    use nakamoto_common::bitcoin::{Network, TxIn};
+    use nakamoto_test::block::gen;
+
+    fn setup(rng: &mut fastrand::Rng) -> (Db, Address, Transaction) {
+        let db = Db::memory().unwrap();
+        let addr = Address::from_script(&gen::script(rng), Network::Bitcoin).unwrap();
+        let mut tx = gen::transaction(rng);
+
+        db.add_address(&addr, 0, None, None).unwrap();
+        tx.output[0].script_pubkey = addr.script_pubkey();
+        tx.output[0].value = 1000;
+
+        (db, addr, tx)
+    }
+
+    #[test]
+    fn test_confirm_and_disconnect() {
+        let mut rng = fastrand::Rng::new();
+        let (db, addr, tx) = setup(&mut rng);
+        let watch = Watch::from_iter([addr.clone()]);
+        let mut utxos = UtxoSet::default();
+
+        let received = utxos.apply(&db, &tx, None, &watch).unwrap();
+        assert_eq!(received, vec![addr]);
+        assert_eq!(
+            db.balances().unwrap(),
+            Balance {
+                confirmed: 0,
+                unconfirmed: 1000
+            }
+        );
+
+        // Applying the transaction again in a block confirms it.
+        let received = utxos.apply(&db, &tx, Some(42), &watch).unwrap();
+        assert!(received.is_empty());
+        assert_eq!(
+            db.balances().unwrap(),
+            Balance {
+                confirmed: 1000,
+                unconfirmed: 0
+            }
+        );
+        let unspent = db.unspent().unwrap();
+        assert_eq!(unspent[0].height, Some(42));
+        assert_eq!(unspent[0].confirmations(44), 3);
+
+        // Reverting the block unconfirms it.
+        utxos.disconnect(&db, 42).unwrap();
+        assert_eq!(db.balances().unwrap().unconfirmed, 1000);
+        assert_eq!(db.unspent().unwrap()[0].confirmations(44), 0);
+    }
+
+    #[test]
+    fn test_pending() {
+        let mut rng = fastrand::Rng::new();
+        let (db, addr, tx) = setup(&mut rng);
+        let watch = Watch::from_iter([addr]);
+        let mut utxos = UtxoSet::default();
+
+        // As if the transaction was matched in a merkle block at height 7.
+        utxos.pending.insert(tx.txid(), 7);
+        utxos.apply(&db, &tx, None, &watch).unwrap();
+
+        assert_eq!(db.unspent().unwrap()[0].height, Some(7));
+        assert!(utxos.pending.is_empty());
+    }
+
+    #[test]
+    fn test_spend_and_disconnect() {
+        let mut rng = fastrand::Rng::new();
+        let (db, addr, tx) = setup(&mut rng);
+        let watch = Watch::from_iter([addr]);
+        let mut utxos = UtxoSet::default();
+
+        utxos.apply(&db, &tx, Some(1), &watch).unwrap();
+
+        let mut spend = gen::transaction(&mut rng);
+        spend.input = vec![TxIn {
+            previous_output: OutPoint {
+                txid: tx.txid(),
+                vout: 0,
+            },
+            ..TxIn::default()
+        }];
+        utxos.apply(&db, &spend, Some(2), &watch).unwrap();
+        assert_eq!(db.balances().unwrap().total(), 0);
+        assert!(db.unspent().unwrap().is_empty());
+
+        // Reverting the spending block leaves the output spent by an unconfirmed transaction.
+        utxos.disconnect(&db, 2).unwrap();
+        assert_eq!(db.balances().unwrap().total(), 0);
+    }
+
+    #[test]
+    fn test_check_match() {
+        let mut rng = fastrand::Rng::new();
+        let (db, addr, tx) = setup(&mut rng);
+        let watch = Watch::from_iter([addr]);
+        let mut utxos = UtxoSet::default();
+
+        assert!(utxos.check_match(&db, &tx, &watch).unwrap());
+        assert!(!utxos
+            .check_match(&db, &gen::transaction(&mut rng), &watch)
+            .unwrap());
+
+        utxos.apply(&db, &tx, Some(1), &watch).unwrap();
+        assert!(utxos.check_match(&db, &tx, &watch).unwrap());
+
+        let mut spend = gen::transaction(&mut rng);
+        spend.input[0].previous_output = OutPoint {
+            txid: tx.txid(),
+            vout: 0,
+        };
+        assert!(utxos.check_match(&db, &spend, &watch).unwrap());
+        assert_eq!(
+            utxos.matches(),
+            Matches {
+                total: 4,
+                false_positives: 1
+            }
+        );
+        assert_eq!(utxos.matches().fp_rate(), 0.25);
+    }
+
+    #[test]
+    fn test_coinbase_maturity() {
+        let mut rng = fastrand::Rng::new();
+        let (db, addr, mut tx) = setup(&mut rng);
+        let watch = Watch::from_iter([addr]);
+        let mut utxos = UtxoSet::default();
+        let settings = Settings::default();
+
+        tx.input = vec![TxIn::default()];
+        assert!(tx.is_coin_base());
+        utxos.apply(&db, &tx, Some(10), &watch).unwrap();
+
+        let unspent = db.unspent().unwrap();
+        assert!(unspent[0].coinbase);
+
+        // The output is immature until it has enough confirmations.
+        let tip = 10 + COINBASE_MATURITY - 2;
+        assert!(!unspent[0].is_mature(tip));
+        assert!(!unspent[0].is_final(tip, &settings));
+        assert_eq!(Balance::new(&unspent, tip, &settings).unconfirmed, 1000);
+
+        let tip = tip + 1;
+        assert!(unspent[0].is_mature(tip));
+        assert_eq!(Balance::new(&unspent, tip, &settings).confirmed, 1000);
+    }
+
+    #[test]
+    fn test_min_confirmations() {
+        let mut rng = fastrand::Rng::new();
+        let (db, addr, tx) = setup(&mut rng);
+        let watch = Watch::from_iter([addr]);
+        let mut utxos = UtxoSet::default();
+
+        assert_eq!(Settings::load(&db).unwrap(), Settings::default());
+
+        let settings = Settings {
+            min_confirmations: 6,
+        };
+        settings.save(&db).unwrap();
+        assert_eq!(Settings::load(&db).unwrap(), settings);
+
+        utxos.apply(&db, &tx, Some(100), &watch).unwrap();
+        let unspent = db.unspent().unwrap();
+        assert!(!unspent[0].coinbase);
+
+        assert_eq!(
+            Balance::new(&unspent, 104, &settings),
+            Balance {
+                confirmed: 0,
+                unconfirmed: 1000
+            }
+        );
+        assert_eq!(
+            Balance::new(&unspent, 105, &settings),
+            Balance {
+                confirmed: 1000,
+                unconfirmed: 0
+            }
+        );
+        // With the default settings, a single confirmation is enough.
+        assert_eq!(
+            Balance::new(&unspent, 100, &Settings::default()).confirmed,
+            1000
+        );
+    }
+
+    #[test]
+    fn test_tokens() {
+        let mut rng = fastrand::Rng::new();
+        let (db, addr, mut tx) = setup(&mut rng);
+        let watch = Watch::from_iter([addr.clone()]);
+        let mut utxos = UtxoSet::default();
+        let id = TokenID::from_inner([7; 32]);
+
+        tx.output[0].token = Some(OutputData {
+            id,
+            bitfield: Structure::HasAmount as u8,
+            amount: 100,
+            commitment: vec![],
+        });
+        tx.output.push(TxOut {
+            value: 1000,
+            script_pubkey: addr.script_pubkey(),
+            token: Some(OutputData {
+                id,
+                bitfield: Structure::HasAmount as u8
+                    | Structure::HasNFT as u8
+                    | Structure::HasCommitmentLength as u8
+                    | Capability::Minting as u8,
+                amount: 5,
+                commitment: vec![0xbe, 0xef],
+            }),
+        });
+        utxos.apply(&db, &tx, None, &watch).unwrap();
+
+        let unspent = db.unspent().unwrap();
+        let balances = tokens(&unspent);
+        assert_eq!(balances.len(), 1);
+        assert_eq!(balances[0].id, id);
+        assert_eq!(balances[0].amount, 105);
+        assert_eq!(balances[0].nfts.len(), 1);
+        assert_eq!(balances[0].nfts[0].commitment, vec![0xbe, 0xef]);
+        assert_eq!(balances[0].nfts[0].capability_name(), "minting");
+    }
+
+    #[test]
+    fn test_import_snapshot() {
+        let mut rng = fastrand::Rng::new();
+        let (db, addr, tx) = setup(&mut rng);
+        let watch = Watch::from_iter([addr]);
+        let mut utxos = UtxoSet::default();
+
+        let snapshot = tx
+            .output
+            .iter()
+            .enumerate()
+            .map(|(vout, txout)| SnapshotUtxo {
+                outpoint: OutPoint {
+                    txid: tx.txid(),
+                    vout: vout as u32,
+                },
+                txout: txout.clone(),
+                height: 7,
+            })
+            .collect::<Vec<_>>();
+
+        // Only the output paying to a watched address is imported.
+        assert_eq!(utxos.import(&db, &snapshot, &watch).unwrap(), 1);
+        assert_eq!(
+            db.balances().unwrap(),
+            Balance {
+                confirmed: 1000,
+                unconfirmed: 0
+            }
+        );
+        assert_eq!(db.unspent().unwrap()[0].height, Some(7));
+
+        // Importing the same outputs again has no effect.
+        assert_eq!(utxos.import(&db, &snapshot, &watch).unwrap(), 0);
+        assert_eq!(db.balances().unwrap().total(), 1000);
+    }
+}